@@ -1,33 +1,139 @@
-use std::{fs, sync::Arc};
+use std::{fs, path::Path, sync::Arc};
 
-use chrono::Utc;
+use chrono::{TimeDelta, Utc};
 use directories::BaseDirs;
 use eyre::{Report, Result};
-use libasc::{repository::Repository, sync::{server::handle_server, stream::StdinStdout}};
-use tokio::sync::Mutex;
+use libasc::{key::PublicKey, repository::Repository, sync::{audit::{self, AuditFilter}, init::ServerConfig, server::{handle_server, handle_server_without_repo}, stream::StdinStdout}, utils::parse_duration};
+use tokio::sync::RwLock;
 
 macro_rules! error {
     ($($t:tt)*) => {{
         eprintln!($($t)*);
 
-        return Ok(());
+        std::process::exit(1);
     }};
 }
 
+/// Load the server's settings from `<config dir>/asc-server/config.toml`,
+/// or use the defaults if that file does not exist.
+fn load_config() -> Result<ServerConfig> {
+    let Some(dirs) = BaseDirs::new() else {
+        return Ok(ServerConfig::default());
+    };
+
+    let path = dirs.config_dir().join("asc-server").join("config.toml");
+
+    if !path.exists() {
+        return Ok(ServerConfig::default());
+    }
+
+    let raw = fs::read_to_string(&path)?;
+
+    let config = toml::from_str(&raw)?;
+
+    Ok(config)
+}
+
+/// Find a user of the repository by their name or the start of their public key.
+fn find_user(repo: &Repository, query: &str) -> Option<PublicKey> {
+    if let Some(user) = repo.users.get_user(query) {
+        return Some(user.public_key);
+    }
+
+    let prefix = query.to_ascii_uppercase();
+
+    let mut found = repo.users
+        .iter()
+        .filter(|user| user.public_key.to_string().starts_with(&prefix));
+
+    let user = found.next()?;
+
+    if found.next().is_some() {
+        return None;
+    }
+
+    Some(user.public_key)
+}
+
+/// `asc-server audit <repo path> [--user USER] [--since AGE] [--event KIND]`
+///
+/// Print the audit log of a repository this server hosts.
+fn run_audit(args: &[String]) -> Result<()> {
+    let Some(repo_path) = args.first() else {
+        error!("Error: repository path was not specified.");
+    };
+
+    let repo = Repository::load_from(repo_path)?;
+
+    let mut filter = AuditFilter::default();
+
+    let mut rest = args[1..].iter();
+
+    while let Some(flag) = rest.next() {
+        let Some(value) = rest.next() else {
+            error!("Error: {flag} needs a value.");
+        };
+
+        match flag.as_str() {
+            "--user" => match find_user(&repo, value) {
+                Some(key) => filter.user = Some(key),
+                None => error!("Error: no single user is called {value:?} or has a key starting with it.")
+            },
+
+            "--since" => {
+                let ago = parse_duration(value)?;
+
+                let since = TimeDelta::from_std(ago)
+                    .ok()
+                    .and_then(|ago| Utc::now().checked_sub_signed(ago));
+
+                match since {
+                    Some(since) => filter.since = Some(since),
+                    None => error!("Error: {value:?} is too long ago.")
+                }
+            }
+
+            "--event" => filter.kind = Some(value.clone()),
+
+            _ => error!("Error: unknown option {flag:?}.")
+        }
+    }
+
+    for record in audit::read_log(&repo)? {
+        if filter.matches(&record) {
+            println!("{record}");
+        }
+    }
+
+    Ok(())
+}
+
 async fn run() -> Result<()> {
     let args: Vec<_> = std::env::args().skip(1).collect();
 
+    if args.first().is_some_and(|arg| arg == "audit") {
+        return run_audit(&args[1..]);
+    }
+
     let Some(repo_path) = args.first() else {
         error!("Error: repository path was not specified.");
     };
 
-    let repo = Arc::new(Mutex::new(
+    let mut stream = StdinStdout::new();
+
+    let path = Path::new(repo_path);
+
+    let config = load_config()?;
+
+    if !path.join(".asc").is_dir() {
+        return handle_server_without_repo(&mut stream, path, Some(&config)).await;
+    }
+
+    let repo = Arc::new(RwLock::new(
         Repository::load_from(repo_path)?
     ));
 
-    let mut stream = StdinStdout::new();
-
-    handle_server(&mut stream, repo).await
+    handle_server(&mut stream, repo, Some(&config)).await
 }
 
 fn save_error(error: &Report) {
@@ -64,11 +170,19 @@ fn save_error(error: &Report) {
 async fn main() -> Result<()> {
     stable_eyre::install()?;
     
-    if let Err(e) = run().await {
-        save_error(&e);
+    let code = match run().await {
+        Ok(()) => 0,
+        Err(e) => {
+            save_error(&e);
 
-        error!("Encountered error while running: {e:?}");
-    }
+            eprintln!("Encountered error while running: {e:?}");
 
-    Ok(())
+            1
+        }
+    };
+
+    // Reading stdin blocks a thread that the runtime would wait on
+    // when shutting down, so a client that stopped sending anything
+    // would keep the server running after it gave up on the client.
+    std::process::exit(code)
 }
@@ -2,12 +2,14 @@
 
 use std::{collections::HashMap, fs::{self, create_dir, remove_dir_all}, io, path::{Path, PathBuf}, pin::pin, process::{Command as StdCommand, Stdio}, sync::Arc};
 
-use async_ssh2_tokio::{AuthMethod, Client as SshClient, ServerCheckMethod};
-use async_trait::async_trait;
 use chrono::Utc;
 use eyre::{Result, eyre};
-use libasc::{graph::Graph, key::PrivateKey, repository::Repository, snapshot::Snapshot, sync::{client::Client,clone::handle_clone_as_server, entry::handle_server, pull::{BranchPullResult, PullResult, TagPullResult, handle_pull_as_client, handle_pull_as_server}, push::{BranchPushResult, PushResult, TagPushResult, handle_push_as_client, handle_push_as_server}, stream::{SshStream, StdinStdout, Stream}}};
-use tokio::{io::{AsyncReadExt, AsyncWriteExt, simplex}, process::{ChildStdin, ChildStdout, Command}, sync::{Mutex, mpsc::channel}};
+use libasc::{graph::Graph, key::PrivateKey, repository::Repository, snapshot::Snapshot, sync::{client::Client, clone::{CloneOptions, handle_clone_as_server}, pull::{BranchPullResult, PullResult, TagPullResult, handle_pull_as_client, handle_pull_as_server}, push::{BranchPushResult, PushResult, TagPushResult, handle_push_as_client, handle_push_as_server}, remote::Remote, server::handle_server, stream::{StdinStdout, Stream}}};
+use tokio::{io::{AsyncReadExt, AsyncWriteExt, simplex}, process::{ChildStdin, ChildStdout, Command}, sync::{RwLock, mpsc::channel}};
+
+fn remote() -> Result<Remote> {
+    Remote::from_url("ssh://localhost/tmp/test-remote-repo")
+}
 
 fn ensure_empty(path: impl AsRef<Path>) -> Result<()> {
     let path = path.as_ref();
@@ -53,15 +55,15 @@ fn setup_pull() -> Result<()> {
         creator.clone(),
         "commit 1".to_string(),
         Utc::now(),
-        [(PathBuf::from("main.py"), content1)].into(),
-        [local.current_hash].into()
+        [("main.py".into(), content1)].into(),
+        [local.current_hash()].into()
     );
 
     println!("saving commit 1 ({})", commit1.hash);
 
-    local.tags.create("v0.1.0".to_string(), commit1.hash);
-    
-    local.append_snapshot(commit1)?;
+    let commit1_hash = local.append_snapshot(commit1)?;
+
+    local.refs.tags_mut().create("v0.1.0".to_string(), commit1_hash);
 
     let content2 = local.save_content(
         "print('goodbye world!')",
@@ -74,15 +76,15 @@ fn setup_pull() -> Result<()> {
         creator.clone(),
         "commit 2".to_string(),
         Utc::now(),
-        [(PathBuf::from("main.py"), content2)].into(),
-        [local.current_hash].into()
+        [("main.py".into(), content2)].into(),
+        [local.current_hash()].into()
     );
     
     println!("saving commit 2 ({})", commit2.hash);
 
-    local.tags.create("v0.2.0".to_string(), commit2.hash);
-    
-    local.append_snapshot(commit2)?;
+    let commit2_hash = local.append_snapshot(commit2)?;
+
+    local.refs.tags_mut().create("v0.2.0".to_string(), commit2_hash);
 
     local.save()?;
 
@@ -109,15 +111,15 @@ fn setup_pull() -> Result<()> {
         creator.clone(),
         "commit 2.1".to_string(),
         Utc::now(),
-        [(PathBuf::from("main.py"), content2_1)].into(),
-        [local.current_hash].into()
+        [("main.py".into(), content2_1)].into(),
+        [local.current_hash()].into()
     );
 
     println!("saving commit 2.1 ({})", commit2_1.hash);
 
-    local.tags.create("v0.2.1".to_string(), commit2_1.hash);
+    let commit2_1_hash = local.append_snapshot(commit2_1)?;
 
-    local.append_snapshot(commit2_1);
+    local.refs.tags_mut().create("v0.2.1".to_string(), commit2_1_hash);
 
     let content2_2 = remote.save_content(
         "print('goodbye world 2.2!')",
@@ -130,15 +132,15 @@ fn setup_pull() -> Result<()> {
         creator.clone(),
         "commit 2.2".to_string(),
         Utc::now(),
-        [(PathBuf::from("main.py"), content2_2)].into(),
-        [remote.current_hash].into()
+        [("main.py".into(), content2_2)].into(),
+        [remote.current_hash()].into()
     );
 
     println!("saving commit 2.2 ({})", commit2_2.hash);
 
-    remote.tags.create("v0.2.2".to_string(), commit2_2.hash);
+    let commit2_2_hash = remote.append_snapshot(commit2_2)?;
 
-    remote.append_snapshot(commit2_2);
+    remote.refs.tags_mut().create("v0.2.2".to_string(), commit2_2_hash);
 
     local.save()?;
     remote.save()?;
@@ -178,15 +180,15 @@ fn setup_push() -> Result<()> {
         creator.clone(),
         "commit 1".to_string(),
         Utc::now(),
-        [(PathBuf::from("main.py"), content1)].into(),
-        [local.current_hash].into()
+        [("main.py".into(), content1)].into(),
+        [local.current_hash()].into()
     );
 
     println!("saving commit 1 ({})", commit1.hash);
 
-    local.tags.create("v0.1.0".to_string(), commit1.hash);
-    
-    local.append_snapshot(commit1)?;
+    let commit1_hash = local.append_snapshot(commit1)?;
+
+    local.refs.tags_mut().create("v0.1.0".to_string(), commit1_hash);
 
     let content2 = local.save_content(
         "print('goodbye world!')",
@@ -199,15 +201,15 @@ fn setup_push() -> Result<()> {
         creator.clone(),
         "commit 2".to_string(),
         Utc::now(),
-        [(PathBuf::from("main.py"), content2)].into(),
-        [local.current_hash].into()
+        [("main.py".into(), content2)].into(),
+        [local.current_hash()].into()
     );
     
     println!("saving commit 2 ({})", commit2.hash);
 
-    local.tags.create("v0.2.0".to_string(), commit2.hash);
-    
-    local.append_snapshot(commit2)?;
+    let commit2_hash = local.append_snapshot(commit2)?;
+
+    local.refs.tags_mut().create("v0.2.0".to_string(), commit2_hash);
 
     let content3 = local.save_content(
         "print('goodbye world 2!')",
@@ -220,15 +222,15 @@ fn setup_push() -> Result<()> {
         creator.clone(),
         "commit 3".to_string(),
         Utc::now(),
-        [(PathBuf::from("main.py"), content3)].into(),
-        [local.current_hash].into()
+        [("main.py".into(), content3)].into(),
+        [local.current_hash()].into()
     );
 
     println!("saving commit 3 ({})", commit3.hash);
 
-    local.tags.create("v0.3.0".to_string(), commit3.hash);
+    let commit3_hash = local.append_snapshot(commit3)?;
 
-    local.append_snapshot(commit3)?;
+    local.refs.tags_mut().create("v0.3.0".to_string(), commit3_hash);
 
     local.save()?;
 
@@ -257,15 +259,15 @@ fn setup_clone() -> Result<PrivateKey> {
         creator.clone(),
         "commit 1".to_string(),
         Utc::now(),
-        [(PathBuf::from("main.py"), content1)].into(),
-        [repo.current_hash].into()
+        [("main.py".into(), content1)].into(),
+        [repo.current_hash()].into()
     );
 
     println!("saving commit 1 ({})", commit1.hash);
 
-    repo.tags.create("v0.1.0".to_string(), commit1.hash);
-    
-    repo.append_snapshot(commit1)?;
+    let commit1_hash = repo.append_snapshot(commit1)?;
+
+    repo.refs.tags_mut().create("v0.1.0".to_string(), commit1_hash);
 
     let content2 = repo.save_content(
         "print('goodbye world!')",
@@ -278,15 +280,15 @@ fn setup_clone() -> Result<PrivateKey> {
         creator.clone(),
         "commit 2".to_string(),
         Utc::now(),
-        [(PathBuf::from("main.py"), content2)].into(),
-        [repo.current_hash].into()
+        [("main.py".into(), content2)].into(),
+        [repo.current_hash()].into()
     );
     
     println!("saving commit 2 ({})", commit2.hash);
 
-    repo.tags.create("v0.2.0".to_string(), commit2.hash);
-    
-    repo.append_snapshot(commit2)?;
+    let commit2_hash = repo.append_snapshot(commit2)?;
+
+    repo.refs.tags_mut().create("v0.2.0".to_string(), commit2_hash);
 
     let content2_1 = repo.save_content(
         "print('goodbye world 2.1!')",
@@ -299,15 +301,15 @@ fn setup_clone() -> Result<PrivateKey> {
         creator.clone(),
         "commit 2.1".to_string(),
         Utc::now(),
-        [(PathBuf::from("main.py"), content2_1)].into(),
-        [repo.current_hash].into()
+        [("main.py".into(), content2_1)].into(),
+        [repo.current_hash()].into()
     );
 
     println!("saving commit 2.1 ({})", commit2_1.hash);
 
-    repo.tags.create("v0.2.1".to_string(), commit2_1.hash);
+    let commit2_1_hash = repo.append_snapshot(commit2_1)?;
 
-    repo.append_snapshot(commit2_1);
+    repo.refs.tags_mut().create("v0.2.1".to_string(), commit2_1_hash);
 
     let content2_2 = repo.save_content(
         "print('goodbye world 2.2!')",
@@ -320,15 +322,15 @@ fn setup_clone() -> Result<PrivateKey> {
         creator.clone(),
         "commit 2.2".to_string(),
         Utc::now(),
-        [(PathBuf::from("main.py"), content2_2)].into(),
-        [repo.current_hash].into()
+        [("main.py".into(), content2_2)].into(),
+        [repo.current_hash()].into()
     );
 
     println!("saving commit 2.2 ({})", commit2_2.hash);
 
-    repo.tags.create("v0.2.2".to_string(), commit2_2.hash);
+    let commit2_2_hash = repo.append_snapshot(commit2_2)?;
 
-    repo.append_snapshot(commit2_2);
+    repo.refs.tags_mut().create("v0.2.2".to_string(), commit2_2_hash);
 
     repo.save()?;
     
@@ -364,15 +366,15 @@ fn list_actions_remote() -> Result<()> {
 }
 
 async fn make_pull() -> Result<()> {
-    let repo = Arc::new(Mutex::new(
+    let repo = Arc::new(RwLock::new(
         Repository::load_from("/tmp/test-local-repo")?
     ));
 
-    let mut client = Client::connect("localhost").await?;
+    let mut client = Client::connect(remote()?).await?;
 
-    let results = client.make_pull(repo.clone()).await?;
+    let results = client.make_pull(repo.clone(), false).await?;
 
-    let local_repo = repo.lock().await;
+    let local_repo = repo.write().await;
 
     for result in results {
         let name = match &result {
@@ -384,13 +386,18 @@ async fn make_pull() -> Result<()> {
             PullResult::Branch(name, branch_result) => match branch_result {
                 BranchPullResult::NotOnRemote => "branch not on remote".to_string(),
                 BranchPullResult::UpToDate => "up-to-date".to_string(),
-                BranchPullResult::FastForward(_, tip) => format!("{} -> {tip} (ffw)", local_repo.branches.get(&name).unwrap()),
-                BranchPullResult::Conflict(_, local, remote) => format!("{local} vs {remote} (split)"),
+                BranchPullResult::FastForward(_, _, tip) => format!("{} -> {tip} (ffw)", local_repo.refs.branches().get(&name).unwrap()),
+                BranchPullResult::Conflict(_, local, remote, _) => format!("{local} vs {remote} (split)"),
+                BranchPullResult::Ahead(local, remote) => format!("{local} ahead of {remote}"),
+                BranchPullResult::Fetched(_, local, remote) => format!("{local} -> {remote} (fetched)"),
             },
 
             PullResult::Tag(name, tag_result) => match tag_result {
-                TagPullResult::Conflict(local, remote) => format!("{local} vs {remote} (tag: {name})"),
-                TagPullResult::New(hash) => format!("new tag ({hash})")
+                TagPullResult::Conflict(local, remote, copy) => format!("{local} vs {remote} (tag: {copy})"),
+                TagPullResult::New(hash) => format!("new tag ({hash})"),
+                TagPullResult::Replaced(local, remote) => format!("{local} -> {remote} (replaced)"),
+                TagPullResult::Kept(local, remote) => format!("{local} vs {remote} (kept)"),
+                TagPullResult::InvalidName(_, reason) => format!("invalid name ({reason})")
             }
         };
 
@@ -427,15 +434,15 @@ async fn test_pull() -> Result<()> {
 }
 
 async fn make_push() -> Result<()> {
-    let repo = Arc::new(Mutex::new(
+    let repo = Arc::new(RwLock::new(
         Repository::load_from("/tmp/test-local-repo")?
     ));
 
-    let mut client = Client::connect("localhost").await?;
+    let mut client = Client::connect(remote()?).await?;
 
-    let results = client.make_push(repo.clone()).await?;
+    let results = client.make_push(repo.clone(), false).await?;
 
-    let local_repo = repo.lock().await;
+    let local_repo = repo.write().await;
 
     for result in results {
         let name = match &result {
@@ -447,13 +454,20 @@ async fn make_push() -> Result<()> {
             PushResult::Branch(name, branch_result) => match branch_result {
                 BranchPushResult::CreatedOnRemote => "created on remote".to_string(),
                 BranchPushResult::UpToDate => "up-to-date".to_string(),
-                BranchPushResult::FastForward(_, tip) => format!("{} -> {tip} (ffw)", local_repo.branches.get(&name).unwrap()),
+                BranchPushResult::FastForward(_, tip) => format!("{} -> {tip} (ffw)", local_repo.refs.branches().get(&name).unwrap()),
                 BranchPushResult::SplitHistory => "split history".to_string(),
+                BranchPushResult::Merge(hash) => format!("merge kept off linear branch ({hash})"),
+                BranchPushResult::InvalidName(reason) => format!("invalid name ({reason})"),
+                BranchPushResult::Held(hash) => format!("would leave behind held {hash}"),
             },
 
             PushResult::Tag(name, tag_result) => match tag_result {
-                TagPushResult::Conflict(local, remote) => format!("tag conflict - {local} vs {remote}"),
-                TagPushResult::New(hash) => format!("new tag ({hash})")
+                TagPushResult::Conflict => "tag conflict".to_string(),
+                TagPushResult::CreatedOnRemote => "new tag".to_string(),
+                TagPushResult::Denied => "denied".to_string(),
+                TagPushResult::Replaced => "replaced".to_string(),
+                TagPushResult::Kept => "kept".to_string(),
+                TagPushResult::InvalidName(reason) => format!("invalid name ({reason})")
             }
         };
 
@@ -496,24 +510,26 @@ async fn make_clone() -> Result<()> {
 
     ensure_empty(path)?;
 
-    let mut client = Client::connect("localhost").await?;
+    let mut client = Client::connect(remote()?).await?;
 
-    client.clone_repo(path, login_key).await
+    client.clone_repo(path, login_key, CloneOptions::default()).await?;
+
+    Ok(())
 }
 
 async fn act_as_server() -> Result<()> {
-    let repo = Arc::new(Mutex::new(
+    let repo = Arc::new(RwLock::new(
         Repository::load_from("/tmp/test-remote-repo")?
     ));
 
     let mut stream = StdinStdout::new();
 
-    handle_server(&mut stream, repo).await
+    handle_server(&mut stream, repo, None).await
 }
 
-#[tokio::main]
+#[tokio::main(flavor = "current_thread")]
 async fn main() -> Result<()> {
-    color_eyre::install()?;
+    stable_eyre::install()?;
 
     // if let Err(e) = act_as_server().await {
     //     fs::write("/home/axo/dev/rust/vcs/server-output.txt", format!("{e:?}"));
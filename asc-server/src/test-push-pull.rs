@@ -4,8 +4,8 @@ use std::{collections::HashMap, fs::{create_dir, remove_dir_all}, path::{Path, P
 
 use chrono::Utc;
 use eyre::{Result, eyre};
-use libasc::{graph::Graph, repository::Repository, snapshot::Snapshot, sync::{pull::{handle_pull_as_client, handle_pull_as_server, BranchPullResult, PullResult, TagPullResult}, push::{handle_push_as_client, handle_push_as_server, BranchPushResult, PushResult, TagPushResult}, stream::{local_duplex, Stream}}};
-use tokio::{io::simplex, sync::Mutex};
+use libasc::{graph::Graph, repository::Repository, snapshot::Snapshot, sync::{pull::{handle_pull_as_client, handle_pull_as_server, BranchPullResult, PullResult, TagPullResult}, push::{handle_push_as_client, handle_push_as_server, BranchPushResult, PushResult, TagPushResult}, remote::Remote, stream::{local_duplex, Stream}}};
+use tokio::{io::simplex, sync::RwLock};
 
 fn ensure_empty(path: impl AsRef<Path>) -> Result<()> {
     let path = path.as_ref();
@@ -51,15 +51,15 @@ fn setup_pull() -> Result<()> {
         creator.clone(),
         "commit 1".to_string(),
         Utc::now(),
-        [(PathBuf::from("main.py"), content1)].into(),
-        [local.current_hash].into()
+        [("main.py".into(), content1)].into(),
+        [local.current_hash()].into()
     );
 
     println!("saving commit 1 ({})", commit1.hash);
 
-    local.tags.create("v0.1.0".to_string(), commit1.hash);
-    
-    local.append_snapshot(commit1)?;
+    let commit1_hash = local.append_snapshot(commit1)?;
+
+    local.refs.tags_mut().create("v0.1.0".to_string(), commit1_hash);
 
     let content2 = local.save_content(
         "print('goodbye world!')",
@@ -72,23 +72,23 @@ fn setup_pull() -> Result<()> {
         creator.clone(),
         "commit 2".to_string(),
         Utc::now(),
-        [(PathBuf::from("main.py"), content2)].into(),
-        [local.current_hash].into()
+        [("main.py".into(), content2)].into(),
+        [local.current_hash()].into()
     );
     
     println!("saving commit 2 ({})", commit2.hash);
 
-    local.tags.create("v0.2.0".to_string(), commit2.hash);
-    
-    local.append_snapshot(commit2)?;
+    let commit2_hash = local.append_snapshot(commit2)?;
+
+    local.refs.tags_mut().create("v0.2.0".to_string(), commit2_hash);
 
     local.save()?;
 
     ensure_empty("/tmp/test-remote-repo")?;
 
-    let mut cmd = Command::new("nu");
+    let mut cmd = Command::new("cp");
 
-    cmd.args(["-c", "cp -r /tmp/test-local-repo/* /tmp/test-remote-repo"]);
+    cmd.args(["-r", "/tmp/test-local-repo/.", "/tmp/test-remote-repo"]);
 
     let mut child = cmd.spawn()?;
     
@@ -107,15 +107,15 @@ fn setup_pull() -> Result<()> {
         creator.clone(),
         "commit 2.1".to_string(),
         Utc::now(),
-        [(PathBuf::from("main.py"), content2_1)].into(),
-        [local.current_hash].into()
+        [("main.py".into(), content2_1)].into(),
+        [local.current_hash()].into()
     );
 
     println!("saving commit 2.1 ({})", commit2_1.hash);
 
-    local.tags.create("v0.2.1".to_string(), commit2_1.hash);
+    let commit2_1_hash = local.append_snapshot(commit2_1)?;
 
-    local.append_snapshot(commit2_1);
+    local.refs.tags_mut().create("v0.2.1".to_string(), commit2_1_hash);
 
     let content2_2 = remote.save_content(
         "print('goodbye world 2.2!')",
@@ -128,15 +128,15 @@ fn setup_pull() -> Result<()> {
         creator.clone(),
         "commit 2.2".to_string(),
         Utc::now(),
-        [(PathBuf::from("main.py"), content2_2)].into(),
-        [remote.current_hash].into()
+        [("main.py".into(), content2_2)].into(),
+        [remote.current_hash()].into()
     );
 
     println!("saving commit 2.2 ({})", commit2_2.hash);
 
-    remote.tags.create("v0.2.2".to_string(), commit2_2.hash);
+    let commit2_2_hash = remote.append_snapshot(commit2_2)?;
 
-    remote.append_snapshot(commit2_2);
+    remote.refs.tags_mut().create("v0.2.2".to_string(), commit2_2_hash);
 
     local.save()?;
     remote.save()?;
@@ -145,20 +145,22 @@ fn setup_pull() -> Result<()> {
 }
 
 async fn make_pull() -> Result<()> {
-    let local = Arc::new(Mutex::new(
+    let local = Arc::new(RwLock::new(
         Repository::load_from("/tmp/test-local-repo")?
     ));
-    let remote = Arc::new(Mutex::new(
+    let remote = Arc::new(RwLock::new(
         Repository::load_from("/tmp/test-remote-repo")?
     ));
     
+    let origin = Remote::from_path("/tmp/test-remote-repo");
+
     let (client, server) = local_duplex();
 
     let client: &'static mut _ = Box::leak(Box::new(client));
     let server: &'static mut _ = Box::leak(Box::new(server));
 
     let results = {
-        let mut client_fut = pin!(handle_pull_as_client(client, local.clone()));
+        let mut client_fut = pin!(handle_pull_as_client(client, local.clone(), &origin, false));
         let server_fut = pin!(handle_pull_as_server(server, remote.clone()));
         
         tokio::select! {
@@ -172,7 +174,7 @@ async fn make_pull() -> Result<()> {
         }?
     };
 
-    let local_repo = local.lock().await;
+    let mut local_repo = local.write().await;
 
     local_repo.save()?;
 
@@ -186,13 +188,18 @@ async fn make_pull() -> Result<()> {
             PullResult::Branch(name, branch_result) => match branch_result {
                 BranchPullResult::NotOnRemote => "branch not on remote".to_string(),
                 BranchPullResult::UpToDate => "up-to-date".to_string(),
-                BranchPullResult::FastForward(_, tip) => format!("{} -> {tip} (ffw)", local_repo.branches.get(&name).unwrap()),
-                BranchPullResult::Conflict(_, local, remote) => format!("{local} vs {remote} (split)"),
+                BranchPullResult::FastForward(_, _, tip) => format!("{} -> {tip} (ffw)", local_repo.refs.branches().get(&name).unwrap()),
+                BranchPullResult::Conflict(_, local, remote, _) => format!("{local} vs {remote} (split)"),
+                BranchPullResult::Ahead(local, remote) => format!("{local} ahead of {remote}"),
+                BranchPullResult::Fetched(_, local, remote) => format!("{local} -> {remote} (fetched)"),
             },
 
             PullResult::Tag(name, tag_result) => match tag_result {
-                TagPullResult::Conflict(local, remote) => format!("{local} vs {remote} (tag: {name})"),
-                TagPullResult::New(hash) => format!("new tag ({hash})")
+                TagPullResult::Conflict(local, remote, copy) => format!("{local} vs {remote} (tag: {copy})"),
+                TagPullResult::New(hash) => format!("new tag ({hash})"),
+                TagPullResult::Replaced(local, remote) => format!("{local} -> {remote} (replaced)"),
+                TagPullResult::Kept(local, remote) => format!("{local} vs {remote} (kept)"),
+                TagPullResult::InvalidName(_, reason) => format!("invalid name ({reason})")
             }
         };
 
@@ -207,9 +214,9 @@ fn setup_push() -> Result<()> {
 
     ensure_empty("/tmp/test-remote-repo")?;
 
-    let mut cmd = Command::new("nu");
+    let mut cmd = Command::new("cp");
 
-    cmd.args(["-c", "cp -r /tmp/test-local-repo/* /tmp/test-remote-repo"]);
+    cmd.args(["-r", "/tmp/test-local-repo/.", "/tmp/test-remote-repo"]);
 
     let mut child = cmd.spawn()?;
     
@@ -234,15 +241,15 @@ fn setup_push() -> Result<()> {
         creator.clone(),
         "commit 1".to_string(),
         Utc::now(),
-        [(PathBuf::from("main.py"), content1)].into(),
-        [local.current_hash].into()
+        [("main.py".into(), content1)].into(),
+        [local.current_hash()].into()
     );
 
     println!("saving commit 1 ({})", commit1.hash);
 
-    local.tags.create("v0.1.0".to_string(), commit1.hash);
-    
-    local.append_snapshot(commit1)?;
+    let commit1_hash = local.append_snapshot(commit1)?;
+
+    local.refs.tags_mut().create("v0.1.0".to_string(), commit1_hash);
 
     let content2 = local.save_content(
         "print('goodbye world!')",
@@ -255,15 +262,15 @@ fn setup_push() -> Result<()> {
         creator.clone(),
         "commit 2".to_string(),
         Utc::now(),
-        [(PathBuf::from("main.py"), content2)].into(),
-        [local.current_hash].into()
+        [("main.py".into(), content2)].into(),
+        [local.current_hash()].into()
     );
     
     println!("saving commit 2 ({})", commit2.hash);
 
-    local.tags.create("v0.2.0".to_string(), commit2.hash);
-    
-    local.append_snapshot(commit2)?;
+    let commit2_hash = local.append_snapshot(commit2)?;
+
+    local.refs.tags_mut().create("v0.2.0".to_string(), commit2_hash);
 
     let content3 = local.save_content(
         "print('goodbye world 2!')",
@@ -276,15 +283,15 @@ fn setup_push() -> Result<()> {
         creator.clone(),
         "commit 3".to_string(),
         Utc::now(),
-        [(PathBuf::from("main.py"), content3)].into(),
-        [local.current_hash].into()
+        [("main.py".into(), content3)].into(),
+        [local.current_hash()].into()
     );
 
     println!("saving commit 3 ({})", commit3.hash);
 
-    local.tags.create("v0.3.0".to_string(), commit3.hash);
+    let commit3_hash = local.append_snapshot(commit3)?;
 
-    local.append_snapshot(commit3)?;
+    local.refs.tags_mut().create("v0.3.0".to_string(), commit3_hash);
 
     local.save()?;
 
@@ -292,14 +299,16 @@ fn setup_push() -> Result<()> {
 }
 
 async fn make_push() -> Result<()> {
-    let local = Arc::new(Mutex::new(
+    let local = Arc::new(RwLock::new(
         Repository::load_from("/tmp/test-local-repo")?
     ));
     
-    let remote = Arc::new(Mutex::new(
+    let remote = Arc::new(RwLock::new(
         Repository::load_from("/tmp/test-remote-repo")?
     ));
     
+    let origin = Remote::from_path("/tmp/test-remote-repo");
+
     let (client, server) = local_duplex();
 
     let client: &'static mut _ = Box::leak(Box::new(client));
@@ -307,7 +316,7 @@ async fn make_push() -> Result<()> {
     let server: &'static mut _ = Box::leak(Box::new(server));
 
     let results = {
-        let mut client_fut = pin!(handle_push_as_client(client, local.clone()));
+        let mut client_fut = pin!(handle_push_as_client(client, local.clone(), &origin, false));
         let server_fut = pin!(handle_push_as_server(server, remote.clone()));
         
         tokio::select! {
@@ -321,7 +330,7 @@ async fn make_push() -> Result<()> {
         }?
     };
 
-    let local_repo = local.lock().await;
+    let mut local_repo = local.write().await;
 
     local_repo.save()?;
 
@@ -335,13 +344,20 @@ async fn make_push() -> Result<()> {
             PushResult::Branch(name, branch_result) => match branch_result {
                 BranchPushResult::CreatedOnRemote => "created on remote".to_string(),
                 BranchPushResult::UpToDate => "up-to-date".to_string(),
-                BranchPushResult::FastForward(_, tip) => format!("{} -> {tip} (ffw)", local_repo.branches.get(&name).unwrap()),
+                BranchPushResult::FastForward(_, tip) => format!("{} -> {tip} (ffw)", local_repo.refs.branches().get(&name).unwrap()),
                 BranchPushResult::SplitHistory => "split history".to_string(),
+                BranchPushResult::Merge(hash) => format!("merge kept off linear branch ({hash})"),
+                BranchPushResult::InvalidName(reason) => format!("invalid name ({reason})"),
+                BranchPushResult::Held(hash) => format!("would leave behind held {hash}"),
             },
 
             PushResult::Tag(name, tag_result) => match tag_result {
-                TagPushResult::Conflict(local, remote) => format!("tag conflict - {local} vs {remote}"),
-                TagPushResult::New(hash) => format!("new tag ({hash})")
+                TagPushResult::Conflict => "tag conflict".to_string(),
+                TagPushResult::CreatedOnRemote => "new tag".to_string(),
+                TagPushResult::Denied => "denied".to_string(),
+                TagPushResult::Replaced => "replaced".to_string(),
+                TagPushResult::Kept => "kept".to_string(),
+                TagPushResult::InvalidName(reason) => format!("invalid name ({reason})")
             }
         };
 
@@ -431,9 +447,9 @@ async fn test_push() -> Result<()> {
     Ok(())
 }
 
-#[tokio::main]
+#[tokio::main(flavor = "current_thread")]
 async fn main() -> Result<()> {
-    color_eyre::install()?;
+    stable_eyre::install()?;
 
     println!();
 
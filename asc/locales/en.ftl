@@ -0,0 +1,115 @@
+# English messages for asc. Every message has to be here,
+# since other languages fall back to these.
+
+yes = yes
+no = no
+current = current
+undone = undone
+
+
+switch-unsaved-changes = Cannot switch versions with unsaved changes.
+switch-done = Switched versions: { $before } -> { $after }
+switch-detached = You are now on { $hash } without a branch. Snapshots made here can only be found again with `asc reflog HEAD` - use `asc switch -c <name>` to put them on a branch.
+switch-leaving-detached = Left { $hash }, which no branch or tag leads to. It can be found again with `asc reflog HEAD`.
+
+commit-amend-other-branch = Cannot put an amended snapshot on another branch.
+commit-amend-has-children = Cannot amend snapshot { $hash } (later snapshots are built on it)
+commit-no-changes = No changes to document in the upcoming commit.
+commit-nothing-tracked = No files are being tracked - empty snapshots are disallowed.
+commit-invalid-message = Invalid snapshot message: { $error }.
+commit-empty-message = Aborting commit: the message is empty. Use '--allow-empty-message' to commit anyway.
+commit-template-unchanged = Aborting commit: the message template was left unchanged. Use '--allow-empty-message' to commit anyway.
+commit-amend-unchanged = No changes were made to the snapshot.
+commit-amended = Amended version: { $old } -> { $new }
+commit-branch-moved = Branch { $branch } has moved: { $old } -> { $new }
+commit-switched-branch = Switched branches: { $before } -> { $after }
+commit-done = New version: { $hash }
+
+branch-detached = HEAD detached at { $hash }
+branch-exists = Branch "{ $name }" already exists.
+branch-invalid-name = Invalid branch name: { $error }.
+branch-created-from-branch = Created new branch: { $name } -> { $basis } ({ $hash })
+branch-created = Created new branch: { $name } -> { $hash }
+branch-not-found = Branch "{ $name }" does not exist.
+branch-move-unchanged = Branch "{ $name }" already points to { $hash }.
+branch-move-unsaved-changes = Cannot move the current branch with unsaved changes.
+branch-moved = Moved branch: { $name } ({ $old } -> { $new })
+branch-renamed = Renamed: { $old } -> { $new }
+branch-deleted = Branch "{ $name }" no longer points to { $hash }.
+branch-ephemeral = Branch "{ $name }" is ephemeral, and will be deleted by `asc clean` once it is merged.
+branch-ephemeral-expires = Branch "{ $name }" is ephemeral, and will be deleted by `asc clean` once it is merged or after { $expires }.
+branch-list-ephemeral = ephemeral
+branch-list-expires = ephemeral, expires { $expires }
+branch-ahead = ahead { $count }
+branch-behind = behind { $count }
+branch-up-to-date = up to date
+branch-upstream = { $upstream }: { $state }
+branch-on = On branch { $name }
+branch-cleanup-none = There are no backups of diverged branches to clean up.
+branch-cleanup-merged = backups/{ $name } ({ $hash }) is merged into a branch.
+branch-cleanup-unmerged = backups/{ $name } ({ $hash }) is not merged into any branch.
+branch-cleanup-hint = Pass `--merged` to delete the backups that are merged.
+branch-cleanup-deleted = Deleted backups/{ $name } ({ $hash }), since it is merged into a branch.
+branch-default-set = Clones will check out "{ $name }" by default.
+branch-default-unset = Clones will check out the current branch by default.
+branch-default-none = There is no default branch, since the head is detached.
+
+fsck-clean = No problems found.
+fsck-repaired = Repaired: { $problem }
+fsck-summary-repaired = Found { $found } problems, repaired { $repaired }.
+fsck-summary = Found { $found } problems ({ $repairable } can be repaired with --repair).
+
+group-created = Created group "{ $name }".
+group-not-found = No group with name "{ $name }" found.
+group-deleted = Deleted group "{ $name }".
+group-member-added = Added "{ $user }" to "{ $group }"
+group-member-exists = "{ $user }" is already in "{ $group }"
+group-member-removed = Removed "{ $user }" from "{ $group }"
+group-member-missing = "{ $user }" is not in "{ $group }"
+group-empty = Group "{ $name }" has no members.
+group-members = Members of "{ $name }":
+group-unknown-member = <unknown user> ({ $key })
+group-none = No groups in the repository.
+group-list = Groups:
+group-list-entry = { $name } ({ $count } members)
+
+user-not-found = No user with name "{ $name }" found.
+user-none-set = No valid user set on this repository.
+
+remote-exists = There is already a remote under the name "{ $name }": { $url }.
+remote-created = Created the remote "{ $name }" at "{ $url }"
+remote-not-found = No remote under the name "{ $name }".
+remote-removed = Removed the remote "{ $name }" ({ $url }).
+remote-none = No remotes are on this repository.
+remote-initialised = Created the repository on "{ $name }" ({ $url }) - run `asc push { $name }` to upload it.
+remote-no-session = No session with "{ $name }" to log out of.
+remote-logged-out = Logged out of "{ $name }" ({ $url }).
+remote-logged-out-all = Logged out of every remote.
+
+whoami-remote = Remote: { $name } ({ $url })
+whoami-key = Key: { $key }
+whoami-user-closed = User: { $user } (closed)
+whoami-user = User: { $user }
+whoami-user-none = User: none (the remote has no account for this key)
+whoami-groups = Groups: { $groups }
+whoami-project-same = Project: same as this repository
+whoami-project-different = Project: different from this repository
+whoami-project-none = Project: none (there is no repository at this location)
+whoami-can-sync = Can push and pull: { $answer }
+whoami-can-create = Can create repositories: { $answer }
+whoami-repositories = Repositories with an account for this key:
+
+stats-took = Took { $time }: { $details }.
+stats-objects = read { $read } objects, wrote { $written } objects
+stats-transfer = sent { $sent }, received { $received }
+stats-cache = { $hits } of { $lookups } cache lookups hit ({ $rate }%)
+
+dry-run-header = Dry run, so nothing was changed. This would have:
+dry-run-nothing = Dry run, so nothing was changed, and nothing would have been.
+dry-run-write = written { $path }
+dry-run-remove = removed { $path }
+dry-run-delete-object = deleted the object { $hash }
+dry-run-create-ref = created { $name } at { $new }
+dry-run-move-ref = moved { $name } from { $old } to { $new }
+dry-run-delete-ref = deleted { $name } (was { $old })
+dry-run-move-head = moved the head to { $head }
@@ -0,0 +1,114 @@
+# Messages en français pour asc.
+
+yes = oui
+no = non
+current = actuel
+undone = annulée
+
+
+switch-unsaved-changes = Impossible de changer de version avec des modifications non enregistrées.
+switch-done = Version changée : { $before } -> { $after }
+switch-detached = Vous êtes maintenant sur { $hash } sans branche. Les snapshots créés ici ne peuvent être retrouvés qu'avec `asc reflog HEAD` - utilisez `asc switch -c <nom>` pour les mettre sur une branche.
+switch-leaving-detached = { $hash } a été quitté, et aucune branche ni étiquette n'y mène. Il peut être retrouvé avec `asc reflog HEAD`.
+
+commit-amend-other-branch = Impossible de placer un instantané modifié sur une autre branche.
+commit-amend-has-children = Impossible de modifier l'instantané { $hash } (des instantanés plus récents en dépendent)
+commit-no-changes = Aucune modification à enregistrer dans le prochain commit.
+commit-nothing-tracked = Aucun fichier n'est suivi - les instantanés vides ne sont pas autorisés.
+commit-invalid-message = Message d'instantané invalide : { $error }.
+commit-empty-message = Commit annulé : le message est vide. Utilisez '--allow-empty-message' pour le créer quand même.
+commit-template-unchanged = Commit annulé : le modèle de message n'a pas été modifié. Utilisez '--allow-empty-message' pour le créer quand même.
+commit-amend-unchanged = Aucune modification n'a été apportée à l'instantané.
+commit-amended = Version modifiée : { $old } -> { $new }
+commit-branch-moved = La branche { $branch } a été déplacée : { $old } -> { $new }
+commit-switched-branch = Branche changée : { $before } -> { $after }
+commit-done = Nouvelle version : { $hash }
+
+branch-detached = HEAD détachée sur { $hash }
+branch-exists = La branche « { $name } » existe déjà.
+branch-invalid-name = Nom de branche invalide : { $error }.
+branch-created-from-branch = Nouvelle branche créée : { $name } -> { $basis } ({ $hash })
+branch-created = Nouvelle branche créée : { $name } -> { $hash }
+branch-not-found = La branche « { $name } » n'existe pas.
+branch-move-unchanged = La branche « { $name } » pointe déjà sur { $hash }.
+branch-move-unsaved-changes = Impossible de déplacer la branche courante avec des modifications non enregistrées.
+branch-moved = Branche déplacée : { $name } ({ $old } -> { $new })
+branch-renamed = Renommée : { $old } -> { $new }
+branch-deleted = La branche « { $name } » ne pointe plus sur { $hash }.
+branch-ephemeral = La branche « { $name } » est éphémère, et sera supprimée par `asc clean` une fois fusionnée.
+branch-ephemeral-expires = La branche « { $name } » est éphémère, et sera supprimée par `asc clean` une fois fusionnée ou après le { $expires }.
+branch-list-ephemeral = éphémère
+branch-list-expires = éphémère, expire le { $expires }
+branch-ahead = { $count } en avance
+branch-behind = { $count } en retard
+branch-up-to-date = à jour
+branch-upstream = { $upstream } : { $state }
+branch-on = Sur la branche { $name }
+branch-cleanup-none = Il n'y a aucune sauvegarde de branche divergente à nettoyer.
+branch-cleanup-merged = backups/{ $name } ({ $hash }) est fusionnée dans une branche.
+branch-cleanup-unmerged = backups/{ $name } ({ $hash }) n'est fusionnée dans aucune branche.
+branch-cleanup-hint = Passez `--merged` pour supprimer les sauvegardes fusionnées.
+branch-cleanup-deleted = backups/{ $name } ({ $hash }) supprimée, puisqu'elle est fusionnée dans une branche.
+branch-default-set = Les clones utiliseront « { $name } » par défaut.
+branch-default-unset = Les clones utiliseront la branche courante par défaut.
+branch-default-none = Il n'y a pas de branche par défaut, puisque la HEAD est détachée.
+
+fsck-clean = Aucun problème trouvé.
+fsck-repaired = Réparé : { $problem }
+fsck-summary-repaired = { $found } problèmes trouvés, { $repaired } réparés.
+fsck-summary = { $found } problèmes trouvés ({ $repairable } réparables avec --repair).
+
+group-created = Groupe « { $name } » créé.
+group-not-found = Aucun groupe nommé « { $name } ».
+group-deleted = Groupe « { $name } » supprimé.
+group-member-added = « { $user } » ajouté à « { $group } »
+group-member-exists = « { $user } » fait déjà partie de « { $group } »
+group-member-removed = « { $user } » retiré de « { $group } »
+group-member-missing = « { $user } » ne fait pas partie de « { $group } »
+group-empty = Le groupe « { $name } » n'a aucun membre.
+group-members = Membres de « { $name } » :
+group-unknown-member = <utilisateur inconnu> ({ $key })
+group-none = Aucun groupe dans le dépôt.
+group-list = Groupes :
+group-list-entry = { $name } ({ $count } membres)
+
+user-not-found = Aucun utilisateur nommé « { $name } ».
+user-none-set = Aucun utilisateur valide n'est défini pour ce dépôt.
+
+remote-exists = Il existe déjà un dépôt distant nommé « { $name } » : { $url }.
+remote-created = Dépôt distant « { $name } » créé sur « { $url } »
+remote-not-found = Aucun dépôt distant nommé « { $name } ».
+remote-removed = Dépôt distant « { $name } » supprimé ({ $url }).
+remote-none = Ce dépôt n'a aucun dépôt distant.
+remote-initialised = Dépôt créé sur « { $name } » ({ $url }) - lancez `asc push { $name }` pour l'envoyer.
+remote-no-session = Aucune session ouverte avec « { $name } ».
+remote-logged-out = Déconnecté de « { $name } » ({ $url }).
+remote-logged-out-all = Déconnecté de tous les dépôts distants.
+
+whoami-remote = Dépôt distant : { $name } ({ $url })
+whoami-key = Clé : { $key }
+whoami-user-closed = Utilisateur : { $user } (fermé)
+whoami-user = Utilisateur : { $user }
+whoami-user-none = Utilisateur : aucun (le dépôt distant n'a pas de compte pour cette clé)
+whoami-groups = Groupes : { $groups }
+whoami-project-same = Projet : le même que ce dépôt
+whoami-project-different = Projet : différent de ce dépôt
+whoami-project-none = Projet : aucun (il n'y a pas de dépôt à cet emplacement)
+whoami-can-sync = Peut envoyer et récupérer : { $answer }
+whoami-can-create = Peut créer des dépôts : { $answer }
+whoami-repositories = Dépôts ayant un compte pour cette clé :
+
+stats-took = Terminé en { $time } : { $details }.
+stats-objects = { $read } objets lus, { $written } objets écrits
+stats-transfer = { $sent } envoyés, { $received } reçus
+stats-cache = { $hits } recherches sur { $lookups } trouvées en cache ({ $rate } %)
+
+dry-run-header = Simulation : rien n'a été modifié. La commande aurait :
+dry-run-nothing = Simulation : rien n'a été modifié, et rien ne l'aurait été.
+dry-run-write = écrit { $path }
+dry-run-remove = supprimé { $path }
+dry-run-delete-object = supprimé l'objet { $hash }
+dry-run-create-ref = créé { $name } sur { $new }
+dry-run-move-ref = déplacé { $name } de { $old } vers { $new }
+dry-run-delete-ref = supprimé { $name } (était { $old })
+dry-run-move-head = déplacé la tête sur { $head }
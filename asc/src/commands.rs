@@ -1,40 +1,110 @@
 mod add;
+mod am;
+mod apply_patch;
+mod audit;
 mod blame;
 mod branch;
+mod bundle;
 mod cat;
 mod changes;
+mod check_ignore;
 mod clean;
 mod clone;
 mod commit;
+mod compare;
 mod diff;
+mod external;
+mod format_patch;
+mod fsck;
+mod grep;
+mod group;
 mod history;
 mod init;
+mod lfs;
+mod link;
 mod log;
 mod ls;
 mod merge;
+mod mirror;
 mod modify;
 mod mv;
+mod pin;
+mod plumbing;
+mod publish;
 mod pull;
 mod push;
 mod redo;
+mod reflog;
 mod remote;
 mod remove;
+mod send_patch;
 mod show;
 mod stash;
+mod stats;
 mod switch;
 mod tag;
 mod trash;
 mod undo;
+mod unlock;
 mod update;
+mod upgrade;
 mod user;
+mod watch;
+mod whoami;
+
+use std::{env, ffi::OsString, path::PathBuf, time::Instant};
 
 use clap::{Parser, Subcommand};
+use eyre::bail;
+use libasc::{config::Config, guard::guard, lock::RepoLock, refs, repository::set_repository_dir, unwrap};
+
+use crate::{exit::{self, Status}, output::{self, Format, Verbosity}};
 
 /// A version control system in Rust, made by axololly.
 #[derive(Parser)]
+#[command(after_long_help = exit::HELP)]
 pub struct Cli {
     #[command(subcommand)]
-    command: Commands
+    command: Commands,
+
+    /// Show output without colours or decorations, and without
+    /// stopping to ask questions. Useful with screen readers.
+    #[arg(long, global = true)]
+    plain: bool,
+
+    /// After commands that can take a while, like commit, push and pull,
+    /// show how long they took and how much work they did.
+    #[arg(long, global = true)]
+    stats: bool,
+
+    /// How to print results. JSON is supported by history, blame, ls,
+    /// diff, changes, stats, compare, branch list and tag list.
+    #[arg(long, global = true, value_enum, default_value_t)]
+    format: Format,
+
+    /// Only print errors and the results asked for, not what commands are doing.
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// Print more detail, like the staged diff in the commit message
+    /// template, unchanged files in changes and hashes in branch list.
+    #[arg(long, global = true)]
+    verbose: bool,
+
+    /// Say what would change, without changing anything. Supported
+    /// by clean, merge, switch, trash add and stash pop.
+    #[arg(long, global = true)]
+    dry_run: bool,
+
+    /// Run as if asc was started in this directory.
+    #[arg(short = 'C', global = true, value_name = "DIR")]
+    directory: Option<PathBuf>,
+
+    /// Use the repository at this path, instead of the one containing
+    /// the working directory. Paths given to commands are still relative
+    /// to the working directory. This can also be set with ASC_DIR.
+    #[arg(long, global = true, value_name = "PATH")]
+    repo: Option<PathBuf>
 }
 
 #[derive(Subcommand)]
@@ -69,10 +139,15 @@ pub enum Commands {
     /// Compare versions in the repository.
     Diff(diff::Args),
 
-    /// Update staged files to match the ignore file.
+    /// Update staged files to match the ignore files.
     Update,
 
-    /// See what changes would be added to the next commit.
+    /// Explain which rule in the ignore files matches a path.
+    CheckIgnore(check_ignore::Args),
+
+    /// See what changes would be added to the next commit, and
+    /// how the current branch compares with where it is on remotes.
+    #[command(visible_alias = "status")]
     Changes(changes::Args),
 
     /// Clean out unused objects and reset the edit stack.
@@ -87,6 +162,9 @@ pub enum Commands {
     /// Review previous actions on the repository.
     Log(log::Args),
 
+    /// See how a branch, tag or other ref has moved over time.
+    Reflog(reflog::Args),
+
     /// List the contents of a directory in the repository.
     Ls(ls::Args),
 
@@ -100,15 +178,46 @@ pub enum Commands {
     /// Merge another branch's tip with the current snapshot.
     Merge(merge::Args),
 
+    /// Review what merging one version into another would bring in: the
+    /// snapshots, the changed files and any conflicts to expect.
+    Compare(compare::Args),
+
+    /// Check the repository for corrupted or missing objects and broken references.
+    Fsck(fsck::Args),
+
+    /// Fix problems in the repository, like branches and tags that point to missing snapshots.
+    Doctor,
+
+    /// Show who holds the lock on the repository, or remove
+    /// one left behind by a command that crashed.
+    Unlock(unlock::Args),
+
+    /// Show what the repository stores and how much space it takes up.
+    Stats(stats::Args),
+
+    /// Keep track of changes to files in the background, so checking for changes is faster.
+    Watch(watch::Args),
+
     /// Remove snapshots from the repository.
     #[command(subcommand)]
     Trash(trash::Subcommands),
 
+    /// Keep a snapshot even once nothing else uses it, so that clean and
+    /// purging the trash never delete it, or list the pinned snapshots.
+    Pin(pin::Args),
+
+    /// Stop keeping a pinned snapshot.
+    Unpin(pin::UnpinArgs),
+
     /// Modify snapshots in the repository.
     #[command(visible_aliases = ["mod", "edit"])]
     Modify(modify::Args),
 
+    /// Search the contents of files in a version, or across the history.
+    Grep(grep::Args),
+
     /// See which user in the repository modified each line in a file.
+    #[command(visible_alias = "annotate")]
     Blame(blame::Args),
 
     /// Alias a snapshot in the repository.
@@ -119,6 +228,14 @@ pub enum Commands {
     #[command(subcommand)]
     User(user::Subcommands),
 
+    /// Show the current user, or which user a remote takes you to be.
+    #[command(name = "whoami")]
+    WhoAmI(whoami::Args),
+
+    /// Manage groups of users in the repository.
+    #[command(subcommand)]
+    Group(group::Subcommands),
+
     /// Interact with remote URLs in the repository.
     #[command(subcommand)]
     Remote(remote::Subcommands),
@@ -133,15 +250,144 @@ pub enum Commands {
     Push(push::Args),
 
     /// Pull changes from another repository.
-    Pull(pull::Args)
+    Pull(pull::Args),
+
+    /// Move history between repositories as a file, without a network.
+    #[command(subcommand)]
+    Bundle(bundle::Subcommands),
+
+    /// Write snapshots as patch files, to be sent by email.
+    FormatPatch(format_patch::Args),
+
+    /// Add the snapshots written in patch files to the current branch.
+    ApplyPatch(apply_patch::Args),
+
+    /// Write snapshots as emails in an mbox, signatures and all.
+    SendPatch(send_patch::Args),
+
+    /// Add the snapshots emailed in an mbox to the current branch,
+    /// checking they are signed by whoever made them.
+    Am(am::Args),
+
+    /// Create this repository on a remote and push to it.
+    Publish(publish::Args),
+
+    /// Make a remote an exact copy of this repository, like for a backup.
+    /// Its branches and tags are moved to match these, even if that isn't
+    /// a fast-forward, and those that aren't here are deleted.
+    Mirror(mirror::Args),
+
+    /// Show the log of who pulled from, pushed to, cloned or mirrored
+    /// to this repository when it was a remote, and which branches changed.
+    Audit(audit::Args),
+
+    /// Embed other repositories at paths in this one.
+    #[command(subcommand)]
+    Link(link::Subcommands),
+
+    /// Manage large files stored outside of the blob store.
+    #[command(subcommand)]
+    Lfs(lfs::Subcommands),
+
+    /// Update asc to the latest release, checking that it is signed.
+    Upgrade(upgrade::Args),
+
+    /// Commands for plugins and scripts, with output that is kept stable.
+    #[command(subcommand)]
+    Plumbing(plumbing::Subcommands),
+
+    /// Run `asc-<name>` from the PATH, for commands that aren't built in.
+    #[command(external_subcommand)]
+    External(Vec<OsString>)
 }
 
 pub fn run() -> eyre::Result<()> {
     let cli = Cli::parse();
 
+    if let Some(dir) = &cli.directory {
+        unwrap!(
+            env::set_current_dir(dir),
+            "cannot change to directory: {}", dir.display()
+        );
+    }
+
+    if let Some(path) = &cli.repo {
+        set_repository_dir(path)?;
+    }
+
+    let verbosity = match (cli.quiet, cli.verbose) {
+        (true, _) => Verbosity::Quiet,
+        (_, true) => Verbosity::Verbose,
+        _ => Verbosity::Normal
+    };
+
+    output::init(cli.plain, cli.stats, cli.format, verbosity)?;
+
+    // Refs changed by this command are logged with how it was run.
+    let invocation: Vec<String> = env::args().skip(1).collect();
+
+    refs::set_reason(invocation.join(" "));
+
     use Commands::*;
 
-    match cli.command {
+    let supports_json = matches!(
+        cli.command,
+        History(_) | Blame(_) | Ls(_) | Diff(_) | Changes(_) | Stats(_) | Compare(_) | Pin(_) | Audit(_)
+            | Branch(branch::Subcommands::List { .. })
+            | Tag(tag::Subcommands::List { .. })
+            | External(_)
+    );
+
+    if output::is_json() && !supports_json {
+        exit::set(Status::Usage);
+
+        bail!("'--format json' is not supported by this command.");
+    }
+
+    let supports_dry_run = matches!(
+        cli.command,
+        Clean | Merge(_) | Switch(_)
+            | Trash(trash::Subcommands::Add { .. })
+            | Stash(stash::Subcommands::Pop { .. })
+    );
+
+    if cli.dry_run && !supports_dry_run {
+        exit::set(Status::Usage);
+
+        bail!("'--dry-run' is not supported by this command.");
+    }
+
+    guard().set_dry_run(cli.dry_run);
+
+    // Commands that change the repository hold its lock until they finish,
+    // so that two running at once can't save over each other.
+    let changes_repo = matches!(
+        cli.command,
+        Add(_) | Remove(_) | Mv(_) | Commit(_) | Branch(_) | Switch(_) | Update | Clean | Undo(_) | Redo(_)
+            | Stash(_) | Merge(_) | Fsck(_) | Doctor | Trash(_) | Pin(_) | Unpin(_) | Modify(_) | Tag(_)
+            | User(_) | Group(_) | Remote(_) | Push(_) | Pull(_) | Bundle(_) | ApplyPatch(_) | Am(_)
+            | Publish(_) | Mirror(_) | Link(_) | Lfs(_)
+            | Plumbing(plumbing::Subcommands::MoveObjects { .. })
+    ) && !matches!(
+        cli.command,
+        Branch(branch::Subcommands::List { .. })
+            | Tag(tag::Subcommands::List { .. } | tag::Subcommands::Latest { .. })
+    );
+
+    let _lock = if changes_repo && !cli.dry_run {
+        let timeout = Config::load_current()?.io.lock_timeout()?;
+
+        RepoLock::acquire_current(&invocation.join(" "), timeout)?
+    }
+    else {
+        None
+    };
+
+    let started = Instant::now();
+
+    let is_long = matches!(cli.command, Commit(_) | Clean | Clone(_) | Push(_) | Pull(_) | Mirror(_));
+
+    let result = match cli.command {
         Init(args) => init::parse(args),
         Add(args) => add::parse(args),
         Remove(args) => remove::parse(args),
@@ -152,24 +398,60 @@ pub fn run() -> eyre::Result<()> {
         Switch(args) => switch::parse(args),
         Diff(args) => diff::parse(args),
         Update => update::parse(),
+        CheckIgnore(args) => check_ignore::parse(args),
         Changes(args) => changes::parse(args),
         Clean => clean::parse(),
         Undo(args) => undo::parse(args),
         Redo(args) => redo::parse(args),
         Log(args) => log::parse(args),
+        Reflog(args) => reflog::parse(args),
         Ls(args) => ls::parse(args),
         Cat(args) => cat::parse(args),
         Stash(subcommand) => stash::parse(subcommand),
         Merge(args) => merge::parse(args),
+        Compare(args) => compare::parse(args),
+        Fsck(args) => fsck::parse(args),
+        Doctor => fsck::doctor(),
+        Unlock(args) => unlock::parse(args),
+        Stats(args) => stats::parse(args),
+        Watch(args) => watch::parse(args),
         Trash(subcommand) => trash::parse(subcommand),
+        Pin(args) => pin::parse(args),
+        Unpin(args) => pin::unpin(args),
         Modify(args) => modify::parse(args),
+        Grep(args) => grep::parse(args),
         Blame(args) => blame::parse(args),
         Tag(subcommand) => tag::parse(subcommand),
         User(subcommand) => user::parse(subcommand),
+        WhoAmI(args) => whoami::parse(args),
+        Group(subcommand) => group::parse(subcommand),
         Remote(subcommand) => remote::parse(subcommand),
         Clone(args) => clone::parse(args),
         Show(args) => show::parse(args),
         Push(args) => push::parse(args),
-        Pull(args) => pull::parse(args)
+        Pull(args) => pull::parse(args),
+        Bundle(subcommand) => bundle::parse(subcommand),
+        FormatPatch(args) => format_patch::parse(args),
+        ApplyPatch(args) => apply_patch::parse(args),
+        SendPatch(args) => send_patch::parse(args),
+        Am(args) => am::parse(args),
+        Publish(args) => publish::parse(args),
+        Mirror(args) => mirror::parse(args),
+        Audit(args) => audit::parse(args),
+        Link(subcommand) => link::parse(subcommand),
+        Lfs(subcommand) => lfs::parse(subcommand),
+        Upgrade(args) => upgrade::parse(args),
+        Plumbing(subcommand) => plumbing::parse(subcommand),
+        External(args) => external::parse(args)
+    };
+
+    if is_long && output::wants_stats() {
+        output::print_stats(started.elapsed());
     }
+
+    if cli.dry_run && result.is_ok() {
+        output::print_dry_run(guard().take_changes());
+    }
+
+    result
 }
@@ -1,8 +1,11 @@
-use std::io::{stdin, stdout, IsTerminal, Write};
+use std::{collections::{HashSet, VecDeque}, fs, io::{stdin, stdout, IsTerminal, Write}, ops::Range};
 
 use eyre::{bail, Result};
-use libasc::{change::FileChange, repository::Repository, utils::resolve_wildcard_path};
-use relative_path::{PathExt, RelativePath};
+use libasc::{change::FileChange, repository::Repository, staging::StagingArea, unwrap, utils::resolve_wildcard_path};
+use relative_path::{PathExt, RelativePath, RelativePathBuf};
+use similar::{DiffOp, DiffTag, TextDiff};
+
+use crate::{exit::{self, Status}, output::{self, say}};
 
 #[derive(clap::Args)]
 pub struct Args {
@@ -15,7 +18,11 @@ pub struct Args {
 
     /// Replace the staged files with those in the snapshot the head is referencing.
     #[arg(long)]
-    reset: bool
+    reset: bool,
+
+    /// Choose which changes in tracked files to stage, one hunk at a time.
+    #[arg(short, long)]
+    patch: bool
 }
 
 enum PromptResult {
@@ -73,18 +80,269 @@ fn prompt_for_path(path: &RelativePath) -> Result<PromptResult> {
     Ok(result)
 }
 
+fn check_is_terminal() -> Result<()> {
+    if !stdin().is_terminal() {
+        bail!("failed to prompt about hunk: stdin is not connected to a tty");
+    }
+
+    if !stdout().is_terminal() {
+        bail!("failed to prompt about hunk: stdout is not connected to a tty");
+    }
+
+    Ok(())
+}
+
+enum HunkChoice {
+    Yes,
+    No,
+    Split,
+    Quit
+}
+
+fn prompt_for_hunk(can_split: bool) -> Result<HunkChoice> {
+    let mut stdout = stdout();
+
+    let options = if can_split { "y,n,s,q" } else { "y,n,q" };
+
+    loop {
+        let prompt = format!("Stage this hunk? [{options}] ");
+
+        stdout.write_all(prompt.as_bytes())?;
+
+        stdout.flush()?;
+
+        let mut input = String::new();
+
+        stdin().read_line(&mut input)?;
+
+        return Ok(match input.trim() {
+            "y" => HunkChoice::Yes,
+            "n" => HunkChoice::No,
+            "s" if can_split => HunkChoice::Split,
+            "q" => HunkChoice::Quit,
+
+            other => {
+                eprintln!("Invalid input: {other:?}");
+
+                continue;
+            }
+        });
+    }
+}
+
+/// Print a hunk in the style of a unified diff.
+fn print_hunk(group: &[DiffOp], old: &[&str], new: &[&str]) {
+    let first = group.first().unwrap();
+    let last = group.last().unwrap();
+
+    let old_range = first.old_range().start .. last.old_range().end;
+    let new_range = first.new_range().start .. last.new_range().end;
+
+    println!(
+        "@@ -{},{} +{},{} @@",
+        old_range.start + 1, old_range.len(),
+        new_range.start + 1, new_range.len()
+    );
+
+    for op in group {
+        let (tag, old_range, new_range) = op.as_tag_tuple();
+
+        match tag {
+            DiffTag::Equal => print_lines(' ', &old[old_range]),
+            DiffTag::Delete => print_lines('-', &old[old_range]),
+            DiffTag::Insert => print_lines('+', &new[new_range]),
+            DiffTag::Replace => {
+                print_lines('-', &old[old_range]);
+                print_lines('+', &new[new_range]);
+            }
+        }
+    }
+}
+
+fn print_lines(prefix: char, lines: &[&str]) {
+    for line in lines {
+        println!("{prefix}{}", line.strip_suffix('\n').unwrap_or(line));
+    }
+}
+
+/// Split a hunk into one hunk per change, dropping the context lines.
+fn split_hunk(group: &[DiffOp]) -> Vec<Vec<DiffOp>> {
+    group.iter()
+        .filter(|op| op.tag() != DiffTag::Equal)
+        .map(|op| vec![*op])
+        .collect()
+}
+
+/// Ask which hunks between the staged and working content of a file to stage.
+/// 
+/// Returns the content to stage (if any hunks were chosen), and whether the user quit.
+fn choose_hunks(path: &RelativePath, staged: &str, working: &str) -> Result<(Option<String>, bool)> {
+    let diff = TextDiff::from_lines(staged, working);
+
+    let old = diff.old_slices();
+    let new = diff.new_slices();
+
+    let mut pending: VecDeque<Vec<DiffOp>> = diff.grouped_ops(3).into();
+
+    // The old ranges of the changes that were accepted.
+    let mut accepted: HashSet<Range<usize>> = HashSet::new();
+
+    let mut quit = false;
+
+    if pending.is_empty() {
+        return Ok((None, false));
+    }
+
+    println!("--- {path}");
+
+    while let Some(group) = pending.pop_front() {
+        print_hunk(&group, old, new);
+
+        let changes = group.iter().filter(|op| op.tag() != DiffTag::Equal).count();
+
+        match prompt_for_hunk(changes > 1)? {
+            HunkChoice::Yes => {
+                accepted.extend(
+                    group.iter()
+                        .filter(|op| op.tag() != DiffTag::Equal)
+                        .map(|op| op.old_range())
+                );
+            }
+
+            HunkChoice::No => {}
+
+            HunkChoice::Split => {
+                for hunk in split_hunk(&group).into_iter().rev() {
+                    pending.push_front(hunk);
+                }
+            }
+
+            HunkChoice::Quit => {
+                quit = true;
+
+                break;
+            }
+        }
+    }
+
+    if accepted.is_empty() {
+        return Ok((None, quit));
+    }
+
+    let mut content = String::new();
+
+    for op in diff.ops() {
+        let lines = if op.tag() == DiffTag::Equal || !accepted.contains(&op.old_range()) {
+            &old[op.old_range()]
+        }
+        else {
+            &new[op.new_range()]
+        };
+
+        content.extend(lines.iter().copied());
+    }
+
+    Ok((Some(content), quit))
+}
+
+/// Stage changes in tracked files one hunk at a time.
+fn add_patch(repo: &mut Repository, globs: Vec<String>) -> Result<()> {
+    check_is_terminal()?;
+
+    let mut paths: Vec<RelativePathBuf> = vec![];
+
+    if globs.is_empty() {
+        paths.extend(repo.staging.paths().cloned());
+    }
+    else {
+        for glob in globs {
+            for result in resolve_wildcard_path(repo.root_dir.join(glob.as_str()))? {
+                let relative = result.relative_to(&repo.root_dir)?;
+
+                if repo.staging.contains(&relative) {
+                    paths.push(relative);
+                }
+            }
+        }
+    }
+
+    let current = repo.fetch_current_snapshot()?;
+
+    let mut staged_count = 0;
+
+    for path in paths {
+        let full_path = path.to_logical_path(&repo.root_dir);
+
+        if !full_path.is_file() {
+            continue;
+        }
+
+        let working = unwrap!(
+            fs::read_to_string(&full_path),
+            "could not read from path: {path}"
+        );
+
+        let staged = match repo.staging.get(&path) {
+            Some(hash) => repo.fetch_string_content(hash)?,
+            None => String::new()
+        };
+
+        let (content, quit) = choose_hunks(&path, &staged, &working)?;
+
+        if let Some(content) = content {
+            let hash = repo.save_content(&content, current.files.get(&path).copied())?;
+
+            repo.staging.stage(path.clone(), hash);
+
+            staged_count += 1;
+        }
+
+        if quit {
+            break;
+        }
+    }
+
+    repo.save()?;
+
+    say!("Staged changes in {staged_count} files.");
+
+    Ok(())
+}
+
+/// Stage the working copy of a path, printing whether it is newly tracked,
+/// has restaged content, or was already staged as it is.
+fn stage(repo: &mut Repository, path: RelativePathBuf) -> Result<()> {
+    let previous = repo.staging.get(&path);
+
+    let previous_mode = repo.staging.mode(&path);
+
+    let hash = repo.stage_path(&path)?;
+
+    let mode_changed = repo.staging.mode(&path) != previous_mode;
+
+    match previous {
+        None => say!("{}", FileChange::Added(path)),
+        Some(previous) if previous != hash || mode_changed => say!("{}", FileChange::Edited(path)),
+        Some(_) => eprintln!("{}", FileChange::Skip(path))
+    }
+
+    Ok(())
+}
+
 pub fn parse(args: Args) -> Result<()> {
     let mut repo = Repository::load()?;
     
     if args.reset {
         let latest_snapshot = repo.fetch_current_snapshot()?;
 
-        repo.staged_files = latest_snapshot.files
-            .into_keys()
-            .collect();
+        repo.staging = StagingArea::from_snapshot(&latest_snapshot);
     }
 
-    let initial_length = repo.staged_files.len();
+    if args.patch {
+        return add_patch(&mut repo, args.paths);
+    }
+
+    let initial_length = repo.staging.len();
 
     let mut resolved_paths = vec![];
     
@@ -94,11 +352,15 @@ pub fn parse(args: Args) -> Result<()> {
         let results = resolve_wildcard_path(full)?;
 
         for result in results {
-            resolved_paths.push(result);
+            if !result.is_dir() {
+                resolved_paths.push(result);
+            }
         }
     }
 
     if resolved_paths.is_empty() {
+        exit::set(Status::Clean);
+
         eprintln!("Nothing to add.");
 
         return Ok(());
@@ -109,32 +371,23 @@ pub fn parse(args: Args) -> Result<()> {
     for path in resolved_paths {
         let relative = path.relative_to(&repo.root_dir)?;
 
-        let should_prompt = repo.is_ignored_path(&path) && (args.force || !should_prompt_on_ignored);
+        let should_prompt = repo.is_ignored_path(&path) && !args.force && should_prompt_on_ignored;
 
         if !should_prompt {
-            if repo.staged_files.contains(&relative) {
-                eprintln!("{}", FileChange::Skip(relative));
-            }
-            else {
-                repo.staged_files.push(relative.clone());
+            stage(&mut repo, relative)?;
 
-                println!("{}", FileChange::Added(relative));
-            }
+            continue;
+        }
+
+        if output::is_plain() {
+            eprintln!(" ! Skipped {relative}, since it is ignored. Use '--force' to add it anyway.");
 
             continue;
         }
         
         match prompt_for_path(&relative)? {
             PromptResult::Yes => {
-                if repo.staged_files.contains(&relative) {
-                    eprintln!("Skipping path {relative:?} because it is already tracked...");
-
-                    continue;
-                }
-
-                repo.staged_files.push(relative.clone());
-
-                println!("{}", FileChange::Added(relative));
+                stage(&mut repo, relative)?;
             }
             
             PromptResult::No => {
@@ -142,7 +395,7 @@ pub fn parse(args: Args) -> Result<()> {
             }
             
             PromptResult::All => {
-                repo.staged_files.push(relative);
+                stage(&mut repo, relative)?;
 
                 should_prompt_on_ignored = false;
 
@@ -150,6 +403,8 @@ pub fn parse(args: Args) -> Result<()> {
             }
 
             PromptResult::Reset => {
+                exit::set(Status::Failure);
+
                 eprintln!("Temporary index reset. No files have been added.");
 
                 return Ok(());
@@ -159,9 +414,9 @@ pub fn parse(args: Args) -> Result<()> {
 
     repo.save()?;
     
-    let new_files_added = repo.staged_files.len() - initial_length;
+    let new_files_added = repo.staging.len() - initial_length;
 
-    println!("Added {new_files_added} new files.");
+    say!("Added {new_files_added} new files.");
 
     Ok(())
 }
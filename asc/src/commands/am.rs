@@ -0,0 +1,38 @@
+use std::{fs, path::PathBuf};
+
+use eyre::Result;
+
+use libasc::{patch::Patch, repository::Repository, unwrap};
+
+use crate::commands::apply_patch::apply_series;
+
+#[derive(clap::Args)]
+pub struct Args {
+    /// The mbox files to apply the patches in, in order.
+    #[arg(required = true)]
+    files: Vec<PathBuf>,
+
+    /// Apply patches even if their signature doesn't match
+    /// their changes, re-signing them as your own.
+    #[arg(long)]
+    no_verify: bool
+}
+
+pub fn parse(args: Args) -> Result<()> {
+    let mut repo = Repository::load()?;
+
+    let mut patches = vec![];
+
+    for file in &args.files {
+        let text = unwrap!(
+            fs::read_to_string(file),
+            "failed to read mbox from {}", file.display()
+        );
+
+        for (index, patch) in Patch::from_mbox(&text)?.into_iter().enumerate() {
+            patches.push((format!("{} patch {}", file.display(), index + 1), patch));
+        }
+    }
+
+    apply_series(&mut repo, patches, !args.no_verify)
+}
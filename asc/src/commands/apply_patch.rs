@@ -0,0 +1,101 @@
+use std::{fs, path::PathBuf};
+
+use eyre::Result;
+
+use libasc::{patch::{Patch, Provenance}, repository::Repository, unwrap};
+
+use crate::{commands::stash, exit::{self, Status}, output::say};
+
+#[derive(clap::Args)]
+pub struct Args {
+    /// The patch files to apply, in order.
+    #[arg(required = true)]
+    files: Vec<PathBuf>
+}
+
+/// Add each patch to the current branch in order, named by where it came from.
+///
+/// With `verify`, stop at the first patch whose signature doesn't match
+/// what it changes, instead of re-signing it like any other patch.
+pub fn apply_series(repo: &mut Repository, patches: Vec<(String, Patch)>, verify: bool) -> Result<()> {
+    if repo.has_unsaved_changes()? {
+        exit::set(Status::Changes);
+
+        eprintln!("Cannot apply patches with unsaved changes. Commit or stash them first.");
+
+        return Ok(());
+    }
+
+    let before = repo.current_hash();
+
+    for (name, patch) in patches {
+        let parent = repo.fetch_current_snapshot()?;
+
+        let (snapshot, provenance) = match patch.apply_checked(repo, &parent) {
+            Ok(applied) => applied,
+
+            Err(e) => {
+                exit::set(Status::Conflicts);
+
+                eprintln!(" ! Failed to apply {name}: {e}");
+
+                break;
+            }
+        };
+
+        if verify && provenance == Provenance::BadSignature {
+            exit::set(Status::Denied);
+
+            eprintln!(" ! Refusing to apply {name}, since its signature doesn't match its changes.");
+            eprintln!("   It was changed after {} made it. Use --no-verify to apply it anyway.", patch.author_name);
+
+            break;
+        }
+
+        let hash = repo.append_snapshot(snapshot)?;
+
+        match provenance {
+            Provenance::Verified => {
+                say!(" * Applied {name} as {hash}: {}", patch.subject());
+            },
+
+            Provenance::UnknownAuthor => {
+                say!(" * Applied {name} as {hash} (re-signed from {}, by unknown user {}): {}", patch.hash, patch.author_name, patch.subject());
+            },
+
+            Provenance::BadSignature => {
+                say!(" * Applied {name} as {hash} (re-signed from {}, which had a bad signature): {}", patch.hash, patch.subject());
+            },
+
+            Provenance::Unchecked => {
+                say!(" * Applied {name} as {hash} (re-signed from {}): {}", patch.hash, patch.subject());
+            }
+        }
+    }
+
+    match repo.follow_head(before) {
+        Ok(backup) => stash::print_backup(backup),
+        Err(e) => eprintln!(" ! Working directory was left at {before}: {e}")
+    }
+
+    repo.save()?;
+
+    Ok(())
+}
+
+pub fn parse(args: Args) -> Result<()> {
+    let mut repo = Repository::load()?;
+
+    let mut patches = vec![];
+
+    for file in &args.files {
+        let text = unwrap!(
+            fs::read_to_string(file),
+            "failed to read patch from {}", file.display()
+        );
+
+        patches.push((file.display().to_string(), text.parse()?));
+    }
+
+    apply_series(&mut repo, patches, false)
+}
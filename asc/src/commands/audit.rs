@@ -0,0 +1,80 @@
+use chrono::{TimeDelta, Utc};
+use eyre::{eyre, Result};
+use libasc::{repository::Repository, sync::audit::{self, AuditFilter}, utils::parse_duration};
+
+use crate::{commands::history::resolve_author, output};
+
+#[derive(clap::Args)]
+pub struct Args {
+    /// Only show what this user did, given by their name or public key.
+    #[arg(long)]
+    user: Option<String>,
+
+    /// Only show what happened in this much time, like `2d` or `1w`.
+    #[arg(long, value_name = "AGE")]
+    since: Option<String>,
+
+    /// Only show one kind of event: `login`, `pull`, `push`,
+    /// `clone`, `mirror` or `branch_update`.
+    #[arg(long, value_name = "KIND")]
+    event: Option<String>,
+
+    /// Only show this many of the newest records.
+    #[arg(short = 'n', long)]
+    limit: Option<usize>
+}
+
+pub fn parse(args: Args) -> Result<()> {
+    let repo = Repository::load()?;
+
+    let user = match &args.user {
+        Some(query) => Some(resolve_author(&repo, query)?),
+        None => None
+    };
+
+    let since = match &args.since {
+        Some(raw) => {
+            let ago = parse_duration(raw)?;
+
+            let since = TimeDelta::from_std(ago)
+                .ok()
+                .and_then(|ago| Utc::now().checked_sub_signed(ago))
+                .ok_or_else(|| eyre!("{raw:?} is too long ago"))?;
+
+            Some(since)
+        }
+
+        None => None
+    };
+
+    let filter = AuditFilter {
+        user,
+        since,
+        kind: args.event
+    };
+
+    let mut records: Vec<_> = audit::read_log(&repo)?
+        .into_iter()
+        .filter(|record| filter.matches(record))
+        .collect();
+
+    if let Some(limit) = args.limit {
+        records.drain(..records.len().saturating_sub(limit));
+    }
+
+    if output::is_json() {
+        return output::print_json(&records);
+    }
+
+    if records.is_empty() {
+        eprintln!("Nothing in the audit log matches.");
+
+        return Ok(());
+    }
+
+    for record in records {
+        println!("{record}");
+    }
+
+    Ok(())
+}
@@ -1,105 +1,109 @@
-use std::{collections::VecDeque, rc::Rc};
+mod browser;
+
+use std::collections::{HashMap, hash_map::Entry};
 
-use chrono::{DateTime, Utc};
 use eyre::Result;
 use relative_path::RelativePathBuf;
 use unicode_width::UnicodeWidthStr;
 
-use libasc::{hash::ObjectHash, repository::Repository, unwrap};
+use serde::Serialize;
+
+use libasc::{blame::blame, hash::ObjectHash, repository::Repository, snapshot::Snapshot};
 
-// TODO: write your own
-use blame_rs::{BlameRevision, blame};
+use crate::{exit::{self, Status}, output};
+
+/// A line of the file, as printed with `--format json`.
+#[derive(Serialize)]
+struct LineEntry<'a> {
+    line: usize,
+    hash: String,
+    author: Option<&'a str>,
+    timestamp: String,
+    content: &'a str
+}
 
 #[derive(clap::Args)]
 pub struct Args {
     /// The path to perform the blame on.
-    path: RelativePathBuf
-}
-
-#[derive(Debug)]
-struct CommitInfo<'a> {
-    hash: ObjectHash,
-    author: &'a str,
-    timestamp: DateTime<Utc>,
-}
+    path: RelativePathBuf,
 
-struct SnapshotData {
-    hash: ObjectHash,
-    author: String,
-    timestamp: DateTime<Utc>,
-    content: String
+    /// Browse the blame interactively, following lines
+    /// back to the versions of the file before them.
+    #[arg(short, long)]
+    interactive: bool
 }
 
 pub fn parse(args: Args) -> Result<()> {
     let repo = Repository::load()?;
 
-    if !repo.staged_files.contains(&args.path) {
+    if !repo.staging.contains(&args.path) {
         eprintln!("Path {} is not staged in the repository.", &args.path);
     }
 
-    let mut queue: VecDeque<ObjectHash> = VecDeque::new();
+    if args.interactive {
+        if output::is_plain() || output::is_json() {
+            exit::set(Status::Usage);
 
-    queue.push_back(repo.current_hash);
+            eprintln!("The blame browser can't be used with plain or JSON output.");
 
-    let mut snapshots: Vec<SnapshotData> = vec![];
-
-    while let Some(next) = queue.pop_front() {
-        let parents = unwrap!(
-            repo.history.get_parents(next),
-            "could not get hash of {next:?} in repository"
-        );
-        
-        if parents.is_empty() {
-            continue;
+            return Ok(());
         }
 
-        queue.extend(parents);
+        return browser::run(&repo, repo.current_hash(), &args.path);
+    }
 
-        let snapshot = repo.fetch_snapshot(next)?;
+    let lines = blame(&repo, repo.current_hash(), &args.path)?;
 
-        let Some(&content_hash) = snapshot.files.get(&args.path) else { continue };
+    let mut snapshots: HashMap<ObjectHash, Snapshot> = HashMap::new();
 
-        snapshots.push(SnapshotData {
-            hash: snapshot.hash,
-            author: snapshot.author.to_string(),
-            timestamp: snapshot.timestamp,
-            content: repo.fetch_string_content(content_hash)?
-        });
+    for line in &lines {
+        if let Entry::Vacant(entry) = snapshots.entry(line.origin) {
+            entry.insert(repo.fetch_snapshot(line.origin)?);
+        }
     }
 
-    let mut revisions: Vec<BlameRevision<CommitInfo>> = vec![];
-
-    for data in &snapshots {
-        revisions.push(BlameRevision {
-            content: &data.content,
-            metadata: Rc::new(CommitInfo {
-                hash: data.hash,
-                author: &data.author,
-                timestamp: data.timestamp
+    if output::is_json() {
+        let entries: Vec<LineEntry> = lines
+            .iter()
+            .enumerate()
+            .map(|(index, line)| {
+                let snapshot = &snapshots[&line.origin];
+
+                LineEntry {
+                    line: index + 1,
+                    hash: snapshot.hash.full(),
+                    author: repo.users.get_user(&snapshot.author).map(|user| user.name.as_str()),
+                    timestamp: snapshot.timestamp.to_rfc3339(),
+                    content: &line.content
+                }
             })
-        });
+            .collect();
+
+        return output::print_json(&entries);
     }
 
-    let result = blame(&revisions)?;
+    let author_of = |hash: &ObjectHash| {
+        repo.users
+            .get_user(&snapshots[hash].author)
+            .map(|user| user.name.as_str())
+            .unwrap_or("<unknown user>")
+    };
 
-    let max_author_width = result
-        .lines()
+    let max_author_width = lines
         .iter()
-        .fold(0, |total, line| {
-            total + line.revision_metadata.author.width()
-        });
+        .map(|line| author_of(&line.origin).width())
+        .max()
+        .unwrap_or_default();
 
-    for line in result.lines() {
-        let data = line.revision_metadata.clone();
+    for line in &lines {
+        let snapshot = &snapshots[&line.origin];
 
-        let mut author = data.author.to_string();
+        let author = author_of(&line.origin);
 
-        for _ in 0 .. (max_author_width - author.width()) {
-            author.push(' ');
-        }
+        let padding = " ".repeat(max_author_width - author.width());
 
-        println!("{}    {}    {author}    {}", data.hash, data.timestamp, line.content);
+        println!("{}    {}    {author}{padding}    {}", snapshot.hash, snapshot.timestamp, line.content);
     }
-    
+
     Ok(())
 }
@@ -0,0 +1,323 @@
+//! An interactive view of `asc blame`, for following a line back through
+//! the versions of a file: choosing a line shows the file as it was just
+//! before the snapshot that last changed that line.
+
+use std::{collections::HashMap, io::{stdin, stdout, IsTerminal, Stdout, Write}};
+
+use crossterm::{cursor, event::{self, Event, KeyCode, KeyEvent, KeyEventKind}, execute, queue, style::{Attribute, Print, SetAttribute}, terminal::{self, ClearType}};
+use eyre::{bail, Result};
+use relative_path::{RelativePath, RelativePathBuf};
+use unicode_width::UnicodeWidthChar;
+
+use libasc::{blame::{blame, BlameLine}, hash::ObjectHash, repository::Repository, snapshot::Snapshot};
+
+/// The file as it was at one version, and where in it the user is.
+struct View {
+    version: ObjectHash,
+    lines: Vec<BlameLine>,
+    selected: usize,
+    top: usize
+}
+
+/// Puts the terminal back how it was when dropped, even after an error.
+struct Screen {
+    out: Stdout
+}
+
+impl Screen {
+    fn enter() -> Result<Screen> {
+        let mut out = stdout();
+
+        terminal::enable_raw_mode()?;
+
+        execute!(out, terminal::EnterAlternateScreen, cursor::Hide)?;
+
+        Ok(Screen { out })
+    }
+}
+
+impl Drop for Screen {
+    fn drop(&mut self) {
+        let _ = execute!(self.out, cursor::Show, terminal::LeaveAlternateScreen);
+
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+struct Browser<'repo> {
+    repo: &'repo Repository,
+    path: RelativePathBuf,
+    snapshots: HashMap<ObjectHash, Snapshot>,
+
+    /// The views gone back from, most recent last.
+    back: Vec<View>,
+    current: View,
+    forward: Vec<View>,
+
+    /// Said at the bottom of the screen until the next key is pressed.
+    status: Option<String>
+}
+
+/// Cut a line down to fit in `width` columns.
+fn fit(text: &str, width: usize) -> String {
+    let mut used = 0;
+
+    text.chars()
+        .map(|c| if c == '\t' { ' ' } else { c })
+        .take_while(|c| {
+            used += c.width().unwrap_or(0);
+
+            used <= width
+        })
+        .collect()
+}
+
+impl<'repo> Browser<'repo> {
+    fn new(repo: &'repo Repository, version: ObjectHash, path: &RelativePath) -> Result<Browser<'repo>> {
+        let mut browser = Browser {
+            repo,
+            path: path.to_relative_path_buf(),
+            snapshots: HashMap::new(),
+            back: vec![],
+            current: View { version, lines: vec![], selected: 0, top: 0 },
+            forward: vec![],
+            status: None
+        };
+
+        browser.current = browser.view(version, 0)?;
+
+        Ok(browser)
+    }
+
+    fn snapshot(&mut self, hash: ObjectHash) -> Result<&Snapshot> {
+        if !self.snapshots.contains_key(&hash) {
+            let snapshot = self.repo.fetch_snapshot(hash)?;
+
+            self.snapshots.insert(hash, snapshot);
+        }
+
+        Ok(&self.snapshots[&hash])
+    }
+
+    /// Blame the file at `version`, with the line at `selected` chosen.
+    fn view(&mut self, version: ObjectHash, selected: usize) -> Result<View> {
+        let lines = blame(self.repo, version, &self.path)?;
+
+        for line in &lines {
+            self.snapshot(line.origin)?;
+        }
+
+        let selected = selected.min(lines.len().saturating_sub(1));
+
+        Ok(View { version, lines, selected, top: selected.saturating_sub(5) })
+    }
+
+    fn author(&self, hash: ObjectHash) -> &str {
+        self.repo.users
+            .get_user(&self.snapshots[&hash].author)
+            .map(|user| user.name.as_str())
+            .unwrap_or("<unknown user>")
+    }
+
+    /// Show the file as it was before the snapshot that made the selected line.
+    fn open_parent(&mut self) -> Result<()> {
+        let Some(line) = self.current.lines.get(self.current.selected) else {
+            return Ok(());
+        };
+
+        let origin = line.origin;
+
+        let snapshot = self.snapshot(origin)?.clone();
+
+        let Some(parent) = self.repo.first_parent(&snapshot)? else {
+            self.status = Some(format!("{origin} is the first snapshot, so there is nothing before it."));
+
+            return Ok(());
+        };
+
+        let existed = self.repo
+            .fetch_snapshot(parent)?
+            .files
+            .contains_key(&self.path);
+
+        if !existed {
+            self.status = Some(format!("{} didn't exist before {origin}.", self.path));
+
+            return Ok(());
+        }
+
+        let view = self.view(parent, self.current.selected)?;
+
+        self.back.push(std::mem::replace(&mut self.current, view));
+
+        self.forward.clear();
+
+        self.status = Some(format!("Before {origin}: {}", snapshot.message.lines().next().unwrap_or_default()));
+
+        Ok(())
+    }
+
+    fn go_back(&mut self) {
+        match self.back.pop() {
+            Some(view) => self.forward.push(std::mem::replace(&mut self.current, view)),
+            None => self.status = Some("Nothing to go back to.".to_string())
+        }
+    }
+
+    fn go_forward(&mut self) {
+        match self.forward.pop() {
+            Some(view) => self.back.push(std::mem::replace(&mut self.current, view)),
+            None => self.status = Some("Nothing to go forward to.".to_string())
+        }
+    }
+
+    fn select(&mut self, by: isize) {
+        let last = self.current.lines.len().saturating_sub(1);
+
+        self.current.selected = self.current.selected
+            .saturating_add_signed(by)
+            .min(last);
+    }
+
+    fn draw(&mut self, out: &mut Stdout) -> Result<()> {
+        let (width, height) = terminal::size()?;
+
+        let (width, height) = (width as usize, height as usize);
+
+        // One line at the top for the file, and three at the bottom for
+        // the selected line's snapshot, the status and the keys.
+        let rows = height.saturating_sub(4).max(1);
+
+        let view = &mut self.current;
+
+        if view.selected < view.top {
+            view.top = view.selected;
+        }
+        else if view.selected >= view.top + rows {
+            view.top = view.selected + 1 - rows;
+        }
+
+        let view = &self.current;
+
+        queue!(out, terminal::Clear(ClearType::All), cursor::MoveTo(0, 0))?;
+
+        let mut header = format!("{} at {}", self.path, view.version);
+
+        if !self.back.is_empty() || !self.forward.is_empty() {
+            header = format!("{header} ({} back, {} forward)", self.back.len(), self.forward.len());
+        }
+
+        queue!(out, SetAttribute(Attribute::Bold), Print(fit(&header, width)), SetAttribute(Attribute::Reset))?;
+
+        let author_width = view.lines
+            .iter()
+            .map(|line| self.author(line.origin).chars().count())
+            .max()
+            .unwrap_or_default()
+            .min(16);
+
+        let number_width = view.lines.len().to_string().len();
+
+        for (row, (index, line)) in view.lines.iter().enumerate().skip(view.top).take(rows).enumerate() {
+            let author = fit(self.author(line.origin), author_width);
+
+            let text = format!("{:>number_width$} {} {author:<author_width$} {}", index + 1, line.origin, line.content);
+
+            queue!(out, cursor::MoveTo(0, row as u16 + 1))?;
+
+            if index == view.selected {
+                queue!(out, SetAttribute(Attribute::Reverse), Print(format!("{:<width$}", fit(&text, width))), SetAttribute(Attribute::Reset))?;
+            }
+            else {
+                queue!(out, Print(fit(&text, width)))?;
+            }
+        }
+
+        if let Some(line) = view.lines.get(view.selected) {
+            let snapshot = &self.snapshots[&line.origin];
+
+            let about = format!(
+                "{} by {} on {}: {}",
+                snapshot.hash,
+                self.author(line.origin),
+                snapshot.timestamp.format("%Y-%m-%d %H:%M"),
+                snapshot.message.lines().next().unwrap_or_default()
+            );
+
+            queue!(out, cursor::MoveTo(0, height.saturating_sub(3) as u16), Print(fit(&about, width)))?;
+        }
+
+        if let Some(status) = &self.status {
+            queue!(out, cursor::MoveTo(0, height.saturating_sub(2) as u16), Print(fit(status, width)))?;
+        }
+
+        let keys = "enter: before this line's snapshot  left/b: back  right/f: forward  up/down: move  q: quit";
+
+        queue!(
+            out,
+            cursor::MoveTo(0, height.saturating_sub(1) as u16),
+            SetAttribute(Attribute::Dim),
+            Print(fit(keys, width)),
+            SetAttribute(Attribute::Reset)
+        )?;
+
+        out.flush()?;
+
+        Ok(())
+    }
+
+    /// Handle a key, returning `false` once the browser should close.
+    fn handle(&mut self, key: KeyEvent, page: isize) -> Result<bool> {
+        self.status = None;
+
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => return Ok(false),
+
+            KeyCode::Up | KeyCode::Char('k') => self.select(-1),
+            KeyCode::Down | KeyCode::Char('j') => self.select(1),
+            KeyCode::PageUp => self.select(-page),
+            KeyCode::PageDown => self.select(page),
+            KeyCode::Home | KeyCode::Char('g') => self.select(isize::MIN),
+            KeyCode::End | KeyCode::Char('G') => self.select(isize::MAX),
+
+            KeyCode::Enter | KeyCode::Char('p') => self.open_parent()?,
+            KeyCode::Left | KeyCode::Backspace | KeyCode::Char('b') => self.go_back(),
+            KeyCode::Right | KeyCode::Char('f') => self.go_forward(),
+
+            _ => {}
+        }
+
+        Ok(true)
+    }
+}
+
+/// Browse the blame of `path` at `version` until the user quits.
+pub fn run(repo: &Repository, version: ObjectHash, path: &RelativePath) -> Result<()> {
+    if !stdin().is_terminal() || !stdout().is_terminal() {
+        bail!("the blame browser needs a terminal to run in");
+    }
+
+    let mut browser = Browser::new(repo, version, path)?;
+
+    let mut screen = Screen::enter()?;
+
+    loop {
+        browser.draw(&mut screen.out)?;
+
+        let Event::Key(key) = event::read()? else {
+            continue;
+        };
+
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+
+        let page = terminal::size()?.1.saturating_sub(4).max(1) as isize;
+
+        if !browser.handle(key, page)? {
+            break;
+        }
+    }
+
+    Ok(())
+}
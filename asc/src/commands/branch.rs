@@ -1,7 +1,9 @@
-use color_eyre::owo_colors::OwoColorize;
+use chrono::Utc;
 use eyre::Result;
 
-use libasc::{action::Action, repository::Repository, utils::filter_with_glob};
+use libasc::{action::Action, hash::ObjectHash, refname, refs::{Ref, RefKind}, repository::Repository, utils::{filter_with_glob, parse_duration}};
+
+use crate::{commands::stash, exit::{self, Status}, i18n::t, output::{self, RefEntry}};
 
 #[derive(clap::Subcommand)]
 pub enum Subcommands {
@@ -16,7 +18,17 @@ pub enum Subcommands {
 
         /// The version this branch refers to.
         /// Defaults to the current version.
-        basis: Option<String>
+        basis: Option<String>,
+
+        /// Make the branch ephemeral: `asc clean` deletes it once it is merged
+        /// into another branch, and `asc push` skips it unless asked.
+        #[arg(long)]
+        ephemeral: bool,
+
+        /// How long the ephemeral branch lasts before `asc clean` deletes it
+        /// even if it isn't merged, like `14d` or `12h`.
+        #[arg(long, requires = "ephemeral")]
+        ttl: Option<String>
     },
 
     /// Move a branch to point to another commit.
@@ -49,16 +61,65 @@ pub enum Subcommands {
         new: String
     },
 
-    /// List all the branches in the repository.
+    /// Clean up the backups kept when pulling replaced a branch that had
+    /// diverged from the remote. Without `--merged`, they are only listed.
+    Cleanup {
+        /// Delete the backups whose snapshots are all on a branch.
+        #[arg(long)]
+        merged: bool
+    },
+
+    /// Show or set the branch that clones of this repository check out,
+    /// unless they pass `--branch`. Without one, clones check out the
+    /// branch that is checked out here.
+    Default {
+        /// The branch to set as the default.
+        name: Option<String>,
+
+        /// Go back to using the branch that is checked out here.
+        #[arg(long, conflicts_with = "name")]
+        unset: bool
+    },
+
+    /// List all the branches in the repository, with
+    /// the hashes they point to when `--verbose` is passed.
     #[command(visible_alias = "ls")]
     List {
         /// Globs to filter the search.
-        globs: Option<Vec<String>>,
+        globs: Option<Vec<String>>
+    }
+}
+
+/// Describe how far a branch is ahead of and behind where
+/// it was last seen on each remote, like `origin/main: ahead 2`.
+pub fn describe_upstreams(repo: &Repository, name: &str) -> Result<Vec<String>> {
+    let Some(tip) = repo.refs.branches().get(name) else {
+        return Ok(vec![]);
+    };
+
+    let mut described = vec![];
 
-        /// Include the hashes each branch points to.
-        #[arg(short, long)]
-        verbose: bool
+    for (upstream, remote_tip) in repo.upstreams(name) {
+        let (ahead, behind) = repo.history.ahead_behind(tip, remote_tip)?;
+
+        let mut state = vec![];
+
+        if ahead > 0 {
+            state.push(t!("branch-ahead", count = ahead));
+        }
+
+        if behind > 0 {
+            state.push(t!("branch-behind", count = behind));
+        }
+
+        if state.is_empty() {
+            state.push(t!("branch-up-to-date"));
+        }
+
+        described.push(t!("branch-upstream", upstream = upstream, state = state.join(", ")));
     }
+
+    Ok(described)
 }
 
 pub fn parse(command: Subcommands) -> Result<()> {
@@ -72,36 +133,60 @@ pub fn parse(command: Subcommands) -> Result<()> {
                 println!("{name}");
             }
             else {
-                println!("HEAD detached at {}", repo.current_hash);
+                println!("{}", t!("branch-detached", hash = repo.current_hash()));
             }
         }
 
-        New { name, basis } => {
+        New { name, basis, ephemeral, ttl } => {
+            let expires = match ttl {
+                Some(raw) => Some(Utc::now() + parse_duration(&raw)?),
+                None => None
+            };
+
             let base_version = if let Some(version) = basis {
                 repo.normalise_version(&version)?
             }
             else {
-                repo.current_hash
+                repo.current_hash()
             };
             
-            if repo.branches.contains(&name) {
-                eprintln!("Branch {name:?} already exists.");
+            if repo.refs.branches().contains(&name) {
+                exit::set(Status::Failure);
+
+                eprintln!("{}", t!("branch-exists", name = name));
+
+                return Ok(());
+            }
+
+            if let Err(e) = refname::check_branch(&name, &repo.config) {
+                exit::set(Status::Failure);
+
+                eprintln!("{}", t!("branch-invalid-name", error = e));
 
                 return Ok(());
             }
 
-            if let Some(branch_name) = repo.branches.get_name_for(base_version) {
-                println!("Created new branch: {name} -> {branch_name} ({base_version})");
+            if let Some(branch_name) = repo.refs.branches().get_name_for(base_version) {
+                println!("{}", t!("branch-created-from-branch", name = name, basis = branch_name, hash = base_version));
             }
             else {
-                println!("Created new branch: {name} -> {base_version}");
+                println!("{}", t!("branch-created", name = name, hash = base_version));
             }
 
-            repo.branches.create(name.clone(), base_version);
+            repo.refs.branches_mut().create(name.clone(), base_version);
+
+            if ephemeral {
+                repo.refs.mark_ephemeral(&name, expires);
+
+                match expires {
+                    Some(expires) => println!("{}", t!("branch-ephemeral-expires", name = name, expires = expires.format("%Y-%m-%d %H:%M"))),
+                    None => println!("{}", t!("branch-ephemeral", name = name))
+                }
+            }
 
             repo.action_history.push(
                 Action::CreateBranch {
-                    hash: repo.current_hash,
+                    hash: base_version,
                     name
                 }
             );
@@ -110,13 +195,40 @@ pub fn parse(command: Subcommands) -> Result<()> {
         Move { name, new } => {
             let version = repo.normalise_version(&new)?;
 
-            let Some(previous) = repo.branches.remove(&name) else {
-                eprintln!("Branch {new:?} does not exist.");
+            let Some(previous) = repo.refs.branches().get(&name) else {
+                exit::set(Status::Failure);
+
+                eprintln!("{}", t!("branch-not-found", name = name));
 
                 return Ok(());
             };
 
-            repo.branches.create(name.clone(), version);
+            if previous == version {
+                exit::set(Status::Clean);
+
+                eprintln!("{}", t!("branch-move-unchanged", name = name, hash = version));
+
+                return Ok(());
+            }
+
+            // The working directory has to follow the branch the head is on.
+            let is_current = repo.current_branch() == Some(name.as_str());
+
+            if is_current && repo.has_unsaved_changes()? {
+                exit::set(Status::Changes);
+
+                eprintln!("{}", t!("branch-move-unsaved-changes"));
+
+                return Ok(());
+            }
+
+            repo.refs.branches_mut().create(name.clone(), version);
+
+            if is_current {
+                stash::print_backup(repo.follow_head(previous)?);
+            }
+
+            println!("{}", t!("branch-moved", name = name, old = previous, new = version));
 
             repo.action_history.push(
                 Action::MoveBranch {
@@ -128,15 +240,33 @@ pub fn parse(command: Subcommands) -> Result<()> {
         }
 
         Rename { old, new } => {
-            let Some(commit_hash) = repo.branches.remove(&old) else {
-                eprintln!("Branch {old:?} does not exist.");
+            let Some(commit_hash) = repo.refs.branches().get(&old) else {
+                exit::set(Status::Failure);
+
+                eprintln!("{}", t!("branch-not-found", name = old));
 
                 return Ok(());
             };
 
-            println!("Renamed: {old} -> {new}");
+            if repo.refs.branches().contains(&new) {
+                exit::set(Status::Failure);
 
-            repo.branches.create(new.clone(), commit_hash);
+                eprintln!("{}", t!("branch-exists", name = new));
+
+                return Ok(());
+            }
+
+            if let Err(e) = refname::check_branch(&new, &repo.config) {
+                exit::set(Status::Failure);
+
+                eprintln!("{}", t!("branch-invalid-name", error = e));
+
+                return Ok(());
+            }
+
+            repo.rename_branch(&old, new.clone())?;
+
+            println!("{}", t!("branch-renamed", old = old, new = new));
 
             repo.action_history.push(
                 Action::RenameBranch {
@@ -149,8 +279,8 @@ pub fn parse(command: Subcommands) -> Result<()> {
 
         Delete { names, keep_going } => {
             for name in names {
-                let Some(was_pointing_to) = repo.branches.remove(&name) else {
-                    eprintln!("Branch {name:?} does not exist.");
+                let Some(was_pointing_to) = repo.refs.branches_mut().remove(&name) else {
+                    eprintln!("{}", t!("branch-not-found", name = name));
 
                     if keep_going {
                         continue;
@@ -159,7 +289,7 @@ pub fn parse(command: Subcommands) -> Result<()> {
                     return Ok(());
                 };
 
-                println!("Branch {name:?} no longer points to {was_pointing_to}.");
+                println!("{}", t!("branch-deleted", name = name, hash = was_pointing_to));
 
                 repo.action_history.push(
                     Action::DeleteBranch {
@@ -170,30 +300,138 @@ pub fn parse(command: Subcommands) -> Result<()> {
             }
         }
 
-        List { globs, verbose } => {
+        Cleanup { merged } => {
+            let backups: Vec<(String, ObjectHash)> = repo.refs
+                .namespace(RefKind::Backup)
+                .iter()
+                .map(|(name, hash)| (name.to_string(), hash))
+                .collect();
+
+            if backups.is_empty() {
+                exit::set(Status::Clean);
+
+                println!("{}", t!("branch-cleanup-none"));
+
+                return Ok(());
+            }
+
+            let on_branches = repo.history.reachable(repo.refs.branches().values(), |_| false)?;
+
+            let mut deleted = 0;
+
+            for (name, hash) in &backups {
+                let is_merged = on_branches.contains(hash);
+
+                if merged && is_merged {
+                    repo.refs.remove(&Ref::Backup(name.clone()));
+
+                    println!("{}", t!("branch-cleanup-deleted", name = name, hash = hash));
+
+                    deleted += 1;
+                }
+                else if !merged {
+                    match is_merged {
+                        true => println!("{}", t!("branch-cleanup-merged", name = name, hash = hash)),
+                        false => println!("{}", t!("branch-cleanup-unmerged", name = name, hash = hash))
+                    }
+                }
+            }
+
+            if !merged {
+                println!("{}", t!("branch-cleanup-hint"));
+            }
+            else if deleted == 0 {
+                exit::set(Status::Clean);
+
+                println!("{}", t!("branch-cleanup-none"));
+            }
+        }
+
+        Default { name, unset } => {
+            if unset {
+                repo.default_branch = None;
+
+                println!("{}", t!("branch-default-unset"));
+            }
+            else if let Some(name) = name {
+                if !repo.refs.branches().contains(&name) {
+                    exit::set(Status::Failure);
+
+                    eprintln!("{}", t!("branch-not-found", name = name));
+
+                    return Ok(());
+                }
+
+                println!("{}", t!("branch-default-set", name = name));
+
+                repo.default_branch = Some(name);
+            }
+            else {
+                match repo.default_branch_name() {
+                    Some(name) => println!("{name}"),
+                    None => println!("{}", t!("branch-default-none"))
+                }
+            }
+        }
+
+        List { globs } => {
+            if output::is_json() {
+                let globs = globs.unwrap_or(vec!["**/*".to_string()]);
+
+                let branch_names: Vec<&str> = repo.refs.branches().names().collect();
+
+                let entries: Vec<RefEntry> = filter_with_glob(globs, &branch_names)
+                    .into_iter()
+                    .map(|name| {
+                        let hash = repo.refs.branches().get(name).unwrap();
+
+                        RefEntry {
+                            name,
+                            hash: hash.full(),
+                            current: Some(repo.current_branch() == Some(*name))
+                        }
+                    })
+                    .collect();
+
+                return output::print_json(&entries);
+            }
+
             if repo.is_head_detached() {
-                let line = format!(" * HEAD detached at {}", repo.current_hash);
+                let line = format!(" * {}", t!("branch-detached", hash = repo.current_hash()));
 
-                println!("{}", line.bright_green().bold());
+                println!("{}", output::highlight(line));
             }
 
             let globs = globs.unwrap_or(vec!["**/*".to_string()]);
 
-            let branch_names: Vec<&String> = repo.branches.names().collect();
+            let branch_names: Vec<&str> = repo.refs.branches().names().collect();
 
             let valid = filter_with_glob(globs, &branch_names);
 
             for branch_name in valid {
-                let commit_hash = *repo.branches.get(branch_name).unwrap();
+                let commit_hash = repo.refs.branches().get(branch_name).unwrap();
                 
                 let mut s = format!(" * {branch_name}");
 
-                if verbose {
+                if output::is_verbose() {
                     s = format!("{s} ({commit_hash})");
                 }
+
+                if let Some(ephemeral) = repo.refs.ephemeral(branch_name) {
+                    let label = match ephemeral.expires {
+                        Some(expires) => t!("branch-list-expires", expires = expires.format("%Y-%m-%d")),
+                        None => t!("branch-list-ephemeral")
+                    };
+
+                    s = format!("{s} [{label}]");
+                }
+
+                for upstream in describe_upstreams(&repo, branch_name)? {
+                    s = format!("{s} [{upstream}]");
+                }
                 
-                if repo.current_hash == commit_hash {
-                    s = format!("{}", s.bright_green().bold());
+                if repo.current_hash() == commit_hash {
+                    s = output::current(s);
                 }
                 
                 println!("{s}")
@@ -0,0 +1,128 @@
+use std::path::PathBuf;
+
+use eyre::Result;
+
+use libasc::{bundle::{Bundle, RefUpdate}, refs::Ref, repository::Repository};
+
+use crate::commands::stash;
+
+#[derive(clap::Subcommand)]
+pub enum Subcommands {
+    /// Write snapshots, with the branches and tags pointing to them, to a file.
+    Create {
+        /// The snapshots to bundle, as `BASE..TIP` to leave out BASE and everything
+        /// before it, or as `TIP` for all of its history. Defaults to every branch and tag.
+        range: Option<String>,
+
+        /// The file to write the bundle to.
+        #[arg(short, long)]
+        output: PathBuf
+    },
+
+    /// Add the history in a bundle file to the repository.
+    Apply {
+        /// The bundle file to apply.
+        file: PathBuf
+    }
+}
+
+pub fn parse(subcommand: Subcommands) -> Result<()> {
+    let mut repo = Repository::load()?;
+
+    use Subcommands::*;
+
+    match subcommand {
+        Create { range, output } => {
+            let (tips, bases) = match range.as_deref() {
+                Some(range) => match range.split_once("..") {
+                    Some((base, tip)) => (
+                        vec![repo.normalise_version(tip)?],
+                        vec![repo.normalise_version(base)?]
+                    ),
+
+                    None => (vec![repo.normalise_version(range)?], vec![])
+                },
+
+                None => {
+                    let tips = repo.refs
+                        .branches()
+                        .values()
+                        .chain(repo.refs.tags().values())
+                        .collect();
+
+                    (tips, vec![])
+                }
+            };
+
+            let bundle = Bundle::create(&repo, &tips, &bases)?;
+
+            bundle.save(&repo, &output)?;
+
+            println!(
+                "Bundled {} snapshots, {} branches and {} tags into {}",
+                bundle.snapshot_count(),
+                bundle.branches.len(),
+                bundle.tags.len(),
+                output.display()
+            );
+
+            if !bundle.prerequisites.is_empty() {
+                println!(" * Applying it needs these snapshots first:");
+
+                for hash in &bundle.prerequisites {
+                    println!("   {hash}");
+                }
+            }
+        },
+
+        Apply { file } => {
+            let before = repo.current_hash();
+
+            let bundle = Bundle::load(&repo, &file)?;
+
+            let count = bundle.snapshot_count();
+
+            let updates = bundle.apply(&mut repo)?;
+
+            println!("Applied {count} snapshots from {}", file.display());
+
+            for (r, update) in updates {
+                let (kind, name) = match &r {
+                    Ref::Tag(name) => ("Tag", name.as_str()),
+                    _ => ("Branch", r.name().unwrap_or_default())
+                };
+
+                let line = match update {
+                    RefUpdate::Created(hash) => format!(" * {kind} {name:?} ({hash}) received from the bundle"),
+
+                    RefUpdate::UpToDate => format!(" * {kind} {name:?} is up-to-date"),
+
+                    RefUpdate::FastForward(old, new) => format!(" * Fast-forwarded {name} ({old} -> {new})"),
+
+                    RefUpdate::Diverged(_, _, copy) if kind == "Tag" => {
+                        format!(" ! Tag {name:?} diverges from the bundle - local version is renamed to `{copy}`")
+                    },
+
+                    RefUpdate::Diverged(_, _, backup) => {
+                        format!(" ! Branch {name} diverges from the bundle - local version is kept as `backups/{backup}`")
+                    },
+
+                    RefUpdate::InvalidName(hash, reason) => {
+                        format!(" ! {kind} {name:?} ({hash}) in the bundle is skipped - {reason}")
+                    }
+                };
+
+                println!("{line}");
+            }
+
+            match repo.follow_head(before) {
+                Ok(backup) => stash::print_backup(backup),
+                Err(e) => eprintln!(" ! Working directory was left at {before}: {e}")
+            }
+
+            repo.save()?;
+        }
+    }
+
+    Ok(())
+}
@@ -1,55 +1,175 @@
-use std::io::{stdout, Write};
+use std::{fs, io::{stdout, Write}};
 
 use eyre::Result;
 
-use libasc::{repository::Repository, utils::filter_paths_with_glob};
+use libasc::{blobs::ObjectStore, content::{Content, Delta, Pointer}, hash::ObjectHash, repository::Repository, utils::filter_paths_with_glob};
 use relative_path::RelativePathBuf;
 
+use crate::output::format_size;
+
 #[derive(clap::Args)]
 pub struct Args {
-    /// The paths to display the contents of.
+    /// The paths to display the contents of. A path can be given as
+    /// `<version>:<path>` to take it from that version.
     globs: Vec<String>,
 
-    /// The version of the file to use.
+    /// The version of the file to use, for paths not given with one.
     #[arg(short, long)]
-    version: Option<String>
+    version: Option<String>,
+
+    /// Show how each file's content is stored, instead of the content.
+    #[arg(long)]
+    raw: bool,
+
+    /// Like --raw, but follow each delta back to the
+    /// content it is based on, showing every step.
+    #[arg(long)]
+    delta_chain: bool
+}
+
+/// Split an argument given as `<version>:<path>`, if what
+/// comes before one of its colons is a version.
+fn split_version<'a>(repo: &Repository, arg: &'a str) -> Option<(ObjectHash, &'a str)> {
+    arg.match_indices(':')
+        .map(|(i, _)| (&arg[..i], &arg[i + 1..]))
+        .filter(|(version, _)| !version.is_empty())
+        .find_map(|(version, path)| {
+            repo.normalise_version(version)
+                .ok()
+                .map(|hash| (hash, path))
+        })
+}
+
+/// Describe how a content object is stored, returning the
+/// content it is a delta of, if it is one.
+fn describe_object(repo: &Repository, hash: ObjectHash) -> Result<(String, Option<ObjectHash>)> {
+    let content = repo.fetch_content_object(hash)?;
+
+    let stored = repo.objects().stored_size(hash)?;
+
+    Ok(match content {
+        Content::Literal(data) => {
+            let kind = format!(
+                "Literal, size compressed: {}, size on disk: {}",
+                format_size(data.len() as u64),
+                format_size(stored)
+            );
+
+            (kind, None)
+        },
+
+        Content::Delta(Delta { original, edit, depth }) => {
+            // Older deltas don't record their depth.
+            let depth = match depth {
+                0 => repo.delta_depth(original)? + 1,
+                depth => depth
+            };
+
+            let kind = format!(
+                "Delta based on {original}, edit size: {}, size on disk: {}, chain depth: {depth}",
+                format_size(edit.len() as u64),
+                format_size(stored)
+            );
+
+            (kind, Some(original))
+        },
+
+        Content::Pointer(Pointer { size, .. }) => {
+            let kind = if repo.has_large_file(hash) {
+                let compressed = fs::metadata(repo.large_file_path(hash))?.len();
+
+                format!("Large file, size: {}, size compressed: {}", format_size(size), format_size(compressed))
+            }
+            else {
+                format!("Large file, size: {}, not downloaded", format_size(size))
+            };
+
+            (kind, None)
+        }
+    })
+}
+
+fn display_raw(repo: &Repository, path: &RelativePathBuf, hash: ObjectHash, chain: bool) -> Result<()> {
+    println!("{path} ({hash:?})");
+
+    let (kind, mut next) = describe_object(repo, hash)?;
+
+    println!(" * {kind}");
+
+    if !chain {
+        return Ok(());
+    }
+
+    while let Some(hash) = next {
+        let (kind, original) = describe_object(repo, hash)?;
+
+        println!(" * {hash}: {kind}");
+
+        next = original;
+    }
+
+    Ok(())
 }
 
 pub fn parse(args: Args) -> Result<()> {
     let repo = Repository::load()?;
 
-    let version = if let Some(version) = args.version {
+    let default_version = if let Some(version) = args.version {
         repo.normalise_version(&version)?
     }
     else {
-        repo.current_hash
+        repo.current_hash()
     };
 
-    let snapshot = repo.fetch_snapshot(version)?;
+    // The globs for each version, in the order the versions were first given.
+    let mut groups: Vec<(ObjectHash, Vec<String>)> = vec![];
 
-    let paths: Vec<&RelativePathBuf> = snapshot.files
-        .keys()
-        .collect();
+    for arg in &args.globs {
+        let (version, glob) = split_version(&repo, arg).unwrap_or((default_version, arg));
 
-    let valid_paths = filter_paths_with_glob(&args.globs, &paths, &repo.root_dir);
+        match groups.iter_mut().find(|(hash, _)| *hash == version) {
+            Some((_, globs)) => globs.push(glob.to_string()),
+            None => groups.push((version, vec![glob.to_string()]))
+        }
+    }
 
-    if valid_paths.is_empty() {
-        eprintln!("No files found.");
+    let raw = args.raw || args.delta_chain;
 
-        return Ok(());
-    }
+    let mut found = false;
 
     let mut stdout = stdout();
 
-    for &path in valid_paths {
-        let content_hash = snapshot.files[path];
+    for (version, globs) in groups {
+        let snapshot = repo.fetch_snapshot(version)?;
+
+        let paths: Vec<&RelativePathBuf> = snapshot.files
+            .keys()
+            .collect();
+
+        let valid_paths = filter_paths_with_glob(&globs, &paths, &repo.root_dir);
 
-        let content = repo.fetch_string_content(content_hash)?;
+        found |= !valid_paths.is_empty();
 
-        stdout.write_all(content.as_bytes())?;
+        for &path in valid_paths {
+            let content_hash = snapshot.files[path];
 
-        stdout.flush()?;
+            if raw {
+                display_raw(&repo, path, content_hash, args.delta_chain)?;
+
+                continue;
+            }
+
+            let content = repo.fetch_string_content(content_hash)?;
+
+            stdout.write_all(content.as_bytes())?;
+
+            stdout.flush()?;
+        }
     }
-    
+
+    if !found {
+        eprintln!("No files found.");
+    }
+
     Ok(())
 }
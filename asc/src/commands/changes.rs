@@ -2,11 +2,14 @@ use eyre::Result;
 
 use libasc::{change::FileChange, repository::Repository};
 
+use crate::{commands::branch, exit::{self, Status}, i18n::t, output::{self, say, FileEntry}};
+
 #[derive(clap::Args)]
 pub struct Args {
-    /// Include unchanged files in the list of changes.
-    #[arg(short, long)]
-    verbose: bool
+    /// Exit with code 4 if there are changes, and 0 if there are none.
+    /// Unchanged files are included in the list with `--verbose`.
+    #[arg(long)]
+    exit_code: bool
 }
 
 pub fn parse(args: Args) -> Result<()> {
@@ -14,10 +17,42 @@ pub fn parse(args: Args) -> Result<()> {
 
     let mut file_changes = repo.list_changes()?;
 
-    if !args.verbose {
+    let changed = file_changes.iter().any(|f| !matches!(f, FileChange::Unchanged(_)));
+
+    if args.exit_code && changed {
+        exit::set(Status::Changes);
+    }
+
+    if !output::is_verbose() {
         file_changes.retain(|f| !matches!(f, FileChange::Unchanged(_)));
     }
 
+    if output::is_json() {
+        let entries: Vec<FileEntry> = file_changes
+            .iter()
+            .map(FileEntry::from)
+            .collect();
+
+        return output::print_json(&entries);
+    }
+
+    match repo.current_branch() {
+        Some(name) => {
+            let upstreams = branch::describe_upstreams(&repo, name)?;
+
+            let line = t!("branch-on", name = name);
+
+            if upstreams.is_empty() {
+                say!("{line}");
+            }
+            else {
+                say!("{line} ({})", upstreams.join("; "));
+            }
+        }
+
+        None => say!("{}", t!("branch-detached", hash = repo.current_hash()))
+    }
+
     if file_changes.is_empty() {
         eprintln!("No changes have been made.");
 
@@ -0,0 +1,57 @@
+use std::{env::current_dir, path::PathBuf};
+
+use eyre::Result;
+use libasc::repository::Repository;
+use relative_path::PathExt;
+
+#[derive(clap::Args)]
+pub struct Args {
+    /// The paths to check.
+    paths: Vec<PathBuf>
+}
+
+pub fn parse(args: Args) -> Result<()> {
+    let repo = Repository::load()?;
+
+    let cwd = current_dir()?;
+
+    for path in args.paths {
+        let full_path = cwd.join(&path);
+
+        let Ok(relative) = full_path.relative_to(&repo.root_dir) else {
+            eprintln!("{}: outside of the repository", path.display());
+
+            continue;
+        };
+
+        let relative = relative.normalize();
+
+        if let Some(link) = repo.links.containing(&relative) {
+            println!("{relative}: ignored (inside the linked repository at {link})");
+
+            continue;
+        }
+
+        let Some(rule) = repo.ignore_rules.explain(relative.to_logical_path(&repo.root_dir)) else {
+            println!("{relative}: not ignored");
+
+            continue;
+        };
+
+        let source = rule.source
+            .relative_to(&repo.root_dir)
+            .map(|source| source.to_string())
+            .unwrap_or_else(|_| rule.source.display().to_string());
+
+        let location = match rule.line {
+            Some(line) => format!("{source}:{line}"),
+            None => source
+        };
+
+        let verdict = if rule.is_negation { "not ignored" } else { "ignored" };
+
+        println!("{relative}: {verdict} ({location}: {})", rule.pattern);
+    }
+
+    Ok(())
+}
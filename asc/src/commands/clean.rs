@@ -1,13 +1,16 @@
-use std::{collections::{HashSet, VecDeque}, fs, path::PathBuf};
+use std::{collections::HashSet, fs};
 
-use libasc::{hash::ObjectHash, repository::Repository, unwrap, utils::resolve_wildcard_path};
+use chrono::Utc;
+use libasc::{blobs::ObjectStore, fence::fenced_hashes, guard::{guard, Change}, hash::ObjectHash, repository::Repository, retention, stash::BACKUP_LIFETIME, unwrap};
 
 use eyre::Result;
 
+use crate::output::say;
+
 pub fn parse() -> Result<()> {
     let mut repo = Repository::load()?;
 
-    let mut valid_blobs: HashSet<PathBuf> = HashSet::new();
+    let mut valid_objects: HashSet<ObjectHash> = HashSet::new();
     
     let mut valid_commits: HashSet<ObjectHash> = HashSet::new();
 
@@ -17,42 +20,53 @@ pub fn parse() -> Result<()> {
         }
     }
 
-    let mut queue: VecDeque<ObjectHash> = repo.branches
-        .values()
-        .chain(repo.tags.values())
-        .cloned()
-        .collect();
-
-    if !queue.contains(&repo.current_hash) {
-        queue.push_back(repo.current_hash);
-    }
+    let tips = repo.refs
+        .iter()
+        .map(|(_, hash)| hash)
+        .chain([repo.current_hash()])
+        .chain(repo.pins.hashes())
+        .chain(retention::held_snapshots(&repo)?);
 
-    while let Some(current) = queue.pop_front() {
-        if repo.trash_contains(current).is_some() {
-            continue;
-        }
+    let reachable = repo.history.reachable(tips, |hash| repo.trash_contains(hash).is_some())?;
 
+    for current in reachable {
         valid_commits.insert(current);
 
-        valid_blobs.insert(repo.hash_to_path(current));
+        valid_objects.insert(current);
 
         let snapshot = repo.fetch_snapshot(current)?;
 
-        valid_blobs.extend(snapshot.files.values().map(|&hash| repo.hash_to_path(hash)));
-
-        let parents = repo.history.get_parents(current).unwrap();
-        
-        queue.extend(parents.iter());
+        valid_objects.extend(snapshot.files.values());
     }
 
+    let pruned_backups = repo.stash.prune_backups(Utc::now() - BACKUP_LIFETIME);
+
+    say!("Old backups removed from the stash: {pruned_backups}");
+
     for entry in repo.stash.iter_entries() {
         let snapshot = repo.fetch_snapshot(entry.basis)?;
 
         valid_commits.insert(snapshot.hash);
 
-        valid_blobs.insert(repo.hash_to_path(snapshot.hash));
+        valid_objects.insert(snapshot.hash);
+
+        valid_objects.extend(snapshot.files.values());
 
-        valid_blobs.extend(snapshot.files.values().map(|&hash| repo.hash_to_path(hash)));
+        // Stashed content isn't in any snapshot either.
+        valid_objects.extend(entry.state.files.values());
+    }
+
+    // Staged content isn't in any snapshot until it is committed.
+    valid_objects.extend(repo.staging.iter().map(|(_, hash)| hash));
+
+    // Objects written by a sync that is still running
+    // aren't referenced yet, but must not be deleted.
+    for hash in fenced_hashes(&repo)? {
+        if repo.history.contains(hash) {
+            valid_commits.insert(hash);
+        }
+
+        valid_objects.insert(hash);
     }
 
     let all_commits: HashSet<ObjectHash> = repo.history.iter_hashes().collect();
@@ -62,27 +76,50 @@ pub fn parse() -> Result<()> {
         repo.history.remove(to_remove);
     }
 
-    println!("Snapshots removed: {removed_commits}");
+    say!("Snapshots removed: {removed_commits}");
     
-    let all_blobs: HashSet<PathBuf> = resolve_wildcard_path(repo.blobs_dir().join("**/*"))?
-        .into_iter()
-        .collect();
+    let all_objects = repo.stored_objects()?;
 
-    let mut removed_files: usize = 0;
+    let objects = repo.objects();
 
-    for path in all_blobs.difference(&valid_blobs) {
-        unwrap!(
-            fs::remove_file(path),
-            "failed to delete path {} when cleaning repository.", path.display()
-        );
+    let mut removed_objects: usize = 0;
+
+    for &hash in all_objects.difference(&valid_objects) {
+        if guard().allow(|| Change::DeleteObject(hash)) {
+            objects.remove_object(hash)?;
+        }
 
-        removed_files += 1;
+        removed_objects += 1;
     }
 
-    println!("Files from disk: {removed_files}");
+    say!("Objects removed: {removed_objects}");
+
+    let shortened = repo.optimise_deltas()?;
+
+    say!("Delta chains shortened: {shortened}");
+
+    // Cached results may be for snapshots that were just removed.
+    let cache_dir = repo.cache_dir();
+
+    if cache_dir.exists() && guard().allow_quietly() {
+        unwrap!(
+            fs::remove_dir_all(&cache_dir),
+            "failed to delete cache: {}", cache_dir.display()
+        );
+    }
 
     repo.action_history.clear();
 
+    // This happens after the objects are deleted, and after the action history
+    // is cleared, so that deleting these branches can be undone until the next clean.
+    let expired = repo.expire_branches()?;
+
+    for (name, hash) in &expired {
+        say!("Deleted ephemeral branch {name:?} (was {hash})");
+    }
+
+    say!("Ephemeral branches deleted: {}", expired.len());
+
     repo.save()?;
 
     Ok(())
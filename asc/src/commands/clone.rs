@@ -1,12 +1,21 @@
-use std::path::{Path, PathBuf};
+mod discovery;
 
-use eyre::Result;
-use libasc::{key::PrivateKey, sync::{client::Client, remote::Remote}, unwrap};
+use std::{path::{Path, PathBuf}, sync::Arc};
+
+use eyre::{Result, bail, eyre};
+use libasc::{key::PrivateKey, repository::Repository, refs::RefKind, sync::{client::Client, clone::{CloneOptions, DroppedRef}, remote::Remote}, unwrap};
+use tokio::sync::RwLock;
+
+use crate::{commands::{lfs::fetch_large_files, link::fetch_links}, exit::{self, Status}, output::say};
+
+use discovery::ProjectId;
 
 #[derive(clap::Args)]
 pub struct Args {
-    /// The repository to clone.
-    /// Can be `ssh` or `file`
+    /// The repository to clone. Can be an `ssh` or `file` URL, or a
+    /// short name like `project@org.example`, which is looked up in
+    /// the project list the organisation serves at
+    /// `https://org.example/.well-known/asc.json`.
     url: String,
 
     /// Where to clone the repository to.
@@ -20,7 +29,52 @@ pub struct Args {
     /// Create the repository, even if
     /// the directory is not empty.
     #[arg(long)]
-    allow_not_empty: bool
+    allow_not_empty: bool,
+
+    /// A local copy of the repository, or a bundle file of its history,
+    /// to take snapshots from, so only what it is missing is fetched from
+    /// the URL. Every branch and tag still comes from the URL.
+    #[arg(long)]
+    seed: Option<PathBuf>,
+
+    /// A repository on this machine to borrow objects from, so only what
+    /// it is missing is fetched from the URL. The clone needs it to stay
+    /// where it is, unless --dissociate is given.
+    #[arg(long, conflicts_with = "seed")]
+    reference: Option<PathBuf>,
+
+    /// Copy the objects borrowed from --reference into the clone
+    /// once it is done, so the clone doesn't depend on it.
+    #[arg(long, requires = "reference")]
+    dissociate: bool,
+
+    /// Leave out the trash. Snapshots in it that no
+    /// branch or tag reaches won't be cloned either.
+    #[arg(long)]
+    no_trash: bool,
+
+    /// Also clone the stash, with what each entry needs to be applied.
+    #[arg(long)]
+    stash: bool,
+
+    /// The branch to check out, instead of the
+    /// one the remote has as its default.
+    #[arg(short, long)]
+    branch: Option<String>,
+
+    /// Only clone the branch being checked out, with the tags on its
+    /// history, instead of every branch and tag on the remote.
+    #[arg(long)]
+    single_branch: bool,
+
+    /// Leave out the tags.
+    #[arg(long)]
+    no_tags: bool,
+
+    /// Don't write the files of the checked out snapshot, like for
+    /// a repository that will only be pushed to or served from.
+    #[arg(long, visible_alias = "no-checkout")]
+    bare: bool
 }
 
 fn check_dir_is_empty(path: &Path) -> Result<bool> {
@@ -43,25 +97,153 @@ fn check_dir_is_empty(path: &Path) -> Result<bool> {
     Ok(true)
 }
 
+/// Clone with the objects of `seed`, so the remote leaves them out.
+///
+/// A bundle file is read as the clone is made, and a repository is borrowed
+/// from like with `--reference`. Either way, the branches and tags come
+/// from the remote, so the clone ends up the same as without a seed.
+///
+/// Returns whether the seed is borrowed from, and its objects need copying.
+fn seed_from(seed: &Path, options: &mut CloneOptions) -> Result<bool> {
+    if seed.is_file() {
+        options.bundle = Some(seed.to_path_buf());
+
+        return Ok(false);
+    }
+
+    if !seed.join(".asc").is_dir() {
+        bail!("no repository or bundle to seed from at {}", seed.display());
+    }
+
+    options.reference = Some(seed.to_path_buf());
+
+    Ok(true)
+}
+
+/// Say which of the remote's branches and tags were left out of a clone.
+pub fn report_dropped(dropped: &[DroppedRef]) {
+    for item in dropped {
+        let kind = match item.kind {
+            RefKind::Tag => "tag",
+            _ => "branch"
+        };
+
+        eprintln!(" ! Left out the remote's {kind} {:?} - {}", item.name, item.reason);
+    }
+}
+
+/// Connect to the first of `remotes` that can be reached.
+async fn connect_first(remotes: Vec<Remote>) -> Result<(Remote, Client)> {
+    let count = remotes.len();
+
+    let mut last_error = None;
+
+    for remote in remotes {
+        match Client::connect(remote.clone()).await {
+            Ok(client) => return Ok((remote, client)),
+
+            Err(e) => {
+                if count > 1 {
+                    eprintln!(" ! Failed to connect to {remote}: {e}");
+                }
+
+                last_error = Some(e);
+            }
+        }
+    }
+
+    Err(last_error.unwrap_or_else(|| eyre!("there are no remotes to clone from")))
+}
+
 #[tokio::main]
 pub async fn parse(args: Args) -> Result<()> {
     if !args.allow_not_empty && !check_dir_is_empty(&args.path)? {
+        exit::set(Status::Failure);
+
         eprintln!("Cannot make repository at {} (not empty)", args.path.display());
 
         return Ok(());
     }
 
-    let remote = Remote::from_url(&args.url)?;
+    let remotes = match ProjectId::parse(&args.url) {
+        Some(id) => {
+            let remotes = id.discover()?;
+
+            say!("Found {} at {}", args.url, remotes.iter().map(Remote::to_string).collect::<Vec<_>>().join(", "));
+
+            remotes
+        },
+
+        None => vec![Remote::from_url(&args.url)?]
+    };
 
-    let user_key = {
-        let bytes = hex::decode(args.login_key)?;
+    let user_key: PrivateKey = args.login_key.parse()?;
 
-        PrivateKey::from_bytes(&bytes)?
+    let mut options = CloneOptions {
+        trash: !args.no_trash,
+        stash: args.stash,
+        reference: args.reference.clone(),
+        bundle: None,
+        branch: args.branch.clone(),
+        single_branch: args.single_branch,
+        tags: !args.no_tags,
+        bare: args.bare,
+        have: vec![]
     };
     
-    let mut client = Client::connect(remote).await?;
+    let seed_borrowed = match &args.seed {
+        Some(seed) => seed_from(seed, &mut options)?,
+        None => false
+    };
+
+    let (remote, mut client) = connect_first(remotes).await?;
+
+    let result = client.clone_repo(&args.path, user_key.clone(), options).await?;
+
+    report_dropped(&result.dropped);
+
+    let mut repo = result.repo;
+
+    if let Some(seed) = &args.seed {
+        // Borrowing from the seed was only to leave out what
+        // it has, so the clone mustn't depend on it afterwards.
+        if seed_borrowed {
+            let copied = repo.dissociate()?;
+
+            repo.save()?;
+
+            say!("Seeded from {} ({copied} objects)", seed.display());
+        }
+        else {
+            say!("Seeded from {}", seed.display());
+        }
+    }
+
+    if let Some(reference) = &args.reference {
+        say!("Borrowing objects from {}", reference.display());
+
+        if args.dissociate {
+            let copied = repo.dissociate()?;
+
+            repo.save()?;
+
+            say!("Copied {copied} borrowed objects");
+        }
+    }
+
+    // Large files and linked repositories are only
+    // fetched to be written to the working directory.
+    if !args.bare {
+        let current_hash = repo.current_hash();
+
+        let repo_arc = Arc::new(RwLock::new(repo));
+
+        fetch_large_files(repo_arc.clone(), vec![current_hash], None).await?;
 
-    let repo = client.clone_repo(&args.path, user_key).await?;
+        repo = Repository::load_from(&args.path)?;
+
+        fetch_links(&repo.root_dir, &repo.links, &user_key, std::slice::from_ref(&remote)).await?;
+    }
 
     let mut blobs = 0;
 
@@ -75,12 +257,24 @@ pub async fn parse(args: Args) -> Result<()> {
         .current_branch()
         .unwrap_or("none");
 
-    println!("Cloned repository {:?}", repo.project_name);
-    println!("Sent: {} | Received: {}", client.bytes_sent(), client.bytes_recv());
-    println!();
-    println!("Commits: {}", repo.history.size());
-    println!("Blobs: {blobs}");
-    println!("Branch: {current_branch} ({})", repo.current_hash);
+    say!("Cloned repository {:?}", repo.project_name);
+    say!("Sent: {} | Received: {}", client.bytes_sent(), client.bytes_recv());
+    say!();
+    say!("Commits: {}", repo.history.size());
+    say!("Blobs: {blobs}");
+    say!("Branch: {current_branch} ({})", repo.current_hash());
+
+    if !repo.links.is_empty() {
+        say!("Linked repositories: {}", repo.links.len());
+    }
+
+    if !repo.trash.is_empty() {
+        say!("Trashed snapshots: {}", repo.trash.size());
+    }
+
+    if !repo.stash.is_empty() {
+        say!("Stash entries: {}", repo.stash.iter().count());
+    }
 
     Ok(())
 }
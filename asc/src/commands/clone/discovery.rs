@@ -0,0 +1,103 @@
+//! Turning short names for projects, like `project@org.example`, into the
+//! remotes to clone them from, so organisations can hand out addresses that
+//! are easy to remember.
+//!
+//! The organisation lists its projects in a JSON file served from
+//! `https://org.example/.well-known/asc.json`, like:
+//!
+//! ```json
+//! {
+//!     "projects": {
+//!         "project": [
+//!             "ssh://asc@vcs.org.example/srv/project",
+//!             "ssh://asc@backup.org.example/srv/project"
+//!         ]
+//!     },
+//!     "template": ["ssh://asc@vcs.org.example/srv/{project}"]
+//! }
+//! ```
+//!
+//! Each project's remotes are listed best first, and are tried in that order
+//! until one connects. Projects that aren't listed use the template instead,
+//! with `{project}` replaced by their name, if there is a template.
+
+use std::collections::BTreeMap;
+
+use eyre::{bail, Result};
+use serde::Deserialize;
+
+use libasc::{sync::remote::Remote, unwrap};
+
+use crate::commands::upgrade::fetch;
+
+/// What an organisation's `asc.json` says about its projects.
+#[derive(Deserialize)]
+struct Directory {
+    #[serde(default)]
+    projects: BTreeMap<String, Vec<String>>,
+
+    #[serde(default)]
+    template: Vec<String>
+}
+
+/// A short name for a project, like `project@org.example`.
+pub struct ProjectId<'a> {
+    pub name: &'a str,
+    pub domain: &'a str
+}
+
+impl<'a> ProjectId<'a> {
+    /// Read a short name for a project, if `raw` is one rather than a URL.
+    pub fn parse(raw: &'a str) -> Option<ProjectId<'a>> {
+        // URLs have a scheme, and `user@host:path` has a colon.
+        if raw.contains("://") || raw.contains(':') || raw.contains('/') {
+            return None;
+        }
+
+        let (name, domain) = raw.split_once('@')?;
+
+        let valid_name = !name.is_empty() && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+
+        if !valid_name || !domain.contains('.') || domain.contains('@') {
+            return None;
+        }
+
+        Some(ProjectId { name, domain })
+    }
+
+    /// Get where the organisation lists its projects.
+    fn directory_url(&self) -> String {
+        format!("https://{}/.well-known/asc.json", self.domain)
+    }
+
+    /// Ask the organisation for the project's remotes, best first.
+    pub fn discover(&self) -> Result<Vec<Remote>> {
+        let url = self.directory_url();
+
+        let raw = fetch(&url)?;
+
+        let directory: Directory = unwrap!(
+            serde_json::from_slice(&raw),
+            "the project list at {url} is invalid"
+        );
+
+        let urls = match directory.projects.get(self.name) {
+            Some(urls) => urls.clone(),
+
+            None => directory.template
+                .iter()
+                .map(|template| template.replace("{project}", self.name))
+                .collect()
+        };
+
+        if urls.is_empty() {
+            bail!("{} doesn't list a project called {:?}", self.domain, self.name);
+        }
+
+        urls.iter()
+            .map(|url| Remote::from_url(url))
+            .collect()
+    }
+}
@@ -1,6 +1,11 @@
+use chrono::{DateTime, Utc};
 use eyre::Result;
 
-use libasc::{repository::Repository, unwrap, utils::get_content_from_editor};
+use libasc::{change::FileChange, clock::{self, FixedClock}, paths, refname, repository::Repository, snapshot::Snapshot, utils::{clean_message, get_content_from_editor}};
+use relative_path::RelativePathBuf;
+use similar::TextDiff;
+
+use crate::{exit::{self, Status}, i18n::t, output::{self, say}};
 
 #[derive(clap::Args)]
 pub struct Args {
@@ -8,15 +13,43 @@ pub struct Args {
     #[arg(short, long)]
     message: Option<String>,
 
-    /// A path to the interactive editor used to write an message.
-    /// Found from the environment variable 'EDITOR'.
+    /// The interactive editor used to write an message, like `code --wait`.
+    /// Found from the 'ui.editor' config or the environment variable 'EDITOR'.
     #[arg(short, long)]
     editor: Option<String>,
 
     /// The branch for this snapshot to go on.
     /// This will override existing branch names.
     #[arg(short, long)]
-    branch: Option<String>
+    branch: Option<String>,
+
+    /// Stage the working copy of every tracked file before committing.
+    #[arg(short, long)]
+    all: bool,
+
+    /// Skip checking the message against the rules in the config.
+    #[arg(long)]
+    no_verify: bool,
+
+    /// Replace the current snapshot with the staged files instead of adding a new one.
+    /// Its message is kept unless a new one is given.
+    #[arg(long)]
+    amend: bool,
+
+    /// Commit even if the message is empty, or is the template left unchanged.
+    #[arg(long)]
+    allow_empty_message: bool,
+
+    /// The time to stamp the snapshot with, like `2025-01-31T12:00:00Z`,
+    /// `2025-01-31` or `@1738324800`, instead of now. The `ASC_COMMIT_DATE`
+    /// environment variable does the same for every new snapshot.
+    #[arg(long, value_parser = parse_date)]
+    date: Option<DateTime<Utc>>
+}
+
+/// Parse a date given for a snapshot on the command line.
+pub fn parse_date(raw: &str) -> Result<DateTime<Utc>, String> {
+    clock::parse_date(raw).map_err(|e| e.to_string())
 }
 
 pub static COMMIT_TEMPLATE_MESSAGE: &str = "
@@ -25,17 +58,146 @@ pub static COMMIT_TEMPLATE_MESSAGE: &str = "
 # Whitespace before and after the message is also ignored.
 ";
 
+/// Get the text an editor is opened with to write a snapshot message,
+/// made from the template in the config (or the message being amended)
+/// and the staged changes.
+fn build_template(repo: &Repository, verbose: bool, amending: Option<&Snapshot>) -> Result<String> {
+    let mut template = match amending {
+        Some(snapshot) => format!("{}\n", snapshot.message),
+        None => repo.commit_template()?.unwrap_or_default()
+    };
+
+    template.push_str(COMMIT_TEMPLATE_MESSAGE);
+
+    let changes: Vec<FileChange<RelativePathBuf>> = repo
+        .list_changes()?
+        .into_iter()
+        .filter(|change| !matches!(change, FileChange::Unchanged(_)))
+        .collect();
+
+    if !changes.is_empty() {
+        template.push_str("#\n# Changes:\n");
+
+        for change in &changes {
+            template.push_str(&format!("#   {change}\n"));
+        }
+    }
+
+    if !verbose {
+        return Ok(template);
+    }
+
+    let current = repo.fetch_current_snapshot()?;
+
+    template.push_str("#\n# Diff of the staged changes:\n");
+
+    for (path, hash) in repo.staging.iter() {
+        let old_hash = current.files.get(path).copied();
+
+        if old_hash == Some(hash) {
+            continue;
+        }
+
+        let old = match old_hash {
+            Some(old_hash) => repo.fetch_string_content(old_hash)?,
+            None => String::new()
+        };
+
+        let new = repo.fetch_string_content(hash)?;
+
+        let diff = TextDiff::from_lines(&old, &new)
+            .unified_diff()
+            .header(path.as_str(), path.as_str())
+            .to_string();
+
+        for line in diff.lines() {
+            template.push_str(&format!("# {line}\n"));
+        }
+    }
+
+    for path in current.files.keys().filter(|path| !repo.staging.contains(path)) {
+        template.push_str(&format!("# {path} removed\n"));
+    }
+
+    Ok(template)
+}
+
 pub fn parse(args: Args) -> Result<()> {
     let mut repo = Repository::load()?;
 
-    if !repo.has_unsaved_changes()? {
-        eprintln!("No changes to document in the upcoming commit.");
+    if let Some(date) = args.date {
+        repo.set_clock(FixedClock(date));
+    }
+
+    if let Some(name) = &args.branch
+        && !repo.refs.branches().contains(name)
+        && let Err(e) = refname::check_branch(name, &repo.config)
+    {
+        exit::set(Status::Failure);
+
+        eprintln!("{}", t!("branch-invalid-name", error = e));
+
+        return Ok(());
+    }
+
+    if args.all {
+        let tracked: Vec<RelativePathBuf> = repo.staging
+            .paths()
+            .filter(|path| paths::on_disk(path, &repo.root_dir).is_file())
+            .cloned()
+            .collect();
+
+        repo.stage_paths(&tracked)?;
+    }
+
+    let amending = if args.amend {
+        if args.branch.is_some() {
+            exit::set(Status::Failure);
+
+            eprintln!("{}", t!("commit-amend-other-branch"));
+
+            return Ok(());
+        }
+
+        let current = repo.fetch_current_snapshot()?;
+
+        let has_children = repo.history
+            .children(current.hash)
+            .is_some_and(|children| !children.is_empty());
+
+        if has_children {
+            exit::set(Status::Failure);
+
+            eprintln!("{}", t!("commit-amend-has-children", hash = current.hash));
+
+            return Ok(());
+        }
+
+        Some(current)
+    }
+    else {
+        None
+    };
+
+    // Amending can change just the message, so
+    // having the same files is fine in that case.
+    let current = repo.fetch_current_snapshot()?;
+
+    let unchanged = repo.staging.files() == &current.files
+        && repo.staging.modes() == &current.modes;
+
+    if amending.is_none() && unchanged {
+        exit::set(Status::Clean);
+
+        eprintln!("{}", t!("commit-no-changes"));
 
         return Ok(());
     }
 
-    if repo.staged_files.is_empty() {
-        eprintln!("No files are being tracked - empty snapshots are disallowed.");
+    if repo.staging.is_empty() {
+        exit::set(Status::Clean);
+
+        eprintln!("{}", t!("commit-nothing-tracked"));
 
         return Ok(());
     }
@@ -44,33 +206,88 @@ pub fn parse(args: Args) -> Result<()> {
         msg
     }
     else {
-        let editor = args.editor.unwrap_or(
-            unwrap!(
-                std::env::var("EDITOR"),
-                "environment variable 'EDITOR' is not set."
-            )
-        );
+        let editor = match args.editor {
+            Some(editor) => editor,
+            None => repo.config.ui.editor()?
+        };
 
         let snapshot_message_path = &repo.main_dir().join("SNAPSHOT_MESSAGE");
 
-        get_content_from_editor(&editor, snapshot_message_path, COMMIT_TEMPLATE_MESSAGE)?
+        let verbose = output::is_verbose() || repo.config.commit.verbose;
+
+        let template = build_template(&repo, verbose, amending.as_ref())?;
+
+        let message = get_content_from_editor(&editor, snapshot_message_path, &template)?;
+
+        // An amended snapshot keeps its message when it is left as it was.
+        if !args.allow_empty_message && amending.is_none() && !message.is_empty() && message == clean_message(&template) {
+            exit::set(Status::Failure);
+
+            eprintln!("{}", t!("commit-template-unchanged"));
+
+            return Ok(());
+        }
+
+        message
     };
 
-    let snapshot = repo.commit_current_state(message)?;
+    if !args.allow_empty_message && message.trim().is_empty() {
+        exit::set(Status::Failure);
+
+        eprintln!("{}", t!("commit-empty-message"));
+
+        return Ok(());
+    }
+
+    if !args.no_verify && let Err(e) = repo.config.commit.validate_message(&message) {
+        exit::set(Status::Failure);
+
+        eprintln!("{}", t!("commit-invalid-message", error = e));
+
+        return Ok(());
+    }
+
+    if let Some(previous) = amending {
+        let snapshot = repo.amend_current_state(Some(message), args.date)?;
+
+        if snapshot.hash == previous.hash {
+            exit::set(Status::Clean);
+
+            eprintln!("{}", t!("commit-amend-unchanged"));
+
+            return Ok(());
+        }
+
+        let hash = repo.save_snapshot(snapshot)?;
+
+        repo.advance_head(hash);
+
+        repo.save()?;
+
+        say!("{}", t!("commit-amended", old = previous.hash, new = format!("{hash:?}")));
+
+        return Ok(());
+    }
+
+    let branch = args.branch
+        .clone()
+        .or_else(|| repo.current_branch().map(String::from));
+
+    let snapshot = repo.commit_current_state(message, branch)?;
 
     if let Some(new_branch) = args.branch {
-        if let Some(previous_hash) = repo.branches.get(&new_branch) {
-            println!("Branch {new_branch} has moved: {previous_hash} -> {}", snapshot.hash);
+        if let Some(previous_hash) = repo.refs.branches().get(&new_branch) {
+            say!("{}", t!("commit-branch-moved", branch = new_branch, old = previous_hash, new = snapshot.hash));
         }
 
         let before = repo
             .current_branch()
             .map(String::from)
-            .unwrap_or(repo.current_hash.to_string());
+            .unwrap_or(repo.current_hash().to_string());
 
         repo.append_snapshot_to_branch(snapshot, new_branch.clone())?;
 
-        println!("Switched branches: {before} -> {new_branch}");
+        say!("{}", t!("commit-switched-branch", before = before, after = new_branch));
     }
     else {
         repo.append_snapshot(snapshot)?;
@@ -78,7 +295,7 @@ pub fn parse(args: Args) -> Result<()> {
 
     repo.save()?;
 
-    println!("New version: {:?}", repo.current_hash);
+    say!("{}", t!("commit-done", hash = format!("{:?}", repo.current_hash())));
     
     Ok(())
 }
@@ -0,0 +1,349 @@
+use eyre::Result;
+use relative_path::RelativePathBuf;
+use serde::Serialize;
+use threeway_merge::{merge_strings, MergeOptions};
+
+use libasc::{compare::{self, Comparison, FileStat}, hash::ObjectHash, repository::Repository, snapshot::Snapshot};
+
+use crate::output;
+
+#[derive(clap::Args)]
+pub struct Args {
+    /// The version that would be merged into, like `main`.
+    base: String,
+
+    /// The version with the changes to review.
+    /// Defaults to the current version.
+    head: Option<String>,
+
+    /// Print the comparison as Markdown, to paste into a review.
+    #[arg(long)]
+    markdown: bool
+}
+
+/// A snapshot in the comparison, as printed with `--format json`.
+#[derive(Serialize)]
+struct SnapshotEntry<'a> {
+    hash: String,
+    author: Option<&'a str>,
+    timestamp: String,
+    message: &'a str
+}
+
+/// A changed file, as printed with `--format json`.
+#[derive(Serialize)]
+struct FileEntry<'a> {
+    path: &'a str,
+    change: &'static str,
+    added: usize,
+    removed: usize
+}
+
+/// The whole comparison, as printed with `--format json`.
+#[derive(Serialize)]
+struct ComparisonEntry<'a> {
+    base: String,
+    head: String,
+    merge_base: Option<String>,
+    ahead: Vec<SnapshotEntry<'a>>,
+    behind: usize,
+    files: Vec<FileEntry<'a>>,
+    added: usize,
+    removed: usize,
+    conflicts: Vec<&'a str>
+}
+
+/// Everything printed about a comparison, gathered up front.
+struct Review<'a> {
+    base: &'a str,
+    head: &'a str,
+    comparison: Comparison,
+    snapshots: Vec<Snapshot>,
+    conflicts: Vec<RelativePathBuf>
+}
+
+fn author<'a>(repo: &'a Repository, snapshot: &Snapshot) -> &'a str {
+    repo.users
+        .get_user(&snapshot.author)
+        .map(|user| user.name.as_str())
+        .unwrap_or("<unknown user>")
+}
+
+impl Review<'_> {
+    /// Count the lines added and removed across every file.
+    fn totals(&self) -> (usize, usize) {
+        self.comparison.files
+            .iter()
+            .fold((0, 0), |(added, removed), file| (added + file.added, removed + file.removed))
+    }
+}
+
+/// Work out which of the files changed on both sides wouldn't merge cleanly.
+fn predict_conflicts(repo: &Repository, comparison: &Comparison) -> Result<Vec<RelativePathBuf>> {
+    let original = match comparison.merge_base {
+        Some(hash) => repo.fetch_snapshot(hash)?.files,
+        None => Default::default()
+    };
+
+    let base = repo.fetch_snapshot(comparison.base)?.files;
+    let head = repo.fetch_snapshot(comparison.head)?.files;
+
+    let mut conflicts = vec![];
+
+    for path in &comparison.overlapping {
+        // A file deleted on one side and changed on the other always conflicts.
+        let (Some(&ours), Some(&theirs)) = (base.get(path), head.get(path)) else {
+            conflicts.push(path.clone());
+
+            continue;
+        };
+
+        let old = match original.get(path) {
+            Some(&hash) => repo.fetch_string_content(hash)?,
+            None => String::new()
+        };
+
+        let result = merge_strings(
+            &old,
+            &repo.fetch_string_content(ours)?,
+            &repo.fetch_string_content(theirs)?,
+            &MergeOptions::default()
+        )?;
+
+        if !result.is_clean_merge() {
+            conflicts.push(path.clone());
+        }
+    }
+
+    Ok(conflicts)
+}
+
+/// Lay out the changed files as a tree of directories, one line each.
+fn file_tree(files: &[FileStat]) -> Vec<String> {
+    let mut lines = vec![];
+
+    let mut open: Vec<&str> = vec![];
+
+    for file in files {
+        let mut components: Vec<&str> = file.path.components().map(|c| c.as_str()).collect();
+
+        let name = components.pop().unwrap_or_default();
+
+        let shared = open
+            .iter()
+            .zip(&components)
+            .take_while(|(a, b)| a == b)
+            .count();
+
+        open.truncate(shared);
+
+        for dir in &components[shared..] {
+            lines.push(format!("{}{dir}/", "  ".repeat(open.len())));
+
+            open.push(dir);
+        }
+
+        let marker = match file.change {
+            "added" => "A",
+            "removed" => "R",
+            _ => "E"
+        };
+
+        lines.push(format!("{}{name} ({marker} +{} -{})", "  ".repeat(open.len()), file.added, file.removed));
+    }
+
+    lines
+}
+
+fn print_json(repo: &Repository, review: &Review) -> Result<()> {
+    let (added, removed) = review.totals();
+
+    let comparison = &review.comparison;
+
+    let entry = ComparisonEntry {
+        base: comparison.base.full(),
+        head: comparison.head.full(),
+        merge_base: comparison.merge_base.map(|hash| hash.full()),
+        ahead: review.snapshots
+            .iter()
+            .map(|snapshot| SnapshotEntry {
+                hash: snapshot.hash.full(),
+                author: repo.users.get_user(&snapshot.author).map(|user| user.name.as_str()),
+                timestamp: snapshot.timestamp.to_rfc3339(),
+                message: &snapshot.message
+            })
+            .collect(),
+        behind: comparison.behind,
+        files: comparison.files
+            .iter()
+            .map(|file| FileEntry {
+                path: file.path.as_str(),
+                change: file.change,
+                added: file.added,
+                removed: file.removed
+            })
+            .collect(),
+        added,
+        removed,
+        conflicts: review.conflicts.iter().map(|path| path.as_str()).collect()
+    };
+
+    output::print_json(&entry)
+}
+
+fn print_text(repo: &Repository, review: &Review) {
+    let comparison = &review.comparison;
+
+    println!("Comparing {} ({}) into {} ({})", review.head, comparison.head, review.base, comparison.base);
+
+    match comparison.merge_base {
+        Some(hash) => println!("Merge base: {hash}"),
+        None => println!("Merge base: none, the histories are unrelated")
+    }
+
+    println!("{} snapshots ahead, {} behind", comparison.ahead.len(), comparison.behind);
+
+    println!();
+    println!("Snapshots:");
+
+    if review.snapshots.is_empty() {
+        println!("  (none)");
+    }
+
+    for snapshot in &review.snapshots {
+        let subject = snapshot.message.lines().next().unwrap_or_default();
+
+        println!("  {} {} - {subject}", snapshot.hash, author(repo, snapshot));
+    }
+
+    let (added, removed) = review.totals();
+
+    println!();
+    println!("Changes: {} files, +{added} -{removed}", comparison.files.len());
+
+    for line in file_tree(&comparison.files) {
+        println!("  {line}");
+    }
+
+    println!();
+
+    if review.conflicts.is_empty() {
+        println!("No conflicts expected.");
+    }
+    else {
+        println!("Expected conflicts:");
+
+        for path in &review.conflicts {
+            println!("  {path}");
+        }
+    }
+}
+
+fn print_markdown(repo: &Repository, review: &Review) {
+    let comparison = &review.comparison;
+
+    println!("## Merging `{}` into `{}`", review.head, review.base);
+    println!();
+
+    match comparison.merge_base {
+        Some(hash) => println!("**Merge base:** `{hash}`  "),
+        None => println!("**Merge base:** none, the histories are unrelated  ")
+    }
+
+    println!("**{}** snapshots ahead, **{}** behind", comparison.ahead.len(), comparison.behind);
+    println!();
+
+    println!("### Snapshots");
+    println!();
+
+    if review.snapshots.is_empty() {
+        println!("_None._");
+    }
+
+    for snapshot in &review.snapshots {
+        let subject = snapshot.message.lines().next().unwrap_or_default();
+
+        println!("- `{}` {subject} ({})", snapshot.hash, author(repo, snapshot));
+    }
+
+    let (added, removed) = review.totals();
+
+    println!();
+    println!("### Changes");
+    println!();
+    println!("**{} files changed**, +{added} -{removed}", comparison.files.len());
+
+    if !comparison.files.is_empty() {
+        println!();
+        println!("| File | Change | Added | Removed |");
+        println!("| --- | --- | ---: | ---: |");
+
+        for file in &comparison.files {
+            println!("| `{}` | {} | {} | {} |", file.path, file.change, file.added, file.removed);
+        }
+
+        println!();
+        println!("```");
+
+        for line in file_tree(&comparison.files) {
+            println!("{line}");
+        }
+
+        println!("```");
+    }
+
+    println!();
+    println!("### Conflicts");
+    println!();
+
+    if review.conflicts.is_empty() {
+        println!("_None expected._");
+    }
+
+    for path in &review.conflicts {
+        println!("- `{path}`");
+    }
+}
+
+pub fn parse(args: Args) -> Result<()> {
+    let repo = Repository::load()?;
+
+    let base: ObjectHash = repo.normalise_version(&args.base)?;
+
+    let head_name = args.head.as_deref().unwrap_or("HEAD");
+
+    let head = match &args.head {
+        Some(version) => repo.normalise_version(version)?,
+        None => repo.current_hash()
+    };
+
+    let comparison = compare::compare(&repo, base, head)?;
+
+    let snapshots = comparison.ahead
+        .iter()
+        .map(|&hash| repo.fetch_snapshot(hash))
+        .collect::<Result<_>>()?;
+
+    let conflicts = predict_conflicts(&repo, &comparison)?;
+
+    let review = Review {
+        base: &args.base,
+        head: head_name,
+        comparison,
+        snapshots,
+        conflicts
+    };
+
+    if output::is_json() {
+        return print_json(&repo, &review);
+    }
+
+    if args.markdown {
+        print_markdown(&repo, &review);
+    }
+    else {
+        print_text(&repo, &review);
+    }
+
+    Ok(())
+}
@@ -4,7 +4,9 @@ use eyre::Result;
 use relative_path::RelativePathBuf;
 use similar::{udiff::UnifiedDiff, TextDiff};
 
-use libasc::{change::FileChange, hash::ObjectHash, repository::Repository, unwrap};
+use libasc::{change::FileChange, hash::ObjectHash, paths, repository::Repository, unwrap};
+
+use crate::{exit::{self, Status}, output::{self, FileEntry}};
 
 #[derive(clap::Args)]
 pub struct Args {
@@ -57,7 +59,7 @@ impl Locator {
             Locator::WithHash(_, hash) => repo.fetch_string_content(*hash),
 
             Locator::FromCwd(path) => Ok(unwrap!(
-                fs::read_to_string(path.to_logical_path(&repo.root_dir)),
+                fs::read_to_string(paths::on_disk(path, &repo.root_dir)),
                 "cannot read from file: {path}"
             ))
         }
@@ -80,8 +82,8 @@ pub fn get_locators(repo: &Repository, snapshot_hash: Option<ObjectHash>) -> Res
             .collect())
     }
     else {
-        Ok(repo.staged_files
-            .iter()
+        Ok(repo.staging
+            .paths()
             .cloned()
             .map(Locator::FromCwd)
             .collect())
@@ -136,46 +138,79 @@ pub fn parse(args: Args) -> Result<()> {
     };
 
     if from.is_none() && to.is_some() {
+        exit::set(Status::Failure);
+
         eprintln!("the option '--to' cannot be used without '--from'.");
 
         return Ok(());
     }
 
-    let old_files = get_locators(&repo, from.or(Some(repo.current_hash)))?;
+    let old_files = get_locators(&repo, from.or(Some(repo.current_hash())))?;
 
     let new_files = get_locators(&repo, to)?;
 
-    let old = BTreeSet::from_iter(&old_files);
-    let new = BTreeSet::from_iter(&new_files);
+    print_diff(&repo, &old_files, &new_files, to.is_some())
+}
+
+/// Print the differences between two sets of files. Files only in `old_files`
+/// are shown as removed if `new_files` are saved, or missing if they're from
+/// the working directory.
+pub fn print_diff(repo: &Repository, old_files: &[Locator], new_files: &[Locator], saved: bool) -> Result<()> {
+    let old = BTreeSet::from_iter(old_files);
+    let new = BTreeSet::from_iter(new_files);
 
     let unique_locators = old.union(&new);
 
     let mut diffs: Vec<String> = vec![];
 
+    let mut entries: Vec<FileEntry> = vec![];
+
     for locator in unique_locators {
         let path = locator.path().clone();
 
-        let diff = match get_before_and_after(&repo, &old_files, &new_files, &path)? {
+        let change = match get_before_and_after(repo, old_files, new_files, &path)? {
             (None, None) => unreachable!(),
 
-            (None, Some(_)) => format!("{}", FileChange::Added(path)),
+            (None, Some(_)) => FileChange::Added(path),
 
             (Some(_), None) => {
-                if to.is_some() {
-                    format!("{}", FileChange::Removed(path))
+                if saved {
+                    FileChange::Removed(path)
                 }
                 else {
-                    format!("{}", FileChange::Missing(path))
+                    FileChange::Missing(path)
                 }
             }
 
-            (Some(old), Some(new)) => create_diff(&path, &old, &new)
+            (Some(old), Some(new)) => {
+                let diff = create_diff(&path, &old, &new);
+
+                if diff.is_empty() {
+                    continue;
+                }
+
+                if output::is_json() {
+                    entries.push(FileEntry { diff: Some(diff), ..FileEntry::from(&FileChange::Edited(path)) });
+                }
+                else {
+                    diffs.push(diff);
+                }
+
+                continue;
+            }
         };
 
-        if !diff.is_empty() {
-            diffs.push(diff);
+        if output::is_json() {
+            entries.push(FileEntry::from(&change));
+        }
+        else {
+            diffs.push(change.to_string());
         }
     }
+
+    if output::is_json() {
+        return output::print_json(&entries);
+    }
     
     if !diffs.is_empty() {
         println!("{}", diffs.join("\n"));
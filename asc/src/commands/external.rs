@@ -0,0 +1,83 @@
+//! Running `asc-<name>` programs on the PATH as if they were asc commands,
+//! so new commands can be added without changing asc itself.
+//!
+//! Anything after the command's name is passed on as it was given. The
+//! program is told about the repository and the global options through
+//! environment variables, and can run `asc plumbing info` or any command
+//! with `--format json` to find out more, through the executable in `ASC_EXE`:
+//!
+//! - `ASC_DIR`: the root of the repository, if there is one. Since asc reads
+//!   this too, commands run by the program work on the same repository.
+//! - `ASC_EXE`: the asc executable that ran the program.
+//! - `ASC_VERSION`: the version of that executable.
+//! - `ASC_PLUGIN_API`: the version of this interface, see [`PLUGIN_API`].
+//! - `ASC_FORMAT`: `text` or `json`, from `--format`.
+//! - `ASC_PLAIN`, `ASC_QUIET` and `ASC_VERBOSE`: `1` if the matching option is on.
+
+use std::{env, ffi::OsString, io::ErrorKind, process::Command};
+
+use eyre::{bail, Result};
+use libasc::repository::{current_root_dir, REPOSITORY_DIR_VAR};
+
+use crate::{exit::{self, Status}, output};
+
+/// The version of the environment and JSON given to plugins. This only
+/// goes up when something is changed or removed, not when it is added to.
+pub static PLUGIN_API: u32 = 1;
+
+fn flag(on: bool) -> &'static str {
+    if on { "1" } else { "0" }
+}
+
+pub fn parse(args: Vec<OsString>) -> Result<()> {
+    let Some((name, rest)) = args.split_first() else {
+        bail!("no command was given.");
+    };
+
+    let name = name.to_string_lossy();
+
+    let program = format!("asc-{name}");
+
+    let mut command = Command::new(&program);
+
+    command
+        .args(rest)
+        .env("ASC_VERSION", env!("CARGO_PKG_VERSION"))
+        .env("ASC_PLUGIN_API", PLUGIN_API.to_string())
+        .env("ASC_FORMAT", if output::is_json() { "json" } else { "text" })
+        .env("ASC_PLAIN", flag(output::is_plain()))
+        .env("ASC_QUIET", flag(output::is_quiet()))
+        .env("ASC_VERBOSE", flag(output::is_verbose()));
+
+    if let Ok(exe) = env::current_exe() {
+        command.env("ASC_EXE", exe);
+    }
+
+    match current_root_dir()? {
+        Some(root) => command.env(REPOSITORY_DIR_VAR, root),
+        None => command.env_remove(REPOSITORY_DIR_VAR)
+    };
+
+    let status = match command.status() {
+        Ok(status) => status,
+
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            exit::set(Status::Usage);
+
+            eprintln!("'{name}' is not an asc command, and there is no '{program}' on the PATH. See 'asc --help'.");
+
+            return Ok(());
+        },
+
+        Err(e) => bail!("failed to run {program}: {e}")
+    };
+
+    // The program's own exit code is passed on, so scripts see it as if asc
+    // had run the command itself. Programs stopped by a signal have no code.
+    match status.code() {
+        Some(code) => exit::set_code(code.clamp(0, 255) as u8),
+        None => exit::set(Status::Failure)
+    }
+
+    Ok(())
+}
@@ -0,0 +1,112 @@
+use std::{fs, path::PathBuf};
+
+use eyre::Result;
+
+use libasc::{hash::ObjectHash, patch::Patch, repository::Repository, unwrap};
+
+use crate::exit::{self, Status};
+
+#[derive(clap::Args)]
+pub struct Args {
+    /// The snapshots to write patches for, as `BASE..TIP` for every
+    /// snapshot after BASE up to TIP, or as a single version.
+    range: String,
+
+    /// The directory to write the patch files to.
+    /// Defaults to the current directory.
+    #[arg(short, long)]
+    output: Option<PathBuf>
+}
+
+/// Turn a message's subject into something that can go in a file name.
+fn slug(subject: &str) -> String {
+    let slug: String = subject
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_lowercase() } else { '-' })
+        .collect();
+
+    let slug: Vec<&str> = slug.split('-').filter(|part| !part.is_empty()).collect();
+
+    slug.join("-").chars().take(52).collect()
+}
+
+/// Get the snapshots in `range`, oldest first, or say why
+/// they can't be made into patches and get nothing.
+///
+/// The range is either `BASE..TIP`, for every snapshot after BASE up to TIP, or a single version.
+pub fn linear_range(repo: &Repository, range: &str) -> Result<Option<Vec<ObjectHash>>> {
+    let snapshots = match range.split_once("..") {
+        Some((base, tip)) => {
+            let base = repo.normalise_version(base)?;
+            let tip = repo.normalise_version(tip)?;
+
+            if !repo.history.is_descendant(tip, base)? {
+                exit::set(Status::Failure);
+
+                eprintln!("Cannot make patches from {base} to {tip}, since {tip} does not come after {base}.");
+
+                return Ok(None);
+            }
+
+            // Patches are applied one after the other, so
+            // the snapshots have to be in a single line.
+            let mut snapshots = vec![];
+
+            let mut next = tip;
+
+            while next != base {
+                let snapshot = repo.fetch_snapshot(next)?;
+
+                let [parent] = snapshot.parents.iter().copied().collect::<Vec<_>>()[..] else {
+                    exit::set(Status::Failure);
+
+                    eprintln!("Cannot make patches through {next}, since it is a merge.");
+
+                    return Ok(None);
+                };
+
+                snapshots.push(next);
+
+                next = parent;
+            }
+
+            snapshots.reverse();
+
+            snapshots
+        },
+
+        None => vec![repo.normalise_version(range)?]
+    };
+
+    Ok(Some(snapshots))
+}
+
+pub fn parse(args: Args) -> Result<()> {
+    let repo = Repository::load()?;
+
+    let Some(snapshots) = linear_range(&repo, &args.range)? else {
+        return Ok(());
+    };
+
+    let dir = args.output.unwrap_or_else(|| PathBuf::from("."));
+
+    unwrap!(
+        fs::create_dir_all(&dir),
+        "failed to create directory: {}", dir.display()
+    );
+
+    for (index, hash) in snapshots.into_iter().enumerate() {
+        let patch = Patch::from_snapshot(&repo, hash)?;
+
+        let path = dir.join(format!("{:04}-{}.patch", index + 1, slug(patch.subject())));
+
+        unwrap!(
+            fs::write(&path, patch.to_string()),
+            "failed to write patch to {}", path.display()
+        );
+
+        println!("{}", path.display());
+    }
+
+    Ok(())
+}
@@ -0,0 +1,63 @@
+use eyre::Result;
+
+use libasc::{fsck::{check_repository, repair}, repository::Repository};
+
+use crate::{exit::{self, Status}, i18n::t};
+
+#[derive(clap::Args)]
+pub struct Args {
+    /// Fix the problems that can be fixed without losing data.
+    #[arg(long)]
+    repair: bool
+}
+
+/// Check the repository and fix what can be fixed, like `asc fsck --repair`.
+pub fn doctor() -> Result<()> {
+    parse(Args { repair: true })
+}
+
+pub fn parse(args: Args) -> Result<()> {
+    let mut repo = Repository::load()?;
+
+    let problems = check_repository(&repo)?;
+
+    if problems.is_empty() {
+        println!("{}", t!("fsck-clean"));
+
+        return Ok(());
+    }
+
+    let mut repaired = 0;
+
+    for problem in &problems {
+        if args.repair && problem.is_repairable() && repair(&mut repo, problem)? {
+            println!(" * {}", t!("fsck-repaired", problem = problem));
+
+            repaired += 1;
+        }
+        else {
+            println!(" ! {problem}");
+        }
+    }
+
+    println!();
+
+    if repaired < problems.len() {
+        exit::set(Status::Corrupt);
+    }
+
+    if args.repair {
+        println!("{}", t!("fsck-summary-repaired", found = problems.len(), repaired = repaired));
+
+        if repaired > 0 {
+            repo.save()?;
+        }
+    }
+    else {
+        let repairable = problems.iter().filter(|problem| problem.is_repairable()).count();
+
+        println!("{}", t!("fsck-summary", found = problems.len(), repairable = repairable));
+    }
+
+    Ok(())
+}
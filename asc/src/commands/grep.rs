@@ -0,0 +1,62 @@
+use eyre::Result;
+
+use libasc::{grep::{pattern, search}, repository::Repository};
+
+#[derive(clap::Args)]
+pub struct Args {
+    /// The pattern to search for, as a regular expression.
+    pattern: String,
+
+    /// The version to search. Defaults to the current snapshot.
+    version: Option<String>,
+
+    /// Search every snapshot before the version as well,
+    /// showing which snapshot each match was found in.
+    #[arg(short, long)]
+    all: bool,
+
+    /// Match letters regardless of case.
+    #[arg(short, long)]
+    ignore_case: bool
+}
+
+pub fn parse(args: Args) -> Result<()> {
+    let repo = Repository::load()?;
+
+    let pattern = pattern(&args.pattern, args.ignore_case)?;
+
+    let version = if let Some(version) = args.version {
+        repo.normalise_version(&version)?
+    }
+    else {
+        repo.current_hash()
+    };
+
+    let snapshots: Vec<_> = if args.all {
+        repo.history
+            .iter_ancestors(version)
+            .collect::<Result<_>>()?
+    }
+    else {
+        vec![version]
+    };
+
+    let matches = search(&repo, snapshots, &pattern)?;
+
+    if matches.is_empty() {
+        eprintln!("No matches found.");
+
+        return Ok(());
+    }
+
+    for found in matches {
+        if args.all {
+            println!("{}:{}:{}: {}", found.snapshot, found.path, found.line, found.content);
+        }
+        else {
+            println!("{}:{}: {}", found.path, found.line, found.content);
+        }
+    }
+
+    Ok(())
+}
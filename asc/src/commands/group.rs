@@ -0,0 +1,167 @@
+use eyre::Result;
+
+use libasc::repository::Repository;
+
+use crate::{exit::{self, Status}, i18n::t};
+
+#[derive(clap::Subcommand)]
+pub enum Subcommands {
+    /// Create a new, empty group.
+    #[command(visible_alias = "new")]
+    Create {
+        name: String
+    },
+
+    /// Delete a group. Its members are kept as users.
+    Delete {
+        name: String
+    },
+
+    /// Add users to a group.
+    Add {
+        /// The name of the group.
+        group: String,
+
+        /// The users to add.
+        usernames: Vec<String>
+    },
+
+    /// Remove users from a group.
+    #[command(visible_alias = "rm")]
+    Remove {
+        /// The name of the group.
+        group: String,
+
+        /// The users to remove.
+        usernames: Vec<String>
+    },
+
+    /// List the groups in the repository, or the members of one group.
+    #[command(visible_alias = "ls")]
+    List {
+        group: Option<String>
+    }
+}
+
+pub fn parse(subcommand: Subcommands) -> Result<()> {
+    let mut repo = Repository::load()?;
+
+    use Subcommands::*;
+
+    match subcommand {
+        Create { name } => {
+            repo.users.groups_mut().create(name.clone())?;
+
+            println!("{}", t!("group-created", name = name));
+        }
+
+        Delete { name } => {
+            if repo.users.groups_mut().delete(&name).is_none() {
+                exit::set(Status::Failure);
+
+                eprintln!("{}", t!("group-not-found", name = name));
+
+                return Ok(());
+            }
+
+            println!("{}", t!("group-deleted", name = name));
+        }
+
+        Add { group, usernames } => {
+            if repo.users.groups().members(&group).is_none() {
+                exit::set(Status::Failure);
+
+                eprintln!("{}", t!("group-not-found", name = group));
+
+                return Ok(());
+            }
+
+            for username in usernames {
+                let Some(user) = repo.users.get_user(&username) else {
+                    eprintln!(" ! {}", t!("user-not-found", name = username));
+
+                    continue;
+                };
+
+                let key = user.public_key;
+
+                if repo.users.groups_mut().add_member(&group, key)? {
+                    println!(" * {}", t!("group-member-added", user = username, group = group));
+                }
+                else {
+                    println!(" * {}", t!("group-member-exists", user = username, group = group));
+                }
+            }
+        }
+
+        Remove { group, usernames } => {
+            if repo.users.groups().members(&group).is_none() {
+                exit::set(Status::Failure);
+
+                eprintln!("{}", t!("group-not-found", name = group));
+
+                return Ok(());
+            }
+
+            for username in usernames {
+                let Some(user) = repo.users.get_user(&username) else {
+                    eprintln!(" ! {}", t!("user-not-found", name = username));
+
+                    continue;
+                };
+
+                let key = user.public_key;
+
+                if repo.users.groups_mut().remove_member(&group, &key)? {
+                    println!(" * {}", t!("group-member-removed", user = username, group = group));
+                }
+                else {
+                    println!(" * {}", t!("group-member-missing", user = username, group = group));
+                }
+            }
+        }
+
+        List { group: Some(group) } => {
+            let Some(members) = repo.users.groups().members(&group) else {
+                exit::set(Status::Failure);
+
+                eprintln!("{}", t!("group-not-found", name = group));
+
+                return Ok(());
+            };
+
+            if members.is_empty() {
+                println!("{}", t!("group-empty", name = group));
+
+                return Ok(());
+            }
+
+            println!("{}", t!("group-members", name = group));
+
+            for key in members {
+                match repo.users.get_user(key) {
+                    Some(user) => println!(" * {}", user.name),
+                    None => println!(" * {}", t!("group-unknown-member", key = key))
+                }
+            }
+        }
+
+        List { group: None } => {
+            if repo.users.groups().is_empty() {
+                eprintln!("{}", t!("group-none"));
+
+                return Ok(());
+            }
+
+            println!("{}", t!("group-list"));
+
+            for (name, members) in repo.users.groups().iter() {
+                println!(" * {}", t!("group-list-entry", name = name, count = members.len()));
+            }
+        }
+    }
+
+    repo.save()?;
+
+    Ok(())
+}
@@ -1,18 +1,59 @@
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, NaiveDate, NaiveTime, TimeDelta, Utc};
 use clap::ValueEnum;
-use color_eyre::owo_colors::OwoColorize;
-use eyre::Result;
+use eyre::{bail, Result};
 
-use libasc::{hash::ObjectHash, repository::Repository, snapshot::Snapshot, unwrap};
+use libasc::{history::{Filter, HistoryWalk}, key::PublicKey, repository::Repository, snapshot::Snapshot, unwrap, utils::parse_duration};
 use relative_path::RelativePathBuf;
+use serde::Serialize;
+
+use crate::output;
 
 #[derive(Clone, Copy, ValueEnum)]
-enum Format {
+enum Style {
     Short,
     Medium,
     Long
 }
 
+/// A snapshot, as printed with `--format json`.
+#[derive(Serialize)]
+struct SnapshotEntry<'a> {
+    hash: String,
+    author: Option<&'a str>,
+    author_key: String,
+    timestamp: String,
+    message: &'a str,
+    parents: Vec<String>,
+    branch: Option<&'a str>,
+    branches: Vec<&'a str>,
+    tags: Vec<&'a str>,
+    current: bool
+}
+
+impl<'a> SnapshotEntry<'a> {
+    fn new(snapshot: &'a Snapshot, repo: &'a Repository) -> SnapshotEntry<'a> {
+        let mut parents: Vec<String> = snapshot.parents
+            .iter()
+            .map(|parent| parent.full())
+            .collect();
+
+        parents.sort();
+
+        SnapshotEntry {
+            hash: snapshot.hash.full(),
+            author: repo.users.get_user(&snapshot.author).map(|user| user.name.as_str()),
+            author_key: snapshot.author.to_string(),
+            timestamp: snapshot.timestamp.to_rfc3339(),
+            message: &snapshot.message,
+            parents,
+            branch: snapshot.branch.as_deref(),
+            branches: repo.refs.branches().get_names_for(snapshot.hash),
+            tags: repo.refs.tags().get_names_for(snapshot.hash),
+            current: repo.current_hash() == snapshot.hash
+        }
+    }
+}
+
 #[derive(clap::Args)]
 pub struct Args {
     /// The path to filter commits based on.
@@ -26,95 +67,139 @@ pub struct Args {
     #[arg(short, long)]
     branch: Option<String>,
 
-    /// The format to use when listing snapshots.
+    /// How much to show about each snapshot.
     #[arg(short, long, value_enum)]
-    format: Option<Format>,
+    style: Option<Style>,
+
+    /// Only show snapshots made by this user, given by name or public key.
+    #[arg(long)]
+    author: Option<String>,
+
+    /// Only show snapshots that change files matching this glob.
+    #[arg(long = "path", value_name = "GLOB")]
+    path_glob: Option<String>,
 
-    /// Check for snapshots before a certain datetime.
-    #[arg(long = "before")]
-    snapshots_before: Option<DateTime<Utc>>,
+    /// Only show snapshots made on or after this date.
+    /// This can be a timestamp, a day like 2025-01-31, or a time ago like 2w.
+    #[arg(long, alias = "after", value_parser = parse_date)]
+    since: Option<DateTime<Utc>>,
 
-    /// Check for snapshots after a certain datetime.
-    #[arg(long = "after")]
-    snapshots_after: Option<DateTime<Utc>>
+    /// Only show snapshots made on or before this date.
+    /// This can be a timestamp, a day like 2025-01-31, or a time ago like 2w.
+    #[arg(long, alias = "before", value_parser = parse_date)]
+    until: Option<DateTime<Utc>>,
+
+    /// Only show snapshots with a message matching this regular expression.
+    #[arg(long, value_name = "PATTERN")]
+    grep: Option<String>
 }
 
 fn first_line_only(message: &str) -> &str {
     message.lines().next().unwrap()
 }
 
-pub fn parse(args: Args) -> Result<()> {
-    if args.snapshots_before.is_some() && args.snapshots_after.is_some() {
-        eprintln!("'--before' and '--after' are mutually exclusive.");
+fn parse_date(raw: &str) -> Result<DateTime<Utc>, String> {
+    if let Ok(datetime) = raw.parse::<DateTime<Utc>>() {
+        return Ok(datetime);
+    }
 
-        return Ok(());
+    if let Ok(date) = raw.parse::<NaiveDate>() {
+        return Ok(date.and_time(NaiveTime::MIN).and_utc());
     }
 
-    let repo = Repository::load()?;
+    // A number on its own is more likely a mistyped date than a number of days.
+    if raw.ends_with(|c: char| c.is_ascii_alphabetic()) {
+        let ago = parse_duration(raw).map_err(|e| e.to_string())?;
 
-    let mut current_hash = if let Some(branch) = args.branch {
-        *unwrap!(
-            repo.branches.get(&branch),
-            "branch {branch:?} does not exist."
-        )
+        return TimeDelta::from_std(ago)
+            .ok()
+            .and_then(|ago| Utc::now().checked_sub_signed(ago))
+            .ok_or_else(|| format!("{raw:?} is too long ago"));
     }
-    else {
-        repo.current_hash
-    };
 
-    let mut snapshots: Vec<Snapshot> = vec![];
+    Err(format!("expected a timestamp, a day like 2025-01-31, or a time ago like 2w, got {raw:?}"))
+}
 
-    loop {
-        let current = repo.fetch_snapshot(current_hash)?;
+/// Find the key of an author, given by their name,
+/// their public key, or the start of their public key.
+pub(crate) fn resolve_author(repo: &Repository, query: &str) -> Result<PublicKey> {
+    if let Some(user) = repo.users.get_user(query) {
+        return Ok(user.public_key);
+    }
 
-        snapshots.push(current);
+    if let Ok(bytes) = hex::decode(query)
+        && let Ok(key) = PublicKey::from_bytes(&bytes)
+    {
+        return Ok(key);
+    }
 
-        let parents = unwrap!(
-            repo.history.get_parents(current_hash),
-            "snapshot hash {current_hash} is not referenced in the snapshot tree."
-        );
+    let prefix = query.to_ascii_uppercase();
 
-        // TODO: allow traversing if node is a merge child?
+    let mut found = repo.users
+        .iter()
+        .filter(|user| user.public_key.to_string().starts_with(&prefix));
 
-        // 0 parents -> root
-        // 2+ parents -> merge
-        if parents.len() != 1 {
-            break;
-        }
-        
-        let &next_hash = parents.iter().next().unwrap();
+    let Some(user) = found.next() else {
+        bail!("no user is called {query:?} or has a key starting with it.");
+    };
 
-        current_hash = next_hash;
+    if found.next().is_some() {
+        bail!("more than one user has a key starting with {query:?}.");
     }
 
-    if let Some(path) = &args.path {
-        let mut valid_snapshots = vec![];
+    Ok(user.public_key)
+}
 
-        let mut current_hash = ObjectHash::default();
+pub fn parse(args: Args) -> Result<()> {
+    let repo = Repository::load()?;
 
-        for snapshot in snapshots {
-            let Some(&content_hash) = snapshot.files.get(path) else {
-                continue;
-            };
+    let start = if let Some(branch) = args.branch {
+        unwrap!(
+            repo.refs.branches().get(&branch),
+            "branch {branch:?} does not exist."
+        )
+    }
+    else {
+        repo.current_hash()
+    };
 
-            if content_hash == current_hash {
-                continue;
-            }
+    let mut walk = HistoryWalk::new(&repo, start);
 
-            current_hash = content_hash;
+    if let Some(author) = &args.author {
+        walk = walk.filter(Filter::Author(resolve_author(&repo, author)?));
+    }
 
-            valid_snapshots.push(snapshot);
-        }
+    if let Some(path) = args.path {
+        walk = walk.filter(Filter::Path(path.into_string()));
+    }
 
-        snapshots = valid_snapshots;
+    if let Some(glob) = args.path_glob {
+        walk = walk.filter(Filter::Path(glob));
     }
 
-    if let Some(datetime) = args.snapshots_before {
-        snapshots.retain(|snapshot| snapshot.timestamp < datetime);
+    if let Some(datetime) = args.since {
+        walk = walk.filter(Filter::Since(datetime));
     }
 
-    if let Some(datetime) = args.snapshots_after {
-        snapshots.retain(|snapshot| snapshot.timestamp > datetime);
+    if let Some(datetime) = args.until {
+        walk = walk.filter(Filter::Until(datetime));
+    }
+
+    if let Some(pattern) = &args.grep {
+        walk = walk.filter(Filter::grep(pattern)?);
+    }
+
+    let snapshots: Vec<Snapshot> = walk
+        .take(args.limit.unwrap_or(usize::MAX))
+        .collect::<Result<_>>()?;
+
+    if output::is_json() {
+        let entries: Vec<SnapshotEntry> = snapshots
+            .iter()
+            .map(|snapshot| SnapshotEntry::new(snapshot, &repo))
+            .collect();
+
+        return output::print_json(&entries);
     }
 
     if snapshots.is_empty() {
@@ -123,24 +208,20 @@ pub fn parse(args: Args) -> Result<()> {
         return Ok(());
     }
 
-    let snapshots_to_show = snapshots
-        .iter()
-        .take(args.limit.unwrap_or(usize::MAX));
-
-    for snapshot in snapshots_to_show {
-        match args.format.unwrap_or(Format::Medium) {
-            Format::Short => {
+    for snapshot in &snapshots {
+        match args.style.unwrap_or(Style::Medium) {
+            Style::Short => {
                 let line = format!("{}", snapshot.hash);
 
-                if repo.current_hash == snapshot.hash {
-                    println!("{}", line.bright_green().bold());
+                if repo.current_hash() == snapshot.hash {
+                    println!("{}", output::current(line));
                 }
                 else {
                     println!("{line}");
                 }
             }
             
-            Format::Medium => {
+            Style::Medium => {
                 let author = repo.users
                     .get_user(&snapshot.author)
                     .map(|u| u.name.as_str())
@@ -150,7 +231,11 @@ pub fn parse(args: Args) -> Result<()> {
                     format!("user: {author}")
                 ];
 
-                let branches = repo.branches.get_names_for(snapshot.hash);
+                if let Some(branch) = &snapshot.branch {
+                    info.push(format!("on: {branch}"));
+                }
+
+                let branches = repo.refs.branches().get_names_for(snapshot.hash);
                 
                 if branches.len() > 1 {
                     info.push(format!("branches: {}", branches.join(", ")));
@@ -159,7 +244,7 @@ pub fn parse(args: Args) -> Result<()> {
                     info.push(format!("branch: {}", branches[0]));
                 }
 
-                let tags = repo.tags.get_names_for(snapshot.hash);
+                let tags = repo.refs.tags().get_names_for(snapshot.hash);
                 
                 if !tags.is_empty() {
                     info.push(format!("tags: {}", tags.join(", ")));
@@ -172,19 +257,19 @@ pub fn parse(args: Args) -> Result<()> {
                     info.join(", ")
                 );
 
-                if repo.current_hash == snapshot.hash {
-                    println!("{}", line.bright_green().bold());
+                if repo.current_hash() == snapshot.hash {
+                    println!("{}", output::current(line));
                 }
                 else {
                     println!("{line}");
                 }
             }
 
-            Format::Long => {
+            Style::Long => {
                 let line = format!("Hash: {:?}", snapshot.hash);
 
-                if repo.current_hash == snapshot.hash {
-                    println!("{}", line.bright_green().bold());
+                if repo.current_hash() == snapshot.hash {
+                    println!("{}", output::current(line));
                 }
                 else {
                     println!("{line}");
@@ -198,7 +283,11 @@ pub fn parse(args: Args) -> Result<()> {
                 println!("Author: {author}");
                 println!("Timestamp: {}", snapshot.timestamp);
 
-                let branches = repo.branches.get_names_for(snapshot.hash);
+                if let Some(branch) = &snapshot.branch {
+                    println!("Committed on: {branch}");
+                }
+
+                let branches = repo.refs.branches().get_names_for(snapshot.hash);
 
                 if branches.len() > 1 {
                     println!("Branches: {}", branches.join(", "));
@@ -207,7 +296,7 @@ pub fn parse(args: Args) -> Result<()> {
                     println!("Branch: {}", branches[0]);
                 }
 
-                let tags = repo.tags.get_names_for(snapshot.hash);
+                let tags = repo.refs.tags().get_names_for(snapshot.hash);
 
                 if !tags.is_empty() {
                     println!("Tags: {}", tags.join(", "));
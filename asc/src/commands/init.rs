@@ -4,6 +4,8 @@ use eyre::Result;
 
 use libasc::repository::Repository;
 
+use crate::{exit::{self, Status}, output::say};
+
 #[derive(clap::Args)]
 pub struct Args {
     /// The directory for the project.
@@ -37,6 +39,8 @@ pub fn parse(args: Args) -> Result<()> {
         .to_str();
 
     let Some(dir_name) = raw_dir_name.map(String::from) else {
+        exit::set(Status::Failure);
+
         eprintln!("Directory name contains invalid UTF-8, which is disallowed by this program.");
 
         return Ok(());
@@ -48,7 +52,7 @@ pub fn parse(args: Args) -> Result<()> {
 
     let repo = Repository::create_new(&root_dir, author, project_name)?;
 
-    println!(
+    say!(
         "Created new project {:?} in {} (user: {})",
         repo.project_name,
         repo.root_dir.display(),
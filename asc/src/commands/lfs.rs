@@ -0,0 +1,238 @@
+use std::{collections::BTreeSet, fs, sync::Arc};
+
+use eyre::Result;
+use libasc::{hash::ObjectHash, repository::Repository, sync::{client::Client, utils::Repo}, unwrap};
+use tokio::sync::RwLock;
+
+use crate::exit::{self, Status};
+
+#[derive(clap::Subcommand)]
+pub enum Subcommands {
+    /// Download large files that aren't stored locally.
+    Fetch {
+        /// The remote to fetch from. Defaults to all.
+        remote: Option<String>,
+
+        /// Fetch large files for every snapshot,
+        /// not just the current one.
+        #[arg(short, long)]
+        all: bool
+    },
+
+    /// Delete local copies of large files that aren't
+    /// used by the current snapshot or any branch tip.
+    /// 
+    /// Files are uploaded to the remote first, and only
+    /// deleted once the remote is known to have them.
+    Prune {
+        /// The remote that must hold the large files.
+        #[arg(default_value = "origin")]
+        remote: String
+    }
+}
+
+/// Download any large files used by `snapshots` that aren't stored
+/// locally, trying each remote in turn until all of them are found.
+/// 
+/// Large files in the current snapshot are written to the working
+/// directory if they were missing from it.
+pub async fn fetch_large_files(
+    repo_arc: Repo,
+    snapshots: Vec<ObjectHash>,
+    remote_filter: Option<&str>
+) -> Result<()>
+{
+    let (mut wanted, remotes) = {
+        let repo = repo_arc.read().await;
+
+        let wanted: BTreeSet<ObjectHash> = repo
+            .list_large_files(snapshots)?
+            .into_iter()
+            .filter(|&hash| !repo.has_large_file(hash))
+            .collect();
+
+        (wanted, repo.remotes.clone())
+    };
+
+    if wanted.is_empty() {
+        return Ok(());
+    }
+
+    for (name, remote) in remotes.into_iter() {
+        if let Some(filter) = remote_filter && name != filter {
+            continue;
+        }
+
+        if wanted.is_empty() {
+            break;
+        }
+
+        let mut client = Client::connect(remote).await?;
+
+        let fetched = client.make_lfs_fetch(
+            repo_arc.clone(),
+            wanted.iter().copied().collect()
+        ).await?;
+
+        println!(" * Fetched {} large files from {name}", fetched.len());
+
+        for hash in fetched {
+            wanted.remove(&hash);
+        }
+    }
+
+    if !wanted.is_empty() {
+        eprintln!(" ! {} large files could not be found on any remote", wanted.len());
+    }
+
+    let repo = repo_arc.read().await;
+
+    for path in repo.restore_large_files()? {
+        println!(" * Restored {path}");
+    }
+
+    Ok(())
+}
+
+/// Upload every large file in the repository's history to a remote
+/// that doesn't have it yet.
+pub async fn push_large_files(repo_arc: Repo, client: &mut Client) -> Result<()> {
+    let offered: Vec<ObjectHash> = {
+        let repo = repo_arc.read().await;
+
+        repo.list_large_files(repo.history.iter_hashes())?
+            .into_iter()
+            .filter(|&hash| repo.has_large_file(hash))
+            .collect()
+    };
+
+    if offered.is_empty() {
+        return Ok(());
+    }
+
+    let result = client.make_lfs_push(repo_arc, offered).await?;
+
+    if !result.uploaded.is_empty() {
+        println!(" * Uploaded {} large files", result.uploaded.len());
+    }
+
+    Ok(())
+}
+
+#[tokio::main]
+pub async fn parse(subcommand: Subcommands) -> Result<()> {
+    let repo = Repository::load()?;
+
+    use Subcommands::*;
+
+    match subcommand {
+        Fetch { remote, all } => {
+            if let Some(name) = &remote && !repo.remotes.contains(name) {
+                exit::set(Status::Failure);
+
+                eprintln!("No remote under the name {name:?}.");
+
+                return Ok(());
+            }
+
+            let snapshots = if all {
+                repo.history.iter_hashes().collect()
+            }
+            else {
+                vec![repo.current_hash()]
+            };
+
+            let repo_arc = Arc::new(RwLock::new(repo));
+
+            fetch_large_files(repo_arc, snapshots, remote.as_deref()).await?;
+        },
+
+        Prune { remote: name } => {
+            let Some(remote) = repo.remotes.get(&name).cloned() else {
+                exit::set(Status::Failure);
+
+                eprintln!("No remote under the name {name:?}.");
+
+                return Ok(());
+            };
+
+            let lfs_dir = repo.lfs_dir();
+
+            if !lfs_dir.exists() {
+                eprintln!("No large files are stored locally.");
+
+                return Ok(());
+            }
+
+            let in_use = {
+                let mut snapshots: Vec<ObjectHash> = repo.refs.branches().values().collect();
+
+                snapshots.push(repo.current_hash());
+
+                repo.list_large_files(snapshots)?
+            };
+
+            let mut candidates = vec![];
+
+            let entries = unwrap!(
+                fs::read_dir(&lfs_dir),
+                "failed to read directory: {}", lfs_dir.display()
+            );
+
+            for entry in entries {
+                let name = entry?.file_name();
+
+                let Ok(hash) = name.to_string_lossy().parse::<ObjectHash>() else {
+                    continue;
+                };
+
+                if !in_use.contains(&hash) {
+                    candidates.push(hash);
+                }
+            }
+
+            if candidates.is_empty() {
+                exit::set(Status::Clean);
+
+                eprintln!("No large files can be pruned.");
+
+                return Ok(());
+            }
+
+            let repo_arc = Arc::new(RwLock::new(repo));
+
+            let mut client = Client::connect(remote).await?;
+
+            let result = client.make_lfs_push(repo_arc.clone(), candidates.clone()).await?;
+
+            let refused: BTreeSet<ObjectHash> = result.refused.into_iter().collect();
+
+            let repo = repo_arc.read().await;
+
+            let mut pruned = 0;
+
+            for hash in candidates {
+                if refused.contains(&hash) {
+                    continue;
+                }
+
+                let path = repo.large_file_path(hash);
+
+                unwrap!(
+                    fs::remove_file(&path),
+                    "failed to remove large file: {}", path.display()
+                );
+
+                pruned += 1;
+            }
+
+            println!("Pruned {pruned} large files.");
+
+            if !refused.is_empty() {
+                eprintln!(" ! Kept {} large files that {name:?} has no snapshots for - push them first", refused.len());
+            }
+        }
+    }
+
+    Ok(())
+}
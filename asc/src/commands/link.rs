@@ -0,0 +1,363 @@
+use std::{fs, io::{stdin, stdout, IsTerminal, Write}, path::Path, sync::Arc};
+
+use eyre::Result;
+use libasc::{action::Action, hash::ObjectHash, key::PrivateKey, link::{self, link_dir, Link, Links}, repository::Repository, sync::{client::Client, clone::CloneOptions, remote::Remote}, unwrap, utils::normalise_with_root};
+use relative_path::{RelativePath, RelativePathBuf};
+use tokio::sync::RwLock;
+
+use crate::{commands::{clone, stash}, exit};
+
+#[derive(clap::Subcommand)]
+pub enum Subcommands {
+    /// Embed another repository at a path in this one.
+    Add {
+        /// Where to place the linked repository.
+        path: RelativePathBuf,
+
+        /// The URL of the repository to link.
+        url: String,
+
+        /// The version of the linked repository to pin.
+        /// Defaults to the version it was cloned at.
+        #[arg(short, long)]
+        version: Option<String>,
+
+        /// The private key to log into the remote with.
+        /// Defaults to the key of the current user.
+        #[arg(long)]
+        login_key: Option<String>
+    },
+
+    /// Pin a linked repository to the version it currently has checked out.
+    Update {
+        /// The path of the linked repository.
+        path: RelativePathBuf,
+
+        /// Switch the linked repository to this version before pinning it.
+        #[arg(short, long)]
+        version: Option<String>
+    },
+
+    /// List linked repositories and whether they match their pinned versions.
+    #[command(visible_alias = "ls")]
+    Status
+}
+
+fn is_repository(path: &Path) -> bool {
+    path.join(".asc").is_dir()
+}
+
+/// Switch a repository to a version, refusing if there are unsaved changes.
+///
+/// Returns `false` if the repository was left untouched because of unsaved changes.
+fn checkout(repo: &mut Repository, hash: ObjectHash) -> Result<bool> {
+    if repo.current_hash() == hash {
+        return Ok(true);
+    }
+
+    if repo.has_unsaved_changes()? {
+        return Ok(false);
+    }
+
+    let snapshot = repo.fetch_snapshot(hash)?;
+
+    stash::print_backup(repo.replace_cwd_with_snapshot(&snapshot)?);
+
+    repo.action_history.push(
+        Action::SwitchVersion {
+            before: repo.current_hash(),
+            after: hash
+        }
+    );
+
+    repo.refs.switch_to_hash(hash);
+
+    repo.save()?;
+
+    Ok(true)
+}
+
+/// Check if two remotes are on the same host, so
+/// one can be trusted to name the other as a link.
+fn same_host(a: &Remote, b: &Remote) -> bool {
+    match (a, b) {
+        (Remote::Ssh(a), Remote::Ssh(b)) => a.host().eq_ignore_ascii_case(b.host()),
+        (Remote::File(_), Remote::File(_)) => true,
+        _ => false
+    }
+}
+
+/// Ask whether to go ahead, which is only ever
+/// agreed to when there is someone to answer.
+fn confirm(message: &str) -> Result<bool> {
+    if !stdin().is_terminal() {
+        return Ok(false);
+    }
+
+    loop {
+        print!("{message} [y/n] ");
+
+        stdout().flush()?;
+
+        let mut input = String::new();
+
+        stdin().read_line(&mut input)?;
+
+        match input.trim() {
+            "y" | "Y" => return Ok(true),
+            "n" | "N" | "" => return Ok(false),
+            other => println!("Invalid input: {other:?}")
+        }
+    }
+}
+
+/// Clone or pull a single linked repository, then check out its pinned version.
+///
+/// Any links inside the linked repository are fetched as well.
+async fn fetch_link(root_dir: &Path, path: &RelativePath, link: &Link, user_key: &PrivateKey) -> Result<()> {
+    let full_path = link_dir(root_dir, path)?;
+
+    let mut client = Client::connect(link.remote.clone()).await?;
+
+    if is_repository(&full_path) {
+        let nested = Arc::new(RwLock::new(Repository::load_from(&full_path)?));
+
+        client.make_pull(nested, false).await?;
+    }
+    else {
+        unwrap!(
+            fs::create_dir_all(&full_path),
+            "failed to create directory for linked repository: {path}"
+        );
+
+        let result = client.clone_repo(&full_path, user_key.clone(), CloneOptions::default()).await?;
+
+        clone::report_dropped(&result.dropped);
+    }
+
+    let mut nested = Repository::load_from(&full_path)?;
+
+    if !checkout(&mut nested, link.hash)? {
+        eprintln!("Linked repository {path} has unsaved changes - leaving it at {}.", nested.current_hash());
+    }
+
+    Box::pin(fetch_links(&nested.root_dir, &nested.links, user_key, std::slice::from_ref(&link.remote))).await
+}
+
+/// Clone or pull every linked repository, checking each one out at its pinned version.
+///
+/// The links came from `sources`, so any on other hosts are only fetched
+/// if the user agrees, since they would be logged into with the user's key.
+/// Links with paths outside the repository are skipped.
+pub async fn fetch_links(root_dir: &Path, links: &Links, user_key: &PrivateKey, sources: &[Remote]) -> Result<()> {
+    for (path, link) in links.iter() {
+        if let Err(e) = link::check_path(path) {
+            exit::set(exit::Status::Failure);
+
+            eprintln!(" ! Skipped linked repository {path} - {e}");
+
+            continue;
+        }
+
+        let trusted = sources.iter().any(|source| same_host(source, &link.remote));
+
+        if !trusted && !confirm(&format!("Linked repository {path} is on another host, at {}. Fetch it?", link.remote))? {
+            exit::set(exit::Status::Failure);
+
+            eprintln!(" ! Skipped linked repository {path}, since {} isn't on the same host as where it was linked from.", link.remote);
+
+            continue;
+        }
+
+        println!("Fetching linked repository: {path} ({})", link.remote);
+
+        fetch_link(root_dir, path, link, user_key).await?;
+    }
+
+    Ok(())
+}
+
+#[tokio::main]
+pub async fn parse(subcommand: Subcommands) -> Result<()> {
+    let mut repo = Repository::load()?;
+
+    use Subcommands::*;
+
+    match subcommand {
+        Add { path, url, version, login_key } => {
+            let path = normalise_with_root(&path, &repo.root_dir);
+
+            if let Err(e) = link::check_path(&path) {
+                exit::set(exit::Status::Failure);
+
+                eprintln!("Cannot link a repository at {path}: {e}");
+
+                return Ok(());
+            }
+
+            if let Some(existing) = repo.links.get(&path) {
+                exit::set(exit::Status::Failure);
+
+                eprintln!("There is already a repository linked at {path} ({}).", existing.remote);
+
+                return Ok(());
+            }
+
+            if repo.staging.paths().any(|p| p.starts_with(&path)) {
+                exit::set(exit::Status::Failure);
+
+                eprintln!("Cannot link a repository at {path} because it contains tracked files.");
+
+                return Ok(());
+            }
+
+            let remote = unwrap!(
+                Remote::from_url(&url),
+                "could not understand URL: {url:?}"
+            );
+
+            let user_key = if let Some(raw_key) = login_key {
+                raw_key.parse::<PrivateKey>()?
+            }
+            else {
+                let Some(user) = repo.current_user() else {
+                    exit::set(exit::Status::Failure);
+
+                    eprintln!("No valid user is set for this repository.");
+
+                    return Ok(());
+                };
+
+                user.private_key.clone().unwrap()
+            };
+
+            let full_path = link_dir(&repo.root_dir, &path)?;
+
+            let mut nested = if is_repository(&full_path) {
+                Repository::load_from(&full_path)?
+            }
+            else {
+                unwrap!(
+                    fs::create_dir_all(&full_path),
+                    "failed to create directory: {path}"
+                );
+
+                let mut client = Client::connect(remote.clone()).await?;
+
+                let result = client.clone_repo(&full_path, user_key.clone(), CloneOptions::default()).await?;
+
+                clone::report_dropped(&result.dropped);
+
+                result.repo
+            };
+
+            if let Some(version) = version {
+                let hash = nested.normalise_version(&version)?;
+
+                if !checkout(&mut nested, hash)? {
+                    exit::set(exit::Status::Changes);
+
+                    eprintln!("Cannot switch linked repository to {hash} with unsaved changes.");
+
+                    return Ok(());
+                }
+            }
+
+            fetch_links(&nested.root_dir, &nested.links, &user_key, std::slice::from_ref(&remote)).await?;
+
+            let hash = nested.current_hash();
+
+            repo.links.add(path.clone(), Link { remote, hash });
+
+            println!("Linked {url} at {path} ({hash})");
+        }
+
+        Update { path, version } => {
+            let path = normalise_with_root(&path, &repo.root_dir);
+
+            let Some(link) = repo.links.get_mut(&path) else {
+                exit::set(exit::Status::Failure);
+
+                eprintln!("No repository is linked at {path}.");
+
+                return Ok(());
+            };
+
+            let full_path = link_dir(&repo.root_dir, &path)?;
+
+            if !is_repository(&full_path) {
+                exit::set(exit::Status::Failure);
+
+                eprintln!("Linked repository at {path} is missing - run `asc pull` to fetch it.");
+
+                return Ok(());
+            }
+
+            let mut nested = Repository::load_from(&full_path)?;
+
+            if let Some(version) = version {
+                let hash = nested.normalise_version(&version)?;
+
+                if !checkout(&mut nested, hash)? {
+                    exit::set(exit::Status::Changes);
+
+                    eprintln!("Cannot switch linked repository to {hash} with unsaved changes.");
+
+                    return Ok(());
+                }
+            }
+
+            let old = link.hash;
+            let new = nested.current_hash();
+
+            if old == new {
+                exit::set(exit::Status::Clean);
+
+                eprintln!("Link {path} is already pinned to {new}.");
+
+                return Ok(());
+            }
+
+            link.hash = new;
+
+            println!("Updated link {path}: {old} -> {new}");
+        }
+
+        Status => {
+            if repo.links.is_empty() {
+                eprintln!("No repositories are linked in this repository.");
+
+                return Ok(());
+            }
+
+            for (path, link) in repo.links.iter() {
+                let full_path = path.to_logical_path(&repo.root_dir);
+
+                if !is_repository(&full_path) {
+                    println!(" ! {path} is missing (pinned at {}, from {})", link.hash, link.remote);
+
+                    continue;
+                }
+
+                let nested = Repository::load_from(&full_path)?;
+
+                let mut line = if nested.current_hash() == link.hash {
+                    format!(" * {path} at {} ({})", link.hash, link.remote)
+                }
+                else {
+                    format!(" ! {path} at {}, pinned at {} ({})", nested.current_hash(), link.hash, link.remote)
+                };
+
+                if nested.has_unsaved_changes()? {
+                    line = format!("{line} [unsaved changes]");
+                }
+
+                println!("{line}");
+            }
+        }
+    }
+
+    repo.save()?;
+
+    Ok(())
+}
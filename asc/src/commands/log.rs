@@ -1,7 +1,8 @@
-use color_eyre::owo_colors::OwoColorize;
 use eyre::Result;
 
-use libasc::repository::Repository;
+use libasc::{repository::Repository, retention};
+
+use crate::output;
 
 #[derive(clap::Args)]
 pub struct Args {
@@ -11,12 +12,50 @@ pub struct Args {
 
     /// Show hidden redoable actions.
     #[arg(long)]
-    all: bool
+    all: bool,
+
+    /// Show the operations that were refused because they would have
+    /// rewritten or deleted history the repository has to keep.
+    #[arg(long, conflicts_with = "all")]
+    blocked: bool
+}
+
+fn list_blocked(repo: &Repository, limit: Option<usize>) -> Result<()> {
+    let log = retention::audit_log(repo)?;
+
+    if log.is_empty() {
+        println!("No operations have been refused by retention rules.");
+
+        return Ok(());
+    }
+
+    println!("Refused operations:");
+
+    for entry in log.iter().rev().take(limit.unwrap_or(usize::MAX)) {
+        let who = entry.user
+            .and_then(|key| repo.users.get_user(&key))
+            .map_or("<unknown user>", |user| user.name.as_str());
+
+        println!(
+            " * {} by {who}: `{}` ({} on {:?} is kept until {})",
+            entry.when.format("%Y-%m-%d %H:%M"),
+            entry.operation,
+            entry.hold.hash,
+            entry.hold.branch,
+            entry.hold.until.format("%Y-%m-%d")
+        );
+    }
+
+    Ok(())
 }
 
 pub fn parse(args: Args) -> Result<()> {
     let repo = Repository::load()?;
 
+    if args.blocked {
+        return list_blocked(&repo, args.limit);
+    }
+
     let (mut actions, redoable) = repo.action_history.as_slices();
 
     if let Some(limit) = args.limit {
@@ -42,7 +81,7 @@ pub fn parse(args: Args) -> Result<()> {
         for action in redoable {
             let s = format!(" * {action}");
 
-            println!("{}", s.dimmed());
+            println!("{}", output::undone(s));
         }
     }
 
@@ -50,7 +89,7 @@ pub fn parse(args: Args) -> Result<()> {
         let mut s = format!(" * {action}");
         
         if Some(action) == repo.action_history.current() {
-            s = format!("{} (you are here)", s.bright_green().bold());
+            s = format!("{} (you are here)", output::highlight(s));
         }
 
         println!("{s}");
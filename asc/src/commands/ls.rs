@@ -1,9 +1,11 @@
-use std::{env::current_dir, fs};
+use std::env::current_dir;
 
 use eyre::Result;
-use libasc::{change::FileChange, repository::Repository, utils::{filter_paths_with_glob_strict, hash_raw_bytes}};
+use libasc::{change::FileChange, mode::{self, FileMode}, repository::Repository, utils::{filter_paths_with_glob_strict, hash_raw_bytes}};
 use relative_path::{PathExt, RelativePathBuf};
 
+use crate::{exit::{self, Status}, output::{self, FileEntry}};
+
 #[derive(clap::Args)]
 pub struct Args {
     /// The pattern to glob against. Omitting this lists from the repository root.
@@ -41,9 +43,11 @@ pub fn parse(mut args: Args) -> Result<()> {
         args.patterns.push(RelativePathBuf::from("."));
     }
 
+    let tracked: Vec<RelativePathBuf> = repo.staging.paths().cloned().collect();
+
     let filter_result = filter_paths_with_glob_strict(
         &args.patterns,
-        &repo.staged_files,
+        &tracked,
         &repo.root_dir
     );
 
@@ -51,13 +55,15 @@ pub fn parse(mut args: Args) -> Result<()> {
         Ok(matches) => matches,
         
         Err(invalid_path) => {
+            exit::set(Status::Failure);
+
             eprintln!("Path outside of tree: {invalid_path}");
 
             return Ok(());
         }
     };
 
-    if valid_paths.is_empty() {
+    if valid_paths.is_empty() && !output::is_json() {
         eprintln!("No paths found.");
 
         return Ok(());
@@ -65,34 +71,47 @@ pub fn parse(mut args: Args) -> Result<()> {
 
     valid_paths.sort();
 
+    let mut entries: Vec<FileEntry> = vec![];
+
     for path in valid_paths {
         let absolute = path.to_logical_path(&repo.root_dir);
 
         let display_path = absolute.relative_to(&current_dir)?;
 
         if !args.include_changes {
-            println!("{display_path}");
+            if output::is_json() {
+                entries.push(FileEntry::new(path));
+            }
+            else {
+                println!("{display_path}");
+            }
 
             continue;
         }
-        
-        if !absolute.exists() {
-            println!("{}", FileChange::Missing(display_path));
 
-            continue;
+        let change = if !mode::exists(&absolute) {
+            FileChange::Missing(display_path)
         }
+        else if hash_raw_bytes(mode::read_working_bytes(&absolute)?) == snapshot.files[path]
+            && FileMode::of(&absolute, snapshot.mode(path))? == snapshot.mode(path)
+        {
+            FileChange::Unchanged(display_path)
+        }
+        else {
+            FileChange::Edited(display_path)
+        };
 
-        let data = fs::read(absolute)?;
-
-        let hash = hash_raw_bytes(data);
-
-        if hash == snapshot.files[path] {
-            println!("{}", FileChange::Unchanged(display_path));
+        if output::is_json() {
+            entries.push(FileEntry { path: path.to_string(), ..FileEntry::from(&change) });
         }
         else {
-            println!("{}", FileChange::Edited(display_path));
+            println!("{change}");
         }
     }
 
+    if output::is_json() {
+        output::print_json(&entries)?;
+    }
+
     Ok(())
 }
@@ -1,6 +1,4 @@
-use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
-
-use chrono::Utc;
+use std::collections::{BTreeMap, HashMap, HashSet};
 
 use eyre::Result;
 
@@ -8,35 +6,9 @@ use relative_path::RelativePathBuf;
 // TODO: write your own
 use threeway_merge::{merge_strings, MergeOptions};
 
-use libasc::{action::Action, graph::Graph, hash::ObjectHash, repository::Repository, set, snapshot::Snapshot, unwrap, utils::get_content_from_editor};
-
-use crate::commands::commit::COMMIT_TEMPLATE_MESSAGE;
-
-fn nodes_to_root(graph: &Graph, node: ObjectHash) -> HashMap<ObjectHash, usize> {
-    let mut queue: VecDeque<(ObjectHash, usize)> = VecDeque::new();
-    let mut distances = HashMap::new();
-
-    queue.push_back((node, 0));
-
-    while let Some((next, distance)) = queue.pop_front() {
-        let parents = graph.get_parents(next).unwrap();
-
-        for parent in parents {
-            let new_distance = if let Some(&existing_distance) = distances.get(parent) {
-                distance.max(existing_distance) + 1
-            }
-            else {
-                distance + 1
-            };
-
-            queue.push_back((*parent, new_distance));
-
-            distances.insert(*parent, new_distance);
-        }
-    }
+use libasc::{graph::Graph, hash::ObjectHash, mode::FileMode, repository::Repository, set, snapshot::Snapshot, utils::get_content_from_editor};
 
-    distances
-}
+use crate::{commands::{commit::COMMIT_TEMPLATE_MESSAGE, stash}, exit::{self, Status}, output::say};
 
 #[derive(Debug)]
 enum Ancestry {
@@ -44,34 +16,24 @@ enum Ancestry {
     Exclusive(ObjectHash)
 }
 
-fn find_closest_common_ancestor(graph: &Graph, u: ObjectHash, v: ObjectHash) -> Option<Ancestry> {
-    let parents_u = nodes_to_root(graph, u);
-
-    // v is a parent of u
-    if parents_u.contains_key(&v) {
-        return Some(Ancestry::Inclusive(v));
-    }
-
-    let parents_v = nodes_to_root(graph, v);
-
-    // u is a parent of v
-    if parents_v.contains_key(&u) {
-        return Some(Ancestry::Inclusive(u));
-    }
+fn find_closest_common_ancestor(graph: &Graph, u: ObjectHash, v: ObjectHash) -> Result<Option<Ancestry>> {
+    let ancestry = graph
+        .merge_base(u, v)?
+        .map(|base| {
+            // One is a parent of the other
+            if base == u || base == v {
+                Ancestry::Inclusive(base)
+            }
+            else {
+                Ancestry::Exclusive(base)
+            }
+        });
 
-    parents_u
-        .iter()
-        .filter_map(|(item, count)| {
-            parents_v
-                .get(item)
-                .map(|count2| (item, count.max(count2)))
-        })
-        .min_by(|(_, u_depth), (_, v_depth)| u_depth.cmp(v_depth))
-        .map(|(&k, _)| Ancestry::Exclusive(k))
+    Ok(ancestry)
 }
 
 pub fn prettify_hash(repo: &Repository, hash: ObjectHash) -> String {
-    if let Some(branch_name) = repo.branches.get_name_for(hash) {
+    if let Some(branch_name) = repo.refs.branches().get_name_for(hash) {
         branch_name.to_string()
     }
     else {
@@ -118,6 +80,8 @@ pub fn parse(args: Args) -> Result<()> {
     let mut repo = Repository::load()?;
 
     if repo.has_unsaved_changes()? {
+        exit::set(Status::Changes);
+
         eprintln!("Cannot merge with unsaved changes.");
 
         return Ok(());
@@ -129,10 +93,12 @@ pub fn parse(args: Args) -> Result<()> {
         repo.normalise_version(&version)?
     }
     else {
-        let u = repo.current_hash;
+        let u = repo.current_hash();
         let v = target;
 
-        let Some(ancestry) = find_closest_common_ancestor(&repo.history, u, v) else {
+        let Some(ancestry) = find_closest_common_ancestor(&repo.history, u, v)? else {
+            exit::set(Status::Failure);
+
             eprintln!("could not identify a common ancestor for snapshots {u} and {v}");
 
             return Ok(());
@@ -140,7 +106,7 @@ pub fn parse(args: Args) -> Result<()> {
 
         match ancestry {
             // Fast-forward, but we're already at the child, so no changes made
-            Ancestry::Inclusive(parent) if parent != repo.current_hash => {
+            Ancestry::Inclusive(parent) if parent == target => {
                 eprintln!("Already on the child of the fast-forward, therefore no changes have been made.");
                 
                 return Ok(());
@@ -150,15 +116,15 @@ pub fn parse(args: Args) -> Result<()> {
             Ancestry::Inclusive(_) => {
                 let snapshot = repo.fetch_snapshot(target)?;
 
-                repo.replace_cwd_with_snapshot(&snapshot)?;
+                stash::print_backup(repo.replace_cwd_with_snapshot(&snapshot)?);
 
-                repo.current_hash = target;
+                repo.advance_head(target);
 
-                if let Some(branch) = repo.branches.get_name_for(target) {
-                    println!("Fast-forwarded to {branch} ({target})");
+                if let Some(branch) = repo.refs.branches().get_name_for(target) {
+                    say!("Fast-forwarded to {branch} ({target})");
                 }
                 else {
-                    println!("Fast-forwaded to {target}");
+                    say!("Fast-forwaded to {target}");
                 }
 
                 repo.save()?;
@@ -170,20 +136,24 @@ pub fn parse(args: Args) -> Result<()> {
         }
     };
 
-    let base_files = repo.fetch_snapshot(ancestor)?.files;
+    let base = repo.fetch_snapshot(ancestor)?;
+
+    let base_files = &base.files;
 
     let options = MergeOptions {
         base_label: Some("original".to_string()),
-        ours_label: Some(prettify_hash(&repo, repo.current_hash)),
+        ours_label: Some(prettify_hash(&repo, repo.current_hash())),
         theirs_label: Some(prettify_hash(&repo, target)),
 
         .. MergeOptions::default()
     };
     
-    let our_files = repo.fetch_current_snapshot()?.files;
+    let ours = repo.fetch_current_snapshot()?;
+    let our_files = &ours.files;
     let our_paths: HashSet<&RelativePathBuf> = HashSet::from_iter(our_files.keys());
 
-    let their_files = repo.fetch_snapshot(target)?.files;
+    let theirs = repo.fetch_snapshot(target)?;
+    let their_files = &theirs.files;
     let their_paths = HashSet::from_iter(their_files.keys());
 
     let mut merged_files: HashMap<RelativePathBuf, MergeType> = HashMap::new();
@@ -221,6 +191,8 @@ pub fn parse(args: Args) -> Result<()> {
     }
     
     let Some(user) = repo.current_user() else {
+        exit::set(Status::Failure);
+
         eprintln!("No valid user is set for this repository.");
 
         return Ok(());
@@ -253,16 +225,30 @@ pub fn parse(args: Args) -> Result<()> {
         files.insert(path, hash);
     }
 
+    // A file whose mode was only changed on one side takes that
+    // side's mode, and files only on one side keep theirs.
+    let modes: BTreeMap<RelativePathBuf, FileMode> = files
+        .keys()
+        .map(|path| {
+            let mode = match (our_files.contains_key(path), their_files.contains_key(path)) {
+                (true, false) => ours.mode(path),
+                (false, _) => theirs.mode(path),
+                (true, true) if ours.mode(path) == base.mode(path) => theirs.mode(path),
+                (true, true) => ours.mode(path)
+            };
+
+            (path.clone(), mode)
+        })
+        .collect();
+
     if !is_clean_merge {
-        let new_staged_files: Vec<RelativePathBuf> = repo.staged_files
-            .iter()
-            .filter(|p| dirty_files.contains(p))
-            .cloned()
-            .collect();
+        let previous_len = repo.staging.len();
 
-        let conflicting_files = repo.staged_files.len() - new_staged_files.len();
+        repo.staging.retain(|p, _| dirty_files.contains(p));
 
-        repo.staged_files = new_staged_files;
+        let conflicting_files = previous_len - repo.staging.len();
+
+        exit::set(Status::Conflicts);
 
         eprintln!("Finished merge unsuccessfully because of {conflicting_files} conflicting files:");
 
@@ -275,18 +261,18 @@ pub fn parse(args: Args) -> Result<()> {
 
     let current_repr = match repo.current_branch() {
         Some(name) => name.to_string(),
-        None => format!("{}", repo.current_hash)
+        None => format!("{}", repo.current_hash())
     };
 
-    let target_repr = match repo.branches.get_name_for(target) {
+    let target_repr = match repo.refs.branches().get_name_for(target) {
         Some(name) => name.to_string(),
-        None => format!("{}", repo.current_hash)
+        None => format!("{}", repo.current_hash())
     };
 
-    println!("Merged {current_repr} and {target_repr}.");
+    say!("Merged {current_repr} and {target_repr}.");
 
     if args.no_commit {
-        repo.replace_cwd_with_files(&files)?;
+        stash::print_backup(repo.replace_cwd_with_files(&files, &modes)?);
 
         eprintln!("Finished merge but snapshot must be committed manually.");
 
@@ -301,45 +287,43 @@ pub fn parse(args: Args) -> Result<()> {
         msg
     }
     else {
-        let editor = args.editor.unwrap_or(
-            unwrap!(
-                std::env::var("EDITOR"),
-                "environment variable 'EDITOR' is not set."
-            )
-        );
+        let editor = match args.editor {
+            Some(editor) => editor,
+            None => repo.config.ui.editor()?
+        };
 
         let snapshot_message_path = &repo.main_dir().join("SNAPSHOT_MESSAGE");
 
-        get_content_from_editor(&editor, snapshot_message_path, COMMIT_TEMPLATE_MESSAGE)?
+        let mut template = repo.commit_template()?.unwrap_or_default();
+
+        template.push_str(COMMIT_TEMPLATE_MESSAGE);
+
+        get_content_from_editor(&editor, snapshot_message_path, &template)?
     };
 
-    let snapshot = Snapshot::new(
-        author_key,
-        message,
-        Utc::now(),
-        files,
-        set![repo.current_hash, target]
-    );
+    if let Err(e) = repo.config.commit.validate_message(&message) {
+        exit::set(Status::Failure);
 
-    repo.history.insert(snapshot.hash, repo.current_hash);
-    repo.history.insert(snapshot.hash, target);
+        eprintln!("Invalid snapshot message: {e}.");
 
-    if let Some(name) = repo.current_branch() {
-        repo.branches.create(name.to_string(), snapshot.hash);
+        return Ok(());
     }
 
-    repo.action_history.push(
-        Action::SwitchVersion {
-            before: repo.current_hash,
-            after: snapshot.hash
-        }
+    let snapshot = Snapshot::new_with_modes(
+        author_key,
+        message,
+        repo.now(),
+        files,
+        modes,
+        set![repo.current_hash(), target],
+        repo.current_branch().map(String::from)
     );
 
-    repo.current_hash = snapshot.hash;
+    let hash = repo.append_snapshot(snapshot)?;
 
     repo.save()?;
     
-    println!("New commit: {:?}", snapshot.hash);
+    say!("New commit: {hash:?}");
     
     Ok(())
 }
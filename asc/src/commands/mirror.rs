@@ -0,0 +1,156 @@
+use std::sync::Arc;
+
+use eyre::Result;
+use libasc::{repository::Repository, sync::{client::Client, mirror::{MirrorOptions, MirrorResult, MirrorUpdate}}};
+use tokio::sync::RwLock;
+
+use crate::{commands::lfs::push_large_files, exit::{self, Status}, output::say};
+
+#[derive(clap::Args)]
+pub struct Args {
+    /// The remote to mirror to.
+    remote: String,
+
+    /// Replace the remote's trash with this one,
+    /// along with what is in it.
+    #[arg(long)]
+    trash: bool,
+
+    /// Replace the remote's stash with this one,
+    /// along with what each entry needs to be applied.
+    #[arg(long)]
+    stash: bool
+}
+
+fn print_results(results: &[MirrorResult]) {
+    if results.is_empty() {
+        say!(" * Every branch and tag is up-to-date");
+
+        return;
+    }
+
+    for result in results {
+        let (kind, name, update) = match result {
+            MirrorResult::Branch(name, update) => ("Branch", name, update),
+            MirrorResult::Tag(name, update) => ("Tag", name, update),
+
+            MirrorResult::Trash(update) => {
+                if let MirrorUpdate::Held(hash) = update {
+                    say!(" ! The remote's trash was left alone, since {hash} would have been trashed, and it is held there for retention");
+                }
+
+                continue;
+            }
+        };
+
+        let line = match update {
+            MirrorUpdate::Created(hash) => format!(" * {kind} {name:?} created on remote ({hash})"),
+
+            MirrorUpdate::Moved(old, new) => format!(" * {kind} {name:?} moved on remote ({old} -> {new})"),
+
+            MirrorUpdate::Deleted(hash) => format!(" * {kind} {name:?} deleted on remote (was at {hash})"),
+
+            MirrorUpdate::Linear => format!(" ! {kind} {name:?} has to stay linear on remote, so it was left alone"),
+
+            MirrorUpdate::Protected => format!(" ! {kind} {name:?} is protected on remote, so it was left alone"),
+
+            MirrorUpdate::Denied => format!(" ! {kind} {name:?} is in a namespace you can't push to"),
+
+            MirrorUpdate::InvalidName(reason) => format!(" ! {kind} {name:?} isn't allowed on remote - {reason}"),
+
+            MirrorUpdate::Held(hash) => {
+                format!(" ! {kind} {name:?} can't be moved back or deleted on remote, since {hash} is held there for retention")
+            }
+        };
+
+        say!("{line}");
+    }
+}
+
+#[tokio::main]
+pub async fn parse(args: Args) -> Result<()> {
+    let repo = Repository::load()?;
+
+    let Some(remote) = repo.remotes.get(&args.remote).cloned() else {
+        exit::set(Status::Failure);
+
+        eprintln!("There is no remote called {:?}.", args.remote);
+
+        return Ok(());
+    };
+
+    let options = MirrorOptions {
+        trash: args.trash,
+        stash: args.stash
+    };
+
+    let repo_arc = Arc::new(RwLock::new(repo));
+
+    say!("Mirroring to: {}", args.remote);
+
+    let mut client = Client::connect(remote.clone()).await?;
+
+    let report = client.make_mirror(repo_arc.clone(), options).await?;
+
+    say!("Sent: {} | Received: {}", client.bytes_sent(), client.bytes_recv());
+
+    say!();
+
+    print_results(&report.results);
+
+    // What the remote has now is remembered, like after a push.
+    let mut repo = repo_arc.write().await;
+
+    let branches: Vec<(String, _)> = repo.refs
+        .branches()
+        .iter()
+        .map(|(name, tip)| (name.to_string(), tip))
+        .collect();
+
+    let refused: Vec<&str> = report.results
+        .iter()
+        .filter_map(|result| match result {
+            MirrorResult::Branch(name, MirrorUpdate::Linear | MirrorUpdate::InvalidName(_) | MirrorUpdate::Held(_)) => Some(name.as_str()),
+            _ => None
+        })
+        .collect();
+
+    for (name, tip) in branches {
+        if !refused.contains(&name.as_str()) {
+            repo.set_remote_tip(&args.remote, &name, tip);
+        }
+    }
+
+    drop(repo);
+
+    say!();
+
+    if report.is_complete() {
+        say!(
+            "Verified: the remote has all {} snapshots and {} pieces of content",
+            report.remote.snapshots,
+            report.remote.contents
+        );
+    }
+    else {
+        exit::set(Status::Failure);
+
+        eprintln!(
+            "The mirror is incomplete: {} snapshots and {} pieces of content here, but {} and {} on the remote.",
+            report.local.snapshots,
+            report.local.contents,
+            report.remote.snapshots,
+            report.remote.contents
+        );
+    }
+
+    // Large files go over their own connection, once
+    // the snapshots that point to them are on the remote.
+    let mut client = Client::connect(remote).await?;
+
+    push_large_files(repo_arc.clone(), &mut client).await?;
+
+    repo_arc.write().await.save()?;
+
+    Ok(())
+}
@@ -1,7 +1,11 @@
+use std::collections::HashMap;
+
 use chrono::{DateTime, Utc};
 use eyre::Result;
 
-use libasc::{action::Action, graph::Graph, hash::ObjectHash, repository::Repository};
+use libasc::{action::Action, hash::ObjectHash, repository::Repository, retention, snapshot::Snapshot, unwrap};
+
+use crate::{commands::commit::parse_date, exit::{self, Status}};
 
 #[derive(clap::Args)]
 pub struct Args {
@@ -16,65 +20,82 @@ pub struct Args {
     #[arg(short, long)]
     message: Option<String>,
 
-    /// The new datetime for the snapshot.
-    #[arg(long)]
-    datetime: Option<DateTime<Utc>>
+    /// The new time for the snapshot, like `2025-01-31T12:00:00Z`,
+    /// `2025-01-31` or `@1738324800`.
+    #[arg(long, visible_alias = "datetime", value_parser = parse_date)]
+    date: Option<DateTime<Utc>>
+}
+
+/// Make a snapshot again after it was edited, so that it gets a
+/// new hash and is signed again by its (possibly new) author.
+fn remake(repo: &Repository, old_hash: ObjectHash, snapshot: Snapshot) -> Result<Snapshot> {
+    let key = unwrap!(
+        repo.users
+            .get_user(&snapshot.author)
+            .and_then(|user| user.private_key.clone()),
+        "cannot sign snapshot {old_hash} again without the private key of its author."
+    );
+
+    Ok(Snapshot::new_with_modes(
+        key,
+        snapshot.message,
+        snapshot.timestamp,
+        snapshot.files,
+        snapshot.modes,
+        snapshot.parents,
+        snapshot.branch
+    ))
 }
 
-fn update_recursively(
+/// Make every descendant of `old` again on top of `new`, moving any
+/// branches at them along, and return how many were made again.
+/// 
+/// Descendants are rewritten parents first, so a merge of two rewritten
+/// snapshots is only rewritten once, with both of its new parents.
+fn update_descendants(
     old: ObjectHash,
     new: ObjectHash,
-    inverted: &Graph,
     repo: &mut Repository,
     updated_branches: &mut Vec<(String, (ObjectHash, ObjectHash))>
 ) -> Result<usize>
 {
-    let mut count = 0;
-
-    if let Some(parents) = repo.history.get_parents(old).cloned() {
-        for parent in parents {
-            repo.history.insert(new, parent);
-        }
-    }
-
-    let children = inverted.get_parents(old).unwrap();
+    let mut rewritten = HashMap::from([(old, new)]);
 
-    for &child in children {
+    for child in repo.history.sorted_descendants(old) {
         let mut child_snapshot = repo.fetch_snapshot(child)?;
 
-        child_snapshot.parents.remove(&old);
-        child_snapshot.parents.insert(new);
-
-        let old = child_snapshot.hash;
+        child_snapshot.parents = child_snapshot.parents
+            .into_iter()
+            .map(|parent| rewritten.get(&parent).copied().unwrap_or(parent))
+            .collect();
 
-        child_snapshot.rehash();
+        let child_snapshot = remake(repo, child, child_snapshot)?;
 
-        let new = child_snapshot.hash;
+        let new_child = repo.save_snapshot(child_snapshot)?;
 
-        repo.save_snapshot(child_snapshot)?;
-
-        let search = repo.branches
+        let search = repo.refs.branches()
             .iter()
-            .find(|(_, hash)| **hash == old)
-            .map(|(name, _)| name)
-            .cloned();
+            .find(|&(_, hash)| hash == child)
+            .map(|(name, _)| name.to_string());
 
         if let Some(name) = search {
-            repo.branches.create(name.clone(), new);
+            repo.refs.branches_mut().create(name.clone(), new_child);
 
-            updated_branches.push((name, (old, new)));
+            updated_branches.push((name, (child, new_child)));
         }
 
-        count += update_recursively(old, new, inverted, repo, updated_branches)?;
+        rewritten.insert(child, new_child);
     }
 
-    Ok(count)
+    Ok(rewritten.len() - 1)
 }
 
 pub fn parse(args: Args) -> Result<()> {
     let mut repo = Repository::load()?;
 
     if repo.current_user().is_none() {
+        exit::set(Status::Failure);
+
         eprintln!("No valid user is set for this repository.");
 
         return Ok(());
@@ -82,18 +103,39 @@ pub fn parse(args: Args) -> Result<()> {
 
     let version = repo.normalise_hash(&args.hash)?;
 
+    let rewritten = repo.history.iter_descendants(version).chain([version]);
+
+    if let Some(hold) = retention::first_hold(&repo, rewritten)? {
+        retention::record_blocked(&repo, format!("modify {version}"), &hold)?;
+
+        exit::set(Status::Failure);
+
+        eprintln!(
+            "Modifying this snapshot rewrites {}, which was made on {:?} and has to be kept until {}.",
+            hold.hash,
+            hold.branch,
+            hold.until.format("%Y-%m-%d")
+        );
+
+        return Ok(());
+    }
+
     let mut snapshot = repo.fetch_snapshot(version)?;
 
     snapshot.verify()?;
 
     if let Some(author) = args.author {
         let Some(new_owner) = repo.users.get_user(&author) else {
+            exit::set(Status::Failure);
+
             eprintln!("No user called {author:?} found.");
 
             return Ok(());
         };
 
         if new_owner.private_key.is_none() {
+            exit::set(Status::Failure);
+
             eprintln!("Cannot rename author of commit to user {:?} (no private key for new owner)", new_owner.name);
 
             return Ok(());
@@ -104,6 +146,8 @@ pub fn parse(args: Args) -> Result<()> {
 
     if let Some(message) = args.message {
         if message.is_empty() {
+            exit::set(Status::Failure);
+
             eprintln!("Empty messages for snapshots are disallowed.");
 
             return Ok(());
@@ -112,28 +156,29 @@ pub fn parse(args: Args) -> Result<()> {
         snapshot.message = message;
     }
 
-    if let Some(datetime) = args.datetime {
-        snapshot.timestamp = datetime;
+    if let Some(date) = args.date {
+        snapshot.timestamp = date;
     }
 
     let old_hash = snapshot.hash;
 
-    snapshot.rehash();
+    if snapshot.expected_hash() == old_hash {
+        exit::set(Status::Clean);
 
-    if old_hash == snapshot.hash {
         eprintln!("No changes were made to the snapshot.");
 
         return Ok(());
     }
 
-    let inverted = repo.history.invert();
+    let snapshot = remake(&repo, old_hash, snapshot)?;
+
+    let new_hash = repo.save_snapshot(snapshot)?;
 
     let mut updated_branches = vec![];
 
-    let updated_nodes = update_recursively(
+    let updated_nodes = update_descendants(
         old_hash,
-        snapshot.hash,
-        &inverted,
+        new_hash,
         &mut repo,
         &mut updated_branches
     )?;
@@ -1,7 +1,9 @@
 use eyre::Result;
-use libasc::{repository::Repository, utils::{IsGlob, filter_paths_with_glob_indexes_strict, normalise_with_root}};
+use libasc::{repository::Repository, utils::{IsGlob, filter_paths_with_glob_strict, normalise_with_root}};
 use relative_path::RelativePathBuf;
 
+use crate::{exit::{self, Status}, output::say};
+
 #[derive(clap::Args)]
 pub struct Args {
     /// The current location of a file.
@@ -18,9 +20,11 @@ pub fn parse(args: Args) -> Result<()> {
 
     let globs = [&args.old];
 
-    let filter_result = filter_paths_with_glob_indexes_strict(
+    let tracked: Vec<RelativePathBuf> = repo.staging.paths().cloned().collect();
+
+    let filter_result = filter_paths_with_glob_strict(
         &globs,
-        &repo.staged_files,
+        &tracked,
         &repo.root_dir
     );
 
@@ -28,6 +32,8 @@ pub fn parse(args: Args) -> Result<()> {
         Ok(matches) => matches,
 
         Err(invalid_path) => {
+            exit::set(Status::Failure);
+
             eprintln!("Path outside of tree: {invalid_path}");
 
             return Ok(());
@@ -48,26 +54,23 @@ pub fn parse(args: Args) -> Result<()> {
     let mut new_path = normalise_with_root(args.new, &repo.root_dir);
     
     if paths_to_move.len() == 1 {
-        let (index, path) = paths_to_move[0];
+        let path = paths_to_move[0];
 
         if new_path.to_logical_path(&repo.root_dir).is_dir() {
             new_path = new_path.join(path.file_name().unwrap());
         }
 
-        println!("Moved: {path} -> {new_path}");
+        say!("Moved: {path} -> {new_path}");
 
-        repo.staged_files[index] = new_path;
+        repo.staging.rename(path, new_path);
     }
     else {
-        let new_paths: Vec<(usize, RelativePathBuf)> = paths_to_move
-            .iter()
-            .map(|(index, path)| (*index, new_path.join(path.file_name().unwrap())))
-            .collect();
+        for path in paths_to_move {
+            let moved = new_path.join(path.file_name().unwrap());
 
-        for (index, path) in new_paths {
-            println!("Moved: {} -> {path}", repo.staged_files[index]);
+            say!("Moved: {path} -> {moved}");
 
-            repo.staged_files[index] = path;
+            repo.staging.rename(path, moved);
         }
     }
 
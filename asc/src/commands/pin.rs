@@ -0,0 +1,163 @@
+use chrono::Utc;
+use eyre::Result;
+use serde::Serialize;
+
+use libasc::{action::Action, pin::Pin, repository::Repository, trash::TrashStatus};
+
+use crate::{exit::{self, Status}, output};
+
+#[derive(clap::Args)]
+pub struct Args {
+    /// The version to pin. Lists the pinned snapshots if this isn't given.
+    version: Option<String>,
+
+    /// Why the snapshot has to be kept.
+    #[arg(short = 'm', long)]
+    reason: Option<String>
+}
+
+#[derive(clap::Args)]
+pub struct UnpinArgs {
+    /// The pinned version to unpin.
+    version: String
+}
+
+/// A pin, as printed with `--format json`.
+#[derive(Serialize)]
+struct PinEntry<'a> {
+    hash: String,
+    reason: &'a str,
+    pinned_by: Option<&'a str>,
+    when: String
+}
+
+fn list(repo: &Repository) -> Result<()> {
+    let name_of = |pin: &Pin| repo.users
+        .get_user(&pin.pinned_by)
+        .map(|user| user.name.as_str());
+
+    if output::is_json() {
+        let entries: Vec<PinEntry> = repo.pins
+            .iter()
+            .map(|pin| PinEntry {
+                hash: pin.hash.full(),
+                reason: &pin.reason,
+                pinned_by: name_of(pin),
+                when: pin.when.to_rfc3339()
+            })
+            .collect();
+
+        return output::print_json(&entries);
+    }
+
+    if repo.pins.is_empty() {
+        println!("No snapshots are pinned.");
+
+        return Ok(());
+    }
+
+    println!("Pinned snapshots:");
+
+    for pin in repo.pins.iter() {
+        let who = name_of(pin).unwrap_or("<unknown user>");
+
+        let when = pin.when.format("%Y-%m-%d");
+
+        if pin.reason.is_empty() {
+            println!(" * {} (by {who} on {when})", pin.hash);
+        }
+        else {
+            println!(" * {} (by {who} on {when}): {}", pin.hash, pin.reason);
+        }
+    }
+
+    Ok(())
+}
+
+pub fn parse(args: Args) -> Result<()> {
+    let mut repo = Repository::load()?;
+
+    let Some(version) = args.version else {
+        return list(&repo);
+    };
+
+    let hash = repo.normalise_version(&version)?;
+
+    if !repo.has_snapshot(hash) {
+        exit::set(Status::Failure);
+
+        eprintln!("Cannot pin {hash}, since it is not a snapshot in this repository.");
+
+        return Ok(());
+    }
+
+    if let Some(status) = repo.trash_contains(hash) {
+        let trashed = match status {
+            TrashStatus::Direct => hash,
+            TrashStatus::Indirect(parent) => parent
+        };
+
+        exit::set(Status::Failure);
+
+        eprintln!("Cannot pin {hash}, since it is in the trash. Recover {trashed} first to pin it.");
+
+        return Ok(());
+    }
+
+    if let Some(pin) = repo.pins.get(hash) {
+        println!("{hash} is already pinned.");
+
+        if !pin.reason.is_empty() {
+            println!("Reason: {}", pin.reason);
+        }
+
+        return Ok(());
+    }
+
+    let Some(user) = repo.current_user() else {
+        exit::set(Status::Failure);
+
+        eprintln!("Cannot pin {hash} without a valid user.");
+
+        return Ok(());
+    };
+
+    let pin = Pin {
+        hash,
+        reason: args.reason.unwrap_or_default(),
+        pinned_by: user.public_key,
+        when: Utc::now()
+    };
+
+    repo.pins.add(pin.clone());
+
+    repo.action_history.push(Action::CreatePin { pin });
+
+    repo.save()?;
+
+    println!("Pinned {hash}. It won't be deleted until it is unpinned.");
+
+    Ok(())
+}
+
+pub fn unpin(args: UnpinArgs) -> Result<()> {
+    let mut repo = Repository::load()?;
+
+    let hash = repo.normalise_version(&args.version)?;
+
+    let Some(pin) = repo.pins.remove(hash) else {
+        exit::set(Status::Failure);
+
+        eprintln!("{hash} is not pinned.");
+
+        return Ok(());
+    };
+
+    repo.action_history.push(Action::RemovePin { pin });
+
+    repo.save()?;
+
+    println!("Unpinned {hash}.");
+
+    Ok(())
+}
@@ -0,0 +1,97 @@
+use eyre::Result;
+use libasc::{config::StoreKind, repository::Repository, sync::proxy};
+use serde::Serialize;
+
+use crate::{commands::external::PLUGIN_API, output::{self, say}};
+
+#[derive(clap::Subcommand)]
+pub enum Subcommands {
+    /// Print what plugins need to know about the repository, as JSON.
+    Info,
+
+    /// Connect to a host and pass stdin and stdout through to it, going
+    /// through the proxy in ASC_PROXY if it is set. This is what ssh runs
+    /// as its ProxyCommand, when asc makes the connection for it.
+    Connect {
+        /// The host to connect to, which can be an IPv6 address.
+        host: String,
+
+        port: u16
+    },
+
+    /// Move every object in the repository to another kind of store, and
+    /// keep new objects there. `sqlite` keeps them all in one database,
+    /// which suits servers with lots of small objects, and `s3` keeps them
+    /// in the bucket set up by `content.s3` in the config.
+    MoveObjects {
+        #[arg(value_parser = ["files", "sqlite", "s3"])]
+        store: String
+    }
+}
+
+/// What `asc plumbing info` prints. Like other JSON output,
+/// fields can be added, but existing ones aren't changed.
+#[derive(Serialize)]
+struct Info {
+    plugin_api: u32,
+    version: &'static str,
+    format: u32,
+    root: String,
+    branch: Option<String>,
+    head: String,
+    user: Option<String>
+}
+
+pub fn parse(subcommand: Subcommands) -> Result<()> {
+    match subcommand {
+        Subcommands::Info => {
+            let repo = Repository::load()?;
+
+            let info = Info {
+                plugin_api: PLUGIN_API,
+                version: env!("CARGO_PKG_VERSION"),
+                format: repo.format,
+                root: repo.root_dir.display().to_string(),
+                branch: repo.current_branch().map(String::from),
+                head: repo.current_hash().full(),
+                user: repo.current_user().map(|user| user.name.clone())
+            };
+
+            output::print_json(&info)
+        },
+
+        Subcommands::Connect { host, port } => connect(&host, port),
+
+        Subcommands::MoveObjects { store } => move_objects(&store)
+    }
+}
+
+fn move_objects(store: &str) -> Result<()> {
+    let kind = match store {
+        "sqlite" => StoreKind::Sqlite,
+        "s3" => StoreKind::S3,
+        _ => StoreKind::Files
+    };
+
+    let mut repo = Repository::load()?;
+
+    let moved = repo.move_objects(kind)?;
+
+    repo.save()?;
+
+    say!("Moved {moved} objects to the {store} store.");
+
+    Ok(())
+}
+
+fn connect(host: &str, port: u16) -> Result<()> {
+    let runtime = tokio::runtime::Runtime::new()?;
+
+    let result = runtime.block_on(proxy::tunnel_stdio(host, port));
+
+    // Reading stdin can't be stopped part way, so once the
+    // remote closes, don't wait for ssh to close stdin too.
+    runtime.shutdown_background();
+
+    result
+}
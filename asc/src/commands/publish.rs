@@ -0,0 +1,67 @@
+use std::sync::Arc;
+
+use eyre::Result;
+use libasc::{repository::Repository, sync::{client::Client, remote::Remote}, unwrap};
+use tokio::sync::RwLock;
+
+use crate::{commands::{lfs::push_large_files, push::print_results}, exit::{self, Status}, output::say};
+
+#[derive(clap::Args)]
+pub struct Args {
+    /// Where to create the repository.
+    /// Can be `ssh` or `file`
+    url: String,
+
+    /// The name to save the remote under.
+    #[arg(short, long, default_value = "origin")]
+    name: String
+}
+
+#[tokio::main]
+pub async fn parse(args: Args) -> Result<()> {
+    let mut repo = Repository::load()?;
+
+    let remote = unwrap!(
+        Remote::from_url(&args.url),
+        "could not understand URL: {:?}", args.url
+    );
+
+    if let Some(existing) = repo.remotes.get(&args.name) {
+        exit::set(Status::Failure);
+
+        eprintln!("There is already a remote under the name {:?}: {existing}.", args.name);
+
+        return Ok(());
+    }
+
+    repo.remotes.create(args.name.clone(), remote.clone());
+
+    let repo_arc = Arc::new(RwLock::new(repo));
+
+    let mut client = Client::connect(remote.clone()).await?;
+
+    client.make_init(repo_arc.clone()).await?;
+
+    say!("Created the repository at {remote}");
+
+    repo_arc.write().await.save()?;
+
+    // The connection is closed once the server has handled one request.
+    let mut client = Client::connect(remote.clone()).await?;
+
+    let results = client.make_push(repo_arc.clone(), false).await?;
+
+    say!("Sent: {} | Received: {}", client.bytes_sent(), client.bytes_recv());
+
+    say!();
+
+    print_results(results);
+
+    let mut client = Client::connect(remote).await?;
+
+    push_large_files(repo_arc.clone(), &mut client).await?;
+
+    repo_arc.write().await.save()?;
+
+    Ok(())
+}
@@ -1,42 +1,89 @@
 use std::sync::Arc;
 
 use eyre::Result;
-use libasc::{repository::Repository, sync::{client::Client, pull::{BranchPullResult, PullResult, TagPullResult}}};
-use tokio::sync::Mutex;
+use libasc::{refs::Ref, repository::Repository, sync::{client::Client, pull::{BranchPullResult, PullResult, TagPullResult}}};
+use tokio::sync::RwLock;
+
+use crate::{commands::{lfs::fetch_large_files, link::fetch_links, stash}, output::say};
 
 #[derive(clap::Args)]
 pub struct Args {
     /// The remote to push to. Defaults to all.
     remote: Option<String>,
 
+    /// Move every branch to its tip on the remote, instead of only the
+    /// current one. Other branches are otherwise only fetched, to compare
+    /// with where they are on the remote.
+    #[arg(long)]
+    all: bool,
+
     // The branch to push. TODO
     // branch: Option<String>
 }
 
+/// Remember where each branch is on the remote called `remote`, so
+/// that `asc changes` and `asc branch list` can compare with it.
+pub fn record_remote_tips(repo: &mut Repository, remote: &str, results: &[PullResult]) {
+    for result in results {
+        let PullResult::Branch(branch, result) = result else {
+            continue;
+        };
+
+        let remote_tip = match result {
+            BranchPullResult::NotOnRemote => {
+                repo.refs.remove(&Ref::Remote(format!("{remote}/{branch}")));
+
+                continue;
+            },
+
+            BranchPullResult::UpToDate => repo.refs.branches().get(branch),
+
+            BranchPullResult::Ahead(_, tip) => Some(*tip),
+
+            BranchPullResult::FastForward(_, _, tip)
+                | BranchPullResult::Conflict(_, _, tip, _)
+                | BranchPullResult::Fetched(_, _, tip) => Some(*tip)
+        };
+
+        if let Some(tip) = remote_tip {
+            repo.set_remote_tip(remote, branch, tip);
+        }
+    }
+}
+
 #[tokio::main]
 pub async fn parse(args: Args) -> Result<()> {
     let repo = Repository::load()?;
 
+    let before = repo.current_hash();
+
     let remotes = repo.remotes.clone();
     
-    let repo_arc = Arc::new(Mutex::new(repo));
+    let repo_arc = Arc::new(RwLock::new(repo));
+
+    // Linked repositories can only come from the remotes pulled from.
+    let mut pulled = vec![];
 
     for (name, remote) in remotes.into_iter() {
         if let Some(remote_arg) = &args.remote && name != *remote_arg {
             continue;
         }
 
-        println!("Pulling from: {name}");
+        say!("Pulling from: {name}");
+
+        pulled.push(remote.clone());
 
         let mut client = Client::connect(remote).await?;
 
-        let results = client.make_pull(repo_arc.clone()).await?;
+        let results = client.make_pull(repo_arc.clone(), !args.all).await?;
+
+        say!("Sent: {} | Received: {}", client.bytes_sent(), client.bytes_recv());
 
-        println!("Sent: {} | Received: {}", client.bytes_sent(), client.bytes_recv());
+        say!();
 
-        println!();
+        say!("Results: ");
 
-        println!("Results: ");
+        record_remote_tips(&mut *repo_arc.write().await, &name, &results);
 
         for result in results {
             let line = match result {
@@ -44,32 +91,65 @@ pub async fn parse(args: Args) -> Result<()> {
                     BranchPullResult::NotOnRemote => format!(" * {name:?} not found on remote"),
                     
                     BranchPullResult::UpToDate => format!(" * Branch {name:?} is up-to-date"),
+
+                    BranchPullResult::Ahead(..) => format!(" * Branch {name:?} is ahead of remote - push to send it"),
                     
                     BranchPullResult::FastForward(_, old_tip, new_tip) => {
                         format!(" * Fast-forwarded {name} ({old_tip} -> {new_tip})")
                     },
 
-                    BranchPullResult::Conflict(..) => {
-                        format!(" ! Branch {name} diverges with remote - local version is renamed to `local/{name}`")
+                    BranchPullResult::Conflict(_, _, _, backup) => {
+                        format!(" ! Branch {name} diverges with remote - local version is kept as `backups/{backup}`")
+                    },
+
+                    BranchPullResult::Fetched(_, local_tip, remote_tip) => {
+                        format!(" * Fetched {name} ({local_tip} locally, {remote_tip} on remote) - switch to it and pull to move it")
                     }
                 },
 
                 PullResult::Tag(name, result) => match result {
                     TagPullResult::New(hash) => format!(" * Tag {name:?} ({hash}) received from remote"),
 
-                    TagPullResult::Conflict(..) => {
-                        format!(" ! Tag {name:?} diverges from remote - local version is renamed to `local/{name}`")
+                    TagPullResult::Conflict(_, _, copy) => {
+                        format!(" ! Tag {name:?} diverges from remote - local version is renamed to `{copy}`")
+                    },
+
+                    TagPullResult::Replaced(local, remote) => {
+                        format!(" * Tag {name:?} moved from {local} to {remote} to match the remote")
+                    },
+
+                    TagPullResult::Kept(local, remote) => {
+                        format!(" * Tag {name:?} kept at {local} over the remote's {remote} - push to settle it")
+                    },
+
+                    TagPullResult::InvalidName(remote, reason) => {
+                        format!(" ! Tag {name:?} ({remote}) on remote is skipped - {reason}")
                     }
                 }
             };
 
-            println!("{line}");
+            say!("{line}");
         }
 
-        println!();
+        say!();
+    }
+
+    let current_hash = repo_arc.read().await.current_hash();
+
+    fetch_large_files(repo_arc.clone(), vec![current_hash], args.remote.as_deref()).await?;
+
+    let mut repo = repo_arc.write().await;
+
+    match repo.follow_head(before) {
+        Ok(backup) => stash::print_backup(backup),
+        Err(e) => eprintln!(" ! Working directory was left at {before}: {e}")
     }
 
-    repo_arc.lock().await.save()?;
+    repo.save()?;
+
+    if let Some(user_key) = repo.current_user().and_then(|user| user.private_key.clone()) {
+        fetch_links(&repo.root_dir, &repo.links, &user_key, &pulled).await?;
+    }
 
     Ok(())
 }
@@ -2,70 +2,130 @@ use std::sync::Arc;
 
 use eyre::Result;
 use libasc::{repository::Repository, sync::{client::Client, push::{BranchPushResult, PushResult, TagPushResult}}};
-use tokio::sync::Mutex;
+use tokio::sync::RwLock;
+
+use crate::{commands::lfs::push_large_files, output::say};
 
 #[derive(clap::Args)]
 pub struct Args {
     /// The remote to push to. Defaults to all.
     remote: Option<String>,
 
+    /// Push ephemeral branches as well.
+    #[arg(long)]
+    ephemeral: bool,
+
     // The branch to push. TODO
     // branch: Option<String>
 }
 
+pub fn print_results(results: Vec<PushResult>) {
+    say!("Results: ");
+
+    for result in results {
+        let line = match result {
+            PushResult::Branch(name, result) => match result {
+                BranchPushResult::CreatedOnRemote => format!(" * Branch {name:?} created on remote"),
+                
+                BranchPushResult::UpToDate => format!(" * Branch {name:?} is up-to-date"),
+                
+                BranchPushResult::FastForward(old_tip, new_tip) => {
+                    format!(" * Fast-forwarded {name} ({old_tip} -> {new_tip})")
+                },
+
+                BranchPushResult::SplitHistory => format!(" ! Branch {name:?} diverges from remote - pull to see more"),
+
+                BranchPushResult::Merge(hash) => {
+                    format!(" ! Branch {name:?} has to stay linear on remote, but {hash} is a merge - rebase or squash it first")
+                },
+
+                BranchPushResult::InvalidName(reason) => format!(" ! Branch {name:?} isn't allowed on remote - {reason}"),
+
+                BranchPushResult::Held(hash) => {
+                    format!(" ! Branch {name:?} can't be moved back on remote, since {hash} is held there for retention")
+                }
+            },
+
+            PushResult::Tag(name, result) => match result {
+                TagPushResult::CreatedOnRemote => format!(" * Tag {name:?} created on remote"),
+
+                TagPushResult::Conflict => format!(" ! Tag {name:?} diverges from remote - pull to see more"),
+
+                TagPushResult::Denied => format!(" ! Tag {name:?} is in a namespace you can't push to"),
+
+                TagPushResult::Replaced => format!(" * Tag {name:?} moved on remote to match the local one"),
+
+                TagPushResult::Kept => format!(" ! Tag {name:?} kept at the remote's version - pull to match it"),
+
+                TagPushResult::InvalidName(reason) => format!(" ! Tag {name:?} isn't allowed on remote - {reason}")
+            }
+        };
+
+        say!("{line}");
+    }
+}
+
 #[tokio::main]
 pub async fn parse(args: Args) -> Result<()> {
     let repo = Repository::load()?;
 
     let remotes = repo.remotes.clone();
+
+    if !args.ephemeral {
+        for name in repo.refs.branches().names().filter(|name| repo.refs.is_ephemeral(name)) {
+            say!("Skipping ephemeral branch {name:?} (push with --ephemeral to include it)");
+        }
+    }
     
-    let repo_arc = Arc::new(Mutex::new(repo));
+    let repo_arc = Arc::new(RwLock::new(repo));
 
     for (name, remote) in remotes.into_iter() {
         if let Some(remote_arg) = &args.remote && name != *remote_arg {
             continue;
         }
 
-        println!("Pushing to: {name}");
+        say!("Pushing to: {name}");
 
-        let mut client = Client::connect(remote).await?;
+        let mut client = Client::connect(remote.clone()).await?;
+
+        let results = client.make_push(repo_arc.clone(), args.ephemeral).await?;
 
-        let results = client.make_push(repo_arc.clone()).await?;
+        say!("Sent: {} | Received: {}", client.bytes_sent(), client.bytes_recv());
 
-        println!("Sent: {} | Received: {}", client.bytes_sent(), client.bytes_recv());
+        say!();
 
-        println!();
+        // What the remote has now is remembered, so that `asc undo`
+        // can tell when it would go back on something already pushed.
+        let mut repo = repo_arc.write().await;
 
-        println!("Results: ");
+        for result in &results {
+            let PushResult::Branch(branch, result) = result else {
+                continue;
+            };
 
-        for result in results {
-            let line = match result {
-                PushResult::Branch(name, result) => match result {
-                    BranchPushResult::CreatedOnRemote => format!(" * Branch {name:?} created on remote"),
-                    
-                    BranchPushResult::UpToDate => format!(" * Branch {name:?} is up-to-date"),
-                    
-                    BranchPushResult::FastForward(old_tip, new_tip) => {
-                        format!(" * Fast-forwarded {name} ({old_tip} -> {new_tip})")
-                    },
+            if matches!(result, BranchPushResult::SplitHistory | BranchPushResult::Merge(_) | BranchPushResult::InvalidName(_) | BranchPushResult::Held(_)) {
+                continue;
+            }
 
-                    BranchPushResult::SplitHistory => format!(" ! Branch {name:?} diverges from remote - pull to see more")
-                },
+            if let Some(tip) = repo.refs.branches().get(branch) {
+                repo.set_remote_tip(&name, branch, tip);
+            }
+        }
 
-                PushResult::Tag(name, result) => match result {
-                    TagPushResult::CreatedOnRemote => format!(" * Tag {name:?} created on remote"),
+        drop(repo);
 
-                    TagPushResult::Conflict => format!(" ! Tag {name:?} diverges from remote - pull to see more")
-                }
-            };
+        print_results(results);
 
-            println!("{line}");
-        }
+        // Large files go over their own connection, once
+        // the snapshots that point to them are on the remote.
+        let mut client = Client::connect(remote).await?;
+
+        push_large_files(repo_arc.clone(), &mut client).await?;
 
-        println!();
+        say!();
     }
 
-    repo_arc.lock().await.save()?;
+    repo_arc.write().await.save()?;
 
     Ok(())
 }
@@ -2,6 +2,8 @@ use eyre::Result;
 
 use libasc::repository::Repository;
 
+use crate::output::say;
+
 #[derive(clap::Args)]
 pub struct Args {
     /// Redo all actions. Overrides '--count'.
@@ -25,7 +27,7 @@ pub fn parse(args: Args) -> Result<()> {
 
     for _ in 0..count {
         if let Some(action) = repo.redo_action()? {
-            println!(" * {action}");
+            say!(" * {action}");
             
             done += 1;
         }
@@ -36,7 +38,7 @@ pub fn parse(args: Args) -> Result<()> {
 
     repo.save()?;
 
-    println!("Redid {done} actions.");
+    say!("Redid {done} actions.");
 
     Ok(())
 }
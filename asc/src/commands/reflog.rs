@@ -0,0 +1,107 @@
+use eyre::Result;
+use libasc::{refs::{Head, Ref}, repository::Repository};
+
+use crate::exit::{self, Status};
+
+#[derive(clap::Args)]
+pub struct Args {
+    /// The ref to show the changes of, like `main` or `tags/v1.0`, or
+    /// `HEAD` for everywhere the head has been. Defaults to the current branch.
+    ///
+    /// Each change is numbered the way it can be given as a version,
+    /// so `main@{1}` is where `main` was before its last change.
+    name: Option<String>
+}
+
+/// Format what made a change, if it was recorded.
+fn because(reason: &Option<String>) -> String {
+    match reason {
+        Some(reason) => format!(" ({reason})"),
+        None => String::new()
+    }
+}
+
+/// Print where the head has been, newest first.
+fn show_head_log(repo: &Repository) {
+    let entries = repo.refs.head_log();
+
+    if entries.is_empty() {
+        eprintln!("No changes recorded for HEAD.");
+
+        return;
+    }
+
+    println!("Changes to HEAD:");
+
+    for (n, entry) in entries.iter().rev().enumerate() {
+        let timestamp = entry.timestamp.format("%d/%m/%Y %H:%M:%S");
+
+        let on = match &entry.head {
+            Head::Branch(name) => format!("on {name}"),
+            Head::Detached(_) => "detached".to_string()
+        };
+
+        println!(" * HEAD@{{{n}}} {timestamp} {} [{on}]{}", entry.hash, because(&entry.reason));
+    }
+}
+
+pub fn parse(args: Args) -> Result<()> {
+    let repo = Repository::load()?;
+
+    if args.name.as_deref() == Some("HEAD") {
+        show_head_log(&repo);
+
+        return Ok(());
+    }
+
+    let r = match args.name {
+        Some(name) => match repo.refs.lookup(&name) {
+            Some((r, _)) => r.clone(),
+
+            // Deleted refs still have a reflog.
+            None => match name.parse::<Ref>() {
+                Ok(r) => r,
+                Err(_) => Ref::Branch(name)
+            }
+        },
+
+        None => {
+            let Some(branch) = repo.current_branch() else {
+                exit::set(Status::Failure);
+
+                eprintln!("Not on a branch - name a ref to show its changes.");
+
+                return Ok(());
+            };
+
+            Ref::Branch(branch.to_string())
+        }
+    };
+
+    let entries = repo.refs.reflog(&r);
+
+    if entries.is_empty() {
+        eprintln!("No changes recorded for {r}.");
+
+        return Ok(());
+    }
+
+    println!("Changes to {r}:");
+
+    let label = r.name().unwrap_or_default();
+
+    for (n, entry) in entries.iter().rev().enumerate() {
+        let timestamp = entry.timestamp.format("%d/%m/%Y %H:%M:%S");
+
+        let change = match (entry.old, entry.new) {
+            (None, Some(new)) => format!("created at {new}"),
+            (Some(old), Some(new)) => format!("moved {old} -> {new}"),
+            (Some(old), None) => format!("deleted (was {old})"),
+            (None, None) => continue
+        };
+
+        println!(" * {label}@{{{n}}} {timestamp} {change}{}", because(&entry.reason));
+    }
+
+    Ok(())
+}
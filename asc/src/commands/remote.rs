@@ -1,5 +1,10 @@
+use std::sync::Arc;
+
 use eyre::Result;
-use libasc::{repository::Repository, sync::remote::Remote, unwrap};
+use libasc::{repository::Repository, sync::{client::Client, remote::Remote, session::SessionCache}, unwrap};
+use tokio::sync::RwLock;
+
+use crate::{exit::{self, Status}, i18n::t};
 
 #[derive(clap::Subcommand)]
 pub enum Subcommands {
@@ -21,10 +26,23 @@ pub enum Subcommands {
     },
 
     /// List URLs this repository can push and pull to.
-    List
+    List,
+
+    /// Create this repository on a remote, ready to be pushed to.
+    Init {
+        name: String
+    },
+
+    /// Forget the login session with a remote, or with every remote.
+    /// 
+    /// The next push or pull to that remote will log in again.
+    Logout {
+        name: Option<String>
+    }
 }
 
-pub fn parse(subcommand: Subcommands) -> Result<()> {
+#[tokio::main]
+pub async fn parse(subcommand: Subcommands) -> Result<()> {
     use Subcommands::*;
 
     let mut repo = Repository::load()?;
@@ -37,33 +55,35 @@ pub fn parse(subcommand: Subcommands) -> Result<()> {
             );
 
             if let Some(original) = repo.remotes.create(name.clone(), remote) {
-                eprintln!("There is already a remote under the name {name:?}: {original}.");
+                eprintln!("{}", t!("remote-exists", name = name, url = original));
             }
             else {
-                println!("Created the remote {name:?} at {url:?}");
+                println!("{}", t!("remote-created", name = name, url = url));
             }
         },
 
         Remove { name } => {
             let Some(remote) = repo.remotes.remove(&name) else {
-                eprintln!("No remote under the name {name:?}");
+                exit::set(Status::Failure);
+
+                eprintln!("{}", t!("remote-not-found", name = name));
 
                 return Ok(());
             };
             
-            println!("Removed the remote {name:?} ({remote}).");
+            println!("{}", t!("remote-removed", name = name, url = remote));
         },
 
         List => {
             if repo.remotes.is_empty() {
-                eprintln!("No remotes are on this repository.");
+                eprintln!("{}", t!("remote-none"));
 
                 return Ok(());
             }
 
             let mut remotes: Vec<_> = repo.remotes.iter().collect();
 
-            remotes.sort_by(|(n1, _), (n2, _)| n1.cmp(n2));
+            remotes.sort_by_key(|(name, _)| *name);
 
             for (name, remote) in remotes {
                 println!("{name}\t{remote}");
@@ -72,12 +92,73 @@ pub fn parse(subcommand: Subcommands) -> Result<()> {
 
         Rename { old, new } => {
             if !repo.remotes.rename(&old, new) {
-                eprintln!("No remote under the name {old:?}.");
+                exit::set(Status::Failure);
+
+                eprintln!("{}", t!("remote-not-found", name = old));
+
+                return Ok(());
+            }
+        },
+
+        Init { name } => {
+            let Some(remote) = repo.remotes.get(&name).cloned() else {
+                exit::set(Status::Failure);
+
+                eprintln!("{}", t!("remote-not-found", name = name));
+
+                return Ok(());
+            };
+
+            let mut client = Client::connect(remote.clone()).await?;
+
+            client.make_init(Arc::new(RwLock::new(repo))).await?;
+
+            println!("{}", t!("remote-initialised", name = name, url = remote));
+
+            return Ok(());
+        }
+
+        Logout { name: Some(name) } => {
+            let Some(remote) = repo.remotes.get(&name) else {
+                exit::set(Status::Failure);
+
+                eprintln!("{}", t!("remote-not-found", name = name));
+
+                return Ok(());
+            };
+
+            let user = unwrap!(
+                repo.current_user(),
+                "no valid user set for this repository."
+            );
+
+            let mut cache = SessionCache::load(&repo)?;
+
+            if !cache.remove(remote, &user.public_key) {
+                exit::set(Status::Failure);
+
+                eprintln!("{}", t!("remote-no-session", name = name));
 
                 return Ok(());
             }
+
+            cache.save(&repo)?;
+
+            println!("{}", t!("remote-logged-out", name = name, url = remote));
+        }
+
+        Logout { name: None } => {
+            let mut cache = SessionCache::load(&repo)?;
+
+            cache.clear();
+
+            cache.save(&repo)?;
+
+            println!("{}", t!("remote-logged-out-all"));
         }
     }
-    
+
+    repo.save()?;
+
     Ok(())
 }
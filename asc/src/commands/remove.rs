@@ -1,5 +1,8 @@
 use eyre::Result;
 use libasc::{change::FileChange, repository::Repository, utils::filter_paths_with_glob_strict};
+use relative_path::RelativePathBuf;
+
+use crate::{exit::{self, Status}, output::say};
 
 #[derive(clap::Args)]
 pub struct Args {
@@ -10,7 +13,7 @@ pub struct Args {
 pub fn parse(args: Args) -> Result<()> {
     let mut repo = Repository::load()?;
 
-    let staged_files = std::mem::take(&mut repo.staged_files);
+    let staged_files: Vec<RelativePathBuf> = repo.staging.paths().cloned().collect();
 
     let filter_result = filter_paths_with_glob_strict(
         &args.paths,
@@ -22,6 +25,8 @@ pub fn parse(args: Args) -> Result<()> {
         Ok(matches) => matches,
         
         Err(invalid_path) => {
+            exit::set(Status::Failure);
+
             eprintln!("Path outside of tree: {invalid_path}");
 
             return Ok(());
@@ -29,20 +34,20 @@ pub fn parse(args: Args) -> Result<()> {
     };
 
     if to_remove.is_empty() {
+        exit::set(Status::Clean);
+
         eprintln!("Nothing to remove.");
 
         return Ok(());
     }
 
     for path in &to_remove {
-        println!("{}", FileChange::Removed(path));
+        say!("{}", FileChange::Removed(path));
     }
 
-    repo.staged_files = staged_files
-        .iter()
-        .filter(|p| !to_remove.contains(p))
-        .cloned()
-        .collect();
+    for path in to_remove {
+        repo.staging.unstage(path);
+    }
 
     repo.save()?;
 
@@ -0,0 +1,76 @@
+use std::{fs, io::{stdout, Write}, path::PathBuf};
+
+use eyre::Result;
+
+use libasc::{patch::Patch, repository::Repository, unwrap};
+
+use crate::commands::format_patch::linear_range;
+
+#[derive(clap::Args)]
+pub struct Args {
+    /// The snapshots to send, as `BASE..TIP` for every
+    /// snapshot after BASE up to TIP, or as a single version.
+    range: String,
+
+    /// The file to write the mbox to.
+    /// Defaults to printing it.
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+
+    /// Who the patches are for, as email addresses.
+    #[arg(long, value_name = "ADDRESS")]
+    to: Vec<String>,
+
+    /// Who the patches are from. Defaults to the current
+    /// user, at an address made from their public key.
+    #[arg(long, value_name = "ADDRESS")]
+    from: Option<String>
+}
+
+pub fn parse(args: Args) -> Result<()> {
+    let repo = Repository::load()?;
+
+    let Some(snapshots) = linear_range(&repo, &args.range)? else {
+        return Ok(());
+    };
+
+    let from = match args.from {
+        Some(from) => from,
+
+        None => match repo.current_user() {
+            Some(user) => {
+                let key = user.public_key.to_string().to_ascii_lowercase();
+
+                format!("\"{}\" <{}@asc.invalid>", user.name, &key[..16])
+            },
+
+            None => "asc <asc@asc.invalid>".to_string()
+        }
+    };
+
+    let total = snapshots.len();
+
+    let mut mbox = String::new();
+
+    for (index, hash) in snapshots.into_iter().enumerate() {
+        let patch = Patch::from_snapshot(&repo, hash)?;
+
+        mbox.push_str(&patch.to_email(index + 1, total, &from, &args.to));
+        mbox.push('\n');
+    }
+
+    match args.output {
+        Some(path) => {
+            unwrap!(
+                fs::write(&path, mbox),
+                "failed to write patches to {}", path.display()
+            );
+
+            println!("{}", path.display());
+        },
+
+        None => stdout().write_all(mbox.as_bytes())?
+    }
+
+    Ok(())
+}
@@ -1,8 +1,8 @@
-use color_eyre::owo_colors::OwoColorize;
 use eyre::Result;
-use libasc::{content::{Content, Delta}, repository::Repository, snapshot::Snapshot, utils::hash_raw_bytes};
+use libasc::{content::{Content, Delta, Pointer}, repository::Repository, snapshot::Snapshot, utils::hash_raw_bytes};
 use similar::TextDiff;
-use size::{Base, Size};
+
+use crate::output::{self, format_size};
 
 #[derive(clap::Args)]
 pub struct Args {
@@ -13,8 +13,8 @@ pub struct Args {
 fn display_snapshot(snapshot: Snapshot, repo: &Repository) {
     let line = format!("Hash: {:?}", snapshot.hash);
     
-    if snapshot.hash == repo.current_hash {
-        println!("{}", line.bright_green().bold());
+    if snapshot.hash == repo.current_hash() {
+        println!("{}", output::current(line));
     }
     else {
         println!("{line}");
@@ -27,9 +27,13 @@ fn display_snapshot(snapshot: Snapshot, repo: &Repository) {
 
     println!("Parents: {}", parents.join(", "));
 
+    if let Some(branch) = &snapshot.branch {
+        println!("Committed on: {branch}");
+    }
+
     // .format("%d/%m/%Y %H:%M:%S")
 
-    let branches_here = repo.branches.get_names_for(snapshot.hash);
+    let branches_here = repo.refs.branches().get_names_for(snapshot.hash);
 
     if branches_here.len() == 1 {
         println!("Branch: {}", branches_here[0]);
@@ -38,15 +42,15 @@ fn display_snapshot(snapshot: Snapshot, repo: &Repository) {
         println!("Branches: {}", branches_here.join(", "));
     }
 
-    let tags_here = repo.tags.get_names_for(snapshot.hash);
+    let tags_here = repo.refs.tags().get_names_for(snapshot.hash);
 
     if !tags_here.is_empty() {
         println!("Tags: {}", tags_here.join(", "));
     }
 
-    let mut tags_on_snapshot: Vec<&str> = repo.tags
+    let mut tags_on_snapshot: Vec<&str> = repo.refs.tags()
         .iter()
-        .filter_map(|(name, &hash)| (hash == snapshot.hash).then_some(name.as_str()))
+        .filter_map(|(name, hash)| (hash == snapshot.hash).then_some(name))
         .collect();
 
     if !tags_on_snapshot.is_empty() {
@@ -76,40 +80,42 @@ fn display_snapshot(snapshot: Snapshot, repo: &Repository) {
     }
 }
 
-fn format_size(n: usize) -> String {
-    let size = Size::from_bytes(n);
-
-    size.format()
-        .with_base(Base::Base10)
-        .to_string()
-}
-
 fn display_content(content: Content, repo: &Repository) -> Result<()> {
     let text = content.resolve(repo)?;
 
     let kind = match &content {
         Content::Literal(data) => {
-            format!("Literal, size compressed: {}", format_size(data.len()))
+            format!("Literal, size compressed: {}", format_size(data.len() as u64))
         }
         
-        Content::Delta(Delta { original, edit }) => {
+        Content::Delta(Delta { original, edit, depth }) => {
             let basis = repo.fetch_string_content(*original)?;
 
             let diff = TextDiff::from_lines(&basis, &text);
 
             let similarity = diff.ratio();
 
+            // Older deltas don't record their depth.
+            let depth = match *depth {
+                0 => repo.delta_depth(*original)? + 1,
+                depth => depth
+            };
+
             format!(
-                "Delta based on {original}, edit size: {}, similarity: {similarity}%",
-                format_size(edit.len())
+                "Delta based on {original}, edit size: {}, similarity: {similarity}%, chain depth: {depth}",
+                format_size(edit.len() as u64)
             )
         }
+
+        Content::Pointer(Pointer { size, .. }) => {
+            format!("Large file, size: {}", format_size(*size))
+        }
     };
 
     println!("---");
     println!("Hash: {:?}", hash_raw_bytes(&text));
     println!("{kind}");
-    println!("Size: {}", format_size(text.len()));
+    println!("Size: {}", format_size(text.len() as u64));
     println!("---");
     println!("{text}");
 
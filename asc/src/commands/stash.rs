@@ -1,33 +1,53 @@
-use std::{collections::BTreeMap, io::Read};
+use std::collections::{BTreeMap, BTreeSet};
 
 use eyre::Result;
-use libasc::{repository::Repository, stash::State, unwrap, utils::{get_content_from_editor, hash_raw_bytes, open_file}};
+use relative_path::RelativePathBuf;
+use threeway_merge::{merge_strings, MergeOptions};
+use libasc::{hash::ObjectHash, mode::{self, FileMode}, paths, repository::Repository, stash::State, unwrap, utils::{filter_with_glob, get_content_from_editor, hash_raw_bytes, remove_path}};
+
+use crate::{commands::{diff::{self, Locator}, merge}, exit::{self, Status}, output::say};
+
+#[derive(clap::Args)]
+pub struct NewArgs {
+    /// The message to go with the stash.
+    #[arg(short, long)]
+    message: Option<String>,
+
+    /// The editor to use to write the message. Not used if message is given.
+    #[arg(short, long)]
+    editor: Option<String>,
+
+    /// Stash even if the message is empty.
+    #[arg(long)]
+    allow_empty_message: bool,
+
+    /// Also stash files that aren't tracked, unless they're ignored.
+    /// They are untracked again when the stash is applied.
+    #[arg(short = 'u', long)]
+    include_untracked: bool,
+
+    /// Only stash the files matching these globs, leaving
+    /// the changes to other files where they are.
+    #[arg(long = "paths", value_name = "GLOB", num_args = 1..)]
+    paths: Vec<String>
+}
 
 #[derive(clap::Subcommand)]
 pub enum Subcommands {
     /// Create a new stash in the repository,
     /// and then revert to the latest snapshot.
     #[command(visible_alias = "create")]
-    New {
-        /// The message to go with the stash.
-        #[arg(short, long)]
-        message: Option<String>,
-
-        /// The editor to use to write the message. Not used if message is given.
-        #[arg(short, long)]
-        editor: Option<String>
-    },
+    New(NewArgs),
 
     /// Create a new stash in the repository,
     /// but do not revert to the latest snapshot.
-    Save {
-        /// The message to go with the stash.
-        #[arg(short, long)]
-        message: Option<String>,
-
-        /// The editor to use to write the message. Not used if message is given.
-        #[arg(short, long)]
-        editor: Option<String>
+    Save(NewArgs),
+
+    /// Show the changes in a stash, compared to the snapshot it was made on.
+    Show {
+        /// The stash ID to show.
+        /// Defaults to the topmost stash ID.
+        id: Option<usize>
     },
 
     /// List stashes on the repository.
@@ -41,15 +61,15 @@ pub enum Subcommands {
         id: Option<usize>
     },
 
-    /// Replace the working directory with a snapshot
-    /// from the stash, deleting the snapshot in the process.
+    /// Merge the changes in a stash onto the current snapshot,
+    /// deleting the stash if there were no conflicts.
     Pop {
         /// The stash ID of the snapshot to use.
         /// Defaults to the topmost stash ID.
         id: Option<usize>
     },
 
-    /// Functions like `pop` but does not delete the snapshot.
+    /// Functions like `pop` but does not delete the stash.
     Apply {
         /// The stash ID of the snapshot to use.
         /// Defaults to the topmost stash ID.
@@ -71,21 +91,72 @@ static TEMPLATE_MESSAGE: &str = "
 # Whitespace before and after the message is also ignored.
 ";
 
-fn save_new_stash(
-    message: Option<String>,
-    editor: Option<String>,
-    repo: &mut Repository
-) -> Result<usize>
-{
+/// A stash entry that was just made.
+struct Stashed {
+    id: usize,
+
+    /// What the working directory would be with the stashed changes taken
+    /// out, as the files and modes to give to `replace_cwd_with_files`.
+    remaining: BTreeMap<RelativePathBuf, ObjectHash>,
+    remaining_modes: BTreeMap<RelativePathBuf, FileMode>,
+
+    /// The files stashed that aren't in the current snapshot,
+    /// which have to be removed to take the changes out.
+    added: Vec<RelativePathBuf>
+}
+
+/// Save the working directory as a new stash entry, or `None` if the message
+/// was empty and that wasn't allowed, or no files matched the paths given.
+///
+/// Only the files matching `args.paths` are stashed, if any are given. The
+/// rest are stashed as they were in the current snapshot, so applying the
+/// entry only brings back the changes to the matching files.
+fn save_new_stash(args: NewArgs, repo: &mut Repository) -> Result<Option<Stashed>> {
+    let NewArgs { message, editor, allow_empty_message, include_untracked, paths: globs } = args;
+
+    let untracked = if include_untracked {
+        repo.untracked_files()?
+    }
+    else {
+        vec![]
+    };
+
+    let current_snapshot = repo.fetch_current_snapshot()?;
+
+    let all_paths: BTreeSet<RelativePathBuf> = repo.staging
+        .paths()
+        .chain(current_snapshot.files.keys())
+        .chain(&untracked)
+        .cloned()
+        .collect();
+
+    let selected: BTreeSet<RelativePathBuf> = if globs.is_empty() {
+        all_paths.clone()
+    }
+    else {
+        let names: Vec<&str> = all_paths.iter().map(|path| path.as_str()).collect();
+
+        filter_with_glob(globs, &names)
+            .into_iter()
+            .map(RelativePathBuf::from)
+            .collect()
+    };
+
+    if selected.is_empty() {
+        exit::set(Status::Failure);
+
+        eprintln!("Aborting stash: no files match the paths given.");
+
+        return Ok(None);
+    }
+
     let message = message
         .map(Ok)
         .unwrap_or_else(|| {
-            let editor = editor.unwrap_or(
-                unwrap!(
-                    std::env::var("EDITOR"),
-                    "environment variable 'EDITOR' is not set."
-                )
-            );
+            let editor = match editor {
+                Some(editor) => editor,
+                None => repo.config.ui.editor()?
+            };
 
             let snapshot_message_path = &repo.main_dir().join("SNAPSHOT_MESSAGE");
 
@@ -93,36 +164,207 @@ fn save_new_stash(
         }
     )?;
 
-    let current_snapshot = repo.fetch_current_snapshot()?;
+    if !allow_empty_message && message.trim().is_empty() {
+        exit::set(Status::Failure);
+
+        eprintln!("Aborting stash: the message is empty. Use '--allow-empty-message' to stash anyway.");
+
+        return Ok(None);
+    }
 
     let mut files = BTreeMap::new();
-    
-    for path in &repo.staged_files {
-        let mut content = String::new();
+    let mut modes = BTreeMap::new();
+
+    let mut remaining = BTreeMap::new();
+    let mut remaining_modes = BTreeMap::new();
+
+    let mut added = vec![];
+
+    for path in &all_paths {
+        let full_path = paths::on_disk(path, &repo.root_dir);
+
+        let is_untracked = !repo.staging.contains(path) && !current_snapshot.files.contains_key(path);
+
+        // Files removed from the staging area, or
+        // deleted from the disk, don't have a working version.
+        let working = if (repo.staging.contains(path) || is_untracked) && mode::exists(&full_path) {
+            let content = unwrap!(
+                mode::read_working(&full_path),
+                "failed to read path: {path}"
+            );
+
+            let mode = FileMode::of(&full_path, repo.staging.mode(path))?;
 
-        let full_path = path.to_logical_path(&repo.root_dir);
-        
-        let mut fp = open_file(full_path)?;
+            let basis = current_snapshot.files
+                .get(path)
+                .cloned();
 
-        fp.read_to_string(&mut content)?;
+            repo.save_content(&content, basis)?;
 
-        let basis = current_snapshot.files
+            Some((hash_raw_bytes(&content), mode))
+        }
+        else {
+            None
+        };
+
+        let saved = current_snapshot.files
             .get(path)
-            .cloned();
+            .map(|&hash| (hash, current_snapshot.modes.get(path).copied().unwrap_or_default()));
+
+        let (stashed, kept) = match selected.contains(path) {
+            true => (working, saved),
+            false => (saved, working)
+        };
 
-        repo.save_content(&content, basis)?;
+        if let Some((hash, mode)) = stashed {
+            files.insert(path.clone(), hash);
+            modes.insert(path.clone(), mode);
 
-        files.insert(path.clone(), hash_raw_bytes(&content));
+            if saved.is_none() {
+                added.push(path.clone());
+            }
+        }
+
+        // Untracked files that weren't stashed are left alone.
+        if let Some((hash, mode)) = kept
+            && (!is_untracked || selected.contains(path))
+        {
+            remaining.insert(path.clone(), hash);
+            remaining_modes.insert(path.clone(), mode);
+        }
     }
 
     let state = State {
         files,
-        message
+        message,
+        modes: mode::special_modes(modes),
+        untracked: untracked
+            .into_iter()
+            .filter(|path| selected.contains(path))
+            .collect()
     };
 
-    let stash_id = repo.stash.add_state(state, repo.current_hash);
+    let id = repo.stash.add_state(state, repo.current_hash());
+
+    Ok(Some(Stashed { id, remaining, remaining_modes: mode::special_modes(remaining_modes), added }))
+}
+
+/// Merge the changes in a stash entry onto the current snapshot, with the
+/// snapshot the entry was made on as the base, so an entry can be applied
+/// after the branch has moved on. Files that were untracked when they were
+/// stashed are kept untracked.
+///
+/// Returns the files that were changed both in the current snapshot and the
+/// entry and couldn't be merged. Text changed on both sides is left with
+/// conflict markers, and a file deleted on one side keeps the other's version.
+fn apply_entry(repo: &mut Repository, id: usize) -> Result<Vec<RelativePathBuf>> {
+    let entry = repo.stash.get_state(id).unwrap().clone();
+
+    let base = repo.fetch_snapshot(entry.basis)?;
+
+    let ours = repo.fetch_current_snapshot()?;
+
+    let theirs = &entry.state;
+
+    let options = MergeOptions {
+        base_label: Some(entry.basis.to_string()),
+        ours_label: Some(merge::prettify_hash(repo, ours.hash)),
+        theirs_label: Some(format!("stash {id}")),
+
+        .. MergeOptions::default()
+    };
+
+    let all_paths: BTreeSet<&RelativePathBuf> = base.files
+        .keys()
+        .chain(ours.files.keys())
+        .chain(theirs.files.keys())
+        .collect();
+
+    let mut files = BTreeMap::new();
+    let mut modes = BTreeMap::new();
+
+    let mut conflicts = vec![];
+
+    for path in all_paths {
+        let in_base = base.files.get(path).copied();
+        let in_ours = ours.files.get(path).copied();
+        let in_theirs = theirs.files.get(path).copied();
+
+        let merged = if in_theirs == in_base || in_theirs == in_ours {
+            in_ours
+        }
+        else if in_ours == in_base {
+            in_theirs
+        }
+        else if let (Some(our_hash), Some(their_hash)) = (in_ours, in_theirs) {
+            let original = match in_base {
+                Some(hash) => repo.fetch_string_content(hash)?,
+                None => String::new()
+            };
+
+            let result = merge_strings(
+                &original,
+                &repo.fetch_string_content(our_hash)?,
+                &repo.fetch_string_content(their_hash)?,
+                &options
+            )?;
+
+            if !result.is_clean_merge() {
+                conflicts.push(path.clone());
+            }
+
+            Some(repo.save_content_raw(&result.content)?)
+        }
+        else {
+            conflicts.push(path.clone());
+
+            in_ours.or(in_theirs)
+        };
+
+        let Some(hash) = merged else {
+            continue;
+        };
+
+        let their_mode = theirs.modes.get(path).copied().unwrap_or_default();
+
+        let mode = if in_theirs.is_some() && their_mode != base.mode(path) {
+            their_mode
+        }
+        else {
+            ours.mode(path)
+        };
+
+        files.insert(path.clone(), hash);
+        modes.insert(path.clone(), mode);
+    }
+
+    print_backup(repo.replace_cwd_with_files(&files, &mode::special_modes(modes))?);
+
+    for path in &theirs.untracked {
+        if !ours.files.contains_key(path) {
+            repo.staging.unstage(path);
+        }
+    }
+
+    Ok(conflicts)
+}
+
+/// Say which files conflicted when applying a stash entry.
+fn print_conflicts(id: usize, conflicts: &[RelativePathBuf]) {
+    exit::set(Status::Conflicts);
+
+    eprintln!("Stash {id} was applied with {} conflicting files:", conflicts.len());
 
-    Ok(stash_id)
+    for path in conflicts {
+        eprintln!(" * {path}");
+    }
+}
+
+/// Say where unsaved changes went, if replacing the working directory backed them up.
+pub fn print_backup(backup: Option<usize>) {
+    if let Some(id) = backup {
+        say!(" * Unsaved changes were backed up as stash {id}. Use 'asc stash apply {id}' to get them back.");
+    }
 }
 
 pub fn parse(subcommand: Subcommands) -> Result<()> {
@@ -131,16 +373,26 @@ pub fn parse(subcommand: Subcommands) -> Result<()> {
     use Subcommands::*;
 
     match subcommand {
-        New { message, editor } => {
-            let stash_id = save_new_stash(message, editor, &mut repo)?;
+        New(args) => {
+            let Some(stashed) = save_new_stash(args, &mut repo)? else {
+                return Ok(());
+            };
 
-            println!("Created new stash with ID {stash_id}");
+            println!("Created new stash with ID {}", stashed.id);
 
             let current = repo.fetch_current_snapshot()?;
 
-            repo.replace_cwd_with_files(&current.files)?;
+            print_backup(repo.replace_cwd_with_files(&stashed.remaining, &stashed.remaining_modes)?);
+
+            for path in &stashed.added {
+                let full_path = paths::on_disk(path, &repo.root_dir);
 
-            let version = if let Some(name) = repo.branches.get_name_for(current.hash) {
+                if mode::exists(&full_path) {
+                    remove_path(full_path, &repo.root_dir)?;
+                }
+            }
+
+            let version = if let Some(name) = repo.refs.branches().get_name_for(current.hash) {
                 format!("{name:?} ({})", current.hash)
             }
             else {
@@ -150,10 +402,41 @@ pub fn parse(subcommand: Subcommands) -> Result<()> {
             println!("Reverted back to: {version}");
         }
 
-        Save { message, editor } => {
-            let stash_id = save_new_stash(message, editor, &mut repo)?;
+        Save(args) => {
+            let Some(stashed) = save_new_stash(args, &mut repo)? else {
+                return Ok(());
+            };
+
+            println!("Created new stash with ID {}", stashed.id);
+        }
+
+        Show { id } => {
+            let Some(id) = id.or(repo.stash.topmost_id()) else {
+                exit::set(Status::Failure);
+
+                eprintln!("The stash is empty.");
+
+                return Ok(());
+            };
+
+            let Some(entry) = repo.stash.get_state(id) else {
+                exit::set(Status::Failure);
+
+                eprintln!("No stash with ID {id}.");
+
+                return Ok(());
+            };
+
+            let old_files = diff::get_locators(&repo, Some(entry.basis))?;
+
+            let new_files: Vec<Locator> = entry.state.files
+                .iter()
+                .map(|(path, &hash)| Locator::WithHash(path.clone(), hash))
+                .collect();
 
-            println!("Created new stash with ID {stash_id}");
+            diff::print_diff(&repo, &old_files, &new_files, true)?;
+
+            return Ok(());
         }
 
         Delete { id: Some(id) } => {
@@ -173,6 +456,8 @@ pub fn parse(subcommand: Subcommands) -> Result<()> {
 
         Pop { id } => {
             let Some(topmost) = repo.stash.topmost_id() else {
+                exit::set(Status::Failure);
+
                 eprintln!("The stash is empty.");
 
                 return Ok(());
@@ -180,25 +465,42 @@ pub fn parse(subcommand: Subcommands) -> Result<()> {
 
             let id = id.unwrap_or(topmost);
             
-            let Some(entry) = repo.stash.get_state(id) else {
+            if repo.stash.get_state(id).is_none() {
+                exit::set(Status::Failure);
+
                 eprintln!("No stash with ID {id}.");
 
                 return Ok(());
-            };
+            }
 
             if repo.has_unsaved_changes()? {
+                exit::set(Status::Changes);
+
                 eprintln!("Cannot update working directory with unsaved changes.");
 
                 return Ok(());
             }
             
-            repo.replace_cwd_with_files(&entry.state.files.clone())?;
+            let conflicts = apply_entry(&mut repo, id)?;
+
+            // The entry is kept while there are conflicts to resolve,
+            // so the stashed changes aren't lost if it goes wrong.
+            if conflicts.is_empty() {
+                repo.stash.remove_state(id);
+
+                println!("Popped stash with ID {id}");
+            }
+            else {
+                print_conflicts(id, &conflicts);
 
-            println!("Popped stash with ID {id}");
+                eprintln!("The stash was kept. Resolve the conflicts, then remove it with 'asc stash delete {id}'.");
+            }
         }
 
         Apply { id } => {
             let Some(topmost) = repo.stash.topmost_id() else {
+                exit::set(Status::Failure);
+
                 eprintln!("The stash is empty.");
 
                 return Ok(());
@@ -206,25 +508,36 @@ pub fn parse(subcommand: Subcommands) -> Result<()> {
 
             let id = id.unwrap_or(topmost);
             
-            let Some(entry) = repo.stash.get_state(id) else {
+            if repo.stash.get_state(id).is_none() {
+                exit::set(Status::Failure);
+
                 eprintln!("No stash with ID {id}.");
 
                 return Ok(());
-            };
+            }
 
             if repo.has_unsaved_changes()? {
+                exit::set(Status::Changes);
+
                 eprintln!("Cannot update working directory with unsaved changes.");
 
                 return Ok(());
             }
             
-            repo.replace_cwd_with_files(&entry.state.files.clone())?;
+            let conflicts = apply_entry(&mut repo, id)?;
 
-            println!("Restored working directory to stash ID {id}");
+            if conflicts.is_empty() {
+                println!("Applied stash with ID {id}");
+            }
+            else {
+                print_conflicts(id, &conflicts);
+            }
         }
 
         Goto { id } => {
             let Some(topmost) = repo.stash.topmost_id() else {
+                exit::set(Status::Failure);
+
                 eprintln!("The stash is empty.");
 
                 return Ok(());
@@ -233,6 +546,8 @@ pub fn parse(subcommand: Subcommands) -> Result<()> {
             let id = id.unwrap_or(topmost);
             
             let Some(entry) = repo.stash.get_state(id) else {
+                exit::set(Status::Failure);
+
                 eprintln!("No stash with ID {id}.");
 
                 return Ok(());
@@ -240,13 +555,13 @@ pub fn parse(subcommand: Subcommands) -> Result<()> {
 
             let snapshot = repo.fetch_snapshot(entry.basis)?;
 
-            let before = repo.current_hash;
+            let before = repo.current_hash();
 
             let after = entry.basis;
 
-            repo.replace_cwd_with_snapshot(&snapshot)?;
+            print_backup(repo.replace_cwd_with_snapshot(&snapshot)?);
 
-            repo.current_hash = after;
+            repo.refs.switch_to_hash(after);
 
             println!("Restored working directory to stash {} (HEAD switched: {before} -> {after})", snapshot.hash);
         },
@@ -261,7 +576,9 @@ pub fn parse(subcommand: Subcommands) -> Result<()> {
             println!("Stashes:");
 
             for (id, entry) in repo.stash.iter() {
-                println!("    {}: [{}] on {}", id, entry.basis, entry.timestamp);
+                let kind = if entry.backup { " (backup)" } else { "" };
+
+                println!("    {}: [{}] on {}{kind}", id, entry.basis, entry.timestamp);
                 println!("        {}", entry.state.message);
             }
         }
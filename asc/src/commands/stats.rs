@@ -0,0 +1,258 @@
+use chrono::{Local, Utc, Weekday};
+use eyre::Result;
+use libasc::{activity::{self, Activity}, repository::Repository, storage};
+
+use crate::output::{self, format_size};
+
+#[derive(clap::Args)]
+pub struct Args {
+    /// How many of the largest files to list.
+    #[arg(short, long, default_value_t = 10)]
+    largest: usize,
+
+    /// Show when snapshots were made and by whom, instead of what is stored.
+    #[arg(long)]
+    activity: bool,
+
+    /// The snapshots to count with --activity, as `BASE..TIP` for every
+    /// snapshot after BASE up to TIP, or as a single version for everything
+    /// up to it. Defaults to the current version.
+    #[arg(requires = "activity")]
+    range: Option<String>,
+
+    /// Print the activity as CSV, one row per count.
+    #[arg(long, requires = "activity")]
+    csv: bool,
+
+    /// Count days and hours in UTC, instead of the local time zone.
+    #[arg(long, requires = "activity")]
+    utc: bool
+}
+
+/// Shades for the heatmap cells, from no snapshots to the most.
+const SHADES: [char; 5] = [' ', '░', '▒', '▓', '█'];
+
+const WEEKDAYS: [Weekday; 7] = [
+    Weekday::Mon,
+    Weekday::Tue,
+    Weekday::Wed,
+    Weekday::Thu,
+    Weekday::Fri,
+    Weekday::Sat,
+    Weekday::Sun
+];
+
+/// Print how many snapshots were made in each hour of the week, as a grid.
+///
+/// In plain mode, the hours with snapshots are listed instead.
+fn print_heatmap(activity: &Activity) {
+    let busiest = activity.by_hour
+        .iter()
+        .flatten()
+        .max()
+        .copied()
+        .unwrap_or(0);
+
+    if output::is_plain() {
+        for (day, hours) in WEEKDAYS.iter().zip(&activity.by_hour) {
+            let counts: Vec<String> = hours
+                .iter()
+                .enumerate()
+                .filter(|(_, count)| **count > 0)
+                .map(|(hour, count)| format!("{hour:02}:00 {count}"))
+                .collect();
+
+            if !counts.is_empty() {
+                println!(" {day}: {}", counts.join(", "));
+            }
+        }
+
+        return;
+    }
+
+    let hours: String = (0..24)
+        .map(|hour| if hour % 6 == 0 { format!("{hour:<6}") } else { String::new() })
+        .collect();
+
+    println!("      {hours}");
+
+    for (day, counts) in WEEKDAYS.iter().zip(&activity.by_hour) {
+        let cells: String = counts
+            .iter()
+            .map(|&count| match count {
+                0 => SHADES[0],
+                count => SHADES[(count * (SHADES.len() - 1)).div_ceil(busiest)]
+            })
+            .collect();
+
+        println!("  {day} {cells}");
+    }
+
+    println!("      {} = {busiest} snapshots", SHADES[SHADES.len() - 1]);
+}
+
+fn print_bars<'a>(rows: impl IntoIterator<Item = (&'a String, &'a usize)>) {
+    let rows: Vec<(&String, &usize)> = rows.into_iter().collect();
+
+    let widest = rows.iter().map(|(_, count)| **count).max().unwrap_or(1);
+
+    let name_width = rows.iter().map(|(name, _)| name.chars().count()).max().unwrap_or(0);
+
+    for (name, count) in rows {
+        let bar = "#".repeat((count * 40).div_ceil(widest));
+
+        println!(" {name:>name_width$} | {bar} {count}");
+    }
+}
+
+/// Quote a field for CSV, if it needs it.
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    }
+    else {
+        field.to_string()
+    }
+}
+
+fn print_csv(activity: &Activity) {
+    println!("view,weekday,hour,month,author,snapshots");
+
+    for (day, hours) in WEEKDAYS.iter().zip(&activity.by_hour) {
+        for (hour, count) in hours.iter().enumerate() {
+            println!("hour,{day},{hour},,,{count}");
+        }
+    }
+
+    for (month, count) in &activity.by_month {
+        println!("month,,,{month},,{count}");
+    }
+
+    for (author, count) in &activity.by_author {
+        println!("author,,,,{},{count}", csv_field(author));
+    }
+}
+
+fn show_activity(repo: &Repository, args: &Args) -> Result<()> {
+    let (base, tip) = match args.range.as_deref() {
+        Some(range) => match range.split_once("..") {
+            Some((base, tip)) => (Some(repo.normalise_version(base)?), repo.normalise_version(tip)?),
+
+            None => (None, repo.normalise_version(range)?)
+        },
+
+        None => (None, repo.current_hash())
+    };
+
+    let snapshots = activity::range(repo, tip, base)?;
+
+    let activity = if args.utc {
+        activity::collect(repo, &snapshots, &Utc)?
+    }
+    else {
+        activity::collect(repo, &snapshots, &Local)?
+    };
+
+    if output::is_json() {
+        return output::print_json(&activity);
+    }
+
+    if args.csv {
+        print_csv(&activity);
+
+        return Ok(());
+    }
+
+    if activity.snapshots == 0 {
+        eprintln!("No snapshots found.");
+
+        return Ok(());
+    }
+
+    println!("Snapshots: {}", activity.snapshots);
+
+    println!();
+    println!("By day and hour{}:", if args.utc { " (UTC)" } else { "" });
+
+    print_heatmap(&activity);
+
+    println!();
+    println!("By month:");
+
+    print_bars(&activity.by_month);
+
+    let mut authors: Vec<(&String, &usize)> = activity.by_author.iter().collect();
+
+    authors.sort_by(|a, b| b.1.cmp(a.1));
+
+    println!();
+    println!("By author:");
+
+    print_bars(authors);
+
+    Ok(())
+}
+
+pub fn parse(args: Args) -> Result<()> {
+    let repo = Repository::load()?;
+
+    if args.activity {
+        return show_activity(&repo, &args);
+    }
+
+    let stats = storage::collect(&repo, args.largest)?;
+
+    if output::is_json() {
+        return output::print_json(&stats);
+    }
+
+    let objects = stats.literals + stats.deltas + stats.large_files;
+
+    println!("Snapshots: {}", stats.snapshots);
+    println!("Content objects: {objects} ({} whole, {} deltas, {} large files)", stats.literals, stats.deltas, stats.large_files);
+    println!("Files in snapshots: {} (stored as {objects} objects)", stats.file_references);
+
+    let ratio = match stats.stored_size {
+        0 => 1.0,
+        stored => stats.logical_size as f64 / stored as f64
+    };
+
+    println!(
+        "Size: {} stored, {} uncompressed ({ratio:.1}x)",
+        format_size(stats.stored_size),
+        format_size(stats.logical_size)
+    );
+
+    if !stats.delta_depths.is_empty() {
+        println!();
+        println!("Delta chain depths:");
+
+        let widest = stats.delta_depths.values().max().copied().unwrap_or(1);
+
+        for (depth, count) in &stats.delta_depths {
+            let bar = "#".repeat((count * 40).div_ceil(widest));
+
+            println!(" {depth:>4} | {bar} {count}");
+        }
+    }
+
+    if !stats.largest_files.is_empty() {
+        println!();
+        println!("Largest files:");
+
+        for file in &stats.largest_files {
+            println!(" * {} ({}, {})", file.path, file.hash, format_size(file.size));
+        }
+    }
+
+    if !stats.branches.is_empty() {
+        println!();
+        println!("Snapshots per branch:");
+
+        for (name, count) in &stats.branches {
+            println!(" * {name}: {count}");
+        }
+    }
+
+    Ok(())
+}
@@ -1,38 +1,119 @@
 use eyre::Result;
 
-use libasc::{action::Action, repository::Repository};
+use libasc::{action::Action, hash::ObjectHash, refname, refs::Ref, repository::Repository};
+
+use crate::{commands::stash, exit::{self, Status}, i18n::t, output::say};
 
 #[derive(clap::Args)]
 pub struct Args {
     /// The version to change to.
-    /// This can be a branch name or a commit hash.
-    version: String
+    /// This can be a branch name or a commit hash, and
+    /// `branch:` or `hash:` in front says which it is.
+    ///
+    /// With `--create`, this is where the new branch
+    /// starts, and defaults to the current version.
+    #[arg(required_unless_present = "create")]
+    version: Option<String>,
+
+    /// Create a branch with this name and switch to it.
+    #[arg(short = 'c', long, value_name = "NEW_BRANCH")]
+    create: Option<String>
+}
+
+/// Check if a snapshot can be reached from a branch or a tag.
+fn is_on_ref(repo: &Repository, hash: ObjectHash) -> Result<bool> {
+    let tips = repo.refs
+        .branches()
+        .values()
+        .chain(repo.refs.tags().values());
+
+    Ok(repo.history.reachable(tips, |_| false)?.contains(&hash))
 }
 
 pub fn parse(args: Args) -> Result<()> {
     let mut repo = Repository::load()?;
 
-    if repo.has_unsaved_changes()? {
-        eprintln!("Cannot switch versions with unsaved changes.");
+    if let Some(name) = &args.create
+        && repo.refs.branches().contains(name)
+    {
+        exit::set(Status::Failure);
+
+        eprintln!("{}", t!("branch-exists", name = name));
+
+        return Ok(());
+    }
+
+    if let Some(name) = &args.create
+        && let Err(e) = refname::check_branch(name, &repo.config)
+    {
+        exit::set(Status::Failure);
+
+        eprintln!("{}", t!("branch-invalid-name", error = e));
 
         return Ok(());
     }
 
-    let previous_hash = repo.current_hash;
+    let previous_hash = repo.current_hash();
+
+    let new_hash = match &args.version {
+        Some(version) => repo.normalise_version(version)?,
+        None => previous_hash
+    };
 
-    let new_hash = repo.normalise_version(&args.version)?;
+    // A new branch at the current version leaves the
+    // working directory as it is, so changes can come along.
+    let moves = new_hash != previous_hash || args.create.is_none();
+
+    if moves && repo.has_unsaved_changes()? {
+        exit::set(Status::Changes);
+
+        eprintln!("{}", t!("switch-unsaved-changes"));
+
+        return Ok(());
+    }
 
-    let before = repo.branches.get_name_for(previous_hash)
+    let before = repo
+        .current_branch()
         .map(String::from)
         .unwrap_or(format!("{}", previous_hash));
 
-    let after = repo.branches.get_name_for(new_hash)
-        .map(String::from)
-        .unwrap_or(format!("{}", new_hash));
+    let leaving_detached = repo.is_head_detached()
+        && new_hash != previous_hash
+        && !is_on_ref(&repo, previous_hash)?;
+
+    // Switching to a branch by name puts the head on it,
+    // while anything else leaves the head detached.
+    let branch = match (&args.create, &args.version) {
+        (Some(name), _) => {
+            repo.refs.branches_mut().create(name.clone(), new_hash);
+
+            println!("{}", t!("branch-created", name = name, hash = new_hash));
+
+            repo.action_history.push(
+                Action::CreateBranch {
+                    name: name.clone(),
+                    hash: new_hash
+                }
+            );
+
+            Some(name.clone())
+        },
+
+        (None, Some(version)) => match repo.refs.lookup(version) {
+            Some((Ref::Branch(name), _)) => Some(name.clone()),
+            _ => None
+        },
+
+        (None, None) => None
+    };
 
-    let new_state = repo.fetch_snapshot(new_hash)?;
+    let after = branch.clone().unwrap_or(format!("{}", new_hash));
 
-    repo.replace_cwd_with_snapshot(&new_state)?;
+    if moves {
+        let new_state = repo.fetch_snapshot(new_hash)?;
+
+        stash::print_backup(repo.replace_cwd_with_snapshot(&new_state)?);
+    }
 
     repo.action_history.push(
         Action::SwitchVersion {
@@ -41,11 +122,22 @@ pub fn parse(args: Args) -> Result<()> {
         }
     );
 
-    repo.current_hash = new_hash;
-    
+    match &branch {
+        Some(name) => { repo.refs.attach_head(name); },
+        None => repo.refs.detach_head(new_hash)
+    }
+
     repo.save()?;
 
-    println!("Switched versions: {before} -> {after}");
+    say!("{}", t!("switch-done", before = before, after = after));
+
+    if leaving_detached {
+        eprintln!("{}", t!("switch-leaving-detached", hash = previous_hash));
+    }
+
+    if branch.is_none() {
+        eprintln!("{}", t!("switch-detached", hash = new_hash));
+    }
 
     Ok(())
 }
@@ -1,8 +1,11 @@
 use std::io::{Read, stdin};
 
+use clap::ValueEnum;
 use eyre::Result;
 
-use libasc::{action::Action, repository::Repository, utils::filter_with_glob};
+use libasc::{action::Action, hash::ObjectHash, refname, refs::compare_tag_names, repository::Repository, semver::{self, Bump}, trash::TrashStatus, utils::filter_with_glob};
+
+use crate::{exit::{self, Status}, output::{self, RefEntry}};
 
 #[derive(clap::Subcommand)]
 pub enum Subcommands {
@@ -17,12 +20,49 @@ pub enum Subcommands {
     },
 
     /// List all the tags in the repository.
+    /// 
+    /// With `--verbose`, the date and message of
+    /// the snapshot each tag points to is shown too.
     #[command(visible_alias = "ls")]
     List {
         globs: Option<Vec<String>>,
 
         #[arg(short = 'n', long)]
-        limit: Option<usize>
+        limit: Option<usize>,
+
+        /// How to order the tags.
+        #[arg(long, value_enum, default_value = "version")]
+        sort: TagSort,
+
+        /// Only list tags on snapshots that have this version in their history.
+        #[arg(long, value_name = "VERSION")]
+        contains: Option<String>,
+
+        /// Only list tags on this version.
+        #[arg(long, value_name = "VERSION")]
+        points_at: Option<String>
+    },
+
+    /// Show the latest release, reading tags as versions like `v1.4.2`.
+    Latest {
+        /// Show the latest release on each release line, like `v1.3.x` and `v1.4.x`.
+        #[arg(long)]
+        lines: bool,
+
+        /// Count pre-releases, like `v2.0.0-rc.1`.
+        #[arg(long)]
+        pre_releases: bool
+    },
+
+    /// Show what the next release is called, after the latest version tagged.
+    Next {
+        /// Which part of the version to move up.
+        #[arg(value_enum)]
+        bump: BumpKind,
+
+        /// Create the tag on this version as well.
+        #[arg(long, value_name = "VERSION")]
+        create: Option<String>
     },
 
     /// Delete tags in the repository.
@@ -46,6 +86,35 @@ pub enum Subcommands {
     }
 }
 
+#[derive(Clone, Copy, ValueEnum)]
+pub enum TagSort {
+    /// By the version each tag names, like `v1.9.0` before `v1.10.0`.
+    Version,
+
+    /// By name, character by character.
+    Name,
+
+    /// By when the tagged snapshot was made, oldest first.
+    Date
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum BumpKind {
+    Major,
+    Minor,
+    Patch
+}
+
+impl From<BumpKind> for Bump {
+    fn from(kind: BumpKind) -> Bump {
+        match kind {
+            BumpKind::Major => Bump::Major,
+            BumpKind::Minor => Bump::Minor,
+            BumpKind::Patch => Bump::Patch
+        }
+    }
+}
+
 fn prompt_user(message: impl AsRef<str>) -> Result<bool> {
     let mut stdin = stdin().lock();
     
@@ -76,11 +145,65 @@ pub fn parse(subcommand: Subcommands) -> Result<()> {
         Create { name, version } => {
             let hash = repo.normalise_version(&version)?;
 
-            if let Some(previous) = repo.tags.create(name.clone(), hash) {
+            if !repo.has_snapshot(hash) {
+                exit::set(Status::Failure);
+
+                eprintln!("Cannot tag {hash}, since it is not a snapshot in this repository.");
+
+                return Ok(());
+            }
+
+            if let Some(status) = repo.trash_contains(hash) {
+                let trashed = match status {
+                    TrashStatus::Direct => hash,
+                    TrashStatus::Indirect(parent) => parent
+                };
+
+                exit::set(Status::Failure);
+
+                eprintln!("Cannot tag {hash}, since it is in the trash. Recover {trashed} first to tag it.");
+
+                return Ok(());
+            }
+
+            if !repo.refs.tags().contains(&name)
+                && let Err(e) = refname::check_tag(&name, &repo.config)
+            {
+                exit::set(Status::Failure);
+
+                eprintln!("Invalid tag name: {e}.");
+
+                return Ok(());
+            }
+
+            if let Some(previous) = repo.refs.tags().get(&name)
+                && previous != hash
+                && repo.config.tags.is_protected(&name)
+            {
+                exit::set(Status::Denied);
+
+                eprintln!("Tag {name:?} is protected, so it can't be moved from {previous}.");
+
+                return Ok(());
+            }
+
+            let before = repo.refs.tags().get(&name);
+
+            if let Some(previous) = repo.refs.tags_mut().create(name.clone(), hash) {
+                if output::is_plain() {
+                    repo.refs.tags_mut().create(name.clone(), previous);
+
+                    exit::set(Status::Failure);
+
+                    eprintln!("Tag {name:?} already points to {previous}. Delete it first to move it to {hash}.");
+
+                    return Ok(());
+                }
+
                 let prompt = format!("You are going to override the tag {name:?} ({previous}) with {hash}. Are you sure you want to do this?");
 
                 if !prompt_user(prompt)? {
-                    repo.tags.create(name.clone(), previous);
+                    repo.refs.tags_mut().create(name.clone(), previous);
                 }
             }
             else {
@@ -92,15 +215,80 @@ pub fn parse(subcommand: Subcommands) -> Result<()> {
                 );
             }
 
+            // Stamp the tag, so other repositories can tell
+            // whose version of it was made first when syncing.
+            if repo.config.tags.replicated
+                && before != Some(hash)
+                && repo.refs.tags().get(&name) == Some(hash)
+                && let Some(creator) = repo.current_user().map(|user| user.public_key)
+            {
+                repo.refs.stamp_tag(&name, creator);
+            }
+
             println!("Created tag: {name:?} -> {hash}");
         },
 
-        List { globs, limit } => {
+        List { globs, limit, sort, contains, points_at } => {
             let globs = globs.unwrap_or(vec!["**/*".to_string()]);
 
-            let all_tags: Vec<&String> = repo.tags.names().collect();
+            let all_tags: Vec<&str> = repo.refs.tags().names().collect();
             
-            let tags: Vec<&&String> = filter_with_glob(globs, &all_tags);
+            let mut tags: Vec<(&str, ObjectHash)> = filter_with_glob(globs, &all_tags)
+                .into_iter()
+                .map(|name| (*name, repo.refs.tags().get(name).unwrap()))
+                .collect();
+
+            if let Some(version) = points_at {
+                let target = repo.normalise_version(&version)?;
+
+                tags.retain(|(_, hash)| *hash == target);
+            }
+
+            if let Some(version) = contains {
+                let ancestor = repo.normalise_version(&version)?;
+
+                let mut kept = vec![];
+
+                for (name, hash) in tags {
+                    if repo.history.is_descendant(hash, ancestor)? {
+                        kept.push((name, hash));
+                    }
+                }
+
+                tags = kept;
+            }
+
+            match sort {
+                TagSort::Version => tags.sort_by(|a, b| compare_tag_names(a.0, b.0)),
+                TagSort::Name => tags.sort_by(|a, b| a.0.cmp(b.0)),
+
+                TagSort::Date => {
+                    let mut dated = vec![];
+
+                    for (name, hash) in tags {
+                        dated.push((repo.fetch_snapshot(hash)?.timestamp, name, hash));
+                    }
+
+                    dated.sort();
+
+                    tags = dated.into_iter().map(|(_, name, hash)| (name, hash)).collect();
+                }
+            }
+
+            tags.truncate(limit.unwrap_or(usize::MAX));
+
+            if output::is_json() {
+                let entries: Vec<RefEntry> = tags
+                    .iter()
+                    .map(|(name, hash)| RefEntry {
+                        name,
+                        hash: hash.full(),
+                        current: None
+                    })
+                    .collect();
+
+                return output::print_json(&entries);
+            }
 
             if tags.is_empty() {
                 println!("No tags found.");
@@ -110,16 +298,78 @@ pub fn parse(subcommand: Subcommands) -> Result<()> {
 
             println!("Tags:");
 
-            for name in tags.iter().take(limit.unwrap_or(usize::MAX)) {
-                let hash = repo.tags.get(name).unwrap();
-                
-                println!(" * {name} -> {hash}");
+            for (name, hash) in tags {
+                if output::is_verbose() {
+                    let snapshot = repo.fetch_snapshot(hash)?;
+
+                    let subject = snapshot.message.lines().next().unwrap_or_default();
+
+                    println!(" * {name} -> {hash} ({}) {subject}", snapshot.timestamp.format("%Y-%m-%d"));
+                }
+                else {
+                    println!(" * {name} -> {hash}");
+                }
             }
         },
 
+        Latest { lines, pre_releases } => {
+            let names: Vec<&str> = repo.refs.tags().names().collect();
+
+            if lines {
+                let latest = semver::latest_per_line(names, pre_releases);
+
+                if latest.is_empty() {
+                    println!("No tags are versions.");
+
+                    return Ok(());
+                }
+
+                for ((major, minor), (name, _)) in latest {
+                    println!(" * {major}.{minor}.x: {name} -> {}", repo.refs.tags().get(name).unwrap());
+                }
+            }
+            else {
+                let Some((name, _)) = semver::latest(names, pre_releases) else {
+                    exit::set(Status::Failure);
+
+                    eprintln!("No tags are versions.");
+
+                    return Ok(());
+                };
+
+                println!("{name} -> {}", repo.refs.tags().get(name).unwrap());
+            }
+        },
+
+        Next { bump, create } => {
+            let next = semver::next_name(repo.refs.tags().names(), bump.into());
+
+            let Some(version) = create else {
+                println!("{next}");
+
+                return Ok(());
+            };
+
+            return parse(Create { name: next, version });
+        },
+
         Delete { names, keep_going } => {
             for name in names {
-                if let Some(removed) = repo.tags.remove(&name) {
+                if repo.refs.tags().get(&name).is_some() && repo.config.tags.is_protected(&name) {
+                    exit::set(Status::Denied);
+
+                    if keep_going {
+                        eprintln!("Tag {name:?} is protected, so it can't be deleted. Continuing...");
+
+                        continue;
+                    }
+
+                    eprintln!("Tag {name:?} is protected, so it can't be deleted. Aborting...");
+
+                    break;
+                }
+
+                if let Some(removed) = repo.refs.tags_mut().remove(&name) {
                     println!("Removed tag {name:?} ({removed}) from the repository.");
 
                     repo.action_history.push(
@@ -141,10 +391,26 @@ pub fn parse(subcommand: Subcommands) -> Result<()> {
         },
 
         Rename { old, new } => {
-            if let Some(hash) = repo.tags.remove(&old) {
+            if repo.refs.tags().get(&old).is_some() && repo.config.tags.is_protected(&old) {
+                exit::set(Status::Denied);
+
+                eprintln!("Tag {old:?} is protected, so it can't be renamed.");
+
+                return Ok(());
+            }
+
+            if let Err(e) = refname::check_tag(&new, &repo.config) {
+                exit::set(Status::Failure);
+
+                eprintln!("Invalid tag name: {e}.");
+
+                return Ok(());
+            }
+
+            if let Some(hash) = repo.refs.tags_mut().remove(&old) {
                 println!("Renamed {old:?} to {new:?} ({hash})");
 
-                repo.tags.create(new.clone(), hash);
+                repo.refs.tags_mut().create(new.clone(), hash);
 
                 repo.action_history.push(
                     Action::RenameTag {
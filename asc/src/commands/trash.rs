@@ -1,6 +1,8 @@
 use eyre::{Result, eyre};
 
-use libasc::{action::Action, graph::Graph, hash::ObjectHash, repository::Repository, trash::{Entry, TrashStatus}, unwrap};
+use libasc::{action::Action, hash::ObjectHash, repository::Repository, retention, trash::{Entry, TrashStatus}, unwrap, utils::parse_duration};
+
+use crate::{commands::stash, exit::{self, Status}};
 
 #[derive(clap::Subcommand)]
 pub enum Subcommands {
@@ -24,33 +26,23 @@ pub enum Subcommands {
 
         /// The number of entries to list, or all if unspecified.
         limit: Option<usize>
-    }
-}
-
-fn count_subnodes(graph: &Graph, node: ObjectHash) -> usize {
-    graph
-        .get_parents(node)
-        .unwrap()
-        .iter()
-        .map(|&node| count_subnodes(graph, node))
-        .sum()
-}
+    },
 
-fn list_all_subnodes(graph: &Graph, hash: ObjectHash) -> Vec<ObjectHash> {
-    let mut v = vec![];
-    
-    for &child in graph.get_parents(hash).unwrap() {
-        v.extend(&list_all_subnodes(graph, child));
+    /// Permanently delete old snapshots in the trash.
+    /// 
+    /// Any files only used by those snapshots are deleted
+    /// with them. This cannot be undone.
+    Purge {
+        /// Only purge snapshots trashed longer ago than this, like `30d` or `12h`.
+        /// Defaults to `trash.retention` in the config, or 30 days.
+        #[arg(long, value_name = "AGE")]
+        older_than: Option<String>
     }
-
-    v
 }
 
 pub fn parse(subcommand: Subcommands) -> Result<()> {
     let mut repo = Repository::load()?;
 
-    let inverse_links = repo.history.invert();
-
     use Subcommands::*;
 
     match subcommand {
@@ -63,6 +55,8 @@ pub fn parse(subcommand: Subcommands) -> Result<()> {
             );
 
             if parents.is_empty() {
+                exit::set(Status::Failure);
+
                 eprintln!("Cannot trash a root snapshot.");
 
                 return Ok(());
@@ -82,38 +76,73 @@ pub fn parse(subcommand: Subcommands) -> Result<()> {
                 return Ok(());
             }
 
-            let branches_to_remove: Vec<&str> = repo.branches
+            let branches_to_remove: Vec<&str> = repo.refs.branches()
                 .iter()
-                .filter_map(|(name, &branch_hash)| {
+                .filter_map(|(name, branch_hash)| {
                     repo.history
                         .is_descendant(branch_hash, hash)
                         .unwrap()
-                        .then_some(name.as_str())
+                        .then_some(name)
                 })
                 .collect();
 
             if !branches_to_remove.is_empty() {
+                exit::set(Status::Failure);
+
                 eprintln!("Trashing this snapshot and its children involves trashing snapshots that are branch tips. To resolve this, run `asc tag delete {}` to delete the offending branches.", branches_to_remove.join(" "));
 
                 return Ok(());
             }
 
-            let tags_to_remove: Vec<&str> = repo.tags
+            let tags_to_remove: Vec<&str> = repo.refs.tags()
                 .iter()
-                .filter_map(|(name, &tag_hash)| {
+                .filter_map(|(name, tag_hash)| {
                     repo.history
                         .is_descendant(tag_hash, hash)
                         .unwrap()
-                        .then_some(name.as_str())
+                        .then_some(name)
                 })
                 .collect();
 
             if !tags_to_remove.is_empty() {
+                exit::set(Status::Failure);
+
                 eprintln!("Trashing this snapshot and its children involves trashing snapshots that have been tagged. To resolve this, run `asc tag delete {}` to delete the offending tags.", tags_to_remove.join(" "));
 
                 return Ok(());
             }
 
+            let pinned: Vec<String> = repo.pins
+                .hashes()
+                .filter(|&pinned| repo.history.is_descendant(pinned, hash).unwrap_or(false))
+                .map(|pinned| pinned.to_string())
+                .collect();
+
+            if !pinned.is_empty() {
+                exit::set(Status::Failure);
+
+                eprintln!("Trashing this snapshot and its children involves trashing snapshots that are pinned. To resolve this, run `asc unpin {}` to unpin them.", pinned.join(" "));
+
+                return Ok(());
+            }
+
+            let trashed = repo.history.iter_descendants(hash).chain([hash]);
+
+            if let Some(hold) = retention::first_hold(&repo, trashed)? {
+                retention::record_blocked(&repo, format!("trash add {hash}"), &hold)?;
+
+                exit::set(Status::Failure);
+
+                eprintln!(
+                    "Trashing this snapshot and its children involves trashing {}, which was made on {:?} and has to be kept until {}.",
+                    hold.hash,
+                    hold.branch,
+                    hold.until.format("%Y-%m-%d")
+                );
+
+                return Ok(());
+            }
+
             repo.trash.add(hash);
 
             // TODO: make it so this makes new branches for all the parent hashes
@@ -124,9 +153,9 @@ pub fn parse(subcommand: Subcommands) -> Result<()> {
                 .cloned()
                 .collect();
 
-            if repo.history.is_descendant(repo.current_hash, hash)? {
+            if repo.history.is_descendant(repo.current_hash(), hash)? {
                 if repo.has_unsaved_changes()? {
-                    let pretty_offending = repo.branches
+                    let pretty_offending = repo.refs.branches()
                         .get_name_for(hash)
                         .map(String::from)
                         .unwrap_or(hash.to_string());
@@ -134,8 +163,10 @@ pub fn parse(subcommand: Subcommands) -> Result<()> {
                     let pretty_current = repo
                         .current_branch()
                         .map(String::from)
-                        .unwrap_or(format!("{}", repo.current_hash));
+                        .unwrap_or(format!("{}", repo.current_hash()));
                     
+                    exit::set(Status::Changes);
+
                     eprintln!("By trashing {pretty_offending}, the HEAD at {pretty_current} would also be trashed. Normally, this would move the HEAD back to one of the parents of {pretty_offending} to move the HEAD out of the trash. However, there are unsaved changes which would be lost. To save these, stash them or introduce a new commit to the repository.");
 
                     return Ok(());
@@ -145,16 +176,16 @@ pub fn parse(subcommand: Subcommands) -> Result<()> {
 
                 let new_snapshot = repo.fetch_snapshot(new_hash)?;
 
-                println!("Changing snapshots: {} -> {new_hash}", repo.current_hash);
+                println!("Changing snapshots: {} -> {new_hash}", repo.current_hash());
 
-                repo.replace_cwd_with_snapshot(&new_snapshot)?;
+                stash::print_backup(repo.replace_cwd_with_snapshot(&new_snapshot)?);
 
-                repo.current_hash = new_hash; // TODO: add this to log?
+                repo.refs.switch_to_hash(new_hash); // TODO: add this to log?
             }
 
             println!("Moved snapshot {hash} to the trash!");
 
-            let others_removed = count_subnodes(&inverse_links, hash);
+            let others_removed = repo.history.iter_descendants(hash).count();
 
             if others_removed > 0 {
                 println!("(Moved {others_removed} other snapshots to the trash too)");
@@ -174,12 +205,16 @@ pub fn parse(subcommand: Subcommands) -> Result<()> {
                 }
 
                 Some(TrashStatus::Indirect(to_remove)) => {
+                    exit::set(Status::Failure);
+
                     eprintln!("Snapshot {hash} cannot be removed from the trash until {to_remove} is removed.");
 
                     return Ok(());
                 }
 
                 None => {
+                    exit::set(Status::Failure);
+
                     eprintln!("Snapshot {hash} does not exist in the trash.");
 
                     return Ok(());
@@ -188,7 +223,7 @@ pub fn parse(subcommand: Subcommands) -> Result<()> {
 
             println!("Recovered {hash} from the trash!");
 
-            let others_recovered = count_subnodes(&inverse_links, hash);
+            let others_recovered = repo.history.iter_descendants(hash).count();
 
             if others_recovered > 0 {
                 println!("(Recovered {others_recovered} other snapshots from the trash too)");
@@ -220,7 +255,7 @@ pub fn parse(subcommand: Subcommands) -> Result<()> {
             for Entry { hash, when } in capped_entries {
                 let mut s = format!(" * {hash} [{when}]");
                 
-                let count = count_subnodes(&repo.history, *hash);
+                let count = repo.history.iter_descendants(*hash).count();
 
                 if count > 0 {
                     s = format!("{s} (+ {count})");
@@ -256,7 +291,9 @@ pub fn parse(subcommand: Subcommands) -> Result<()> {
 
             println!("Trash - implicitly trashed nodes of {hash}:");
 
-            let subnodes = list_all_subnodes(&repo.history, hash);
+            let mut subnodes: Vec<ObjectHash> = repo.history.iter_descendants(hash).collect();
+
+            subnodes.sort();
             
             let capped_subnodes = subnodes
                 .chunks(limit)
@@ -273,6 +310,32 @@ pub fn parse(subcommand: Subcommands) -> Result<()> {
                 println!("(+ {remaining} remaining subnodes)");
             }
         }
+
+        Purge { older_than } => {
+            let older_than = match older_than {
+                Some(raw) => parse_duration(&raw)?,
+                None => repo.config.trash.retention()?
+            };
+
+            let purged = repo.purge_trash(older_than)?;
+
+            for hash in &purged.kept {
+                eprintln!(" ! Kept {hash}, since a branch, tag, stash or the HEAD still uses it.");
+            }
+
+            if purged.entries.is_empty() {
+                println!("Nothing in the trash is old enough to purge.");
+
+                return Ok(());
+            }
+
+            for entry in &purged.entries {
+                println!(" * Purged {} [{}]", entry.hash, entry.when);
+            }
+
+            println!("Deleted {} snapshots and {} objects.", purged.snapshots.len(), purged.objects);
+            println!("This can't be undone, so the undo history has been cleared.");
+        }
     }
 
     repo.save()?;
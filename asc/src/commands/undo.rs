@@ -1,6 +1,8 @@
 use eyre::Result;
 
-use libasc::repository::Repository;
+use libasc::{action::Action, refs::Ref, repository::Repository};
+
+use crate::output::say;
 
 #[derive(clap::Args)]
 pub struct Args {
@@ -10,7 +12,22 @@ pub struct Args {
 
     /// The number of actions to undo. Defaults to 1.
     #[arg(short, long)]
-    count: Option<usize>
+    count: Option<usize>,
+
+    /// Undo actions even if they have already been pushed to a remote.
+    #[arg(short, long)]
+    force: bool
+}
+
+fn warn_published(action: &Action, published: &[Ref]) {
+    let remotes: Vec<String> = published
+        .iter()
+        .map(|r| r.name().unwrap_or_default().to_string())
+        .collect();
+
+    eprintln!(" ! {action} - this has already been pushed to {}.", remotes.join(", "));
+    eprintln!("   Undoing it leaves the branch behind the remote, so your history no longer");
+    eprintln!("   matches it, and the next pull brings the undone snapshots back.");
 }
 
 pub fn parse(args: Args) -> Result<()> {
@@ -24,8 +41,24 @@ pub fn parse(args: Args) -> Result<()> {
     let mut done = 0;
 
     for _ in 0..count {
+        let Some(next) = repo.action_history.current().cloned() else {
+            break;
+        };
+
+        let published = repo.published_refs(&next)?;
+
+        if !published.is_empty() {
+            warn_published(&next, &published);
+
+            if !args.force {
+                eprintln!("   Use '--force' to undo it anyway.");
+
+                break;
+            }
+        }
+
         if let Some(action) = repo.undo_action()? {
-            println!(" * {action}");
+            say!(" * {action}");
             
             done += 1;
         }
@@ -36,7 +69,7 @@ pub fn parse(args: Args) -> Result<()> {
 
     repo.save()?;
 
-    println!("Undid {done} actions.");
+    say!("Undid {done} actions.");
 
     Ok(())
 }
@@ -0,0 +1,45 @@
+use eyre::Result;
+use libasc::{lock, repository::Repository};
+
+use crate::exit::{self, Status};
+
+#[derive(clap::Args)]
+pub struct Args {
+    /// Remove the lock, even though the process holding it is still
+    /// running. Only use this for a process that is stuck.
+    #[arg(long)]
+    force: bool
+}
+
+pub fn parse(args: Args) -> Result<()> {
+    let repo = Repository::load()?;
+
+    let Some(holder) = lock::holder(&repo.root_dir) else {
+        exit::set(Status::Clean);
+
+        eprintln!("The repository isn't locked.");
+
+        return Ok(());
+    };
+
+    let held_by = if holder.pid == 0 {
+        "another process, which doesn't say who it is".to_string()
+    }
+    else {
+        format!("PID {} (running `asc {}` since {})", holder.pid, holder.command, holder.since.format("%Y-%m-%d %H:%M:%S"))
+    };
+
+    if !args.force {
+        exit::set(Status::Failure);
+
+        eprintln!("The repository is locked by {held_by}. If it is stuck, stop it, or run `asc unlock --force` to remove the lock.");
+
+        return Ok(());
+    }
+
+    lock::force_unlock(&repo.root_dir)?;
+
+    println!("Removed the lock held by {held_by}.");
+
+    Ok(())
+}
@@ -1,53 +1,63 @@
-use std::collections::HashSet;
-
 use libasc::{change::FileChange, repository::Repository, utils::resolve_wildcard_path};
 
 use eyre::Result;
-use relative_path::{PathExt, RelativePathBuf};
+use relative_path::PathExt;
+
+use crate::output::say;
 
 pub fn parse() -> Result<()> {
     let mut repo = Repository::load()?;
 
-    let mut staged_files: HashSet<RelativePathBuf> = repo.staged_files
-        .drain(..)
-        .collect();
-
     let mut added = vec![];
+    let mut edited = vec![];
     let mut removed = vec![];
-        
+
     for path in resolve_wildcard_path(&repo.root_dir)? {
+        if path.is_dir() {
+            continue;
+        }
+
         let relative = path.relative_to(&repo.root_dir)?;
 
         if repo.is_ignored_path(&path) {
-            if staged_files.contains(&relative) {
+            if repo.staging.contains(&relative) {
                 removed.push(relative);
             }
-            
+
             continue;
         }
 
-        if !staged_files.contains(&relative) {
-            added.push(relative);
+        match repo.staging.get(&relative) {
+            None => added.push(relative),
+            Some(previous) => {
+                if repo.stage_path(&relative)? != previous {
+                    edited.push(relative);
+                }
+            }
         }
     }
 
     let added_files = added.len();
 
     for path in added {
-        staged_files.insert(path.clone());
+        repo.stage_path(&path)?;
 
-        println!("{}", FileChange::Added(path));
+        say!("{}", FileChange::Added(path));
+    }
+
+    for path in edited {
+        say!("{}", FileChange::Edited(path));
     }
 
     let removed_files = removed.len();
-    
+
     for path in removed {
-        staged_files.remove(&path);
+        repo.staging.unstage(&path);
 
-        println!("{}", FileChange::Removed(path));
+        say!("{}", FileChange::Removed(path));
     }
 
-    println!("Added {added_files} files, removed {removed_files} files");
+    say!("Added {added_files} files, removed {removed_files} files");
 
     repo.save()?;
 
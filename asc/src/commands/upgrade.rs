@@ -0,0 +1,239 @@
+use std::{collections::HashMap, env::{self, consts}, fs, process::Command};
+
+use eyre::{bail, Result};
+use directories::BaseDirs;
+use libasc::{config::{Config, UpgradeConfig}, key::{PublicKey, Signature}, repository::REPOSITORY_FORMAT, unwrap, utils::hash_raw_bytes};
+use serde::Deserialize;
+
+use crate::{exit::{self, Status}, output::say};
+
+#[derive(clap::Args)]
+pub struct Args {
+    /// Only check for a newer version, without installing it.
+    #[arg(long)]
+    check: bool,
+
+    /// The URL to look for releases under, instead of the configured one.
+    #[arg(long)]
+    endpoint: Option<String>,
+
+    /// The public key releases are signed with, in hex, instead of the configured one.
+    #[arg(long)]
+    key: Option<String>
+}
+
+/// The latest release, as described by `latest.json` under the endpoint.
+#[derive(Deserialize)]
+struct Manifest {
+    version: String,
+
+    /// The repository format the release writes.
+    #[serde(default)]
+    format: u32,
+
+    /// The builds in the release, by target, like `x86_64-linux`.
+    binaries: HashMap<String, Binary>
+}
+
+#[derive(Deserialize)]
+struct Binary {
+    /// Where to download the build from, which can be relative to the endpoint.
+    url: String,
+
+    /// The SHA-256 hash of the build, in hex.
+    sha256: String,
+
+    /// A signature by the release key of what [`release_statement`] gives
+    /// for this build, in hex. Signing the version and target along with the
+    /// hash stops an older build, or one for another target, being offered
+    /// as this one.
+    signature: String
+}
+
+/// Get what the release key signs for a build: the version and repository
+/// format of the release, the target of the build, and its hash.
+fn release_statement(manifest: &Manifest, target: &str, sha256: &str) -> Vec<u8> {
+    format!(
+        "asc-release:{}:{}:{target}:{}",
+        manifest.version,
+        manifest.format,
+        sha256.trim().to_ascii_lowercase()
+    ).into_bytes()
+}
+
+/// Load the `upgrade` settings from the user's own config file, like
+/// `~/.config/asc/config.toml`. A repository's config is never used,
+/// since anyone could have written it.
+fn load_user_config() -> Result<UpgradeConfig> {
+    let Some(dirs) = BaseDirs::new() else {
+        return Ok(UpgradeConfig::default());
+    };
+
+    let path = dirs.config_dir().join("asc").join("config.toml");
+
+    Ok(Config::load(path)?.upgrade)
+}
+
+/// Download a file, or read it if the URL is a `file://` one.
+pub fn fetch(url: &str) -> Result<Vec<u8>> {
+    if let Some(path) = url.strip_prefix("file://") {
+        return Ok(unwrap!(fs::read(path), "failed to read {url}"));
+    }
+
+    let output = unwrap!(
+        Command::new("curl")
+            .args(["--fail", "--silent", "--show-error", "--location", url])
+            .output(),
+        "failed to run curl to download {url} - is it installed?"
+    );
+
+    if !output.status.success() {
+        bail!("failed to download {url}: {}", String::from_utf8_lossy(&output.stderr).trim());
+    }
+
+    Ok(output.stdout)
+}
+
+fn resolve(endpoint: &str, url: &str) -> String {
+    if url.contains("://") {
+        url.to_string()
+    }
+    else {
+        format!("{}/{url}", endpoint.trim_end_matches('/'))
+    }
+}
+
+/// Split a version like `1.2.3` into numbers that can be compared.
+fn parse_version(raw: &str) -> Vec<u64> {
+    raw.trim_start_matches('v')
+        .split('.')
+        .map(|part| part.parse().unwrap_or(0))
+        .collect()
+}
+
+/// Put a new build in place of the one that is running.
+fn install(bytes: &[u8]) -> Result<()> {
+    let exe = env::current_exe()?;
+
+    let new = exe.with_extension("new");
+
+    unwrap!(fs::write(&new, bytes), "failed to write to {}", new.display());
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        fs::set_permissions(&new, fs::Permissions::from_mode(0o755))?;
+    }
+
+    // Windows can't overwrite a running program, but it can move it out of the way.
+    let old = exe.with_extension("old");
+
+    let _ = fs::remove_file(&old);
+
+    unwrap!(fs::rename(&exe, &old), "failed to replace {}", exe.display());
+
+    if let Err(e) = fs::rename(&new, &exe) {
+        let _ = fs::rename(&old, &exe);
+
+        bail!("failed to replace {}: {e}", exe.display());
+    }
+
+    // This fails on Windows while the old build is still running.
+    let _ = fs::remove_file(&old);
+
+    Ok(())
+}
+
+pub fn parse(args: Args) -> Result<()> {
+    let config = load_user_config()?;
+
+    let endpoint = args.endpoint
+        .or(config.endpoint)
+        .or(option_env!("ASC_RELEASE_ENDPOINT").map(String::from));
+
+    let Some(endpoint) = endpoint else {
+        exit::set(Status::Failure);
+
+        eprintln!("No release endpoint is set. Set 'upgrade.endpoint' in your own config file, or pass '--endpoint'.");
+
+        return Ok(());
+    };
+
+    let raw = fetch(&resolve(&endpoint, "latest.json"))?;
+
+    let manifest: Manifest = unwrap!(
+        serde_json::from_slice(&raw),
+        "the release manifest under {endpoint} is invalid"
+    );
+
+    let current = env!("CARGO_PKG_VERSION");
+
+    if parse_version(&manifest.version) <= parse_version(current) {
+        exit::set(Status::Clean);
+
+        say!("asc {current} is the latest version.");
+
+        return Ok(());
+    }
+
+    println!("asc {} is available (this is {current}).", manifest.version);
+
+    if manifest.format > REPOSITORY_FORMAT {
+        println!("It stores repositories in a newer format, which this version can't read once it has written to them.");
+    }
+
+    if args.check {
+        return Ok(());
+    }
+
+    let target = format!("{}-{}", consts::ARCH, consts::OS);
+
+    let Some(binary) = manifest.binaries.get(&target) else {
+        exit::set(Status::Failure);
+
+        eprintln!("No build of asc {} is published for {target}.", manifest.version);
+
+        return Ok(());
+    };
+
+    let key = args.key
+        .or(config.public_key)
+        .or(option_env!("ASC_RELEASE_KEY").map(String::from));
+
+    let Some(key) = key else {
+        exit::set(Status::Failure);
+
+        eprintln!("No release key is set, so the download can't be checked. Set 'upgrade.public_key' in your own config file, or pass '--key'.");
+
+        return Ok(());
+    };
+
+    let key = unwrap!(
+        PublicKey::from_bytes(&unwrap!(hex::decode(key.trim()), "the release key is not valid hex")),
+        "the release key is not a valid public key"
+    );
+
+    let bytes = fetch(&resolve(&endpoint, &binary.url))?;
+
+    let hash = hash_raw_bytes(&bytes);
+
+    if !hash.full().eq_ignore_ascii_case(binary.sha256.trim()) {
+        bail!("the download of asc {} does not match its published hash.", manifest.version);
+    }
+
+    let signature = Signature::from_bytes(&unwrap!(
+        hex::decode(binary.signature.trim()),
+        "the signature of asc {} is not valid hex", manifest.version
+    ))?;
+
+    if signature.key() != key || !signature.verify(&release_statement(&manifest, &target, &binary.sha256)) {
+        bail!("the download of asc {} is not signed by the release key.", manifest.version);
+    }
+
+    install(&bytes)?;
+
+    say!("Upgraded asc from {current} to {}.", manifest.version);
+
+    Ok(())
+}
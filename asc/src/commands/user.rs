@@ -1,7 +1,10 @@
-use color_eyre::owo_colors::OwoColorize;
+use std::{fs, path::{Path, PathBuf}};
+
 use eyre::Result;
 
-use libasc::{action::Action, repository::Repository};
+use libasc::{action::Action, invite::{Invitation, JoinRequest, load_invitation, remove_invitation, save_invitation}, repository::Repository, unwrap};
+
+use crate::{exit::{self, Status}, output};
 
 #[derive(clap::Subcommand)]
 pub enum Subcommands {
@@ -45,9 +48,49 @@ pub enum Subcommands {
     Rename {
         old: String,
         new: String
+    },
+
+    /// Invite someone to join the repository as a new user.
+    /// This gives a one-time token for them to use with `asc user join`.
+    Invite {
+        username: String,
+
+        /// Write the token to a file instead of printing it.
+        #[arg(short, long)]
+        output: Option<PathBuf>
+    },
+
+    /// Accept an invitation, making a new user with keys that stay on this machine.
+    /// This gives a token to send back to whoever invited you.
+    Join {
+        /// The invitation token, or a file containing it.
+        invitation: String
+    },
+
+    /// Approve a request to join from an invited user, adding their account.
+    /// Push afterwards to add them to the remote.
+    Approve {
+        /// The join request token, or a file containing it.
+        request: String
     }
 }
 
+/// Read a token given on the command line, or from a file if it is a path.
+fn read_token(token: &str) -> Result<String> {
+    let path = Path::new(token);
+
+    if !path.is_file() {
+        return Ok(token.to_string());
+    }
+
+    let content = unwrap!(
+        fs::read_to_string(path),
+        "failed to read token from: {}", path.display()
+    );
+
+    Ok(content.trim().to_string())
+}
+
 pub fn parse(subcommand: Subcommands) -> Result<()> {
     let mut repo = Repository::load()?;
 
@@ -73,7 +116,7 @@ pub fn parse(subcommand: Subcommands) -> Result<()> {
                 let mut line = format!(" * {}", user.name);
 
                 if let Some(current_user) = repo.current_user() && current_user.name == user.name {
-                    line = format!("{}", line.bright_green().bold());
+                    line = output::current(line);
                 }
 
                 println!("{line}");
@@ -83,6 +126,8 @@ pub fn parse(subcommand: Subcommands) -> Result<()> {
         Info { username, show_private_key } => {
             let user = if let Some(name) = username {
                 let Some(user) = repo.users.get_user(name.as_str()) else {
+                    exit::set(Status::Failure);
+
                     eprintln!("No user with name {name:?} found.");
 
                     return Ok(());
@@ -92,6 +137,8 @@ pub fn parse(subcommand: Subcommands) -> Result<()> {
             }
             else {
                 let Some(user) = repo.current_user() else {
+                    exit::set(Status::Failure);
+
                     eprintln!("No valid user set on this repository.");
 
                     return Ok(());
@@ -109,6 +156,12 @@ pub fn parse(subcommand: Subcommands) -> Result<()> {
 
             println!("Name: {name}");
             println!("Public key: {}", user.public_key);
+
+            let groups: Vec<&str> = repo.users.groups().groups_of(&user.public_key).collect();
+
+            if !groups.is_empty() {
+                println!("Groups: {}", groups.join(", "));
+            }
             
             if show_private_key {
                 println!("Private key: {}", match &user.private_key {
@@ -120,6 +173,8 @@ pub fn parse(subcommand: Subcommands) -> Result<()> {
 
         Close { username } => {
             let Some(user) = repo.users.get_user_mut(&username) else {
+                exit::set(Status::Failure);
+
                 eprintln!("No user with name {username:?} found.");
 
                 return Ok(());
@@ -137,6 +192,8 @@ pub fn parse(subcommand: Subcommands) -> Result<()> {
 
         Reopen { username } => {
             let Some(user) = repo.users.get_user_mut(&username) else {
+                exit::set(Status::Failure);
+
                 eprintln!("No user with name {username:?} in this repository.");
 
                 return Ok(());
@@ -169,6 +226,8 @@ pub fn parse(subcommand: Subcommands) -> Result<()> {
 
         Rename { old, new } => {
             let Some(user) = repo.users.get_user_mut(&old) else {
+                exit::set(Status::Failure);
+
                 eprintln!("No user with name {old:?} found.");
 
                 return Ok(());
@@ -186,6 +245,89 @@ pub fn parse(subcommand: Subcommands) -> Result<()> {
 
             println!("Renamed user: {old:?} -> {:?}", user.name);
         }
+
+        Invite { username, output } => {
+            if repo.users.has_user(username.as_str()) {
+                exit::set(Status::Failure);
+
+                eprintln!("User {username:?} already exists.");
+
+                return Ok(());
+            }
+
+            let invitation = Invitation::new(username.clone(), repo.project_code);
+
+            save_invitation(&repo, &invitation)?;
+
+            if let Some(path) = output {
+                unwrap!(
+                    fs::write(&path, invitation.to_string()),
+                    "failed to write invitation to: {}", path.display()
+                );
+
+                println!("Invitation for {username:?} written to {}", path.display());
+            }
+            else {
+                println!("Invitation for {username:?}:");
+                println!("{invitation}");
+            }
+
+            println!("They can accept it with `asc user join`, and you can approve their reply with `asc user approve`.");
+        }
+
+        Join { invitation } => {
+            let invitation: Invitation = read_token(&invitation)?.parse()?;
+
+            if invitation.project_code != repo.project_code {
+                exit::set(Status::Failure);
+
+                eprintln!("This invitation is for a different repository.");
+
+                return Ok(());
+            }
+
+            if invitation.is_expired() {
+                exit::set(Status::Failure);
+
+                eprintln!("This invitation has expired.");
+
+                return Ok(());
+            }
+
+            let user = repo.users.create_user(invitation.name.clone())?;
+
+            let mut key = user.private_key.clone().unwrap();
+
+            let request = invitation.accept(&mut key);
+
+            repo.set_current_user(&invitation.name)?;
+
+            println!("Created user {:?} and switched to it.", invitation.name);
+            println!("Send this back to whoever invited you:");
+            println!("{request}");
+        }
+
+        Approve { request } => {
+            let request: JoinRequest = read_token(&request)?.parse()?;
+
+            let Some(invitation) = load_invitation(&repo, request.code)? else {
+                exit::set(Status::Failure);
+
+                eprintln!("No pending invitation matches this request (it may have been used already).");
+
+                return Ok(());
+            };
+
+            let user = request.verify(&invitation)?;
+
+            let name = user.name.clone();
+
+            repo.users.add_user(user)?;
+
+            remove_invitation(&repo, invitation.code)?;
+
+            println!("Approved {name:?}. Push to add them to the remote.");
+        }
     }
 
     repo.save()?;
@@ -0,0 +1,88 @@
+use std::{sync::mpsc, time::Duration};
+
+use eyre::Result;
+use libasc::{repository::Repository, unwrap, watch::WatchState};
+use notify::{Event, RecursiveMode, Watcher};
+
+#[derive(clap::Args)]
+pub struct Args {
+    /// How often to look over every file anyway, in milliseconds, to catch
+    /// changes the OS didn't report, and trust files that changed recently.
+    #[arg(short, long, default_value_t = 1000)]
+    interval: u64,
+
+    /// Don't list files as they change.
+    #[arg(short, long)]
+    quiet: bool
+}
+
+/// How long to wait for more changes after one is reported,
+/// so a burst of them, like from a build, is handled at once.
+const SETTLE_TIME: Duration = Duration::from_millis(50);
+
+pub fn parse(args: Args) -> Result<()> {
+    let repo = Repository::load()?;
+
+    let mut state = WatchState::load(&repo);
+
+    state.refresh(&repo)?;
+    state.save(&repo)?;
+
+    let (sender, receiver) = mpsc::channel::<notify::Result<Event>>();
+
+    let mut watcher = unwrap!(
+        notify::recommended_watcher(sender),
+        "failed to start watching for changes"
+    );
+
+    unwrap!(
+        watcher.watch(&repo.root_dir, RecursiveMode::Recursive),
+        "failed to watch {}", repo.root_dir.display()
+    );
+
+    // Saving the state is reported like any other change,
+    // but it doesn't need to be looked at again.
+    let state_file = repo.main_dir().join("watch-state");
+
+    let state_files = [state_file.with_extension("tmp"), state_file];
+
+    println!("Watching {} files in {}. Press Ctrl-C to stop.", state.files.len(), repo.root_dir.display());
+
+    let interval = Duration::from_millis(args.interval.max(1));
+
+    loop {
+        match receiver.recv_timeout(interval) {
+            Ok(Ok(event)) if event.paths.iter().all(|path| state_files.contains(path)) => continue,
+
+            // Anything else might be a change, and the files are
+            // looked over in full if it can't be told what it was.
+            Ok(_) => {
+                while receiver.recv_timeout(SETTLE_TIME).is_ok() {}
+            }
+
+            Err(mpsc::RecvTimeoutError::Timeout) => {}
+
+            Err(mpsc::RecvTimeoutError::Disconnected) => break
+        }
+
+        // Reload every time, so that files staged or
+        // unstaged by other commands are picked up.
+        let repo = Repository::load()?;
+
+        let changed = state.refresh(&repo)?;
+
+        if changed.is_empty() {
+            continue;
+        }
+
+        state.save(&repo)?;
+
+        if !args.quiet {
+            for path in changed {
+                println!(" * {path} changed");
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,88 @@
+use std::sync::Arc;
+
+use eyre::Result;
+use libasc::{repository::Repository, sync::client::Client};
+use tokio::sync::RwLock;
+
+use crate::{exit::{self, Status}, i18n::t};
+
+#[derive(clap::Args)]
+pub struct Args {
+    /// Ask a remote which user it takes you to be,
+    /// and what you can do there.
+    #[arg(short, long)]
+    remote: Option<String>
+}
+
+fn yes_no(value: bool) -> String {
+    if value { t!("yes") } else { t!("no") }
+}
+
+#[tokio::main]
+pub async fn parse(args: Args) -> Result<()> {
+    let repo = Repository::load()?;
+
+    let Some(user) = repo.current_user() else {
+        exit::set(Status::Failure);
+
+        eprintln!("{}", t!("user-none-set"));
+
+        return Ok(());
+    };
+
+    let Some(name) = args.remote else {
+        println!("{} ({})", user.name, user.public_key);
+
+        return Ok(());
+    };
+
+    let Some(remote) = repo.remotes.get(&name).cloned() else {
+        exit::set(Status::Failure);
+
+        eprintln!("{}", t!("remote-not-found", name = name));
+
+        return Ok(());
+    };
+
+    let key = user.public_key;
+
+    let mut client = Client::connect(remote.clone()).await?;
+
+    let identity = client.make_whoami(Arc::new(RwLock::new(repo))).await?;
+
+    println!("{}", t!("whoami-remote", name = name, url = remote));
+    println!("{}", t!("whoami-key", key = key));
+
+    match &identity.user {
+        Some(user) if identity.closed => println!("{}", t!("whoami-user-closed", user = user)),
+        Some(user) => println!("{}", t!("whoami-user", user = user)),
+        None => println!("{}", t!("whoami-user-none"))
+    }
+
+    if !identity.groups.is_empty() {
+        println!("{}", t!("whoami-groups", groups = identity.groups.join(", ")));
+    }
+
+    match identity.same_project {
+        Some(true) => println!("{}", t!("whoami-project-same")),
+        Some(false) => println!("{}", t!("whoami-project-different")),
+        None => println!("{}", t!("whoami-project-none"))
+    }
+
+    println!();
+
+    println!("{}", t!("whoami-can-sync", answer = yes_no(identity.can_sync())));
+    println!("{}", t!("whoami-can-create", answer = yes_no(identity.can_create)));
+
+    if !identity.repositories.is_empty() {
+        println!();
+
+        println!("{}", t!("whoami-repositories"));
+
+        for path in &identity.repositories {
+            println!(" * {}", path.display());
+        }
+    }
+
+    Ok(())
+}
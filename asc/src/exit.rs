@@ -0,0 +1,91 @@
+//! Exit codes, so scripts can tell what happened without reading the output.
+//!
+//! Each code is listed in [`HELP`], which is shown by `asc --help`. They are
+//! kept stable, so new codes can be added, but existing ones aren't changed.
+//!
+//! Commands that stop early without an error, like when there is nothing to
+//! commit, [`set`] the code themselves. Errors from `libasc` that have their
+//! own code are picked out of the error report by their type.
+
+use std::{process::ExitCode, sync::atomic::{AtomicU8, Ordering}};
+
+use eyre::{Report, Result};
+use libasc::{repository::{CorruptObject, UnsavedChanges}, sync::utils::LoginRefused};
+
+/// How a command finished, given as its exit code.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Status {
+    Success = 0,
+    Failure = 1,
+    Usage = 2,
+    Clean = 3,
+    Changes = 4,
+    Conflicts = 5,
+    Denied = 6,
+    Corrupt = 7
+}
+
+/// The exit codes, as listed in `asc --help`.
+pub static HELP: &str = "\
+Exit codes:
+  0  The command did what it was asked.
+  1  Something went wrong that doesn't have its own code.
+  2  The arguments given were wrong.
+  3  There was nothing to do, like committing with no changes.
+  4  There are unsaved changes, which stopped the command or were found by `asc changes --exit-code`.
+  5  A merge or patch left conflicts to resolve.
+  6  Something was refused: a remote didn't let the user log in, a protected tag would have been changed, or a patch's signature doesn't match its changes.
+  7  The repository has missing or corrupted objects.";
+
+static STATUS: AtomicU8 = AtomicU8::new(Status::Success as u8);
+
+/// Set how the command finished, for commands that stop early
+/// without an error, like when there is nothing to commit.
+pub fn set(status: Status) {
+    STATUS.store(status as u8, Ordering::Relaxed);
+}
+
+/// Set the exit code directly, for passing on the
+/// code of another program asc has run.
+pub fn set_code(code: u8) {
+    STATUS.store(code, Ordering::Relaxed);
+}
+
+/// Work out how a command finished from the error it stopped with,
+/// unless the command already set a status before failing.
+fn from_error(report: &Report) -> u8 {
+    for cause in report.chain() {
+        if cause.is::<UnsavedChanges>() {
+            return Status::Changes as u8;
+        }
+
+        if cause.is::<LoginRefused>() {
+            return Status::Denied as u8;
+        }
+
+        if cause.is::<CorruptObject>() {
+            return Status::Corrupt as u8;
+        }
+    }
+
+    match STATUS.load(Ordering::Relaxed) {
+        0 => Status::Failure as u8,
+        code => code
+    }
+}
+
+/// Report how a command finished, printing its error if it failed.
+pub fn finish(result: Result<()>) -> ExitCode {
+    let code = match result {
+        Ok(()) => STATUS.load(Ordering::Relaxed),
+
+        Err(report) => {
+            eprintln!("Error: {report:?}");
+
+            from_error(&report)
+        }
+    };
+
+    ExitCode::from(code)
+}
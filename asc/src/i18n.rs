@@ -0,0 +1,207 @@
+//! Translations of the messages shown to users.
+//!
+//! Messages are kept in catalogs under `asc/locales`, written in a
+//! small subset of the Fluent syntax:
+//!
+//! ```text
+//! # A comment.
+//! message-id = Some text with a { $variable }.
+//!     Indented lines carry on the message above.
+//! ```
+//!
+//! Output meant for other programs to read, like lists of
+//! names or hashes, is never translated.
+
+use std::{collections::HashMap, env, sync::OnceLock};
+
+use libasc::config::Config;
+
+/// The language every message has to exist in, used when a
+/// message is missing from the chosen language's catalog.
+static DEFAULT_LANGUAGE: &str = "en";
+
+static CATALOGS: &[(&str, &str)] = &[
+    ("en", include_str!("../locales/en.ftl")),
+    ("fr", include_str!("../locales/fr.ftl"))
+];
+
+struct Catalog {
+    messages: HashMap<&'static str, String>
+}
+
+impl Catalog {
+    fn parse(source: &'static str) -> Catalog {
+        let mut messages: HashMap<&str, String> = HashMap::new();
+
+        let mut current: Option<&str> = None;
+
+        for line in source.lines() {
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            if line.starts_with(char::is_whitespace) {
+                if let Some(message) = current.and_then(|id| messages.get_mut(id)) {
+                    message.push('\n');
+                    message.push_str(line.trim());
+                }
+
+                continue;
+            }
+
+            let Some((id, value)) = line.split_once('=') else {
+                current = None;
+
+                continue;
+            };
+
+            let id = id.trim();
+
+            messages.insert(id, value.trim().to_string());
+
+            current = Some(id);
+        }
+
+        Catalog { messages }
+    }
+
+    fn load(language: &str) -> Option<Catalog> {
+        CATALOGS
+            .iter()
+            .find(|(name, _)| *name == language)
+            .map(|(_, source)| Catalog::parse(source))
+    }
+}
+
+struct Translator {
+    chosen: Option<Catalog>,
+    fallback: Catalog
+}
+
+/// Turn a locale like `fr_FR.UTF-8` into a tag like `fr-fr`.
+fn normalise_locale(raw: &str) -> Option<String> {
+    let tag = raw
+        .split(['.', '@'])
+        .next()?
+        .replace('_', "-")
+        .to_lowercase();
+
+    if tag.is_empty() || tag == "c" || tag == "posix" {
+        return None;
+    }
+
+    Some(tag)
+}
+
+/// Find the languages the user asked for, in order of preference.
+fn requested_languages() -> Vec<String> {
+    let configured = Config::load_current()
+        .ok()
+        .and_then(|config| config.ui.language);
+
+    let mut requested = vec![];
+
+    requested.extend(env::var("ASC_LANG").ok());
+    requested.extend(configured);
+
+    for var in ["LC_ALL", "LC_MESSAGES", "LANG"] {
+        requested.extend(env::var(var).ok());
+    }
+
+    requested
+        .iter()
+        .filter_map(|raw| normalise_locale(raw))
+        .collect()
+}
+
+/// Pick the first language with a catalog, trying
+/// `pt-br` before `pt` for each language asked for.
+fn choose_language() -> &'static str {
+    for tag in requested_languages() {
+        let primary = tag.split('-').next().unwrap_or_default();
+
+        for candidate in [tag.as_str(), primary] {
+            if let Some((name, _)) = CATALOGS.iter().find(|(name, _)| name.eq_ignore_ascii_case(candidate)) {
+                return name;
+            }
+        }
+    }
+
+    DEFAULT_LANGUAGE
+}
+
+fn translator() -> &'static Translator {
+    static TRANSLATOR: OnceLock<Translator> = OnceLock::new();
+
+    TRANSLATOR.get_or_init(|| {
+        let language = choose_language();
+
+        Translator {
+            chosen: (language != DEFAULT_LANGUAGE).then(|| Catalog::load(language)).flatten(),
+            fallback: Catalog::load(DEFAULT_LANGUAGE).unwrap()
+        }
+    })
+}
+
+/// Fill in the `{ $name }` placeholders in a message.
+fn fill(template: &str, args: &[(&str, String)]) -> String {
+    let mut out = String::with_capacity(template.len());
+
+    let mut rest = template;
+
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]);
+
+        let Some(end) = rest[start..].find('}') else {
+            break;
+        };
+
+        let inner = rest[start + 1 .. start + end].trim();
+
+        let value = inner
+            .strip_prefix('$')
+            .and_then(|name| args.iter().find(|(arg, _)| *arg == name));
+
+        match value {
+            Some((_, value)) => out.push_str(value),
+            None => out.push_str(&rest[start ..= start + end])
+        }
+
+        rest = &rest[start + end + 1 ..];
+    }
+
+    out.push_str(rest);
+
+    out
+}
+
+/// Look up a message in the user's language, filling in its arguments.
+///
+/// If no catalog has the message, its ID is returned so
+/// that a missing translation is easy to spot.
+pub fn message(id: &str, args: &[(&str, String)]) -> String {
+    let translator = translator();
+
+    let template = translator.chosen
+        .as_ref()
+        .and_then(|catalog| catalog.messages.get(id))
+        .or_else(|| translator.fallback.messages.get(id));
+
+    match template {
+        Some(template) => fill(template, args),
+        None => id.to_string()
+    }
+}
+
+/// Get a translated message by its ID, with any arguments given as `name = value`.
+///
+/// ```ignore
+/// eprintln!("{}", t!("branch-not-found", name = name));
+/// ```
+macro_rules! t {
+    ($id:literal $(, $name:ident = $value:expr)* $(,)?) => {
+        $crate::i18n::message($id, &[$((stringify!($name), $value.to_string())),*])
+    };
+}
+
+pub(crate) use t;
@@ -1,8 +1,11 @@
 mod commands;
+mod exit;
+mod i18n;
+mod output;
+use std::process::ExitCode;
+
 use commands::run;
 
-fn main() -> eyre::Result<()> {
-    color_eyre::install()?;
-    
-    run()
+fn main() -> ExitCode {
+    exit::finish(run())
 }
@@ -0,0 +1,284 @@
+//! How output is shown to users.
+//!
+//! Plain mode is turned on with `--plain` or the `ui.plain` setting. In
+//! plain mode nothing is coloured, so anything a colour would have shown,
+//! like which branch is the current one, is written out in words instead.
+//! Commands also don't stop to ask questions, and explain what they did
+//! instead, so the output can be followed line by line with a screen reader.
+//!
+//! Stats, turned on with `--stats` or the `ui.stats` setting, are
+//! shown after commands that can take a while, to say what they cost.
+//!
+//! Commands that list things can print them as JSON with `--format json`,
+//! for scripts and editors. The fields printed are kept stable, so new
+//! fields can be added, but existing ones aren't renamed or removed.
+//!
+//! With `--dry-run`, the commands that support it only say what they would
+//! change, which is printed once they finish.
+//!
+//! With `--quiet`, commands only print errors and the results asked for,
+//! not what they are doing. With `--verbose`, they print more detail.
+
+use std::{fmt::Display, sync::OnceLock, time::Duration};
+
+use clap::ValueEnum;
+use color_eyre::{config::{HookBuilder, Theme}, owo_colors::OwoColorize};
+use eyre::Result;
+use libasc::{change::FileChange, config::Config, guard::Change, refs::Head, stats};
+use relative_path::RelativePath;
+use serde::Serialize;
+use size::{Base, Size};
+
+use crate::i18n::t;
+
+static PLAIN: OnceLock<bool> = OnceLock::new();
+
+static STATS: OnceLock<bool> = OnceLock::new();
+
+static FORMAT: OnceLock<Format> = OnceLock::new();
+
+static VERBOSITY: OnceLock<Verbosity> = OnceLock::new();
+
+/// How much commands say about what they are doing.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Verbosity {
+    Quiet,
+
+    #[default]
+    Normal,
+
+    Verbose
+}
+
+/// How commands print what they found.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    /// Text for people to read.
+    #[default]
+    Text,
+
+    /// JSON for other programs to read.
+    Json
+}
+
+/// Decide whether to use plain mode and show stats, and set up error reports to match.
+///
+/// The arguments are whether `--plain` and `--stats` were passed,
+/// the `--format` given, and the verbosity from `--quiet` or `--verbose`.
+pub fn init(plain: bool, stats: bool, format: Format, verbosity: Verbosity) -> Result<()> {
+    let _ = FORMAT.set(format);
+
+    let _ = VERBOSITY.set(verbosity);
+
+    let config = Config::load_current().map(|config| config.ui).unwrap_or_default();
+
+    let plain = plain || config.plain;
+
+    let _ = PLAIN.set(plain);
+
+    let _ = STATS.set(stats || config.stats);
+
+    if plain {
+        HookBuilder::new().theme(Theme::new()).install()
+    }
+    else {
+        color_eyre::install()
+    }
+}
+
+/// Check if output should be plain.
+pub fn is_plain() -> bool {
+    PLAIN.get().copied().unwrap_or_default()
+}
+
+/// Check if commands should leave out what they are doing.
+pub fn is_quiet() -> bool {
+    VERBOSITY.get().copied().unwrap_or_default() == Verbosity::Quiet
+}
+
+/// Check if commands should print more detail.
+pub fn is_verbose() -> bool {
+    VERBOSITY.get().copied().unwrap_or_default() == Verbosity::Verbose
+}
+
+/// Print a line saying what a command is doing, unless `--quiet` was passed.
+macro_rules! say {
+    ($($arg:tt)*) => {
+        if !$crate::output::is_quiet() {
+            println!($($arg)*);
+        }
+    };
+}
+
+pub(crate) use say;
+
+/// Check if results should be printed as JSON.
+pub fn is_json() -> bool {
+    FORMAT.get().copied().unwrap_or_default() == Format::Json
+}
+
+/// Print a value as JSON, on one line.
+pub fn print_json(value: &impl Serialize) -> Result<()> {
+    println!("{}", serde_json::to_string(value)?);
+
+    Ok(())
+}
+
+/// A file in a list printed as JSON, and how it has changed.
+#[derive(Serialize)]
+pub struct FileEntry {
+    pub path: String,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub change: Option<&'static str>,
+
+    /// The unified diff of the file, for edited files.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub diff: Option<String>
+}
+
+impl FileEntry {
+    /// Describe a file that hasn't been compared with anything.
+    pub fn new(path: &RelativePath) -> FileEntry {
+        FileEntry {
+            path: path.to_string(),
+            change: None,
+            diff: None
+        }
+    }
+}
+
+impl<P: AsRef<RelativePath>> From<&FileChange<P>> for FileEntry {
+    fn from(change: &FileChange<P>) -> FileEntry {
+        FileEntry {
+            path: change.path().to_string(),
+            change: Some(change.kind()),
+            diff: None
+        }
+    }
+}
+
+/// A branch or tag in a list printed as JSON.
+#[derive(Serialize)]
+pub struct RefEntry<'a> {
+    pub name: &'a str,
+    pub hash: String,
+
+    /// Whether the head is on this branch. This is left out for tags.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub current: Option<bool>
+}
+
+/// Check if commands should say how long they took with [`print_stats`].
+pub fn wants_stats() -> bool {
+    STATS.get().copied().unwrap_or_default()
+}
+
+/// Format a number of bytes for people to read, like `1.2 MB`.
+pub(crate) fn format_size(n: u64) -> String {
+    Size::from_bytes(n)
+        .format()
+        .with_base(Base::Base10)
+        .to_string()
+}
+
+/// Show how long a command took, and the work it did to get there.
+pub fn print_stats(elapsed: Duration) {
+    let stats = stats::totals();
+
+    let mut details = vec![
+        t!("stats-objects", read = stats.objects_read, written = stats.objects_written)
+    ];
+
+    if stats.bytes_sent > 0 || stats.bytes_received > 0 {
+        details.push(t!(
+            "stats-transfer",
+            sent = format_size(stats.bytes_sent),
+            received = format_size(stats.bytes_received)
+        ));
+    }
+
+    if let Some(rate) = stats.cache_hit_rate() {
+        details.push(t!(
+            "stats-cache",
+            hits = stats.cache_hits,
+            lookups = stats.cache_hits + stats.cache_misses,
+            rate = format!("{:.0}", rate * 100.0)
+        ));
+    }
+
+    eprintln!("{}", t!("stats-took", time = format!("{elapsed:.2?}"), details = details.join(", ")));
+}
+
+/// Say what a command run with `--dry-run` would have changed.
+pub fn print_dry_run(changes: Vec<Change>) {
+    if changes.is_empty() {
+        println!("{}", t!("dry-run-nothing"));
+
+        return;
+    }
+
+    println!("{}", t!("dry-run-header"));
+
+    for change in changes {
+        let line = match change {
+            Change::Write(path) => t!("dry-run-write", path = path.as_str()),
+
+            Change::Remove(path) => t!("dry-run-remove", path = path.as_str()),
+
+            Change::DeleteObject(hash) => t!("dry-run-delete-object", hash = hash.full()),
+
+            Change::MoveRef { r, old: None, new: Some(new) } => {
+                t!("dry-run-create-ref", name = r.to_string(), new = new.to_string())
+            },
+
+            Change::MoveRef { r, old: Some(old), new: None } => {
+                t!("dry-run-delete-ref", name = r.to_string(), old = old.to_string())
+            },
+
+            Change::MoveRef { r, old, new } => t!(
+                "dry-run-move-ref",
+                name = r.to_string(),
+                old = old.map(|hash| hash.to_string()).unwrap_or_default(),
+                new = new.map(|hash| hash.to_string()).unwrap_or_default()
+            ),
+
+            Change::MoveHead { head: Head::Branch(name), .. } => t!("dry-run-move-head", head = name),
+
+            Change::MoveHead { hash, .. } => t!("dry-run-move-head", head = hash.to_string())
+        };
+
+        println!(" * {line}");
+    }
+}
+
+/// Make a line stand out, which is only done with colour.
+pub fn highlight(text: impl Display) -> String {
+    if is_plain() {
+        text.to_string()
+    }
+    else {
+        text.bright_green().bold().to_string()
+    }
+}
+
+/// Mark a line as the current item in a list, like the branch
+/// the head is on, which is said outright in plain mode.
+pub fn current(text: impl Display) -> String {
+    if is_plain() {
+        format!("{text} ({})", t!("current"))
+    }
+    else {
+        highlight(text)
+    }
+}
+
+/// Mark a line as an action that has been undone.
+pub fn undone(text: impl Display) -> String {
+    if is_plain() {
+        format!("{text} ({})", t!("undone"))
+    }
+    else {
+        text.dimmed().to_string()
+    }
+}
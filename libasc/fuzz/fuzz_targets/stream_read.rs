@@ -0,0 +1,89 @@
+//! Feed arbitrary bytes to the reading end of a connection.
+//!
+//! The first byte picks how the connection is set up: whether a hello is
+//! read first to agree on checked frames, how large a frame can be, and how
+//! large a message can be. The rest is read as messages until it runs out,
+//! which has to fail cleanly, however the frames and checks are mangled.
+//!
+//! Run with `cargo fuzz run stream_read` from `libasc`.
+
+#![no_main]
+
+use std::io;
+
+use async_trait::async_trait;
+use libasc::{hash::ObjectHash, sync::{server::Method, stream::{ConnectionLimits, Framing, Stream, negotiate_as_server}}};
+use libfuzzer_sys::fuzz_target;
+use serde::de::IgnoredAny;
+
+/// A connection that reads from a fixed set of bytes, and drops what is written.
+struct SliceStream<'a> {
+    data: &'a [u8],
+    framing: Framing
+}
+
+#[async_trait]
+impl Stream for SliceStream<'_> {
+    async fn raw_read(&mut self, n: usize) -> io::Result<Vec<u8>> {
+        if n > self.data.len() {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+
+        let (read, rest) = self.data.split_at(n);
+
+        self.data = rest;
+
+        Ok(read.to_vec())
+    }
+
+    async fn raw_write(&mut self, _: &[u8]) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn framing(&mut self) -> &mut Framing {
+        &mut self.framing
+    }
+
+    async fn close(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fuzz_target!(|data: &[u8]| {
+    let Some((&setup, data)) = data.split_first() else {
+        return;
+    };
+
+    let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+
+    runtime.block_on(async {
+        let mut stream = SliceStream { data, framing: Framing::default() };
+
+        // Small limits, so messages split over frames and running
+        // over the message limit are both reached quickly.
+        stream.framing().set_limits(ConnectionLimits {
+            max_message_size: Some(1 << (setup & 0x0f)),
+            ..Default::default()
+        });
+
+        if setup & 0x10 != 0 {
+            let max_frame_size = 1 + u64::from(setup >> 5) * 16;
+
+            if negotiate_as_server(&mut stream, max_frame_size).await.is_err() {
+                return;
+            }
+        }
+
+        for i in 0.. {
+            let result = match i % 3 {
+                0 => stream.receive::<Method>().await.map(|_| ()),
+                1 => stream.receive::<Vec<ObjectHash>>().await.map(|_| ()),
+                _ => stream.receive::<IgnoredAny>().await.map(|_| ())
+            };
+
+            if result.is_err() {
+                break;
+            }
+        }
+    });
+});
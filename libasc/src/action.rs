@@ -2,7 +2,7 @@ use derive_more::Display;
 
 use serde::{Deserialize, Serialize};
 
-use crate::{hash::ObjectHash, key::PublicKey};
+use crate::{hash::ObjectHash, key::PublicKey, pin::Pin};
 
 /// Represents an action made on the repository.
 /// 
@@ -40,6 +40,20 @@ pub enum Action {
         after: ObjectHash
     },
 
+    // Snapshots
+    #[display("Created snapshot {hash} on {}", branch.as_deref().unwrap_or("a detached head"))]
+    CreateSnapshot {
+        hash: ObjectHash,
+        parent: ObjectHash,
+        branch: Option<String>
+    },
+    #[display("Discarded snapshot {hash}, going back to {parent}")]
+    DiscardSnapshot {
+        hash: ObjectHash,
+        parent: ObjectHash,
+        branch: Option<String>
+    },
+
     // Tags
     #[display("Added tag {name:?} at {hash}")]
     CreateTag {
@@ -74,6 +88,16 @@ pub enum Action {
         hash: ObjectHash
     },
 
+    // Pins
+    #[display("Pinned {}", pin.hash)]
+    CreatePin {
+        pin: Pin
+    },
+    #[display("Unpinned {}", pin.hash)]
+    RemovePin {
+        pin: Pin
+    },
+
     // Users
     #[display("Opened account {name:?} (key: {})", &id.to_string()[..8])]
     OpenAccount {
@@ -0,0 +1,69 @@
+//! When snapshots were made and by whom, for `asc stats --activity`.
+
+use std::collections::{BTreeMap, HashSet};
+
+use chrono::{Datelike, TimeZone, Timelike};
+use eyre::Result;
+use serde::Serialize;
+
+use crate::{hash::ObjectHash, repository::Repository};
+
+/// How many snapshots were made in each period, found by [`collect`].
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct Activity {
+    /// Snapshots counted.
+    pub snapshots: usize,
+
+    /// Snapshots made in each hour of each day of the week,
+    /// indexed by the day (Monday first) and then the hour.
+    pub by_hour: [[usize; 24]; 7],
+
+    /// Snapshots made in each month, keyed like `2025-01`.
+    pub by_month: BTreeMap<String, usize>,
+
+    /// Snapshots made by each author, keyed by their name, or by
+    /// their public key if they don't have an account any more.
+    pub by_author: BTreeMap<String, usize>
+}
+
+/// Get the snapshots from `tip` back through its history, stopping at
+/// `base` and everything before it, if given. Every parent of a merge is
+/// followed, so the snapshots on branches merged in are included too.
+pub fn range(repo: &Repository, tip: ObjectHash, base: Option<ObjectHash>) -> Result<HashSet<ObjectHash>> {
+    let excluded = match base {
+        Some(base) => repo.history.reachable([base], |_| false)?,
+        None => HashSet::new()
+    };
+
+    repo.history.reachable([tip], |hash| excluded.contains(&hash))
+}
+
+/// Count when the snapshots in `snapshots` were made, in the time zone `tz`.
+pub fn collect<Tz: TimeZone>(repo: &Repository, snapshots: &HashSet<ObjectHash>, tz: &Tz) -> Result<Activity> {
+    let mut activity = Activity::default();
+
+    for &hash in snapshots {
+        let snapshot = repo.fetch_snapshot(hash)?;
+
+        let time = snapshot.timestamp.with_timezone(tz);
+
+        activity.snapshots += 1;
+
+        let day = time.weekday().num_days_from_monday() as usize;
+
+        activity.by_hour[day][time.hour() as usize] += 1;
+
+        let month = format!("{:04}-{:02}", time.year(), time.month());
+
+        *activity.by_month.entry(month).or_default() += 1;
+
+        let author = match repo.users.get_user(&snapshot.author) {
+            Some(user) => user.name.clone(),
+            None => snapshot.author.to_string()
+        };
+
+        *activity.by_author.entry(author).or_default() += 1;
+    }
+
+    Ok(activity)
+}
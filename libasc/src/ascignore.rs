@@ -0,0 +1,222 @@
+use std::{collections::HashMap, fs, path::{Path, PathBuf}, sync::{Arc, Mutex}};
+
+use eyre::{Result, bail};
+use ignore::{Match, gitignore::{Gitignore, GitignoreBuilder, Glob}};
+
+use crate::unwrap;
+
+/// The name of the files that rules are read from.
+pub static IGNORE_FILE_NAME: &str = ".ascignore";
+
+/// A rule from an `.ascignore` file that matched a path.
+#[derive(Clone, Debug)]
+pub struct IgnoreRule {
+    /// The `.ascignore` file the rule is from.
+    pub source: PathBuf,
+
+    /// The line the rule is on, starting from 1.
+    pub line: Option<usize>,
+
+    /// The rule as it was written.
+    pub pattern: String,
+
+    /// Whether the rule re-includes paths (starts with `!`)
+    /// rather than ignoring them.
+    pub is_negation: bool
+}
+
+impl IgnoreRule {
+    fn from_glob(glob: &Glob) -> IgnoreRule {
+        let source = glob.from()
+            .map(Path::to_path_buf)
+            .unwrap_or_default();
+
+        let line = fs::read_to_string(&source)
+            .ok()
+            .and_then(|text| {
+                text.lines()
+                    .position(|line| line.trim_end() == glob.original())
+                    .map(|index| index + 1)
+            });
+
+        IgnoreRule {
+            source,
+            line,
+            pattern: glob.original().to_string(),
+            is_negation: glob.is_whitelist()
+        }
+    }
+}
+
+/// The rules from every `.ascignore` file in a repository.
+/// 
+/// Like Git, rules in deeper files take priority over rules in
+/// shallower files, and later rules in a file take priority over
+/// earlier ones. A path can't be re-included if one of its parent
+/// directories is ignored.
+///
+/// Only the file at the root is read up front. The others are read the
+/// first time a path under their directory is checked, so walking the
+/// working directory only reads the files in directories it goes into.
+#[derive(Debug)]
+pub struct IgnoreRules {
+    root: PathBuf,
+
+    /// The rules read so far, by the directory they are for,
+    /// or `None` for directories without any.
+    matchers: Mutex<HashMap<PathBuf, Option<Arc<Gitignore>>>>
+}
+
+impl Clone for IgnoreRules {
+    fn clone(&self) -> IgnoreRules {
+        IgnoreRules {
+            root: self.root.clone(),
+            matchers: Mutex::new(self.matchers.lock().unwrap().clone())
+        }
+    }
+}
+
+/// Read the rules in `dir`, if it has an `.ascignore` file.
+fn read_matcher(dir: &Path) -> Result<Option<Gitignore>> {
+    let file = dir.join(IGNORE_FILE_NAME);
+
+    if !file.is_file() {
+        return Ok(None);
+    }
+
+    let mut builder = GitignoreBuilder::new(dir);
+
+    if let Some(e) = builder.add(&file) {
+        bail!("failed to read ignore rules from {}: {e}", file.display());
+    }
+
+    let matcher = unwrap!(
+        builder.build(),
+        "failed to build ignore matcher for {}", file.display()
+    );
+
+    Ok(Some(matcher))
+}
+
+impl IgnoreRules {
+    /// Read the `.ascignore` file at `root`, ready to read
+    /// the ones under it as paths under them are checked.
+    pub fn load(root: impl AsRef<Path>) -> Result<IgnoreRules> {
+        let root = root.as_ref().to_path_buf();
+
+        let matcher = read_matcher(&root)?.map(Arc::new);
+
+        Ok(IgnoreRules {
+            matchers: Mutex::new(HashMap::from([(root.clone(), matcher)])),
+            root
+        })
+    }
+
+    /// Get the rules for `dir`, reading them if they haven't been yet.
+    ///
+    /// Nested repositories have rules of their own, which don't apply here.
+    /// A file that can't be read is warned about, and treated as empty.
+    fn matcher_for(&self, dir: &Path) -> Option<Arc<Gitignore>> {
+        if let Some(matcher) = self.matchers.lock().unwrap().get(dir) {
+            return matcher.clone();
+        }
+
+        let matcher = if dir.join(".asc").is_dir() {
+            None
+        }
+        else {
+            match read_matcher(dir) {
+                Ok(matcher) => matcher.map(Arc::new),
+                Err(e) => {
+                    eprintln!("warning: {e}");
+
+                    None
+                }
+            }
+        };
+
+        self.matchers.lock().unwrap().insert(dir.to_path_buf(), matcher.clone());
+
+        matcher
+    }
+
+    /// Find the `.ascignore` files that have been read so far.
+    pub fn files(&self) -> Vec<PathBuf> {
+        let mut dirs: Vec<PathBuf> = self.matchers
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, matcher)| matcher.is_some())
+            .map(|(dir, _)| dir.join(IGNORE_FILE_NAME))
+            .collect();
+
+        dirs.sort();
+
+        dirs
+    }
+
+    /// Match a single path against the rules, without considering its parents.
+    fn match_one(&self, path: &Path, is_dir: bool) -> Option<IgnoreRule> {
+        // The deepest directories come first.
+        let dirs = path
+            .ancestors()
+            .skip(1)
+            .take_while(|dir| dir.starts_with(&self.root));
+
+        for dir in dirs {
+            let Some(matcher) = self.matcher_for(dir) else {
+                continue;
+            };
+
+            match matcher.matched(path, is_dir) {
+                Match::None => continue,
+                Match::Ignore(glob) | Match::Whitelist(glob) => {
+                    return Some(IgnoreRule::from_glob(glob));
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Find the rule that decides whether a path is ignored, if any.
+    /// 
+    /// If a parent directory of the path is ignored, the rule
+    /// that ignored the parent directory is returned.
+    pub fn explain(&self, path: impl AsRef<Path>) -> Option<IgnoreRule> {
+        let path = path.as_ref();
+
+        let relative = path.strip_prefix(&self.root).unwrap_or(path);
+
+        let mut current = self.root.clone();
+
+        let mut components = relative.components().peekable();
+
+        while let Some(component) = components.next() {
+            current.push(component);
+
+            let is_last = components.peek().is_none();
+
+            // A symbolic link to a directory is tracked as a file,
+            // so it isn't matched by rules for directories.
+            let is_dir = !is_last || current.symlink_metadata().is_ok_and(|meta| meta.is_dir());
+
+            let rule = self.match_one(&current, is_dir);
+
+            if is_last {
+                return rule;
+            }
+
+            if let Some(rule) = rule && !rule.is_negation {
+                return Some(rule);
+            }
+        }
+
+        None
+    }
+
+    /// Check if a path is ignored by the rules.
+    pub fn is_ignored(&self, path: impl AsRef<Path>) -> bool {
+        self.explain(path).is_some_and(|rule| !rule.is_negation)
+    }
+}
@@ -0,0 +1,255 @@
+use std::{collections::HashMap, fs, path::PathBuf, rc::Rc};
+
+use eyre::{bail, Result};
+use relative_path::RelativePath;
+use serde::{Deserialize, Serialize};
+use similar::{DiffTag, TextDiff};
+
+use crate::{hash::ObjectHash, repository::Repository, stats, unwrap, utils::{hash_raw_bytes, load_as_msgpack, save_as_msgpack}};
+
+/// A line of a file, and the snapshot it was last changed in.
+#[derive(Clone, Debug)]
+pub struct BlameLine {
+    pub origin: ObjectHash,
+    pub content: String
+}
+
+/// The snapshot each line of a file comes from, stored as runs
+/// of lines from the same snapshot to keep the cache small.
+#[derive(Default, Deserialize, Serialize)]
+struct Attribution {
+    runs: Vec<(ObjectHash, usize)>
+}
+
+impl Attribution {
+    fn from_origins(origins: &[ObjectHash]) -> Attribution {
+        let mut runs: Vec<(ObjectHash, usize)> = vec![];
+
+        for &origin in origins {
+            match runs.last_mut() {
+                Some((last, count)) if *last == origin => *count += 1,
+                _ => runs.push((origin, 1))
+            }
+        }
+
+        Attribution { runs }
+    }
+
+    fn origins(&self) -> Vec<ObjectHash> {
+        self.runs
+            .iter()
+            .flat_map(|&(origin, count)| std::iter::repeat_n(origin, count))
+            .collect()
+    }
+}
+
+fn cache_path(repo: &Repository, snapshot: ObjectHash, path: &RelativePath) -> PathBuf {
+    let mut key = snapshot.as_bytes().to_vec();
+
+    key.extend(path.as_str().as_bytes());
+
+    repo.cache_dir()
+        .join("blame")
+        .join(hash_raw_bytes(key).full())
+}
+
+fn load_cached(repo: &Repository, snapshot: ObjectHash, path: &RelativePath) -> Option<Vec<ObjectHash>> {
+    let cache_path = cache_path(repo, snapshot, path);
+
+    let origins = cache_path
+        .exists()
+        .then(|| load_as_msgpack::<Attribution>(cache_path).ok())
+        .flatten()
+        .map(|attribution| attribution.origins());
+
+    stats::record_lookup(origins.is_some());
+
+    origins
+}
+
+/// The cache only saves time, so failing to write to it isn't an error.
+fn save_cached(repo: &Repository, snapshot: ObjectHash, path: &RelativePath, origins: &[ObjectHash]) {
+    let cache_path = cache_path(repo, snapshot, path);
+
+    if let Some(dir) = cache_path.parent()
+        && fs::create_dir_all(dir).is_ok()
+    {
+        let _ = save_as_msgpack(&Attribution::from_origins(origins), cache_path);
+    }
+}
+
+/// A snapshot in the part of the history that has the file.
+struct Node {
+    content: ObjectHash,
+
+    /// Parents that also have the file.
+    parents: Vec<ObjectHash>,
+
+    /// The origin of each line, once it has been worked out.
+    origins: Option<Rc<Vec<ObjectHash>>>
+}
+
+/// Work out which snapshot each line of a file was last changed in,
+/// as it is in the snapshot `start`.
+///
+/// The history is walked once, and each line is carried back
+/// through the diffs between a version of the file and the
+/// versions it came from, until it reaches the snapshot that
+/// added it. Results are cached, so later calls only need to
+/// look at the snapshots made since.
+pub fn blame(repo: &Repository, start: ObjectHash, path: &RelativePath) -> Result<Vec<BlameLine>> {
+    // Every snapshot the walk reaches is read once, to find the version of the file in it.
+    let mut contents: HashMap<ObjectHash, Option<ObjectHash>> = HashMap::new();
+
+    let mut content_at = |hash: ObjectHash| -> Result<Option<ObjectHash>> {
+        if let Some(&content) = contents.get(&hash) {
+            return Ok(content);
+        }
+
+        let content = repo.fetch_snapshot(hash)?.files.get(path).copied();
+
+        contents.insert(hash, content);
+
+        Ok(content)
+    };
+
+    let Some(content) = content_at(start)? else {
+        bail!("path {path} is not in snapshot {start}.");
+    };
+
+    let mut nodes: HashMap<ObjectHash, Node> = HashMap::new();
+
+    let mut order: Vec<ObjectHash> = vec![];
+
+    // Snapshots are ordered so that every parent comes before
+    // its children, stopping at any snapshot already cached.
+    let mut stack: Vec<(ObjectHash, bool)> = vec![(start, false)];
+
+    while let Some((hash, expanded)) = stack.pop() {
+        if expanded {
+            order.push(hash);
+
+            continue;
+        }
+
+        if nodes.contains_key(&hash) {
+            continue;
+        }
+
+        let content = unwrap!(
+            content_at(hash)?,
+            "path {path} is not in snapshot {hash}."
+        );
+
+        if let Some(origins) = load_cached(repo, hash, path) {
+            nodes.insert(hash, Node { content, parents: vec![], origins: Some(Rc::new(origins)) });
+
+            continue;
+        }
+
+        let mut parents = vec![];
+
+        for &parent in unwrap!(repo.history.get_parents(hash), "snapshot {hash} is not in the history.") {
+            if content_at(parent)?.is_some() {
+                parents.push(parent);
+            }
+        }
+
+        parents.sort();
+
+        stack.push((hash, true));
+
+        stack.extend(parents.iter().map(|&parent| (parent, false)));
+
+        nodes.insert(hash, Node { content, parents, origins: None });
+    }
+
+    let mut texts: HashMap<ObjectHash, Rc<String>> = HashMap::new();
+
+    let mut fetch_text = |hash: ObjectHash| -> Result<Rc<String>> {
+        if let Some(text) = texts.get(&hash) {
+            return Ok(text.clone());
+        }
+
+        let text = Rc::new(repo.fetch_string_content(hash)?);
+
+        texts.insert(hash, text.clone());
+
+        Ok(text)
+    };
+
+    for hash in order {
+        let node = &nodes[&hash];
+
+        // An unchanged file has the same lines as the version it was copied from.
+        let same = node.parents
+            .iter()
+            .find(|parent| nodes[*parent].content == node.content);
+
+        if let Some(parent) = same {
+            let origins = nodes[parent].origins.clone();
+
+            nodes.get_mut(&hash).unwrap().origins = origins;
+
+            continue;
+        }
+
+        let text = fetch_text(node.content)?;
+
+        let mut origins: Vec<Option<ObjectHash>> = vec![None; text.lines().count()];
+
+        for parent in &node.parents {
+            let parent = &nodes[parent];
+
+            let parent_text = fetch_text(parent.content)?;
+
+            let parent_origins = parent.origins.as_ref().unwrap();
+
+            let diff = TextDiff::from_lines(parent_text.as_str(), text.as_str());
+
+            for op in diff.ops() {
+                if op.tag() != DiffTag::Equal {
+                    continue;
+                }
+
+                let old = op.old_range();
+
+                for (offset, line) in op.new_range().enumerate() {
+                    let slot = &mut origins[line];
+
+                    if slot.is_none() {
+                        *slot = parent_origins.get(old.start + offset).copied();
+                    }
+                }
+            }
+        }
+
+        let origins: Vec<ObjectHash> = origins
+            .into_iter()
+            .map(|origin| origin.unwrap_or(hash))
+            .collect();
+
+        save_cached(repo, hash, path, &origins);
+
+        nodes.get_mut(&hash).unwrap().origins = Some(Rc::new(origins));
+    }
+
+    let origins = nodes[&start].origins.clone().unwrap();
+
+    save_cached(repo, start, path, &origins);
+
+    let text = fetch_text(content)?;
+
+    // A cache that doesn't fit the file can't be trusted.
+    if origins.len() != text.lines().count() {
+        bail!("cached blame for {path} at {start} does not match the file.");
+    }
+
+    let lines = text
+        .lines()
+        .zip(origins.iter())
+        .map(|(line, &origin)| BlameLine { origin, content: line.to_string() })
+        .collect();
+
+    Ok(lines)
+}
@@ -0,0 +1,412 @@
+//! The blob store on its own, apart from the rest of a [`Repository`].
+//!
+//! A [`BlobStore`] only holds where the blobs are and the repository's object
+//! cache, so it is cheap to clone and can be moved to another thread. It is
+//! one kind of [`ObjectStore`], which is all the sync code needs to send and
+//! receive objects. Reading a large object on the runtime would stall every
+//! other connection to a server, so with the `sync` feature, stores also have
+//! async versions of their reads and writes, from [`AsyncObjectStore`], that
+//! run on tokio's blocking threads instead.
+//!
+//! A store can also borrow objects from the blob stores of other repositories
+//! on the same machine, its alternates, so a clone made with `--reference`
+//! doesn't need its own copy of everything the other repository has. Objects
+//! are only ever written to the store's own directory.
+//!
+//! [`Repository`]: crate::repository::Repository
+
+use std::{collections::HashSet, fs, path::{Path, PathBuf}, sync::Arc};
+
+use eyre::Result;
+
+use crate::{cache::ObjectCache, content::Content, guard::guard, hash::ObjectHash, repository::CorruptObject, snapshot::Snapshot, stats, unwrap, utils::{open_file, resolve_wildcard_path, save_as_msgpack}};
+
+/// Where a repository's objects are stored, along with its object cache.
+#[derive(Clone)]
+pub struct BlobStore {
+    dir: PathBuf,
+    cache: Arc<ObjectCache>,
+
+    /// The blob directories of other repositories that objects are borrowed from.
+    alternates: Vec<PathBuf>
+}
+
+impl BlobStore {
+    /// Make a store for the blobs in `dir`, sharing `cache`.
+    pub fn new(dir: PathBuf, cache: Arc<ObjectCache>) -> BlobStore {
+        BlobStore { dir, cache, alternates: vec![] }
+    }
+
+    /// Borrow objects from the blob directories in `alternates`,
+    /// when they aren't in this store's own directory.
+    pub fn with_alternates(mut self, alternates: Vec<PathBuf>) -> BlobStore {
+        self.alternates = alternates;
+
+        self
+    }
+
+    /// Convert an [`ObjectHash`] to its location on disk.
+    pub fn hash_to_path(&self, hash: ObjectHash) -> PathBuf {
+        let full = hash.full();
+
+        let (dir, rest) = full.split_at(2);
+
+        self.dir
+            .join(dir)
+            .join(rest)
+    }
+
+    /// Get the hash an object's file is named after, if it is one.
+    fn path_to_hash(&self, path: &Path) -> Option<ObjectHash> {
+        if path.is_dir() {
+            return None;
+        }
+
+        let dir = path.parent()?.file_name()?.to_string_lossy();
+        let rest = path.file_name()?.to_string_lossy();
+
+        format!("{dir}{rest}").parse().ok()
+    }
+
+    /// Find where an object is stored, in this store's own
+    /// directory or one of its alternates, if it is anywhere.
+    pub fn find(&self, hash: ObjectHash) -> Option<PathBuf> {
+        let own = self.hash_to_path(hash);
+
+        if own.exists() {
+            return Some(own);
+        }
+
+        let full = hash.full();
+
+        let (dir, rest) = full.split_at(2);
+
+        self.alternates
+            .iter()
+            .map(|alternate| alternate.join(dir).join(rest))
+            .find(|path| path.exists())
+    }
+
+    /// Check if an object is stored here or in one of the alternates.
+    pub fn contains(&self, hash: ObjectHash) -> bool {
+        self.find(hash).is_some()
+    }
+
+    /// Copy an object borrowed from an alternate into this store's own
+    /// directory, returning whether there was anything to copy.
+    pub fn localise(&self, hash: ObjectHash) -> Result<bool> {
+        let own = self.hash_to_path(hash);
+
+        if own.exists() {
+            return Ok(false);
+        }
+
+        let Some(borrowed) = self.find(hash) else {
+            return Ok(false);
+        };
+
+        unwrap!(
+            fs::create_dir_all(own.parent().unwrap()),
+            "failed to create directory for: {}", own.display()
+        );
+
+        unwrap!(
+            fs::copy(&borrowed, &own),
+            "failed to copy {} to {}", borrowed.display(), own.display()
+        );
+
+        Ok(true)
+    }
+
+    /// Fetch a [`Content`] object, addressed by its hash.
+    pub fn fetch_content_object(&self, content_hash: ObjectHash) -> Result<Content> {
+        if let Some(content) = self.cache.get_content(content_hash) {
+            return Ok(content);
+        }
+
+        let path = self.find(content_hash).unwrap_or_else(|| self.hash_to_path(content_hash));
+
+        let raw = unwrap!(
+            fs::read(&path),
+            "failed to read bytes from: {}", path.display()
+        );
+
+        let content: Content = rmp_serde::from_slice(&raw).map_err(|e| CorruptObject {
+            hash: content_hash,
+            reason: e.to_string()
+        })?;
+
+        stats::record_read();
+
+        self.cache.insert_content(content_hash, content.clone());
+
+        Ok(content)
+    }
+
+    /// Fetch a [`Snapshot`], addressed by its hash.
+    pub fn fetch_snapshot(&self, snapshot_hash: ObjectHash) -> Result<Snapshot> {
+        if let Some(snapshot) = self.cache.get_snapshot(snapshot_hash) {
+            return Ok(snapshot);
+        }
+
+        let path = self.find(snapshot_hash).unwrap_or_else(|| self.hash_to_path(snapshot_hash));
+
+        let fp = open_file(path)?;
+
+        let snapshot: Snapshot = rmp_serde::from_read(fp).map_err(|e| CorruptObject {
+            hash: snapshot_hash,
+            reason: e.to_string()
+        })?;
+
+        stats::record_read();
+
+        if let Err(e) = snapshot.verify() {
+            return Err(CorruptObject { hash: snapshot_hash, reason: e.to_string() }.into());
+        }
+
+        self.cache.insert_snapshot(snapshot.clone());
+
+        Ok(snapshot)
+    }
+
+    /// Save a [`Content`] object, unless one is already stored
+    /// under its hash, here or in one of the alternates.
+    pub fn save_content_object(&self, object: Content, hash: ObjectHash) -> Result<()> {
+        if self.contains(hash) || !guard().allow_quietly() {
+            return Ok(());
+        }
+
+        stats::record_write();
+
+        save_as_msgpack(&object, self.hash_to_path(hash))
+    }
+}
+
+/// Somewhere objects are stored, addressed by their hash.
+///
+/// [`BlobStore`] keeps them as files on disk, which is the default, and
+/// with the `sqlite` feature, [`SqliteStore`] keeps them in one database
+/// file, which suits servers holding lots of small objects better. With the
+/// `s3` feature, [`S3Store`] keeps them in a bucket instead of on disk. Stores
+/// are used from several threads at once, and with the `sync` feature, any
+/// store that can be cloned is also an [`AsyncObjectStore`].
+///
+/// [`SqliteStore`]: crate::sqlite::SqliteStore
+/// [`S3Store`]: crate::s3::S3Store
+pub trait ObjectStore: Send + Sync {
+    /// Check if an object is stored.
+    fn contains(&self, hash: ObjectHash) -> bool;
+
+    /// Read an object as it is stored, without checking it.
+    fn fetch_raw(&self, hash: ObjectHash) -> Result<Vec<u8>>;
+
+    /// Fetch a [`Content`] object, addressed by its hash.
+    fn fetch_content_object(&self, content_hash: ObjectHash) -> Result<Content>;
+
+    /// Fetch a [`Snapshot`], addressed by its hash.
+    fn fetch_snapshot(&self, snapshot_hash: ObjectHash) -> Result<Snapshot>;
+
+    /// Save a [`Content`] object, unless one is already stored under its hash.
+    fn save_content_object(&self, object: Content, hash: ObjectHash) -> Result<()>;
+
+    /// Save a [`Snapshot`] under its hash, which should already be checked.
+    fn save_snapshot_object(&self, snapshot: &Snapshot) -> Result<()>;
+
+    /// Delete an object, returning whether it was stored.
+    fn remove_object(&self, hash: ObjectHash) -> Result<bool>;
+
+    /// Get the hash of every object in the store.
+    fn stored_objects(&self) -> Result<HashSet<ObjectHash>>;
+
+    /// Get the hashes of the objects in the store starting with `prefix`,
+    /// which is lowercase hex.
+    fn find_prefix(&self, prefix: &str) -> Result<Vec<ObjectHash>>;
+
+    /// Get how many bytes an object takes up in the store.
+    fn stored_size(&self, hash: ObjectHash) -> Result<u64>;
+}
+
+impl ObjectStore for BlobStore {
+    fn contains(&self, hash: ObjectHash) -> bool {
+        BlobStore::contains(self, hash)
+    }
+
+    fn fetch_raw(&self, hash: ObjectHash) -> Result<Vec<u8>> {
+        let path = self.find(hash).unwrap_or_else(|| self.hash_to_path(hash));
+
+        let raw = unwrap!(
+            fs::read(&path),
+            "failed to read bytes from: {}", path.display()
+        );
+
+        Ok(raw)
+    }
+
+    fn fetch_content_object(&self, content_hash: ObjectHash) -> Result<Content> {
+        BlobStore::fetch_content_object(self, content_hash)
+    }
+
+    fn fetch_snapshot(&self, snapshot_hash: ObjectHash) -> Result<Snapshot> {
+        BlobStore::fetch_snapshot(self, snapshot_hash)
+    }
+
+    fn save_content_object(&self, object: Content, hash: ObjectHash) -> Result<()> {
+        BlobStore::save_content_object(self, object, hash)
+    }
+
+    fn save_snapshot_object(&self, snapshot: &Snapshot) -> Result<()> {
+        if !guard().allow_quietly() {
+            return Ok(());
+        }
+
+        stats::record_write();
+
+        save_as_msgpack(snapshot, self.hash_to_path(snapshot.hash))
+    }
+
+    fn remove_object(&self, hash: ObjectHash) -> Result<bool> {
+        let path = self.hash_to_path(hash);
+
+        self.cache.invalidate(hash);
+
+        if !path.exists() {
+            return Ok(false);
+        }
+
+        // The directories are left, since objects are only ever saved into existing ones.
+        unwrap!(
+            fs::remove_file(&path),
+            "failed to delete object: {}", path.display()
+        );
+
+        Ok(true)
+    }
+
+    fn stored_objects(&self) -> Result<HashSet<ObjectHash>> {
+        let mut hashes = HashSet::new();
+
+        for path in resolve_wildcard_path(self.dir.join("**/*"))? {
+            if let Some(hash) = self.path_to_hash(&path) {
+                hashes.insert(hash);
+            }
+        }
+
+        Ok(hashes)
+    }
+
+    fn find_prefix(&self, prefix: &str) -> Result<Vec<ObjectHash>> {
+        let (dir, file) = prefix.split_at(prefix.len().min(2));
+
+        let mut glob = self.dir.clone();
+
+        glob.push(if dir.len() == 2 { dir.to_string() } else { format!("{dir}*") });
+        glob.push(format!("{file}*"));
+
+        let hashes = resolve_wildcard_path(&glob)?
+            .iter()
+            .filter_map(|path| self.path_to_hash(path))
+            .collect();
+
+        Ok(hashes)
+    }
+
+    fn stored_size(&self, hash: ObjectHash) -> Result<u64> {
+        let path = self.find(hash).unwrap_or_else(|| self.hash_to_path(hash));
+
+        let metadata = unwrap!(
+            fs::metadata(&path),
+            "failed to read metadata of: {}", path.display()
+        );
+
+        Ok(metadata.len())
+    }
+}
+
+impl<S: ObjectStore + ?Sized> ObjectStore for Arc<S> {
+    fn contains(&self, hash: ObjectHash) -> bool {
+        (**self).contains(hash)
+    }
+
+    fn fetch_raw(&self, hash: ObjectHash) -> Result<Vec<u8>> {
+        (**self).fetch_raw(hash)
+    }
+
+    fn fetch_content_object(&self, content_hash: ObjectHash) -> Result<Content> {
+        (**self).fetch_content_object(content_hash)
+    }
+
+    fn fetch_snapshot(&self, snapshot_hash: ObjectHash) -> Result<Snapshot> {
+        (**self).fetch_snapshot(snapshot_hash)
+    }
+
+    fn save_content_object(&self, object: Content, hash: ObjectHash) -> Result<()> {
+        (**self).save_content_object(object, hash)
+    }
+
+    fn save_snapshot_object(&self, snapshot: &Snapshot) -> Result<()> {
+        (**self).save_snapshot_object(snapshot)
+    }
+
+    fn remove_object(&self, hash: ObjectHash) -> Result<bool> {
+        (**self).remove_object(hash)
+    }
+
+    fn stored_objects(&self) -> Result<HashSet<ObjectHash>> {
+        (**self).stored_objects()
+    }
+
+    fn find_prefix(&self, prefix: &str) -> Result<Vec<ObjectHash>> {
+        (**self).find_prefix(prefix)
+    }
+
+    fn stored_size(&self, hash: ObjectHash) -> Result<u64> {
+        (**self).stored_size(hash)
+    }
+}
+
+/// The reads and writes of an [`ObjectStore`], run on tokio's blocking
+/// threads so they don't hold up other tasks on the runtime.
+#[cfg(feature = "sync")]
+#[async_trait::async_trait]
+pub trait AsyncObjectStore {
+    /// Like [`ObjectStore::fetch_content_object`], without blocking the runtime.
+    async fn fetch_content_object_async(&self, content_hash: ObjectHash) -> Result<Content>;
+
+    /// Like [`ObjectStore::fetch_snapshot`], without blocking the runtime.
+    async fn fetch_snapshot_async(&self, snapshot_hash: ObjectHash) -> Result<Snapshot>;
+
+    /// Like [`ObjectStore::save_content_object`], without blocking the runtime.
+    async fn save_content_object_async(&self, object: Content, hash: ObjectHash) -> Result<()>;
+}
+
+/// Run `f` with a clone of `store` on one of tokio's blocking threads.
+#[cfg(feature = "sync")]
+async fn spawn_blocking<S, T, F>(store: &S, f: F) -> Result<T>
+where
+    S: ObjectStore + Clone + 'static,
+    T: Send + 'static,
+    F: FnOnce(S) -> Result<T> + Send + 'static
+{
+    let store = store.clone();
+
+    unwrap!(
+        tokio::task::spawn_blocking(move || f(store)).await,
+        "object store task failed"
+    )
+}
+
+#[cfg(feature = "sync")]
+#[async_trait::async_trait]
+impl<S: ObjectStore + Clone + 'static> AsyncObjectStore for S {
+    async fn fetch_content_object_async(&self, content_hash: ObjectHash) -> Result<Content> {
+        spawn_blocking(self, move |store| store.fetch_content_object(content_hash)).await
+    }
+
+    async fn fetch_snapshot_async(&self, snapshot_hash: ObjectHash) -> Result<Snapshot> {
+        spawn_blocking(self, move |store| store.fetch_snapshot(snapshot_hash)).await
+    }
+
+    async fn save_content_object_async(&self, object: Content, hash: ObjectHash) -> Result<()> {
+        spawn_blocking(self, move |store| store.save_content_object(object, hash)).await
+    }
+}
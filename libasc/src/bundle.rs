@@ -0,0 +1,329 @@
+//! Bundles hold part of a repository's history in a single file, so it
+//! can be moved between machines that can't reach each other over a network.
+//!
+//! A bundle holds the snapshots it was made from, the content they need,
+//! and the branches and tags pointing into them, using the same [`Object`]s
+//! as are sent when syncing. It is signed by the user who made it, and can
+//! only be applied to a repository that already knows that user.
+
+use std::{collections::{BTreeMap, HashMap, HashSet, VecDeque}, fs, path::Path};
+
+use eyre::{Result, bail};
+use serde::{Deserialize, Serialize};
+use serde_bytes::ByteBuf;
+
+use crate::{action::Action, content::Content, fence::Fence, hash::ObjectHash, key::{PublicKey, Signature}, refname, refs::{Ref, RefKind}, repository::Repository, sync::utils::Object, unwrap, utils::{compress_data, decompress_data}};
+
+/// The history held in a bundle file.
+#[derive(Deserialize, Serialize)]
+pub struct Bundle {
+    pub project_code: ObjectHash,
+    pub branches: BTreeMap<String, ObjectHash>,
+    pub tags: BTreeMap<String, ObjectHash>,
+
+    /// Snapshots the bundle builds on but doesn't hold,
+    /// which must already be in a repository to apply it.
+    pub prerequisites: Vec<ObjectHash>,
+    pub objects: HashMap<ObjectHash, Object>
+}
+
+/// A bundle as it is written to disk: compressed, and signed by its maker.
+#[derive(Deserialize, Serialize)]
+struct BundleFile {
+    data: ByteBuf,
+    signature: Signature
+}
+
+/// What happened to a branch or tag when a bundle was applied.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RefUpdate {
+    /// The ref didn't exist, and was made at this hash.
+    Created(ObjectHash),
+
+    /// The ref already pointed to the same snapshot.
+    UpToDate,
+
+    /// The branch was moved forward from the first hash to the second.
+    FastForward(ObjectHash, ObjectHash),
+
+    /// The ref points somewhere else locally. The local hash is kept under
+    /// the name given, as with a pull, and the ref is moved to the bundle's.
+    /// For branches, this name is in the `backups` namespace.
+    Diverged(ObjectHash, ObjectHash, String),
+
+    /// The ref didn't exist, and its name isn't allowed here, so it wasn't
+    /// made. This holds the bundle's hash for it, and why it isn't allowed.
+    InvalidName(ObjectHash, String)
+}
+
+impl Bundle {
+    /// Make a bundle with every snapshot reachable from `tips`,
+    /// except for `bases` and the snapshots before them.
+    ///
+    /// Branches and tags are included if they point to
+    /// one of the snapshots that ends up in the bundle.
+    pub fn create(repo: &Repository, tips: &[ObjectHash], bases: &[ObjectHash]) -> Result<Bundle> {
+        let excluded = repo.history.reachable(bases.iter().copied(), |_| false)?;
+
+        let selected = repo.history.reachable(tips.iter().copied(), |hash| excluded.contains(&hash))?;
+
+        if selected.is_empty() {
+            bail!("there are no snapshots to bundle in this range.");
+        }
+
+        let mut objects = HashMap::new();
+
+        let mut prerequisites = HashSet::new();
+
+        for &hash in &selected {
+            let snapshot = repo.fetch_snapshot(hash)?;
+
+            for &parent in &snapshot.parents {
+                if !selected.contains(&parent) {
+                    prerequisites.insert(parent);
+                }
+            }
+
+            objects.insert(hash, Object::Commit(Box::new(snapshot)));
+        }
+
+        // Content the prerequisites have is already
+        // on the other side, so it doesn't need sending.
+        let mut known = HashSet::new();
+
+        for &hash in &prerequisites {
+            known.extend(repo.fetch_snapshot(hash)?.files.into_values());
+        }
+
+        let mut queue: VecDeque<ObjectHash> = objects
+            .values()
+            .filter_map(|object| match object {
+                Object::Commit(snapshot) => Some(snapshot.files.values().copied()),
+                Object::Content(_) => None
+            })
+            .flatten()
+            .collect();
+
+        while let Some(hash) = queue.pop_front() {
+            if known.contains(&hash) || objects.contains_key(&hash) {
+                continue;
+            }
+
+            let content = repo.fetch_content_object(hash)?;
+
+            if let Content::Delta(delta) = &content {
+                queue.push_back(delta.original);
+            }
+
+            objects.insert(hash, Object::Content(content));
+        }
+
+        let pick = |(name, hash): (&str, ObjectHash)| {
+            selected.contains(&hash).then(|| (name.to_string(), hash))
+        };
+
+        let mut prerequisites: Vec<_> = prerequisites.into_iter().collect();
+
+        prerequisites.sort();
+
+        Ok(Bundle {
+            project_code: repo.project_code,
+            branches: repo.refs.branches().iter().filter_map(pick).collect(),
+            tags: repo.refs.tags().iter().filter_map(pick).collect(),
+            prerequisites,
+            objects
+        })
+    }
+
+    /// Count the snapshots in the bundle.
+    pub fn snapshot_count(&self) -> usize {
+        self.objects
+            .values()
+            .filter(|object| matches!(object, Object::Commit(_)))
+            .count()
+    }
+
+    /// Write the bundle to a file, signed by the current user.
+    pub fn save(&self, repo: &Repository, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+
+        let Some(mut key) = repo.current_user().and_then(|user| user.private_key.clone()) else {
+            bail!("a bundle can only be made by a user with a private key.");
+        };
+
+        let data = compress_data(rmp_serde::to_vec(self)?);
+
+        let signature = key.sign(&data);
+
+        let file = BundleFile {
+            data: ByteBuf::from(data),
+            signature
+        };
+
+        unwrap!(
+            fs::write(path, rmp_serde::to_vec(&file)?),
+            "failed to write bundle to {}", path.display()
+        );
+
+        Ok(())
+    }
+
+    /// Read a bundle from a file, checking it was signed by a user of `repo`.
+    pub fn load(repo: &Repository, path: impl AsRef<Path>) -> Result<Bundle> {
+        let path = path.as_ref();
+
+        let (bundle, maker) = Bundle::read(path)?;
+
+        if repo.users.get_user(&maker).is_none() {
+            bail!("bundle {} was made by a user this repository doesn't know.", path.display());
+        }
+
+        Ok(bundle)
+    }
+
+    /// Read a bundle from a file, checking its signature, and return it
+    /// with the key of the user who made it, for when there is no
+    /// repository yet to check that user against.
+    pub fn read(path: impl AsRef<Path>) -> Result<(Bundle, PublicKey)> {
+        let path = path.as_ref();
+
+        let bytes = unwrap!(
+            fs::read(path),
+            "failed to read bundle from {}", path.display()
+        );
+
+        let file: BundleFile = unwrap!(
+            rmp_serde::from_slice(&bytes),
+            "{} is not a bundle file", path.display()
+        );
+
+        unwrap!(
+            file.signature.check(&file.data),
+            "bundle {} has an invalid signature.", path.display()
+        );
+
+        let bundle = unwrap!(
+            rmp_serde::from_slice(&decompress_data(file.data)?),
+            "failed to read the contents of bundle {}", path.display()
+        );
+
+        Ok((bundle, file.signature.key()))
+    }
+
+    /// Add the bundle's snapshots and content to `repo`,
+    /// then update its branches and tags to match.
+    ///
+    /// The repository is saved before this returns.
+    pub fn apply(self, repo: &mut Repository) -> Result<Vec<(Ref, RefUpdate)>> {
+        if self.project_code != repo.project_code {
+            bail!("bundle is for a different project.");
+        }
+
+        let missing: Vec<String> = self.prerequisites
+            .iter()
+            .filter(|&&hash| !repo.has_snapshot(hash))
+            .map(|hash| hash.to_string())
+            .collect();
+
+        if !missing.is_empty() {
+            bail!("bundle builds on snapshots this repository doesn't have: {}", missing.join(", "));
+        }
+
+        // Nothing on disk points to the new objects until the
+        // repository is saved, so keep them safe from a clean.
+        let mut fence = Fence::new(repo)?;
+
+        fence.extend(self.objects.keys().copied())?;
+
+        for (hash, object) in self.objects {
+            match object {
+                Object::Commit(snapshot) => { repo.save_snapshot(*snapshot)?; },
+                Object::Content(content) => repo.save_content_object(content, hash)?
+            }
+        }
+
+        let mut updates = vec![];
+
+        for (name, new) in self.branches {
+            if !repo.refs.branches().contains(&name)
+                && let Err(e) = refname::check_branch(&name, &repo.config)
+            {
+                updates.push((Ref::Branch(name), RefUpdate::InvalidName(new, e.to_string())));
+
+                continue;
+            }
+
+            let update = match repo.refs.branches().get(&name) {
+                None => {
+                    repo.refs.branches_mut().create(name.clone(), new);
+
+                    repo.action_history.push(Action::CreateBranch { name: name.clone(), hash: new });
+
+                    RefUpdate::Created(new)
+                },
+
+                Some(old) if old == new => RefUpdate::UpToDate,
+
+                Some(old) => {
+                    let update = if repo.history.is_descendant(new, old)? {
+                        RefUpdate::FastForward(old, new)
+                    }
+                    else {
+                        let backup = repo.refs.conflict_copy_name(RefKind::Backup, &name, old);
+
+                        repo.refs.set(Ref::Backup(backup.clone()), old);
+
+                        RefUpdate::Diverged(old, new, backup)
+                    };
+
+                    repo.refs.branches_mut().create(name.clone(), new);
+
+                    repo.action_history.push(Action::MoveBranch { name: name.clone(), old, new });
+
+                    update
+                }
+            };
+
+            updates.push((Ref::Branch(name), update));
+        }
+
+        for (name, new) in self.tags {
+            if !repo.refs.tags().contains(&name)
+                && let Err(e) = refname::check_tag(&name, &repo.config)
+            {
+                updates.push((Ref::Tag(name), RefUpdate::InvalidName(new, e.to_string())));
+
+                continue;
+            }
+
+            let update = match repo.refs.tags().get(&name) {
+                None => RefUpdate::Created(new),
+                Some(old) if old == new => RefUpdate::UpToDate,
+
+                Some(old) => {
+                    let renamed = repo.refs.conflict_copy_name(RefKind::Tag, &name, old);
+
+                    repo.refs.tags_mut().rename(&name, renamed.clone());
+
+                    repo.action_history.push(Action::RenameTag { old: name.clone(), new: renamed.clone(), hash: old });
+
+                    RefUpdate::Diverged(old, new, renamed)
+                }
+            };
+
+            if update != RefUpdate::UpToDate {
+                repo.refs.tags_mut().create(name.clone(), new);
+
+                repo.action_history.push(Action::CreateTag { name: name.clone(), hash: new });
+            }
+
+            updates.push((Ref::Tag(name), update));
+        }
+
+        repo.save()?;
+
+        drop(fence);
+
+        Ok(updates)
+    }
+}
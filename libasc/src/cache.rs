@@ -0,0 +1,194 @@
+//! An in-memory cache of objects read from the blob store, so that commands
+//! like blame, merge and diff, which read the same snapshots and content over
+//! and over, only read and deserialise each one from disk once.
+//!
+//! The cache holds as many bytes as the `io.cache_size` config allows,
+//! dropping the objects that were used least recently to make room.
+//! Lookups are counted in [`crate::stats`], so `--stats` shows how well it works.
+
+use std::{collections::{BTreeMap, HashMap}, sync::{Mutex, MutexGuard}};
+
+use crate::{content::Content, hash::ObjectHash, snapshot::Snapshot, stats};
+
+/// How many bytes of objects are kept in memory,
+/// unless the repository's config says otherwise.
+pub static DEFAULT_CACHE_SIZE: u64 = 64 * 1024 * 1024;
+
+#[derive(Clone)]
+enum Object {
+    Snapshot(Box<Snapshot>),
+    Content(Content)
+}
+
+impl Object {
+    /// Roughly how much memory the object takes up.
+    fn size(&self) -> u64 {
+        let size = match self {
+            Object::Snapshot(snapshot) => {
+                let files: usize = snapshot.files
+                    .keys()
+                    .map(|path| path.as_str().len() + 32)
+                    .sum();
+
+                snapshot.message.len() + files + 256
+            },
+
+            Object::Content(Content::Literal(data)) => data.len(),
+            Object::Content(Content::Delta(delta)) => delta.edit.len() + 64,
+            Object::Content(Content::Pointer(_)) => 64
+        };
+
+        size as u64
+    }
+}
+
+struct Entry {
+    object: Object,
+    size: u64,
+    last_used: u64
+}
+
+#[derive(Default)]
+struct Inner {
+    entries: HashMap<ObjectHash, Entry>,
+
+    /// The hashes in the cache, by when they were last used.
+    by_use: BTreeMap<u64, ObjectHash>,
+
+    size: u64,
+    clock: u64
+}
+
+/// Snapshots and content read from disk, kept in memory
+/// up to a size limit. This can be shared between threads.
+pub struct ObjectCache {
+    inner: Mutex<Inner>,
+    capacity: u64
+}
+
+impl ObjectCache {
+    /// Make an empty cache that holds up to `capacity` bytes.
+    /// A capacity of `0` turns the cache off.
+    pub fn new(capacity: u64) -> ObjectCache {
+        ObjectCache {
+            inner: Mutex::new(Inner::default()),
+            capacity
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Inner> {
+        // Nothing is left half-changed if a thread panics while
+        // holding the lock, so the cache is still fine to use.
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn get(&self, hash: ObjectHash) -> Option<Object> {
+        if self.capacity == 0 {
+            return None;
+        }
+
+        let mut inner = self.lock();
+
+        inner.clock += 1;
+
+        let now = inner.clock;
+
+        let found = match inner.entries.get_mut(&hash) {
+            Some(entry) => {
+                let before = entry.last_used;
+
+                entry.last_used = now;
+
+                Some((before, entry.object.clone()))
+            },
+
+            None => None
+        };
+
+        stats::record_lookup(found.is_some());
+
+        let (before, object) = found?;
+
+        inner.by_use.remove(&before);
+        inner.by_use.insert(now, hash);
+
+        Some(object)
+    }
+
+    fn insert(&self, hash: ObjectHash, object: Object) {
+        let size = object.size();
+
+        // Something bigger than the whole cache would only push everything else out.
+        if size > self.capacity {
+            return;
+        }
+
+        let mut inner = self.lock();
+
+        if let Some(old) = inner.entries.remove(&hash) {
+            inner.by_use.remove(&old.last_used);
+
+            inner.size -= old.size;
+        }
+
+        while inner.size + size > self.capacity {
+            let Some((_, oldest)) = inner.by_use.pop_first() else {
+                break;
+            };
+
+            if let Some(old) = inner.entries.remove(&oldest) {
+                inner.size -= old.size;
+            }
+        }
+
+        inner.clock += 1;
+
+        let now = inner.clock;
+
+        inner.entries.insert(hash, Entry { object, size, last_used: now });
+        inner.by_use.insert(now, hash);
+        inner.size += size;
+    }
+
+    /// Get a snapshot, if it is in the cache.
+    pub fn get_snapshot(&self, hash: ObjectHash) -> Option<Snapshot> {
+        match self.get(hash)? {
+            Object::Snapshot(snapshot) => Some(*snapshot),
+            Object::Content(_) => None
+        }
+    }
+
+    /// Get a content object, if it is in the cache.
+    pub fn get_content(&self, hash: ObjectHash) -> Option<Content> {
+        match self.get(hash)? {
+            Object::Content(content) => Some(content),
+            Object::Snapshot(_) => None
+        }
+    }
+
+    /// Keep a snapshot that has been read from disk and verified.
+    pub fn insert_snapshot(&self, snapshot: Snapshot) {
+        self.insert(snapshot.hash, Object::Snapshot(Box::new(snapshot)));
+    }
+
+    /// Keep a content object that has been read from disk.
+    pub fn insert_content(&self, hash: ObjectHash, content: Content) {
+        self.insert(hash, Object::Content(content));
+    }
+
+    /// Forget an object, because it has been rewritten or deleted on disk.
+    pub fn invalidate(&self, hash: ObjectHash) {
+        let mut inner = self.lock();
+
+        if let Some(old) = inner.entries.remove(&hash) {
+            inner.by_use.remove(&old.last_used);
+
+            inner.size -= old.size;
+        }
+    }
+
+    /// Forget every object.
+    pub fn clear(&self) {
+        *self.lock() = Inner::default();
+    }
+}
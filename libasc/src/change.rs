@@ -12,6 +12,10 @@ pub enum FileChange<P: AsRef<RelativePath>> {
     #[display("EDITED      {_0}")]
     Edited(P),
 
+    /// The file on disk differs from the content staged for it.
+    #[display("MODIFIED    {_0}")]
+    Modified(P),
+
     #[display("UNCHANGED   {_0}")]
     Unchanged(P),
 
@@ -21,3 +25,31 @@ pub enum FileChange<P: AsRef<RelativePath>> {
     #[display("SKIP        {_0}")]
     Skip(P)
 }
+
+impl<P: AsRef<RelativePath>> FileChange<P> {
+    /// Get the path that changed.
+    pub fn path(&self) -> &RelativePath {
+        match self {
+            FileChange::Added(path)
+            | FileChange::Removed(path)
+            | FileChange::Edited(path)
+            | FileChange::Modified(path)
+            | FileChange::Unchanged(path)
+            | FileChange::Missing(path)
+            | FileChange::Skip(path) => path.as_ref()
+        }
+    }
+
+    /// Get the kind of change as a lowercase word, like `added`.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            FileChange::Added(_) => "added",
+            FileChange::Removed(_) => "removed",
+            FileChange::Edited(_) => "edited",
+            FileChange::Modified(_) => "modified",
+            FileChange::Unchanged(_) => "unchanged",
+            FileChange::Missing(_) => "missing",
+            FileChange::Skip(_) => "skip"
+        }
+    }
+}
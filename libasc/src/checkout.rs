@@ -0,0 +1,103 @@
+//! Snapshots written out to a temporary directory, for tools that need
+//! a version's files on disk without touching the working directory,
+//! like diff tools, build checks and scripts run against old versions.
+//!
+//! The files are made read-only, since changes to them go nowhere, and
+//! the directory is deleted when the [`TempCheckout`] is dropped.
+
+use std::{env, fs, io, path::{Path, PathBuf}, process, sync::atomic::{AtomicU64, Ordering}};
+
+use eyre::{bail, Result};
+
+use crate::{hash::ObjectHash, unwrap};
+
+/// Temporary directories made by this process so far, to keep their names apart.
+static MADE: AtomicU64 = AtomicU64::new(0);
+
+/// A snapshot written out to a temporary directory,
+/// made by [`Repository::materialize`](crate::repository::Repository::materialize).
+///
+/// The directory is deleted when this is dropped, unless it is kept with [`TempCheckout::keep`].
+#[derive(Debug)]
+pub struct TempCheckout {
+    path: PathBuf,
+    hash: ObjectHash,
+    keep: bool
+}
+
+impl TempCheckout {
+    /// Make a new, empty temporary directory for the snapshot `hash`.
+    pub(crate) fn create(hash: ObjectHash) -> Result<TempCheckout> {
+        let base = env::temp_dir();
+
+        loop {
+            let n = MADE.fetch_add(1, Ordering::Relaxed);
+
+            let name = format!("asc-{hash}-{}-{n}", process::id());
+
+            let path = base.join(name);
+
+            match fs::create_dir(&path) {
+                Ok(()) => return Ok(TempCheckout { path, hash, keep: false }),
+
+                // Left behind by an earlier process with the same id.
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+
+                Err(e) => bail!("failed to create temporary directory {}: {e}", path.display())
+            }
+        }
+    }
+
+    /// Get the directory the snapshot is written to.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Get the hash of the snapshot that was written out.
+    pub fn hash(&self) -> ObjectHash {
+        self.hash
+    }
+
+    /// Stop the directory from being deleted when this is dropped, returning where it is.
+    pub fn keep(mut self) -> PathBuf {
+        self.keep = true;
+
+        self.path.clone()
+    }
+}
+
+impl Drop for TempCheckout {
+    fn drop(&mut self) {
+        if self.keep {
+            return;
+        }
+
+        // There is nothing to do about a directory that
+        // can't be deleted, and the OS clears them out anyway.
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+/// Make a file that has been written out read-only. Symbolic links are skipped,
+/// since changing their permissions would change the file they point to.
+pub(crate) fn make_read_only(path: &Path) -> Result<()> {
+    let metadata = unwrap!(
+        fs::symlink_metadata(path),
+        "failed to read metadata of: {}", path.display()
+    );
+
+    if metadata.file_type().is_symlink() {
+        return Ok(());
+    }
+
+    let mut permissions = metadata.permissions();
+
+    permissions.set_readonly(true);
+
+    unwrap!(
+        fs::set_permissions(path, permissions),
+        "failed to make read-only: {}", path.display()
+    );
+
+    Ok(())
+}
@@ -0,0 +1,81 @@
+//! Where the times new snapshots are stamped with come from.
+//!
+//! Snapshots are normally stamped with the time they're made, but tests
+//! and tools importing history from elsewhere need to pick the time
+//! themselves. A repository takes the time for new snapshots from its
+//! [`Clock`], which is a [`FixedClock`] at `ASC_COMMIT_DATE` if that is
+//! set when the repository is loaded, and can be replaced with
+//! `Repository::set_clock`.
+
+use std::sync::Arc;
+
+use chrono::{DateTime, NaiveDate, NaiveDateTime, NaiveTime, Utc};
+use eyre::{bail, Result};
+
+/// The environment variable that sets the time new snapshots are stamped with.
+pub static COMMIT_DATE_VAR: &str = "ASC_COMMIT_DATE";
+
+/// Something that tells the time.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+/// The time on this machine.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// A clock stopped at one time.
+#[derive(Clone, Copy, Debug)]
+pub struct FixedClock(pub DateTime<Utc>);
+
+impl Clock for FixedClock {
+    fn now(&self) -> DateTime<Utc> {
+        self.0
+    }
+}
+
+/// Parse a date for a snapshot, which can be a timestamp like
+/// `2025-01-31T12:00:00Z`, a time like `2025-01-31 12:00:00` or a day
+/// like `2025-01-31` in UTC, or the seconds since 1970 like `@1738324800`.
+pub fn parse_date(raw: &str) -> Result<DateTime<Utc>> {
+    let raw = raw.trim();
+
+    if let Some(seconds) = raw.strip_prefix('@')
+        && let Ok(seconds) = seconds.parse()
+        && let Some(datetime) = DateTime::from_timestamp(seconds, 0)
+    {
+        return Ok(datetime);
+    }
+
+    if let Ok(datetime) = raw.parse::<DateTime<Utc>>() {
+        return Ok(datetime);
+    }
+
+    if let Ok(datetime) = NaiveDateTime::parse_from_str(raw, "%Y-%m-%d %H:%M:%S") {
+        return Ok(datetime.and_utc());
+    }
+
+    if let Ok(date) = raw.parse::<NaiveDate>() {
+        return Ok(date.and_time(NaiveTime::MIN).and_utc());
+    }
+
+    bail!("expected a timestamp, a day like 2025-01-31, or seconds like @1738324800, got {raw:?}")
+}
+
+/// Get the clock set by `ASC_COMMIT_DATE`, or the system's if it isn't set.
+pub fn from_env() -> Result<Arc<dyn Clock>> {
+    match std::env::var(COMMIT_DATE_VAR) {
+        Ok(raw) if !raw.is_empty() => match parse_date(&raw) {
+            Ok(datetime) => Ok(Arc::new(FixedClock(datetime))),
+            Err(e) => bail!("{COMMIT_DATE_VAR} is invalid: {e}")
+        },
+
+        _ => Ok(Arc::new(SystemClock))
+    }
+}
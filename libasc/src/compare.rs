@@ -0,0 +1,144 @@
+//! What one version would bring into another if they were merged,
+//! for `asc compare`.
+
+use std::{cmp::Reverse, collections::{BTreeMap, BTreeSet}};
+
+use eyre::Result;
+use relative_path::RelativePathBuf;
+use similar::{ChangeTag, TextDiff};
+
+use crate::{activity, hash::ObjectHash, repository::Repository};
+
+/// How a file changed, and by how many lines.
+#[derive(Clone, Debug)]
+pub struct FileStat {
+    pub path: RelativePathBuf,
+
+    /// One of `added`, `removed` or `edited`.
+    pub change: &'static str,
+
+    pub added: usize,
+    pub removed: usize
+}
+
+/// How `head` compares with `base`, found by [`compare`].
+#[derive(Clone, Debug)]
+pub struct Comparison {
+    pub base: ObjectHash,
+    pub head: ObjectHash,
+
+    /// The closest snapshot in the history of both,
+    /// which the changes in `files` are counted from.
+    pub merge_base: Option<ObjectHash>,
+
+    /// The snapshots in the history of `head` that
+    /// aren't in the history of `base`, newest first.
+    pub ahead: Vec<ObjectHash>,
+
+    /// How many snapshots are in the history of `base` but not `head`.
+    pub behind: usize,
+
+    /// The files `head` changed since the merge base, by path.
+    pub files: Vec<FileStat>,
+
+    /// The files changed differently on both sides since the merge
+    /// base, which might conflict when `head` is merged into `base`.
+    pub overlapping: Vec<RelativePathBuf>
+}
+
+/// Count the lines added and removed going from `old` to `new`.
+fn count_lines(old: &str, new: &str) -> (usize, usize) {
+    let diff = TextDiff::from_lines(old, new);
+
+    let mut added = 0;
+    let mut removed = 0;
+
+    for change in diff.iter_all_changes() {
+        match change.tag() {
+            ChangeTag::Insert => added += 1,
+            ChangeTag::Delete => removed += 1,
+            ChangeTag::Equal => {}
+        }
+    }
+
+    (added, removed)
+}
+
+/// Get the paths whose content differs between two sets of files.
+fn changed_paths<'a>(
+    old: &'a BTreeMap<RelativePathBuf, ObjectHash>,
+    new: &'a BTreeMap<RelativePathBuf, ObjectHash>
+) -> BTreeSet<&'a RelativePathBuf>
+{
+    old.keys()
+        .chain(new.keys())
+        .filter(|&path| old.get(path) != new.get(path))
+        .collect()
+}
+
+/// Compare `head` with `base`, like a review of merging `head` into `base` would.
+pub fn compare(repo: &Repository, base: ObjectHash, head: ObjectHash) -> Result<Comparison> {
+    let merge_base = repo.history.merge_base(base, head)?;
+
+    let mut ahead: Vec<_> = activity::range(repo, head, Some(base))?
+        .into_iter()
+        .map(|hash| repo.fetch_snapshot(hash))
+        .collect::<Result<_>>()?;
+
+    ahead.sort_by_key(|snapshot| Reverse(snapshot.timestamp));
+
+    let behind = activity::range(repo, base, Some(head))?.len();
+
+    let original = match merge_base {
+        Some(hash) => repo.fetch_snapshot(hash)?.files,
+        None => BTreeMap::new()
+    };
+
+    let base_files = repo.fetch_snapshot(base)?.files;
+    let head_files = repo.fetch_snapshot(head)?.files;
+
+    let mut files = vec![];
+
+    for path in changed_paths(&original, &head_files) {
+        let old = original.get(path).copied();
+        let new = head_files.get(path).copied();
+
+        let change = match (old, new) {
+            (None, _) => "added",
+            (_, None) => "removed",
+            _ => "edited"
+        };
+
+        let old = match old {
+            Some(hash) => repo.fetch_string_content(hash)?,
+            None => String::new()
+        };
+
+        let new = match new {
+            Some(hash) => repo.fetch_string_content(hash)?,
+            None => String::new()
+        };
+
+        let (added, removed) = count_lines(&old, &new);
+
+        files.push(FileStat { path: path.clone(), change, added, removed });
+    }
+
+    let changed_on_base = changed_paths(&original, &base_files);
+
+    let overlapping = changed_paths(&original, &head_files)
+        .intersection(&changed_on_base)
+        .filter(|&&path| base_files.get(path) != head_files.get(path))
+        .map(|&path| path.clone())
+        .collect();
+
+    Ok(Comparison {
+        base,
+        head,
+        merge_base,
+        ahead: ahead.into_iter().map(|snapshot| snapshot.hash).collect(),
+        behind,
+        files,
+        overlapping
+    })
+}
@@ -0,0 +1,647 @@
+use std::{collections::BTreeMap, fs, path::{Path, PathBuf}, time::Duration};
+
+use eyre::{bail, Result};
+use glob_match::glob_match;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::{cache::DEFAULT_CACHE_SIZE, content::DEFAULT_MAX_DELTA_DEPTH, key::PublicKey, lock::DEFAULT_LOCK_TIMEOUT, paths::DEFAULT_CASE_SENSITIVE, repository::{locate_root_dir, search_start}, sync::{DEFAULT_HOOK_TIMEOUT, DEFAULT_MAX_FRAME_SIZE}, trash::DEFAULT_TRASH_RETENTION, unwrap, user::Users, utils::parse_duration};
+
+/// Settings for a repository, read from `.asc/config.toml`.
+///
+/// Every field has a default, so a missing file or
+/// missing keys are treated as the default settings.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Config {
+    pub lfs: LfsConfig,
+    pub branches: BranchesConfig,
+    pub commit: CommitConfig,
+    pub content: ContentConfig,
+    pub hooks: HooksConfig,
+    pub io: IoConfig,
+    pub paths: PathsConfig,
+    pub retention: RetentionConfig,
+    pub sync: SyncConfig,
+    pub tags: TagsConfig,
+    pub trash: TrashConfig,
+    pub upgrade: UpgradeConfig,
+    pub ui: UiConfig
+}
+
+/// Settings for storing large files outside of the blob store.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct LfsConfig {
+    /// Files larger than this many bytes are stored as large files.
+    ///
+    /// If this is unset, every file is stored in the blob store.
+    pub threshold: Option<u64>
+}
+
+/// Settings for branches.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct BranchesConfig {
+    /// Branches, or patterns of them like `release/*`, that have to
+    /// keep a linear history on a server. Pushes to them are only taken
+    /// if they fast-forward the branch without bringing in any merges,
+    /// so work has to be rebased or squashed onto them first.
+    pub linear: Vec<String>,
+
+    /// A regular expression the whole name of a new branch has to match,
+    /// like `(main|(feature|fix)/[a-z0-9-]+)`. This applies to branches
+    /// made or renamed here, and to ones that come from other repositories.
+    pub name_pattern: Option<String>
+}
+
+impl BranchesConfig {
+    /// Check if a branch has to keep a linear history.
+    pub fn is_linear(&self, name: &str) -> bool {
+        self.linear
+            .iter()
+            .any(|pattern| glob_match(pattern, name))
+    }
+}
+
+/// Settings for writing and checking snapshot messages.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct CommitConfig {
+    /// A file, relative to the repository root, used to start
+    /// every message that is written in an editor.
+    ///
+    /// If this is unset, `.asc/SNAPSHOT_TEMPLATE` is used if it exists.
+    pub template: Option<PathBuf>,
+
+    /// Show the staged changes as a diff when writing a message in an editor.
+    pub verbose: bool,
+
+    /// The most characters allowed in the first line of a message.
+    pub max_subject_length: Option<usize>,
+
+    /// A regular expression the first line of a message has to match.
+    pub subject_pattern: Option<String>
+}
+
+/// Settings for how file content is stored.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ContentConfig {
+    /// How many deltas can be applied in a row to get a file back.
+    /// Content that would go past this is saved whole instead.
+    ///
+    /// If this is unset, [`DEFAULT_MAX_DELTA_DEPTH`] is used.
+    pub max_delta_depth: Option<usize>,
+
+    /// Where snapshots and content are kept. Changing this doesn't move
+    /// objects that are already stored, which `asc plumbing move-objects` does.
+    pub store: StoreKind,
+
+    /// Where objects are kept when `store` is `s3`.
+    pub s3: S3Config
+}
+
+/// The kinds of [`ObjectStore`] a repository can keep its objects in.
+///
+/// [`ObjectStore`]: crate::blobs::ObjectStore
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StoreKind {
+    /// A file for each object, under `.asc/blobs`.
+    #[default]
+    Files,
+
+    /// One SQLite database, `.asc/objects.sqlite`,
+    /// if asc was built with the `sqlite` feature.
+    Sqlite,
+
+    /// A bucket in S3, or storage with the same API, set up by
+    /// `content.s3`, if asc was built with the `s3` feature.
+    S3
+}
+
+/// Settings for keeping objects in S3, or storage with the same API.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct S3Config {
+    /// The URL requests are sent to, like `http://localhost:9000`.
+    /// Buckets are always addressed by path, after this URL.
+    ///
+    /// If this is unset, the AWS endpoint for `region` is used.
+    pub endpoint: Option<String>,
+
+    /// The region the bucket is in. If this is unset, `us-east-1` is used.
+    pub region: Option<String>,
+
+    pub bucket: String,
+
+    /// Put before the key of every object, like `repos/project`,
+    /// so repositories can share a bucket.
+    pub prefix: String,
+
+    /// If these are unset, the `AWS_ACCESS_KEY_ID` and
+    /// `AWS_SECRET_ACCESS_KEY` environment variables are used.
+    pub access_key_id: Option<String>,
+    pub secret_access_key: Option<String>,
+
+    /// Objects larger than this many bytes are uploaded in parts of this size,
+    /// which can't be smaller than 5 MiB.
+    ///
+    /// If this is unset, 16 MiB is used.
+    pub part_size: Option<u64>
+}
+
+impl ContentConfig {
+    /// Get the longest a chain of deltas can be.
+    pub fn max_delta_depth(&self) -> usize {
+        self.max_delta_depth.unwrap_or(DEFAULT_MAX_DELTA_DEPTH)
+    }
+}
+
+/// Settings for telling other programs when a push updates branches
+/// here, like to start CI. Each runs once for every branch updated.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct HooksConfig {
+    /// Whether to run hooks and send webhooks at all. Set this to `false`
+    /// for a repository whose config you didn't write yourself.
+    ///
+    /// If this is unset, they are.
+    pub enabled: Option<bool>,
+
+    /// Commands run from the root of the repository, without a shell and
+    /// with only some of the server's environment, with the update as JSON
+    /// on stdin. What they print is only shown if they fail.
+    pub post_push: Vec<String>,
+
+    /// URLs the update is POSTed to as JSON.
+    pub webhooks: Vec<String>,
+
+    /// How long a hook can run before it is killed, like `30s` or `2m`.
+    ///
+    /// If this is unset, [`DEFAULT_HOOK_TIMEOUT`] is used.
+    pub timeout: Option<String>
+}
+
+impl HooksConfig {
+    /// Get whether hooks and webhooks are run.
+    pub fn enabled(&self) -> bool {
+        self.enabled.unwrap_or(true)
+    }
+
+    /// Get how long a hook can run for.
+    pub fn timeout(&self) -> Result<Duration> {
+        match &self.timeout {
+            Some(raw) => parse_duration(raw),
+            None => Ok(DEFAULT_HOOK_TIMEOUT)
+        }
+    }
+}
+
+/// Settings for reading and writing files.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct IoConfig {
+    /// How many threads to hash, compress, check and write files on
+    /// when there are lots of them, like when committing or switching.
+    ///
+    /// If this is unset, one thread is used for each CPU.
+    pub threads: Option<usize>,
+
+    /// How many bytes of snapshots and content to keep in memory
+    /// once they have been read, or `0` to always read from disk.
+    ///
+    /// If this is unset, [`DEFAULT_CACHE_SIZE`] is used.
+    pub cache_size: Option<u64>,
+
+    /// How long a command that changes the repository waits for another
+    /// one to let go of the lock on it, like `30s` or `2m`.
+    ///
+    /// If this is unset, [`DEFAULT_LOCK_TIMEOUT`] is used.
+    pub lock_timeout: Option<String>
+}
+
+impl IoConfig {
+    /// Get how many bytes of objects to keep in memory.
+    pub fn cache_size(&self) -> u64 {
+        self.cache_size.unwrap_or(DEFAULT_CACHE_SIZE)
+    }
+
+    /// Get how long to wait for the lock on the repository.
+    pub fn lock_timeout(&self) -> Result<Duration> {
+        match &self.lock_timeout {
+            Some(raw) => parse_duration(raw),
+            None => Ok(DEFAULT_LOCK_TIMEOUT)
+        }
+    }
+}
+
+/// Settings for how paths are stored and checked out.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct PathsConfig {
+    /// Whether paths that only differ in case are different files here.
+    /// Where they aren't, those paths can't be committed or checked out together.
+    ///
+    /// If this is unset, [`DEFAULT_CASE_SENSITIVE`] is used.
+    pub case_sensitive: Option<bool>
+}
+
+impl PathsConfig {
+    /// Check if paths that only differ in case are different files.
+    pub fn case_sensitive(&self) -> bool {
+        self.case_sensitive.unwrap_or(DEFAULT_CASE_SENSITIVE)
+    }
+}
+
+/// Settings for history that has to be kept for some time, like for a legal hold.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct RetentionConfig {
+    /// How long snapshots made on branches matching each pattern,
+    /// like `main` or `release/*`, have to be kept, like `90d` or `7y`.
+    /// Where a branch matches more than one pattern, the longest time applies.
+    pub branches: BTreeMap<String, String>
+}
+
+impl RetentionConfig {
+    /// Get how long snapshots made on a branch have to be kept, if they do.
+    pub fn period_for(&self, branch: &str) -> Result<Option<Duration>> {
+        Ok(self.longest_for(branch)?.map(|(period, _)| period))
+    }
+
+    /// Get the longest time snapshots made on a branch have
+    /// to be kept, along with how it was written in the config.
+    fn longest_for(&self, branch: &str) -> Result<Option<(Duration, &String)>> {
+        let mut longest = None;
+
+        for (pattern, raw) in &self.branches {
+            if !glob_match(pattern, branch) {
+                continue;
+            }
+
+            let period = parse_duration(raw)?;
+
+            if longest.is_none_or(|(most, _)| period > most) {
+                longest = Some((period, raw));
+            }
+        }
+
+        Ok(longest)
+    }
+
+    /// Check if no branches have to be kept.
+    pub fn is_empty(&self) -> bool {
+        self.branches.is_empty()
+    }
+}
+
+/// Settings for talking to remotes.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct SyncConfig {
+    /// The largest frame, in bytes, read from a remote.
+    /// Remotes split anything larger over several frames.
+    ///
+    /// If this is unset, [`DEFAULT_MAX_FRAME_SIZE`] is used.
+    pub max_frame_size: Option<u64>,
+
+    /// The proxy to reach remotes through, like `socks5://localhost:1080`
+    /// or `http://proxy:3128`.
+    ///
+    /// If this is unset, the `ALL_PROXY` environment variable is used,
+    /// except for hosts in `NO_PROXY`.
+    pub proxy: Option<String>,
+
+    /// Proxies for remotes on particular hosts, by patterns like
+    /// `*.example.com`, used instead of `proxy`. A proxy of `none`
+    /// means remotes on those hosts are reached directly.
+    pub proxies: BTreeMap<String, String>,
+
+    /// Reach SSH remotes through asc even without a proxy, so their
+    /// IPv6 and IPv4 addresses are tried side by side, instead of
+    /// one after the other by ssh.
+    pub happy_eyeballs: bool,
+
+    /// Send and receive pins with pushes and pulls, so snapshots
+    /// pinned on one side are kept on the other. Both sides need
+    /// this set for pins to be exchanged.
+    pub pins: bool
+}
+
+impl SyncConfig {
+    /// Get the largest frame read from a remote.
+    pub fn max_frame_size(&self) -> u64 {
+        self.max_frame_size.unwrap_or(DEFAULT_MAX_FRAME_SIZE)
+    }
+
+    /// Get the proxy set for remotes on `host`, which might be `none`.
+    pub fn proxy_for(&self, host: &str) -> Option<&str> {
+        self.proxies
+            .iter()
+            .find(|(pattern, _)| glob_match(pattern, host))
+            .map(|(_, proxy)| proxy.as_str())
+            .or(self.proxy.as_deref())
+    }
+}
+
+/// Settings for who can change which tags.
+///
+/// Patterns are globs matched against the whole tag name,
+/// like `release/*` or `v*`.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct TagsConfig {
+    /// Namespaces of tags that only some users can push to a server, with
+    /// the users (by name, or `@group`) that can. Where a tag matches more
+    /// than one namespace, the longest pattern is the one that applies.
+    pub namespaces: BTreeMap<String, Vec<String>>,
+
+    /// Tags that can't be moved, renamed or deleted once they exist.
+    pub protected: Vec<String>,
+
+    /// Settle tags that differ between repositories by keeping the first
+    /// one made, the same way everywhere, instead of renaming the local
+    /// one. Both sides of a push or pull need this set for it to apply.
+    pub replicated: bool,
+
+    /// A regular expression the whole name of a new tag has to match,
+    /// like `v[0-9]+\.[0-9]+\.[0-9]+`. This applies to tags made
+    /// or renamed here, and to ones that come from other repositories.
+    pub name_pattern: Option<String>
+}
+
+impl TagsConfig {
+    /// Get the users that can push a tag, or `None` if it isn't in a namespace.
+    pub fn namespace_of(&self, name: &str) -> Option<&[String]> {
+        self.namespaces
+            .iter()
+            .filter(|(pattern, _)| glob_match(pattern, name))
+            .max_by_key(|(pattern, _)| pattern.len())
+            .map(|(_, rules)| rules.as_slice())
+    }
+
+    /// Check if a user can push a tag to this repository.
+    pub fn can_push(&self, name: &str, users: &Users, key: &PublicKey) -> bool {
+        self.namespace_of(name)
+            .is_none_or(|rules| rules.iter().any(|rule| users.matches_rule(rule, key)))
+    }
+
+    /// Check if a tag is protected from being moved, renamed or deleted.
+    pub fn is_protected(&self, name: &str) -> bool {
+        self.protected
+            .iter()
+            .any(|pattern| glob_match(pattern, name))
+    }
+}
+
+/// Settings for the trash.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct TrashConfig {
+    /// How long snapshots are kept in the trash before
+    /// `asc trash purge` deletes them, like `30d` or `2w`.
+    ///
+    /// If this is unset, [`DEFAULT_TRASH_RETENTION`] is used.
+    pub retention: Option<String>
+}
+
+impl TrashConfig {
+    /// Get how long snapshots are kept in the trash.
+    pub fn retention(&self) -> Result<Duration> {
+        match &self.retention {
+            Some(raw) => parse_duration(raw),
+            None => Ok(DEFAULT_TRASH_RETENTION)
+        }
+    }
+}
+
+/// Settings for where `asc upgrade` gets new versions from.
+///
+/// Both of these are built in to release builds, so they
+/// only need setting to use a mirror, or a build of your own.
+/// They are only read from the user's own config file, like
+/// `~/.config/asc/config.toml`, and never from a repository's.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct UpgradeConfig {
+    /// The URL releases are published under, holding a `latest.json`
+    /// manifest. `file://` URLs can be used for a local mirror.
+    pub endpoint: Option<String>,
+
+    /// The public key, in hex, that releases are signed with.
+    pub public_key: Option<String>
+}
+
+/// Settings for how the command line talks to users.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct UiConfig {
+    /// The editor used to write messages, like `vim` or `code --wait`,
+    /// when one isn't given on the command line.
+    ///
+    /// If this is unset, the `EDITOR` environment variable is used.
+    pub editor: Option<String>,
+
+    /// The language to show messages in, like `fr` or `pt-BR`.
+    ///
+    /// If this is unset, the language is taken from the environment.
+    pub language: Option<String>,
+
+    /// Show output without colours or decorations, and without
+    /// stopping to ask questions, for screen readers and other tools.
+    pub plain: bool,
+
+    /// Show how long commands like commit, push and pull took,
+    /// along with the objects and bytes they read and wrote.
+    pub stats: bool
+}
+
+impl UiConfig {
+    /// Get the editor used to write messages.
+    pub fn editor(&self) -> Result<String> {
+        if let Some(editor) = &self.editor {
+            return Ok(editor.clone());
+        }
+
+        Ok(unwrap!(
+            std::env::var("EDITOR"),
+            "no editor is set - set 'ui.editor' in the config, or the environment variable 'EDITOR'."
+        ))
+    }
+}
+
+impl CommitConfig {
+    /// Check a message against the validation rules.
+    pub fn validate_message(&self, message: &str) -> Result<()> {
+        let subject = message.lines().next().unwrap_or_default();
+
+        if let Some(max) = self.max_subject_length {
+            let length = subject.chars().count();
+
+            if length > max {
+                bail!("the first line is {length} characters long, but the limit is {max}");
+            }
+        }
+
+        if let Some(pattern) = &self.subject_pattern {
+            let regex = unwrap!(
+                Regex::new(pattern),
+                "invalid subject pattern in config: {pattern:?}"
+            );
+
+            if !regex.is_match(subject) {
+                bail!("the first line {subject:?} doesn't match the pattern {pattern:?}");
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Config {
+    /// Load the config from a file, falling back to
+    /// the default config if the file doesn't exist.
+    pub fn load(path: impl AsRef<Path>) -> Result<Config> {
+        let path = path.as_ref();
+
+        if !path.exists() {
+            return Ok(Config::default());
+        }
+
+        let raw = unwrap!(
+            fs::read_to_string(path),
+            "failed to read config from: {}", path.display()
+        );
+
+        let config = unwrap!(
+            toml::from_str(&raw),
+            "failed to parse config at: {}", path.display()
+        );
+
+        Ok(config)
+    }
+
+    /// Load the config of the repository that [`crate::repository::Repository::load`]
+    /// would load, if there is one.
+    pub fn load_current() -> Result<Config> {
+        let Some(root_dir) = locate_root_dir(search_start()?)? else {
+            return Ok(Config::default());
+        };
+
+        Config::load(root_dir.join(".asc").join("config.toml"))
+    }
+
+    /// Make the settings for branch `old` apply to `new` too, for when it is
+    /// renamed, returning whether anything had to change.
+    ///
+    /// `branches.linear` is checked against the names branches have now, so
+    /// entries naming `old` are moved over. `retention.branches` is checked
+    /// against the branch each snapshot was made on, which never changes,
+    /// so `old` is kept for the snapshots made on it so far, and `new` is
+    /// added for those made from now on. Where `old` is only matched by a
+    /// pattern, like `release/*`, `new` is added by itself.
+    pub fn rename_branch(&mut self, old: &str, new: &str) -> Result<bool> {
+        let mut changed = false;
+
+        if self.branches.is_linear(old) {
+            for pattern in &mut self.branches.linear {
+                if pattern == old {
+                    *pattern = new.to_string();
+
+                    changed = true;
+                }
+            }
+
+            if !self.branches.is_linear(new) {
+                self.branches.linear.push(new.to_string());
+
+                changed = true;
+            }
+        }
+
+        if let Some((period, raw)) = self.retention.longest_for(old)?
+            && self.retention.period_for(new)?.is_none_or(|kept| kept < period)
+        {
+            let raw = raw.clone();
+
+            self.retention.branches.insert(new.to_string(), raw);
+
+            changed = true;
+        }
+
+        Ok(changed)
+    }
+
+    /// Write the config to a file.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+
+        let raw = toml::to_string_pretty(self)?;
+
+        unwrap!(
+            fs::write(path, raw),
+            "failed to write config to: {}", path.display()
+        );
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(linear: &[&str], retention: &[(&str, &str)]) -> Config {
+        let mut config = Config::default();
+
+        config.branches.linear = linear.iter().map(|name| name.to_string()).collect();
+
+        config.retention.branches = retention
+            .iter()
+            .map(|(name, raw)| (name.to_string(), raw.to_string()))
+            .collect();
+
+        config
+    }
+
+    #[test]
+    fn renamed_branches_stay_linear() {
+        let mut exact = config(&["main"], &[]);
+
+        assert!(exact.rename_branch("main", "trunk").unwrap());
+        assert_eq!(exact.branches.linear, ["trunk"]);
+
+        let mut pattern = config(&["release/*"], &[]);
+
+        assert!(pattern.rename_branch("release/1.0", "v1").unwrap());
+        assert_eq!(pattern.branches.linear, ["release/*", "v1"]);
+
+        let mut unrelated = config(&["main"], &[]);
+
+        assert!(!unrelated.rename_branch("topic", "feature").unwrap());
+        assert_eq!(unrelated.branches.linear, ["main"]);
+    }
+
+    #[test]
+    fn renamed_branches_are_kept_as_long() {
+        let mut exact = config(&[], &[("main", "7y")]);
+
+        assert!(exact.rename_branch("main", "trunk").unwrap());
+
+        // Snapshots already made on `main` are still held.
+        assert_eq!(exact.retention.period_for("main").unwrap(), exact.retention.period_for("trunk").unwrap());
+        assert!(exact.retention.branches.contains_key("main"));
+
+        let mut pattern = config(&[], &[("release/*", "2y")]);
+
+        assert!(pattern.rename_branch("release/1.0", "v1").unwrap());
+        assert_eq!(pattern.retention.branches.get("v1").map(String::as_str), Some("2y"));
+
+        let mut longer = config(&[], &[("main", "1y"), ("trunk", "7y")]);
+
+        assert!(!longer.rename_branch("main", "trunk").unwrap());
+        assert_eq!(longer.retention.branches.get("trunk").map(String::as_str), Some("7y"));
+    }
+}
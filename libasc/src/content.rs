@@ -1,8 +1,15 @@
+#[cfg(feature = "fs")]
 use eyre::Result;
 use serde::{Deserialize, Serialize};
-use similar::TextDiff;
 
-use crate::{hash::ObjectHash, repository::Repository, unwrap, utils::{decompress_data, hash_raw_bytes}};
+use crate::hash::ObjectHash;
+
+#[cfg(feature = "fs")]
+use crate::{repository::Repository, unwrap, utils::{compress_data, decompress_data, hash_raw_bytes}};
+
+/// How many deltas can be applied in a row to get some content back,
+/// unless the repository's config says otherwise. Content past this is saved whole.
+pub static DEFAULT_MAX_DELTA_DEPTH: usize = 50;
 
 #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 pub struct Delta {
@@ -10,8 +17,15 @@ pub struct Delta {
     
     #[serde(with = "serde_bytes")]
     pub edit: Vec<u8>,
+
+    /// How many deltas have to be applied to get the content back,
+    /// counting this one. Deltas saved before this was kept track
+    /// of have it as `0`, and their chain has to be followed instead.
+    #[serde(default)]
+    pub depth: usize
 }
 
+#[cfg(feature = "fs")]
 impl Delta {
     pub fn new_unchecked(old: &str, new: &str) -> Delta {
         let original = hash_raw_bytes(old);
@@ -21,25 +35,39 @@ impl Delta {
 
         Delta {
             original,
-            edit
+            edit,
+            depth: 1
         }
     }
 
-    pub fn new(old: &str, new: &str, min_similarity: f32) -> Option<Delta> {
-        let diff = TextDiff::from_lines(old, new);
+    /// Make a delta from `old` to `new`, if it would take up less
+    /// space than storing `new` whole.
+    ///
+    /// The delta is made from the bytes of the two strings, so this works
+    /// just as well for content with long lines or no lines at all.
+    pub fn new(old: &str, new: &str) -> Option<Delta> {
+        let delta = Delta::new_unchecked(old, new);
 
-        (diff.ratio() >= min_similarity).then(|| {
-            Delta::new_unchecked(old, new)
-        })
+        (delta.edit.len() < compress_data(new).len()).then_some(delta)
     }
 }
 
+/// Stands in for the content of a large file, which is kept
+/// in `.asc/lfs` instead of the blob store.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Pointer {
+    pub hash: ObjectHash,
+    pub size: u64
+}
+
 #[derive(Clone, Deserialize, PartialEq, Serialize)]
 pub enum Content {
     Literal(#[serde(with = "serde_bytes")] Vec<u8>),
-    Delta(Delta)
+    Delta(Delta),
+    Pointer(Pointer)
 }
 
+#[cfg(feature = "fs")]
 impl Content {
     /// Obtain a `String` from [`Content`] by potentially resolving deltas.
     pub fn resolve(&self, repo: &Repository) -> Result<String> {
@@ -61,7 +89,9 @@ impl Content {
                 );
 
                 String::from_utf8(resolved_bytes)?
-            }
+            },
+
+            Self::Pointer(pointer) => repo.fetch_large_file(pointer.hash)?
         })
     }
 }
@@ -0,0 +1,199 @@
+//! Running other programs, like hooks and editors, under a [`Policy`].
+//!
+//! A program named in a config file may not be one the user chose, so it
+//! is only given what it needs: it is run without a shell, from a fixed
+//! directory, with asc's environment cleared down to a few variables, and
+//! can be stopped if it takes too long and have its output kept from asc's.
+
+use std::{ffi::OsString, io::{Read, Write}, path::PathBuf, process::{Command, ExitStatus, Stdio}, thread, time::{Duration, Instant}};
+
+use eyre::{bail, eyre, Result};
+
+use crate::{unwrap, utils::split_command};
+
+/// Variables every program keeps from asc's environment,
+/// so it can find other programs, the user's files and their language.
+pub const BASE_ENV: &[&str] = &[
+    "PATH", "HOME", "USER", "LOGNAME", "LANG", "LC_ALL", "LC_CTYPE", "TMPDIR",
+    // Windows can't start most programs without these.
+    "SYSTEMROOT", "SYSTEMDRIVE", "USERPROFILE", "APPDATA", "LOCALAPPDATA", "PATHEXT", "TEMP", "TMP"
+];
+
+/// Variables programs the user works with, like editors, also keep,
+/// so they can draw in the terminal or open a window, and find their settings.
+pub const TERMINAL_ENV: &[&str] = &[
+    "TERM", "COLORTERM", "SHELL", "DISPLAY", "WAYLAND_DISPLAY", "XAUTHORITY",
+    "XDG_RUNTIME_DIR", "XDG_CONFIG_HOME", "XDG_DATA_HOME", "XDG_CACHE_HOME"
+];
+
+/// How often to check whether a program with a timeout has ended.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// What a program is allowed when it is run.
+#[derive(Clone, Debug)]
+pub struct Policy {
+    /// The directory the program is run from.
+    pub dir: PathBuf,
+
+    /// Variables kept from asc's environment. Every other one is cleared.
+    pub keep_env: Vec<String>,
+
+    /// Variables set for the program, after the rest are cleared.
+    pub env: Vec<(String, String)>,
+
+    /// How long the program can run before it is killed,
+    /// or `None` to wait for it however long it takes.
+    pub timeout: Option<Duration>,
+
+    /// Whether to keep what the program prints in [`Output`]. Otherwise
+    /// it shares asc's terminal, stdin included.
+    pub capture: bool
+}
+
+/// How a program ended, and what it printed if it was captured.
+#[derive(Clone, Debug)]
+pub struct Output {
+    pub status: ExitStatus,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>
+}
+
+impl Policy {
+    /// A policy for programs that run on their own, like hooks: only
+    /// [`BASE_ENV`] is kept, what they print is captured, and they are
+    /// killed after `timeout`.
+    pub fn background(dir: impl Into<PathBuf>, timeout: Duration) -> Policy {
+        Policy {
+            dir: dir.into(),
+            keep_env: BASE_ENV.iter().map(|key| key.to_string()).collect(),
+            env: vec![],
+            timeout: Some(timeout),
+            capture: true
+        }
+    }
+
+    /// A policy for programs the user works with, like editors: the
+    /// [`TERMINAL_ENV`] is kept as well, they share asc's terminal,
+    /// and they have as long as the user needs.
+    pub fn interactive(dir: impl Into<PathBuf>) -> Policy {
+        Policy {
+            dir: dir.into(),
+            keep_env: BASE_ENV.iter().chain(TERMINAL_ENV).map(|key| key.to_string()).collect(),
+            env: vec![],
+            timeout: None,
+            capture: false
+        }
+    }
+
+    /// Set `key` to `value` for the program.
+    pub fn env(mut self, key: &str, value: &str) -> Policy {
+        self.env.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    fn command(&self, program: &str, args: &[OsString]) -> Command {
+        let mut command = Command::new(program);
+
+        command
+            .args(args)
+            .current_dir(&self.dir)
+            .env_clear();
+
+        for key in &self.keep_env {
+            if let Some(value) = std::env::var_os(key) {
+                command.env(key, value);
+            }
+        }
+
+        command.envs(self.env.iter().map(|(key, value)| (key, value)));
+
+        command
+    }
+
+    /// Run `command`, split into a program and arguments with [`split_command`]
+    /// and with `extra_args` added on the end, giving it `stdin` if it is captured.
+    ///
+    /// The program ending with a failure isn't an error,
+    /// but it being killed for taking too long is.
+    pub fn run(&self, command: &str, extra_args: &[OsString], stdin: &[u8]) -> Result<Output> {
+        let words = split_command(command)?;
+
+        let Some((program, args)) = words.split_first() else {
+            bail!("the command is empty");
+        };
+
+        let args: Vec<OsString> = args.iter().map(OsString::from).chain(extra_args.iter().cloned()).collect();
+
+        let mut cmd = self.command(program, &args);
+
+        if self.capture {
+            cmd.stdin(Stdio::piped()).stdout(Stdio::piped()).stderr(Stdio::piped());
+        }
+
+        let mut child = unwrap!(
+            cmd.spawn(),
+            "failed to start {program}"
+        );
+
+        // Pipes are filled and drained on their own threads,
+        // so a program printing a lot can't block on them.
+        let stdin = stdin.to_vec();
+
+        let writer = child.stdin.take().map(|mut pipe| thread::spawn(move || {
+            // Programs don't have to read what they are given.
+            let _ = pipe.write_all(&stdin);
+        }));
+
+        let stdout = child.stdout.take().map(|mut pipe| thread::spawn(move || {
+            let mut buffer = vec![];
+            let _ = pipe.read_to_end(&mut buffer);
+            buffer
+        }));
+
+        let stderr = child.stderr.take().map(|mut pipe| thread::spawn(move || {
+            let mut buffer = vec![];
+            let _ = pipe.read_to_end(&mut buffer);
+            buffer
+        }));
+
+        let status = match self.timeout {
+            None => child.wait()?,
+
+            Some(timeout) => {
+                let started = Instant::now();
+
+                loop {
+                    if let Some(status) = child.try_wait()? {
+                        break status;
+                    }
+
+                    if started.elapsed() >= timeout {
+                        let _ = child.kill();
+                        let _ = child.wait();
+
+                        bail!("{program} was killed after running for longer than {}s", timeout.as_secs_f64());
+                    }
+
+                    thread::sleep(POLL_INTERVAL);
+                }
+            }
+        };
+
+        if let Some(writer) = writer {
+            let _ = writer.join();
+        }
+
+        let join = |handle: Option<thread::JoinHandle<Vec<u8>>>| -> Result<Vec<u8>> {
+            match handle {
+                Some(handle) => handle.join().map_err(|_| eyre!("failed to read the output of {program}")),
+                None => Ok(vec![])
+            }
+        };
+
+        Ok(Output {
+            status,
+            stdout: join(stdout)?,
+            stderr: join(stderr)?
+        })
+    }
+}
@@ -0,0 +1,117 @@
+use std::{collections::HashSet, fs, path::PathBuf, time::Duration};
+
+use chrono::{DateTime, Utc};
+use eyre::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::{hash::ObjectHash, repository::Repository, unwrap, utils::{hash_raw_bytes, load_as_msgpack, save_as_msgpack}};
+
+/// How long a fence can go without being renewed before it is assumed
+/// to belong to an operation that crashed, and can be ignored.
+pub static FENCE_TIMEOUT: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Deserialize, Serialize)]
+struct FenceData {
+    hashes: Vec<ObjectHash>,
+    renewed: DateTime<Utc>
+}
+
+/// Marks objects that an operation in progress (like a sync) has written,
+/// but that no branch or tag points to yet, so cleaning the repository
+/// doesn't delete them before the operation finishes.
+/// 
+/// The fence is removed from disk when it is dropped.
+pub struct Fence {
+    path: PathBuf,
+    data: FenceData
+}
+
+impl Fence {
+    /// Create an empty fence in the repository.
+    pub fn new(repo: &Repository) -> Result<Fence> {
+        let dir = repo.fences_dir();
+
+        unwrap!(
+            fs::create_dir_all(&dir),
+            "failed to create directory: {}", dir.display()
+        );
+
+        let now = Utc::now();
+
+        let id = hash_raw_bytes(format!("{}-{}", std::process::id(), now.timestamp_nanos_opt().unwrap_or_default()));
+
+        let mut fence = Fence {
+            path: dir.join(id.full()),
+            data: FenceData {
+                hashes: vec![],
+                renewed: now
+            }
+        };
+
+        fence.renew()?;
+
+        Ok(fence)
+    }
+
+    /// Add objects to the fence, which also renews it.
+    pub fn extend(&mut self, hashes: impl IntoIterator<Item = ObjectHash>) -> Result<()> {
+        self.data.hashes.extend(hashes);
+
+        self.renew()
+    }
+
+    /// Write the fence to disk again, so it isn't mistaken for a stale fence.
+    pub fn renew(&mut self) -> Result<()> {
+        self.data.renewed = Utc::now();
+
+        save_as_msgpack(&self.data, &self.path)
+    }
+}
+
+impl Drop for Fence {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Collect every object protected by a fence in the repository.
+/// 
+/// Fences that haven't been renewed within [`FENCE_TIMEOUT`]
+/// are deleted instead.
+pub fn fenced_hashes(repo: &Repository) -> Result<HashSet<ObjectHash>> {
+    let dir = repo.fences_dir();
+
+    let mut hashes = HashSet::new();
+
+    if !dir.exists() {
+        return Ok(hashes);
+    }
+
+    let entries = unwrap!(
+        fs::read_dir(&dir),
+        "failed to read directory: {}", dir.display()
+    );
+
+    let now = Utc::now();
+
+    for entry in entries {
+        let path = entry?.path();
+
+        // The fence might be removed while we're reading it.
+        let Ok(data) = load_as_msgpack::<FenceData>(&path) else {
+            continue;
+        };
+
+        let age = (now - data.renewed).to_std().unwrap_or_default();
+
+        if age > FENCE_TIMEOUT {
+            let _ = fs::remove_file(&path);
+
+            continue;
+        }
+
+        hashes.extend(data.hashes);
+    }
+
+    Ok(hashes)
+}
@@ -0,0 +1,383 @@
+use std::{collections::{HashMap, HashSet}, path::PathBuf};
+
+use derive_more::Display;
+use eyre::Result;
+
+use crate::{blobs::ObjectStore, content::Content, fence::fenced_hashes, hash::ObjectHash, refs::{Head, Ref}, repository::Repository, snapshot::Snapshot, utils::{hash_raw_bytes, resolve_wildcard_path}};
+
+/// Something wrong with a repository, found by [`check_repository`].
+#[derive(Clone, Debug, Display, PartialEq)]
+pub enum Problem {
+    /// A file in the blob store whose name isn't a hash.
+    #[display("stray file in the blob store: {}", _0.display())]
+    StrayFile(PathBuf),
+
+    /// An object that can't be read.
+    #[display("object {hash} is corrupted: {error}")]
+    Corrupted {
+        hash: ObjectHash,
+        error: String
+    },
+
+    /// An object whose content doesn't match the hash it is stored under.
+    #[display("object {expected} has the hash {actual}")]
+    HashMismatch {
+        expected: ObjectHash,
+        actual: ObjectHash
+    },
+
+    /// A snapshot whose signature can't be verified.
+    #[display("snapshot {_0} has an invalid signature")]
+    BadSignature(ObjectHash),
+
+    /// A snapshot signed by a key that belongs to no user.
+    #[display("snapshot {_0} was created by an unknown user")]
+    UnknownAuthor(ObjectHash),
+
+    /// A snapshot whose parents in the history differ from its own.
+    #[display("snapshot {_0} has different parents in the history")]
+    ParentsDiffer(ObjectHash),
+
+    /// A snapshot in the history that isn't stored on disk.
+    #[display("snapshot {_0} is in the history but missing from disk")]
+    MissingSnapshot(ObjectHash),
+
+    /// A snapshot in the history with a parent that isn't in the history.
+    #[display("snapshot {hash} has a parent {parent} that is not in the history")]
+    DanglingParent {
+        hash: ObjectHash,
+        parent: ObjectHash
+    },
+
+    /// Content used by a snapshot, stash entry or delta that is missing from disk.
+    #[display("content {_0} is used but missing from disk")]
+    MissingContent(ObjectHash),
+
+    /// An object nothing refers to. These are removed by `asc clean`.
+    #[display("object {_0} is not used by anything")]
+    Orphaned(ObjectHash),
+
+    /// A ref pointing to a snapshot that is missing.
+    #[display("{name} points to a missing snapshot {hash}")]
+    DanglingRef {
+        name: Ref,
+        hash: ObjectHash
+    },
+
+    /// The head is detached at a snapshot that is missing.
+    #[display("the head is at a missing snapshot {_0}")]
+    DanglingHead(ObjectHash),
+
+    /// A stash entry made from a snapshot that is missing.
+    #[display("stash entry {id} was made from a missing snapshot {basis}")]
+    DanglingStash {
+        id: usize,
+        basis: ObjectHash
+    },
+
+    /// A trash entry for a snapshot that is missing.
+    #[display("trash has an entry for a missing snapshot {_0}")]
+    DanglingTrash(ObjectHash)
+}
+
+impl Problem {
+    /// Check if [`repair`] can fix this problem.
+    pub fn is_repairable(&self) -> bool {
+        matches!(
+            self,
+            Problem::ParentsDiffer(_)
+                | Problem::MissingSnapshot(_)
+                | Problem::DanglingRef { .. }
+                | Problem::DanglingStash { .. }
+                | Problem::DanglingTrash(_)
+        )
+    }
+}
+
+/// Read a snapshot without verifying it, so problems can be reported.
+fn read_snapshot(objects: &dyn ObjectStore, hash: ObjectHash) -> Result<Snapshot> {
+    Ok(rmp_serde::from_slice(&objects.fetch_raw(hash)?)?)
+}
+
+/// Read a content object without resolving it.
+fn read_content(objects: &dyn ObjectStore, hash: ObjectHash) -> Result<Content> {
+    Ok(rmp_serde::from_slice(&objects.fetch_raw(hash)?)?)
+}
+
+/// Check every object in the repository, along with
+/// the history and everything that refers to it.
+///
+/// Unlike [`Repository::validate_state`], this looks at objects
+/// that aren't reachable, and reports every problem it finds.
+pub fn check_repository(repo: &Repository) -> Result<Vec<Problem>> {
+    let mut problems = vec![];
+
+    let mut on_disk: HashSet<ObjectHash> = HashSet::new();
+
+    let mut contents: HashMap<ObjectHash, Content> = HashMap::new();
+
+    let mut used: HashSet<ObjectHash> = HashSet::new();
+
+    for path in resolve_wildcard_path(repo.blobs_dir().join("**/*"))? {
+        if path.is_dir() {
+            continue;
+        }
+
+        let name = path
+            .parent()
+            .and_then(|dir| dir.file_name())
+            .zip(path.file_name())
+            .map(|(dir, rest)| format!("{}{}", dir.to_string_lossy(), rest.to_string_lossy()));
+
+        if name.and_then(|name| name.parse::<ObjectHash>().ok()).is_none() {
+            problems.push(Problem::StrayFile(path));
+        }
+    }
+
+    let objects = repo.objects();
+
+    for hash in objects.stored_objects()? {
+        on_disk.insert(hash);
+
+        if !repo.history.contains(hash) {
+            match read_content(&*objects, hash) {
+                Ok(content) => { contents.insert(hash, content); },
+                Err(e) => problems.push(Problem::Corrupted { hash, error: e.to_string() })
+            }
+
+            continue;
+        }
+
+        let snapshot = match read_snapshot(&*objects, hash) {
+            Ok(snapshot) => snapshot,
+            Err(e) => {
+                problems.push(Problem::Corrupted { hash, error: e.to_string() });
+
+                continue;
+            }
+        };
+
+        let actual = snapshot.expected_hash();
+
+        if actual != hash {
+            problems.push(Problem::HashMismatch { expected: hash, actual });
+        }
+        else if !snapshot.is_valid() {
+            problems.push(Problem::BadSignature(hash));
+        }
+
+        if repo.users.get_user(&snapshot.signature.key()).is_none() {
+            problems.push(Problem::UnknownAuthor(hash));
+        }
+
+        if repo.history.get_parents(hash) != Some(&snapshot.parents) {
+            problems.push(Problem::ParentsDiffer(hash));
+        }
+
+        used.extend(snapshot.files.values());
+    }
+
+    // Objects borrowed from the repository's alternates count as stored,
+    // though only the repository's own objects are checked.
+    let blobs = repo.blobs();
+
+    let borrowed: Vec<ObjectHash> = repo.history
+        .iter_hashes()
+        .filter(|&hash| !on_disk.contains(&hash) && blobs.contains(hash))
+        .collect();
+
+    on_disk.extend(borrowed);
+
+    for entry in repo.stash.iter_entries() {
+        used.extend(entry.state.files.values());
+    }
+
+    used.extend(repo.staging.iter().map(|(_, hash)| hash));
+
+    // Deltas need the content they are based on.
+    let mut queue: Vec<ObjectHash> = used.iter().copied().collect();
+
+    while let Some(hash) = queue.pop() {
+        if let Some(Content::Delta(delta)) = contents.get(&hash)
+            && used.insert(delta.original)
+        {
+            queue.push(delta.original);
+        }
+    }
+
+    let mut missing: Vec<ObjectHash> = used
+        .iter()
+        .copied()
+        .filter(|&hash| !on_disk.contains(&hash) && !blobs.contains(hash))
+        .collect();
+
+    missing.sort();
+
+    problems.extend(missing.into_iter().map(Problem::MissingContent));
+
+    // Objects written by a sync that is still running aren't used
+    // yet, and might not all be on disk, so they are only kept
+    // from being reported as orphaned.
+    used.extend(fenced_hashes(repo)?);
+
+    let mut hashes: Vec<ObjectHash> = contents.keys().copied().collect();
+
+    hashes.sort();
+
+    for hash in hashes {
+        let content = &contents[&hash];
+
+        if !used.contains(&hash) {
+            problems.push(Problem::Orphaned(hash));
+        }
+
+        // Large files are only checked if they have been downloaded.
+        if let Content::Pointer(pointer) = content {
+            if pointer.hash != hash {
+                problems.push(Problem::HashMismatch { expected: hash, actual: pointer.hash });
+
+                continue;
+            }
+
+            if !repo.has_large_file(hash) {
+                continue;
+            }
+        }
+
+        if let Content::Delta(delta) = content
+            && !on_disk.contains(&delta.original)
+            && !blobs.contains(delta.original)
+        {
+            continue;
+        }
+
+        match content.resolve(repo) {
+            Ok(text) => {
+                let actual = hash_raw_bytes(&text);
+
+                if actual != hash {
+                    problems.push(Problem::HashMismatch { expected: hash, actual });
+                }
+            }
+
+            Err(e) => problems.push(Problem::Corrupted { hash, error: e.to_string() })
+        }
+    }
+
+    // References are checked before the history, so that
+    // repairing them can free up missing snapshots to be removed.
+    let exists = |hash: ObjectHash| repo.history.contains(hash) && on_disk.contains(&hash);
+
+    for (name, hash) in repo.refs.iter() {
+        if !exists(hash) {
+            problems.push(Problem::DanglingRef { name: name.clone(), hash });
+        }
+    }
+
+    if let Head::Detached(hash) = repo.refs.head()
+        && !exists(*hash)
+    {
+        problems.push(Problem::DanglingHead(*hash));
+    }
+
+    let mut stash: Vec<_> = repo.stash.iter().collect();
+
+    stash.sort_by_key(|(id, _)| *id);
+
+    for (id, entry) in stash {
+        if !exists(entry.basis) {
+            problems.push(Problem::DanglingStash { id, basis: entry.basis });
+        }
+    }
+
+    for entry in repo.trash.entries() {
+        if !exists(entry.hash) {
+            problems.push(Problem::DanglingTrash(entry.hash));
+        }
+    }
+
+    for (hash, parents) in repo.history.iter() {
+        if !on_disk.contains(&hash) {
+            problems.push(Problem::MissingSnapshot(hash));
+        }
+
+        for &parent in parents {
+            if !repo.history.contains(parent) {
+                problems.push(Problem::DanglingParent { hash, parent });
+            }
+        }
+    }
+
+    Ok(problems)
+}
+
+/// Try to fix a problem found by [`check_repository`].
+///
+/// Returns `false` if the problem can't be fixed, like
+/// a missing snapshot that other snapshots are built on.
+pub fn repair(repo: &mut Repository, problem: &Problem) -> Result<bool> {
+    match problem {
+        // The snapshot's own parents are signed, so they are trusted over the history.
+        Problem::ParentsDiffer(hash) => {
+            let snapshot = read_snapshot(&*repo.objects(), *hash)?;
+
+            if !snapshot.is_valid() {
+                return Ok(false);
+            }
+
+            repo.history.upsert(*hash, snapshot.parents);
+
+            Ok(true)
+        }
+
+        Problem::MissingSnapshot(hash) => {
+            let hash = *hash;
+
+            let has_children = repo.history
+                .children(hash)
+                .is_some_and(|children| !children.is_empty());
+
+            let has_refs = repo.refs.refs_to(hash).next().is_some();
+
+            if has_children || has_refs || repo.current_hash() == hash {
+                return Ok(false);
+            }
+
+            repo.history.remove(hash);
+
+            Ok(true)
+        }
+
+        // A ref is put back where it last pointed to a snapshot that
+        // still exists, and is only deleted if there is nowhere to go.
+        Problem::DanglingRef { name, hash } => {
+            let previous = repo.refs
+                .reflog(name)
+                .iter()
+                .rev()
+                .filter_map(|entry| entry.new)
+                .find(|&previous| previous != *hash && repo.has_snapshot(previous));
+
+            if let Some(previous) = previous {
+                repo.refs.set(name.clone(), previous);
+
+                return Ok(true);
+            }
+
+            // The head can't be left on a missing snapshot.
+            if let Ref::Branch(branch) = name
+                && repo.current_branch() == Some(branch.as_str())
+            {
+                return Ok(false);
+            }
+
+            Ok(repo.refs.remove(name).is_some())
+        }
+
+        Problem::DanglingStash { id, .. } => Ok(repo.stash.remove_state(*id).is_some()),
+
+        Problem::DanglingTrash(hash) => Ok(repo.trash.remove(*hash)),
+
+        _ => Ok(false)
+    }
+}
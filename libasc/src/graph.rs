@@ -1,6 +1,6 @@
-use std::collections::{HashMap, HashSet, VecDeque};
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque, hash_map::Entry};
 
-use eyre::Result;
+use eyre::{bail, eyre, Result};
 use serde::{Deserialize, Serialize};
 
 use crate::{hash::ObjectHash, unwrap};
@@ -12,10 +12,51 @@ type RawGraph = HashMap<ObjectHash, Parents>;
 /// Represents the DAG (directed acylic graph) used to
 /// store snapshots and their relationships.
 /// 
-/// This is implemented with a [`HashMap`] of nodes to parents.
+/// This is implemented with a [`HashMap`] of nodes to parents, along
+/// with one of nodes to children that is kept up to date with it, so
+/// children can be found without going through the whole graph.
 #[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(from = "StoredGraph")]
 pub struct Graph {
-    links: RawGraph
+    links: RawGraph,
+    children: RawGraph
+}
+
+/// A [`Graph`] as it is read from disk. Graphs written before
+/// format 3 of the repository have no children stored with them.
+#[derive(Deserialize)]
+struct StoredGraph {
+    links: RawGraph,
+
+    #[serde(default)]
+    children: Option<RawGraph>
+}
+
+impl From<StoredGraph> for Graph {
+    fn from(stored: StoredGraph) -> Graph {
+        let children = match stored.children {
+            Some(children) => children,
+            None => invert_links(&stored.links)
+        };
+
+        Graph { links: stored.links, children }
+    }
+}
+
+/// Map every node in `links` to the nodes that have it as a parent.
+fn invert_links(links: &RawGraph) -> RawGraph {
+    let mut inverted: RawGraph = links
+        .keys()
+        .map(|&hash| (hash, Parents::new()))
+        .collect();
+
+    for (&hash, parents) in links {
+        for &parent in parents {
+            inverted.entry(parent).or_default().insert(hash);
+        }
+    }
+
+    inverted
 }
 
 impl Graph {
@@ -36,32 +77,97 @@ impl Graph {
         let parents = self.links.entry(hash).or_default();
         
         parents.insert(parent);
+
+        self.children.entry(hash).or_default();
+
+        self.children.entry(parent).or_default().insert(hash);
     }
 
     /// Insert a hash with no parents.
     /// 
     /// This is usually used for creating a root snapshot.
     pub fn insert_orphan(&mut self, hash: ObjectHash) {
-        self.links.insert(hash, HashSet::new());
+        if let Some(parents) = self.links.insert(hash, HashSet::new()) {
+            self.unlink_parents(hash, &parents);
+        }
+
+        self.children.entry(hash).or_default();
+    }
+
+    /// Stop listing `hash` as a child of each of `parents`.
+    fn unlink_parents(&mut self, hash: ObjectHash, parents: &Parents) {
+        for parent in parents {
+            if let Some(children) = self.children.get_mut(parent) {
+                children.remove(&hash);
+            }
+        }
     }
 
     /// Remove a hash from the DAG, returning the parents of the removed hash.
+    /// 
+    /// Its children are left in the DAG without it as a parent. To
+    /// remove them along with it, use [`Graph::prune_subtree`].
     pub fn remove(&mut self, hash: ObjectHash) -> Option<Parents> {
         let node_parents = self.links.remove(&hash);
 
-        for parents in self.links.values_mut() {
-            parents.remove(&hash);
+        if let Some(parents) = &node_parents {
+            self.unlink_parents(hash, parents);
+        }
+
+        for child in self.children.remove(&hash).unwrap_or_default() {
+            if let Some(parents) = self.links.get_mut(&child) {
+                parents.remove(&hash);
+            }
         }
 
         node_parents
     }
 
+    /// Remove a hash along with every hash descended from it, returning
+    /// the hashes that were removed, or nothing if `hash` isn't present.
+    /// 
+    /// Merges of the subtree into other history are removed as well,
+    /// so no hash left in the DAG has a parent that isn't in it.
+    pub fn prune_subtree(&mut self, hash: ObjectHash) -> HashSet<ObjectHash> {
+        if !self.contains(hash) {
+            return HashSet::new();
+        }
+
+        let mut removed = self.descendants(hash);
+
+        removed.insert(hash);
+
+        // Anything with a removed parent is a descendant, so only
+        // the children of the hashes that are left need updating.
+        for &node in &removed {
+            if let Some(parents) = self.links.remove(&node) {
+                self.unlink_parents(node, &parents);
+            }
+        }
+
+        self.children.retain(|node, _| !removed.contains(node));
+
+        removed
+    }
+
     /// Perform [`Graph::remove`] on the hash, then [`Graph::insert`]
     /// with the hash and the new parents.
     pub fn upsert(&mut self, hash: ObjectHash, new_parents: impl IntoIterator<Item = ObjectHash>) -> Option<Parents> {
         let removed = self.links.remove(&hash);
+
+        if let Some(parents) = &removed {
+            self.unlink_parents(hash, parents);
+        }
+
+        let new_parents: Parents = new_parents.into_iter().collect();
+
+        for &parent in &new_parents {
+            self.children.entry(parent).or_default().insert(hash);
+        }
+
+        self.children.entry(hash).or_default();
         
-        self.links.insert(hash, new_parents.into_iter().collect());
+        self.links.insert(hash, new_parents);
 
         removed
     }
@@ -87,47 +193,281 @@ impl Graph {
     }
 
     /// Check if `a` is a descendant of `b` in the graph.
+    /// 
+    /// A hash counts as a descendant of itself.
     pub fn is_descendant(&self, a: ObjectHash, b: ObjectHash) -> Result<bool> {
-        let mut queue = VecDeque::new();
+        for ancestor in self.iter_ancestors(a) {
+            if ancestor? == b {
+                return Ok(true);
+            }
+        }
 
-        queue.push_back(a);
+        Ok(false)
+    }
+
+    /// Count the hashes in the history of `a` that aren't in the history
+    /// of `b`, and the other way around, like how far a branch is ahead of
+    /// and behind where it is on a remote.
+    pub fn ahead_behind(&self, a: ObjectHash, b: ObjectHash) -> Result<(usize, usize)> {
+        let from_a = self.reachable([a], |_| false)?;
+        let from_b = self.reachable([b], |_| false)?;
+
+        Ok((from_a.difference(&from_b).count(), from_b.difference(&from_a).count()))
+    }
+
+    /// Return an iterator over a hash and all of its ancestors, breadth-first.
+    /// 
+    /// Each hash is only visited once, so merges and cycles are handled.
+    /// This yields an error for any hash that isn't in the DAG.
+    pub fn iter_ancestors(&self, hash: ObjectHash) -> Ancestors<'_> {
+        Ancestors {
+            graph: self,
+            queue: VecDeque::from([hash]),
+            seen: HashSet::from([hash])
+        }
+    }
+
+    /// Get every hash reachable from `starts` by following parents,
+    /// including the starting hashes themselves.
+    /// 
+    /// Hashes for which `skip` returns `true` are left out, and
+    /// their ancestors are only included if reachable another way.
+    pub fn reachable(
+        &self,
+        starts: impl IntoIterator<Item = ObjectHash>,
+        skip: impl Fn(ObjectHash) -> bool
+    ) -> Result<HashSet<ObjectHash>>
+    {
+        let mut found = HashSet::new();
+
+        let mut queue: VecDeque<ObjectHash> = starts.into_iter().collect();
 
         while let Some(next) = queue.pop_front() {
-            if next == b {
-                return Ok(true);
+            if found.contains(&next) || skip(next) {
+                continue;
             }
-            
+
             let parents = unwrap!(
                 self.get_parents(next),
                 "failed to get parents of hash {next:?}"
             );
 
-            queue.extend(parents.iter());
+            found.insert(next);
+
+            queue.extend(parents);
         }
 
-        Ok(false)
+        Ok(found)
     }
 
-    /// Get the number of nodes in the DAG.
-    pub fn size(&self) -> usize {
-        self.links.len()
+    /// Get the children of a hash, if the hash is present.
+    pub fn children(&self, hash: ObjectHash) -> Option<Parents> {
+        if !self.contains(hash) {
+            return None;
+        }
+
+        Some(self.children.get(&hash).cloned().unwrap_or_default())
     }
 
-    /// Return a [`HashMap`] inverting all the links in this [`Graph`].
+    /// Get every hash that has `hash` as an ancestor, not including `hash` itself.
+    pub fn descendants(&self, hash: ObjectHash) -> HashSet<ObjectHash> {
+        self.iter_descendants(hash).collect()
+    }
+
+    /// Return an iterator over every hash descended from `hash`, breadth-first,
+    /// not including `hash` itself.
     /// 
-    /// This clones the entire graph for convenience.
-    pub fn invert(&self) -> Graph {
-        let mut graph = Graph::new();
+    /// Like [`Graph::iter_ancestors`], each hash is only visited once, so
+    /// a merge reached along two paths isn't counted twice.
+    pub fn iter_descendants(&self, hash: ObjectHash) -> Descendants<'_> {
+        let mut descendants = Descendants {
+            graph: self,
+            queue: VecDeque::new(),
+            seen: HashSet::from([hash])
+        };
 
-        for (hash, parents) in self.iter() {
-            graph.insert_orphan(hash);
+        descendants.visit_children(hash);
+
+        descendants
+    }
+
+    /// Get every hash descended from `hash`, sorted so that each comes
+    /// after any of its parents that are also descended from `hash`.
+    /// 
+    /// This is the order to rewrite a subtree in, so that every hash is
+    /// rewritten once, after all of the parents it has in the subtree.
+    /// Hashes that are ready at the same time are ordered by hash,
+    /// and any caught in a cycle are left out.
+    pub fn sorted_descendants(&self, hash: ObjectHash) -> Vec<ObjectHash> {
+        let subtree = self.descendants(hash);
+
+        let mut waiting_on: HashMap<ObjectHash, usize> = subtree
+            .iter()
+            .map(|&node| {
+                let count = self.links[&node]
+                    .iter()
+                    .filter(|parent| subtree.contains(parent))
+                    .count();
+
+                (node, count)
+            })
+            .collect();
 
+        let mut ready: BTreeSet<ObjectHash> = waiting_on
+            .iter()
+            .filter(|(_, count)| **count == 0)
+            .map(|(&node, _)| node)
+            .collect();
+
+        let mut sorted = Vec::with_capacity(subtree.len());
+
+        while let Some(node) = ready.pop_first() {
+            sorted.push(node);
+
+            for child in self.children.get(&node).into_iter().flatten() {
+                if let Some(count) = waiting_on.get_mut(child) {
+                    *count -= 1;
+
+                    if *count == 0 {
+                        ready.insert(*child);
+                    }
+                }
+            }
+        }
+
+        sorted
+    }
+
+    /// Find the best common ancestor of `a` and `b`.
+    /// 
+    /// If one is an ancestor of the other, that one is returned.
+    /// When there are several candidates, as can happen after criss-cross
+    /// merges, the one closest to both hashes is picked.
+    pub fn merge_base(&self, a: ObjectHash, b: ObjectHash) -> Result<Option<ObjectHash>> {
+        let distances_a = self.distances_from(a)?;
+        let distances_b = self.distances_from(b)?;
+
+        let common: HashSet<ObjectHash> = distances_a
+            .keys()
+            .filter(|hash| distances_b.contains_key(hash))
+            .copied()
+            .collect();
+
+        // Ancestors of other common ancestors are never the best choice.
+        let mut redundant = HashSet::new();
+
+        for &hash in &common {
+            if redundant.contains(&hash) {
+                continue;
+            }
+
+            for ancestor in self.iter_ancestors(hash).skip(1) {
+                redundant.insert(ancestor?);
+            }
+        }
+
+        let best = common
+            .into_iter()
+            .filter(|hash| !redundant.contains(hash))
+            .min_by_key(|hash| (distances_a[hash].max(distances_b[hash]), *hash));
+
+        Ok(best)
+    }
+
+    /// Get the shortest number of steps from `hash` to each of its ancestors.
+    fn distances_from(&self, hash: ObjectHash) -> Result<HashMap<ObjectHash, usize>> {
+        let mut distances = HashMap::from([(hash, 0)]);
+
+        let mut queue = VecDeque::from([hash]);
+
+        while let Some(next) = queue.pop_front() {
+            let parents = unwrap!(
+                self.get_parents(next),
+                "failed to get parents of hash {next:?}"
+            );
+
+            let distance = distances[&next] + 1;
+
+            for &parent in parents {
+                if let Entry::Vacant(entry) = distances.entry(parent) {
+                    entry.insert(distance);
+
+                    queue.push_back(parent);
+                }
+            }
+        }
+
+        Ok(distances)
+    }
+
+    /// Sort every hash in the DAG so that parents come before their children.
+    /// 
+    /// Hashes that are ready at the same time are ordered by hash,
+    /// so the result is the same every time. This fails if the
+    /// graph has a cycle, or a parent that isn't in the DAG.
+    pub fn topo_sort(&self) -> Result<Vec<ObjectHash>> {
+        let mut waiting_on: HashMap<ObjectHash, usize> = HashMap::new();
+
+        for (hash, parents) in self.iter() {
             for &parent in parents {
-                graph.insert(parent, hash);
+                if !self.contains(parent) {
+                    bail!("snapshot {hash} has a parent {parent} that is not in the graph");
+                }
+            }
+
+            waiting_on.insert(hash, parents.len());
+        }
+
+        let mut ready: BTreeSet<ObjectHash> = waiting_on
+            .iter()
+            .filter(|(_, count)| **count == 0)
+            .map(|(&hash, _)| hash)
+            .collect();
+
+        let mut sorted = Vec::with_capacity(self.size());
+
+        while let Some(hash) = ready.pop_first() {
+            sorted.push(hash);
+
+            for &child in self.children.get(&hash).into_iter().flatten() {
+                let count = waiting_on.get_mut(&child).unwrap();
+
+                *count -= 1;
+
+                if *count == 0 {
+                    ready.insert(child);
+                }
             }
         }
 
-        graph
+        if sorted.len() < self.size() {
+            let stuck = waiting_on
+                .iter()
+                .filter(|(_, count)| **count > 0)
+                .map(|(&hash, _)| hash)
+                .min()
+                .unwrap();
+
+            bail!("history has a cycle involving snapshot {stuck}");
+        }
+
+        Ok(sorted)
+    }
+
+    /// Get the number of nodes in the DAG.
+    pub fn size(&self) -> usize {
+        self.links.len()
+    }
+
+    /// Return a [`Graph`] with all the links in this one reversed.
+    /// 
+    /// This clones the entire graph, so use [`Graph::children`]
+    /// or [`Graph::descendants`] to follow links one way.
+    pub fn invert(&self) -> Graph {
+        Graph {
+            links: self.children.clone(),
+            children: self.links.clone()
+        }
     }
 
     pub fn extend(&mut self, other: &Graph) {
@@ -139,9 +479,67 @@ impl Graph {
     }
 }
 
+/// An iterator over a hash and its ancestors, made by [`Graph::iter_ancestors`].
+pub struct Ancestors<'a> {
+    graph: &'a Graph,
+    queue: VecDeque<ObjectHash>,
+    seen: HashSet<ObjectHash>
+}
+
+impl Iterator for Ancestors<'_> {
+    type Item = Result<ObjectHash>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let next = self.queue.pop_front()?;
+
+        let Some(parents) = self.graph.get_parents(next) else {
+            self.queue.clear();
+
+            return Some(Err(eyre!("failed to get parents of hash {next:?}")));
+        };
+
+        for &parent in parents {
+            if self.seen.insert(parent) {
+                self.queue.push_back(parent);
+            }
+        }
+
+        Some(Ok(next))
+    }
+}
+
+/// An iterator over the descendants of a hash, made by [`Graph::iter_descendants`].
+pub struct Descendants<'a> {
+    graph: &'a Graph,
+    queue: VecDeque<ObjectHash>,
+    seen: HashSet<ObjectHash>
+}
+
+impl Descendants<'_> {
+    fn visit_children(&mut self, hash: ObjectHash) {
+        for &child in self.graph.children.get(&hash).into_iter().flatten() {
+            if self.seen.insert(child) {
+                self.queue.push_back(child);
+            }
+        }
+    }
+}
+
+impl Iterator for Descendants<'_> {
+    type Item = ObjectHash;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let next = self.queue.pop_front()?;
+
+        self.visit_children(next);
+
+        Some(next)
+    }
+}
+
 impl From<RawGraph> for Graph {
     fn from(value: RawGraph) -> Self {
-        Graph { links: value }
+        Graph { children: invert_links(&value), links: value }
     }
 }
 
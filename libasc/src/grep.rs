@@ -0,0 +1,116 @@
+use std::collections::{HashMap, HashSet};
+
+use eyre::Result;
+use rayon::prelude::*;
+use regex::{Regex, RegexBuilder};
+use relative_path::RelativePathBuf;
+
+use crate::{hash::ObjectHash, repository::Repository, unwrap};
+
+/// A line of a file that matched the pattern given to [`search`].
+#[derive(Clone, Debug)]
+pub struct Match {
+    /// The snapshot the file was found in.
+    pub snapshot: ObjectHash,
+    pub path: RelativePathBuf,
+
+    /// The line number, counting from 1.
+    pub line: usize,
+    pub content: String
+}
+
+/// Make a pattern for [`search`] from a regular expression.
+pub fn pattern(pattern: &str, ignore_case: bool) -> Result<Regex> {
+    let regex = unwrap!(
+        RegexBuilder::new(pattern)
+            .case_insensitive(ignore_case)
+            .build(),
+        "invalid pattern: {pattern:?}"
+    );
+
+    Ok(regex)
+}
+
+/// Find the lines matching `pattern` in each piece of content.
+///
+/// Content is decompressed and searched on as many threads as the config allows.
+fn search_contents(
+    repo: &Repository,
+    contents: HashSet<ObjectHash>,
+    pattern: &Regex
+) -> Result<HashMap<ObjectHash, Vec<(usize, String)>>>
+{
+    repo.with_thread_pool(|| search_in_parallel(repo, contents, pattern))?
+}
+
+fn search_in_parallel(
+    repo: &Repository,
+    contents: HashSet<ObjectHash>,
+    pattern: &Regex
+) -> Result<HashMap<ObjectHash, Vec<(usize, String)>>>
+{
+    contents
+        .into_par_iter()
+        .map(|hash| {
+            let text = repo.fetch_string_content(hash)?;
+
+            let lines = text
+                .lines()
+                .enumerate()
+                .filter(|(_, line)| pattern.is_match(line))
+                .map(|(index, line)| (index + 1, line.to_string()))
+                .collect();
+
+            Ok((hash, lines))
+        })
+        .collect()
+}
+
+/// Search the files in some snapshots for lines matching `pattern`.
+///
+/// A file is only searched in the first snapshot it is found in with
+/// that content, so when searching history from newest to oldest, each
+/// match is reported against the newest snapshot that still had it.
+/// Matches are in the order of the snapshots given, then by path.
+pub fn search(
+    repo: &Repository,
+    snapshots: impl IntoIterator<Item = ObjectHash>,
+    pattern: &Regex
+) -> Result<Vec<Match>>
+{
+    let mut seen: HashSet<(RelativePathBuf, ObjectHash)> = HashSet::new();
+
+    let mut files: Vec<(ObjectHash, RelativePathBuf, ObjectHash)> = vec![];
+
+    for hash in snapshots {
+        let snapshot = repo.fetch_snapshot(hash)?;
+
+        for (path, &content) in &snapshot.files {
+            if seen.insert((path.clone(), content)) {
+                files.push((hash, path.clone(), content));
+            }
+        }
+    }
+
+    let contents = files
+        .iter()
+        .map(|&(_, _, content)| content)
+        .collect();
+
+    let found = search_contents(repo, contents, pattern)?;
+
+    let mut matches = vec![];
+
+    for (snapshot, path, content) in files {
+        for (line, text) in &found[&content] {
+            matches.push(Match {
+                snapshot,
+                path: path.clone(),
+                line: *line,
+                content: text.clone()
+            });
+        }
+    }
+
+    Ok(matches)
+}
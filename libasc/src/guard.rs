@@ -0,0 +1,90 @@
+//! Whether commands change the repository and the working directory, or
+//! only say what they would change, for `--dry-run`.
+//!
+//! There is one [`WriteGuard`] for the whole process, got with [`guard`].
+//! The places that write to the disk for a command ask it first, and when
+//! it is set to a dry run, it records a [`Change`] for each of them instead
+//! of letting them go ahead. Objects added to the blob store aren't recorded,
+//! as nothing can see them until a ref points to them.
+
+use std::{sync::{atomic::{AtomicBool, Ordering}, Mutex}};
+
+use relative_path::RelativePathBuf;
+
+use crate::{hash::ObjectHash, refs::{Head, Ref}};
+
+/// Something a command would have changed, if it wasn't a dry run.
+#[derive(Clone, Debug)]
+pub enum Change {
+    /// A file in the working directory would be written, or overwritten.
+    Write(RelativePathBuf),
+
+    /// A file in the working directory would be removed.
+    Remove(RelativePathBuf),
+
+    /// An object would be deleted from the repository's object store.
+    DeleteObject(ObjectHash),
+
+    /// A ref would be created (with no `old`), moved or deleted (with no `new`).
+    MoveRef {
+        r: Ref,
+        old: Option<ObjectHash>,
+        new: Option<ObjectHash>
+    },
+
+    /// The head would be put somewhere else.
+    MoveHead {
+        head: Head,
+        hash: ObjectHash
+    }
+}
+
+/// Lets writes go ahead, or records them as [`Change`]s during a dry run.
+pub struct WriteGuard {
+    dry_run: AtomicBool,
+    changes: Mutex<Vec<Change>>
+}
+
+static GUARD: WriteGuard = WriteGuard {
+    dry_run: AtomicBool::new(false),
+    changes: Mutex::new(vec![])
+};
+
+/// Get the guard for this process.
+pub fn guard() -> &'static WriteGuard {
+    &GUARD
+}
+
+impl WriteGuard {
+    /// Only record changes from now on, instead of making them.
+    pub fn set_dry_run(&self, dry_run: bool) {
+        self.dry_run.store(dry_run, Ordering::Relaxed);
+    }
+
+    /// Check if changes are only being recorded.
+    pub fn is_dry_run(&self) -> bool {
+        self.dry_run.load(Ordering::Relaxed)
+    }
+
+    /// Check if a write can go ahead, recording the change
+    /// made by `change` instead if this is a dry run.
+    pub fn allow(&self, change: impl FnOnce() -> Change) -> bool {
+        if !self.is_dry_run() {
+            return true;
+        }
+
+        self.changes.lock().unwrap().push(change());
+
+        false
+    }
+
+    /// Check if a write that isn't worth reporting can go ahead.
+    pub fn allow_quietly(&self) -> bool {
+        !self.is_dry_run()
+    }
+
+    /// Take the changes recorded so far, in the order they were made.
+    pub fn take_changes(&self) -> Vec<Change> {
+        std::mem::take(&mut self.changes.lock().unwrap())
+    }
+}
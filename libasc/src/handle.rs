@@ -0,0 +1,322 @@
+//! A way for other programs, like editors and bots, to change
+//! a repository without knowing how the commands go about it.
+//!
+//! [`Repository`] has everything the commands are built from, but leaves
+//! it to them to check for unsaved changes, record actions for `asc undo`,
+//! bring the working directory along when the head moves, and save at the
+//! end. Each method on a [`RepositoryHandle`] does all of that, so the
+//! repository is always left as one of the commands would leave it. If a
+//! method can't go ahead safely, it fails before changing anything.
+
+use std::{collections::{BTreeMap, BTreeSet}, path::Path};
+
+use eyre::{bail, Result};
+use relative_path::RelativePathBuf;
+use threeway_merge::{merge_strings, MergeOptions};
+
+use crate::{action::Action, hash::ObjectHash, mode::{self, FileMode}, paths, refname, refs::Ref, repository::{Repository, UnsavedChanges}, set, snapshot::Snapshot, unwrap};
+
+/// What [`RepositoryHandle::merge`] did.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MergeOutcome {
+    /// The version was already in the history of the current one.
+    UpToDate,
+
+    /// The current version was in the history of the other,
+    /// so the head was moved forward to it.
+    FastForward(ObjectHash),
+
+    /// A snapshot was made with both versions as its parents.
+    Merged(ObjectHash),
+
+    /// These files were changed differently by both versions,
+    /// so nothing was merged.
+    Conflicts(Vec<RelativePathBuf>)
+}
+
+/// A repository that is only changed in ways that keep it consistent.
+pub struct RepositoryHandle {
+    repo: Repository
+}
+
+/// The files of a merge, or the paths that conflicted.
+struct MergedFiles {
+    files: BTreeMap<RelativePathBuf, ObjectHash>,
+    modes: BTreeMap<RelativePathBuf, FileMode>,
+    conflicts: Vec<RelativePathBuf>
+}
+
+impl RepositoryHandle {
+    /// Open the repository whose root directory is `root_dir`.
+    pub fn open(root_dir: impl AsRef<Path>) -> Result<RepositoryHandle> {
+        Ok(RepositoryHandle { repo: Repository::load_from(root_dir)? })
+    }
+
+    /// Get the repository, to read from.
+    pub fn repository(&self) -> &Repository {
+        &self.repo
+    }
+
+    /// Stop using the handle, getting the repository back.
+    pub fn into_inner(self) -> Repository {
+        self.repo
+    }
+
+    fn refuse_unsaved_changes(&self, action: impl Into<String>) -> Result<()> {
+        if self.repo.has_unsaved_changes()? {
+            return Err(UnsavedChanges { action: action.into() }.into());
+        }
+
+        Ok(())
+    }
+
+    /// Stage `paths` as they are in the working directory, unstaging those
+    /// that were deleted, then commit every staged file to the current branch.
+    pub fn commit(&mut self, paths: &[RelativePathBuf], message: &str) -> Result<ObjectHash> {
+        if message.trim().is_empty() {
+            bail!("cannot commit with an empty message.");
+        }
+
+        self.repo.config.commit.validate_message(message)?;
+
+        let (present, deleted): (Vec<RelativePathBuf>, Vec<RelativePathBuf>) = paths
+            .iter()
+            .map(|path| path.normalize())
+            .partition(|path| mode::exists(&paths::on_disk(path, &self.repo.root_dir)));
+
+        if let Some(path) = deleted.iter().find(|&path| !self.repo.staging.contains(path)) {
+            bail!("cannot commit {path}: it isn't in the working directory, or staged.");
+        }
+
+        for path in &deleted {
+            self.repo.staging.unstage(path);
+        }
+
+        self.repo.stage_paths(&present)?;
+
+        let current = self.repo.fetch_current_snapshot()?;
+
+        if self.repo.staging.files() == &current.files && self.repo.staging.modes() == &current.modes {
+            bail!("cannot commit: nothing changed since {}.", current.hash);
+        }
+
+        if self.repo.staging.is_empty() {
+            bail!("cannot commit: no files are tracked.");
+        }
+
+        let branch = self.repo.current_branch().map(String::from);
+
+        let snapshot = self.repo.commit_current_state(message.to_string(), branch)?;
+
+        let hash = self.repo.append_snapshot(snapshot)?;
+
+        self.repo.save()?;
+
+        Ok(hash)
+    }
+
+    /// Create a branch called `name` at `rev`, without switching to it.
+    pub fn create_branch(&mut self, name: &str, rev: &str) -> Result<ObjectHash> {
+        if self.repo.refs.branches().contains(name) {
+            bail!("cannot create branch {name:?}: it already exists.");
+        }
+
+        unwrap!(refname::check_branch(name, &self.repo.config), "cannot create branch {name:?}");
+
+        let hash = self.repo.normalise_version(rev)?;
+
+        self.repo.refs.branches_mut().create(name.to_string(), hash);
+
+        self.repo.action_history.push(Action::CreateBranch { name: name.to_string(), hash });
+
+        self.repo.save()?;
+
+        Ok(hash)
+    }
+
+    /// Switch to `rev`, bringing the working directory along. A branch
+    /// name puts the head on that branch, and anything else detaches it.
+    ///
+    /// This returns the stash ID of any backup made of files
+    /// that weren't tracked but would have been overwritten.
+    pub fn checkout(&mut self, rev: &str) -> Result<Option<usize>> {
+        let before = self.repo.current_hash();
+
+        let hash = self.repo.normalise_version(rev)?;
+
+        self.refuse_unsaved_changes(format!("check out {rev}"))?;
+
+        let snapshot = self.repo.fetch_snapshot(hash)?;
+
+        let backup = self.repo.replace_cwd_with_snapshot(&snapshot)?;
+
+        self.repo.action_history.push(Action::SwitchVersion { before, after: hash });
+
+        match self.repo.refs.lookup(rev) {
+            Some((Ref::Branch(name), _)) => {
+                let name = name.clone();
+
+                self.repo.refs.attach_head(&name);
+            },
+
+            _ => self.repo.refs.detach_head(hash)
+        }
+
+        self.repo.save()?;
+
+        Ok(backup)
+    }
+
+    /// Merge the files of `ours` and `theirs`, which both come from `base`.
+    fn merge_files(&self, base: &Snapshot, ours: &Snapshot, theirs: &Snapshot, options: &MergeOptions) -> Result<MergedFiles> {
+        let paths: BTreeSet<&RelativePathBuf> = ours.files
+            .keys()
+            .chain(theirs.files.keys())
+            .collect();
+
+        let mut merged = MergedFiles {
+            files: BTreeMap::new(),
+            modes: BTreeMap::new(),
+            conflicts: vec![]
+        };
+
+        for path in paths {
+            let original = base.files.get(path).copied();
+            let our_hash = ours.files.get(path).copied();
+            let their_hash = theirs.files.get(path).copied();
+
+            let hash = if our_hash == their_hash || their_hash == original {
+                our_hash
+            }
+            else if our_hash == original {
+                their_hash
+            }
+            else if let (Some(our_hash), Some(their_hash)) = (our_hash, their_hash) {
+                let original = match original {
+                    Some(hash) => self.repo.fetch_string_content(hash)?,
+                    None => String::new()
+                };
+
+                let result = unwrap!(
+                    merge_strings(
+                        &original,
+                        &self.repo.fetch_string_content(our_hash)?,
+                        &self.repo.fetch_string_content(their_hash)?,
+                        options
+                    ),
+                    "failed to merge {path}"
+                );
+
+                if !result.is_clean_merge() {
+                    merged.conflicts.push(path.clone());
+
+                    continue;
+                }
+
+                Some(self.repo.save_content_raw(&result.content)?)
+            }
+            else {
+                // Deleted on one side, and changed on the other.
+                merged.conflicts.push(path.clone());
+
+                continue;
+            };
+
+            let Some(hash) = hash else {
+                continue;
+            };
+
+            // A mode only changed on one side takes that side's mode.
+            let mode = match our_hash {
+                Some(_) if ours.mode(path) != base.mode(path) || their_hash.is_none() => ours.mode(path),
+                _ => theirs.mode(path)
+            };
+
+            merged.files.insert(path.clone(), hash);
+            merged.modes.insert(path.clone(), mode);
+        }
+
+        Ok(merged)
+    }
+
+    /// Merge `rev` into the current version, committing the result with
+    /// `message`, or moving forward to `rev` if there is nothing to merge.
+    ///
+    /// If any files conflict, nothing is changed, and they are returned instead.
+    pub fn merge(&mut self, rev: &str, message: &str) -> Result<MergeOutcome> {
+        let current = self.repo.current_hash();
+
+        let target = self.repo.normalise_version(rev)?;
+
+        self.refuse_unsaved_changes(format!("merge {rev}"))?;
+
+        let base = unwrap!(
+            self.repo.history.merge_base(current, target)?,
+            "cannot merge {rev}: it has no history in common with {current}."
+        );
+
+        if base == target {
+            return Ok(MergeOutcome::UpToDate);
+        }
+
+        if base == current {
+            let snapshot = self.repo.fetch_snapshot(target)?;
+
+            self.repo.replace_cwd_with_snapshot(&snapshot)?;
+
+            self.repo.advance_head(target);
+
+            self.repo.save()?;
+
+            return Ok(MergeOutcome::FastForward(target));
+        }
+
+        if message.trim().is_empty() {
+            bail!("cannot merge with an empty message.");
+        }
+
+        self.repo.config.commit.validate_message(message)?;
+
+        let key = unwrap!(
+            self.repo.current_user().and_then(|user| user.private_key.clone()),
+            "cannot merge {rev}: no valid user."
+        );
+
+        let options = MergeOptions {
+            base_label: Some("original".to_string()),
+            ours_label: Some(self.repo.current_branch().map(String::from).unwrap_or(current.to_string())),
+            theirs_label: Some(rev.to_string()),
+
+            .. MergeOptions::default()
+        };
+
+        let merged = self.merge_files(
+            &self.repo.fetch_snapshot(base)?,
+            &self.repo.fetch_current_snapshot()?,
+            &self.repo.fetch_snapshot(target)?,
+            &options
+        )?;
+
+        if !merged.conflicts.is_empty() {
+            return Ok(MergeOutcome::Conflicts(merged.conflicts));
+        }
+
+        let snapshot = Snapshot::new_with_modes(
+            key,
+            message.to_string(),
+            self.repo.now(),
+            merged.files,
+            merged.modes,
+            set![current, target],
+            self.repo.current_branch().map(String::from)
+        );
+
+        let hash = self.repo.append_snapshot(snapshot)?;
+
+        self.repo.follow_head(current)?;
+
+        self.repo.save()?;
+
+        Ok(MergeOutcome::Merged(hash))
+    }
+}
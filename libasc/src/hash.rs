@@ -1,11 +1,39 @@
-use std::{fmt::{Debug, Display, Formatter}, hash::{DefaultHasher, Hasher}, str::FromStr};
+use std::{fmt::{Debug, Display, Formatter}, str::FromStr};
+#[cfg(feature = "sync")]
+use std::hash::{DefaultHasher, Hasher};
 
 use eyre::bail;
+#[cfg(feature = "sync")]
 use rateless_tables::Symbol;
 use serde::{Deserialize, Serialize};
 
 pub type RawObjectHash = [u8; 32];
 
+/// The number of hex characters an [`ObjectHash`] is shortened to when displayed.
+pub const SHORT_HASH_LEN: usize = 10;
+
+const HEX_DIGITS: &[u8; 16] = b"0123456789abcdef";
+
+/// Convert a single hex character to its value.
+fn hex_value(c: u8) -> Option<u8> {
+    match c {
+        b'0' ..= b'9' => Some(c - b'0'),
+        b'a' ..= b'f' => Some(c - b'a' + 10),
+        b'A' ..= b'F' => Some(c - b'A' + 10),
+        _ => None
+    }
+}
+
+/// Check that a string only contains hex characters, so it
+/// could be (part of) an [`ObjectHash`].
+pub fn validate_hex(value: &str) -> eyre::Result<()> {
+    if let Some((i, c)) = value.char_indices().find(|(_, c)| !c.is_ascii_hexdigit()) {
+        bail!("invalid character {c:?} at position {i} in hash {value:?} (expected 0-9 or a-f)");
+    }
+
+    Ok(())
+}
+
 /// A SHA-256 wrapper type used to uniquely identify content in the repository.
 #[derive(Clone, Copy, Default, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
 #[repr(transparent)]
@@ -18,7 +46,34 @@ impl ObjectHash {
     /// the hash will be shrunk to 10 characters. This instead
     /// returns the full hash.
     pub fn full(&self) -> String {
-        format!("{self:?}")
+        let hex = self.to_hex();
+
+        String::from_utf8_lossy(&hex).into_owned()
+    }
+
+    /// Write the hash as hex into a buffer on the stack.
+    pub fn to_hex(&self) -> [u8; 64] {
+        let mut buf = [0; 64];
+
+        for (i, byte) in self.0.iter().enumerate() {
+            buf[i * 2] = HEX_DIGITS[(byte >> 4) as usize];
+            buf[i * 2 + 1] = HEX_DIGITS[(byte & 0xf) as usize];
+        }
+
+        buf
+    }
+
+    /// Compare two hashes in constant time.
+    /// 
+    /// This should be used over `==` when comparing hashes that an
+    /// attacker could use timing to learn about, like in logins.
+    pub fn ct_eq(&self, other: &ObjectHash) -> bool {
+        let diff = self.0
+            .iter()
+            .zip(other.0.iter())
+            .fold(0, |acc, (a, b)| acc | (a ^ b));
+
+        std::hint::black_box(diff) == 0
     }
 
     /// Get the individual bytes that make up this `ObjectHash`.
@@ -35,13 +90,18 @@ impl From<RawObjectHash> for ObjectHash {
 
 impl Display for ObjectHash {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", &self.full()[..10])
+        let hex = self.to_hex();
+
+        // Hex digits are always valid UTF-8.
+        f.write_str(std::str::from_utf8(&hex[..SHORT_HASH_LEN]).unwrap())
     }
 }
 
 impl Debug for ObjectHash {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", hex::encode(self.0))
+        let hex = self.to_hex();
+
+        f.write_str(std::str::from_utf8(&hex).unwrap())
     }
 }
 
@@ -49,16 +109,24 @@ impl FromStr for ObjectHash {
     type Err = eyre::Report;
     
     fn from_str(value: &str) -> Result<Self, Self::Err> {
-        let bytes = hex::decode(value)?;
+        validate_hex(value)?;
+
+        if value.len() != 64 {
+            bail!("invalid length of hash {value:?}: {} characters (expected 64)", value.len());
+        }
+
+        let mut raw = [0; 32];
 
-        if bytes.len() != 32 {
-            bail!("invalid length of string hash: {} (expected 32)", bytes.len());
+        for (i, pair) in value.as_bytes().chunks_exact(2).enumerate() {
+            // Both characters were checked by `validate_hex` above.
+            raw[i] = (hex_value(pair[0]).unwrap() << 4) | hex_value(pair[1]).unwrap();
         }
 
-        Ok(ObjectHash(bytes[..].try_into()?))
+        Ok(ObjectHash(raw))
     }
 }
 
+#[cfg(feature = "sync")]
 impl Symbol for ObjectHash {
     fn get_hash(&self) -> u64 {
         let mut hasher = DefaultHasher::new();
@@ -0,0 +1,161 @@
+use chrono::{DateTime, Utc};
+use eyre::{bail, Result};
+use glob_match::glob_match;
+use regex::Regex;
+
+use crate::{hash::ObjectHash, key::PublicKey, repository::Repository, snapshot::Snapshot, unwrap};
+
+/// A condition a snapshot has to meet to be yielded by a [`HistoryWalk`].
+#[derive(Clone, Debug)]
+pub enum Filter {
+    /// Snapshots made by the user with this key.
+    Author(PublicKey),
+
+    /// Snapshots that add, change or remove a file matching this glob,
+    /// compared to the snapshot before them in the walk.
+    ///
+    /// A glob also matches everything in a directory with that name.
+    Path(String),
+
+    /// Snapshots made at or after this time.
+    Since(DateTime<Utc>),
+
+    /// Snapshots made at or before this time.
+    Until(DateTime<Utc>),
+
+    /// Snapshots with a message matching this pattern.
+    Grep(Regex)
+}
+
+impl Filter {
+    /// Make a filter for messages matching a regular expression.
+    pub fn grep(pattern: &str) -> Result<Filter> {
+        let regex = unwrap!(
+            Regex::new(pattern),
+            "invalid pattern: {pattern:?}"
+        );
+
+        Ok(Filter::Grep(regex))
+    }
+
+    fn matches(&self, snapshot: &Snapshot, previous: Option<&Snapshot>) -> bool {
+        match self {
+            Filter::Author(key) => snapshot.author == *key,
+
+            Filter::Path(pattern) => {
+                let in_dir = format!("{}/**", pattern.trim_end_matches('/'));
+
+                let matches = |path: &str| glob_match(pattern, path) || glob_match(&in_dir, path);
+
+                let Some(previous) = previous else {
+                    return snapshot.files.keys().any(|path| matches(path.as_str()));
+                };
+
+                let changed = snapshot.files
+                    .iter()
+                    .any(|(path, hash)| previous.files.get(path) != Some(hash) && matches(path.as_str()));
+
+                let removed = previous.files
+                    .keys()
+                    .any(|path| !snapshot.files.contains_key(path) && matches(path.as_str()));
+
+                changed || removed
+            }
+
+            Filter::Since(datetime) => snapshot.timestamp >= *datetime,
+
+            Filter::Until(datetime) => snapshot.timestamp <= *datetime,
+
+            Filter::Grep(regex) => regex.is_match(&snapshot.message)
+        }
+    }
+}
+
+/// Walk back through the history from a snapshot, yielding
+/// the snapshots that meet every filter, newest first.
+///
+/// Merges are followed through the parent on the same
+/// branch, in the same way as [`Repository::first_parent`].
+pub struct HistoryWalk<'repo> {
+    repo: &'repo Repository,
+    next: Option<ObjectHash>,
+    filters: Vec<Filter>,
+
+    /// The parent of the last snapshot, if it had to be read for a filter.
+    parent: Option<Snapshot>
+}
+
+impl<'repo> HistoryWalk<'repo> {
+    /// Start a walk at the given snapshot.
+    pub fn new(repo: &'repo Repository, start: ObjectHash) -> HistoryWalk<'repo> {
+        HistoryWalk {
+            repo,
+            next: Some(start),
+            filters: vec![],
+            parent: None
+        }
+    }
+
+    /// Only yield snapshots that also meet this filter.
+    pub fn filter(mut self, filter: Filter) -> HistoryWalk<'repo> {
+        self.filters.push(filter);
+
+        self
+    }
+
+    fn needs_parent(&self) -> bool {
+        self.filters
+            .iter()
+            .any(|filter| matches!(filter, Filter::Path(_)))
+    }
+
+    fn fetch(&mut self, hash: ObjectHash) -> Result<Snapshot> {
+        if let Some(parent) = self.parent.take_if(|parent| parent.hash == hash) {
+            return Ok(parent);
+        }
+
+        self.repo.fetch_snapshot(hash)
+    }
+
+    fn step(&mut self) -> Result<Option<Snapshot>> {
+        while let Some(hash) = self.next.take() {
+            if !self.repo.history.contains(hash) {
+                bail!("snapshot hash {hash} is not referenced in the snapshot tree.");
+            }
+
+            let snapshot = self.fetch(hash)?;
+
+            self.next = self.repo.first_parent(&snapshot)?;
+
+            self.parent = match self.next {
+                Some(parent) if self.needs_parent() => Some(self.repo.fetch_snapshot(parent)?),
+                _ => None
+            };
+
+            let previous = self.parent.as_ref();
+
+            if self.filters.iter().all(|filter| filter.matches(&snapshot, previous)) {
+                return Ok(Some(snapshot));
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+impl Iterator for HistoryWalk<'_> {
+    type Item = Result<Snapshot>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.step() {
+            Ok(snapshot) => snapshot.map(Ok),
+
+            // Stop after an error, rather than trying the same snapshot again.
+            Err(e) => {
+                self.next = None;
+
+                Some(Err(e))
+            }
+        }
+    }
+}
@@ -0,0 +1,187 @@
+use std::{fmt::{Display, Formatter, Result as FmtResult}, fs, path::PathBuf, str::FromStr, time::Duration};
+
+use chrono::{DateTime, Utc};
+use eyre::{bail, Result};
+use rand::random;
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+
+use crate::{hash::ObjectHash, key::{PrivateKey, PublicKey, Signature}, repository::Repository, unwrap, user::User, utils::{hash_raw_bytes, load_as_msgpack, save_as_msgpack}};
+
+/// How long an invitation can be accepted for after it is made.
+pub static INVITATION_LIFETIME: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// An invitation for someone to join a repository as a new user.
+///
+/// This is shared with the invitee as a token. It holds no keys: the
+/// invitee makes their own and sends back a [`JoinRequest`], so a private
+/// key never has to leave the invitee's machine.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Invitation {
+    pub name: String,
+    pub project_code: ObjectHash,
+
+    /// A random code that ties a [`JoinRequest`] to this invitation.
+    pub code: ObjectHash,
+    pub created: DateTime<Utc>
+}
+
+impl Invitation {
+    /// Create an invitation for a new user called `name`.
+    pub fn new(name: String, project_code: ObjectHash) -> Invitation {
+        let code = hash_raw_bytes(random::<[u8; 32]>());
+
+        Invitation {
+            name,
+            project_code,
+            code,
+            created: Utc::now()
+        }
+    }
+
+    /// Check if the invitation is too old to be accepted.
+    pub fn is_expired(&self) -> bool {
+        let age = (Utc::now() - self.created).to_std().unwrap_or_default();
+
+        age > INVITATION_LIFETIME
+    }
+
+    /// Accept the invitation with the invitee's own key.
+    pub fn accept(&self, key: &mut PrivateKey) -> JoinRequest {
+        JoinRequest {
+            name: self.name.clone(),
+            code: self.code,
+            signature: key.sign(self.code.as_bytes())
+        }
+    }
+}
+
+/// An invitee's answer to an [`Invitation`], holding the public key
+/// of the account they made. It is signed with the matching private key.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct JoinRequest {
+    pub name: String,
+    pub code: ObjectHash,
+    pub signature: Signature
+}
+
+impl JoinRequest {
+    /// Get the public key the invitee wants to use.
+    pub fn public_key(&self) -> PublicKey {
+        self.signature.key()
+    }
+
+    /// Check the request against the invitation it answers,
+    /// returning the account to add to the repository.
+    pub fn verify(&self, invitation: &Invitation) -> Result<User> {
+        if self.code != invitation.code {
+            bail!("join request does not answer this invitation.");
+        }
+
+        if self.name != invitation.name {
+            bail!("join request is for {:?}, but the invitation was for {:?}.", self.name, invitation.name);
+        }
+
+        if invitation.is_expired() {
+            bail!("invitation for {:?} has expired.", invitation.name);
+        }
+
+        unwrap!(
+            self.signature.check(self.code.as_bytes()),
+            "join request for {:?} has an invalid signature.", self.name
+        );
+
+        Ok(User {
+            name: self.name.clone(),
+            public_key: self.public_key(),
+            private_key: None,
+            closed: false
+        })
+    }
+}
+
+fn encode_token<T: Serialize>(value: &T, f: &mut Formatter<'_>) -> FmtResult {
+    let bytes = rmp_serde::to_vec(value).map_err(|_| std::fmt::Error)?;
+
+    write!(f, "{}", hex::encode(bytes))
+}
+
+fn decode_token<T: DeserializeOwned>(token: &str, kind: &str) -> Result<T> {
+    let bytes = unwrap!(
+        hex::decode(token.trim()),
+        "{kind} is not valid hex"
+    );
+
+    let value = unwrap!(
+        rmp_serde::from_slice(&bytes),
+        "{kind} could not be read"
+    );
+
+    Ok(value)
+}
+
+impl Display for Invitation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        encode_token(self, f)
+    }
+}
+
+impl FromStr for Invitation {
+    type Err = eyre::Report;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        decode_token(value, "invitation")
+    }
+}
+
+impl Display for JoinRequest {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        encode_token(self, f)
+    }
+}
+
+impl FromStr for JoinRequest {
+    type Err = eyre::Report;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        decode_token(value, "join request")
+    }
+}
+
+fn invitation_path(repo: &Repository, code: ObjectHash) -> PathBuf {
+    repo.invitations_dir().join(code.full())
+}
+
+/// Keep an invitation in the repository until it is answered.
+pub fn save_invitation(repo: &Repository, invitation: &Invitation) -> Result<()> {
+    let dir = repo.invitations_dir();
+
+    unwrap!(
+        fs::create_dir_all(&dir),
+        "failed to create directory: {}", dir.display()
+    );
+
+    save_as_msgpack(invitation, invitation_path(repo, invitation.code))
+}
+
+/// Find a pending invitation by its code.
+pub fn load_invitation(repo: &Repository, code: ObjectHash) -> Result<Option<Invitation>> {
+    let path = invitation_path(repo, code);
+
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    load_as_msgpack(path).map(Some)
+}
+
+/// Remove a pending invitation, so it can't be used again.
+pub fn remove_invitation(repo: &Repository, code: ObjectHash) -> Result<()> {
+    let path = invitation_path(repo, code);
+
+    unwrap!(
+        fs::remove_file(&path),
+        "failed to remove invitation: {}", path.display()
+    );
+
+    Ok(())
+}
@@ -1,18 +1,27 @@
 use std::{fmt::{Debug, Display, Formatter, Result as FmtResult}, hash::Hash, ops::{Deref, DerefMut}};
+#[cfg(feature = "signing")]
+use std::str::FromStr;
 
+#[cfg(feature = "signing")]
 use crate::unwrap;
 
-use ecdsa::{SigningKey, VerifyingKey, signature::{SignerMut, Verifier}};
+use ecdsa::{VerifyingKey, signature::Verifier};
+#[cfg(feature = "signing")]
+use ecdsa::{SigningKey, signature::SignerMut};
 use eyre::Result;
 use p256::{NistP256};
+#[cfg(feature = "signing")]
 use rand::{CryptoRng, RngCore};
 use serde::{Deserialize, Serialize};
+#[cfg(feature = "signing")]
 use serde_bytes::ByteBuf;
 
 /// A private key used for creating signatures.
+#[cfg(feature = "signing")]
 #[derive(Clone, Debug)]
 pub struct PrivateKey(SigningKey<NistP256>);
 
+#[cfg(feature = "signing")]
 impl PrivateKey {
     /// Create a new random [`PrivateKey`].
     #[allow(clippy::new_without_default, reason = "keys are randomly generated")]
@@ -58,6 +67,21 @@ impl PrivateKey {
     }
 }
 
+#[cfg(feature = "signing")]
+impl FromStr for PrivateKey {
+    type Err = eyre::Report;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let bytes = unwrap!(
+            hex::decode(value),
+            "private key is not valid hex"
+        );
+
+        PrivateKey::from_bytes(&bytes)
+    }
+}
+
+#[cfg(feature = "signing")]
 impl Deref for PrivateKey {
     type Target = SigningKey::<NistP256>;
 
@@ -66,24 +90,28 @@ impl Deref for PrivateKey {
     }
 }
 
+#[cfg(feature = "signing")]
 impl PartialEq for PrivateKey {
     fn eq(&self, other: &Self) -> bool {
         self.to_bytes() == other.to_bytes()
     }
 }
 
+#[cfg(feature = "signing")]
 impl Display for PrivateKey {
     fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
         write!(f, "{}", hex::encode_upper(self.to_bytes()))
     }
 }
 
+#[cfg(feature = "signing")]
 impl Hash for PrivateKey {
     fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
         state.write(&self.to_bytes());
     }
 }
 
+#[cfg(feature = "signing")]
 impl Serialize for PrivateKey {
     fn serialize<S: serde::Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
         let buf = ByteBuf::from(self.to_bytes());
@@ -92,6 +120,7 @@ impl Serialize for PrivateKey {
     }
 }
 
+#[cfg(feature = "signing")]
 impl<'de> Deserialize<'de> for PrivateKey {
     fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> std::result::Result<PrivateKey, D::Error> {
         let buf = ByteBuf::deserialize(deserializer)?;
@@ -1,15 +1,92 @@
+//! The core of asc.
+//!
+//! Without any features, this only has the object model: hashes, the history
+//! graph, snapshots, keys and signatures. The `signing` feature adds making
+//! keys and signing snapshots with them, `fs` adds repositories on disk and
+//! everything that works with them, and `sync` adds talking to remotes.
+
 pub mod action;
 pub mod change;
+pub mod clock;
 pub mod content;
+pub mod exec;
 pub mod graph;
 pub mod hash;
 pub mod key;
-pub mod repository;
+pub mod mode;
+pub mod paths;
+pub mod pin;
+pub mod semver;
 pub mod snapshot;
+pub mod staging;
 pub mod stash;
+pub mod utils;
+
+#[cfg(feature = "signing")]
+pub mod user;
+
+#[cfg(feature = "fs")]
+pub mod activity;
+#[cfg(feature = "fs")]
+pub mod ascignore;
+#[cfg(feature = "fs")]
+pub mod blame;
+#[cfg(feature = "fs")]
+pub mod blobs;
+#[cfg(feature = "fs")]
+pub mod cache;
+#[cfg(feature = "fs")]
+pub mod checkout;
+#[cfg(feature = "fs")]
+pub mod compare;
+#[cfg(feature = "fs")]
+pub mod config;
+#[cfg(feature = "fs")]
+pub mod fence;
+#[cfg(feature = "fs")]
+pub mod fsck;
+#[cfg(feature = "fs")]
+pub mod grep;
+#[cfg(feature = "fs")]
+pub mod guard;
+#[cfg(feature = "fs")]
+pub mod handle;
+#[cfg(feature = "fs")]
+pub mod history;
+#[cfg(feature = "fs")]
+pub mod invite;
+#[cfg(feature = "fs")]
+pub mod link;
+#[cfg(feature = "fs")]
+pub mod lock;
+#[cfg(feature = "fs")]
+pub mod patch;
+#[cfg(feature = "fs")]
+pub mod refname;
+#[cfg(feature = "fs")]
+pub mod refs;
+#[cfg(feature = "fs")]
+pub mod repository;
+#[cfg(feature = "fs")]
+pub mod retention;
+#[cfg(feature = "fs")]
+pub mod stats;
+#[cfg(feature = "fs")]
+pub mod storage;
+#[cfg(feature = "fs")]
 pub mod sync;
+#[cfg(feature = "fs")]
 pub mod trash;
-pub mod user;
-pub mod utils;
+#[cfg(feature = "fs")]
+pub mod watch;
+
+#[cfg(feature = "sync")]
+pub mod bundle;
+
+#[cfg(feature = "s3")]
+pub mod s3;
+
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
 
 mod macros;
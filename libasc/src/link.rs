@@ -0,0 +1,151 @@
+use std::{collections::BTreeMap, path::{Path, PathBuf}};
+
+use eyre::{bail, Result};
+use relative_path::{Component, RelativePath, RelativePathBuf};
+use serde::{Deserialize, Serialize};
+
+use crate::{hash::ObjectHash, mode, sync::remote::Remote, unwrap};
+
+/// Represents another repository embedded at a path in this one.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Link {
+    /// Where the linked repository is cloned and pulled from.
+    pub remote: Remote,
+
+    /// The snapshot in the linked repository that should be checked out.
+    pub hash: ObjectHash
+}
+
+/// A collection of linked repositories, addressed by the
+/// path they are placed at relative to the repository root.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Links {
+    inner: BTreeMap<RelativePathBuf, Link>
+}
+
+impl Links {
+    /// Create an empty [`Links`].
+    pub fn new() -> Links {
+        Links::default()
+    }
+
+    /// Add a link at a path, returning the previous link at that path if there was one.
+    pub fn add(&mut self, path: RelativePathBuf, link: Link) -> Option<Link> {
+        self.inner.insert(path.normalize(), link)
+    }
+
+    /// Get the link at a path.
+    pub fn get(&self, path: impl AsRef<RelativePath>) -> Option<&Link> {
+        self.inner.get(&path.as_ref().normalize())
+    }
+
+    /// Get the link at a path as mutable.
+    pub fn get_mut(&mut self, path: impl AsRef<RelativePath>) -> Option<&mut Link> {
+        self.inner.get_mut(&path.as_ref().normalize())
+    }
+
+    /// Remove the link at a path.
+    pub fn remove(&mut self, path: impl AsRef<RelativePath>) -> Option<Link> {
+        self.inner.remove(&path.as_ref().normalize())
+    }
+
+    /// Find the path of the link that contains a given path, if any.
+    ///
+    /// This is used to stop the outer repository from tracking
+    /// files that belong to a linked repository.
+    pub fn containing(&self, path: impl AsRef<RelativePath>) -> Option<&RelativePathBuf> {
+        let path = path.as_ref().normalize();
+
+        self.inner
+            .keys()
+            .find(|link_path| path.starts_with(link_path))
+    }
+
+    /// Iterate through all the links in order of their paths.
+    pub fn iter(&self) -> impl Iterator<Item = (&RelativePathBuf, &Link)> {
+        self.inner.iter()
+    }
+
+    /// Check if there are no links.
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
+    /// Get the number of links.
+    pub fn len(&self) -> usize {
+        self.inner.len()
+    }
+}
+
+/// Check that a link's path stays inside the repository and out of any
+/// `.asc` directory, since links can come from a remote, which could
+/// otherwise have a repository written anywhere, or over its metadata.
+pub fn check_path(path: &RelativePath) -> Result<()> {
+    let raw = path.as_str();
+
+    if raw.starts_with('/') || raw.contains('\\') || raw.contains(':') {
+        bail!("{raw:?} isn't a relative path");
+    }
+
+    let mut parts = 0;
+
+    for component in path.components() {
+        match component {
+            Component::CurDir => {},
+            Component::ParentDir => bail!("{raw:?} goes outside of the repository"),
+            Component::Normal(name) => {
+                if name.eq_ignore_ascii_case(".asc") {
+                    bail!("{raw:?} is inside a .asc directory");
+                }
+
+                parts += 1;
+            }
+        }
+    }
+
+    if parts == 0 {
+        bail!("the path is empty");
+    }
+
+    Ok(())
+}
+
+/// Find where the link at `path` goes in the repository at `root_dir`,
+/// checking that it, and the directories on the way to it, are inside it.
+pub fn link_dir(root_dir: &Path, path: &RelativePath) -> Result<PathBuf> {
+    check_path(path)?;
+
+    let full_path = path.normalize().to_logical_path(root_dir);
+
+    if !full_path.starts_with(root_dir) {
+        bail!("{path:?} is outside of {}", root_dir.display());
+    }
+
+    // The linked repository's own `.asc` is checked, so that
+    // the link can't be a symbolic link to somewhere else either.
+    unwrap!(
+        mode::check_no_symlink_ancestors(root_dir, &full_path.join(".asc")),
+        "refusing to place a linked repository at {path}"
+    );
+
+    Ok(full_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paths_inside_the_repository_are_allowed() {
+        for path in ["vendor/lib", "lib", "./lib", "a/b/c"] {
+            assert!(check_path(RelativePath::new(path)).is_ok(), "{path}");
+        }
+    }
+
+    #[test]
+    fn paths_leaving_the_repository_are_refused() {
+        for path in ["", ".", "../x", "a/../../x", "/etc", "C:/x", "a\\..\\x", ".asc", ".ASC/lfs", "lib/.asc"] {
+            assert!(check_path(RelativePath::new(path)).is_err(), "{path}");
+        }
+    }
+}
@@ -0,0 +1,201 @@
+//! An advisory lock on a repository, held by whatever reads or changes it,
+//! so that two processes can't interleave their saves, or read a
+//! repository halfway through another one saving it.
+//!
+//! The lock is an OS file lock on `.asc/lock`, so it is let go of when the
+//! process holding it ends, even if it crashes. It can be held by any number
+//! of readers at once, like servers handling pulls, or by one writer, like a
+//! command that commits or a server handling a push. A writer also puts who
+//! it is in the file, to say who holds it. Nothing stops a process that
+//! doesn't take the lock from writing anyway.
+
+use std::{fs::{self, File, OpenOptions, TryLockError}, io::Write, path::{Path, PathBuf}, process, thread, time::{Duration, Instant}};
+
+use chrono::{DateTime, Utc};
+use eyre::{bail, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{repository::{locate_root_dir, search_start}, unwrap};
+
+/// How long to wait for another process to let go of the lock
+/// before giving up, if `io.lock_timeout` is unset.
+pub static DEFAULT_LOCK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long to wait between tries at taking the lock.
+const RETRY_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Who holds the lock on a repository.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct LockHolder {
+    pub pid: u32,
+    pub since: DateTime<Utc>,
+
+    /// How the command holding the lock was run, like `commit -m "Fix"`.
+    pub command: String
+}
+
+/// How a lock is held.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LockMode {
+    /// Held alongside any other readers, but never while a writer has it.
+    Shared,
+
+    /// Held by this process alone.
+    Exclusive
+}
+
+/// The lock on a repository, which is let go of when this is dropped.
+pub struct RepoLock {
+    file: File,
+    mode: LockMode
+}
+
+/// Get where the lock for the repository at `root_dir` is kept.
+pub fn lock_path(root_dir: impl AsRef<Path>) -> PathBuf {
+    root_dir.as_ref().join(".asc").join("lock")
+}
+
+/// Find who holds the lock on the repository at `root_dir`, if anyone.
+///
+/// Readers don't say who they are, so while only they hold it, or if who
+/// holds it can't be read, it is held by a [`LockHolder`] with a PID of `0`.
+pub fn holder(root_dir: impl AsRef<Path>) -> Option<LockHolder> {
+    let path = lock_path(root_dir);
+
+    let file = File::open(&path).ok()?;
+
+    if file.try_lock().is_ok() {
+        return None;
+    }
+
+    let holder = fs::read(&path)
+        .ok()
+        .and_then(|raw| rmp_serde::from_slice(&raw).ok())
+        .unwrap_or(LockHolder {
+            pid: 0,
+            since: DateTime::UNIX_EPOCH,
+            command: String::new()
+        });
+
+    Some(holder)
+}
+
+/// Remove the lock on the repository at `root_dir`, whoever holds it,
+/// returning who did.
+///
+/// The lock is let go of when a process ends, so this is only for one held
+/// by a process that is stuck. That process carries on as if it held the
+/// lock, so it should be stopped as well.
+pub fn force_unlock(root_dir: impl AsRef<Path>) -> Result<Option<LockHolder>> {
+    let root_dir = root_dir.as_ref();
+
+    let Some(holder) = holder(root_dir) else {
+        return Ok(None);
+    };
+
+    let path = lock_path(root_dir);
+
+    unwrap!(
+        fs::remove_file(&path),
+        "failed to remove lock: {}", path.display()
+    );
+
+    Ok(Some(holder))
+}
+
+impl RepoLock {
+    /// Take the lock on the repository at `root_dir` for `command`, waiting
+    /// up to `timeout` for whoever holds it to let go of it first.
+    pub fn acquire(root_dir: impl AsRef<Path>, command: &str, timeout: Duration) -> Result<RepoLock> {
+        let mut lock = RepoLock::acquire_with(root_dir.as_ref(), LockMode::Exclusive, timeout)?;
+
+        let data = rmp_serde::to_vec(&LockHolder {
+            pid: process::id(),
+            since: Utc::now(),
+            command: command.to_string()
+        })?;
+
+        unwrap!(
+            lock.file.write_all(&data),
+            "failed to write lock: {}", lock_path(root_dir).display()
+        );
+
+        Ok(lock)
+    }
+
+    /// Take the lock on the repository at `root_dir` alongside any other
+    /// readers, waiting up to `timeout` for a writer to let go of it first.
+    pub fn acquire_shared(root_dir: impl AsRef<Path>, timeout: Duration) -> Result<RepoLock> {
+        RepoLock::acquire_with(root_dir.as_ref(), LockMode::Shared, timeout)
+    }
+
+    fn acquire_with(root_dir: &Path, mode: LockMode, timeout: Duration) -> Result<RepoLock> {
+        let path = lock_path(root_dir);
+
+        let file = unwrap!(
+            OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&path),
+            "failed to open lock at {}", path.display()
+        );
+
+        let started = Instant::now();
+
+        loop {
+            let attempt = match mode {
+                LockMode::Shared => file.try_lock_shared(),
+                LockMode::Exclusive => file.try_lock()
+            };
+
+            match attempt {
+                Ok(()) => break,
+                Err(TryLockError::WouldBlock) => {},
+                Err(TryLockError::Error(e)) => bail!("failed to lock {}: {e}", path.display())
+            }
+
+            if started.elapsed() >= timeout {
+                let Some(holder) = holder(root_dir).filter(|holder| holder.pid != 0) else {
+                    bail!("repository is in use by another process - try again once it has finished");
+                };
+
+                bail!(
+                    "repository is locked by PID {} (running `asc {}` since {}) - if it is stuck, stop it, or run `asc unlock --force`",
+                    holder.pid,
+                    holder.command,
+                    holder.since.format("%Y-%m-%d %H:%M:%S")
+                );
+            }
+
+            thread::sleep(RETRY_INTERVAL);
+        }
+
+        if mode == LockMode::Exclusive {
+            unwrap!(
+                file.set_len(0),
+                "failed to clear lock: {}", path.display()
+            );
+        }
+
+        Ok(RepoLock { file, mode })
+    }
+
+    /// Take the lock on the repository that [`crate::repository::Repository::load`]
+    /// would load, if there is one.
+    pub fn acquire_current(command: &str, timeout: Duration) -> Result<Option<RepoLock>> {
+        let Some(root_dir) = locate_root_dir(search_start()?)? else {
+            return Ok(None);
+        };
+
+        RepoLock::acquire(root_dir, command, timeout).map(Some)
+    }
+}
+
+impl Drop for RepoLock {
+    fn drop(&mut self) {
+        // The file is kept, since another process may already be waiting on
+        // it, but who held it is cleared before it is let go of.
+        if self.mode == LockMode::Exclusive {
+            let _ = self.file.set_len(0);
+        }
+
+        let _ = self.file.unlock();
+    }
+}
@@ -0,0 +1,213 @@
+//! What kind of file each path in a snapshot is: a regular file, an
+//! executable one, or a symbolic link.
+//!
+//! The content of a symbolic link is the path it points to, written with
+//! forward slashes, so links are stored and compared like any other file.
+//!
+//! Snapshots only record the paths that aren't regular files, in
+//! [`Snapshot::modes`](crate::snapshot::Snapshot::modes), so snapshots made
+//! before modes were tracked are read as having only regular files.
+//!
+//! Windows has no executable bit, so there a file keeps the mode it was
+//! staged with before, and symbolic links that can't be made there are
+//! written as regular files holding the path they point to.
+
+use std::{collections::BTreeMap, fs, io, path::Path};
+
+use relative_path::RelativePathBuf;
+use serde::{Deserialize, Serialize};
+
+use crate::hash::ObjectHash;
+
+/// The kind of file at a path.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub enum FileMode {
+    #[default]
+    Regular,
+    Executable,
+    Symlink
+}
+
+impl FileMode {
+    /// The byte a mode is hashed as in a snapshot.
+    pub(crate) fn as_byte(self) -> u8 {
+        match self {
+            FileMode::Regular => 0,
+            FileMode::Executable => 1,
+            FileMode::Symlink => 2
+        }
+    }
+
+    /// Find the mode of a file on disk, without following symbolic links.
+    ///
+    /// `previous` is the mode the path had before, which is kept where the
+    /// platform can't tell, like whether a file is executable on Windows.
+    pub fn of(path: &Path, previous: FileMode) -> io::Result<FileMode> {
+        let metadata = fs::symlink_metadata(path)?;
+
+        if metadata.file_type().is_symlink() {
+            return Ok(FileMode::Symlink);
+        }
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+
+            let _ = previous;
+
+            if metadata.permissions().mode() & 0o111 != 0 {
+                Ok(FileMode::Executable)
+            }
+            else {
+                Ok(FileMode::Regular)
+            }
+        }
+
+        #[cfg(not(unix))]
+        match previous {
+            FileMode::Symlink => Ok(FileMode::Regular),
+            other => Ok(other)
+        }
+    }
+}
+
+/// A file in a snapshot: its content and what kind of file it is.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct FileEntry {
+    pub hash: ObjectHash,
+    pub mode: FileMode
+}
+
+/// Keep only the modes that aren't [`FileMode::Regular`], which is how snapshots store them.
+pub fn special_modes(modes: impl IntoIterator<Item = (RelativePathBuf, FileMode)>) -> BTreeMap<RelativePathBuf, FileMode> {
+    modes
+        .into_iter()
+        .filter(|(_, mode)| *mode != FileMode::Regular)
+        .collect()
+}
+
+/// Check if there is anything at a path, including a symbolic link to nothing.
+pub fn exists(path: &Path) -> bool {
+    fs::symlink_metadata(path).is_ok()
+}
+
+/// Read the content of a file in the working directory,
+/// which for a symbolic link is the path it points to.
+pub fn read_working(path: &Path) -> io::Result<String> {
+    let metadata = fs::symlink_metadata(path)?;
+
+    if !metadata.file_type().is_symlink() {
+        return fs::read_to_string(path);
+    }
+
+    read_link_target(path)
+}
+
+/// Read the content of a file in the working directory like
+/// [`read_working`], without needing it to be valid UTF-8.
+pub fn read_working_bytes(path: &Path) -> io::Result<Vec<u8>> {
+    let metadata = fs::symlink_metadata(path)?;
+
+    if !metadata.file_type().is_symlink() {
+        return fs::read(path);
+    }
+
+    Ok(read_link_target(path)?.into_bytes())
+}
+
+fn read_link_target(path: &Path) -> io::Result<String> {
+    let target = fs::read_link(path)?;
+
+    let target = target.to_string_lossy();
+
+    if cfg!(windows) {
+        Ok(target.replace('\\', "/"))
+    }
+    else {
+        Ok(target.into_owned())
+    }
+}
+
+/// Check that nothing between `root` and `path` is a symbolic link, so that
+/// writing to `path` can't end up outside of `root`. Directories that don't
+/// exist yet are fine, since creating them makes real directories.
+pub fn check_no_symlink_ancestors(root: &Path, path: &Path) -> io::Result<()> {
+    let Ok(relative) = path.strip_prefix(root) else {
+        return Err(io::Error::other(format!("{} is outside of {}", path.display(), root.display())));
+    };
+
+    let mut current = root.to_path_buf();
+
+    let Some(parent) = relative.parent() else {
+        return Ok(());
+    };
+
+    for component in parent.components() {
+        current.push(component);
+
+        match fs::symlink_metadata(&current) {
+            Ok(metadata) if metadata.file_type().is_symlink() => {
+                return Err(io::Error::other(format!("{} is a symbolic link, so it can't be written through", current.display())));
+            },
+
+            Ok(_) => {},
+
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+
+            Err(e) => return Err(e)
+        }
+    }
+
+    Ok(())
+}
+
+/// Write a file to the working directory with the given content and mode,
+/// replacing anything that is there already.
+pub fn write_working(path: &Path, content: &str, mode: FileMode) -> io::Result<()> {
+    // Writing through a symbolic link would change the file it points to.
+    if fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_symlink()) {
+        fs::remove_file(path)?;
+    }
+
+    if mode == FileMode::Symlink && make_symlink(content, path).is_ok() {
+        return Ok(());
+    }
+
+    fs::write(path, content)?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        let mut permissions = fs::metadata(path)?.permissions();
+
+        let bits = permissions.mode();
+
+        let bits = match mode {
+            // Executable for everyone who can read it.
+            FileMode::Executable => bits | ((bits & 0o444) >> 2),
+            _ => bits & !0o111
+        };
+
+        permissions.set_mode(bits);
+
+        fs::set_permissions(path, permissions)?;
+    }
+
+    Ok(())
+}
+
+fn make_symlink(target: &str, path: &Path) -> io::Result<()> {
+    if fs::symlink_metadata(path).is_ok() {
+        fs::remove_file(path)?;
+    }
+
+    #[cfg(unix)]
+    return std::os::unix::fs::symlink(target, path);
+
+    #[cfg(windows)]
+    return std::os::windows::fs::symlink_file(target.replace('/', "\\"), path);
+
+    #[cfg(not(any(unix, windows)))]
+    return Err(io::Error::from(io::ErrorKind::Unsupported));
+}
@@ -0,0 +1,817 @@
+//! Patches describe a snapshot as the changes it makes to its parent,
+//! written as plain text so they can be sent by email and read before
+//! they are applied.
+//!
+//! A patch starts with headers for the snapshot's hash, author, date,
+//! branch, parent and signature, then its message, then a unified diff
+//! of each file it changes. When a patch is applied on the same parent
+//! it was made from, the original snapshot is rebuilt exactly, signature
+//! and all. Otherwise it is re-signed by the user applying it.
+//!
+//! Patches can also be written as emails in an mbox, with the headers
+//! moved into `X-Asc-` email headers, and read back from one.
+
+use std::{collections::{BTreeMap, HashSet}, fmt::{Display, Formatter, Result as FmtResult, Write}, str::FromStr};
+
+use chrono::{DateTime, Utc};
+use eyre::{Result, bail};
+use relative_path::{RelativePath, RelativePathBuf};
+use similar::{ChangeTag, TextDiff};
+
+use crate::{hash::ObjectHash, key::{PublicKey, Signature}, repository::Repository, snapshot::Snapshot, unwrap};
+
+/// How a file is changed by a patch.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FileKind {
+    Added,
+    Edited,
+    Removed
+}
+
+/// A run of lines that a patch replaces, with the lines
+/// around them that have to match for it to apply.
+///
+/// Lines keep their line endings, so a last line without
+/// a newline is kept as it is.
+#[derive(Clone, Debug, Default)]
+struct Hunk {
+    /// The line the hunk starts on in the old file, counting from 0.
+    start: usize,
+
+    /// Each line, and whether it is kept (`' '`), removed (`'-'`) or added (`'+'`).
+    lines: Vec<(char, String)>
+}
+
+impl Hunk {
+    /// Get the lines the hunk expects to find in the old file.
+    fn old_lines(&self) -> Vec<&str> {
+        self.lines
+            .iter()
+            .filter(|(tag, _)| *tag != '+')
+            .map(|(_, line)| line.as_str())
+            .collect()
+    }
+
+    /// Get the lines the hunk leaves in the new file.
+    fn new_lines(&self) -> impl Iterator<Item = &str> {
+        self.lines
+            .iter()
+            .filter(|(tag, _)| *tag != '-')
+            .map(|(_, line)| line.as_str())
+    }
+}
+
+/// The changes a patch makes to a single file.
+#[derive(Clone, Debug)]
+pub struct FilePatch {
+    pub path: RelativePathBuf,
+    pub kind: FileKind,
+    hunks: Vec<Hunk>
+}
+
+/// A snapshot, written as the changes it makes to its parent.
+#[derive(Clone, Debug)]
+pub struct Patch {
+    /// The hash of the snapshot the patch was made from.
+    pub hash: ObjectHash,
+
+    /// The name of the author, as the repository the patch was made in knew them.
+    pub author_name: String,
+    pub author: PublicKey,
+    pub timestamp: DateTime<Utc>,
+    pub branch: Option<String>,
+    pub parent: Option<ObjectHash>,
+    pub signature: Signature,
+    pub message: String,
+    pub files: Vec<FilePatch>
+}
+
+impl FilePatch {
+    fn diff(path: &RelativePath, old: Option<&str>, new: Option<&str>) -> FilePatch {
+        let kind = match (old, new) {
+            (None, _) => FileKind::Added,
+            (_, None) => FileKind::Removed,
+            _ => FileKind::Edited
+        };
+
+        let diff = TextDiff::from_lines(old.unwrap_or_default(), new.unwrap_or_default());
+
+        let hunks = diff
+            .unified_diff()
+            .iter_hunks()
+            .map(|hunk| Hunk {
+                start: hunk.ops().first().map(|op| op.old_range().start).unwrap_or_default(),
+
+                lines: hunk
+                    .iter_changes()
+                    .map(|change| {
+                        let tag = match change.tag() {
+                            ChangeTag::Equal => ' ',
+                            ChangeTag::Delete => '-',
+                            ChangeTag::Insert => '+'
+                        };
+
+                        (tag, change.value().to_string())
+                    })
+                    .collect()
+            })
+            .collect();
+
+        FilePatch {
+            path: path.to_owned(),
+            kind,
+            hunks
+        }
+    }
+
+    /// Apply the changes to the old content of the file.
+    fn apply(&self, old: &str) -> Result<String> {
+        let lines: Vec<&str> = old.split_inclusive('\n').collect();
+
+        let mut result = String::new();
+
+        let mut position = 0;
+
+        for hunk in &self.hunks {
+            let old = hunk.old_lines();
+
+            let end = hunk.start + old.len();
+
+            if hunk.start < position || end > lines.len() || lines[hunk.start..end] != old {
+                bail!("patch does not apply to {}: the file has changed around line {}.", self.path, hunk.start + 1);
+            }
+
+            result.extend(lines[position..hunk.start].iter().copied());
+
+            result.extend(hunk.new_lines());
+
+            position = end;
+        }
+
+        result.extend(lines[position..].iter().copied());
+
+        Ok(result)
+    }
+}
+
+impl Patch {
+    /// Make a patch from a snapshot in the repository.
+    ///
+    /// Merges can't be written as a patch, since they have more than one parent.
+    pub fn from_snapshot(repo: &Repository, hash: ObjectHash) -> Result<Patch> {
+        let snapshot = repo.fetch_snapshot(hash)?;
+
+        if snapshot.parents.len() > 1 {
+            bail!("cannot make a patch of {hash}, since it is a merge.");
+        }
+
+        let parent = snapshot.parents.iter().next().copied();
+
+        let old_files = match parent {
+            Some(parent) => repo.fetch_snapshot(parent)?.files,
+            None => BTreeMap::new()
+        };
+
+        let mut paths: Vec<&RelativePathBuf> = old_files.keys().chain(snapshot.files.keys()).collect();
+
+        paths.sort();
+        paths.dedup();
+
+        let mut files = vec![];
+
+        for path in paths {
+            let old_hash = old_files.get(path);
+            let new_hash = snapshot.files.get(path);
+
+            if old_hash == new_hash {
+                continue;
+            }
+
+            let old = old_hash.map(|&hash| repo.fetch_string_content(hash)).transpose()?;
+            let new = new_hash.map(|&hash| repo.fetch_string_content(hash)).transpose()?;
+
+            files.push(FilePatch::diff(path, old.as_deref(), new.as_deref()));
+        }
+
+        let author_name = repo.users
+            .get_user(&snapshot.author)
+            .map(|user| user.name.clone())
+            .unwrap_or_else(|| "<unknown user>".to_string());
+
+        Ok(Patch {
+            hash,
+            author_name,
+            author: snapshot.author,
+            timestamp: snapshot.timestamp,
+            branch: snapshot.branch,
+            parent,
+            signature: snapshot.signature,
+            message: snapshot.message,
+            files
+        })
+    }
+
+    /// Get the first line of the message.
+    pub fn subject(&self) -> &str {
+        self.message.lines().next().unwrap_or_default()
+    }
+
+    /// Apply the patch on top of `parent`, saving the content
+    /// of the changed files and returning the snapshot to add.
+    ///
+    /// The snapshot is the one the patch was made from if it can be
+    /// rebuilt exactly. If not, it is re-signed by the current user,
+    /// on the current branch, noting the original author in the message.
+    pub fn apply(&self, repo: &Repository, parent: &Snapshot) -> Result<Snapshot> {
+        Ok(self.apply_checked(repo, parent)?.0)
+    }
+
+    /// Like [`Patch::apply`], also saying how far the snapshot's
+    /// signature could be checked against the patch's author.
+    ///
+    /// The original snapshot is only kept if it was signed by a user
+    /// of this repository, since a snapshot by anyone else can't be saved.
+    pub fn apply_checked(&self, repo: &Repository, parent: &Snapshot) -> Result<(Snapshot, Provenance)> {
+        let mut files = parent.files.clone();
+
+        for file in &self.files {
+            let old_hash = files.get(&file.path).copied();
+
+            match (file.kind, old_hash) {
+                (FileKind::Added, Some(_)) => bail!("patch adds {}, but it already exists.", file.path),
+                (FileKind::Edited | FileKind::Removed, None) => bail!("patch changes {}, but it doesn't exist.", file.path),
+                _ => {}
+            }
+
+            let old = old_hash.map(|hash| repo.fetch_string_content(hash)).transpose()?;
+
+            let new = file.apply(old.as_deref().unwrap_or_default())?;
+
+            if file.kind == FileKind::Removed {
+                if !new.is_empty() {
+                    bail!("patch removes {}, but it has lines the patch doesn't.", file.path);
+                }
+
+                files.remove(&file.path);
+            }
+            else {
+                let hash = repo.save_content(&new, old_hash)?;
+
+                files.insert(file.path.clone(), hash);
+            }
+        }
+
+        // Patches only carry content, so files keep the modes they had.
+        let modes: BTreeMap<_, _> = parent.modes
+            .iter()
+            .filter(|(path, _)| files.contains_key(*path))
+            .map(|(path, &mode)| (path.clone(), mode))
+            .collect();
+
+        let original = Snapshot {
+            hash: self.hash,
+            author: self.author,
+            message: self.message.clone(),
+            timestamp: self.timestamp,
+            files: files.clone(),
+            parents: self.parent.into_iter().collect(),
+            signature: self.signature.clone(),
+            branch: self.branch.clone(),
+            modes: modes.clone()
+        };
+
+        let provenance = if self.parent != Some(parent.hash) {
+            Provenance::Unchecked
+        }
+        else if !original.is_valid() {
+            Provenance::BadSignature
+        }
+        else if repo.users.get_user(&self.author).is_none() {
+            Provenance::UnknownAuthor
+        }
+        else {
+            return Ok((original, Provenance::Verified));
+        };
+
+        let user = unwrap!(
+            repo.current_user(),
+            "cannot apply patch: no valid user."
+        );
+
+        let mut message = self.message.clone();
+
+        if user.public_key != self.author {
+            message.push_str(&format!("\n\nPatch-Author: {} <{}>", self.author_name, self.author));
+        }
+
+        let snapshot = Snapshot::new_with_modes(
+            user.private_key.clone().unwrap(),
+            message,
+            self.timestamp,
+            files,
+            modes,
+            HashSet::from([parent.hash]),
+            repo.current_branch().map(String::from)
+        );
+
+        Ok((snapshot, provenance))
+    }
+
+    /// Write the changes to each file as a unified diff.
+    fn write_files(&self, f: &mut impl Write) -> FmtResult {
+        for file in &self.files {
+            let old = match file.kind {
+                FileKind::Added => "/dev/null".to_string(),
+                _ => format!("a/{}", file.path)
+            };
+
+            let new = match file.kind {
+                FileKind::Removed => "/dev/null".to_string(),
+                _ => format!("b/{}", file.path)
+            };
+
+            writeln!(f, "--- {old}")?;
+            writeln!(f, "+++ {new}")?;
+
+            // Where each hunk starts in the new file depends
+            // on how many lines the hunks before it added.
+            let mut offset = 0isize;
+
+            for hunk in &file.hunks {
+                let old_len = hunk.old_lines().len();
+                let new_len = hunk.new_lines().count();
+
+                let range = |start: usize, len: usize| match len {
+                    0 => format!("{start},0"),
+                    1 => format!("{}", start + 1),
+                    _ => format!("{},{len}", start + 1)
+                };
+
+                let new_start = hunk.start.saturating_add_signed(offset);
+
+                writeln!(f, "@@ -{} +{} @@", range(hunk.start, old_len), range(new_start, new_len))?;
+
+                for (tag, line) in &hunk.lines {
+                    write!(f, "{tag}{line}")?;
+
+                    if !line.ends_with('\n') {
+                        writeln!(f)?;
+                        writeln!(f, "\\ No newline at end of file")?;
+                    }
+                }
+
+                offset += new_len as isize - old_len as isize;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Write the patch as an email in an mbox, as patch `index` (from 1)
+    /// of `total` in a series, from `from` and to each of `to`.
+    ///
+    /// What is needed to rebuild the snapshot exactly, like its signature
+    /// and the exact time it was made, goes in `X-Asc-` headers, so it
+    /// survives being sent even though mail clients rewrite the others.
+    pub fn to_email(&self, index: usize, total: usize, from: &str, to: &[String]) -> String {
+        let mut email = String::new();
+
+        // The date on the mbox separator is fixed, since only the
+        // line's start matters to anything that reads the mbox.
+        let _ = writeln!(email, "From {} Mon Sep 17 00:00:00 2001", self.hash.full());
+        let _ = writeln!(email, "From: {from}");
+
+        if !to.is_empty() {
+            let _ = writeln!(email, "To: {}", to.join(", "));
+        }
+
+        let _ = writeln!(email, "Date: {}", self.timestamp.to_rfc2822());
+
+        let _ = match total {
+            1 => writeln!(email, "Subject: [PATCH] {}", self.subject()),
+            _ => writeln!(email, "Subject: [PATCH {index}/{total}] {}", self.subject())
+        };
+
+        let _ = writeln!(email, "Message-Id: <{}@asc>", self.hash.full());
+        let _ = writeln!(email, "MIME-Version: 1.0");
+        let _ = writeln!(email, "Content-Type: text/plain; charset=UTF-8");
+        let _ = writeln!(email, "Content-Transfer-Encoding: 8bit");
+        let _ = writeln!(email, "X-Asc-Hash: {}", self.hash.full());
+        let _ = writeln!(email, "X-Asc-Author: {} <{}>", self.author_name, self.author);
+        let _ = writeln!(email, "X-Asc-Date: {}", self.timestamp.to_rfc3339());
+
+        if let Some(branch) = &self.branch {
+            let _ = writeln!(email, "X-Asc-Branch: {branch}");
+        }
+
+        if let Some(parent) = self.parent {
+            let _ = writeln!(email, "X-Asc-Parent: {}", parent.full());
+        }
+
+        let _ = writeln!(email, "X-Asc-Signature: {}", hex::encode(self.signature.to_bytes()));
+        let _ = writeln!(email);
+
+        if let Some((_, body)) = self.message.split_once('\n') {
+            for line in body.lines() {
+                // Lines that look like the start of a message are quoted,
+                // the mboxrd way, and unquoted when the mbox is read.
+                if line.trim_start_matches('>').starts_with("From ") {
+                    email.push('>');
+                }
+
+                let _ = writeln!(email, "{line}");
+            }
+        }
+
+        let _ = writeln!(email, "---");
+        let _ = self.write_files(&mut email);
+
+        email
+    }
+
+    /// Read every patch in an mbox written by [`Patch::to_email`], in order.
+    pub fn from_mbox(text: &str) -> Result<Vec<Patch>> {
+        let mut emails: Vec<Vec<&str>> = vec![];
+
+        for line in text.lines() {
+            if line.starts_with("From ") {
+                emails.push(vec![]);
+            }
+            else if let Some(email) = emails.last_mut() {
+                email.push(line);
+            }
+        }
+
+        emails
+            .into_iter()
+            .map(|lines| Patch::from_email(&lines))
+            .collect()
+    }
+
+    /// Read a patch from the lines of one email, after the mbox separator.
+    fn from_email(lines: &[&str]) -> Result<Patch> {
+        let mut lines = lines.iter().copied();
+
+        let mut headers: BTreeMap<String, String> = BTreeMap::new();
+
+        let mut last: Option<String> = None;
+
+        for line in lines.by_ref() {
+            if line.is_empty() {
+                break;
+            }
+
+            // Long headers can be folded onto the lines after them.
+            if line.starts_with([' ', '\t']) {
+                if let Some(value) = last.as_ref().and_then(|name| headers.get_mut(name)) {
+                    value.push(' ');
+                    value.push_str(line.trim());
+                }
+
+                continue;
+            }
+
+            if let Some((name, value)) = line.split_once(':') {
+                let name = name.to_ascii_lowercase();
+
+                headers.insert(name.clone(), value.trim().to_string());
+
+                last = Some(name);
+            }
+        }
+
+        let header = |name: &str| -> Result<&str> {
+            Ok(unwrap!(
+                headers.get(&name.to_ascii_lowercase()).map(String::as_str),
+                "email has no {name:?} header, so it wasn't written by asc."
+            ))
+        };
+
+        let hash: ObjectHash = header("X-Asc-Hash")?.parse()?;
+
+        let (author_name, key) = unwrap!(
+            header("X-Asc-Author")?.rsplit_once(" <"),
+            "email has an invalid author."
+        );
+
+        let author = PublicKey::from_bytes(&unwrap!(
+            hex::decode(key.trim_end_matches('>')),
+            "email has an invalid author key."
+        ))?;
+
+        let timestamp = unwrap!(
+            DateTime::parse_from_rfc3339(header("X-Asc-Date")?),
+            "email has an invalid date."
+        );
+
+        let signature = Signature::from_bytes(&unwrap!(
+            hex::decode(header("X-Asc-Signature")?),
+            "email has an invalid signature."
+        ))?;
+
+        let parent = headers
+            .get("x-asc-parent")
+            .map(|parent| parent.parse())
+            .transpose()?;
+
+        // The subject is prefixed with where the patch is in its series.
+        let subject = header("Subject")?;
+
+        let subject = match subject.strip_prefix('[').and_then(|rest| rest.split_once("] ")) {
+            Some((tag, rest)) if tag.starts_with("PATCH") => rest,
+            _ => subject
+        };
+
+        let mut message = subject.to_string();
+
+        let mut body = vec![];
+
+        for line in lines.by_ref() {
+            if line == "---" {
+                break;
+            }
+
+            let unquoted = line.strip_prefix('>').filter(|rest| rest.trim_start_matches('>').starts_with("From "));
+
+            body.push(unquoted.unwrap_or(line));
+        }
+
+        if !body.is_empty() {
+            message.push('\n');
+            message.push_str(&body.join("\n"));
+        }
+
+        Ok(Patch {
+            hash,
+            author_name: author_name.to_string(),
+            author,
+            timestamp: timestamp.with_timezone(&Utc),
+            branch: headers.get("x-asc-branch").cloned(),
+            parent,
+            signature,
+            message,
+            files: parse_files(lines)?
+        })
+    }
+}
+
+/// How much applying a patch could check that its author made it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Provenance {
+    /// The original snapshot was rebuilt, its signature
+    /// matched, and it was made by a user of this repository.
+    Verified,
+
+    /// The original snapshot was rebuilt and its signature matched,
+    /// but by someone who isn't a user here, so it was re-signed.
+    UnknownAuthor,
+
+    /// The original snapshot was rebuilt, but its signature didn't match,
+    /// so the patch was changed after it was made, and it was re-signed.
+    BadSignature,
+
+    /// The patch was applied on a different parent to the one it was
+    /// made on, so there was no snapshot to check, and it was re-signed.
+    Unchecked
+}
+
+impl Display for Patch {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        writeln!(f, "From {}", self.hash.full())?;
+        writeln!(f, "Author: {} <{}>", self.author_name, self.author)?;
+        writeln!(f, "Date: {}", self.timestamp.to_rfc3339())?;
+
+        if let Some(branch) = &self.branch {
+            writeln!(f, "Branch: {branch}")?;
+        }
+
+        if let Some(parent) = self.parent {
+            writeln!(f, "Parent: {}", parent.full())?;
+        }
+
+        writeln!(f, "Signature: {}", hex::encode(self.signature.to_bytes()))?;
+
+        let (subject, body) = self.message.split_once('\n').unwrap_or((&self.message, ""));
+
+        writeln!(f, "Subject: {subject}")?;
+        writeln!(f)?;
+
+        if !body.is_empty() {
+            writeln!(f, "{body}")?;
+        }
+
+        writeln!(f, "---")?;
+
+        self.write_files(f)
+    }
+}
+
+/// Read where a hunk starts in the old file and how many
+/// lines it covers there, from a range like `-3,4`.
+fn parse_range(range: &str) -> Option<(usize, usize)> {
+    let (start, len): (usize, usize) = match range.split_once(',') {
+        Some((start, len)) => (start.parse().ok()?, len.parse().ok()?),
+        None => (range.parse().ok()?, 1)
+    };
+
+    // Empty ranges start on the line before them.
+    let start = if len == 0 { start } else { start.checked_sub(1)? };
+
+    Some((start, len))
+}
+
+/// Read a hunk header like `@@ -1,3 +1,4 @@`, returning the
+/// hunk along with how many old and new lines it has.
+fn parse_hunk_header(line: &str) -> Option<(Hunk, usize, usize)> {
+    let ranges = line.strip_prefix("@@ -")?.split(" @@").next()?;
+
+    let (old, new) = ranges.split_once(" +")?;
+
+    let (start, old_len) = parse_range(old)?;
+    let (_, new_len) = parse_range(new)?;
+
+    let hunk = Hunk {
+        start,
+        lines: vec![]
+    };
+
+    Some((hunk, old_len, new_len))
+}
+
+/// Handle a `\ No newline at end of file` marker, which
+/// is about the line that comes just before it.
+fn remove_newline(hunk: &mut Hunk) {
+    if let Some((_, last)) = hunk.lines.last_mut() {
+        last.pop();
+    }
+}
+
+/// Read the changes to each file, from the lines after a patch's message.
+fn parse_files<'a>(lines: impl Iterator<Item = &'a str>) -> Result<Vec<FilePatch>> {
+    let mut files: Vec<FilePatch> = vec![];
+
+    let mut lines = lines.peekable();
+
+    while let Some(line) = lines.next() {
+        let Some(old) = line.strip_prefix("--- ") else {
+            // Anything between files, like a summary
+            // added by hand, is left out.
+            continue;
+        };
+
+        let new = unwrap!(
+            lines.next().and_then(|line| line.strip_prefix("+++ ")),
+            "patch has no \"+++\" line after {line:?}."
+        );
+
+        let (kind, path) = match (old, new) {
+            ("/dev/null", new) => (FileKind::Added, new),
+            (old, "/dev/null") => (FileKind::Removed, old),
+            (_, new) => (FileKind::Edited, new)
+        };
+
+        let path = unwrap!(
+            path.split_once('/').map(|(_, path)| path),
+            "patch has an invalid path: {path:?}"
+        );
+
+        let mut file = FilePatch {
+            path: RelativePathBuf::from(path),
+            kind,
+            hunks: vec![]
+        };
+
+        while let Some(line) = lines.next_if(|line| line.starts_with("@@ ")) {
+            let (mut hunk, mut old_left, mut new_left) = unwrap!(
+                parse_hunk_header(line),
+                "patch has an invalid hunk header: {line:?}"
+            );
+
+            while old_left > 0 || new_left > 0 {
+                let line = unwrap!(lines.next(), "patch ends in the middle of {}.", file.path);
+
+                // Some mail clients remove the space
+                // at the start of an empty line.
+                let (tag, content) = match line.chars().next() {
+                    None => (' ', ""),
+                    Some(tag @ (' ' | '-' | '+')) => (tag, &line[1..]),
+
+                    Some('\\') => {
+                        remove_newline(&mut hunk);
+
+                        continue;
+                    },
+
+                    Some(_) => bail!("patch has an invalid line in {}: {line:?}", file.path)
+                };
+
+                if tag != '+' {
+                    old_left = old_left.saturating_sub(1);
+                }
+
+                if tag != '-' {
+                    new_left = new_left.saturating_sub(1);
+                }
+
+                hunk.lines.push((tag, format!("{content}\n")));
+            }
+
+            if lines.next_if(|line| line.starts_with('\\')).is_some() {
+                remove_newline(&mut hunk);
+            }
+
+            file.hunks.push(hunk);
+        }
+
+        files.push(file);
+    }
+
+    Ok(files)
+}
+
+impl FromStr for Patch {
+    type Err = eyre::Report;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let mut lines = text.lines();
+
+        let mut headers: BTreeMap<&str, &str> = BTreeMap::new();
+
+        let mut hash = None;
+
+        for line in lines.by_ref() {
+            if line.is_empty() {
+                break;
+            }
+
+            if let Some(value) = line.strip_prefix("From ") {
+                hash = Some(value);
+            }
+            else if let Some((name, value)) = line.split_once(": ") {
+                headers.insert(name, value);
+            }
+        }
+
+        let header = |name: &str| -> Result<&str> {
+            Ok(unwrap!(headers.get(name).copied(), "patch has no {name:?} header."))
+        };
+
+        let hash: ObjectHash = unwrap!(hash, "patch has no \"From\" line.").parse()?;
+
+        let (author_name, key) = unwrap!(
+            header("Author")?.rsplit_once(" <"),
+            "patch has an invalid author."
+        );
+
+        let author = PublicKey::from_bytes(&unwrap!(
+            hex::decode(key.trim_end_matches('>')),
+            "patch has an invalid author key."
+        ))?;
+
+        let timestamp = unwrap!(
+            DateTime::parse_from_rfc3339(header("Date")?),
+            "patch has an invalid date."
+        );
+
+        let signature = Signature::from_bytes(&unwrap!(
+            hex::decode(header("Signature")?),
+            "patch has an invalid signature."
+        ))?;
+
+        let parent = headers
+            .get("Parent")
+            .map(|parent| parent.parse())
+            .transpose()?;
+
+        let mut message = header("Subject")?.to_string();
+
+        let mut body = vec![];
+
+        for line in lines.by_ref() {
+            if line == "---" {
+                break;
+            }
+
+            body.push(line);
+        }
+
+        if !body.is_empty() {
+            message.push('\n');
+            message.push_str(&body.join("\n"));
+        }
+
+        let files = parse_files(lines)?;
+
+        Ok(Patch {
+            hash,
+            author_name: author_name.to_string(),
+            author,
+            timestamp: timestamp.with_timezone(&Utc),
+            branch: headers.get("Branch").map(|branch| branch.to_string()),
+            parent,
+            signature,
+            message,
+            files
+        })
+    }
+}
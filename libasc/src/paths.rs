@@ -0,0 +1,163 @@
+//! The form paths are stored in, so a repository made on one
+//! operating system checks out the same way on another.
+//!
+//! Paths are stored with forward slashes, without `.` or `..` parts,
+//! and in Unicode NFC. macOS hands out names in a decomposed form, so
+//! `é` would otherwise be stored as `e` and a combining accent there,
+//! and as a different path to the same file created anywhere else.
+//!
+//! Case is kept as it was written. Whether two paths that only differ
+//! in case are the same file depends on the file system, which is set
+//! with `paths.case_sensitive` in the config. Where they are, paths
+//! like that can't be committed or checked out together.
+
+use std::{collections::{BTreeMap, HashMap}, fs, path::{Path, PathBuf}};
+
+use eyre::{bail, Result};
+use relative_path::{RelativePath, RelativePathBuf};
+use unicode_normalization::UnicodeNormalization;
+
+use crate::hash::ObjectHash;
+
+/// Whether paths that only differ in case are different files, unless the
+/// repository's config says otherwise. This follows the default file
+/// systems of each platform, which ignore case on Windows and macOS.
+pub static DEFAULT_CASE_SENSITIVE: bool = cfg!(not(any(windows, target_os = "macos")));
+
+/// Put a path in the form it is stored in.
+pub fn canonical(path: impl AsRef<RelativePath>) -> RelativePathBuf {
+    let path = path.as_ref().as_str();
+
+    // Backslashes are only separators on Windows, and can
+    // be part of a file's name everywhere else.
+    let path = if cfg!(windows) {
+        path.replace('\\', "/")
+    }
+    else {
+        path.to_string()
+    };
+
+    let composed: String = path.as_str().nfc().collect();
+
+    RelativePathBuf::from(composed).normalize()
+}
+
+/// Find where a stored path is in a directory.
+///
+/// This is where the path leads, unless nothing is there, but there is a file
+/// whose name is only written in another Unicode form. File systems on Linux
+/// keep names exactly as they were given, so a file copied from macOS can
+/// still have a decomposed name, which the stored path wouldn't find.
+pub fn on_disk(path: impl AsRef<RelativePath>, root: impl AsRef<Path>) -> PathBuf {
+    let path = path.as_ref();
+
+    let direct = path.to_logical_path(&root);
+
+    if direct.exists() {
+        return direct;
+    }
+
+    let mut current = root.as_ref().to_path_buf();
+
+    for name in path.iter() {
+        let next = current.join(name);
+
+        if next.exists() {
+            current = next;
+
+            continue;
+        }
+
+        let Ok(entries) = fs::read_dir(&current) else {
+            return direct;
+        };
+
+        let found = entries
+            .flatten()
+            .find(|entry| entry
+                .file_name()
+                .to_str()
+                .is_some_and(|other| other.nfc().eq(name.nfc()))
+            );
+
+        match found {
+            Some(entry) => current = entry.path(),
+            None => return direct
+        }
+    }
+
+    current
+}
+
+/// Check if a path is already in the form it is stored in.
+pub fn is_canonical(path: impl AsRef<RelativePath>) -> bool {
+    let path = path.as_ref();
+
+    canonical(path) == path
+}
+
+/// What two paths are compared by, to see if they are the same file.
+fn fold(path: &RelativePath, case_sensitive: bool) -> String {
+    let path = canonical(path).into_string();
+
+    if case_sensitive {
+        path
+    }
+    else {
+        path.to_lowercase()
+    }
+}
+
+/// Find the pairs of paths that would be the same file, either
+/// because they only differ in how their Unicode is written, or
+/// because they only differ in case and `case_sensitive` is false.
+pub fn find_collisions<'a>(
+    paths: impl IntoIterator<Item = &'a RelativePathBuf>,
+    case_sensitive: bool
+) -> Vec<(RelativePathBuf, RelativePathBuf)>
+{
+    let mut seen: HashMap<String, &RelativePathBuf> = HashMap::new();
+
+    let mut collisions = vec![];
+
+    for path in paths {
+        if let Some(other) = seen.insert(fold(path, case_sensitive), path) {
+            collisions.push((other.clone(), path.clone()));
+        }
+    }
+
+    collisions
+}
+
+/// Fail if any of the paths would be the same file, as found by [`find_collisions`].
+pub fn check_collisions<'a>(
+    paths: impl IntoIterator<Item = &'a RelativePathBuf>,
+    case_sensitive: bool
+) -> Result<()>
+{
+    let collisions = find_collisions(paths, case_sensitive);
+
+    let Some((a, b)) = collisions.first() else {
+        return Ok(());
+    };
+
+    if fold(a, true) == fold(b, true) {
+        bail!("{a:?} and {b:?} are the same path once their Unicode is normalised.");
+    }
+
+    bail!(
+        "{a:?} and {b:?} only differ in case, which this file system ignores \
+        (set paths.case_sensitive = true in the config if it doesn't)."
+    );
+}
+
+/// Put every path in a set of files in the form it is stored in.
+///
+/// If two paths end up the same, the content of the last one is kept,
+/// so check them with [`check_collisions`] first where that matters.
+pub fn canonical_files(files: &BTreeMap<RelativePathBuf, ObjectHash>) -> BTreeMap<RelativePathBuf, ObjectHash> {
+    files
+        .iter()
+        .map(|(path, &hash)| (canonical(path), hash))
+        .collect()
+}
@@ -0,0 +1,90 @@
+//! Snapshots that have to be kept, like releases that must be retained for
+//! compliance, even once nothing else in the repository points to them.
+//!
+//! A pinned snapshot, and the history it is built on, is never deleted by
+//! `asc clean` or by purging the trash, and can't be trashed until it is
+//! unpinned. With `sync.pins` set on both sides, pins are sent along with
+//! pushes and pulls. Only new pins are sent, so a pin removed here comes
+//! back with the next pull from a repository that still has it.
+
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{hash::ObjectHash, key::PublicKey};
+
+/// Why a snapshot was pinned, and by who.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Pin {
+    pub hash: ObjectHash,
+    pub reason: String,
+    pub pinned_by: PublicKey,
+    pub when: DateTime<Utc>
+}
+
+/// Every pin in a repository, by the hash of the pinned snapshot.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Pins {
+    pins: BTreeMap<ObjectHash, Pin>
+}
+
+impl Pins {
+    /// Create an empty [`Pins`].
+    pub fn new() -> Pins {
+        Pins::default()
+    }
+
+    /// Pin a snapshot, returning the pin it replaced, if it was already pinned.
+    pub fn add(&mut self, pin: Pin) -> Option<Pin> {
+        self.pins.insert(pin.hash, pin)
+    }
+
+    /// Unpin a snapshot, returning its pin if it was pinned.
+    pub fn remove(&mut self, hash: ObjectHash) -> Option<Pin> {
+        self.pins.remove(&hash)
+    }
+
+    /// Get the pin on a snapshot, if it is pinned.
+    pub fn get(&self, hash: ObjectHash) -> Option<&Pin> {
+        self.pins.get(&hash)
+    }
+
+    /// Check if a snapshot is pinned.
+    pub fn contains(&self, hash: ObjectHash) -> bool {
+        self.pins.contains_key(&hash)
+    }
+
+    /// Get every pin, ordered by hash.
+    pub fn iter(&self) -> impl Iterator<Item = &Pin> {
+        self.pins.values()
+    }
+
+    /// Get the hashes of every pinned snapshot.
+    pub fn hashes(&self) -> impl Iterator<Item = ObjectHash> + '_ {
+        self.pins.keys().copied()
+    }
+
+    /// Check if there are no pins.
+    pub fn is_empty(&self) -> bool {
+        self.pins.is_empty()
+    }
+
+    /// Add the pins from another repository that aren't here yet,
+    /// returning the hashes that were pinned.
+    pub fn merge(&mut self, pins: Vec<Pin>) -> Vec<ObjectHash> {
+        let mut added = vec![];
+
+        for pin in pins {
+            if self.contains(pin.hash) {
+                continue;
+            }
+
+            added.push(pin.hash);
+
+            self.add(pin);
+        }
+
+        added
+    }
+}
@@ -0,0 +1,104 @@
+//! Rules for what branches and tags can be called.
+//!
+//! Names are checked when they're made or renamed, and when they come in
+//! from another repository, so that every name can be written as a version
+//! on the command line without being read as something else.
+
+use eyre::{bail, Result};
+use regex::Regex;
+
+use crate::{config::Config, refs::RefKind, unwrap};
+
+/// Names this long or longer that are only hex digits are refused,
+/// since they would be read the same as the start of a hash.
+pub const MIN_HASH_LIKE_LEN: usize = 7;
+
+/// Characters that mean something else in a version, or in a glob.
+const RESERVED_CHARS: &[char] = &[':', '~', '^', '?', '*', '[', '\\'];
+
+/// Check a name against the rules every branch and tag follows.
+///
+/// `/` can be used to group names, like `release/1.0`, but not at
+/// either end, twice in a row, or after the namespace of a ref.
+pub fn check(name: &str) -> Result<()> {
+    if name.is_empty() {
+        bail!("the name is empty");
+    }
+
+    if name.chars().any(char::is_whitespace) {
+        bail!("{name:?} contains whitespace");
+    }
+
+    if name.chars().any(char::is_control) {
+        bail!("{name:?} contains a control character");
+    }
+
+    if let Some(c) = name.chars().find(|c| RESERVED_CHARS.contains(c)) {
+        bail!("{name:?} contains {c:?}, which is reserved");
+    }
+
+    if name.starts_with('-') {
+        bail!("{name:?} starts with '-', so it would be read as an option");
+    }
+
+    if name.contains("@{") {
+        bail!("{name:?} contains '@{{', which is used to look back through reflogs");
+    }
+
+    if name == "HEAD" {
+        bail!("\"HEAD\" is reserved for the head");
+    }
+
+    for part in name.split('/') {
+        if part.is_empty() {
+            bail!("{name:?} has an empty part - '/' can't be at the start or end, or doubled");
+        }
+
+        if part == "." || part == ".." {
+            bail!("{name:?} has a {part:?} part");
+        }
+    }
+
+    if let Some((namespace, _)) = name.split_once('/') {
+        let kinds = [RefKind::Branch, RefKind::Tag, RefKind::Remote, RefKind::Backup, RefKind::Stash];
+
+        if kinds.iter().any(|kind| kind.to_string() == namespace) {
+            bail!("{name:?} starts with the namespace {namespace:?}, so it would be read as a full ref name");
+        }
+    }
+
+    if name.len() >= MIN_HASH_LIKE_LEN && name.chars().all(|c| c.is_ascii_hexdigit()) {
+        bail!("{name:?} is only hex digits, so it would be read as a hash");
+    }
+
+    Ok(())
+}
+
+/// Check a name against the rules, then against a regular
+/// expression from the config that has to match all of it.
+fn check_with_pattern(name: &str, pattern: Option<&str>) -> Result<()> {
+    check(name)?;
+
+    if let Some(pattern) = pattern {
+        let regex = unwrap!(
+            Regex::new(&format!("^(?:{pattern})$")),
+            "invalid name pattern in config: {pattern:?}"
+        );
+
+        if !regex.is_match(name) {
+            bail!("{name:?} doesn't match the pattern {pattern:?}");
+        }
+    }
+
+    Ok(())
+}
+
+/// Check the name of a branch, including `branches.name_pattern`.
+pub fn check_branch(name: &str, config: &Config) -> Result<()> {
+    check_with_pattern(name, config.branches.name_pattern.as_deref())
+}
+
+/// Check the name of a tag, including `tags.name_pattern`.
+pub fn check_tag(name: &str, config: &Config) -> Result<()> {
+    check_with_pattern(name, config.tags.name_pattern.as_deref())
+}
@@ -0,0 +1,898 @@
+use std::{cmp::Ordering, collections::{BTreeMap, BTreeSet}, fs, path::Path, str::FromStr, sync::RwLock};
+
+use chrono::{DateTime, Utc};
+use derive_more::Display;
+use eyre::{bail, Report, Result};
+use serde::{Deserialize, Serialize, Serializer};
+
+use crate::{hash::ObjectHash, key::PublicKey, repository::NamedItems, semver::Version, unwrap, utils::{load_as_msgpack, save_as_msgpack}};
+
+/// Why this process is changing refs, recorded with each change in their logs.
+static REASON: RwLock<Option<String>> = RwLock::new(None);
+
+/// Set why refs are being changed from here on, like the command being run.
+pub fn set_reason(reason: impl Into<String>) {
+    *REASON.write().unwrap() = Some(reason.into());
+}
+
+fn reason() -> Option<String> {
+    REASON.read().unwrap().clone()
+}
+
+/// The namespaces a [`Ref`] can live in.
+#[derive(Clone, Copy, Debug, Display, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum RefKind {
+    #[display("branches")]
+    Branch,
+
+    #[display("tags")]
+    Tag,
+
+    #[display("remotes")]
+    Remote,
+
+    #[display("backups")]
+    Backup,
+
+    #[display("stash")]
+    Stash
+}
+
+/// A named pointer to a snapshot.
+///
+/// The full name of a ref is its namespace followed by its name,
+/// like `branches/main`, `tags/v1.0` or `remotes/origin/main`.
+#[derive(Clone, Debug, Deserialize, Display, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+pub enum Ref {
+    #[display("branches/{_0}")]
+    Branch(String),
+
+    #[display("tags/{_0}")]
+    Tag(String),
+
+    /// A branch as it was last seen on a remote, named `<remote>/<branch>`.
+    #[display("remotes/{_0}")]
+    Remote(String),
+
+    /// The old tip of a branch, kept when pulling replaced it with a diverging one.
+    #[display("backups/{_0}")]
+    Backup(String),
+
+    /// An entry in the [`Stash`](crate::stash::Stash), by its ID.
+    ///
+    /// These aren't kept in a [`RefStore`] and point to the
+    /// snapshot the entry was stashed on top of.
+    #[display("stash/{_0}")]
+    Stash(usize)
+}
+
+impl Ref {
+    /// Create a ref of a given kind from its name within that kind's namespace.
+    pub fn new(kind: RefKind, name: &str) -> Result<Ref> {
+        let r = match kind {
+            RefKind::Branch => Ref::Branch(name.to_string()),
+            RefKind::Tag => Ref::Tag(name.to_string()),
+            RefKind::Backup => Ref::Backup(name.to_string()),
+
+            RefKind::Remote => {
+                if !name.contains('/') {
+                    bail!("remote ref {name:?} should be written as <remote>/<branch>");
+                }
+
+                Ref::Remote(name.to_string())
+            }
+
+            RefKind::Stash => match name.parse() {
+                Ok(id) => Ref::Stash(id),
+                Err(_) => bail!("invalid stash ID: {name:?}")
+            }
+        };
+
+        Ok(r)
+    }
+
+    /// Get the namespace this ref is in.
+    pub fn kind(&self) -> RefKind {
+        match self {
+            Ref::Branch(_) => RefKind::Branch,
+            Ref::Tag(_) => RefKind::Tag,
+            Ref::Remote(_) => RefKind::Remote,
+            Ref::Backup(_) => RefKind::Backup,
+            Ref::Stash(_) => RefKind::Stash
+        }
+    }
+
+    /// Get the name of this ref within its namespace.
+    ///
+    /// This is `None` for stash refs, which are named by a number.
+    pub fn name(&self) -> Option<&str> {
+        match self {
+            Ref::Branch(name) | Ref::Tag(name) | Ref::Remote(name) | Ref::Backup(name) => Some(name),
+            Ref::Stash(_) => None
+        }
+    }
+}
+
+impl FromStr for Ref {
+    type Err = Report;
+
+    /// Parse the full name of a ref, like `tags/v1.0`.
+    fn from_str(s: &str) -> Result<Ref> {
+        let Some((namespace, name)) = s.split_once('/') else {
+            bail!("ref {s:?} has no namespace");
+        };
+
+        if name.is_empty() {
+            bail!("ref {s:?} has no name");
+        }
+
+        let kind = match namespace {
+            "branches" => RefKind::Branch,
+            "tags" => RefKind::Tag,
+            "remotes" => RefKind::Remote,
+            "backups" => RefKind::Backup,
+            "stash" => RefKind::Stash,
+            _ => bail!("unknown ref namespace: {namespace:?}")
+        };
+
+        Ref::new(kind, name)
+    }
+}
+
+/// A change made to a ref, where `None` means the ref didn't exist.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ReflogEntry {
+    pub old: Option<ObjectHash>,
+    pub new: Option<ObjectHash>,
+    pub timestamp: DateTime<Utc>,
+
+    /// What made the change, if it was recorded.
+    #[serde(default)]
+    pub reason: Option<String>
+}
+
+/// What is currently checked out.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum Head {
+    /// On a branch, following its tip as it moves.
+    Branch(String),
+
+    /// On a snapshot that isn't followed by any branch.
+    Detached(ObjectHash)
+}
+
+/// A place the head has been, kept so that snapshots only the
+/// head pointed to, like ones made while detached, can be found again.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct HeadLogEntry {
+    pub head: Head,
+    pub hash: ObjectHash,
+    pub timestamp: DateTime<Utc>,
+
+    /// What moved the head, if it was recorded.
+    #[serde(default)]
+    pub reason: Option<String>
+}
+
+impl Default for Head {
+    fn default() -> Head {
+        Head::Detached(ObjectHash::default())
+    }
+}
+
+/// When a branch made for a short-lived experiment can be deleted.
+///
+/// Ephemeral branches are deleted by `asc clean` once they are merged
+/// into another branch or have expired, and aren't pushed unless asked.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Ephemeral {
+    pub created: DateTime<Utc>,
+
+    /// Where the branch pointed when it was made ephemeral. Until it moves
+    /// from here, it has nothing of its own to merge, so it is kept.
+    pub basis: ObjectHash,
+
+    /// When the branch can be deleted even if it isn't merged,
+    /// or `None` to only delete it once it is merged.
+    pub expires: Option<DateTime<Utc>>
+}
+
+/// Who made a version of a tag and when, so that when two repositories
+/// have the tag at different snapshots, both can agree on which to keep
+/// without asking each other. Used when `tags.replicated` is set.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct TagStamp {
+    pub created: DateTime<Utc>,
+    pub creator: PublicKey,
+
+    /// A Lamport clock: one more than the highest counter the
+    /// repository had seen on any tag when this version was made.
+    pub counter: u64,
+
+    /// The counters and creators of the earlier versions of
+    /// the tag that this one replaced by moving it.
+    pub replaces: Vec<(u64, PublicKey)>
+}
+
+impl TagStamp {
+    fn id(&self) -> (u64, PublicKey) {
+        (self.counter, self.creator)
+    }
+
+    /// Compare two versions of a tag, where the lesser one is kept.
+    ///
+    /// A version that replaced the other one by moving the tag is kept.
+    /// Otherwise, the first one made is kept: the one with the lowest
+    /// counter, then the earliest, then the one with the lowest key.
+    pub fn precedence(&self, other: &TagStamp) -> Ordering {
+        if self.replaces.contains(&other.id()) {
+            return Ordering::Less;
+        }
+
+        if other.replaces.contains(&self.id()) {
+            return Ordering::Greater;
+        }
+
+        self.counter.cmp(&other.counter)
+            .then(self.created.cmp(&other.created))
+            .then_with(|| self.creator.to_bytes().cmp(&other.creator.to_bytes()))
+    }
+}
+
+/// Check if our version of a tag is kept over theirs.
+///
+/// A stamped version is kept over one that isn't, and between
+/// two that aren't stamped, the one at the lowest hash is kept.
+pub fn keeps_tag(ours: (ObjectHash, Option<&TagStamp>), theirs: (ObjectHash, Option<&TagStamp>)) -> bool {
+    match (ours.1, theirs.1) {
+        (Some(a), Some(b)) => a.precedence(b) != Ordering::Greater,
+        (Some(_), None) => true,
+        (None, Some(_)) => false,
+        (None, None) => ours.0 <= theirs.0
+    }
+}
+
+/// Order tag names by the versions they name, so `v1.10.0` comes after
+/// `v1.9.0` and `v2.0.0-rc.1` comes before `v2.0.0`.
+/// 
+/// Names that aren't versions come after those that are, by name.
+pub fn compare_tag_names(a: &str, b: &str) -> Ordering {
+    match (Version::parse(a), Version::parse(b)) {
+        (Some(x), Some(y)) => x.cmp(&y).then_with(|| a.cmp(b)),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => a.cmp(b)
+    }
+}
+
+/// Every ref in a repository, along with a log of how each one has changed.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct RefStore {
+    refs: BTreeMap<Ref, ObjectHash>,
+
+    /// Kept in `.asc/logs` rather than with the refs, so this is only
+    /// read from refs saved before that, and always saved empty.
+    #[serde(serialize_with = "serialize_empty")]
+    reflogs: BTreeMap<Ref, Vec<ReflogEntry>>,
+
+    /// Always either detached, or on a branch that exists.
+    #[serde(default)]
+    head: Head,
+
+    /// The branches that are ephemeral, by name.
+    #[serde(default)]
+    ephemeral: BTreeMap<String, Ephemeral>,
+
+    /// Where the head has been, oldest first. Like the reflogs,
+    /// this is kept in `.asc/logs` and always saved empty.
+    #[serde(default, serialize_with = "serialize_empty")]
+    head_log: Vec<HeadLogEntry>,
+
+    /// Who made each tag, by name, for tags made with `tags.replicated` set.
+    #[serde(default)]
+    tag_stamps: BTreeMap<String, TagStamp>,
+
+    /// The reflogs changed since they were last saved.
+    #[serde(skip)]
+    changed_logs: BTreeSet<Ref>,
+
+    #[serde(skip)]
+    head_log_changed: bool
+}
+
+/// Save a collection as if it were empty, whatever it holds.
+fn serialize_empty<T: Default + Serialize, S: Serializer>(_: &T, serializer: S) -> Result<S::Ok, S::Error> {
+    T::default().serialize(serializer)
+}
+
+/// Get the file a ref's log is kept in, under `dir`. Slashes in the name are
+/// escaped, so that a branch like `a` can have a log next to one like `a/b`.
+fn log_path(dir: &Path, r: &Ref) -> std::path::PathBuf {
+    let name = match r.name() {
+        Some(name) => name.replace('%', "%25").replace('/', "%2F"),
+        None => r.to_string()
+    };
+
+    dir.join(r.kind().to_string()).join(name)
+}
+
+impl RefStore {
+    /// Create an empty [`RefStore`].
+    pub fn new() -> RefStore {
+        RefStore::default()
+    }
+
+    /// Build a [`RefStore`] from branches and tags stored
+    /// the way they were before refs were introduced.
+    /// 
+    /// The head is put on a branch at `current` if there is
+    /// one, like it was treated before, or detached otherwise.
+    pub fn from_legacy(branches: NamedItems<ObjectHash>, tags: NamedItems<ObjectHash>, current: ObjectHash) -> RefStore {
+        let mut refs = BTreeMap::new();
+
+        refs.extend(branches.into_iter().map(|(name, hash)| (Ref::Branch(name), hash)));
+        refs.extend(tags.into_iter().map(|(name, hash)| (Ref::Tag(name), hash)));
+
+        let mut store = RefStore { refs, reflogs: BTreeMap::new(), head: Head::default(), ephemeral: BTreeMap::new(), head_log: vec![], tag_stamps: BTreeMap::new(), changed_logs: BTreeSet::new(), head_log_changed: false };
+
+        store.switch_to_hash(current);
+
+        store
+    }
+
+    /// Get what is currently checked out.
+    pub fn head(&self) -> &Head {
+        &self.head
+    }
+
+    /// Get the snapshot that is currently checked out.
+    pub fn head_hash(&self) -> ObjectHash {
+        match &self.head {
+            Head::Branch(name) => self.branches()
+                .get(name)
+                .expect("head is on a branch that doesn't exist"),
+
+            &Head::Detached(hash) => hash
+        }
+    }
+
+    /// Get the branch the head is on, if it isn't detached.
+    pub fn current_branch(&self) -> Option<&str> {
+        match &self.head {
+            Head::Branch(name) => Some(name),
+            Head::Detached(_) => None
+        }
+    }
+
+    /// Put the head on a branch.
+    /// 
+    /// Returns `false` if the branch doesn't exist.
+    pub fn attach_head(&mut self, name: &str) -> bool {
+        if !self.branches().contains(name) {
+            return false;
+        }
+
+        self.head = Head::Branch(name.to_string());
+
+        self.log_head();
+
+        true
+    }
+
+    /// Detach the head at a snapshot.
+    pub fn detach_head(&mut self, hash: ObjectHash) {
+        self.head = Head::Detached(hash);
+
+        self.log_head();
+    }
+
+    /// Replace the head, like with one from [`RefStore::head`].
+    /// 
+    /// Returns `false` if the head would be on a branch that doesn't exist.
+    pub fn set_head(&mut self, head: Head) -> bool {
+        match head {
+            Head::Branch(name) => self.attach_head(&name),
+
+            Head::Detached(hash) => {
+                self.detach_head(hash);
+
+                true
+            }
+        }
+    }
+
+    /// Move the head to a snapshot, putting it on a branch
+    /// if one is at that snapshot.
+    /// 
+    /// This is for when only a hash is known, like when undoing a switch.
+    pub fn switch_to_hash(&mut self, hash: ObjectHash) {
+        if self.head_hash() == hash {
+            return;
+        }
+
+        match self.branches().get_name_for(hash).map(String::from) {
+            Some(name) => self.head = Head::Branch(name),
+            None => self.head = Head::Detached(hash)
+        }
+
+        self.log_head();
+    }
+
+    /// Record where the head is in its history, unless it was already there.
+    fn log_head(&mut self) {
+        let hash = self.head_hash();
+
+        if let Some(last) = self.head_log.last()
+            && last.head == self.head
+            && last.hash == hash
+        {
+            return;
+        }
+
+        self.head_log.push(HeadLogEntry { head: self.head.clone(), hash, timestamp: Utc::now(), reason: reason() });
+
+        self.head_log_changed = true;
+    }
+
+    /// Stamp a tag as just made by `creator`, replacing any stamp it had before.
+    pub fn stamp_tag(&mut self, name: &str, creator: PublicKey) -> &TagStamp {
+        let counter = self.tag_stamps
+            .values()
+            .map(|stamp| stamp.counter)
+            .max()
+            .unwrap_or_default() + 1;
+
+        let replaces = match self.tag_stamps.remove(name) {
+            Some(old) => {
+                let mut replaces = old.replaces.clone();
+
+                replaces.push(old.id());
+
+                replaces
+            },
+
+            None => vec![]
+        };
+
+        let stamp = TagStamp { created: Utc::now(), creator, counter, replaces };
+
+        self.tag_stamps.entry(name.to_string()).insert_entry(stamp).into_mut()
+    }
+
+    /// Get who made a tag, if it was stamped.
+    pub fn tag_stamp(&self, name: &str) -> Option<&TagStamp> {
+        self.tag_stamps.get(name)
+    }
+
+    /// Get the stamps of every tag that has one.
+    pub fn tag_stamps(&self) -> &BTreeMap<String, TagStamp> {
+        &self.tag_stamps
+    }
+
+    /// Set a tag's stamp to one received from another repository,
+    /// or remove it, if the other repository's version had none.
+    pub fn set_tag_stamp(&mut self, name: &str, stamp: Option<TagStamp>) {
+        match stamp {
+            Some(stamp) => { self.tag_stamps.insert(name.to_string(), stamp); },
+            None => { self.tag_stamps.remove(name); }
+        }
+    }
+
+    /// Get where the head has been, oldest first.
+    pub fn head_log(&self) -> &[HeadLogEntry] {
+        &self.head_log
+    }
+
+    fn log(&mut self, r: &Ref, old: Option<ObjectHash>, new: Option<ObjectHash>) {
+        if old == new {
+            return;
+        }
+
+        let entry = ReflogEntry { old, new, timestamp: Utc::now(), reason: reason() };
+
+        self.reflogs
+            .entry(r.clone())
+            .or_default()
+            .push(entry);
+
+        self.changed_logs.insert(r.clone());
+
+        // Moving the current branch moves the head with it.
+        if new.is_some()
+            && let Ref::Branch(name) = r
+            && self.current_branch() == Some(name)
+        {
+            self.log_head();
+        }
+    }
+
+    /// Read the reflogs and the head's log from `dir`, where [`RefStore::save_logs`] keeps them.
+    ///
+    /// Logs read from the refs file, saved before logs were kept apart,
+    /// are kept, and are written to `dir` the next time logs are saved.
+    pub fn load_logs(&mut self, dir: &Path) -> Result<()> {
+        self.changed_logs.extend(self.reflogs.keys().cloned());
+
+        self.head_log_changed = !self.head_log.is_empty();
+
+        let head_path = dir.join("HEAD");
+
+        if head_path.exists() {
+            self.head_log = load_as_msgpack(head_path)?;
+        }
+
+        let kinds = [RefKind::Branch, RefKind::Tag, RefKind::Remote, RefKind::Backup];
+
+        for kind in kinds {
+            let kind_dir = dir.join(kind.to_string());
+
+            if !kind_dir.is_dir() {
+                continue;
+            }
+
+            let entries = unwrap!(
+                fs::read_dir(&kind_dir),
+                "failed to read logs from: {}", kind_dir.display()
+            );
+
+            for entry in entries {
+                let path = entry?.path();
+
+                let Some(file_name) = path.file_name().and_then(|name| name.to_str()) else {
+                    continue;
+                };
+
+                let name = file_name.replace("%2F", "/").replace("%25", "%");
+
+                let log = load_as_msgpack(&path)?;
+
+                self.reflogs.insert(Ref::new(kind, &name)?, log);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Write the logs changed since they were last loaded or saved to `dir`.
+    pub fn save_logs(&mut self, dir: &Path) -> Result<()> {
+        if self.head_log_changed {
+            unwrap!(
+                fs::create_dir_all(dir),
+                "failed to create directory: {}", dir.display()
+            );
+
+            save_as_msgpack(&self.head_log, dir.join("HEAD"))?;
+        }
+
+        for r in &self.changed_logs {
+            let path = log_path(dir, r);
+
+            match self.reflogs.get(r) {
+                Some(log) if !log.is_empty() => {
+                    let parent = path.parent().unwrap();
+
+                    unwrap!(
+                        fs::create_dir_all(parent),
+                        "failed to create directory: {}", parent.display()
+                    );
+
+                    save_as_msgpack(log, &path)?;
+                },
+
+                // The log was moved to another ref by a rename.
+                _ => if path.exists() {
+                    unwrap!(
+                        fs::remove_file(&path),
+                        "failed to remove old log: {}", path.display()
+                    );
+                }
+            }
+        }
+
+        self.changed_logs.clear();
+
+        self.head_log_changed = false;
+
+        Ok(())
+    }
+
+    /// Get the snapshot a ref points to.
+    pub fn get(&self, r: &Ref) -> Option<ObjectHash> {
+        self.refs.get(r).copied()
+    }
+
+    /// Check if a ref exists.
+    pub fn contains(&self, r: &Ref) -> bool {
+        self.refs.contains_key(r)
+    }
+
+    /// Point a ref at a snapshot, returning where it pointed before.
+    pub fn set(&mut self, r: Ref, hash: ObjectHash) -> Option<ObjectHash> {
+        let old = self.refs.insert(r.clone(), hash);
+
+        self.log(&r, old, Some(hash));
+
+        old
+    }
+
+    /// Delete a ref, returning where it pointed.
+    ///
+    /// The reflog of the ref is kept.
+    /// 
+    /// If the head is on a branch that is removed, it is detached.
+    pub fn remove(&mut self, r: &Ref) -> Option<ObjectHash> {
+        let old = self.refs.remove(r)?;
+
+        self.log(r, Some(old), None);
+
+        if let Ref::Tag(name) = r {
+            self.tag_stamps.remove(name);
+        }
+
+        if let Ref::Branch(name) = r {
+            self.ephemeral.remove(name);
+
+            if self.current_branch() == Some(name) {
+                self.head = Head::Detached(old);
+            }
+        }
+
+        Some(old)
+    }
+
+    /// Rename a ref, carrying its reflog and the head over to the new name.
+    ///
+    /// Returns `false` if `old` doesn't exist.
+    pub fn rename(&mut self, old: &Ref, new: Ref) -> bool {
+        let Some(hash) = self.refs.remove(old) else {
+            return false;
+        };
+
+        if let Some(log) = self.reflogs.remove(old) {
+            self.reflogs.insert(new.clone(), log);
+
+            self.changed_logs.insert(old.clone());
+            self.changed_logs.insert(new.clone());
+        }
+
+        if let (Ref::Tag(old_name), Ref::Tag(new_name)) = (old, &new)
+            && let Some(stamp) = self.tag_stamps.remove(old_name)
+        {
+            self.tag_stamps.insert(new_name.clone(), stamp);
+        }
+
+        if let (Ref::Branch(old_name), Ref::Branch(new_name)) = (old, &new) {
+            if let Some(ephemeral) = self.ephemeral.remove(old_name) {
+                self.ephemeral.insert(new_name.clone(), ephemeral);
+            }
+
+            if self.current_branch() == Some(old_name) {
+                self.head = Head::Branch(new_name.clone());
+            }
+        }
+
+        self.set(new, hash);
+
+        true
+    }
+
+    /// Find a ref from a name given by a user.
+    ///
+    /// Full names like `tags/v1.0` are looked up directly, as are names
+    /// with their kind in front, like `branch:main` or `tag:v1.0`, while
+    /// `hash:` in front means it isn't a ref at all. Otherwise, branches
+    /// are checked before tags, remote refs and backups.
+    pub fn lookup(&self, name: &str) -> Option<(&Ref, ObjectHash)> {
+        let explicit = match name.trim_start().split_once(':') {
+            Some(("branch", rest)) => Some(Ref::Branch(rest.to_string())),
+            Some(("tag", rest)) => Some(Ref::Tag(rest.to_string())),
+            Some(("hash", _)) => return None,
+            _ => None
+        };
+
+        if let Some(r) = explicit {
+            return self.refs.get_key_value(&r).map(|(r, &hash)| (r, hash));
+        }
+
+        if let Ok(r) = name.parse::<Ref>()
+            && let Some((r, &hash)) = self.refs.get_key_value(&r)
+        {
+            return Some((r, hash));
+        }
+
+        [RefKind::Branch, RefKind::Tag, RefKind::Remote, RefKind::Backup]
+            .into_iter()
+            .filter_map(|kind| Ref::new(kind, name).ok())
+            .find_map(|r| self.refs.get_key_value(&r))
+            .map(|(r, &hash)| (r, hash))
+    }
+
+    /// Iterate through every ref, in order of namespace and then name.
+    pub fn iter(&self) -> impl Iterator<Item = (&Ref, ObjectHash)> {
+        self.refs.iter().map(|(r, &hash)| (r, hash))
+    }
+
+    /// Iterate through the refs of one kind.
+    pub fn iter_kind(&self, kind: RefKind) -> impl Iterator<Item = (&Ref, ObjectHash)> {
+        self.iter().filter(move |(r, _)| r.kind() == kind)
+    }
+
+    /// Make a name for the local version of a ref, kept when pulling finds
+    /// another version of it, like `main-local-2025-06-01-ab12cd`. The name
+    /// is made from the day and the local hash, with a number added to the
+    /// end if a ref of `kind` already has it.
+    pub fn conflict_copy_name(&self, kind: RefKind, name: &str, hash: ObjectHash) -> String {
+        let base = format!("{name}-local-{}-{}", Utc::now().format("%Y-%m-%d"), &hash.full()[..6]);
+
+        let taken = |candidate: &str| {
+            Ref::new(kind, candidate).is_ok_and(|r| self.contains(&r))
+        };
+
+        if !taken(&base) {
+            return base;
+        }
+
+        (2..)
+            .map(|n| format!("{base}-{n}"))
+            .find(|candidate| !taken(candidate))
+            .unwrap()
+    }
+
+    /// Get the refs pointing to a snapshot.
+    pub fn refs_to(&self, hash: ObjectHash) -> impl Iterator<Item = &Ref> {
+        self.iter()
+            .filter(move |&(_, target)| target == hash)
+            .map(|(r, _)| r)
+    }
+
+    /// Mark a branch as ephemeral, to be deleted once it is merged or
+    /// after `expires`. Returns `false` if the branch doesn't exist.
+    pub fn mark_ephemeral(&mut self, name: &str, expires: Option<DateTime<Utc>>) -> bool {
+        let Some(&basis) = self.refs.get(&Ref::Branch(name.to_string())) else {
+            return false;
+        };
+
+        self.ephemeral.insert(name.to_string(), Ephemeral { created: Utc::now(), basis, expires });
+
+        true
+    }
+
+    /// Get when an ephemeral branch can be deleted, or `None` if the branch isn't ephemeral.
+    pub fn ephemeral(&self, name: &str) -> Option<&Ephemeral> {
+        self.ephemeral.get(name)
+    }
+
+    /// Check if a branch is ephemeral.
+    pub fn is_ephemeral(&self, name: &str) -> bool {
+        self.ephemeral.contains_key(name)
+    }
+
+    /// Get the changes made to a ref, oldest first.
+    pub fn reflog(&self, r: &Ref) -> &[ReflogEntry] {
+        self.reflogs
+            .get(r)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    /// View the branches.
+    pub fn branches(&self) -> Namespace<'_> {
+        Namespace { store: self, kind: RefKind::Branch }
+    }
+
+    /// Edit the branches.
+    pub fn branches_mut(&mut self) -> NamespaceMut<'_> {
+        NamespaceMut { store: self, kind: RefKind::Branch }
+    }
+
+    /// View the tags.
+    pub fn tags(&self) -> Namespace<'_> {
+        Namespace { store: self, kind: RefKind::Tag }
+    }
+
+    /// Edit the tags.
+    pub fn tags_mut(&mut self) -> NamespaceMut<'_> {
+        NamespaceMut { store: self, kind: RefKind::Tag }
+    }
+
+    /// View the refs of any kind by their short names.
+    pub fn namespace(&self, kind: RefKind) -> Namespace<'_> {
+        Namespace { store: self, kind }
+    }
+}
+
+/// A view of the refs of one kind, addressed by their short names.
+#[derive(Clone, Copy)]
+pub struct Namespace<'a> {
+    store: &'a RefStore,
+    kind: RefKind
+}
+
+impl<'a> Namespace<'a> {
+    pub fn get(&self, name: &str) -> Option<ObjectHash> {
+        self.store.get(&Ref::new(self.kind, name).ok()?)
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&'a str, ObjectHash)> + use<'a> {
+        self.store
+            .iter_kind(self.kind)
+            .filter_map(|(r, hash)| Some((r.name()?, hash)))
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &'a str> + use<'a> {
+        self.iter().map(|(name, _)| name)
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = ObjectHash> + use<'a> {
+        self.iter().map(|(_, hash)| hash)
+    }
+
+    pub fn get_name_for(&self, hash: ObjectHash) -> Option<&'a str> {
+        self.get_names_for(hash).into_iter().next()
+    }
+
+    pub fn get_names_for(&self, hash: ObjectHash) -> Vec<&'a str> {
+        self.iter()
+            .filter(|&(_, target)| target == hash)
+            .map(|(name, _)| name)
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.iter().count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.iter().next().is_none()
+    }
+
+    /// Copy these refs into the format used when syncing.
+    pub fn to_named_items(&self) -> NamedItems<ObjectHash> {
+        let mut items = NamedItems::new();
+
+        for (name, hash) in self.iter() {
+            items.create(name.to_string(), hash);
+        }
+
+        items
+    }
+}
+
+/// An editable view of the branches or the tags, addressed by their names.
+pub struct NamespaceMut<'a> {
+    store: &'a mut RefStore,
+    kind: RefKind
+}
+
+impl NamespaceMut<'_> {
+    fn make_ref(&self, name: String) -> Ref {
+        match self.kind {
+            RefKind::Tag => Ref::Tag(name),
+            _ => Ref::Branch(name)
+        }
+    }
+
+    /// Point a ref at a snapshot, returning where it pointed before.
+    pub fn create(&mut self, name: String, hash: ObjectHash) -> Option<ObjectHash> {
+        let r = self.make_ref(name);
+
+        self.store.set(r, hash)
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<ObjectHash> {
+        let r = self.make_ref(name.to_string());
+
+        self.store.remove(&r)
+    }
+
+    pub fn rename(&mut self, old: &str, new: String) -> bool {
+        let old = self.make_ref(old.to_string());
+        let new = self.make_ref(new);
+
+        self.store.rename(&old, new)
+    }
+}
@@ -1,12 +1,19 @@
-use std::{collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque}, env::current_dir, fs, path::{Path, PathBuf}, str::FromStr, sync::{Arc, RwLock}};
+use std::{collections::{BTreeMap, BTreeSet, HashMap, HashSet}, env::{self, current_dir}, fs, path::{Path, PathBuf}, sync::{Arc, OnceLock, RwLock}, time::Duration};
 
-use crate::{action::{Action, ActionHistory}, change::FileChange, content::{Content, Delta}, graph::Graph, hash::ObjectHash, key::PublicKey, set, snapshot::Snapshot, stash::Stash, sync::remote::Remote, trash::{Entry, Trash, TrashStatus}, unwrap, user::{User, Users}, utils::{compress_data, create_file, hash_raw_bytes, load_as_msgpack, open_file, remove_path, resolve_wildcard_path, save_as_msgpack}};
+use crate::{action::{Action, ActionHistory}, ascignore::IgnoreRules, blobs::{BlobStore, ObjectStore}, cache::ObjectCache, change::FileChange, checkout::{self, TempCheckout}, clock::{self, Clock}, config::{Config, ContentConfig, StoreKind}, content::{Content, Delta, Pointer}, fence::fenced_hashes, guard::{guard, Change}, graph::Graph, hash::{ObjectHash, validate_hex}, key::PublicKey, link::Links, mode::{self, FileMode}, paths, pin::{Pin, Pins}, refs::{Head, Ref, RefKind, RefStore}, retention, set, snapshot::Snapshot, staging::StagingArea, stash::{Stash, State}, sync::remote::Remote, trash::{Entry, Purged, Trash, TrashStatus}, unwrap, user::{User, Users}, utils::{compress_data, create_file, decompress_data, hash_raw_bytes, load_as_msgpack, open_file, remove_path, resolve_wildcard_path, save_as_msgpack}, watch::WatchState};
 
-use chrono::Utc;
+#[cfg(feature = "s3")]
+use crate::s3::S3Store;
+
+#[cfg(feature = "sqlite")]
+use crate::sqlite::SqliteStore;
+
+use chrono::{DateTime, TimeDelta, Utc};
+use derive_more::{Display, Error};
 use expand_tilde::ExpandTilde;
 use eyre::{bail, eyre, Result};
-use ignore::gitignore::{Gitignore, GitignoreBuilder};
-use relative_path::{PathExt, RelativePathBuf};
+use rayon::{prelude::*, ThreadPoolBuilder};
+use relative_path::{PathExt, RelativePath, RelativePathBuf};
 use serde::{Deserialize, Serialize};
 
 #[derive(Clone, Debug, Default, Deserialize, Serialize)]
@@ -92,21 +99,71 @@ impl<T: Clone> NamedItems<T> {
     }
 }
 
+/// The error given when something would overwrite changes in the
+/// working directory that haven't been committed or stashed.
+#[derive(Debug, Display, Error)]
+#[display("cannot {action} with unsaved changes.")]
+pub struct UnsavedChanges {
+    pub(crate) action: String
+}
+
+/// The error given when an object in the blob store can't be read,
+/// or doesn't match its hash or signature.
+#[derive(Debug, Display, Error)]
+#[display("object {hash} is corrupted: {reason}")]
+pub struct CorruptObject {
+    pub hash: ObjectHash,
+    pub(crate) reason: String
+}
+
 pub struct Repository {
     pub project_name: String,
     pub project_code: ObjectHash,
     pub root_dir: PathBuf,
     pub history: Graph,
     pub action_history: ActionHistory,
-    pub branches: NamedItems<ObjectHash>,
-    pub current_hash: ObjectHash,
-    pub staged_files: Vec<RelativePathBuf>,
-    pub ignore_matcher: Gitignore,
+
+    /// The branches, tags and other refs, as well as the head.
+    pub refs: RefStore,
+
+    /// The tracked files and the content staged for each,
+    /// which make up the next snapshot.
+    pub staging: StagingArea,
+    pub ignore_rules: IgnoreRules,
     pub stash: Stash,
     pub trash: Trash,
-    pub tags: NamedItems<ObjectHash>,
+
+    /// The snapshots that are kept even when nothing else uses them.
+    pub pins: Pins,
+
     pub users: Users,
     pub remotes: NamedItems<Remote>,
+    pub links: Links,
+    pub config: Config,
+
+    /// Objects recently read from the blob store,
+    /// shared with every [`BlobStore`] made from this.
+    pub cache: Arc<ObjectCache>,
+
+    /// The format the repository is stored in, which is
+    /// newer than [`REPOSITORY_FORMAT`] if a newer version wrote it.
+    pub format: u32,
+
+    /// The roots of other repositories on this machine that objects
+    /// missing from this one are borrowed from, like a clone made with
+    /// `--reference`. Those repositories have to be kept around.
+    pub alternates: Vec<PathBuf>,
+
+    /// The branch clones check out unless they ask for another one.
+    /// The branch checked out here is used if this isn't set.
+    pub default_branch: Option<String>,
+
+    /// The store objects are kept in, when the config
+    /// says to keep them somewhere other than the blob store.
+    pub(crate) store: Option<Arc<dyn ObjectStore>>,
+
+    /// Where new snapshots get their timestamps from.
+    pub(crate) clock: Arc<dyn Clock>,
 
     pub(crate) current_user: Arc<RwLock<Option<PublicKey>>>
 }
@@ -186,176 +243,545 @@ impl Repository {
         Ok(())
     }
 
+    /// Get the snapshot that is currently checked out.
+    pub fn current_hash(&self) -> ObjectHash {
+        self.refs.head_hash()
+    }
+
     /// Get the branch the repository is currently on.
     /// 
-    /// This is only found if the `current_hash` points to a branch tip.
-    /// Any other snapshot will result in `None`.
+    /// This is `None` if the head is detached, even if
+    /// a branch happens to point to the current snapshot.
     pub fn current_branch(&self) -> Option<&str> {
-        self.branches.get_name_for(self.current_hash)
+        self.refs.current_branch()
     }
 
-    /// Find if the `current_hash` doesn't point to the tip of any branch.
+    /// Get the branch clones of this repository check out by default: the
+    /// [`default_branch`](Repository::default_branch) if it still exists,
+    /// or the branch checked out here otherwise.
+    pub fn default_branch_name(&self) -> Option<&str> {
+        self.default_branch
+            .as_deref()
+            .filter(|name| self.refs.branches().contains(name))
+            .or_else(|| self.current_branch())
+    }
+
+    /// Find if the head isn't on a branch.
     pub fn is_head_detached(&self) -> bool {
         self.current_branch().is_none()
     }
 
-    fn append_snapshot_internal(&mut self, snapshot: Snapshot, branch_name: Option<String>) -> Result<()> {
-        let hash = snapshot.hash;
+    fn append_snapshot_internal(&mut self, snapshot: Snapshot, branch_name: Option<String>) -> Result<ObjectHash> {
+        let before = self.current_hash();
+
+        let hash = self.save_snapshot(snapshot)?;
 
-        if let Some(name) = branch_name {
-            self.branches.create(name.clone(), hash);
+        // Committing to the branch the head is on (or to a detached head)
+        // only moves the head forward, which is undone in one step.
+        let Some(name) = branch_name.filter(|name| self.current_branch() != Some(name)) else {
+            let branch = self.current_branch().map(String::from);
 
             self.action_history.push(
-                Action::CreateBranch {
-                    name,
-                    hash
+                Action::CreateSnapshot {
+                    hash,
+                    parent: before,
+                    branch: branch.clone()
                 }
             );
-        }
 
-        self.save_snapshot(snapshot)?;
+            self.place_head(hash, branch);
+
+            return Ok(hash);
+        };
 
         self.action_history.push(
-            Action::SwitchVersion {
-                before: self.current_hash,
-                after: hash
+            Action::CreateSnapshot {
+                hash,
+                parent: before,
+                branch: None
             }
         );
 
-        self.current_hash = hash;
+        self.refs.detach_head(hash);
+
+        let previous = self.refs.branches_mut().create(name.clone(), hash);
+
+        match previous {
+            Some(old) => self.action_history.push(
+                Action::MoveBranch {
+                    name: name.clone(),
+                    old,
+                    new: hash
+                }
+            ),
+
+            None => self.action_history.push(
+                Action::CreateBranch {
+                    name: name.clone(),
+                    hash
+                }
+            )
+        }
+
+        self.refs.attach_head(&name);
         
+        Ok(hash)
+    }
+
+    /// Rename a branch, along with what refers to it by name: the head,
+    /// its reflog, the backups kept from pulling it, where it was last
+    /// seen on each remote, and its settings in the config.
+    /// 
+    /// The config is saved straight away if its settings had to change,
+    /// as described in [`Config::rename_branch`].
+    /// 
+    /// Returns `false` if there is no branch called `old`.
+    pub fn rename_branch(&mut self, old: &str, new: String) -> Result<bool> {
+        if !self.refs.branches().contains(old) {
+            return Ok(false);
+        }
+
+        if self.config.rename_branch(old, &new)? {
+            self.config.save(self.main_dir().join("config.toml"))?;
+        }
+
+        self.refs.branches_mut().rename(old, new.clone());
+
+        let backups: Vec<String> = self.conflict_copies(old)
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+
+        for backup in backups {
+            let renamed = format!("{new}{}", &backup[old.len()..]);
+
+            self.refs.rename(&Ref::Backup(backup), Ref::Backup(renamed));
+        }
+
+        // So it is still compared with where it is on each remote.
+        let remotes: Vec<String> = self.remotes.names().cloned().collect();
+
+        for remote in remotes {
+            self.refs.rename(
+                &Ref::Remote(format!("{remote}/{old}")),
+                Ref::Remote(format!("{remote}/{new}"))
+            );
+        }
+
+        if self.default_branch.as_deref() == Some(old) {
+            self.default_branch = Some(new);
+        }
+
+        Ok(true)
+    }
+
+    /// Find the backups kept from pulling a branch when it had diverged from
+    /// the remote, by their names in the `backups` namespace. These are named
+    /// like `main-local-2025-06-01-ab12cd`, or just `main` by older versions.
+    pub fn conflict_copies(&self, branch: &str) -> Vec<(String, ObjectHash)> {
+        let prefix = format!("{branch}-local-");
+
+        self.refs
+            .namespace(RefKind::Backup)
+            .iter()
+            .filter(|(name, _)| *name == branch || name.starts_with(&prefix))
+            .map(|(name, hash)| (name.to_string(), hash))
+            .collect()
+    }
+
+    /// Find the ephemeral branches that can be deleted: those that have
+    /// expired, and those that have moved since they were made, and whose
+    /// tip is already on another, lasting branch.
+    ///
+    /// The current branch is never included.
+    pub fn expired_branches(&self) -> Result<Vec<String>> {
+        let now = Utc::now();
+
+        let branches = self.refs.branches();
+
+        let lasting: Vec<ObjectHash> = branches
+            .iter()
+            .filter(|(name, _)| !self.refs.is_ephemeral(name))
+            .map(|(_, hash)| hash)
+            .collect();
+
+        let mut expired = vec![];
+
+        for (name, tip) in branches.iter() {
+            let Some(ephemeral) = self.refs.ephemeral(name) else {
+                continue;
+            };
+
+            if self.current_branch() == Some(name) {
+                continue;
+            }
+
+            let is_expired = ephemeral.expires.is_some_and(|expires| expires <= now);
+
+            let mut is_merged = false;
+
+            if tip != ephemeral.basis {
+                for &other in &lasting {
+                    if self.history.is_descendant(other, tip)? {
+                        is_merged = true;
+
+                        break;
+                    }
+                }
+            }
+
+            if is_expired || is_merged {
+                expired.push(name.to_string());
+            }
+        }
+
+        Ok(expired)
+    }
+
+    /// Delete the ephemeral branches found by [`Repository::expired_branches`],
+    /// returning their names and the hashes they pointed to.
+    ///
+    /// Each deletion is recorded as an action, so it can be undone.
+    pub fn expire_branches(&mut self) -> Result<Vec<(String, ObjectHash)>> {
+        let mut deleted = vec![];
+
+        for name in self.expired_branches()? {
+            let Some(hash) = self.refs.branches_mut().remove(&name) else {
+                continue;
+            };
+
+            self.action_history.push(Action::DeleteBranch { name: name.clone(), hash });
+
+            deleted.push((name, hash));
+        }
+
+        Ok(deleted)
+    }
+
+    /// Put the head at a snapshot, either on `branch` (moving it there first) or detached.
+    fn place_head(&mut self, hash: ObjectHash, branch: Option<String>) {
+        match branch {
+            Some(name) => {
+                self.refs.branches_mut().create(name.clone(), hash);
+
+                self.refs.attach_head(&name);
+            }
+
+            None => self.refs.detach_head(hash)
+        }
+    }
+
+    /// Move the head to a snapshot like [`Repository::place_head`],
+    /// bringing the working directory along with it.
+    fn place_head_with_cwd(&mut self, hash: ObjectHash, branch: Option<String>) -> Result<()> {
+        if self.has_unsaved_changes()? {
+            return Err(UnsavedChanges { action: format!("move to snapshot {hash}") }.into());
+        }
+
+        let before = self.current_hash();
+
+        self.place_head(hash, branch);
+
+        self.follow_head(before)?;
+
         Ok(())
     }
 
+    /// Move the head forward to a snapshot, taking the current branch with it.
+    /// 
+    /// If the head is detached, it stays detached at the new snapshot.
+    pub fn advance_head(&mut self, hash: ObjectHash) {
+        let before = self.current_hash();
+
+        let action = match self.current_branch().map(String::from) {
+            Some(name) => {
+                self.refs.branches_mut().create(name.clone(), hash);
+
+                Action::MoveBranch { name, old: before, new: hash }
+            }
+
+            None => {
+                self.refs.detach_head(hash);
+
+                Action::SwitchVersion { before, after: hash }
+            }
+        };
+
+        self.action_history.push(action);
+    }
+
+    /// Update the working directory after the head was moved
+    /// without it, like when pulling moves the current branch.
+    /// 
+    /// `before` is the snapshot the working directory is at. This fails
+    /// if there are unsaved changes, leaving the working directory alone.
+    /// 
+    /// This returns the stash ID of any backup made on the way, as
+    /// with [`Repository::replace_cwd_with_snapshot`].
+    pub fn follow_head(&mut self, before: ObjectHash) -> Result<Option<usize>> {
+        let after = self.current_hash();
+
+        if after == before {
+            return Ok(None);
+        }
+
+        let head = self.refs.head().clone();
+
+        self.refs.detach_head(before);
+
+        let snapshot = self.fetch_snapshot(after)?;
+
+        let result = self.replace_cwd_with_snapshot(&snapshot);
+
+        self.refs.set_head(head);
+
+        result
+    }
+
     /// Append a snapshot to the tip of the current branch,
     /// moving the branch pointer to point to the added snapshot.
-    pub fn append_snapshot(&mut self, snapshot: Snapshot) -> Result<()> {
+    /// 
+    /// This returns the hash of the snapshot, as it was saved.
+    pub fn append_snapshot(&mut self, snapshot: Snapshot) -> Result<ObjectHash> {
         self.append_snapshot_internal(snapshot, self.current_branch().map(String::from))
     }
 
     /// Append a snapshot to the tip of any branch,
     /// moving that branch's pointer to point to the added snapshot.
-    pub fn append_snapshot_to_branch(&mut self, snapshot: Snapshot, branch_name: String) -> Result<()> {
+    /// 
+    /// This returns the hash of the snapshot, as it was saved.
+    pub fn append_snapshot_to_branch(&mut self, snapshot: Snapshot, branch_name: String) -> Result<ObjectHash> {
         self.append_snapshot_internal(snapshot, Some(branch_name))
     }
 
     /// Check if a given path is ignored by the `.ascignore`
-    /// file in the repository, if it is present.
+    /// files in the repository, if any are present.
+    /// 
+    /// Paths inside the `.asc` directory or a linked repository are always ignored.
     pub fn is_ignored_path(&self, path: &Path) -> bool {
-        self.ignore_matcher.matched(path, path.is_dir()).is_ignore()
+        if let Ok(relative) = path.relative_to(&self.root_dir)
+            && (relative.starts_with(".asc") || self.links.containing(&relative).is_some())
+        {
+            return true;
+        }
+
+        self.ignore_rules.is_ignored(path)
+    }
+
+    /// Find the files in the working directory that aren't tracked or ignored.
+    pub fn untracked_files(&self) -> Result<Vec<RelativePathBuf>> {
+        let mut untracked = vec![];
+
+        for path in resolve_wildcard_path(self.root_dir.join("*"))? {
+            // Directories are only listed for what is in them,
+            // unless they're symbolic links, which are tracked as files.
+            if self.is_ignored_path(&path) || (path.is_dir() && !path.is_symlink()) {
+                continue;
+            }
+
+            let relative = path.relative_to(&self.root_dir)?;
+
+            if !self.staging.contains(&relative) {
+                untracked.push(relative);
+            }
+        }
+
+        Ok(untracked)
     }
 
     /// Convert a smaller hash in string form into its full [`ObjectHash`] version.
     /// 
     /// This works for snapshots and content blobs.
     pub fn normalise_hash(&self, raw_hash: &str) -> Result<ObjectHash> {
-        let (dir, file) = raw_hash.split_at(2);
-
-        if dir.is_empty() {
+        if raw_hash.is_empty() {
             bail!("expected input to normalise, got an empty string.");
         }
 
-        let mut glob = self.blobs_dir();
-
-        if dir.len() == 2 {
-            glob.push(dir);
-        }
-        else {
-            glob.push(format!("{dir}*"));
-        }
+        validate_hex(raw_hash)?;
 
-        if file.len() == 30 {
-            glob.push(file);
-        }
-        else {
-            glob.push(format!("{file}*"));
-        }
+        // Blobs are stored under lowercase hex names.
+        let raw_hash = raw_hash.to_ascii_lowercase();
 
-        let results = resolve_wildcard_path(&glob)?;
+        let results = self.objects().find_prefix(&raw_hash)?;
 
         if results.is_empty() {
-            bail!("found no results when looking for {raw_hash:?} ({glob:?})");
+            bail!("found no results when looking for {raw_hash:?}");
         }
 
         if results.len() != 1 {
-            bail!("found multiple results when looking for {raw_hash:?} ({glob:?}) - {results:?}");
+            bail!("found multiple results when looking for {raw_hash:?} - {results:?}");
         }
 
-        let path = results[0].relative_to(self.blobs_dir())?;
-
-        let second = path
-            .file_stem()
-            .unwrap();
-
-        let first = path
-            .parent()
-            .and_then(|p| p.file_stem())
-            .unwrap();
-
-        let raw_hash = format!("{first}{second}");
-
-        let hash = ObjectHash::from_str(&raw_hash)?;
-
-        Ok(hash)
+        Ok(results[0])
     }
 
     /// Convert a version in string form into its full [`ObjectHash`] version
     /// by trying to interpret it as a branch name, then trying to interpret
     /// it as the hash of a snapshot.
+    ///
+    /// `branch:`, `tag:` or `hash:` in front of a version says which it is.
+    /// Without one, a name that finds a ref but also starts the hash of a
+    /// different snapshot is refused as ambiguous, instead of hiding it.
     pub fn normalise_version(&self, raw_version: &str) -> Result<ObjectHash> {
-        if let Some(&corresponding_hash) = self.branches.get(raw_version) {
-            Ok(corresponding_hash)
+        if let Some((kind, name)) = raw_version.trim_start().split_once(':') {
+            match kind {
+                "branch" => return self.refs.branches().get(name).ok_or(eyre!("no branch called {name:?}")),
+                "tag" => return self.refs.tags().get(name).ok_or(eyre!("no tag called {name:?}")),
+                "hash" => return self.normalise_hash(name),
+                _ => {}
+            }
         }
-        else if let Some((prefix, name)) = raw_version.split_once("tag:")
-            && prefix.split_whitespace().next().is_none()
+
+        if let Some((name, rest)) = raw_version.rsplit_once("@{")
+            && let Some(n) = rest.strip_suffix('}')
         {
-            self.tags.get(name)
-                .cloned()
-                .ok_or(eyre!("no tag called {name:?}"))
+            let Ok(n) = n.parse() else {
+                bail!("expected a number of changes back in {raw_version:?}");
+            };
+
+            return self.resolve_reflog(name, n);
+        }
+
+        if let Ok(r @ Ref::Stash(_)) = raw_version.parse::<Ref>() {
+            return self.resolve_ref(&r).ok_or(eyre!("no stash entry called {r}"));
+        }
+
+        if let Some((r, hash)) = self.refs.lookup(raw_version) {
+            self.check_unambiguous(raw_version, r, hash)?;
+
+            Ok(hash)
         }
         else {
             self.normalise_hash(raw_version)
         }
     }
 
+    /// Check that a name which found a ref doesn't also start the hash of
+    /// another snapshot, since the ref would hide that snapshot.
+    fn check_unambiguous(&self, name: &str, r: &Ref, hash: ObjectHash) -> Result<()> {
+        if validate_hex(name).is_err() {
+            return Ok(());
+        }
+
+        let matches = self.objects().find_prefix(&name.to_ascii_lowercase())?;
+
+        // A prefix that matches more than one object isn't a version on its own.
+        if let [other] = matches[..]
+            && other != hash
+            && self.has_snapshot(other)
+        {
+            let explicit = match r {
+                Ref::Branch(_) => format!("branch:{name}"),
+                Ref::Tag(_) => format!("tag:{name}"),
+                _ => r.to_string()
+            };
+
+            bail!("{name:?} is ambiguous: it is {r} ({hash}), and the start of snapshot {other} - use `{explicit}` or `hash:{name}` to pick one");
+        }
+
+        Ok(())
+    }
+
+    /// Find where a ref pointed `n` changes ago, for versions like `main@{2}`,
+    /// where `main@{0}` is where it points now.
+    ///
+    /// `HEAD` looks back through where the head has been. No name at all means
+    /// the current branch, or the head when it isn't on one.
+    pub fn resolve_reflog(&self, name: &str, n: usize) -> Result<ObjectHash> {
+        if name == "HEAD" || (name.is_empty() && self.current_branch().is_none()) {
+            let log = self.refs.head_log();
+
+            return log.iter()
+                .rev()
+                .nth(n)
+                .map(|entry| entry.hash)
+                .ok_or(eyre!("HEAD has only been in {} places", log.len()));
+        }
+
+        let r = match self.refs.lookup(name) {
+            Some((r, _)) => r.clone(),
+
+            None if name.is_empty() => Ref::Branch(self.current_branch().unwrap().to_string()),
+
+            // Deleted refs still have a reflog.
+            None => name.parse::<Ref>().unwrap_or(Ref::Branch(name.to_string()))
+        };
+
+        let log = self.refs.reflog(&r);
+
+        let Some(entry) = log.iter().rev().nth(n) else {
+            bail!("{r} only has {} recorded changes", log.len());
+        };
+
+        entry.new.ok_or(eyre!("{r}@{{{n}}} is where {r} was deleted"))
+    }
+
+    /// Get the snapshot a [`Ref`] points to.
+    /// 
+    /// Stash refs point to the snapshot their entry was stashed on top of.
+    pub fn resolve_ref(&self, r: &Ref) -> Option<ObjectHash> {
+        match r {
+            &Ref::Stash(id) => self.stash.get_state(id).map(|entry| entry.basis),
+            _ => self.refs.get(r)
+        }
+    }
+
     fn apply_action(&mut self, action: Action) -> Result<()> {
         use Action::*;
 
         match action {
-            CreateBranch { name, .. } => {
-                self.branches.remove(&name);
+            CreateBranch { name, hash } => {
+                self.refs.branches_mut().create(name, hash);
             }
 
             DeleteBranch { name, hash } => {
-                self.branches.create(name, hash);
+                let was_current = self.current_branch() == Some(name.as_str());
+
+                self.refs.branches_mut().remove(&name);
+
+                // Undoing `asc switch -c` should leave the head where it was
+                // before, which is usually another branch at the same snapshot.
+                if was_current
+                    && let Some(other) = self.refs.branches().get_name_for(hash).map(String::from)
+                {
+                    self.refs.attach_head(&other);
+                }
             }
 
             MoveBranch { name, new, .. } => {
-                self.branches.create(name, new);
+                self.refs.branches_mut().create(name, new);
             },
 
             RenameBranch { old, new, .. } => {
-                self.branches.rename(&old, new);
+                self.rename_branch(&old, new)?;
             }
 
             SwitchVersion { after, .. } => {
-                self.current_hash = after;
+                self.refs.switch_to_hash(after);
+            },
+
+            CreateSnapshot { hash, branch, .. } => {
+                self.place_head_with_cwd(hash, branch)?;
+            },
+
+            DiscardSnapshot { parent, branch, .. } => {
+                self.place_head_with_cwd(parent, branch)?;
             },
 
             CreateTag { name, hash } => {
-                self.tags.create(name, hash);
+                self.refs.tags_mut().create(name, hash);
             },
 
             MoveTag { name, new, .. } => {
-                self.tags.create(name, new);
+                self.refs.tags_mut().create(name, new);
             },
 
             RemoveTag { name, .. } => {
-                self.tags.remove(&name);
+                self.refs.tags_mut().remove(&name);
             },
 
             RenameTag { old, new, .. } => {
-                self.tags.rename(&old, new);
+                self.refs.tags_mut().rename(&old, new);
             },
 
             OpenAccount { id, .. } => {
@@ -391,6 +817,14 @@ impl Repository {
 
             TrashRecover { hash } => {
                 self.trash.remove(hash);
+            },
+
+            CreatePin { pin } => {
+                self.pins.add(pin);
+            },
+
+            RemovePin { pin } => {
+                self.pins.remove(pin.hash);
             }
         }
 
@@ -414,6 +848,9 @@ impl Repository {
 
             SwitchVersion { before, after } => SwitchVersion { before: after, after: before },
 
+            CreateSnapshot { hash, parent, branch } => DiscardSnapshot { hash, parent, branch },
+            DiscardSnapshot { hash, parent, branch } => CreateSnapshot { hash, parent, branch },
+
             CreateTag { name, hash } => RemoveTag { name, hash },
             RemoveTag { name, hash } => CreateTag { name, hash },
             MoveTag { name, old, new } => MoveTag { name, old: new, new: old },
@@ -425,6 +862,9 @@ impl Repository {
 
             TrashAdd { hash } => TrashRecover { hash },
             TrashRecover { hash } => TrashAdd { hash },
+
+            CreatePin { pin } => RemovePin { pin },
+            RemovePin { pin } => CreatePin { pin }
         };
 
         self.apply_action(inverse.clone())?;
@@ -444,6 +884,70 @@ impl Repository {
         Ok(Some(action))
     }
 
+    /// Find the remote-tracking refs that already have the snapshots
+    /// an [`Action`] moved a branch to, so that undoing it would leave
+    /// the branch behind where it is on those remotes.
+    ///
+    /// Only actions that move a branch forward can be published like
+    /// this, so this is empty for anything else.
+    pub fn published_refs(&self, action: &Action) -> Result<Vec<Ref>> {
+        let (name, old, new) = match action {
+            Action::MoveBranch { name, old, new } => (name, *old, *new),
+
+            Action::CreateSnapshot { hash, parent, branch: Some(name) } => (name, *parent, *hash),
+
+            _ => return Ok(vec![])
+        };
+
+        // Moving a branch back to somewhere it has already been
+        // loses nothing, so undoing that can't diverge either.
+        if self.history.is_descendant(old, new)? {
+            return Ok(vec![]);
+        }
+
+        let mut published = vec![];
+
+        for remote in self.remotes.names() {
+            let r = Ref::Remote(format!("{remote}/{name}"));
+
+            let Some(tip) = self.refs.get(&r) else {
+                continue;
+            };
+
+            if self.history.contains(tip) && self.history.is_descendant(tip, new)? {
+                published.push(r);
+            }
+        }
+
+        published.sort();
+
+        Ok(published)
+    }
+
+    /// Remember where `branch` was last seen on the remote called `remote`,
+    /// as the remote-tracking ref `remotes/<remote>/<branch>`.
+    pub fn set_remote_tip(&mut self, remote: &str, branch: &str, tip: ObjectHash) {
+        self.refs.set(Ref::Remote(format!("{remote}/{branch}")), tip);
+    }
+
+    /// Get where `branch` was last seen on each remote it has been pushed to
+    /// or pulled from, as the names of the remote-tracking refs (`<remote>/<branch>`)
+    /// and the hashes they point to.
+    ///
+    /// Tips that aren't in the history any more are left out.
+    pub fn upstreams(&self, branch: &str) -> Vec<(String, ObjectHash)> {
+        self.remotes
+            .names()
+            .filter_map(|remote| {
+                let name = format!("{remote}/{branch}");
+
+                let tip = self.refs.get(&Ref::Remote(name.clone()))?;
+
+                self.history.contains(tip).then_some((name, tip))
+            })
+            .collect()
+    }
+
     /// Check if an [`ObjectHash`] of a snapshot is included in the trash.
     pub fn trash_contains(&self, hash: ObjectHash) -> Option<TrashStatus> {
         if self.trash.contains(hash) {
@@ -458,42 +962,296 @@ impl Repository {
 
         None
     }
-}
 
-fn locate_root_dir(from: impl AsRef<Path>) -> Result<Option<PathBuf>> {
-    let absolute = from.as_ref().canonicalize()?;
-    let mut current: &Path = &absolute;
+    /// Permanently delete snapshots that have been in the trash for longer
+    /// than `older_than`, along with the snapshots trashed with them and
+    /// any content nothing else uses.
+    /// 
+    /// This can't be undone, so the action history is
+    /// cleared if anything is deleted.
+    pub fn purge_trash(&mut self, older_than: Duration) -> Result<Purged> {
+        let cutoff = TimeDelta::from_std(older_than)
+            .ok()
+            .and_then(|age| Utc::now().checked_sub_signed(age))
+            .unwrap_or(DateTime::<Utc>::MIN_UTC);
+
+        let expired: Vec<Entry> = self.trash
+            .entries()
+            .iter()
+            .filter(|entry| entry.when <= cutoff)
+            .cloned()
+            .collect();
 
-    while !current.join(".asc").is_dir() {
-        let Some(parent) = current.parent() else {
-            return Ok(None);
-        };
+        let mut purged = Purged::default();
 
-        current = parent;
-    }
+        if expired.is_empty() {
+            return Ok(purged);
+        }
 
-    Ok(Some(current.to_path_buf()))
-}
+        let tips = self.refs
+            .iter()
+            .map(|(_, hash)| hash)
+            .chain([self.current_hash()])
+            .chain(self.stash.iter_entries().map(|entry| entry.basis))
+            .chain(self.pins.hashes())
+            .chain(retention::held_snapshots(self)?);
 
-fn get_ignore_matcher(root_dir: impl AsRef<Path>) -> Result<Gitignore> {
-    let mut builder = GitignoreBuilder::new(root_dir);
+        let live = self.history.reachable(tips, |_| false)?;
 
-    builder.add(".ascignore");
+        let mut doomed: HashSet<ObjectHash> = HashSet::new();
 
-    let matcher = unwrap!(builder.build(), "failed to build ignore matcher");
+        for entry in expired {
+            let mut snapshots = self.history.descendants(entry.hash);
 
-    Ok(matcher)
-}
+            snapshots.insert(entry.hash);
+
+            if snapshots.iter().any(|hash| live.contains(hash)) {
+                purged.kept.push(entry.hash);
+
+                continue;
+            }
+
+            doomed.extend(snapshots);
+
+            purged.entries.push(entry);
+        }
+
+        if doomed.is_empty() {
+            return Ok(purged);
+        }
+
+        let mut doomed_content: HashSet<ObjectHash> = HashSet::new();
+
+        for &hash in &doomed {
+            doomed_content.extend(self.fetch_snapshot(hash)?.files.into_values());
+        }
+
+        for entry in &purged.entries {
+            // Entries trashed along with an earlier one are already gone.
+            for hash in self.history.prune_subtree(entry.hash) {
+                self.trash.remove(hash);
+            }
+        }
+
+        // Content shared with anything left has to stay,
+        // along with whatever its deltas are based on.
+        let mut used: HashSet<ObjectHash> = HashSet::new();
+
+        for hash in self.history.iter_hashes() {
+            used.extend(self.fetch_snapshot(hash)?.files.into_values());
+        }
+
+        for entry in self.stash.iter_entries() {
+            used.extend(entry.state.files.values());
+        }
+
+        used.extend(self.staging.iter().map(|(_, hash)| hash));
+
+        used.extend(fenced_hashes(self)?);
+
+        let mut queue: Vec<ObjectHash> = used.iter().copied().collect();
+
+        while let Some(hash) = queue.pop() {
+            if let Ok(Content::Delta(delta)) = self.fetch_content_object(hash)
+                && used.insert(delta.original)
+            {
+                queue.push(delta.original);
+            }
+        }
+
+        let objects = self.objects();
+
+        for &hash in doomed_content.difference(&used) {
+            if !objects.contains(hash) {
+                continue;
+            }
+
+            if let Ok(Content::Pointer(_)) = self.fetch_content_object(hash)
+                && self.has_large_file(hash)
+            {
+                let large = self.large_file_path(hash);
+
+                unwrap!(
+                    fs::remove_file(&large),
+                    "failed to delete large file: {}", large.display()
+                );
+            }
+
+            objects.remove_object(hash)?;
+
+            purged.objects += 1;
+        }
+
+        for &hash in &doomed {
+            objects.remove_object(hash)?;
+        }
+
+        purged.snapshots = doomed.into_iter().collect();
+
+        purged.snapshots.sort();
+
+        self.action_history.clear();
+
+        Ok(purged)
+    }
+}
+
+/// The version of how repositories are stored, which is raised when
+/// older versions of asc would misread what newer ones write.
+/// 
+/// Format 2 records file modes and symbolic links in snapshots.
+/// Format 3 stores the children of each snapshot in the history.
+pub static REPOSITORY_FORMAT: u32 = 3;
+
+/// The environment variable naming the repository to use,
+/// instead of the one containing the working directory.
+pub static REPOSITORY_DIR_VAR: &str = "ASC_DIR";
+
+static REPOSITORY_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+/// Make [`Repository::load`] use the repository at `path`, or the one
+/// containing it, instead of the one containing the working directory.
+/// This takes priority over the `ASC_DIR` environment variable.
+///
+/// This can only be set once, and later calls are ignored.
+pub fn set_repository_dir(path: impl AsRef<Path>) -> Result<()> {
+    let _ = REPOSITORY_DIR.set(std::path::absolute(path)?);
+
+    Ok(())
+}
+
+/// Where to start looking for the repository to load: the path given to
+/// [`set_repository_dir`], then `ASC_DIR`, then the working directory.
+pub(crate) fn search_start() -> Result<PathBuf> {
+    if let Some(path) = REPOSITORY_DIR.get() {
+        return Ok(path.clone());
+    }
+
+    if let Some(path) = env::var_os(REPOSITORY_DIR_VAR)
+        && !path.is_empty()
+    {
+        return Ok(PathBuf::from(path));
+    }
+
+    Ok(current_dir()?)
+}
+
+/// Find the root of the repository [`Repository::load`] would load,
+/// without loading it, or `None` if there isn't one.
+pub fn current_root_dir() -> Result<Option<PathBuf>> {
+    locate_root_dir(search_start()?)
+}
+
+pub(crate) fn locate_root_dir(from: impl AsRef<Path>) -> Result<Option<PathBuf>> {
+    // A path that doesn't exist, like a mistyped `ASC_DIR`, has no repository.
+    let Ok(absolute) = from.as_ref().canonicalize() else {
+        return Ok(None);
+    };
+    let mut current: &Path = &absolute;
+
+    while !current.join(".asc").is_dir() {
+        let Some(parent) = current.parent() else {
+            return Ok(None);
+        };
+
+        current = parent;
+    }
+
+    Ok(Some(current.to_path_buf()))
+}
+
+/// Open the store a repository whose `.asc` directory is `content_dir`
+/// keeps its objects in, if it isn't the blob store.
+pub fn open_store(kind: StoreKind, config: &ContentConfig, content_dir: &Path, cache: &Arc<ObjectCache>) -> Result<Option<Arc<dyn ObjectStore>>> {
+    match kind {
+        StoreKind::Files => Ok(None),
+
+        #[cfg(feature = "sqlite")]
+        StoreKind::Sqlite => {
+            let store = SqliteStore::open(content_dir.join("objects.sqlite"), Arc::clone(cache))?;
+
+            Ok(Some(Arc::new(store)))
+        },
+
+        #[cfg(not(feature = "sqlite"))]
+        StoreKind::Sqlite => {
+            let _ = (content_dir, cache);
+
+            bail!("this repository keeps its objects in SQLite, which this build of asc can't read.")
+        },
+
+        #[cfg(feature = "s3")]
+        StoreKind::S3 => {
+            let store = S3Store::open(&config.s3, content_dir.join("s3-cache"), Arc::clone(cache))?;
+
+            Ok(Some(Arc::new(store)))
+        },
+
+        #[cfg(not(feature = "s3"))]
+        StoreKind::S3 => {
+            let _ = (config, content_dir, cache);
+
+            bail!("this repository keeps its objects in S3, which this build of asc can't reach.")
+        }
+    }
+}
+
+/// The staging index, as stored in `.asc/index`.
+enum Index {
+    Staging(StagingArea),
+
+    /// The older format, which was only a list of paths.
+    Legacy(Vec<RelativePathBuf>)
+}
+
+impl Index {
+    fn load(path: impl AsRef<Path>) -> Result<Index> {
+        let path = path.as_ref();
+
+        let raw = unwrap!(
+            fs::read(path),
+            "failed to read index from: {}", path.display()
+        );
+
+        if let Ok(staging) = rmp_serde::from_slice(&raw) {
+            return Ok(Index::Staging(staging));
+        }
+
+        let paths = unwrap!(
+            rmp_serde::from_slice(&raw),
+            "failed to parse index from: {}", path.display()
+        );
+
+        Ok(Index::Legacy(paths))
+    }
+}
 
 #[derive(Deserialize, Serialize)]
 pub struct ProjectInfo {
     pub project_name: String,
     pub project_code: ObjectHash,
     pub current_user: Option<PublicKey>,
+
+    /// Only read when migrating a repository from before
+    /// branches were kept in `.asc/refs`.
     pub branches: NamedItems<ObjectHash>,
     pub current_hash: ObjectHash,
     pub stash: Stash,
-    pub remotes: NamedItems<Remote>
+    pub remotes: NamedItems<Remote>,
+
+    #[serde(default)]
+    pub links: Links,
+
+    /// The [`REPOSITORY_FORMAT`] of the version that wrote the repository,
+    /// or `0` for versions from before the format was recorded.
+    #[serde(default)]
+    pub format: u32,
+
+    #[serde(default)]
+    pub alternates: Vec<PathBuf>,
+
+    #[serde(default)]
+    pub default_branch: Option<String>
 }
 
 impl ProjectInfo {
@@ -551,38 +1309,50 @@ impl Repository {
 
         let current_user = Arc::new(RwLock::new(Some(first_user.public_key())));
 
+        let clock = clock::from_env()?;
+
         let mut history = Graph::new();
 
-        let root_snapshot = Snapshot::new(
+        let root_snapshot = Snapshot::new_on_branch(
             first_user,
             "initial snapshot".to_string(),
-            Utc::now(),
+            clock.now(),
             BTreeMap::new(),
-            set![]
+            set![],
+            Some("main".to_string())
         );
 
         history.insert_orphan(root_snapshot.hash);
 
-        let mut branches = NamedItems::<ObjectHash>::new();
+        let mut refs = RefStore::new();
+
+        refs.set(Ref::Branch("main".to_string()), root_snapshot.hash);
 
-        branches.create("main".to_string(), root_snapshot.hash);
+        refs.attach_head("main");
 
         let mut repo = Repository {
             project_name,
             project_code,
-            ignore_matcher: get_ignore_matcher(&root_dir)?,
+            ignore_rules: IgnoreRules::load(&root_dir)?,
             root_dir,
             action_history: ActionHistory::new(),
             history,
-            branches,
-            current_hash: root_snapshot.hash,
+            refs,
+            clock,
             current_user,
-            staged_files: vec![],
+            staging: StagingArea::new(),
             stash: Stash::new(),
             trash: Trash::new(),
-            tags: NamedItems::new(),
+            pins: Pins::new(),
             users,
-            remotes: NamedItems::new()
+            remotes: NamedItems::new(),
+            links: Links::new(),
+            cache: Arc::new(ObjectCache::new(Config::default().io.cache_size())),
+            format: REPOSITORY_FORMAT,
+            alternates: vec![],
+            default_branch: None,
+            store: None,
+            config: Config::default()
         };
 
         repo.save_snapshot(root_snapshot)?;
@@ -595,8 +1365,11 @@ impl Repository {
     /// Load the repository in the current directory, searching
     /// upwards from the current working directory until a directory
     /// containing an `.acs` directory is found.
+    ///
+    /// The search starts somewhere else if [`set_repository_dir`]
+    /// was called, or the `ASC_DIR` environment variable is set.
     pub fn load() -> Result<Repository> {
-        let start = current_dir()?;
+        let start = search_start()?;
 
         let Some(root_dir) = locate_root_dir(&start)? else {
             bail!("no .acs directory found when searching recursively from: {}", start.display());
@@ -622,41 +1395,173 @@ impl Repository {
 
         let info: ProjectInfo = load_as_msgpack(content_dir.join("info"))?;
 
+        if info.format > REPOSITORY_FORMAT {
+            eprintln!(
+                "warning: {} was written by a newer version of asc (format {}, but this version knows up to {REPOSITORY_FORMAT}). Run `asc upgrade` to update.",
+                root_dir.display(),
+                info.format
+            );
+        }
+
         let history = load_as_msgpack(content_dir.join("tree"))?;
 
-        let staged_files = load_as_msgpack(content_dir.join("index"))?;
+        let index = Index::load(content_dir.join("index"))?;
 
         let action_history = load_as_msgpack(content_dir.join("history"))?;
 
         let trash = load_as_msgpack(content_dir.join("trash"))?;
 
-        let tags = load_as_msgpack(content_dir.join("tags"))?;
+        let pins_path = content_dir.join("pins");
+
+        // Repositories from before pins were added have no file for them.
+        let pins = if pins_path.exists() {
+            load_as_msgpack(pins_path)?
+        }
+        else {
+            Pins::new()
+        };
+
+        let refs_path = content_dir.join("refs");
+
+        let mut refs = if refs_path.exists() {
+            let mut refs: RefStore = load_as_msgpack(refs_path)?;
+
+            // Refs saved before the head was stored with them.
+            if refs.head() == &Head::default() {
+                refs.switch_to_hash(info.current_hash);
+            }
+
+            refs
+        }
+        else {
+            let tags = load_as_msgpack(content_dir.join("tags"))?;
+
+            RefStore::from_legacy(info.branches, tags, info.current_hash)
+        };
+
+        refs.load_logs(&content_dir.join("logs"))?;
 
         let users = load_as_msgpack(content_dir.join("users"))?;
 
-        let repo = Repository {
+        let config = Config::load(content_dir.join("config.toml"))?;
+
+        let cache = Arc::new(ObjectCache::new(config.io.cache_size()));
+
+        let store = open_store(config.content.store, &config.content, &content_dir, &cache)?;
+
+        let mut repo = Repository {
             project_name: info.project_name,
             project_code: info.project_code,
-            ignore_matcher: get_ignore_matcher(&root_dir)?,
+            ignore_rules: IgnoreRules::load(&root_dir)?,
             root_dir,
             action_history,
             history,
-            branches: info.branches,
-            current_hash: info.current_hash,
+            refs,
+            clock: clock::from_env()?,
             current_user: Arc::new(RwLock::new(info.current_user)),
-            staged_files,
+            staging: StagingArea::new(),
             stash: info.stash,
             trash,
-            tags,
+            pins,
             users,
-            remotes: info.remotes
+            remotes: info.remotes,
+            links: info.links,
+            cache,
+            format: info.format,
+            alternates: info.alternates,
+            default_branch: info.default_branch,
+            store,
+            config
+        };
+
+        repo.staging = match index {
+            // Indexes written by older versions may have paths
+            // that aren't in the form they are stored in now.
+            Index::Staging(staging) => {
+                let mut canonical = StagingArea::from_files(staging.files().clone());
+
+                canonical.set_modes(staging.modes());
+
+                canonical
+            },
+
+            Index::Legacy(paths) => repo.migrate_legacy_index(paths)?
         };
 
+        if repo.format < 2 {
+            repo.migrate_modes();
+        }
+
         Ok(repo)
     }
 
+    /// Stage the modes tracked files have in the working directory, for
+    /// repositories from before modes were recorded, so that executable
+    /// files and symbolic links keep what they are from the next commit on.
+    fn migrate_modes(&mut self) {
+        let modes: Vec<(RelativePathBuf, FileMode)> = self.staging
+            .paths()
+            .filter_map(|path| {
+                let full_path = paths::on_disk(path, &self.root_dir);
+
+                let mode = FileMode::of(&full_path, FileMode::Regular).ok()?;
+
+                Some((path.clone(), mode))
+            })
+            .collect();
+
+        for (path, mode) in modes {
+            self.staging.set_mode(path, mode);
+        }
+    }
+
+    /// Build a [`StagingArea`] from an index that was only a list of paths.
+    /// 
+    /// Tracked files keep the content they have in the current snapshot,
+    /// while files added since then are staged from the working directory.
+    fn migrate_legacy_index(&self, paths: Vec<RelativePathBuf>) -> Result<StagingArea> {
+        let current = self.fetch_current_snapshot()?;
+
+        let mut staging = StagingArea::new();
+
+        for path in paths {
+            let path = path.normalize();
+
+            if let Some(&hash) = current.files.get(&path) {
+                staging.stage(path, hash);
+
+                continue;
+            }
+
+            let full_path = path.to_logical_path(&self.root_dir);
+
+            if !full_path.is_file() {
+                continue;
+            }
+
+            let content = unwrap!(
+                fs::read_to_string(&full_path),
+                "could not read from path: {path}"
+            );
+
+            let hash = self.save_content(&content, None)?;
+
+            staging.stage(path, hash);
+        }
+
+        Ok(staging)
+    }
+
     /// Save the current state of the repository to disk.
+    /// 
+    /// During a dry run, nothing is written, and how the refs and the
+    /// head would have moved is recorded instead. The state isn't checked
+    /// then, as the snapshots made during a dry run were never saved.
     pub fn save(&mut self) -> Result<()> {
+        if guard().is_dry_run() {
+            return self.record_ref_changes();
+        }
+
         self.validate_state()?;
         
         let current_user = *self.current_user.read().unwrap();
@@ -667,38 +1572,70 @@ impl Repository {
             project_name: self.project_name.clone(),
             project_code: self.project_code,
             current_user,
-            branches: self.branches.clone(),
-            current_hash: self.current_hash,
+            branches: NamedItems::new(),
+            current_hash: self.current_hash(),
             stash: self.stash.clone(),
-            remotes: self.remotes.clone()
+            remotes: self.remotes.clone(),
+            links: self.links.clone(),
+            format: self.format.max(REPOSITORY_FORMAT),
+            alternates: self.alternates.clone(),
+            default_branch: self.default_branch.clone()
         };
 
         save_as_msgpack(&info, content_dir.join("info"))?;
 
         save_as_msgpack(&self.history, content_dir.join("tree"))?;
 
-        let set: BTreeSet<RelativePathBuf> = self.staged_files
-            .iter()
-            .map(|path| path.normalize())
-            .collect();
-
-        self.staged_files = set.into_iter().collect();
-
-        save_as_msgpack(&self.staged_files, content_dir.join("index"))?;
+        save_as_msgpack(&self.staging, content_dir.join("index"))?;
 
         save_as_msgpack(&self.action_history, content_dir.join("history"))?;
         
         save_as_msgpack(&self.trash, content_dir.join("trash"))?;
 
-        save_as_msgpack(&self.tags, content_dir.join("tags"))?;
+        save_as_msgpack(&self.pins, content_dir.join("pins"))?;
+
+        self.refs.save_logs(&self.logs_dir())?;
+
+        save_as_msgpack(&self.refs, content_dir.join("refs"))?;
 
         save_as_msgpack(&self.users, content_dir.join("users"))?;
 
         Ok(())
     }
-}
 
-pub static MIN_DELTA_SIMILARITY: f32 = 0.65;
+    /// Record how the refs and the head differ from what is saved on disk,
+    /// as the changes that saving the repository would make.
+    fn record_ref_changes(&self) -> Result<()> {
+        let refs_path = self.main_dir().join("refs");
+
+        let saved: RefStore = if refs_path.exists() {
+            load_as_msgpack(refs_path)?
+        }
+        else {
+            RefStore::default()
+        };
+
+        let before: BTreeMap<&Ref, ObjectHash> = saved.iter().collect();
+        let after: BTreeMap<&Ref, ObjectHash> = self.refs.iter().collect();
+
+        let all_refs: BTreeSet<&Ref> = before.keys().chain(after.keys()).copied().collect();
+
+        for r in all_refs {
+            let old = before.get(r).copied();
+            let new = after.get(r).copied();
+
+            if old != new {
+                guard().allow(|| Change::MoveRef { r: r.clone(), old, new });
+            }
+        }
+
+        if saved.head() != self.refs.head() {
+            guard().allow(|| Change::MoveHead { head: self.refs.head().clone(), hash: self.current_hash() });
+        }
+
+        Ok(())
+    }
+}
 
 impl Repository {
     /// Get the directory the repository operates in.
@@ -713,29 +1650,34 @@ impl Repository {
         self.main_dir().join("blobs")
     }
     
-    /// Convert an [`ObjectHash`] to its location on disk.
-    pub fn hash_to_path(&self, hash: ObjectHash) -> PathBuf {
-        let full = hash.full();
+    /// Get the blob store on its own, to read objects from another thread.
+    pub fn blobs(&self) -> BlobStore {
+        let alternates = self.alternates
+            .iter()
+            .map(|root| root.join(".asc").join("blobs"))
+            .collect();
+
+        BlobStore::new(self.blobs_dir(), Arc::clone(&self.cache))
+            .with_alternates(alternates)
+    }
 
-        let (dir, rest) = full.split_at(2);
+    /// Get the store the repository's objects are kept in, which is
+    /// [`Repository::blobs`] unless the config says otherwise.
+    pub fn objects(&self) -> Arc<dyn ObjectStore> {
+        match &self.store {
+            Some(store) => Arc::clone(store),
+            None => Arc::new(self.blobs())
+        }
+    }
 
-        self.blobs_dir()
-            .join(dir)
-            .join(rest)
+    /// Convert an [`ObjectHash`] to its location on disk.
+    pub fn hash_to_path(&self, hash: ObjectHash) -> PathBuf {
+        self.blobs().hash_to_path(hash)
     }
 
     /// Fetch a [`Content`] object from the repository, addressed by its hash.
     pub fn fetch_content_object(&self, content_hash: ObjectHash) -> Result<Content> {
-        let path = self.hash_to_path(content_hash);
-
-        let raw = unwrap!(
-            fs::read(&path),
-            "failed to read bytes from: {}", path.display()
-        );
-
-        let content: Content = rmp_serde::from_slice(&raw)?;
-
-        Ok(content)
+        self.objects().fetch_content_object(content_hash)
     }
     
     /// Fetch a `String` from the repository, addressed by its hash.
@@ -747,103 +1689,573 @@ impl Repository {
 
     /// Fetch a [`Snapshot`] from the repository, addressed by its hash.
     pub fn fetch_snapshot(&self, snapshot_hash: ObjectHash) -> Result<Snapshot> {
-        let path = self.hash_to_path(snapshot_hash);
-        
-        let fp = open_file(path)?;
-
-        let snapshot: Snapshot = rmp_serde::from_read(fp)?;
-
-        snapshot.verify()?;
-
-        Ok(snapshot)
+        self.objects().fetch_snapshot(snapshot_hash)
     }
 
     /// Fetch the [`Snapshot`] the HEAD is currently on from the repository.
     pub fn fetch_current_snapshot(&self) -> Result<Snapshot> {
-        self.fetch_snapshot(self.current_hash)
+        self.fetch_snapshot(self.current_hash())
     }
 
-    /// Save a string to disk with optional delta compression if `basis` is provided
-    /// and the basis is similar enough to `content` (determined by [`MIN_DELTA_SIMILARITY`]).
-    pub fn save_content(&self, content: &str, basis: Option<ObjectHash>) -> Result<ObjectHash> {
-        let Some(basis) = basis else {
-            return self.save_content_raw(content);
-        };
+    /// Find the parent of a snapshot that is on the same line of history.
+    /// 
+    /// For a merge, this is the parent committed to the same branch as the
+    /// merge itself. Returns `None` for a root snapshot, or a merge where
+    /// no parent (or more than one) was committed to that branch.
+    pub fn first_parent(&self, snapshot: &Snapshot) -> Result<Option<ObjectHash>> {
+        if snapshot.parents.len() <= 1 {
+            return Ok(snapshot.parents.iter().next().copied());
+        }
 
-        let Some(hash) = self.save_content_delta(content, basis)? else {
-            return self.save_content_raw(content);
-        };
+        if snapshot.branch.is_none() {
+            return Ok(None);
+        }
 
-        Ok(hash)
-    }
+        let mut found = None;
 
-    /// Save a string as a compressed blob to disk and return the hash used to load it.
-    pub fn save_content_raw(&self, content: &str) -> Result<ObjectHash> {
-        let hash = hash_raw_bytes(content);
+        for &parent in &snapshot.parents {
+            if self.fetch_snapshot(parent)?.branch != snapshot.branch {
+                continue;
+            }
 
-        let object = Content::Literal(compress_data(content));
+            if found.is_some() {
+                return Ok(None);
+            }
 
-        self.save_content_object(object, hash)?;
+            found = Some(parent);
+        }
 
-        Ok(hash)
+        Ok(found)
     }
 
-    /// Save a string as a delta of some other string on disk, but reject the delta
-    /// if the two strings have a similarity lower than [`MIN_DELTA_SIMILARITY`].
-    pub fn save_content_delta(&self, content: &str, basis: ObjectHash) -> Result<Option<ObjectHash>> {
-        let original = self.fetch_string_content(basis)?;
+    /// Get the directory where fences for operations in progress are stored.
+    pub fn fences_dir(&self) -> PathBuf {
+        self.main_dir().join("fences")
+    }
 
-        let hash = hash_raw_bytes(content);
+    /// Get the directory where invitations waiting for an answer are stored.
+    pub fn invitations_dir(&self) -> PathBuf {
+        self.main_dir().join("invitations")
+    }
 
-        let Some(delta) = Delta::new(
-            &original,
-            content,
-            MIN_DELTA_SIMILARITY
-        ) else {
-            return Ok(None);
-        };
+    /// Get the directory where a server keeps the sessions it has issued.
+    pub fn sessions_dir(&self) -> PathBuf {
+        self.main_dir().join("sessions")
+    }
 
-        self.save_content_object(Content::Delta(delta), hash)?;
+    /// Get the directory for data that can be worked out
+    /// again, like blame results, and is safe to delete.
+    pub fn cache_dir(&self) -> PathBuf {
+        self.main_dir().join("cache")
+    }
 
-        Ok(Some(hash))
+    /// Get the directory where the logs of refs and the head are kept.
+    pub fn logs_dir(&self) -> PathBuf {
+        self.main_dir().join("logs")
     }
 
-    /// Save a string as a delta of some other string on disk, regardless of the similarity
-    /// of the two strings.
-    /// 
-    /// For a method that considers similarity, use the safer [`Repository::save_content_delta`],
-    /// or the higher-level [`Repository::save_content`].
+    /// Get the directory where large files are stored.
+    pub fn lfs_dir(&self) -> PathBuf {
+        self.main_dir().join("lfs")
+    }
+
+    /// Convert the hash of a large file to its location on disk.
+    pub fn large_file_path(&self, hash: ObjectHash) -> PathBuf {
+        self.lfs_dir().join(hash.full())
+    }
+
+    /// Check if a snapshot is in the history and stored on disk.
+    pub fn has_snapshot(&self, hash: ObjectHash) -> bool {
+        self.history.contains(hash) && self.objects().contains(hash)
+    }
+
+    /// Get the time new snapshots are stamped with, from the repository's clock.
+    pub fn now(&self) -> DateTime<Utc> {
+        self.clock.now()
+    }
+
+    /// Stamp new snapshots with the time from `clock`, instead of
+    /// the one set up when the repository was loaded.
+    pub fn set_clock(&mut self, clock: impl Clock + 'static) {
+        self.clock = Arc::new(clock);
+    }
+
+    /// Add pins received from a remote, skipping those on snapshots
+    /// that aren't here. Returns the hashes that were pinned.
+    pub fn merge_pins(&mut self, pins: Vec<Pin>) -> Vec<ObjectHash> {
+        let pins = pins
+            .into_iter()
+            .filter(|pin| self.has_snapshot(pin.hash))
+            .collect();
+
+        self.pins.merge(pins)
+    }
+
+    /// Check if a content object is stored on disk and can be read,
+    /// along with the content it is a delta of, if any.
+    /// 
+    /// Objects can be stored without anything referring to them yet,
+    /// like those received by a push that was interrupted.
+    pub fn has_content(&self, hash: ObjectHash) -> bool {
+        if !self.objects().contains(hash) {
+            return false;
+        }
+
+        match self.fetch_content_object(hash) {
+            Ok(Content::Delta(delta)) => self.has_content(delta.original),
+            Ok(_) => true,
+            Err(_) => false
+        }
+    }
+
+    /// Copy every object this repository borrows from its alternates into
+    /// its own blob store, and stop borrowing from them, so they can be
+    /// deleted. Returns how many objects were copied.
+    pub fn dissociate(&mut self) -> Result<usize> {
+        let blobs = self.blobs();
+
+        let mut copied = 0;
+
+        let mut queue: Vec<ObjectHash> = vec![];
+
+        for hash in self.history.iter_hashes() {
+            copied += blobs.localise(hash)? as usize;
+
+            queue.extend(self.fetch_snapshot(hash)?.files.into_values());
+        }
+
+        for entry in self.stash.iter_entries() {
+            queue.extend(entry.state.files.values());
+        }
+
+        queue.extend(self.staging.iter().map(|(_, hash)| hash));
+
+        let mut seen = HashSet::new();
+
+        while let Some(hash) = queue.pop() {
+            if !seen.insert(hash) {
+                continue;
+            }
+
+            copied += blobs.localise(hash)? as usize;
+
+            // Deltas need the content they are based on.
+            if let Ok(Content::Delta(delta)) = blobs.fetch_content_object(hash) {
+                queue.push(delta.original);
+            }
+        }
+
+        self.alternates.clear();
+
+        Ok(copied)
+    }
+
+    /// Move every object into the kind of store given, and set the config
+    /// to keep new objects there too. Returns how many objects were moved.
+    ///
+    /// The objects are only deleted from the old store once they are
+    /// all in the new one, and the config is saved.
+    pub fn move_objects(&mut self, kind: StoreKind) -> Result<usize> {
+        if kind == self.config.content.store {
+            return Ok(0);
+        }
+
+        let source = self.objects();
+
+        let target = match open_store(kind, &self.config.content, &self.main_dir(), &self.cache)? {
+            Some(store) => store,
+            None => Arc::new(self.blobs())
+        };
+
+        let hashes = source.stored_objects()?;
+
+        for &hash in &hashes {
+            // Snapshots from an interrupted push aren't in the history yet.
+            match source.fetch_content_object(hash) {
+                Ok(content) if !self.history.contains(hash) => target.save_content_object(content, hash)?,
+                _ => target.save_snapshot_object(&source.fetch_snapshot(hash)?)?
+            }
+        }
+
+        self.config.content.store = kind;
+
+        self.config.save(self.main_dir().join("config.toml"))?;
+
+        self.store = (kind != StoreKind::Files).then_some(target);
+
+        for &hash in &hashes {
+            source.remove_object(hash)?;
+        }
+
+        Ok(hashes.len())
+    }
+
+    /// Add a snapshot that is stored on disk, but missing from the history,
+    /// back into the history, returning whether it could be. Its parents
+    /// have to be in the history, and its content stored, first.
+    /// 
+    /// This picks up snapshots from a push that was interrupted
+    /// before the repository was saved.
+    pub fn restore_snapshot(&mut self, hash: ObjectHash) -> Result<bool> {
+        if self.history.contains(hash) {
+            return Ok(self.has_snapshot(hash));
+        }
+
+        if !self.objects().contains(hash) {
+            return Ok(false);
+        }
+
+        let Ok(snapshot) = self.fetch_snapshot(hash) else {
+            return Ok(false);
+        };
+
+        if snapshot.parents.iter().any(|&parent| !self.history.contains(parent)) {
+            return Ok(false);
+        }
+
+        if snapshot.files.values().any(|&content| !self.has_content(content)) {
+            return Ok(false);
+        }
+
+        self.save_snapshot(snapshot)?;
+
+        Ok(true)
+    }
+
+    /// Check if the data for a large file is stored locally.
+    pub fn has_large_file(&self, hash: ObjectHash) -> bool {
+        self.large_file_path(hash).exists()
+    }
+
+    /// Fetch the content of a large file, addressed by its hash.
+    pub fn fetch_large_file(&self, hash: ObjectHash) -> Result<String> {
+        let path = self.large_file_path(hash);
+
+        if !path.exists() && guard().allow_quietly() {
+            bail!("large file {hash} is not stored locally - run `asc lfs fetch` to download it.");
+        }
+
+        let raw = unwrap!(
+            fs::read(&path),
+            "failed to read bytes from: {}", path.display()
+        );
+
+        let decompressed = decompress_data(raw)?;
+
+        Ok(String::from_utf8(decompressed)?)
+    }
+
+    /// Save a string as a large file, storing a [`Pointer`]
+    /// to it in the blob store, and return the hash used to load it.
+    pub fn save_content_large(&self, content: &str) -> Result<ObjectHash> {
+        let hash = hash_raw_bytes(content);
+
+        let path = self.large_file_path(hash);
+
+        if !path.exists() {
+            unwrap!(
+                fs::create_dir_all(self.lfs_dir()),
+                "failed to create directory: {}", self.lfs_dir().display()
+            );
+
+            unwrap!(
+                fs::write(&path, compress_data(content)),
+                "failed to write large file to: {}", path.display()
+            );
+        }
+
+        let pointer = Pointer {
+            hash,
+            size: content.len() as u64
+        };
+
+        self.save_content_object(Content::Pointer(pointer), hash)?;
+
+        Ok(hash)
+    }
+
+    /// Check if the content of a file is too big for the blob store,
+    /// according to the LFS threshold in the repository's config.
+    pub fn is_large_content(&self, content: &str) -> bool {
+        self.config.lfs.threshold
+            .is_some_and(|threshold| content.len() as u64 > threshold)
+    }
+
+    /// Find the hashes of all the large files used by the given snapshots.
+    pub fn list_large_files(&self, snapshots: impl IntoIterator<Item = ObjectHash>) -> Result<BTreeSet<ObjectHash>> {
+        let mut large_files = BTreeSet::new();
+        let mut seen = HashSet::new();
+
+        for snapshot_hash in snapshots {
+            let snapshot = self.fetch_snapshot(snapshot_hash)?;
+
+            for hash in snapshot.files.into_values() {
+                if !seen.insert(hash) {
+                    continue;
+                }
+
+                if let Content::Pointer(pointer) = self.fetch_content_object(hash)? {
+                    large_files.insert(pointer.hash);
+                }
+            }
+        }
+
+        Ok(large_files)
+    }
+
+    /// Write large files from the current snapshot that are missing
+    /// from the working directory, because they weren't stored locally
+    /// when the snapshot was checked out.
+    /// 
+    /// This returns the paths that were written.
+    pub fn restore_large_files(&self) -> Result<Vec<RelativePathBuf>> {
+        let current = self.fetch_current_snapshot()?;
+
+        let mut restored = vec![];
+
+        for (path, hash) in current.files {
+            let full_path = path.to_logical_path(&self.root_dir);
+
+            if full_path.exists() || !self.has_large_file(hash) {
+                continue;
+            }
+
+            let content = self.fetch_string_content(hash)?;
+
+            unwrap!(
+                fs::create_dir_all(full_path.parent().unwrap()),
+                "failed to create directory for: {path}"
+            );
+
+            unwrap!(
+                fs::write(&full_path, content),
+                "failed to write to path: {path}"
+            );
+
+            restored.push(path);
+        }
+
+        Ok(restored)
+    }
+
+    /// Save a string to disk with optional delta compression if `basis` is provided
+    /// and a delta against it is smaller than `content` compressed on its own.
+    /// 
+    /// Content over the LFS threshold is saved with [`Repository::save_content_large`] instead.
+    pub fn save_content(&self, content: &str, basis: Option<ObjectHash>) -> Result<ObjectHash> {
+        if self.is_large_content(content) {
+            return self.save_content_large(content);
+        }
+
+        let Some(basis) = basis else {
+            return self.save_content_raw(content);
+        };
+
+        // Deltas against large files would need the large file to be
+        // stored locally just to read the content back.
+        if let Content::Pointer(_) = self.fetch_content_object(basis)? {
+            return self.save_content_raw(content);
+        }
+
+        let Some(hash) = self.save_content_delta(content, basis)? else {
+            return self.save_content_raw(content);
+        };
+
+        Ok(hash)
+    }
+
+    /// Save a string as a compressed blob to disk and return the hash used to load it.
+    pub fn save_content_raw(&self, content: &str) -> Result<ObjectHash> {
+        let hash = hash_raw_bytes(content);
+
+        let object = Content::Literal(compress_data(content));
+
+        self.save_content_object(object, hash)?;
+
+        Ok(hash)
+    }
+
+    /// Save a string as a delta of some other string on disk, but reject the delta
+    /// if it is no smaller than `content` compressed on its own, or if it would
+    /// make the chain of deltas longer than the config allows.
+    pub fn save_content_delta(&self, content: &str, basis: ObjectHash) -> Result<Option<ObjectHash>> {
+        let original = self.fetch_string_content(basis)?;
+
+        let hash = hash_raw_bytes(content);
+
+        let depth = self.delta_depth(basis)? + 1;
+
+        if depth > self.config.content.max_delta_depth() {
+            return Ok(None);
+        }
+
+        let Some(mut delta) = Delta::new(&original, content) else {
+            return Ok(None);
+        };
+
+        delta.depth = depth;
+
+        self.save_content_object(Content::Delta(delta), hash)?;
+
+        Ok(Some(hash))
+    }
+
+    /// Save a string as a delta of some other string on disk, regardless of
+    /// whether the delta is any smaller than the string itself.
+    /// 
+    /// For a method that considers size, use the safer [`Repository::save_content_delta`],
+    /// or the higher-level [`Repository::save_content`].
     pub fn save_content_delta_unchecked(&self, content: &str, basis: ObjectHash) -> Result<ObjectHash> {
         let original = self.fetch_string_content(basis)?;
 
         let hash = hash_raw_bytes(content);
 
-        let delta = Content::Delta(Delta::new_unchecked(&original, content));
+        let mut delta = Delta::new_unchecked(&original, content);
 
-        self.save_content_object(delta, hash)?;
+        delta.depth = self.delta_depth(basis)? + 1;
+
+        self.save_content_object(Content::Delta(delta), hash)?;
 
         Ok(hash)
     }
 
-    /// Save a [`Content`] object, most likely obtained from network transfer.
-    pub fn save_content_object(&self, object: Content, hash: ObjectHash) -> Result<()> {
-        let path = self.hash_to_path(hash);
+    /// Work out how many deltas have to be applied to get some
+    /// content back, which is `0` for content that is stored whole.
+    pub fn delta_depth(&self, hash: ObjectHash) -> Result<usize> {
+        let mut depth = 0;
+
+        let mut next = hash;
+
+        loop {
+            match self.fetch_content_object(next)? {
+                Content::Delta(delta) if delta.depth > 0 => return Ok(depth + delta.depth),
+
+                Content::Delta(delta) => {
+                    depth += 1;
+
+                    next = delta.original;
+                },
+
+                _ => return Ok(depth)
+            }
+        }
+    }
+
+    /// Get the hash of every object in the repository's store,
+    /// skipping any files in the blob store that aren't named after a hash.
+    pub fn stored_objects(&self) -> Result<HashSet<ObjectHash>> {
+        self.objects().stored_objects()
+    }
+
+    /// Save content whole wherever a chain of deltas is longer than the config
+    /// allows, and fill in the depth of deltas saved before it was recorded.
+    /// 
+    /// Returns how many objects were saved whole to shorten a chain.
+    pub fn optimise_deltas(&self) -> Result<usize> {
+        let max_depth = self.config.content.max_delta_depth();
+
+        let mut deltas: HashMap<ObjectHash, Delta> = HashMap::new();
+
+        for hash in self.stored_objects()? {
+            if self.history.contains(hash) {
+                continue;
+            }
+
+            if let Ok(Content::Delta(delta)) = self.fetch_content_object(hash) {
+                deltas.insert(hash, delta);
+            }
+        }
+
+        let mut depths: HashMap<ObjectHash, usize> = HashMap::new();
+
+        let mut shortened = 0;
+
+        let hashes: Vec<ObjectHash> = deltas.keys().copied().collect();
+
+        for hash in hashes {
+            // Walk back to the first object whose depth is settled,
+            // then settle the deltas on the way back down.
+            let mut chain = vec![];
+
+            let mut next = hash;
+
+            let mut depth = loop {
+                if let Some(&depth) = depths.get(&next) {
+                    break depth;
+                }
+
+                match deltas.get(&next) {
+                    Some(delta) if !chain.contains(&next) => {
+                        chain.push(next);
+
+                        next = delta.original;
+                    },
+
+                    _ => break 0
+                }
+            };
+
+            for &link in chain.iter().rev() {
+                let delta = &deltas[&link];
+
+                depth += 1;
+
+                if depth > max_depth {
+                    let content = self.fetch_string_content(link)?;
+
+                    self.overwrite_content_object(Content::Literal(compress_data(content)), link)?;
+
+                    shortened += 1;
+
+                    depth = 0;
+                }
+                else if delta.depth != depth {
+                    let updated = Delta {
+                        depth,
+                        ..delta.clone()
+                    };
 
-        if path.exists() {
+                    self.overwrite_content_object(Content::Delta(updated), link)?;
+                }
+
+                depths.insert(link, depth);
+            }
+        }
+
+        Ok(shortened)
+    }
+
+    /// Replace a [`Content`] object that is already stored with one that
+    /// resolves to the same text, unlike [`Repository::save_content_object`].
+    fn overwrite_content_object(&self, object: Content, hash: ObjectHash) -> Result<()> {
+        if !guard().allow_quietly() {
             return Ok(());
         }
-        
-        save_as_msgpack(&object, path)
+
+        let objects = self.objects();
+
+        objects.remove_object(hash)?;
+
+        objects.save_content_object(object, hash)
+    }
+
+    /// Save a [`Content`] object, most likely obtained from network transfer.
+    pub fn save_content_object(&self, object: Content, hash: ObjectHash) -> Result<()> {
+        self.objects().save_content_object(object, hash)
     }
 
-    /// Save a snapshot as a compressed blob to disk.
-    pub fn save_snapshot(&mut self, mut snapshot: Snapshot) -> Result<()> {
-        snapshot.rehash();
+    /// Save a snapshot as a compressed blob to disk, returning its hash.
+    /// 
+    /// The hash is checked against the contents of the snapshot, rather
+    /// than worked out again, so a snapshot that was changed after it
+    /// was made is refused instead of being saved under a new hash.
+    pub fn save_snapshot(&mut self, snapshot: Snapshot) -> Result<ObjectHash> {
+        let hash = snapshot.hash;
 
-        self.history.insert_orphan(snapshot.hash);
+        let expected = snapshot.expected_hash();
 
-        for &parent in &snapshot.parents {
-            self.history.insert(snapshot.hash, parent);
+        if hash != expected {
+            bail!("snapshot {hash} does not match its contents (expected {expected}).");
         }
 
         if self.users.get_user(&snapshot.signature.key()).is_none()  {
@@ -852,15 +2264,48 @@ impl Repository {
 
         snapshot.verify()?;
 
-        let path = self.hash_to_path(snapshot.hash);
+        self.history.insert_orphan(hash);
+
+        for &parent in &snapshot.parents {
+            self.history.insert(hash, parent);
+        }
+
+        if !guard().allow_quietly() {
+            return Ok(hash);
+        }
 
-        save_as_msgpack(&snapshot, path)
+        self.objects().save_snapshot_object(&snapshot)?;
+
+        Ok(hash)
     }
 
-    /// Assemble a [`Snapshot`] from the repository's tracked files.
+    /// Get the template that snapshot messages written in an editor start with.
     /// 
-    /// This saves the tracked files' contents to disk, as well as the [`Snapshot`].
-    pub fn commit_current_state(&self, message: String) -> Result<Snapshot> {
+    /// This is read from the file set in the config, or from
+    /// `.asc/SNAPSHOT_TEMPLATE` if no file is set and it exists.
+    pub fn commit_template(&self) -> Result<Option<String>> {
+        let path = match &self.config.commit.template {
+            Some(path) => self.root_dir.join(path),
+            None => self.main_dir().join("SNAPSHOT_TEMPLATE")
+        };
+
+        if self.config.commit.template.is_none() && !path.exists() {
+            return Ok(None);
+        }
+
+        let template = unwrap!(
+            fs::read_to_string(&path),
+            "failed to read snapshot template: {}", path.display()
+        );
+
+        Ok(Some(template))
+    }
+
+    /// Assemble a [`Snapshot`] from the repository's staged files.
+    /// 
+    /// The staged content is already saved to disk, but the [`Snapshot`] isn't.
+    /// `branch` is recorded as the branch it was committed to.
+    pub fn commit_current_state(&self, message: String, branch: Option<String>) -> Result<Snapshot> {
         let user = unwrap!(
             self.current_user(),
             "cannot commit state: no valid user.",
@@ -868,29 +2313,48 @@ impl Repository {
         
         let key = user.private_key.clone().unwrap();
 
-        let base_files = self.fetch_current_snapshot()?.files;
+        paths::check_collisions(self.staging.paths(), self.config.paths.case_sensitive())?;
 
-        let mut files = BTreeMap::new();
-        
-        for path in &self.staged_files {
-            let full_path = path.to_logical_path(&self.root_dir);
+        let snapshot = Snapshot::new_with_modes(
+            key,
+            message,
+            self.now(),
+            paths::canonical_files(self.staging.files()),
+            self.staging.modes().clone(),
+            set![self.current_hash()],
+            branch
+        );
 
-            let content = unwrap!(
-                fs::read_to_string(full_path),
-                "could not read from path: {path}"
-            );
+        Ok(snapshot)
+    }
 
-            let hash = self.save_content(&content, base_files.get(path).cloned())?;
+    /// Rebuild the current snapshot from the staged files, keeping its author
+    /// and parents. The message and timestamp are kept unless new ones are given.
+    /// 
+    /// The snapshot is re-signed, so its author needs a private key in this repository.
+    /// Use [`Repository::save_snapshot`] and [`Repository::advance_head`] to put it in place.
+    pub fn amend_current_state(&self, message: Option<String>, timestamp: Option<DateTime<Utc>>) -> Result<Snapshot> {
+        let current = self.fetch_current_snapshot()?;
 
-            files.insert(path.clone(), hash);
-        }
+        let author = unwrap!(
+            self.users.get_user(&current.author),
+            "cannot amend snapshot {}: authored by an unknown user.", current.hash
+        );
+
+        let Some(key) = author.private_key.clone() else {
+            bail!("cannot amend snapshot {}: no private key for its author {:?}.", current.hash, author.name);
+        };
+
+        paths::check_collisions(self.staging.paths(), self.config.paths.case_sensitive())?;
 
-        let snapshot = Snapshot::new(
+        let snapshot = Snapshot::new_with_modes(
             key,
-            message,
-            Utc::now(),
-            files,
-            set![self.current_hash]
+            message.unwrap_or(current.message),
+            timestamp.unwrap_or(current.timestamp),
+            paths::canonical_files(self.staging.files()),
+            self.staging.modes().clone(),
+            current.parents,
+            current.branch
         );
 
         Ok(snapshot)
@@ -898,24 +2362,25 @@ impl Repository {
 }
 
 impl Repository {
-    fn cwd_differs_from_snapshot(&self, files: &BTreeMap<RelativePathBuf, ObjectHash>) -> Result<bool> {
+    fn cwd_differs_from_snapshot(
+        &self,
+        files: &BTreeMap<RelativePathBuf, ObjectHash>,
+        modes: &BTreeMap<RelativePathBuf, FileMode>,
+        watched: &WatchState
+    ) -> Result<bool>
+    {
+        // The staged paths are canonical, while snapshots
+        // made by older versions might not be.
+        let files = &paths::canonical_files(files);
+
         let mut paths_remaining: HashSet<_> = files.keys().collect();
 
-        for path in &self.staged_files {
+        for path in self.staging.paths() {
             paths_remaining.remove(path);
 
-            let full_path = path.to_logical_path(&self.root_dir);
-
-            if !full_path.exists() {
+            let Some(current_content_hash) = watched.working_hash(self, path)? else {
                 return Ok(true);
-            }
-
-            let current_content = unwrap!(
-                fs::read_to_string(full_path),
-                "failed to read path: {path}"
-            );
-
-            let current_content_hash = hash_raw_bytes(&current_content);
+            };
 
             let Some(&previous_content_hash) = files.get(path) else {
                 return Ok(true)
@@ -924,6 +2389,14 @@ impl Repository {
             if previous_content_hash != current_content_hash {
                 return Ok(true);
             }
+
+            let previous_mode = modes.get(path).copied().unwrap_or_default();
+
+            let full_path = paths::on_disk(path, &self.root_dir);
+
+            if FileMode::of(&full_path, previous_mode)? != previous_mode {
+                return Ok(true);
+            }
         }
 
         if !paths_remaining.is_empty() {
@@ -940,16 +2413,18 @@ impl Repository {
     pub fn has_unsaved_changes(&self) -> Result<bool> {
         let current = self.fetch_current_snapshot()?;
 
+        let watched = WatchState::load(self);
+
         // If the CWD matches the current snapshot,
         // no changes are made, and content is safe.
-        if !self.cwd_differs_from_snapshot(&current.files)? {
+        if !self.cwd_differs_from_snapshot(&current.files, &current.modes, &watched)? {
             return Ok(false);
         }
 
         // If the CWD matches a snapshot in the stash,
         // no changes are made, and content is safe.
         for entry in self.stash.iter_entries() {
-            if !self.cwd_differs_from_snapshot(&entry.state.files)? {
+            if !self.cwd_differs_from_snapshot(&entry.state.files, &entry.state.modes, &watched)? {
                 return Ok(false);
             }
         }
@@ -961,36 +2436,175 @@ impl Repository {
     /// from another [`Snapshot`].
     /// 
     /// This is used to switch the repository to a different version,
-    /// and will fail if there are unsaved changes.
-    pub fn replace_cwd_with_snapshot(&mut self, snapshot: &Snapshot) -> Result<()> {
+    /// and will fail if there are unsaved changes. Files that aren't
+    /// staged but would be overwritten are backed up in the same way
+    /// as [`Repository::replace_cwd_with_files`].
+    pub fn replace_cwd_with_snapshot(&mut self, snapshot: &Snapshot) -> Result<Option<usize>> {
         if self.has_unsaved_changes()? {
-            bail!("cannot change snapshots with unsaved changes.");
+            return Err(UnsavedChanges { action: "change snapshots".to_string() }.into());
         }
 
-        self.replace_cwd_with_files(&snapshot.files)
+        self.replace_cwd_with_files(&snapshot.files, &snapshot.modes)
+    }
+
+    /// Save what is in the working directory to the stash as a backup,
+    /// if replacing it with `files` would lose anything, returning the
+    /// stash ID of the backup.
+    /// 
+    /// As well as unsaved changes to staged files, this keeps files that
+    /// aren't staged but would be overwritten by a file in `files`.
+    fn backup_cwd(&mut self, files: &BTreeMap<RelativePathBuf, ObjectHash>) -> Result<Option<usize>> {
+        let mut paths: Vec<RelativePathBuf> = self.staging.paths().cloned().collect();
+
+        let mut overwritten = false;
+
+        for (path, &hash) in files {
+            let full_path = path.to_logical_path(&self.root_dir);
+
+            if self.staging.contains(path) || !mode::exists(&full_path) {
+                continue;
+            }
+
+            paths.push(path.clone());
+
+            if hash_raw_bytes(mode::read_working_bytes(&full_path)?) != hash {
+                overwritten = true;
+            }
+        }
+
+        if !overwritten && !self.has_unsaved_changes()? {
+            return Ok(None);
+        }
+
+        let current = self.fetch_current_snapshot()?;
+
+        let mut saved = BTreeMap::new();
+
+        let mut modes = BTreeMap::new();
+
+        for path in paths {
+            let full_path = paths::on_disk(&path, &self.root_dir);
+
+            if !mode::exists(&full_path) {
+                continue;
+            }
+
+            let content = unwrap!(
+                mode::read_working(&full_path),
+                "failed to read path: {path}"
+            );
+
+            let hash = self.save_content(&content, current.files.get(&path).copied())?;
+
+            modes.insert(path.clone(), FileMode::of(&full_path, self.staging.mode(&path))?);
+
+            saved.insert(path, hash);
+        }
+
+        let state = State {
+            message: "Backup of the working directory before it was replaced".to_string(),
+            files: saved,
+            modes: mode::special_modes(modes),
+            untracked: BTreeSet::new()
+        };
+
+        Ok(Some(self.stash.add_backup(state, current.hash)))
     }
 
     /// Replace the state of the current working directory with that
     /// from a set of files, but **DO NOT** check if there are
     /// unsaved changes.
     /// 
+    /// Anything that would be lost is first saved to the stash as
+    /// a backup, and the stash ID of the backup is returned.
+    /// 
+    /// Files are written at their paths as given by [`paths::canonical`],
+    /// and nothing is written if two paths would be the same file here.
+    /// 
+    /// Paths in `modes` are written as that kind of file, and any others
+    /// are written as regular files.
+    /// 
+    /// During a dry run, the files that would be written or
+    /// removed are recorded with the [`guard`] instead.
+    /// 
     /// For a safer alternative, use [`Repository::replace_cwd_with_snapshot`].
-    pub fn replace_cwd_with_files(&mut self, files: &BTreeMap<RelativePathBuf, ObjectHash>) -> Result<()> {
-        let current = self.fetch_current_snapshot()?;
+    pub fn replace_cwd_with_files(
+        &mut self,
+        files: &BTreeMap<RelativePathBuf, ObjectHash>,
+        modes: &BTreeMap<RelativePathBuf, FileMode>
+    ) -> Result<Option<usize>>
+    {
+        // Paths that are the same file here would overwrite each other.
+        paths::check_collisions(files.keys(), self.config.paths.case_sensitive())?;
+
+        let files = &paths::canonical_files(files);
+
+        let modes: BTreeMap<RelativePathBuf, FileMode> = modes
+            .iter()
+            .map(|(path, &mode)| (paths::canonical(path), mode))
+            .collect();
+
+        let backup = self.backup_cwd(files)?;
+
+        let current = paths::canonical_files(&self.fetch_current_snapshot()?.files);
 
         // Delete paths that are in this snapshot but not the destination snapshot.
-        for path in current.files.keys() {
-            let full_path = path.to_logical_path(&self.root_dir);
+        for path in current.keys() {
+            let full_path = paths::on_disk(path, &self.root_dir);
 
-            if !files.contains_key(path) {
+            if !files.contains_key(path)
+                && mode::exists(&full_path)
+                && guard().allow(|| Change::Remove(path.clone()))
+            {
                 remove_path(full_path, &self.root_dir)?;
             }
         }
 
-        for (path, &new) in files {
-            let content = self.fetch_string_content(new)?;
+        if guard().is_dry_run() {
+            // Only files that would end up different are worth reporting.
+            for (path, &hash) in files {
+                let full_path = paths::on_disk(path, &self.root_dir);
 
-            let full_path = path.to_logical_path(&self.root_dir);
+                let unchanged = mode::exists(&full_path)
+                    && hash_raw_bytes(mode::read_working_bytes(&full_path)?) == hash;
+
+                if !unchanged {
+                    guard().allow(|| Change::Write(path.clone()));
+                }
+            }
+        }
+        else {
+            self.write_files(&self.root_dir, files, &modes, false)?;
+        }
+
+        self.staging = StagingArea::from_files(files.clone());
+
+        self.staging.set_modes(&modes);
+
+        Ok(backup)
+    }
+
+    /// Write files with the given content and modes into `dir`, replacing
+    /// anything already there, and making them read-only if `read_only` is set.
+    fn write_files(
+        &self,
+        dir: &Path,
+        files: &BTreeMap<RelativePathBuf, ObjectHash>,
+        modes: &BTreeMap<RelativePathBuf, FileMode>,
+        read_only: bool
+    ) -> Result<()>
+    {
+        let write = |path: &RelativePathBuf, hash: ObjectHash, mode: FileMode| -> Result<()> {
+            let content = self.fetch_string_content(hash)?;
+
+            let full_path = path.to_logical_path(dir);
+
+            // A symbolic link where a directory is expected would
+            // send the file to wherever the link points instead.
+            unwrap!(
+                mode::check_no_symlink_ancestors(dir, &full_path),
+                "refusing to write to path: {path}"
+            );
 
             unwrap!(
                 fs::create_dir_all(full_path.parent().unwrap()),
@@ -998,89 +2612,212 @@ impl Repository {
             );
 
             unwrap!(
-                fs::write(&full_path, content),
+                mode::write_working(&full_path, &content, mode),
                 "failed to write to path: {path}"
             );
+
+            if read_only {
+                checkout::make_read_only(&full_path)?;
+            }
+
+            Ok(())
+        };
+
+        let (links, regular): (Vec<_>, Vec<_>) = files
+            .iter()
+            .map(|(path, &hash)| (path, hash, modes.get(path).copied().unwrap_or_default()))
+            .partition(|(_, _, mode)| *mode == FileMode::Symlink);
+
+        self.with_thread_pool(|| {
+            regular.par_iter().try_for_each(|&(path, hash, mode)| write(path, hash, mode))
+        })??;
+
+        // Links are only made once every regular file is written, so
+        // none of those writes can go through a link made along the way.
+        for (path, hash, mode) in links {
+            write(path, hash, mode)?;
         }
 
-        self.staged_files = files
-            .keys()
-            .cloned()
+        Ok(())
+    }
+
+    /// Write the files of a version out to a new temporary directory,
+    /// without changing the working directory or the staging area.
+    /// 
+    /// The directory is deleted when the returned [`TempCheckout`] is dropped.
+    pub fn materialize(&self, version: &str) -> Result<TempCheckout> {
+        let hash = self.normalise_version(version)?;
+
+        let snapshot = self.fetch_snapshot(hash)?;
+
+        // Paths that are the same file here would overwrite each other.
+        paths::check_collisions(snapshot.files.keys(), self.config.paths.case_sensitive())?;
+
+        let files = paths::canonical_files(&snapshot.files);
+
+        let modes: BTreeMap<RelativePathBuf, FileMode> = snapshot.modes
+            .iter()
+            .map(|(path, &mode)| (paths::canonical(path), mode))
             .collect();
 
-        Ok(())
+        let checkout = TempCheckout::create(hash)?;
+
+        self.write_files(checkout.path(), &files, &modes, true)?;
+
+        Ok(checkout)
     }
 
     /// List all the changes as [`FileChange`] objects between
-    /// the current snapshot and the current working directory.
+    /// the current snapshot, the staging area and the current
+    /// working directory.
+    /// 
+    /// A path can appear twice: once for how its staged content
+    /// differs from the current snapshot, and once for how the
+    /// working directory differs from what is staged.
     pub fn list_changes(&self) -> Result<Vec<FileChange<RelativePathBuf>>> {
-        let checkout_files = self.fetch_current_snapshot()?.files;
+        let current = self.fetch_current_snapshot()?;
 
-        let checkout: HashSet<&RelativePathBuf> = checkout_files
-            .keys()
-            .collect();
+        let checkout_files = paths::canonical_files(&current.files);
 
-        let staged: HashSet<&RelativePathBuf> = self.staged_files
+        let checkout_modes: BTreeMap<RelativePathBuf, FileMode> = current.modes
             .iter()
+            .map(|(path, &mode)| (paths::canonical(path), mode))
             .collect();
 
-        let all_paths: HashSet<&&RelativePathBuf> = checkout
-            .iter()
-            .chain(staged.iter())
+        let all_paths: BTreeSet<&RelativePathBuf> = checkout_files
+            .keys()
+            .chain(self.staging.paths())
             .collect();
 
+        let watched = WatchState::load(self);
+
         let mut file_changes = vec![];
 
         for path in all_paths {
-            let path_buf = (*path).clone();
-
-            if !checkout.contains(path) && staged.contains(path) {
-                file_changes.push(FileChange::Added(path_buf));
+            let path_buf = path.clone();
 
-                continue;
-            }
+            let committed = checkout_files.get(path).copied();
 
-            if checkout.contains(path) && !staged.contains(path) {
+            let Some(staged) = self.staging.get(path) else {
                 file_changes.push(FileChange::Removed(path_buf));
 
                 continue;
-            }
+            };
 
-            let full_path = path.to_logical_path(&self.root_dir);
+            let mut changed = true;
 
-            if !full_path.exists() {
-                file_changes.push(FileChange::Missing(path_buf));
+            let staged_mode = self.staging.mode(path);
 
-                continue;
+            let committed_mode = checkout_modes.get(path).copied().unwrap_or_default();
+
+            match committed {
+                None => file_changes.push(FileChange::Added(path_buf.clone())),
+                Some(hash) if hash != staged || committed_mode != staged_mode => file_changes.push(FileChange::Edited(path_buf.clone())),
+                Some(_) => changed = false
             }
 
-            let disk_data = fs::read(full_path)?;
+            let Some(working) = watched.working_hash(self, path)? else {
+                file_changes.push(FileChange::Missing(path_buf));
 
-            let disk_hash = hash_raw_bytes(disk_data);
-            
-            let content_hash = checkout_files[*path];
+                continue;
+            };
 
-            if content_hash == disk_hash {
-                file_changes.push(FileChange::Unchanged(path_buf));
+            let working_mode = FileMode::of(&paths::on_disk(path, &self.root_dir), staged_mode)?;
+
+            if working != staged || working_mode != staged_mode {
+                file_changes.push(FileChange::Modified(path_buf));
             }
-            else {
-                file_changes.push(FileChange::Edited(path_buf));
+            else if !changed {
+                file_changes.push(FileChange::Unchanged(path_buf));
             }
         }
 
-        /*
-        commit, index, disk
+        Ok(file_changes)
+    }
 
-        in commit, in index, no changes on disk = UNCHANGED
-        not in commit, in index, ... = ADDED
-        in commit, not in index, ... = REMOVED
-        in commit, in index, not on disk = MISSING
-        in commit, in index, changes on disk = EDITED
-        */
+    /// Stage the content a tracked or new file has in the working directory.
+    /// 
+    /// The content is saved straight away, so later edits to the file
+    /// don't change what is committed until it is staged again.
+    pub fn stage_path(&mut self, path: &RelativePath) -> Result<ObjectHash> {
+        let path = path.normalize();
 
-        // TODO: check it works
+        let full_path = paths::on_disk(&path, &self.root_dir);
 
-        Ok(file_changes)
+        let content = unwrap!(
+            mode::read_working(&full_path),
+            "could not read from path: {path}"
+        );
+
+        let mode = FileMode::of(&full_path, self.staging.mode(&path))?;
+
+        // Staged content that is never committed can be cleaned up,
+        // so only committed content is used as a basis for deltas.
+        let basis = self.fetch_current_snapshot()?.files.get(&path).copied();
+
+        let hash = self.save_content(&content, basis)?;
+
+        self.staging.set_mode(&path, mode);
+
+        self.staging.stage(path, hash);
+
+        Ok(hash)
+    }
+
+    /// Stage the content several files have in the working directory,
+    /// like [`Repository::stage_path`], but reading and saving them in parallel.
+    /// 
+    /// The hashes are returned in the same order as the paths.
+    pub fn stage_paths(&mut self, paths: &[RelativePathBuf]) -> Result<Vec<ObjectHash>> {
+        let current = self.fetch_current_snapshot()?;
+
+        let paths: Vec<RelativePathBuf> = paths.iter().map(|path| path.normalize()).collect();
+
+        let staged = self.with_thread_pool(|| {
+            paths
+                .par_iter()
+                .map(|path| {
+                    let full_path = paths::on_disk(path, &self.root_dir);
+
+                    let content = unwrap!(
+                        mode::read_working(&full_path),
+                        "could not read from path: {path}"
+                    );
+
+                    let mode = FileMode::of(&full_path, self.staging.mode(path))?;
+
+                    let hash = self.save_content(&content, current.files.get(path).copied())?;
+
+                    Ok((hash, mode))
+                })
+                .collect::<Result<Vec<_>>>()
+        })??;
+
+        let mut hashes = vec![];
+
+        for (path, (hash, mode)) in paths.into_iter().zip(staged) {
+            self.staging.set_mode(&path, mode);
+
+            self.staging.stage(path, hash);
+
+            hashes.push(hash);
+        }
+
+        Ok(hashes)
+    }
+
+    /// Run `op` on a thread pool with as many threads as the `io.threads`
+    /// config allows, which parallel iterators used inside it are kept to.
+    pub fn with_thread_pool<R: Send>(&self, op: impl FnOnce() -> R + Send) -> Result<R> {
+        let mut builder = ThreadPoolBuilder::new();
+
+        if let Some(threads) = self.config.io.threads {
+            builder = builder.num_threads(threads);
+        }
+
+        let pool = unwrap!(builder.build(), "failed to start a thread pool");
+
+        Ok(pool.install(op))
     }
 
     /// Performs a check across the entire repository to see if:
@@ -1090,47 +2827,83 @@ impl Repository {
     /// * all commit authors are valid users
     /// * all commit parents are correct
     /// * all content is present
-    /// * all branch pointers and tags are valid
+    /// * every branch, tag and other ref points to a stored snapshot
     /// 
-    /// This only considers reachable commits.
+    /// This only considers reachable commits, which are checked in parallel.
     pub fn validate_state(&self) -> Result<()> {
-        let mut queue = VecDeque::new();
+        let reachable = self.history.reachable(self.refs.branches().values(), |_| false)?;
 
-        queue.extend(self.branches.values().cloned());
+        self.with_thread_pool(|| {
+            reachable.par_iter().try_for_each(|&current| self.validate_snapshot(current))
+        })??;
 
-        while let Some(current) = queue.pop_back() {
-            let snapshot = self.fetch_snapshot(current)?;
-
-            let parents = unwrap!(
-                self.history.get_parents(current),
-                "cannot get parents for hash {current:?}"
-            );
-
-            if parents != &snapshot.parents {
-                bail!("snapshot {current} has invalid parents (parents in graph differ from parents in signature)");
+        // Refs to missing snapshots are repaired with `fsck::repair`.
+        for (name, hash) in self.refs.iter() {
+            if !self.has_snapshot(hash) {
+                bail!("{name} points to a missing snapshot {hash}.");
             }
+        }
 
-            let author = snapshot.signature.key();
+        Ok(())
+    }
 
-            if self.users.get_user(&author).is_none() {
-                bail!("snapshot {current} was created by an unknown user (key {author} matches no user)");
-            }
+    /// Check one snapshot for [`Repository::validate_state`].
+    fn validate_snapshot(&self, current: ObjectHash) -> Result<()> {
+        let snapshot = self.fetch_snapshot(current)?;
 
-            snapshot.verify()?;
+        let parents = unwrap!(
+            self.history.get_parents(current),
+            "cannot get parents for hash {current:?}"
+        );
 
-            for hash in snapshot.files.into_values() {
-                self.fetch_content_object(hash)?;
-            }
+        if parents != &snapshot.parents {
+            bail!("snapshot {current} has invalid parents (parents in graph differ from parents in signature)");
+        }
+
+        let author = snapshot.signature.key();
 
-            queue.extend(parents);
+        if self.users.get_user(&author).is_none() {
+            bail!("snapshot {current} was created by an unknown user (key {author} matches no user)");
         }
 
-        for (name, &hash) in self.tags.iter() {
-            if !self.history.contains(hash) {
-                bail!("tag {name:?} points to an unrecorded ({hash:?})");
-            }
+        snapshot.verify()?;
+
+        for hash in snapshot.files.into_values() {
+            self.fetch_content_object(hash)?;
         }
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renamed_branches_keep_their_upstreams_and_settings() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let mut repo = Repository::create_new(dir.path(), "axo".to_string(), "test".to_string()).unwrap();
+
+        let tip = repo.current_hash();
+
+        repo.remotes.create("origin".to_string(), Remote::from_path("/elsewhere"));
+
+        repo.set_remote_tip("origin", "main", tip);
+
+        repo.config.branches.linear.push("main".to_string());
+
+        assert!(repo.rename_branch("main", "trunk".to_string()).unwrap());
+
+        assert_eq!(repo.current_branch(), Some("trunk"));
+        assert_eq!(repo.upstreams("trunk"), [("origin/trunk".to_string(), tip)]);
+        assert!(repo.upstreams("main").is_empty());
+
+        let saved = Config::load(repo.main_dir().join("config.toml")).unwrap();
+
+        assert!(saved.branches.is_linear("trunk"));
+
+        assert!(!repo.rename_branch("main", "other".to_string()).unwrap());
+    }
+}
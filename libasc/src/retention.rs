@@ -0,0 +1,173 @@
+//! Keeping history that has to be retained, like for a legal hold,
+//! from being rewritten or deleted before its time is up.
+//!
+//! A server sets how long history on its branches has to be kept:
+//!
+//! ```toml
+//! [retention.branches]
+//! "main" = "7y"
+//! "release/*" = "2y"
+//! ```
+//!
+//! Snapshots made on those branches less than that long ago are held: they
+//! can't be trashed or modified, and cleaning and purging the trash keep them
+//! even once nothing points to them. A server also refuses pushes and mirrors
+//! that would move a branch back or delete it, leaving held snapshots behind,
+//! and mirrors that would trash them. Every operation refused because of a
+//! hold is written to the repository's audit log, along with who tried it.
+
+use std::{collections::HashSet, path::PathBuf};
+
+use chrono::{DateTime, TimeDelta, Utc};
+use eyre::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::{hash::ObjectHash, key::PublicKey, repository::Repository, utils::{load_as_msgpack, save_as_msgpack}};
+
+/// Why a snapshot has to be kept.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Hold {
+    pub hash: ObjectHash,
+
+    /// The branch the snapshot was made on.
+    pub branch: String,
+
+    /// When the snapshot stops being held.
+    pub until: DateTime<Utc>
+}
+
+/// An operation that was refused because it would have
+/// rewritten or deleted a snapshot that is held.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct AuditEntry {
+    pub when: DateTime<Utc>,
+
+    /// Who tried the operation, if a user was set.
+    pub user: Option<PublicKey>,
+
+    /// What was tried, like `trash add 1a2b3c4d5e`.
+    pub operation: String,
+
+    pub hold: Hold
+}
+
+/// Get the hold on a snapshot, if it is held.
+pub fn hold_on(repo: &Repository, hash: ObjectHash) -> Result<Option<Hold>> {
+    if repo.config.retention.is_empty() {
+        return Ok(None);
+    }
+
+    let snapshot = repo.fetch_snapshot(hash)?;
+
+    let Some(branch) = snapshot.branch else {
+        return Ok(None);
+    };
+
+    let Some(period) = repo.config.retention.period_for(&branch)? else {
+        return Ok(None);
+    };
+
+    let until = TimeDelta::from_std(period)
+        .ok()
+        .and_then(|period| snapshot.timestamp.checked_add_signed(period))
+        .unwrap_or(DateTime::<Utc>::MAX_UTC);
+
+    if until <= Utc::now() {
+        return Ok(None);
+    }
+
+    Ok(Some(Hold { hash, branch, until }))
+}
+
+/// Get the hold on the first of `hashes` that is held, if any are.
+pub fn first_hold(repo: &Repository, hashes: impl IntoIterator<Item = ObjectHash>) -> Result<Option<Hold>> {
+    if repo.config.retention.is_empty() {
+        return Ok(None);
+    }
+
+    for hash in hashes {
+        if let Some(hold) = hold_on(repo, hash)? {
+            return Ok(Some(hold));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Get the hold on the first snapshot that moving a branch from `old_tip` to
+/// `new_tip`, or deleting it if `new_tip` is `None`, would leave behind.
+pub fn first_hold_left_behind(repo: &Repository, old_tip: ObjectHash, new_tip: Option<ObjectHash>) -> Result<Option<Hold>> {
+    if repo.config.retention.is_empty() {
+        return Ok(None);
+    }
+
+    let kept = match new_tip {
+        Some(tip) => repo.history.reachable([tip], |_| false)?,
+        None => HashSet::new()
+    };
+
+    let left_behind = repo.history
+        .reachable([old_tip], |hash| kept.contains(&hash))?
+        .into_iter()
+        .filter(|&hash| repo.has_snapshot(hash));
+
+    first_hold(repo, left_behind)
+}
+
+/// Get every snapshot in the history that is held.
+pub fn held_snapshots(repo: &Repository) -> Result<Vec<ObjectHash>> {
+    let mut held = vec![];
+
+    if repo.config.retention.is_empty() {
+        return Ok(held);
+    }
+
+    for hash in repo.history.iter_hashes() {
+        // Snapshots that are already gone can't be kept.
+        if !repo.has_snapshot(hash) {
+            continue;
+        }
+
+        if hold_on(repo, hash)?.is_some() {
+            held.push(hash);
+        }
+    }
+
+    Ok(held)
+}
+
+fn audit_path(repo: &Repository) -> PathBuf {
+    repo.main_dir().join("audit")
+}
+
+/// Get every operation refused because of a hold, oldest first.
+pub fn audit_log(repo: &Repository) -> Result<Vec<AuditEntry>> {
+    let path = audit_path(repo);
+
+    if !path.exists() {
+        return Ok(vec![]);
+    }
+
+    load_as_msgpack(path)
+}
+
+/// Write down that `operation` was refused because of `hold`.
+pub fn record_blocked(repo: &Repository, operation: impl Into<String>, hold: &Hold) -> Result<()> {
+    record_blocked_by(repo, repo.current_user().map(|user| user.public_key), operation, hold)
+}
+
+/// Write down that `operation`, tried by `user` rather than the
+/// repository's current user, like over a connection to a server,
+/// was refused because of `hold`.
+pub fn record_blocked_by(repo: &Repository, user: Option<PublicKey>, operation: impl Into<String>, hold: &Hold) -> Result<()> {
+    let mut log = audit_log(repo)?;
+
+    log.push(AuditEntry {
+        when: Utc::now(),
+        user,
+        operation: operation.into(),
+        hold: hold.clone()
+    });
+
+    save_as_msgpack(&log, audit_path(repo))
+}
@@ -0,0 +1,626 @@
+//! An [`ObjectStore`] that keeps objects in an S3 bucket, or any storage
+//! with the same API, so a hosted server doesn't need disks big enough
+//! for every object it holds. Everything else, like the history and
+//! refs, stays in `.asc` as usual. A repository uses it when its config has:
+//!
+//! ```toml
+//! [content]
+//! store = "s3"
+//!
+//! [content.s3]
+//! region = "eu-west-2"
+//! bucket = "asc-objects"
+//! prefix = "project"
+//! ```
+//!
+//! Requests are made and signed by curl, so it has to be installed, at
+//! 7.75 or later. Objects are kept in the bucket exactly as they would be
+//! in their own files, and each one read or saved is also kept in
+//! `.asc/s3-cache`, so it only has to be downloaded once.
+
+use std::{collections::HashSet, fs, io::Write, path::{Path, PathBuf}, process::{Command, Stdio}, sync::Arc};
+
+use eyre::{bail, eyre, Result};
+use rand::random;
+
+use crate::{blobs::{BlobStore, ObjectStore}, cache::ObjectCache, config::S3Config, content::Content, guard::guard, hash::ObjectHash, repository::CorruptObject, snapshot::Snapshot, unwrap, utils::{decompress_data, hash_raw_bytes}};
+
+/// How big the parts of large objects are when
+/// they're uploaded, unless the config says otherwise.
+pub static DEFAULT_PART_SIZE: u64 = 16 * 1024 * 1024;
+
+/// The smallest part S3 takes, other than the last one.
+static MIN_PART_SIZE: u64 = 5 * 1024 * 1024;
+
+/// What a request to the bucket got back.
+struct Response {
+    status: u16,
+    headers: String,
+    body: Vec<u8>
+}
+
+impl Response {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .lines()
+            .filter_map(|line| line.split_once(':'))
+            .find(|(key, _)| key.trim().eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.trim())
+    }
+
+    fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+
+    /// Fail with the error S3 sent back, unless the request worked.
+    fn check(self, what: &str) -> Result<Response> {
+        let text = self.text();
+
+        // Completing an upload can fail after a 200 has been sent.
+        if (200..300).contains(&self.status) && !text.contains("<Error>") {
+            return Ok(self);
+        }
+
+        let reason = xml_values(&text, "Message")
+            .into_iter()
+            .next()
+            .unwrap_or_else(|| format!("status {}", self.status));
+
+        bail!("failed to {what} in S3: {reason}")
+    }
+}
+
+/// Objects kept in an S3 bucket, with a local copy of those used.
+#[derive(Clone)]
+pub struct S3Store {
+    endpoint: String,
+    region: String,
+    bucket: String,
+    prefix: String,
+    access_key_id: String,
+    secret_access_key: String,
+    session_token: Option<String>,
+    part_size: u64,
+
+    /// Where objects are kept once they've been read or saved.
+    cache_dir: PathBuf,
+    local: BlobStore
+}
+
+impl S3Store {
+    /// Use the bucket set up by `config`, keeping the objects used in `cache_dir`.
+    pub fn open(config: &S3Config, cache_dir: PathBuf, cache: Arc<ObjectCache>) -> Result<S3Store> {
+        if config.bucket.is_empty() {
+            bail!("objects are kept in S3, but content.s3.bucket isn't set in the config.");
+        }
+
+        let env = |name: &str| std::env::var(name).ok().filter(|value| !value.is_empty());
+
+        let (Some(access_key_id), Some(secret_access_key)) = (
+            config.access_key_id.clone().or_else(|| env("AWS_ACCESS_KEY_ID")),
+            config.secret_access_key.clone().or_else(|| env("AWS_SECRET_ACCESS_KEY"))
+        ) else {
+            bail!("no credentials for S3: set AWS_ACCESS_KEY_ID and AWS_SECRET_ACCESS_KEY, or content.s3 in the config.");
+        };
+
+        let region = config.region.clone().unwrap_or("us-east-1".to_string());
+
+        let endpoint = match &config.endpoint {
+            Some(endpoint) => endpoint.trim_end_matches('/').to_string(),
+            None => format!("https://s3.{region}.amazonaws.com")
+        };
+
+        if !cache_dir.exists() {
+            for x in 0..=255u8 {
+                let label = cache_dir.join(hex::encode([x]));
+
+                unwrap!(
+                    fs::create_dir_all(&label),
+                    "failed to create directory: {}", label.display()
+                );
+            }
+        }
+
+        Ok(S3Store {
+            endpoint,
+            region,
+            bucket: config.bucket.clone(),
+            prefix: config.prefix.trim_matches('/').to_string(),
+            access_key_id,
+            secret_access_key,
+            session_token: env("AWS_SESSION_TOKEN"),
+            part_size: config.part_size.unwrap_or(DEFAULT_PART_SIZE).max(MIN_PART_SIZE),
+            local: BlobStore::new(cache_dir.clone(), cache),
+            cache_dir
+        })
+    }
+
+    /// Get the key an object is kept under in the bucket.
+    fn key(&self, hash: ObjectHash) -> String {
+        self.key_prefix() + &hash.full()
+    }
+
+    /// Get what the key of every object in the bucket starts with.
+    fn key_prefix(&self) -> String {
+        if self.prefix.is_empty() {
+            String::new()
+        }
+        else {
+            format!("{}/", self.prefix)
+        }
+    }
+
+    /// Send a request about `key`, signed by curl. The query has to be
+    /// sorted by name, since that's how it is signed. `upload` is a file
+    /// to send, and `data` is a short body to send instead.
+    fn request(&self, method: &str, key: &str, query: &[(&str, &str)], upload: Option<&Path>, data: Option<&str>) -> Result<Response> {
+        let mut url = format!("{}/{}", self.endpoint, encode(&self.bucket, false));
+
+        if !key.is_empty() {
+            url = format!("{url}/{}", encode(key, true));
+        }
+
+        if !query.is_empty() {
+            let query: Vec<String> = query
+                .iter()
+                .map(|(name, value)| format!("{name}={}", encode(value, false)))
+                .collect();
+
+            url = format!("{url}?{}", query.join("&"));
+        }
+
+        // The credentials go in through stdin, so they don't show up
+        // in the list of processes running on the machine.
+        let mut config = format!(
+            "user = {}\naws-sigv4 = {}\n",
+            quote(&format!("{}:{}", self.access_key_id, self.secret_access_key)),
+            quote(&format!("aws:amz:{}:s3", self.region))
+        );
+
+        if let Some(token) = &self.session_token {
+            config += &format!("header = {}\n", quote(&format!("x-amz-security-token: {token}")));
+        }
+
+        if let Some(data) = data {
+            config += &format!("data-binary = {}\n", quote(data));
+        }
+
+        let mut command = Command::new("curl");
+
+        command.args(["--silent", "--show-error", "--config", "-", "--header", "Expect:"]);
+
+        if method == "HEAD" {
+            command.arg("--head");
+        }
+        else {
+            command.args(["--request", method, "--dump-header", "-"]);
+        }
+
+        if let Some(path) = upload {
+            command.arg("--upload-file").arg(path);
+        }
+
+        let mut child = unwrap!(
+            command
+                .arg(&url)
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .spawn(),
+            "failed to run curl to reach S3 - is it installed?"
+        );
+
+        child.stdin.take().unwrap().write_all(config.as_bytes())?;
+
+        let output = child.wait_with_output()?;
+
+        if !output.status.success() {
+            bail!("failed to reach S3 at {url}: {}", String::from_utf8_lossy(&output.stderr).trim());
+        }
+
+        let split = output.stdout
+            .windows(4)
+            .position(|window| window == b"\r\n\r\n")
+            .unwrap_or(output.stdout.len());
+
+        let headers = String::from_utf8_lossy(&output.stdout[..split]).into_owned();
+
+        let body = output.stdout.get(split + 4..).unwrap_or_default().to_vec();
+
+        // Like `HTTP/1.1 200 OK`, or `HTTP/2 200`.
+        let status = headers
+            .split_whitespace()
+            .nth(1)
+            .and_then(|status| status.parse().ok())
+            .ok_or_else(|| eyre!("got a response from S3 at {url} that isn't HTTP."))?;
+
+        Ok(Response { status, headers, body })
+    }
+
+    /// Get the size of an object in the bucket, if it is there.
+    fn head(&self, hash: ObjectHash) -> Result<Option<u64>> {
+        let response = self.request("HEAD", &self.key(hash), &[], None, None)?;
+
+        if response.status == 404 {
+            return Ok(None);
+        }
+
+        let response = response.check(&format!("look up object {hash}"))?;
+
+        let size = response
+            .header("content-length")
+            .and_then(|size| size.parse().ok())
+            .unwrap_or(0);
+
+        Ok(Some(size))
+    }
+
+    /// Download an object into the local copy, unless it's there already.
+    fn download(&self, hash: ObjectHash) -> Result<()> {
+        if self.local.contains(hash) {
+            return Ok(());
+        }
+
+        let response = self.request("GET", &self.key(hash), &[], None, None)?;
+
+        if response.status == 404 {
+            bail!("object {hash} is not stored.");
+        }
+
+        let response = response.check(&format!("read object {hash}"))?;
+
+        self.check_downloaded(hash, &response.body)?;
+
+        // Other threads can download the same object at once, so each
+        // writes to its own temporary file, and the object only appears
+        // under its hash once all of it is there.
+        let path = self.local.hash_to_path(hash);
+
+        let temp = path.with_extension(format!("{:016x}.tmp", random::<u64>()));
+
+        unwrap!(
+            fs::write(&temp, &response.body),
+            "failed to write object to: {}", temp.display()
+        );
+
+        if let Err(e) = fs::rename(&temp, &path) {
+            let _ = fs::remove_file(&temp);
+
+            bail!("failed to move object to {}: {e}", path.display());
+        }
+
+        Ok(())
+    }
+
+    /// Check that a downloaded object is the one stored under `hash`,
+    /// so nothing in the bucket can stand in for another object.
+    fn check_downloaded(&self, hash: ObjectHash, raw: &[u8]) -> Result<()> {
+        if let Ok(snapshot) = rmp_serde::from_slice::<Snapshot>(raw) {
+            if snapshot.hash != hash || !snapshot.is_valid() {
+                bail!("object {hash} in S3 is a different or unsigned snapshot");
+            }
+
+            return Ok(());
+        }
+
+        let content: Content = rmp_serde::from_slice(raw).map_err(|e| CorruptObject {
+            hash,
+            reason: e.to_string()
+        })?;
+
+        let actual = match &content {
+            // The large file itself is checked when it is fetched.
+            Content::Pointer(pointer) => Some(pointer.hash),
+            _ => self.resolve_checked(&content)?.map(hash_raw_bytes)
+        };
+
+        if let Some(actual) = actual
+            && actual != hash
+        {
+            bail!("object {hash} in S3 has the hash {actual}");
+        }
+
+        Ok(())
+    }
+
+    /// Get the text of a content object, whose originals are downloaded
+    /// and checked first, or `None` if it comes from a large file.
+    fn resolve_checked(&self, content: &Content) -> Result<Option<String>> {
+        let text = match content {
+            Content::Literal(compressed) => String::from_utf8(decompress_data(compressed)?)?,
+
+            Content::Delta(delta) => {
+                let original = self.fetch_content_object(delta.original)?;
+
+                let Some(source) = self.resolve_checked(&original)? else {
+                    return Ok(None);
+                };
+
+                let resolved = unwrap!(
+                    xdelta3::decode(&delta.edit, source.as_bytes()),
+                    "failed to decode delta: {delta:?}"
+                );
+
+                String::from_utf8(resolved)?
+            }
+
+            Content::Pointer(_) => return Ok(None)
+        };
+
+        Ok(Some(text))
+    }
+
+    /// Upload an object from the local copy, in parts if it is large.
+    fn upload(&self, hash: ObjectHash) -> Result<()> {
+        let path = self.local.hash_to_path(hash);
+
+        let size = unwrap!(
+            fs::metadata(&path),
+            "failed to read metadata of: {}", path.display()
+        ).len();
+
+        if size > self.part_size {
+            return self.upload_in_parts(hash, &path);
+        }
+
+        self.request("PUT", &self.key(hash), &[], Some(&path), None)?
+            .check(&format!("save object {hash}"))?;
+
+        Ok(())
+    }
+
+    fn upload_in_parts(&self, hash: ObjectHash, path: &Path) -> Result<()> {
+        let key = self.key(hash);
+
+        let response = self.request("POST", &key, &[("uploads", "")], None, None)?
+            .check(&format!("start uploading object {hash}"))?;
+
+        let upload_id = unwrap!(
+            xml_values(&response.text(), "UploadId").into_iter().next(),
+            "S3 didn't say where to upload object {hash}"
+        );
+
+        let result = self.upload_parts(hash, path, &key, &upload_id);
+
+        if result.is_err() {
+            // Nothing can be done if this fails too, and the
+            // first error is the one that says what went wrong.
+            let _ = self.request("DELETE", &key, &[("uploadId", &upload_id)], None, None);
+        }
+
+        result
+    }
+
+    fn upload_parts(&self, hash: ObjectHash, path: &Path, key: &str, upload_id: &str) -> Result<()> {
+        let raw = unwrap!(
+            fs::read(path),
+            "failed to read bytes from: {}", path.display()
+        );
+
+        let part_path = self.cache_dir.join(format!("upload-{}", hash.full()));
+
+        let mut parts = String::new();
+
+        for (index, chunk) in raw.chunks(self.part_size as usize).enumerate() {
+            let number = (index + 1).to_string();
+
+            unwrap!(
+                fs::write(&part_path, chunk),
+                "failed to write part of object to: {}", part_path.display()
+            );
+
+            let response = self.request(
+                "PUT",
+                key,
+                &[("partNumber", &number), ("uploadId", upload_id)],
+                Some(&part_path),
+                None
+            );
+
+            let _ = fs::remove_file(&part_path);
+
+            let response = response?.check(&format!("upload part {number} of object {hash}"))?;
+
+            let etag = unwrap!(
+                response.header("etag"),
+                "S3 didn't send an ETag for part {number} of object {hash}"
+            );
+
+            parts += &format!("<Part><PartNumber>{number}</PartNumber><ETag>{}</ETag></Part>", escape(etag));
+        }
+
+        let body = format!("<CompleteMultipartUpload>{parts}</CompleteMultipartUpload>");
+
+        self.request("POST", key, &[("uploadId", upload_id)], None, Some(&body))?
+            .check(&format!("finish uploading object {hash}"))?;
+
+        Ok(())
+    }
+
+    /// Get every object in the bucket whose hash starts with `prefix`.
+    fn list(&self, prefix: &str) -> Result<Vec<ObjectHash>> {
+        let key_prefix = self.key_prefix();
+
+        let search = format!("{key_prefix}{prefix}");
+
+        let mut hashes = vec![];
+
+        let mut token: Option<String> = None;
+
+        loop {
+            let mut query = vec![];
+
+            if let Some(token) = &token {
+                query.push(("continuation-token", token.as_str()));
+            }
+
+            query.push(("list-type", "2"));
+            query.push(("prefix", &search));
+
+            let response = self.request("GET", "", &query, None, None)?
+                .check("list objects")?;
+
+            let text = response.text();
+
+            hashes.extend(
+                xml_values(&text, "Key")
+                    .iter()
+                    .filter_map(|key| key.strip_prefix(&key_prefix)?.parse::<ObjectHash>().ok())
+            );
+
+            token = xml_values(&text, "NextContinuationToken").into_iter().next();
+
+            if token.is_none() {
+                return Ok(hashes);
+            }
+        }
+    }
+
+    /// Keep an object that was just saved in the local copy, removing
+    /// it from there again if it couldn't be uploaded, so only objects
+    /// in the bucket are ever kept locally.
+    fn upload_saved(&self, hash: ObjectHash) -> Result<()> {
+        if let Err(e) = self.upload(hash) {
+            self.local.remove_object(hash)?;
+
+            return Err(e);
+        }
+
+        Ok(())
+    }
+}
+
+impl ObjectStore for S3Store {
+    fn contains(&self, hash: ObjectHash) -> bool {
+        self.local.contains(hash) || self.head(hash).is_ok_and(|size| size.is_some())
+    }
+
+    fn fetch_raw(&self, hash: ObjectHash) -> Result<Vec<u8>> {
+        self.download(hash)?;
+
+        self.local.fetch_raw(hash)
+    }
+
+    fn fetch_content_object(&self, content_hash: ObjectHash) -> Result<Content> {
+        self.download(content_hash)?;
+
+        self.local.fetch_content_object(content_hash)
+    }
+
+    fn fetch_snapshot(&self, snapshot_hash: ObjectHash) -> Result<Snapshot> {
+        self.download(snapshot_hash)?;
+
+        self.local.fetch_snapshot(snapshot_hash)
+    }
+
+    fn save_content_object(&self, object: Content, hash: ObjectHash) -> Result<()> {
+        // Objects in the local copy are always in the bucket too.
+        if self.local.contains(hash) || !guard().allow_quietly() {
+            return Ok(());
+        }
+
+        self.local.save_content_object(object, hash)?;
+
+        self.upload_saved(hash)
+    }
+
+    fn save_snapshot_object(&self, snapshot: &Snapshot) -> Result<()> {
+        if self.local.contains(snapshot.hash) || !guard().allow_quietly() {
+            return Ok(());
+        }
+
+        self.local.save_snapshot_object(snapshot)?;
+
+        self.upload_saved(snapshot.hash)
+    }
+
+    fn remove_object(&self, hash: ObjectHash) -> Result<bool> {
+        let stored = self.head(hash)?.is_some();
+
+        if stored {
+            self.request("DELETE", &self.key(hash), &[], None, None)?
+                .check(&format!("delete object {hash}"))?;
+        }
+
+        let cached = self.local.remove_object(hash)?;
+
+        Ok(stored || cached)
+    }
+
+    fn stored_objects(&self) -> Result<HashSet<ObjectHash>> {
+        Ok(self.list("")?.into_iter().collect())
+    }
+
+    fn find_prefix(&self, prefix: &str) -> Result<Vec<ObjectHash>> {
+        self.list(prefix)
+    }
+
+    fn stored_size(&self, hash: ObjectHash) -> Result<u64> {
+        if self.local.contains(hash) {
+            return self.local.stored_size(hash);
+        }
+
+        self.head(hash)?.ok_or_else(|| eyre!("object {hash} is not stored."))
+    }
+}
+
+/// Percent-encode part of a URL the way S3 signs it,
+/// leaving slashes alone if `keep_slashes` is set.
+fn encode(raw: &str, keep_slashes: bool) -> String {
+    let mut encoded = String::new();
+
+    for byte in raw.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => encoded.push(byte as char),
+            b'/' if keep_slashes => encoded.push('/'),
+            _ => encoded += &format!("%{byte:02X}")
+        }
+    }
+
+    encoded
+}
+
+/// Quote a value for a curl config file.
+fn quote(raw: &str) -> String {
+    format!("\"{}\"", raw.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn escape(raw: &str) -> String {
+    raw.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn unescape(raw: &str) -> String {
+    raw.replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&")
+}
+
+/// Get the text of every `<tag>` element in some XML from S3, which
+/// is all the parsing its responses need.
+fn xml_values(xml: &str, tag: &str) -> Vec<String> {
+    let open = format!("<{tag}>");
+    let close = format!("</{tag}>");
+
+    let mut values = vec![];
+
+    let mut rest = xml;
+
+    while let Some(start) = rest.find(&open) {
+        rest = &rest[start + open.len()..];
+
+        let Some(end) = rest.find(&close) else {
+            break;
+        };
+
+        values.push(unescape(&rest[..end]));
+
+        rest = &rest[end + close.len()..];
+    }
+
+    values
+}
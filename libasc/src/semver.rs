@@ -0,0 +1,225 @@
+//! Reading tag names as semantic versions, like `v1.4.2` or `2.0.0-rc.1`.
+//!
+//! Release tooling can use this to find the latest release, or the latest
+//! on each release line, and to work out what the next release is called.
+
+use std::{cmp::Ordering, collections::BTreeMap, fmt};
+
+/// A tag name read as a semantic version.
+///
+/// `v1` and `v1.2` are read as `v1.0.0` and `v1.2.0`. Build metadata
+/// after a `+` is kept, but doesn't change which version it is.
+#[derive(Clone, Debug, Eq)]
+pub struct Version {
+    /// What comes before the numbers, which is either `v` or nothing.
+    pub prefix: String,
+
+    pub major: u64,
+    pub minor: u64,
+    pub patch: u64,
+
+    /// What comes after a `-`, like `rc.1`.
+    pub pre_release: Option<String>,
+
+    /// What comes after a `+`.
+    pub build: Option<String>
+}
+
+/// Which part of a version to move up.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Bump {
+    Major,
+    Minor,
+    Patch
+}
+
+impl Version {
+    /// Read a tag name as a version, if it is one.
+    pub fn parse(name: &str) -> Option<Version> {
+        let (prefix, rest) = match name.strip_prefix(['v', 'V']) {
+            Some(rest) => (&name[..1], rest),
+            None => ("", name)
+        };
+
+        let (rest, build) = match rest.split_once('+') {
+            Some((rest, build)) => (rest, Some(build.to_string())),
+            None => (rest, None)
+        };
+
+        let (core, pre_release) = match rest.split_once('-') {
+            Some((core, pre)) if !pre.is_empty() => (core, Some(pre.to_string())),
+            Some(_) => return None,
+            None => (rest, None)
+        };
+
+        let numbers = core
+            .split('.')
+            .map(|part| part.parse().ok())
+            .collect::<Option<Vec<u64>>>()?;
+
+        if numbers.len() > 3 {
+            return None;
+        }
+
+        let number = |i: usize| numbers.get(i).copied().unwrap_or(0);
+
+        Some(Version {
+            prefix: prefix.to_string(),
+            major: number(0),
+            minor: number(1),
+            patch: number(2),
+            pre_release,
+            build
+        })
+    }
+
+    pub fn is_pre_release(&self) -> bool {
+        self.pre_release.is_some()
+    }
+
+    /// Get the release line of the version, which is its major and minor number.
+    pub fn line(&self) -> (u64, u64) {
+        (self.major, self.minor)
+    }
+
+    /// Get the version after this one, keeping its prefix.
+    ///
+    /// A pre-release moves up to the release it comes before if that is
+    /// enough, so the patch after `1.3.0-rc.1` is `1.3.0`, not `1.3.1`.
+    pub fn bump(&self, bump: Bump) -> Version {
+        let (major, minor, patch) = (self.major, self.minor, self.patch);
+
+        let releasing = self.is_pre_release();
+
+        let (major, minor, patch) = match bump {
+            Bump::Major if releasing && minor == 0 && patch == 0 => (major, 0, 0),
+            Bump::Major => (major + 1, 0, 0),
+
+            Bump::Minor if releasing && patch == 0 => (major, minor, 0),
+            Bump::Minor => (major, minor + 1, 0),
+
+            Bump::Patch if releasing => (major, minor, patch),
+            Bump::Patch => (major, minor, patch + 1)
+        };
+
+        Version {
+            prefix: self.prefix.clone(),
+            major,
+            minor,
+            patch,
+            pre_release: None,
+            build: None
+        }
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}.{}.{}", self.prefix, self.major, self.minor, self.patch)?;
+
+        if let Some(pre) = &self.pre_release {
+            write!(f, "-{pre}")?;
+        }
+
+        if let Some(build) = &self.build {
+            write!(f, "+{build}")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Compare pre-releases like `rc.2` and `rc.10` the way semver does: part
+/// by part, with numbers compared as numbers and before anything else.
+fn compare_pre_releases(a: &str, b: &str) -> Ordering {
+    let mut a_parts = a.split('.');
+    let mut b_parts = b.split('.');
+
+    loop {
+        let order = match (a_parts.next(), b_parts.next()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+
+            (Some(a), Some(b)) => match (a.parse::<u64>(), b.parse::<u64>()) {
+                (Ok(a), Ok(b)) => a.cmp(&b),
+                (Ok(_), Err(_)) => Ordering::Less,
+                (Err(_), Ok(_)) => Ordering::Greater,
+                (Err(_), Err(_)) => a.cmp(b)
+            }
+        };
+
+        if order != Ordering::Equal {
+            return order;
+        }
+    }
+}
+
+impl Ord for Version {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.major.cmp(&other.major)
+            .then(self.minor.cmp(&other.minor))
+            .then(self.patch.cmp(&other.patch))
+            .then_with(|| match (&self.pre_release, &other.pre_release) {
+                // A pre-release comes before the release itself.
+                (None, None) => Ordering::Equal,
+                (None, Some(_)) => Ordering::Greater,
+                (Some(_), None) => Ordering::Less,
+                (Some(a), Some(b)) => compare_pre_releases(a, b)
+            })
+    }
+}
+
+impl PartialOrd for Version {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Version {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+/// Read every name that is a version, leaving out pre-releases
+/// unless `pre_releases` is set.
+fn versions<'a>(names: impl IntoIterator<Item = &'a str>, pre_releases: bool) -> impl Iterator<Item = (&'a str, Version)> {
+    names
+        .into_iter()
+        .filter_map(|name| Version::parse(name).map(|version| (name, version)))
+        .filter(move |(_, version)| pre_releases || !version.is_pre_release())
+}
+
+/// Find the name of the latest version among `names`.
+pub fn latest<'a>(names: impl IntoIterator<Item = &'a str>, pre_releases: bool) -> Option<(&'a str, Version)> {
+    versions(names, pre_releases).max_by(|a, b| a.1.cmp(&b.1).then_with(|| a.0.cmp(b.0)))
+}
+
+/// Find the name of the latest version on each release line among `names`,
+/// by the major and minor numbers of the line.
+pub fn latest_per_line<'a>(names: impl IntoIterator<Item = &'a str>, pre_releases: bool) -> BTreeMap<(u64, u64), (&'a str, Version)> {
+    let mut lines: BTreeMap<(u64, u64), (&str, Version)> = BTreeMap::new();
+
+    for (name, version) in versions(names, pre_releases) {
+        match lines.get(&version.line()) {
+            Some((_, latest)) if *latest >= version => {}
+            _ => { lines.insert(version.line(), (name, version)); }
+        }
+    }
+
+    lines
+}
+
+/// Work out the name of the release after the latest among `names`, like
+/// `v1.5.0` for a minor bump after `v1.4.2`.
+///
+/// Pre-releases are counted, so a patch after `v2.0.0-rc.1` is `v2.0.0`.
+/// With no versions at all, this bumps `v0.0.0`.
+pub fn next_name<'a>(names: impl IntoIterator<Item = &'a str>, bump: Bump) -> String {
+    let current = latest(names, true)
+        .map(|(_, version)| version)
+        .unwrap_or_else(|| Version::parse("v0.0.0").unwrap());
+
+    current.bump(bump).to_string()
+}
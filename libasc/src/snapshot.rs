@@ -1,13 +1,17 @@
 use std::collections::{BTreeMap, HashSet};
 
 use chrono::{DateTime, Utc};
-use eyre::Result;
-use relative_path::RelativePathBuf;
+use eyre::{bail, Result};
+use relative_path::{RelativePath, RelativePathBuf};
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 
-use crate::{hash::{ObjectHash, RawObjectHash}, key::{PrivateKey, PublicKey, Signature}, unwrap};
+use crate::{hash::{ObjectHash, RawObjectHash}, key::{PublicKey, Signature}, mode::{FileEntry, FileMode}, unwrap};
 
+#[cfg(feature = "signing")]
+use crate::key::PrivateKey;
+
+#[cfg(feature = "fs")]
 #[allow(unused_imports, reason = "used for documentation.")]
 use super::repository::Repository;
 
@@ -30,7 +34,21 @@ pub struct Snapshot {
     pub files: BTreeMap<RelativePathBuf, ObjectHash>,
 
     pub parents: HashSet<ObjectHash>,
-    pub signature: Signature
+    pub signature: Signature,
+
+    /// The branch this snapshot was committed to, if any.
+    /// 
+    /// This is part of the hash, so it stays the same when the
+    /// branch is later moved, renamed or deleted.
+    #[serde(default)]
+    pub branch: Option<String>,
+
+    /// The paths in `files` that aren't regular files, and what they are.
+    /// 
+    /// Regular files are left out, so snapshots made before modes were
+    /// recorded are read with every file regular, and keep their hash.
+    #[serde(default)]
+    pub modes: BTreeMap<RelativePathBuf, FileMode>
 }
 
 fn hash_from_parts(
@@ -38,7 +56,9 @@ fn hash_from_parts(
     message: &str,
     timestamp: &DateTime<Utc>,
     files: &BTreeMap<RelativePathBuf, ObjectHash>,
-    parents: &HashSet<ObjectHash>
+    parents: &HashSet<ObjectHash>,
+    branch: Option<&str>,
+    modes: &BTreeMap<RelativePathBuf, FileMode>
 ) -> ObjectHash
 {
     let mut hasher = Sha256::new();
@@ -55,10 +75,34 @@ fn hash_from_parts(
         hasher.update(hash.as_bytes());
     }
 
+    // A `HashSet` is iterated in a different order each time
+    // it is loaded, so the parents are sorted first.
+    let mut parents: Vec<&ObjectHash> = parents.iter().collect();
+
+    parents.sort();
+
     for parent in parents {
         hasher.update(parent.as_bytes());
     }
 
+    // Snapshots from before branches were recorded
+    // have to keep the same hash.
+    if let Some(branch) = branch {
+        hasher.update(branch.as_bytes());
+    }
+
+    // Likewise for snapshots from before modes were recorded,
+    // which only have regular files.
+    if !modes.is_empty() {
+        hasher.update(b"modes");
+
+        for (path, mode) in modes {
+            hasher.update(path.as_str());
+
+            hasher.update([mode.as_byte()]);
+        }
+    }
+
     let raw_hash: RawObjectHash = hasher.finalize().into();
 
     raw_hash.into()
@@ -68,22 +112,64 @@ impl Snapshot {
     /// Create a new [`SignedSnapshot`].
     /// 
     /// Ensure that the [`PrivateKey`] here belongs to the snapshot's author.
+    #[cfg(feature = "signing")]
     pub fn new(
-        mut creator: PrivateKey,
+        creator: PrivateKey,
         message: String,
         timestamp: DateTime<Utc>,
         files: BTreeMap<RelativePathBuf, ObjectHash>,
         parents: HashSet<ObjectHash>
     ) -> Snapshot
+    {
+        Snapshot::new_on_branch(creator, message, timestamp, files, parents, None)
+    }
+
+    /// Create a new [`SignedSnapshot`] that records the branch it was committed to.
+    /// 
+    /// Ensure that the [`PrivateKey`] here belongs to the snapshot's author.
+    #[cfg(feature = "signing")]
+    pub fn new_on_branch(
+        creator: PrivateKey,
+        message: String,
+        timestamp: DateTime<Utc>,
+        files: BTreeMap<RelativePathBuf, ObjectHash>,
+        parents: HashSet<ObjectHash>,
+        branch: Option<String>
+    ) -> Snapshot
+    {
+        Snapshot::new_with_modes(creator, message, timestamp, files, BTreeMap::new(), parents, branch)
+    }
+
+    /// Create a new [`SignedSnapshot`] with files that aren't all regular files.
+    /// 
+    /// Only the paths in `modes` that aren't [`FileMode::Regular`] are kept.
+    /// Ensure that the [`PrivateKey`] here belongs to the snapshot's author.
+    #[cfg(feature = "signing")]
+    pub fn new_with_modes(
+        mut creator: PrivateKey,
+        message: String,
+        timestamp: DateTime<Utc>,
+        files: BTreeMap<RelativePathBuf, ObjectHash>,
+        modes: BTreeMap<RelativePathBuf, FileMode>,
+        parents: HashSet<ObjectHash>,
+        branch: Option<String>
+    ) -> Snapshot
     {
         let author = creator.public_key();
+
+        let modes = modes
+            .into_iter()
+            .filter(|(path, mode)| *mode != FileMode::Regular && files.contains_key(path))
+            .collect();
         
         let hash = hash_from_parts(
             author,
             &message,
             &timestamp,
             &files,
-            &parents
+            &parents,
+            branch.as_deref(),
+            &modes
         );
 
         let signature = creator.sign(hash.as_bytes());
@@ -95,32 +181,53 @@ impl Snapshot {
             timestamp,
             files,
             parents,
-            signature
+            signature,
+            branch,
+            modes
         }
     }
 
-    /// Rehash the [`Snapshot`] in case anything has changed.
-    pub fn rehash(&mut self) {
-        self.hash = hash_from_parts(
+    /// Work out what the hash of the [`Snapshot`] should be from its contents.
+    /// 
+    /// The hash is only set when a snapshot is made, so this differs
+    /// from [`Snapshot::hash`] if anything was changed afterwards.
+    pub fn expected_hash(&self) -> ObjectHash {
+        hash_from_parts(
             self.author,
             &self.message,
             &self.timestamp,
             &self.files,
-            &self.parents
-        );
+            &self.parents,
+            self.branch.as_deref(),
+            &self.modes
+        )
+    }
+
+    /// Get what kind of file a path is. Paths that aren't
+    /// in the snapshot are counted as regular files.
+    pub fn mode(&self, path: &RelativePath) -> FileMode {
+        self.modes.get(path).copied().unwrap_or_default()
+    }
+
+    /// Get the content and mode of a file in the snapshot.
+    pub fn entry(&self, path: &RelativePath) -> Option<FileEntry> {
+        let hash = *self.files.get(path)?;
+
+        Some(FileEntry { hash, mode: self.mode(path) })
+    }
+
+    /// Iterate through the files in the snapshot with their modes, in order of path.
+    pub fn entries(&self) -> impl Iterator<Item = (&RelativePathBuf, FileEntry)> {
+        self.files
+            .iter()
+            .map(|(path, &hash)| (path, FileEntry { hash, mode: self.mode(path) }))
     }
 
     /// Check if the snapshot is authentic.
     /// 
     /// This will return `false` if an error unrelated to verifying the signature arises.
     pub fn is_valid(&self) -> bool {
-        let hash = hash_from_parts(
-            self.author,
-            &self.message,
-            &self.timestamp,
-            &self.files,
-            &self.parents
-        );
+        let hash = self.expected_hash();
 
         if self.hash != hash {
             return false;
@@ -138,6 +245,39 @@ impl Snapshot {
             self.hash, self.signature.key()
         );
 
+        self.check_symlink_ancestors()
+    }
+
+    /// Check that no path is inside one of the snapshot's symbolic links,
+    /// since writing it would follow the link out of the working directory.
+    ///
+    /// Paths are compared ignoring case, so this also holds
+    /// where the working directory isn't case-sensitive.
+    pub fn check_symlink_ancestors(&self) -> Result<()> {
+        let links: HashSet<String> = self.modes
+            .iter()
+            .filter(|(_, mode)| **mode == FileMode::Symlink)
+            .map(|(path, _)| path.as_str().to_lowercase())
+            .collect();
+
+        if links.is_empty() {
+            return Ok(());
+        }
+
+        for path in self.files.keys() {
+            let mut ancestor = path.parent();
+
+            while let Some(dir) = ancestor
+                && !dir.as_str().is_empty()
+            {
+                if links.contains(&dir.as_str().to_lowercase()) {
+                    bail!("snapshot {} has {path:?} inside the symbolic link {dir:?}", self.hash);
+                }
+
+                ancestor = dir.parent();
+            }
+        }
+
         Ok(())
     }
 }
@@ -0,0 +1,210 @@
+//! An [`ObjectStore`] that keeps every object in one SQLite database,
+//! for repositories with so many small objects that a file for each
+//! wastes space and slows the filesystem down, which mostly happens on
+//! servers. A repository uses it when its config has:
+//!
+//! ```toml
+//! [content]
+//! store = "sqlite"
+//! ```
+//!
+//! Objects are stored exactly as they would be in their own files, so
+//! `asc plumbing move-objects` can move them between the two stores.
+
+use std::{collections::HashSet, path::Path, sync::{Arc, Mutex, MutexGuard}};
+
+use eyre::{eyre, Result};
+use rusqlite::{params, Connection, OptionalExtension};
+
+use crate::{blobs::ObjectStore, cache::ObjectCache, content::Content, guard::guard, hash::ObjectHash, repository::CorruptObject, snapshot::Snapshot, stats, unwrap};
+
+/// Objects kept in a SQLite database, along with the repository's object cache.
+#[derive(Clone)]
+pub struct SqliteStore {
+    connection: Arc<Mutex<Connection>>,
+    cache: Arc<ObjectCache>
+}
+
+impl SqliteStore {
+    /// Open the database at `path`, creating it if it doesn't exist.
+    pub fn open(path: impl AsRef<Path>, cache: Arc<ObjectCache>) -> Result<SqliteStore> {
+        let path = path.as_ref();
+
+        let connection = unwrap!(
+            Connection::open(path),
+            "failed to open object database: {}", path.display()
+        );
+
+        // Readers don't block the writer, and the other way round.
+        unwrap!(
+            connection.pragma_update(None, "journal_mode", "WAL"),
+            "failed to set up object database: {}", path.display()
+        );
+
+        unwrap!(
+            connection.execute(
+                "CREATE TABLE IF NOT EXISTS objects (hash TEXT PRIMARY KEY, data BLOB NOT NULL) WITHOUT ROWID",
+                []
+            ),
+            "failed to set up object database: {}", path.display()
+        );
+
+        Ok(SqliteStore {
+            connection: Arc::new(Mutex::new(connection)),
+            cache
+        })
+    }
+
+    fn connection(&self) -> MutexGuard<'_, Connection> {
+        self.connection
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Save an object's bytes, unless one is already stored under its hash.
+    fn insert(&self, hash: ObjectHash, data: &[u8]) -> Result<()> {
+        unwrap!(
+            self.connection().execute(
+                "INSERT OR IGNORE INTO objects (hash, data) VALUES (?1, ?2)",
+                params![hash.full(), data]
+            ),
+            "failed to save object {hash}"
+        );
+
+        Ok(())
+    }
+}
+
+impl ObjectStore for SqliteStore {
+    fn contains(&self, hash: ObjectHash) -> bool {
+        self.connection()
+            .query_row("SELECT 1 FROM objects WHERE hash = ?1", [hash.full()], |_| Ok(()))
+            .optional()
+            .is_ok_and(|found| found.is_some())
+    }
+
+    fn fetch_raw(&self, hash: ObjectHash) -> Result<Vec<u8>> {
+        let data = unwrap!(
+            self.connection()
+                .query_row("SELECT data FROM objects WHERE hash = ?1", [hash.full()], |row| row.get(0))
+                .optional(),
+            "failed to read object {hash}"
+        );
+
+        data.ok_or_else(|| eyre!("object {hash} is not stored."))
+    }
+
+    fn fetch_content_object(&self, content_hash: ObjectHash) -> Result<Content> {
+        if let Some(content) = self.cache.get_content(content_hash) {
+            return Ok(content);
+        }
+
+        let raw = self.fetch_raw(content_hash)?;
+
+        let content: Content = rmp_serde::from_slice(&raw).map_err(|e| CorruptObject {
+            hash: content_hash,
+            reason: e.to_string()
+        })?;
+
+        stats::record_read();
+
+        self.cache.insert_content(content_hash, content.clone());
+
+        Ok(content)
+    }
+
+    fn fetch_snapshot(&self, snapshot_hash: ObjectHash) -> Result<Snapshot> {
+        if let Some(snapshot) = self.cache.get_snapshot(snapshot_hash) {
+            return Ok(snapshot);
+        }
+
+        let raw = self.fetch_raw(snapshot_hash)?;
+
+        let snapshot: Snapshot = rmp_serde::from_slice(&raw).map_err(|e| CorruptObject {
+            hash: snapshot_hash,
+            reason: e.to_string()
+        })?;
+
+        stats::record_read();
+
+        if let Err(e) = snapshot.verify() {
+            return Err(CorruptObject { hash: snapshot_hash, reason: e.to_string() }.into());
+        }
+
+        self.cache.insert_snapshot(snapshot.clone());
+
+        Ok(snapshot)
+    }
+
+    fn save_content_object(&self, object: Content, hash: ObjectHash) -> Result<()> {
+        if !guard().allow_quietly() {
+            return Ok(());
+        }
+
+        stats::record_write();
+
+        self.insert(hash, &rmp_serde::to_vec(&object)?)
+    }
+
+    fn save_snapshot_object(&self, snapshot: &Snapshot) -> Result<()> {
+        if !guard().allow_quietly() {
+            return Ok(());
+        }
+
+        stats::record_write();
+
+        self.insert(snapshot.hash, &rmp_serde::to_vec(snapshot)?)
+    }
+
+    fn remove_object(&self, hash: ObjectHash) -> Result<bool> {
+        self.cache.invalidate(hash);
+
+        let removed = unwrap!(
+            self.connection().execute("DELETE FROM objects WHERE hash = ?1", [hash.full()]),
+            "failed to delete object {hash}"
+        );
+
+        Ok(removed > 0)
+    }
+
+    fn stored_objects(&self) -> Result<HashSet<ObjectHash>> {
+        let connection = self.connection();
+
+        let mut statement = connection.prepare("SELECT hash FROM objects")?;
+
+        let hashes = statement
+            .query_map([], |row| row.get::<_, String>(0))?
+            .filter_map(|hash| hash.ok()?.parse().ok())
+            .collect();
+
+        Ok(hashes)
+    }
+
+    fn find_prefix(&self, prefix: &str) -> Result<Vec<ObjectHash>> {
+        let connection = self.connection();
+
+        // The prefix is hex, so there's nothing in it for GLOB to expand.
+        let mut statement = connection.prepare("SELECT hash FROM objects WHERE hash GLOB ?1")?;
+
+        let hashes = statement
+            .query_map([format!("{prefix}*")], |row| row.get::<_, String>(0))?
+            .filter_map(|hash| hash.ok()?.parse().ok())
+            .collect();
+
+        Ok(hashes)
+    }
+
+    fn stored_size(&self, hash: ObjectHash) -> Result<u64> {
+        let size: Option<i64> = unwrap!(
+            self.connection()
+                .query_row("SELECT length(data) FROM objects WHERE hash = ?1", [hash.full()], |row| row.get(0))
+                .optional(),
+            "failed to read object {hash}"
+        );
+
+        match size {
+            Some(size) => Ok(size as u64),
+            None => Err(eyre!("object {hash} is not stored."))
+        }
+    }
+}
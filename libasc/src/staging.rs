@@ -0,0 +1,156 @@
+use std::collections::BTreeMap;
+
+use relative_path::{RelativePath, RelativePathBuf};
+use serde::{Deserialize, Serialize};
+
+use crate::{hash::ObjectHash, mode::FileMode, paths::{canonical, canonical_files}, snapshot::Snapshot};
+
+/// The files that will make up the next snapshot, and the
+/// content each one had when it was staged.
+/// 
+/// Editing a file after staging it doesn't change what is
+/// committed until the file is staged again.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct StagingArea {
+    inner: BTreeMap<RelativePathBuf, ObjectHash>,
+
+    /// The staged paths that aren't regular files.
+    #[serde(default)]
+    modes: BTreeMap<RelativePathBuf, FileMode>
+}
+
+impl StagingArea {
+    /// Create an empty [`StagingArea`].
+    pub fn new() -> StagingArea {
+        StagingArea::default()
+    }
+
+    /// Create a [`StagingArea`] holding the files of a snapshot.
+    pub fn from_files(files: BTreeMap<RelativePathBuf, ObjectHash>) -> StagingArea {
+        StagingArea {
+            inner: canonical_files(&files),
+            modes: BTreeMap::new()
+        }
+    }
+
+    /// Create a [`StagingArea`] holding the files of a snapshot, with their modes.
+    pub fn from_snapshot(snapshot: &Snapshot) -> StagingArea {
+        let mut staging = StagingArea::from_files(snapshot.files.clone());
+
+        staging.set_modes(&snapshot.modes);
+
+        staging
+    }
+
+    /// Stage content for a path, returning the content that was staged before, if any.
+    pub fn stage(&mut self, path: RelativePathBuf, hash: ObjectHash) -> Option<ObjectHash> {
+        self.inner.insert(canonical(path), hash)
+    }
+
+    /// Stop tracking a path, returning the content that was staged for it.
+    pub fn unstage(&mut self, path: impl AsRef<RelativePath>) -> Option<ObjectHash> {
+        let path = canonical(path);
+
+        self.modes.remove(&path);
+
+        self.inner.remove(&path)
+    }
+
+    /// Move the content staged for a path to another path.
+    /// 
+    /// Returns `false` if nothing is staged for `old`.
+    pub fn rename(&mut self, old: impl AsRef<RelativePath>, new: RelativePathBuf) -> bool {
+        let mode = self.mode(&old);
+
+        let Some(hash) = self.unstage(old) else {
+            return false;
+        };
+
+        self.set_mode(&new, mode);
+
+        self.stage(new, hash);
+
+        true
+    }
+
+    /// Get the content staged for a path.
+    pub fn get(&self, path: impl AsRef<RelativePath>) -> Option<ObjectHash> {
+        self.inner.get(&canonical(path)).copied()
+    }
+
+    /// Get what kind of file a path was staged as.
+    pub fn mode(&self, path: impl AsRef<RelativePath>) -> FileMode {
+        self.modes.get(&canonical(path)).copied().unwrap_or_default()
+    }
+
+    /// Set what kind of file a path is, to be committed with its content.
+    pub fn set_mode(&mut self, path: impl AsRef<RelativePath>, mode: FileMode) {
+        let path = canonical(path);
+
+        if mode == FileMode::Regular {
+            self.modes.remove(&path);
+        }
+        else {
+            self.modes.insert(path, mode);
+        }
+    }
+
+    /// Set the modes of several paths, like [`StagingArea::set_mode`].
+    pub fn set_modes(&mut self, modes: &BTreeMap<RelativePathBuf, FileMode>) {
+        for (path, &mode) in modes {
+            self.set_mode(path, mode);
+        }
+    }
+
+    /// Get the staged paths that aren't regular files, in the
+    /// form a [`Snapshot`](crate::snapshot::Snapshot) stores them.
+    pub fn modes(&self) -> &BTreeMap<RelativePathBuf, FileMode> {
+        &self.modes
+    }
+
+    /// Check if a path is tracked.
+    pub fn contains(&self, path: impl AsRef<RelativePath>) -> bool {
+        self.inner.contains_key(&canonical(path))
+    }
+
+    /// Iterate through the tracked paths in order.
+    pub fn paths(&self) -> impl Iterator<Item = &RelativePathBuf> {
+        self.inner.keys()
+    }
+
+    /// Iterate through the tracked paths and their staged content, in order of path.
+    pub fn iter(&self) -> impl Iterator<Item = (&RelativePathBuf, ObjectHash)> {
+        self.inner.iter().map(|(path, &hash)| (path, hash))
+    }
+
+    /// Get the staged files in the form a [`Snapshot`](crate::snapshot::Snapshot) stores them.
+    pub fn files(&self) -> &BTreeMap<RelativePathBuf, ObjectHash> {
+        &self.inner
+    }
+
+    /// Keep only the paths matching a predicate.
+    pub fn retain(&mut self, mut f: impl FnMut(&RelativePathBuf, ObjectHash) -> bool) {
+        self.inner.retain(|path, &mut hash| f(path, hash));
+
+        let inner = &self.inner;
+
+        self.modes.retain(|path, _| inner.contains_key(path));
+    }
+
+    /// Stop tracking every path.
+    pub fn clear(&mut self) {
+        self.inner.clear();
+
+        self.modes.clear();
+    }
+
+    /// Get the number of tracked paths.
+    pub fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    /// Check if no paths are tracked.
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+}
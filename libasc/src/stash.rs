@@ -1,15 +1,24 @@
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, TimeDelta, Utc};
 use relative_path::RelativePathBuf;
 use serde::{Deserialize, Serialize};
 
-use crate::hash::ObjectHash;
+use crate::{hash::ObjectHash, mode::FileMode};
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct State {
     pub message: String,
-    pub files: BTreeMap<RelativePathBuf, ObjectHash>
+    pub files: BTreeMap<RelativePathBuf, ObjectHash>,
+
+    /// The files that aren't regular files, like in [`Snapshot::modes`](crate::snapshot::Snapshot::modes).
+    #[serde(default)]
+    pub modes: BTreeMap<RelativePathBuf, FileMode>,
+
+    /// The files in `files` that weren't tracked when they were stashed,
+    /// and so shouldn't be tracked when the stash is applied.
+    #[serde(default)]
+    pub untracked: BTreeSet<RelativePathBuf>
 }
 
 /// Represents a snapshot independent of the history.
@@ -19,9 +28,17 @@ pub struct State {
 pub struct Entry {
     pub state: State,
     pub basis: ObjectHash,
-    pub timestamp: DateTime<Utc>
+    pub timestamp: DateTime<Utc>,
+
+    /// Whether this was made automatically, to keep unsaved
+    /// changes before the working directory was replaced.
+    #[serde(default)]
+    pub backup: bool
 }
 
+/// How long backups are kept in the stash before `asc clean` removes them.
+pub const BACKUP_LIFETIME: TimeDelta = TimeDelta::days(14);
+
 #[derive(Clone, Debug, Default, Deserialize, Serialize)]
 pub struct Stash {
     entries: HashMap<usize, Entry>,
@@ -34,11 +51,12 @@ impl Stash {
         Self::default()
     }
 
-    pub fn add_state(&mut self, state: State, basis: ObjectHash) -> usize {
+    fn add_entry(&mut self, state: State, basis: ObjectHash, backup: bool) -> usize {
         let entry = Entry {
             state,
             basis,
-            timestamp: Utc::now()
+            timestamp: Utc::now(),
+            backup
         };
 
         self.entries.insert(self.count, entry);
@@ -48,6 +66,25 @@ impl Stash {
         self.count - 1
     }
 
+    pub fn add_state(&mut self, state: State, basis: ObjectHash) -> usize {
+        self.add_entry(state, basis, false)
+    }
+
+    /// Add a backup of the working directory, which is
+    /// removed once it is older than [`BACKUP_LIFETIME`].
+    pub fn add_backup(&mut self, state: State, basis: ObjectHash) -> usize {
+        self.add_entry(state, basis, true)
+    }
+
+    /// Remove backups made before `cutoff`, returning how many were removed.
+    pub fn prune_backups(&mut self, cutoff: DateTime<Utc>) -> usize {
+        let before = self.entries.len();
+
+        self.entries.retain(|_, entry| !entry.backup || entry.timestamp > cutoff);
+
+        before - self.entries.len()
+    }
+
     pub fn get_state(&self, id: usize) -> Option<&Entry> {
         self.entries.get(&id)
     }
@@ -0,0 +1,82 @@
+//! Counts of the work done by this process, so
+//! that commands can report what they cost.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// The work done by this process so far.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Stats {
+    /// Snapshots and content objects read from the blob store.
+    pub objects_read: u64,
+
+    /// Snapshots and content objects written to the blob store.
+    pub objects_written: u64,
+
+    /// Bytes sent to remotes.
+    pub bytes_sent: u64,
+
+    /// Bytes received from remotes.
+    pub bytes_received: u64,
+
+    /// Lookups that were answered by a cache.
+    pub cache_hits: u64,
+
+    /// Lookups that a cache couldn't answer.
+    pub cache_misses: u64
+}
+
+impl Stats {
+    /// Get the share of cache lookups that were hits,
+    /// or `None` if no lookups were made.
+    pub fn cache_hit_rate(&self) -> Option<f64> {
+        let lookups = self.cache_hits + self.cache_misses;
+
+        (lookups > 0).then(|| self.cache_hits as f64 / lookups as f64)
+    }
+}
+
+static OBJECTS_READ: AtomicU64 = AtomicU64::new(0);
+static OBJECTS_WRITTEN: AtomicU64 = AtomicU64::new(0);
+static BYTES_SENT: AtomicU64 = AtomicU64::new(0);
+static BYTES_RECEIVED: AtomicU64 = AtomicU64::new(0);
+static CACHE_HITS: AtomicU64 = AtomicU64::new(0);
+static CACHE_MISSES: AtomicU64 = AtomicU64::new(0);
+
+pub(crate) fn record_read() {
+    OBJECTS_READ.fetch_add(1, Ordering::Relaxed);
+}
+
+pub(crate) fn record_write() {
+    OBJECTS_WRITTEN.fetch_add(1, Ordering::Relaxed);
+}
+
+#[cfg(feature = "sync")]
+pub(crate) fn record_sent(bytes: usize) {
+    BYTES_SENT.fetch_add(bytes as u64, Ordering::Relaxed);
+}
+
+#[cfg(feature = "sync")]
+pub(crate) fn record_received(bytes: usize) {
+    BYTES_RECEIVED.fetch_add(bytes as u64, Ordering::Relaxed);
+}
+
+pub(crate) fn record_lookup(hit: bool) {
+    if hit {
+        CACHE_HITS.fetch_add(1, Ordering::Relaxed);
+    }
+    else {
+        CACHE_MISSES.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Get the work done by this process so far.
+pub fn totals() -> Stats {
+    Stats {
+        objects_read: OBJECTS_READ.load(Ordering::Relaxed),
+        objects_written: OBJECTS_WRITTEN.load(Ordering::Relaxed),
+        bytes_sent: BYTES_SENT.load(Ordering::Relaxed),
+        bytes_received: BYTES_RECEIVED.load(Ordering::Relaxed),
+        cache_hits: CACHE_HITS.load(Ordering::Relaxed),
+        cache_misses: CACHE_MISSES.load(Ordering::Relaxed)
+    }
+}
@@ -0,0 +1,146 @@
+//! Numbers describing what a repository stores and how well it is
+//! compressed, for `asc stats` and for servers to report about their repositories.
+
+use std::collections::{BTreeMap, HashMap};
+
+use eyre::Result;
+use relative_path::RelativePathBuf;
+use serde::{Serialize, Serializer};
+
+use crate::{blobs::ObjectStore, content::Content, hash::ObjectHash, repository::Repository};
+
+/// What is stored in a repository, found by [`collect`].
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct StorageStats {
+    /// Snapshots in the history.
+    pub snapshots: usize,
+
+    /// Content stored whole.
+    pub literals: usize,
+
+    /// Content stored as a delta of other content.
+    pub deltas: usize,
+
+    /// Large files, stored outside the blob store.
+    pub large_files: usize,
+
+    /// How many times snapshots refer to a file. Since identical content
+    /// is only stored once, this is usually far more than the content objects.
+    pub file_references: usize,
+
+    /// The space content takes up in the object store, in bytes.
+    pub stored_size: u64,
+
+    /// The size content would be if every object was stored whole and
+    /// uncompressed, in bytes. Large files count with their full size.
+    pub logical_size: u64,
+
+    /// How many deltas have each chain depth.
+    pub delta_depths: BTreeMap<usize, usize>,
+
+    /// The largest content objects, largest first.
+    pub largest_files: Vec<FileSize>,
+
+    /// How many snapshots each branch has in its history.
+    pub branches: BTreeMap<String, usize>
+}
+
+/// A content object, with a path a snapshot stores it at.
+#[derive(Clone, Debug, Serialize)]
+pub struct FileSize {
+    pub path: RelativePathBuf,
+
+    #[serde(serialize_with = "serialize_hex")]
+    pub hash: ObjectHash,
+    pub size: u64
+}
+
+fn serialize_hex<S: Serializer>(hash: &ObjectHash, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&hash.full())
+}
+
+/// Go over every object in the repository and count what is there.
+///
+/// `largest` is how many of the largest files to list. This resolves
+/// every content object, so it can take a while on large repositories.
+pub fn collect(repo: &Repository, largest: usize) -> Result<StorageStats> {
+    let mut stats = StorageStats {
+        snapshots: repo.history.iter_hashes().count(),
+        ..Default::default()
+    };
+
+    let mut paths: HashMap<ObjectHash, RelativePathBuf> = HashMap::new();
+
+    for hash in repo.history.iter_hashes() {
+        let snapshot = repo.fetch_snapshot(hash)?;
+
+        stats.file_references += snapshot.files.len();
+
+        for (path, content) in snapshot.files {
+            paths.entry(content).or_insert(path);
+        }
+    }
+
+    let mut sizes = vec![];
+
+    let objects = repo.objects();
+
+    for hash in objects.stored_objects()? {
+        if repo.history.contains(hash) {
+            continue;
+        }
+
+        // Anything unreadable is left for `asc fsck` to report.
+        let Ok(content) = repo.fetch_content_object(hash) else {
+            continue;
+        };
+
+        stats.stored_size += objects.stored_size(hash)?;
+
+        let size = match &content {
+            Content::Literal(_) => {
+                stats.literals += 1;
+
+                content.resolve(repo)?.len() as u64
+            },
+
+            Content::Delta(_) => {
+                stats.deltas += 1;
+
+                *stats.delta_depths.entry(repo.delta_depth(hash)?).or_default() += 1;
+
+                content.resolve(repo)?.len() as u64
+            },
+
+            Content::Pointer(pointer) => {
+                stats.large_files += 1;
+
+                pointer.size
+            }
+        };
+
+        stats.logical_size += size;
+
+        if let Some(path) = paths.get(&hash) {
+            sizes.push(FileSize {
+                path: path.clone(),
+                hash,
+                size
+            });
+        }
+    }
+
+    sizes.sort_by(|a, b| b.size.cmp(&a.size).then(a.path.cmp(&b.path)));
+
+    sizes.truncate(largest);
+
+    stats.largest_files = sizes;
+
+    for (name, tip) in repo.refs.branches().iter() {
+        let history = repo.history.reachable([tip], |_| false)?;
+
+        stats.branches.insert(name.to_string(), history.len());
+    }
+
+    Ok(stats)
+}
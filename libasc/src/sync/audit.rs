@@ -0,0 +1,213 @@
+//! The server's record of who synced with a repository, and what they did.
+//!
+//! Every login, pull, push, clone and mirror the repository serves, and every
+//! branch a push or mirror changes or is refused, is added to the end of
+//! `.asc/audit.log` as a line of JSON, like:
+//!
+//! ```json
+//! {"when":"2025-06-01T12:00:00Z","user":"04AB...","event":"branch_update","branch":"main","old_tip":"1a2b...","new_tip":"3c4d...","accepted":true,"reason":null}
+//! ```
+//!
+//! Nothing in the log is ever rewritten, so it can be followed with `tail -f`.
+
+use std::{fmt, fs::{self, OpenOptions}, io::Write, path::PathBuf};
+
+use chrono::{DateTime, Utc};
+use eyre::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::{guard::guard, hash::ObjectHash, key::PublicKey, repository::Repository, unwrap};
+
+/// Something a client did, or tried to do.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum SyncEvent {
+    /// The client tried to log in, and was let in if `accepted` is set.
+    Login {
+        accepted: bool,
+        reason: Option<String>
+    },
+
+    Pull,
+    Push,
+    Clone,
+    Mirror,
+
+    /// A push or mirror created, moved or deleted a branch, or tried to.
+    BranchUpdate {
+        branch: String,
+        old_tip: Option<String>,
+        new_tip: Option<String>,
+        accepted: bool,
+
+        /// Why the update was refused, if it was.
+        reason: Option<String>
+    }
+}
+
+impl SyncEvent {
+    /// Describe a branch being created, moved or deleted.
+    pub fn branch_update(branch: &str, old_tip: Option<ObjectHash>, new_tip: Option<ObjectHash>, refused: Option<&str>) -> SyncEvent {
+        SyncEvent::BranchUpdate {
+            branch: branch.to_string(),
+            old_tip: old_tip.map(|hash| hash.full()),
+            new_tip: new_tip.map(|hash| hash.full()),
+            accepted: refused.is_none(),
+            reason: refused.map(String::from)
+        }
+    }
+
+    /// Get the name the event is stored under, like `login` or `branch_update`.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            SyncEvent::Login { .. } => "login",
+            SyncEvent::Pull => "pull",
+            SyncEvent::Push => "push",
+            SyncEvent::Clone => "clone",
+            SyncEvent::Mirror => "mirror",
+            SyncEvent::BranchUpdate { .. } => "branch_update"
+        }
+    }
+}
+
+/// A line of the audit log.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SyncRecord {
+    pub when: DateTime<Utc>,
+
+    /// The public key of the user, as hex, if the client got far enough to give one.
+    pub user: Option<String>,
+
+    #[serde(flatten)]
+    pub event: SyncEvent
+}
+
+impl fmt::Display for SyncRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let user = self.user.as_deref().map_or("<unknown user>", |key| &key[..key.len().min(16)]);
+
+        write!(f, "{} {user} ", self.when.format("%Y-%m-%d %H:%M:%S"))?;
+
+        let short = |hash: &Option<String>| hash.as_deref().map_or("none", |hash| &hash[..hash.len().min(10)]).to_string();
+
+        match &self.event {
+            SyncEvent::Login { accepted: true, .. } => write!(f, "logged in"),
+            SyncEvent::Login { accepted: false, reason } => write!(f, "failed to log in: {}", reason.as_deref().unwrap_or("unknown reason")),
+
+            SyncEvent::BranchUpdate { branch, old_tip, new_tip, accepted, reason } => {
+                write!(f, "{branch} {} -> {}", short(old_tip), short(new_tip))?;
+
+                if !accepted {
+                    write!(f, " (refused: {})", reason.as_deref().unwrap_or("unknown reason"))?;
+                }
+
+                Ok(())
+            }
+
+            event => write!(f, "{}", event.kind())
+        }
+    }
+}
+
+/// Which records of the audit log to show.
+#[derive(Clone, Debug, Default)]
+pub struct AuditFilter {
+    /// Only records for this user's public key.
+    pub user: Option<PublicKey>,
+
+    /// Only records from this time onwards.
+    pub since: Option<DateTime<Utc>>,
+
+    /// Only events of this kind, like `push`. See [`SyncEvent::kind`].
+    pub kind: Option<String>
+}
+
+impl AuditFilter {
+    pub fn matches(&self, record: &SyncRecord) -> bool {
+        if let Some(user) = &self.user {
+            let key = user.to_string();
+
+            if !record.user.as_deref().is_some_and(|recorded| recorded.eq_ignore_ascii_case(&key)) {
+                return false;
+            }
+        }
+
+        if self.since.is_some_and(|since| record.when < since) {
+            return false;
+        }
+
+        if self.kind.as_deref().is_some_and(|kind| record.event.kind() != kind) {
+            return false;
+        }
+
+        true
+    }
+}
+
+fn log_path(repo: &Repository) -> PathBuf {
+    repo.main_dir().join("audit.log")
+}
+
+/// Add what `user` did to the end of the audit log.
+pub fn record(repo: &Repository, user: Option<&PublicKey>, event: SyncEvent) -> Result<()> {
+    if !guard().allow_quietly() {
+        return Ok(());
+    }
+
+    let record = SyncRecord {
+        when: Utc::now(),
+        user: user.map(PublicKey::to_string),
+        event
+    };
+
+    let mut line = serde_json::to_vec(&record)?;
+
+    line.push(b'\n');
+
+    let path = log_path(repo);
+
+    let mut file = unwrap!(
+        OpenOptions::new().create(true).append(true).open(&path),
+        "failed to open the audit log at {}", path.display()
+    );
+
+    // One write per line, so lines from sessions
+    // running side by side don't get mixed up.
+    unwrap!(
+        file.write_all(&line),
+        "failed to write to the audit log at {}", path.display()
+    );
+
+    Ok(())
+}
+
+/// Read the audit log, oldest first.
+pub fn read_log(repo: &Repository) -> Result<Vec<SyncRecord>> {
+    let path = log_path(repo);
+
+    if !path.exists() {
+        return Ok(vec![]);
+    }
+
+    let raw = unwrap!(
+        fs::read_to_string(&path),
+        "failed to read the audit log at {}", path.display()
+    );
+
+    let mut records = vec![];
+
+    for (i, line) in raw.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let record = unwrap!(
+            serde_json::from_str(line),
+            "line {} of the audit log at {} is invalid", i + 1, path.display()
+        );
+
+        records.push(record);
+    }
+
+    Ok(records)
+}
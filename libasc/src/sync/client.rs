@@ -2,11 +2,9 @@ use std::{io, path::Path, process::Stdio, sync::Arc};
 
 use async_trait::async_trait;
 use eyre::Result;
-use tokio::{process::Command, sync::Mutex};
+use tokio::{process::Command, sync::RwLock};
 
-use crate::{key::PrivateKey, repository::Repository, sync::{clone::handle_clone_as_client, pull::{handle_pull_as_client, PullResult}, push::{handle_push_as_client, PushResult}, remote::{FileRemote, Remote, SshRemote}, server::{handle_server, Method}, stream::{local_duplex, ChildProcessStream, LocalStream, Stream}}};
-
-type Repo = Arc<Mutex<Repository>>;
+use crate::{config::{Config, SyncConfig}, hash::ObjectHash, key::PrivateKey, repository::Repository, stats, sync::{clone::{CloneOptions, CloneResult, handle_clone_as_client}, init::handle_init_as_client, lfs::{handle_lfs_fetch_as_client, handle_lfs_push_as_client, LfsPushResult}, mirror::{handle_mirror_as_client, MirrorOptions, MirrorReport}, proxy::{self, PROXY_VAR}, pull::{handle_pull_as_client, PullResult}, push::{handle_push_as_client, PushResult}, remote::{FileRemote, Remote, SshRemote}, server::{handle_server, handle_server_without_repo, Method}, stream::{local_duplex, negotiate_as_client, ChildProcessStream, Framing, LocalStream, Stream}, utils::Repo, whoami::{handle_whoami_as_client, Identity}}};
 
 enum InnerConnection {
     Ssh(ChildProcessStream),
@@ -15,6 +13,7 @@ enum InnerConnection {
 
 pub struct Connection {
     inner: InnerConnection,
+    framing: Framing,
     read_bytes: usize,
     written_bytes: usize
 }
@@ -23,6 +22,8 @@ pub struct Connection {
 impl Stream for Connection {
     async fn raw_read(&mut self, n: usize) -> io::Result<Vec<u8>> {
         self.read_bytes += n;
+
+        stats::record_received(n);
         
         match &mut self.inner {
             InnerConnection::Ssh(stream) => stream.raw_read(n).await,
@@ -33,12 +34,18 @@ impl Stream for Connection {
     async fn raw_write(&mut self, bytes: &[u8]) -> io::Result<()> {
         self.written_bytes += bytes.len();
 
+        stats::record_sent(bytes.len());
+
         match &mut self.inner {
             InnerConnection::Ssh(stream) => stream.raw_write(bytes).await,
             InnerConnection::File(stream) => stream.raw_write(bytes).await
         }
     }
 
+    fn framing(&mut self) -> &mut Framing {
+        &mut self.framing
+    }
+
     async fn close(&mut self) -> io::Result<()> {
         match &mut self.inner {
             InnerConnection::Ssh(stream) => stream.close().await,
@@ -53,12 +60,25 @@ pub struct Client {
 }
 
 impl Client {
-    async fn connect_ssh(remote: SshRemote) -> Result<Client> {
+    async fn connect_ssh(remote: SshRemote, config: &SyncConfig) -> Result<Client> {
         let address = remote.to_string();
 
+        let proxy = proxy::proxy_for(config, remote.host())?;
+
         let mut ssh = {
             let mut proc = Command::new("ssh");
 
+            // ssh can't use proxies, or try addresses side by side,
+            // so it is given asc to make the connection for it.
+            if proxy.is_some() || config.happy_eyeballs {
+                proc.args(["-o", "ProxyCommand=asc plumbing connect %h %p"]);
+
+                match &proxy {
+                    Some(proxy) => proc.env(PROXY_VAR, proxy.to_string()),
+                    None => proc.env_remove(PROXY_VAR)
+                };
+            }
+
             proc.args([
                 address,
                 "asc-server".to_string(),
@@ -83,6 +103,7 @@ impl Client {
 
         let conn = Connection {
             inner: InnerConnection::Ssh(stream),
+            framing: Framing::default(),
             read_bytes: 0,
             written_bytes: 0
         };
@@ -93,21 +114,34 @@ impl Client {
     }
 
     async fn connect_file(remote: FileRemote) -> Result<Client> {
-        let remote_repo = Arc::new(Mutex::new(
-            Repository::load_from(remote.path())?
-        ));
-
         let (stream, mut server) = local_duplex();
 
-        tokio::spawn(async move {
-            handle_server(
-                &mut server,
-                remote_repo.clone()
-            ).await
-        });
+        let path = remote.path().clone();
+
+        // With no repository at the path, the only thing
+        // that can be done is creating one.
+        if !path.join(".asc").is_dir() {
+            tokio::spawn(async move {
+                handle_server_without_repo(&mut server, &path, None).await
+            });
+        }
+        else {
+            let remote_repo = Arc::new(RwLock::new(
+                Repository::load_from(&path)?
+            ));
+
+            tokio::spawn(async move {
+                handle_server(
+                    &mut server,
+                    remote_repo.clone(),
+                    None
+                ).await
+            });
+        }
 
         let conn = Connection {
             inner: InnerConnection::File(stream),
+            framing: Framing::default(),
             read_bytes: 0,
             written_bytes: 0
         };
@@ -118,40 +152,92 @@ impl Client {
     }
 
     pub async fn connect(remote: Remote) -> Result<Client> {
-        match remote {
-            Remote::File(rem) => Client::connect_file(rem).await,
-            Remote::Ssh(rem) => Client::connect_ssh(rem).await
-        }
+        let config = Config::load_current()?.sync;
+
+        let mut client = match remote {
+            Remote::File(rem) => Client::connect_file(rem).await?,
+            Remote::Ssh(rem) => Client::connect_ssh(rem, &config).await?
+        };
+
+        negotiate_as_client(&mut client.conn, config.max_frame_size()).await?;
+
+        Ok(client)
     }
 
-    pub async fn make_pull(&mut self, repo: Repo) -> Result<Vec<PullResult>> {
+    /// Pull the branches and tags of `repo`, only moving
+    /// the current branch if `current_only` is set.
+    pub async fn make_pull(&mut self, repo: Repo, current_only: bool) -> Result<Vec<PullResult>> {
         self.conn.send(&Method::Pull).await?;
 
-        handle_pull_as_client(&mut self.conn, repo).await
+        handle_pull_as_client(&mut self.conn, repo, &self.remote, current_only).await
     }
 
-    pub async fn make_push(&mut self, repo: Repo) -> Result<Vec<PushResult>> {
+    /// Push the branches and tags of `repo`, including
+    /// its ephemeral branches if `ephemeral` is set.
+    pub async fn make_push(&mut self, repo: Repo, ephemeral: bool) -> Result<Vec<PushResult>> {
         self.conn.send(&Method::Push).await?;
 
-        handle_push_as_client(&mut self.conn, repo).await
+        handle_push_as_client(&mut self.conn, repo, &self.remote, ephemeral).await
+    }
+
+    /// Make the remote's branches and tags match those of `repo`, even
+    /// where that isn't a fast-forward, and check it has everything they reach.
+    pub async fn make_mirror(&mut self, repo: Repo, options: MirrorOptions) -> Result<MirrorReport> {
+        self.conn.send(&Method::Mirror).await?;
+
+        handle_mirror_as_client(&mut self.conn, repo, &self.remote, options).await
+    }
+
+    /// Create a repository on the remote from the metadata of `repo`.
+    /// 
+    /// The new repository has no branches, so a push is needed afterwards.
+    pub async fn make_init(&mut self, repo: Repo) -> Result<()> {
+        self.conn.send(&Method::Init).await?;
+
+        handle_init_as_client(&mut self.conn, repo).await
+    }
+
+    /// Download large files from the remote, returning the hashes of those it had.
+    pub async fn make_lfs_fetch(&mut self, repo: Repo, wanted: Vec<ObjectHash>) -> Result<Vec<ObjectHash>> {
+        self.conn.send(&Method::LfsFetch).await?;
+
+        handle_lfs_fetch_as_client(&mut self.conn, repo, &self.remote, wanted).await
+    }
+
+    /// Upload large files to the remote, skipping those it already has.
+    pub async fn make_lfs_push(&mut self, repo: Repo, offered: Vec<ObjectHash>) -> Result<LfsPushResult> {
+        self.conn.send(&Method::LfsPush).await?;
+
+        handle_lfs_push_as_client(&mut self.conn, repo, &self.remote, offered).await
+    }
+
+    /// Ask the remote which user it takes the current user of `repo` to be.
+    pub async fn make_whoami(&mut self, repo: Repo) -> Result<Identity> {
+        self.conn.send(&Method::WhoAmI).await?;
+
+        handle_whoami_as_client(&mut self.conn, repo).await
     }
 
     pub async fn clone_repo(
         &mut self,
         local_repo_path: &Path,
-        user_key: PrivateKey
-    ) -> Result<Repository>
+        user_key: PrivateKey,
+        options: CloneOptions
+    ) -> Result<CloneResult>
     {
         self.conn.send(&Method::Clone).await?;
 
-        handle_clone_as_client(
+        let dropped = handle_clone_as_client(
             &mut self.conn,
             self.remote.clone(),
             local_repo_path,
-            user_key
+            user_key,
+            options
         ).await?;
 
-        Repository::load_from(local_repo_path)
+        let repo = Repository::load_from(local_repo_path)?;
+
+        Ok(CloneResult { repo, dropped })
     }
 
     // TODO: allow this to be hooked into, so that data transfer
@@ -1,19 +1,168 @@
-use std::{collections::{HashMap, HashSet, VecDeque}, fs, path::Path};
+use std::{collections::{BTreeMap, HashMap, HashSet, VecDeque}, path::{Path, PathBuf}};
 
-use eyre::{Result, eyre};
+use eyre::{Result, bail, eyre};
+use serde::{Deserialize, Serialize};
 use serde_bytes::ByteBuf;
 
-use crate::{content::Content, hash::ObjectHash, key::{PrivateKey, Signature}, repository::Repository, sync::{remote::Remote, stream::Stream, utils::{Object, Repo, ServerSecret, get_server_secret}}, unwrap, utils::{compress_data, decompress_data}};
+use crate::{blobs::AsyncObjectStore, bundle::Bundle, content::Content, hash::ObjectHash, key::{PrivateKey, Signature}, lock::LockMode, mode::{self, FileMode}, refname, refs::{RefKind, RefStore, TagStamp}, repository::{NamedItems, Repository}, staging::StagingArea, sync::{audit::{self, SyncEvent}, remote::Remote, session::allow_session, stream::Stream, utils::{Object, Repo, ServerSecret, get_server_secret, lock_repo, save_received_snapshot}}, unwrap, utils::{compress_data, decompress_data}};
+
+/// What a clone receives besides its history.
+///
+/// Every clone gets the branches, tags, head, users (without their
+/// private keys) and links, along with every snapshot and piece of
+/// content they reach. The rest is only sent if it is asked for here.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct CloneOptions {
+    /// Send the trash, along with the snapshots in it and those trashed
+    /// with them, so the clone hides the same history and can recover it.
+    pub trash: bool,
+
+    /// Send the stash, along with the snapshots each entry was
+    /// made on and its content, so the entries can be applied.
+    pub stash: bool,
+
+    /// A repository on this machine to borrow objects from, so only
+    /// what it doesn't have is sent. The clone keeps borrowing from it
+    /// afterwards, until [`Repository::dissociate`] is called.
+    #[serde(skip)]
+    pub reference: Option<PathBuf>,
+
+    /// A bundle file with some of the repository's history, whose
+    /// snapshots are left out like those of `reference`, and taken from
+    /// the bundle instead. It has to be made by one of the remote's users.
+    #[serde(skip)]
+    pub bundle: Option<PathBuf>,
+
+    /// The branch to check out, instead of the one the server
+    /// says is its default.
+    pub branch: Option<String>,
+
+    /// Only send the branch being checked out, along with the tags
+    /// on its history, instead of every branch and tag.
+    pub single_branch: bool,
+
+    /// Send the tags. Tags that are left out don't keep
+    /// the history they point to from being left out too.
+    pub tags: bool,
+
+    /// Don't write the checked out snapshot to the working directory,
+    /// for a clone that is only pushed to or served from.
+    #[serde(skip)]
+    pub bare: bool,
+
+    /// Snapshots the client already has, which are left out along with
+    /// their content. This is filled in from `reference` when cloning.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub have: Vec<ObjectHash>
+}
+
+impl Default for CloneOptions {
+    fn default() -> Self {
+        CloneOptions {
+            trash: true,
+            stash: false,
+            reference: None,
+            bundle: None,
+            branch: None,
+            single_branch: false,
+            tags: true,
+            bare: false,
+            have: vec![]
+        }
+    }
+}
+
+/// The refs a clone is sent, given the options it asked for.
+pub struct SentRefs {
+    pub branches: NamedItems<ObjectHash>,
+    pub tags: NamedItems<ObjectHash>,
+
+    /// The snapshot the clone checks out if it
+    /// doesn't check out one of the branches.
+    pub head: ObjectHash
+}
+
+impl SentRefs {
+    pub fn new(repo: &Repository, options: &CloneOptions) -> Result<SentRefs> {
+        let mut branches = repo.refs.branches().to_named_items();
+        let mut tags = repo.refs.tags().to_named_items();
+        let mut head = repo.current_hash();
+
+        if options.single_branch {
+            branches = NamedItems::new();
+
+            let name = options.branch.as_deref().or(repo.default_branch_name());
+
+            if let Some(name) = name
+                && let Some(tip) = repo.refs.branches().get(name)
+            {
+                branches.create(name.to_string(), tip);
+
+                head = tip;
+            }
+
+            // Tags elsewhere in the history would bring the rest of it along.
+            let history = repo.history.reachable([head], |_| false)?;
+
+            tags = NamedItems::new();
+
+            for (name, hash) in repo.refs.tags().iter() {
+                if history.contains(&hash) {
+                    tags.create(name.to_string(), hash);
+                }
+            }
+        }
+
+        if !options.tags {
+            tags = NamedItems::new();
+        }
+
+        Ok(SentRefs { branches, tags, head })
+    }
+}
+
+pub async fn fetch_repo_objecs(repo: &Repository, options: &CloneOptions, refs: &SentRefs) -> Result<HashMap<ObjectHash, Object>> {
+    let blobs = repo.objects();
 
-pub fn fetch_repo_objecs(repo: &Repository) -> Result<HashMap<ObjectHash, Object>> {
     let mut objects = HashMap::new();
 
     let mut queue = VecDeque::new();
     let mut hashes_seen = HashSet::new();
 
-    queue.extend(repo.branches.values().cloned());
+    // Snapshots the client has are left out with their content,
+    // and so are their parents, which it has as well.
+    for &hash in &options.have {
+        if !repo.has_snapshot(hash) {
+            continue;
+        }
+
+        let snapshot = blobs.fetch_snapshot_async(hash).await?;
+
+        hashes_seen.insert(hash);
+        hashes_seen.extend(snapshot.files.values());
+    }
+
+    queue.push_back(refs.head);
 
-    queue.extend(repo.tags.values().cloned());
+    queue.extend(refs.branches.values().copied());
+
+    queue.extend(refs.tags.values().copied());
+
+    if options.trash {
+        for entry in repo.trash.entries() {
+            queue.push_back(entry.hash);
+
+            queue.extend(repo.history.descendants(entry.hash));
+        }
+    }
+
+    if options.stash {
+        for entry in repo.stash.iter_entries() {
+            queue.push_back(entry.basis);
+
+            queue.extend(entry.state.files.values());
+        }
+    }
 
     while let Some(hash) = queue.pop_front() {
         if hashes_seen.contains(&hash) {
@@ -23,7 +172,7 @@ pub fn fetch_repo_objecs(repo: &Repository) -> Result<HashMap<ObjectHash, Object
         hashes_seen.insert(hash);
 
         if repo.history.contains(hash) {
-            let snapshot = repo.fetch_snapshot(hash)?;
+            let snapshot = blobs.fetch_snapshot_async(hash).await?;
 
             queue.extend(snapshot.parents.iter().cloned());
 
@@ -32,7 +181,7 @@ pub fn fetch_repo_objecs(repo: &Repository) -> Result<HashMap<ObjectHash, Object
             objects.insert(hash, Object::Commit(Box::new(snapshot)));
         }
         else {
-            let content = repo.fetch_content_object(hash)?;
+            let content = blobs.fetch_content_object_async(hash).await?;
 
             if let Content::Delta(delta) = &content {
                 queue.push_back(delta.original);
@@ -45,13 +194,163 @@ pub fn fetch_repo_objecs(repo: &Repository) -> Result<HashMap<ObjectHash, Object
     Ok(objects)
 }
 
+/// Add the snapshots borrowed from a reference repository to the history,
+/// since only the snapshots the server sent have been saved into it.
+fn add_borrowed_history(repo: &mut Repository) -> Result<()> {
+    let mut queue: VecDeque<ObjectHash> = repo.history.iter_hashes().collect();
+
+    queue.extend(repo.refs.iter().map(|(_, hash)| hash));
+
+    queue.extend(repo.trash.entries().iter().map(|entry| entry.hash));
+
+    queue.extend(repo.stash.iter_entries().map(|entry| entry.basis));
+
+    let blobs = repo.blobs();
+
+    let mut seen = HashSet::new();
+
+    while let Some(hash) = queue.pop_front() {
+        // Snapshots that were sent are in the history already, with their parents.
+        if !seen.insert(hash) || blobs.hash_to_path(hash).exists() {
+            continue;
+        }
+
+        let snapshot = unwrap!(
+            blobs.fetch_snapshot(hash),
+            "snapshot {hash} is missing from the reference repository"
+        );
+
+        repo.history.upsert(hash, snapshot.parents.iter().copied());
+
+        queue.extend(snapshot.parents);
+    }
+
+    Ok(())
+}
+
+/// Save the objects from a bundle that the clone reaches, since the server
+/// left them out of what it sent. The rest of the bundle is left out too,
+/// so the clone ends up with the same history as without it.
+fn add_bundled_objects(repo: &mut Repository, mut objects: HashMap<ObjectHash, Object>) -> Result<()> {
+    let mut queue: Vec<ObjectHash> = repo.history.iter_hashes().collect();
+
+    queue.extend(repo.refs.iter().map(|(_, hash)| hash));
+
+    queue.extend(repo.trash.entries().iter().map(|entry| entry.hash));
+
+    let mut seen = HashSet::new();
+
+    while let Some(hash) = queue.pop() {
+        if objects.is_empty() {
+            break;
+        }
+
+        if !seen.insert(hash) {
+            continue;
+        }
+
+        match objects.remove(&hash) {
+            Some(Object::Commit(snapshot)) => { save_received_snapshot(repo, *snapshot)?; },
+            Some(Object::Content(content)) => repo.save_content_object(content, hash)?,
+            None => {}
+        }
+
+        // Snapshots and deltas the server sent can
+        // still need content it left out for the bundle.
+        if repo.history.contains(hash) {
+            let snapshot = repo.fetch_snapshot(hash)?;
+
+            queue.extend(snapshot.parents);
+
+            queue.extend(snapshot.files.into_values());
+        }
+        else if let Content::Delta(delta) = repo.fetch_content_object(hash)? {
+            queue.push(delta.original);
+        }
+    }
+
+    Ok(())
+}
+
+/// A branch or tag the server sent with a name that isn't
+/// allowed, which was left out of the clone.
+#[derive(Clone, Debug)]
+pub struct DroppedRef {
+    pub kind: RefKind,
+    pub name: String,
+
+    /// Why the name isn't allowed.
+    pub reason: String
+}
+
+/// What cloning made, and what it had to leave out.
+pub struct CloneResult {
+    pub repo: Repository,
+    pub dropped: Vec<DroppedRef>
+}
+
+/// Leave out the refs a server sent with names that aren't allowed,
+/// returning which ones were left out and why.
+fn drop_invalid_names(items: &mut NamedItems<ObjectHash>, kind: RefKind) -> Vec<DroppedRef> {
+    let dropped: Vec<DroppedRef> = items
+        .names()
+        .filter_map(|name| refname::check(name).err().map(|e| DroppedRef {
+            kind,
+            name: name.clone(),
+            reason: e.to_string()
+        }))
+        .collect();
+
+    for item in &dropped {
+        items.remove(&item.name);
+    }
+
+    dropped
+}
+
 pub async fn handle_clone_as_client(
     stream: &mut impl Stream,
     remote: Remote,
     local_repo_path: &Path,
-    mut user_key: PrivateKey
-) -> Result<()>
+    mut user_key: PrivateKey,
+    mut options: CloneOptions
+) -> Result<Vec<DroppedRef>>
 {
+    let reference = match &options.reference {
+        Some(path) => {
+            let reference = Repository::load_from(path)?;
+
+            options.have = reference.history
+                .iter_hashes()
+                .filter(|&hash| reference.has_snapshot(hash))
+                .collect();
+
+            Some(reference.root_dir)
+        },
+
+        None => None
+    };
+
+    let bundle = match &options.bundle {
+        Some(path) => {
+            let (bundle, maker) = Bundle::read(path)?;
+
+            if !bundle.prerequisites.is_empty() {
+                bail!("bundle {} builds on snapshots it doesn't hold, so it can't seed a clone.", path.display());
+            }
+
+            options.have.extend(bundle.objects
+                .iter()
+                .filter(|(_, object)| matches!(object, Object::Commit(_)))
+                .map(|(&hash, _)| hash)
+            );
+
+            Some((path.clone(), bundle, maker))
+        },
+
+        None => None
+    };
+
     let secret: ServerSecret = stream.receive().await?;
 
     let signature = user_key.sign(&secret);
@@ -62,6 +361,8 @@ pub async fn handle_clone_as_client(
 
     result.map_err(|message| eyre!("server error: {message}"))?;
 
+    stream.send(&options).await?;
+
     let mut repo = Repository::create_new(
         local_repo_path,
         "axo".to_string(),
@@ -72,16 +373,61 @@ pub async fn handle_clone_as_client(
 
     repo.history.remove(root_hash);
 
+    repo.alternates.extend(reference);
+
     repo.project_name = stream.receive().await?;
     repo.project_code = stream.receive().await?;
 
-    repo.branches = stream.receive().await?;
-    repo.tags = stream.receive().await?;
+    let mut branches = stream.receive().await?;
+    let mut tags = stream.receive().await?;
+    let tag_stamps: BTreeMap<String, TagStamp> = stream.receive().await?;
+
+    let mut dropped = drop_invalid_names(&mut branches, RefKind::Branch);
+
+    dropped.extend(drop_invalid_names(&mut tags, RefKind::Tag));
     
-    repo.current_hash = stream.receive().await?;
+    let current_hash = stream.receive().await?;
+    let default_branch: Option<String> = stream.receive().await?;
+
+    repo.refs = RefStore::from_legacy(branches, tags, current_hash);
+
+    // Servers that have no branch checked out (or no default)
+    // leave the clone on the snapshot they have checked out.
+    if let Some(name) = options.branch.as_ref().or(default_branch.as_ref())
+        && !repo.refs.attach_head(name)
+        && options.branch.is_some()
+    {
+        bail!("the remote has no branch called {name:?}");
+    }
+
+    for (name, stamp) in tag_stamps {
+        if repo.refs.tags().contains(&name) {
+            repo.refs.set_tag_stamp(&name, Some(stamp));
+        }
+    }
 
     repo.users = stream.receive().await?;
 
+    if let Some((path, bundle, maker)) = &bundle {
+        if bundle.project_code != repo.project_code {
+            bail!("bundle {} is for a different project.", path.display());
+        }
+
+        if repo.users.get_user(maker).is_none() {
+            bail!("bundle {} was made by a user the remote doesn't know.", path.display());
+        }
+    }
+
+    repo.links = stream.receive().await?;
+
+    if options.trash {
+        repo.trash = stream.receive().await?;
+    }
+
+    if options.stash {
+        repo.stash = stream.receive().await?;
+    }
+
     {
         let main_user = repo.users.get_user_mut(&user_key.public_key()).unwrap();
 
@@ -96,6 +442,16 @@ pub async fn handle_clone_as_client(
 
     repo.remotes.create("origin".to_string(), remote);
 
+    let branches: Vec<(String, ObjectHash)> = repo.refs
+        .branches()
+        .iter()
+        .map(|(name, tip)| (name.to_string(), tip))
+        .collect();
+
+    for (name, tip) in branches {
+        repo.set_remote_tip("origin", &name, tip);
+    }
+
     let compressed: ByteBuf = stream.receive().await?;
 
     let decompressed = decompress_data(compressed)?;
@@ -104,34 +460,65 @@ pub async fn handle_clone_as_client(
 
     for (hash, object) in objects {
         match object {
-            Object::Commit(snapshot) => repo.save_snapshot(*snapshot)?,
+            Object::Commit(snapshot) => { save_received_snapshot(&mut repo, *snapshot)?; },
             Object::Content(content) => repo.save_content_object(content, hash)?
         }
     }
 
+    if let Some((_, bundle, _)) = bundle {
+        add_bundled_objects(&mut repo, bundle.objects)?;
+    }
+
+    if !repo.alternates.is_empty() {
+        add_borrowed_history(&mut repo)?;
+    }
+
     repo.save()?;
 
     let current = repo.fetch_current_snapshot()?;
 
-    repo.staged_files = current.files
-        .keys()
-        .cloned()
-        .collect();
+    repo.staging = StagingArea::from_snapshot(&current);
+
+    if options.bare {
+        repo.save()?;
+
+        return Ok(dropped);
+    }
+
+    let mut entries: Vec<_> = current.entries().collect();
+
+    // Links are made last, so no file is written through one.
+    entries.sort_by_key(|(_, entry)| entry.mode == FileMode::Symlink);
+
+    for (path, entry) in entries {
+        let content_hash = entry.hash;
 
-    for (path, content_hash) in current.files {
         let full_path = path.to_logical_path(&repo.root_dir);
 
+        unwrap!(
+            mode::check_no_symlink_ancestors(&repo.root_dir, &full_path),
+            "refusing to write to {}", full_path.display()
+        );
+
+        // Large files are downloaded separately, and
+        // written once they are stored locally.
+        if let Content::Pointer(_) = repo.fetch_content_object(content_hash)?
+            && !repo.has_large_file(content_hash)
+        {
+            continue;
+        }
+
         let content = repo.fetch_string_content(content_hash)?;
 
         unwrap!(
-            fs::write(&full_path, content),
+            mode::write_working(&full_path, &content, entry.mode),
             "could not write to {}", full_path.display()
         );
     }
 
     repo.save()?;
 
-    Ok(())
+    Ok(dropped)
 }
 
 pub async fn handle_clone_as_server(
@@ -139,42 +526,99 @@ pub async fn handle_clone_as_server(
     repo: Repo
 ) -> Result<()>
 {
-    let repo = repo.lock().await;
+    // Like pulling, the client is logged in before the lock is taken.
+    {
+        let repo = repo.read().await;
 
-    let secret = get_server_secret();
+        let secret = get_server_secret();
 
-    stream.send(&secret).await?;
+        stream.send(&secret).await?;
 
-    let signature: Signature = stream.receive().await?;
+        let signature: Signature = stream.receive().await?;
 
-    if repo.users.get_user(&signature.key()).is_some() {
-        let ok: Result<(), ()> = Ok(());
+        let key = signature.key();
 
-        stream.send(&ok).await?;
-    }
-    else {
-        let error: Result<(), String> = Err("user does not exist".to_string());
+        // Every attempt counts towards the limit, so guessing
+        // at logins is limited as much as connecting is.
+        let result: Result<(), String> = if let Some(limit) = stream.framing().limits().sessions_per_minute
+            && !allow_session(&repo, key, limit)?
+        {
+            Err(format!("too many connections - only {limit} are allowed a minute"))
+        }
+        else if !signature.verify(&secret) {
+            Err("failed to verify signature".to_string())
+        }
+        else if repo.users.get_user(&key).is_none() {
+            Err("user does not exist".to_string())
+        }
+        else {
+            Ok(())
+        };
 
-        stream.send(&error).await?;
+        stream.send(&result).await?;
 
-        return Ok(());
+        audit::record(&repo, Some(&key), SyncEvent::Login {
+            accepted: result.is_ok(),
+            reason: result.clone().err()
+        })?;
+
+        if result.is_err() {
+            return Ok(());
+        }
+
+        audit::record(&repo, Some(&key), SyncEvent::Clone)?;
     }
 
+    // Cloning doesn't change the repository, so like pulling,
+    // it shares the lock with other readers.
+    let _lock = lock_repo(&repo, LockMode::Shared, "clone").await?;
+
+    let repo = repo.read().await;
+
+    let options: CloneOptions = stream.receive().await?;
+
     stream.send(&repo.project_name).await?;
     stream.send(&repo.project_code).await?;
 
-    stream.send(&repo.branches).await?;
-    stream.send(&repo.tags).await?;
+    let refs = SentRefs::new(&repo, &options)?;
+
+    let tag_stamps: BTreeMap<&String, &TagStamp> = repo.refs
+        .tag_stamps()
+        .iter()
+        .filter(|(name, _)| refs.tags.contains(name))
+        .collect();
 
-    stream.send(&repo.current_hash).await?;
+    stream.send(&refs.branches).await?;
+    stream.send(&refs.tags).await?;
+    stream.send(&tag_stamps).await?;
+
+    stream.send(&refs.head).await?;
+    stream.send(&repo.default_branch_name()).await?;
 
     stream.send(&repo.users.without_private_keys()).await?;
 
-    let objects = fetch_repo_objecs(&repo)?;
+    stream.send(&repo.links).await?;
+
+    if options.trash {
+        stream.send(&repo.trash).await?;
+    }
+
+    if options.stash {
+        stream.send(&repo.stash).await?;
+    }
 
-    let serialised = rmp_serde::to_vec(&objects)?;
+    let objects = fetch_repo_objecs(&repo, &options, &refs).await?;
 
-    let compressed = serde_bytes::ByteBuf::from(compress_data(serialised));
+    // Compressing a whole repository takes a while, so it
+    // shouldn't hold up the other connections on the runtime.
+    let compressed = unwrap!(
+        tokio::task::spawn_blocking(move || -> Result<ByteBuf> {
+            let serialised = rmp_serde::to_vec(&objects)?;
+
+            Ok(ByteBuf::from(compress_data(serialised)))
+        }).await,
+        "failed to compress objects"
+    )?;
 
     stream.send(&compressed).await?;
     
@@ -0,0 +1,155 @@
+//! Telling other programs when a push updates branches, like to start CI.
+//!
+//! A server lists what to tell in its config:
+//!
+//! ```toml
+//! [hooks]
+//! post_push = ["/srv/ci/notify --queue builds"]
+//! webhooks = ["https://ci.example.com/asc"]
+//! ```
+//!
+//! Once a push is saved, every hook is run and every webhook is sent a
+//! [`BranchUpdate`] for each branch the push created or moved, as JSON.
+//! Hooks are also given it in the `ASC_BRANCH`, `ASC_OLD_TIP`, `ASC_NEW_TIP`
+//! and `ASC_PUSHER` environment variables. A hook or webhook that fails is
+//! reported on stderr, but doesn't fail the push, which is already saved.
+//!
+//! Hooks run under a [`Policy`]: from the root of the repository, with
+//! most of the environment cleared, and killed after `hooks.timeout`.
+//! Setting `hooks.enabled = false` turns hooks and webhooks off entirely.
+
+use std::{io::Write, path::Path, process::{Command, Stdio}};
+
+use eyre::{bail, Result};
+use serde::Serialize;
+
+use crate::{config::HooksConfig, exec::Policy, hash::ObjectHash, key::PublicKey, unwrap};
+
+/// How long a webhook has to answer, in seconds.
+const WEBHOOK_TIMEOUT: &str = "10";
+
+/// A branch that was created or moved by a push, as hooks and webhooks get it.
+#[derive(Clone, Debug, Serialize)]
+pub struct BranchUpdate {
+    /// The name of the project.
+    pub repository: String,
+
+    pub branch: String,
+
+    /// Where the branch was before, as a full hash,
+    /// or `None` if the push created it.
+    pub old_tip: Option<String>,
+
+    pub new_tip: String,
+
+    /// The public key of the user who pushed, as hex.
+    pub pusher: String
+}
+
+impl BranchUpdate {
+    pub fn new(repository: &str, branch: &str, old_tip: Option<ObjectHash>, new_tip: ObjectHash, pusher: &PublicKey) -> BranchUpdate {
+        BranchUpdate {
+            repository: repository.to_string(),
+            branch: branch.to_string(),
+            old_tip: old_tip.map(|hash| hash.full()),
+            new_tip: new_tip.full(),
+            pusher: pusher.to_string()
+        }
+    }
+}
+
+fn run_hook(hook: &str, policy: &Policy, update: &BranchUpdate, payload: &[u8]) -> Result<()> {
+    let policy = policy.clone()
+        .env("ASC_BRANCH", &update.branch)
+        .env("ASC_OLD_TIP", update.old_tip.as_deref().unwrap_or(""))
+        .env("ASC_NEW_TIP", &update.new_tip)
+        .env("ASC_PUSHER", &update.pusher);
+
+    // On a server reached over ssh, stdout is the connection to the client,
+    // so what the hook prints is captured rather than let through.
+    let output = policy.run(hook, &[], payload)?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+
+        match stderr.trim() {
+            "" => bail!("exited with {}", output.status),
+            stderr => bail!("exited with {}: {stderr}", output.status)
+        }
+    }
+
+    Ok(())
+}
+
+fn send_webhook(url: &str, payload: &[u8]) -> Result<()> {
+    let mut child = unwrap!(
+        Command::new("curl")
+            .args([
+                "--fail", "--silent", "--show-error",
+                "--max-time", WEBHOOK_TIMEOUT,
+                "--header", "Content-Type: application/json",
+                "--data-binary", "@-",
+                url
+            ])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn(),
+        "failed to run curl to send a webhook to {url} - is it installed?"
+    );
+
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(payload)?;
+    }
+
+    let output = child.wait_with_output()?;
+
+    if !output.status.success() {
+        bail!("{}", String::from_utf8_lossy(&output.stderr).trim());
+    }
+
+    Ok(())
+}
+
+/// Run every hook and send every webhook in `config` for each of `updates`.
+///
+/// Nothing is run if `hooks.enabled` is `false`.
+pub fn notify_push(config: &HooksConfig, root_dir: &Path, updates: &[BranchUpdate]) {
+    if !config.enabled() {
+        return;
+    }
+
+    let timeout = match config.timeout() {
+        Ok(timeout) => timeout,
+        Err(e) => {
+            eprintln!("warning: not running post-push hooks: {e}");
+
+            return;
+        }
+    };
+
+    let policy = Policy::background(root_dir, timeout);
+
+    for update in updates {
+        let payload = match serde_json::to_vec(update) {
+            Ok(payload) => payload,
+            Err(e) => {
+                eprintln!("warning: could not describe the update to {}: {e}", update.branch);
+
+                continue;
+            }
+        };
+
+        for hook in &config.post_push {
+            if let Err(e) = run_hook(hook, &policy, update, &payload) {
+                eprintln!("warning: post-push hook {hook:?} failed for {}: {e}", update.branch);
+            }
+        }
+
+        for url in &config.webhooks {
+            if let Err(e) = send_webhook(url, &payload) {
+                eprintln!("warning: webhook to {url} failed for {}: {e}", update.branch);
+            }
+        }
+    }
+}
@@ -0,0 +1,253 @@
+use std::{fs, path::{Component, Path, PathBuf}, time::Duration};
+
+use eyre::{Result, bail, eyre};
+use serde::{Deserialize, Serialize};
+
+use crate::{graph::Graph, hash::ObjectHash, key::{PublicKey, Signature}, refs::RefStore, repository::Repository, snapshot::Snapshot, sync::{stream::{ConnectionLimits, DEFAULT_MAX_FRAME_SIZE, DEFAULT_SERVER_MAX_MESSAGE_SIZE, Stream}, utils::{Repo, ServerSecret, get_server_secret}}, unwrap, user::Users};
+
+/// Settings for a server hosting repositories.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct ServerConfig {
+    /// The directory new repositories can be created in.
+    ///
+    /// If this is unset, clients cannot create repositories.
+    pub repositories_dir: Option<PathBuf>,
+
+    /// The public keys (as hex) of users allowed to create repositories.
+    pub admins: Vec<String>,
+
+    /// The largest frame, in bytes, read from a client.
+    ///
+    /// If this is unset, [`DEFAULT_MAX_FRAME_SIZE`] is used.
+    #[serde(default)]
+    pub max_frame_size: Option<u64>,
+
+    /// How many seconds to wait for a client to send something
+    /// before giving up on it. If this is unset, it is waited for.
+    #[serde(default)]
+    pub read_timeout: Option<u64>,
+
+    /// How many seconds to wait for a client to take what is sent
+    /// before giving up on it. If this is unset, it is waited for.
+    #[serde(default)]
+    pub write_timeout: Option<u64>,
+
+    /// How many times each user can try to log in a minute, whether they can or not.
+    #[serde(default)]
+    pub sessions_per_minute: Option<u32>,
+
+    /// The most bytes a client can send over one connection.
+    #[serde(default)]
+    pub max_session_bytes: Option<u64>,
+
+    /// The most bytes a client can send in one message, which can be
+    /// split over many frames.
+    ///
+    /// If this is unset, [`DEFAULT_SERVER_MAX_MESSAGE_SIZE`] is used.
+    #[serde(default)]
+    pub max_message_size: Option<u64>
+}
+
+impl ServerConfig {
+    /// Get the largest frame read from a client.
+    pub fn max_frame_size(&self) -> u64 {
+        self.max_frame_size.unwrap_or(DEFAULT_MAX_FRAME_SIZE)
+    }
+
+    /// Get the largest message read from a client.
+    pub fn max_message_size(&self) -> u64 {
+        self.max_message_size.unwrap_or(DEFAULT_SERVER_MAX_MESSAGE_SIZE)
+    }
+
+    /// Get the limits to put on each connection to the server.
+    pub fn connection_limits(&self) -> ConnectionLimits {
+        ConnectionLimits {
+            read_timeout: self.read_timeout.map(Duration::from_secs),
+            write_timeout: self.write_timeout.map(Duration::from_secs),
+            max_bytes: self.max_session_bytes,
+            max_message_size: Some(self.max_message_size()),
+            sessions_per_minute: self.sessions_per_minute
+        }
+    }
+
+    /// Check if a public key belongs to an admin of the server.
+    pub fn is_admin(&self, key: &PublicKey) -> bool {
+        let key = key.to_string();
+
+        self.admins
+            .iter()
+            .any(|admin| admin.eq_ignore_ascii_case(&key))
+    }
+
+    /// Resolve where a repository would be created, if it is
+    /// inside [`ServerConfig::repositories_dir`].
+    ///
+    /// Relative paths are resolved from the repositories directory.
+    pub fn resolve_path(&self, path: &Path) -> Option<PathBuf> {
+        let base = self.repositories_dir.as_ref()?;
+
+        if path.components().any(|c| c == Component::ParentDir) {
+            return None;
+        }
+
+        let full = base.join(path);
+
+        full.starts_with(base).then_some(full)
+    }
+}
+
+/// The project metadata a client sends to bootstrap a repository on the server.
+#[derive(Deserialize, Serialize)]
+struct InitRequest {
+    project_name: String,
+    project_code: ObjectHash,
+    users: Users,
+    roots: Vec<Snapshot>
+}
+
+/// Ask the server to create a new repository from the metadata of `repo`.
+///
+/// The repository is created with no branches, ready to be pushed to.
+pub async fn handle_init_as_client(
+    stream: &mut impl Stream,
+    repo: Repo
+) -> Result<()>
+{
+    let repo = repo.read().await;
+
+    let user = unwrap!(
+        repo.current_user(),
+        "no valid user set for this repository."
+    );
+
+    let mut key = user.private_key.clone().unwrap();
+
+    let secret: Result<ServerSecret, String> = stream.receive().await?;
+
+    let secret = secret.map_err(|message| eyre!("server error: {message}"))?;
+
+    stream.send(&key.sign(&secret)).await?;
+
+    let result: Result<(), String> = stream.receive().await?;
+
+    result.map_err(|message| eyre!("server error: {message}"))?;
+
+    let mut roots = vec![];
+
+    for (hash, parents) in repo.history.iter() {
+        if parents.is_empty() {
+            roots.push(repo.fetch_snapshot(hash)?);
+        }
+    }
+
+    let request = InitRequest {
+        project_name: repo.project_name.clone(),
+        project_code: repo.project_code,
+        users: repo.users.without_private_keys(),
+        roots
+    };
+
+    stream.send(&request).await?;
+
+    let result: Result<(), String> = stream.receive().await?;
+
+    result.map_err(|message| eyre!("server error: {message}"))
+}
+
+fn create_from_request(path: &Path, request: InitRequest) -> Result<()> {
+    unwrap!(
+        fs::create_dir_all(path),
+        "failed to create directory: {}", path.display()
+    );
+
+    let mut repo = Repository::create_new(
+        path,
+        "asc-server".to_string(),
+        request.project_name
+    )?;
+
+    repo.project_code = request.project_code;
+    repo.users = request.users;
+    repo.history = Graph::new();
+    repo.refs = RefStore::new();
+
+    let mut lock = repo.current_user.write().unwrap();
+
+    *lock = None;
+
+    drop(lock);
+
+    let Some(first_root) = request.roots.first().map(|root| root.hash) else {
+        bail!("no root snapshots were sent by the client");
+    };
+
+    for root in request.roots {
+        repo.save_snapshot(root)?;
+    }
+
+    repo.refs.detach_head(first_root);
+
+    repo.save()
+}
+
+/// Serve a request to create a new repository at `path`.
+///
+/// If `config` is `None`, the client is trusted to create a repository
+/// anywhere, which is only suitable for local (`file://`) remotes.
+pub async fn handle_init_as_server(
+    stream: &mut impl Stream,
+    path: &Path,
+    config: Option<&ServerConfig>
+) -> Result<()>
+{
+    let resolved = match config {
+        Some(config) => config.resolve_path(path),
+        None => Some(path.to_path_buf())
+    };
+
+    let secret: Result<ServerSecret, String> = match &resolved {
+        Some(full) if full.join(".asc").exists() => Err("a repository already exists at this location".to_string()),
+        Some(_) => Ok(get_server_secret()),
+        None => Err("repositories cannot be created at this location".to_string())
+    };
+
+    stream.send(&secret).await?;
+
+    let (Ok(secret), Some(full_path)) = (secret, resolved) else {
+        return Ok(());
+    };
+
+    let login: Signature = stream.receive().await?;
+
+    let result: Result<(), String> = if !login.verify(&secret) {
+        Err("failed to verify signature".to_string())
+    }
+    else if config.is_some_and(|config| !config.is_admin(&login.key())) {
+        Err("only admins can create repositories on this server".to_string())
+    }
+    else {
+        Ok(())
+    };
+
+    stream.send(&result).await?;
+
+    if result.is_err() {
+        return Ok(());
+    }
+
+    let request: InitRequest = stream.receive().await?;
+
+    let result = create_from_request(&full_path, request)
+        .map_err(|e| e.to_string());
+
+    stream.send(&result).await?;
+
+    Ok(())
+}
+
+/// Refuse a request to create a repository because one already exists.
+pub async fn refuse_init(stream: &mut impl Stream) -> Result<()> {
+    let error: Result<ServerSecret, String> = Err("a repository already exists at this location".to_string());
+
+    stream.send(&error).await
+}
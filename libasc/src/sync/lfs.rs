@@ -0,0 +1,532 @@
+use std::{fs::{self, File}, io::{Read, Write}, path::PathBuf};
+
+use eyre::{Report, Result, bail, eyre};
+use miniz_oxide::{DataFormat, MZError, MZFlush, MZStatus, inflate::stream::{InflateState, inflate}};
+use rand::random;
+use serde_bytes::ByteBuf;
+use sha2::{Digest, Sha256};
+
+use crate::{content::Content, hash::{ObjectHash, RawObjectHash}, lock::LockMode, repository::Repository, sync::{remote::Remote, stream::Stream, utils::{Repo, handle_login, lock_repo, login_as}}, unwrap, user::User};
+
+/// The most bytes of a large file sent in one message. Files are sent in
+/// chunks this big, so neither end holds a whole one in memory at once.
+pub const LARGE_FILE_CHUNK_SIZE: usize = 4 * 1024 * 1024;
+
+/// Read and write large files on one of tokio's blocking threads, since
+/// they can be big enough to stall every other connection on the runtime.
+async fn off_runtime<T: Send + 'static>(f: impl FnOnce() -> Result<T> + Send + 'static) -> Result<T> {
+    unwrap!(
+        tokio::task::spawn_blocking(f).await,
+        "large file task failed"
+    )
+}
+
+/// Hashes the compressed data of a large file as it comes in,
+/// to check it matches the hash it was sent under.
+struct LargeFileCheck {
+    state: Box<InflateState>,
+    hasher: Sha256,
+    ended: bool
+}
+
+impl LargeFileCheck {
+    fn new() -> LargeFileCheck {
+        LargeFileCheck {
+            state: InflateState::new_boxed(DataFormat::Raw),
+            hasher: Sha256::new(),
+            ended: false
+        }
+    }
+
+    /// Decompress the next part of the file into the hash, finishing
+    /// the file if `flush` is [`MZFlush::Finish`].
+    fn update(&mut self, mut input: &[u8], flush: MZFlush) -> Result<()> {
+        let mut output = vec![0; LARGE_FILE_CHUNK_SIZE];
+
+        loop {
+            if self.ended {
+                if !input.is_empty() {
+                    bail!("large file has data after its end");
+                }
+
+                return Ok(());
+            }
+
+            let result = inflate(&mut self.state, input, &mut output, flush);
+
+            input = &input[result.bytes_consumed..];
+
+            self.hasher.update(&output[..result.bytes_written]);
+
+            match result.status {
+                Ok(MZStatus::StreamEnd) => self.ended = true,
+                Ok(_) | Err(MZError::Buf) => {
+                    let stuck = result.bytes_consumed == 0 && result.bytes_written == 0;
+
+                    // Only stop once everything given has been taken in,
+                    // and everything that came out of it has been hashed.
+                    if (input.is_empty() && result.bytes_written < output.len()) || stuck {
+                        if flush == MZFlush::Finish {
+                            bail!("large file ends early");
+                        }
+
+                        if !input.is_empty() {
+                            bail!("failed to decompress large file");
+                        }
+
+                        return Ok(());
+                    }
+                }
+                Err(e) => bail!("failed to decompress large file: {e:?}")
+            }
+        }
+    }
+
+    /// Check the whole file has been decompressed, and matches `hash`.
+    fn finish(mut self, hash: ObjectHash) -> Result<()> {
+        self.update(&[], MZFlush::Finish)?;
+
+        let raw: RawObjectHash = self.hasher.finalize().into();
+
+        if !ObjectHash::from(raw).ct_eq(&hash) {
+            bail!("large file {hash} was corrupted in transfer");
+        }
+
+        Ok(())
+    }
+}
+
+/// Writes a large file as it comes in, to a temporary file that is only
+/// moved to where it belongs once the whole of it has been checked.
+///
+/// Failing to write doesn't stop the rest of the file being taken
+/// from the connection, so the error is kept until it's finished.
+struct LargeFileWriter {
+    hash: ObjectHash,
+    temp: PathBuf,
+    path: PathBuf,
+    file: Option<File>,
+    check: LargeFileCheck,
+    error: Option<Report>
+}
+
+impl LargeFileWriter {
+    fn new(repo: &Repository, hash: ObjectHash) -> LargeFileWriter {
+        let dir = repo.lfs_dir();
+
+        let path = repo.large_file_path(hash);
+
+        // Two connections can be sent the same file at once,
+        // so each one writes to its own temporary file.
+        let temp = path.with_extension(format!("{:016x}.tmp", random::<u64>()));
+
+        let file = fs::create_dir_all(&dir)
+            .map_err(|e| eyre!("failed to create directory {}: {e}", dir.display()))
+            .and_then(|_| File::create(&temp).map_err(|e| eyre!("failed to create {}: {e}", temp.display())));
+
+        let (file, error) = match file {
+            Ok(file) => (Some(file), None),
+            Err(e) => (None, Some(e))
+        };
+
+        LargeFileWriter {
+            hash,
+            temp,
+            path,
+            file,
+            check: LargeFileCheck::new(),
+            error
+        }
+    }
+
+    fn write(&mut self, chunk: &[u8]) {
+        if self.error.is_some() {
+            return;
+        }
+
+        let result = self.check.update(chunk, MZFlush::None).and_then(|_| {
+            let Some(file) = self.file.as_mut() else {
+                return Ok(());
+            };
+
+            file.write_all(chunk).map_err(|e| eyre!("failed to write large file to {}: {e}", self.temp.display()))
+        });
+
+        if let Err(e) = result {
+            self.error = Some(e);
+        }
+    }
+
+    fn finish(mut self) -> Result<()> {
+        let file = self.file.take();
+
+        let result = match self.error.take() {
+            Some(e) => Err(e),
+            None => self.check.finish(self.hash).and_then(|_| {
+                if let Some(file) = file {
+                    unwrap!(
+                        file.sync_all(),
+                        "failed to write large file to: {}", self.temp.display()
+                    );
+                }
+
+                unwrap!(
+                    fs::rename(&self.temp, &self.path),
+                    "failed to move large file to: {}", self.path.display()
+                );
+
+                Ok(())
+            })
+        };
+
+        if result.is_err() {
+            let _ = fs::remove_file(&self.temp);
+        }
+
+        result
+    }
+}
+
+/// Find how many bytes a large file takes up, which is
+/// sent before its data so the other end knows when it ends.
+fn large_file_size(repo: &Repository, hash: ObjectHash) -> Result<u64> {
+    let path = repo.large_file_path(hash);
+
+    let metadata = unwrap!(
+        fs::metadata(&path),
+        "failed to read large file from: {}", path.display()
+    );
+
+    Ok(metadata.len())
+}
+
+/// Send the `size` bytes of a large file in chunks.
+async fn send_large_file(stream: &mut impl Stream, repo: &Repository, hash: ObjectHash, size: u64) -> Result<()> {
+    let path = repo.large_file_path(hash);
+
+    let mut file = unwrap!(
+        File::open(&path),
+        "failed to read large file from: {}", path.display()
+    );
+
+    let mut left = size;
+
+    while left > 0 {
+        let (returned, chunk) = off_runtime(move || {
+            let mut chunk = vec![0; left.min(LARGE_FILE_CHUNK_SIZE as u64) as usize];
+
+            unwrap!(
+                file.read_exact(&mut chunk),
+                "failed to read large file {hash}"
+            );
+
+            Ok((file, ByteBuf::from(chunk)))
+        }).await?;
+
+        file = returned;
+
+        left -= chunk.len() as u64;
+
+        stream.send(&chunk).await?;
+    }
+
+    Ok(())
+}
+
+/// Take a large file of `size` bytes from the connection, in chunks, and
+/// store it. The outer error is for the connection, and the inner one for
+/// the file, after which the connection can still be used.
+async fn receive_large_file(stream: &mut impl Stream, repo: &Repository, hash: ObjectHash, size: u64) -> Result<Result<()>> {
+    let mut writer = LargeFileWriter::new(repo, hash);
+
+    let mut left = size;
+
+    while left > 0 {
+        let chunk: ByteBuf = stream.receive().await?;
+
+        if chunk.is_empty() || chunk.len() as u64 > left || chunk.len() > LARGE_FILE_CHUNK_SIZE {
+            bail!("large file {hash} was sent in malformed chunks");
+        }
+
+        left -= chunk.len() as u64;
+
+        writer = off_runtime(move || {
+            writer.write(&chunk);
+
+            Ok(writer)
+        }).await?;
+    }
+
+    off_runtime(move || Ok(writer.finish())).await
+}
+
+/// Download the given large files from the server.
+/// 
+/// Each file is sent in chunks of at most [`LARGE_FILE_CHUNK_SIZE`]
+/// bytes, and written to disk as they come in, so large files are
+/// never held in memory all at once. Files the server doesn't have
+/// are skipped.
+/// 
+/// This returns the hashes of the files that were downloaded.
+pub async fn handle_lfs_fetch_as_client(
+    stream: &mut impl Stream,
+    repo: Repo,
+    remote: &Remote,
+    wanted: Vec<ObjectHash>
+) -> Result<Vec<ObjectHash>>
+{
+    let mut repo = repo.write().await;
+
+    let user = unwrap!(
+        repo.current_user(),
+        "no valid user set for this repository."
+    );
+
+    login_as(
+        user.public_key,
+        stream,
+        &mut repo,
+        remote
+    ).await?;
+
+    stream.send(&wanted).await?;
+
+    let mut fetched = vec![];
+
+    for hash in wanted {
+        let size: Option<u64> = stream.receive().await?;
+
+        let Some(size) = size else {
+            continue;
+        };
+
+        receive_large_file(stream, &repo, hash, size).await??;
+
+        fetched.push(hash);
+    }
+
+    Ok(fetched)
+}
+
+pub async fn handle_lfs_fetch_as_server(
+    stream: &mut impl Stream,
+    repo: Repo
+) -> Result<()>
+{
+    let check = |_: &User| Ok(());
+
+    // Like pulling, the client is logged in before the lock is taken.
+    {
+        let repo = repo.read().await;
+
+        let Some(_login) = handle_login(&repo, stream, check).await? else {
+            return Ok(());
+        };
+    }
+
+    let _lock = lock_repo(&repo, LockMode::Shared, "lfs fetch").await?;
+
+    let repo = repo.read().await;
+
+    let wanted: Vec<ObjectHash> = stream.receive().await?;
+
+    for hash in wanted {
+        let size = if repo.has_large_file(hash) {
+            Some(large_file_size(&repo, hash)?)
+        }
+        else {
+            None
+        };
+
+        stream.send(&size).await?;
+
+        if let Some(size) = size {
+            send_large_file(stream, &repo, hash, size).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// The outcome of uploading large files to a server.
+pub struct LfsPushResult {
+    /// Large files the server was missing, and were uploaded.
+    pub uploaded: Vec<ObjectHash>,
+
+    /// Large files the server refused, because none of its snapshots use them.
+    pub refused: Vec<ObjectHash>
+}
+
+/// Upload the given large files to the server, skipping any it already has.
+pub async fn handle_lfs_push_as_client(
+    stream: &mut impl Stream,
+    repo: Repo,
+    remote: &Remote,
+    offered: Vec<ObjectHash>
+) -> Result<LfsPushResult>
+{
+    let mut repo = repo.write().await;
+
+    let user = unwrap!(
+        repo.current_user(),
+        "no valid user set for this repository."
+    );
+
+    login_as(
+        user.public_key,
+        stream,
+        &mut repo,
+        remote
+    ).await?;
+
+    stream.send(&offered).await?;
+
+    let (missing, refused): (Vec<ObjectHash>, Vec<ObjectHash>) = stream.receive().await?;
+
+    for &hash in &missing {
+        let size = large_file_size(&repo, hash)?;
+
+        stream.send(&size).await?;
+
+        send_large_file(stream, &repo, hash, size).await?;
+    }
+
+    let result: Result<(), String> = stream.receive().await?;
+
+    result.map_err(|message| eyre!("server error: {message}"))?;
+
+    Ok(LfsPushResult { uploaded: missing, refused })
+}
+
+pub async fn handle_lfs_push_as_server(
+    stream: &mut impl Stream,
+    repo: Repo
+) -> Result<()>
+{
+    let check = |_: &User| Ok(());
+
+    {
+        let repo = repo.read().await;
+
+        let Some(_login) = handle_login(&repo, stream, check).await? else {
+            return Ok(());
+        };
+    }
+
+    // Large files are only ever added, under their hash, and no metadata
+    // is saved, so uploading them can share the lock with readers.
+    let _lock = lock_repo(&repo, LockMode::Shared, "lfs push").await?;
+
+    let repo = repo.read().await;
+
+    let offered: Vec<ObjectHash> = stream.receive().await?;
+
+    // Only accept large files that a pushed snapshot points to.
+    let mut missing = vec![];
+    let mut refused = vec![];
+
+    for hash in offered {
+        if repo.has_large_file(hash) {
+            continue;
+        }
+
+        let is_pointer = repo.objects().contains(hash)
+            && matches!(repo.fetch_content_object(hash)?, Content::Pointer(_));
+
+        if is_pointer {
+            missing.push(hash);
+        }
+        else {
+            refused.push(hash);
+        }
+    }
+
+    stream.send(&(&missing, &refused)).await?;
+
+    let mut result = Ok(());
+
+    for hash in missing {
+        let size: u64 = stream.receive().await?;
+
+        if let Err(e) = receive_large_file(stream, &repo, hash, size).await? {
+            result = Err(e.to_string());
+        }
+    }
+
+    stream.send(&result).await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use miniz_oxide::deflate::compress_to_vec;
+
+    use super::*;
+
+    /// Make a large file, returning its compressed data and hash.
+    fn large_file() -> (Vec<u8>, ObjectHash) {
+        let data: Vec<u8> = (0..200_000u32).map(|n| (n * 7919 % 251) as u8).collect();
+
+        let raw: RawObjectHash = Sha256::digest(&data).into();
+
+        (compress_to_vec(&data, 6), raw.into())
+    }
+
+    fn check_in_chunks(compressed: &[u8], hash: ObjectHash, chunk_size: usize) -> Result<()> {
+        let mut check = LargeFileCheck::new();
+
+        for chunk in compressed.chunks(chunk_size) {
+            check.update(chunk, MZFlush::None)?;
+        }
+
+        check.finish(hash)
+    }
+
+    #[test]
+    fn large_files_split_anywhere_are_accepted() {
+        let (compressed, hash) = large_file();
+
+        for chunk_size in [1, 7, 4096, compressed.len()] {
+            assert!(check_in_chunks(&compressed, hash, chunk_size).is_ok(), "{chunk_size}");
+        }
+    }
+
+    #[test]
+    fn corrupted_large_files_are_refused() {
+        let (mut compressed, hash) = large_file();
+
+        let middle = compressed.len() / 2;
+
+        compressed[middle] ^= 1;
+
+        assert!(check_in_chunks(&compressed, hash, 4096).is_err());
+    }
+
+    #[test]
+    fn large_files_under_the_wrong_hash_are_refused() {
+        let (compressed, _) = large_file();
+
+        let other: RawObjectHash = Sha256::digest(b"something else").into();
+
+        assert!(check_in_chunks(&compressed, other.into(), 4096).is_err());
+    }
+
+    #[test]
+    fn cut_off_large_files_are_refused() {
+        let (compressed, hash) = large_file();
+
+        let cut = &compressed[..compressed.len() - 10];
+
+        assert!(check_in_chunks(cut, hash, 4096).is_err());
+    }
+
+    #[test]
+    fn data_after_the_end_of_a_large_file_is_refused() {
+        let (mut compressed, hash) = large_file();
+
+        compressed.extend(b"more");
+
+        assert!(check_in_chunks(&compressed, hash, 4096).is_err());
+    }
+}
@@ -0,0 +1,527 @@
+//! Making another repository an exact copy of this one, like for a backup.
+//!
+//! Unlike a push, a mirror moves the remote's branches and tags to wherever
+//! they are here, even when that isn't a fast-forward, and deletes the ones
+//! that aren't here. Branches that have to stay linear or that would leave
+//! held snapshots behind (see [`crate::retention`]), tags the user can't
+//! push or that are protected, and refs with names the remote doesn't allow
+//! are left as they are on the remote.
+//!
+//! Once it is done, both ends count the snapshots and content the mirrored
+//! refs reach, so the client can check nothing was missed.
+
+use std::{collections::{BTreeMap, HashMap, HashSet}, iter};
+
+use eyre::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::{action::Action, blobs::AsyncObjectStore, content::Content, fence::Fence, hash::ObjectHash, key::PublicKey, lock::LockMode, refname, refs::TagStamp, repository::{NamedItems, Repository}, retention, sync::{audit::{self, SyncEvent}, push::{check_linear, client_send_objects, server_receive_objects}, remote::Remote, stream::Stream, utils::{handle_login, lock_repo, login_as, Repo}}, trash::Trash, unwrap, user::{User, Users}};
+
+/// What a mirror copies besides the branches and tags.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct MirrorOptions {
+    /// Replace the remote's trash with this one, and
+    /// send the snapshots in it and those trashed with them.
+    pub trash: bool,
+
+    /// Replace the remote's stash with this one, and send the
+    /// snapshots each entry was made on and its content.
+    pub stash: bool
+}
+
+/// How a ref on the remote was changed by a mirror.
+#[derive(Debug, Deserialize, Serialize)]
+pub enum MirrorUpdate {
+    Created(ObjectHash),
+    Moved(ObjectHash, ObjectHash),
+    Deleted(ObjectHash),
+
+    /// The branch has to keep a linear history on the remote,
+    /// so it can't be moved there or deleted.
+    Linear,
+
+    /// The tag is protected on the remote, so it can't be moved or deleted.
+    Protected,
+
+    /// The tag is in a namespace the user can't push to.
+    Denied,
+
+    /// The ref isn't on the remote, and its name isn't allowed there.
+    InvalidName(String),
+
+    /// Moving the branch back or deleting it, or replacing the trash, would
+    /// leave behind or trash this snapshot, which is held for retention.
+    Held(ObjectHash)
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub enum MirrorResult {
+    Branch(String, MirrorUpdate),
+    Tag(String, MirrorUpdate),
+
+    /// The remote's trash was left as it was, rather than replaced.
+    Trash(MirrorUpdate)
+}
+
+/// How much the mirrored refs reach in a repository.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct MirrorCounts {
+    pub snapshots: usize,
+    pub contents: usize
+}
+
+/// What a mirror did, and how much the refs reach on either end afterwards.
+pub struct MirrorReport {
+    /// The refs that were changed on the remote, or left alone when
+    /// they should have been. Refs that were already the same aren't here.
+    pub results: Vec<MirrorResult>,
+
+    pub local: MirrorCounts,
+    pub remote: MirrorCounts
+}
+
+impl MirrorReport {
+    /// Check that the remote has everything the mirrored refs reach here.
+    pub fn is_complete(&self) -> bool {
+        self.local == self.remote
+    }
+}
+
+/// Get every snapshot the refs mirrored with `options` reach.
+fn mirrored_snapshots(repo: &Repository, options: &MirrorOptions) -> Result<HashSet<ObjectHash>> {
+    let mut tips: Vec<ObjectHash> = repo.refs.branches().values().collect();
+
+    tips.extend(repo.refs.tags().values());
+
+    if options.trash {
+        for entry in repo.trash.entries() {
+            tips.push(entry.hash);
+
+            tips.extend(repo.history.descendants(entry.hash));
+        }
+    }
+
+    if options.stash {
+        tips.extend(repo.stash.iter_entries().map(|entry| entry.basis));
+    }
+
+    repo.history.reachable(tips, |_| false)
+}
+
+/// Get the content stashed in the entries of the stash.
+fn stashed_content(repo: &Repository) -> Vec<ObjectHash> {
+    let hashes: HashSet<ObjectHash> = repo.stash
+        .iter_entries()
+        .flat_map(|entry| entry.state.files.values().copied())
+        .collect();
+
+    hashes.into_iter().collect()
+}
+
+/// Count the snapshots and content the refs mirrored with `options` reach.
+pub fn count_mirrored(repo: &Repository, options: &MirrorOptions) -> Result<MirrorCounts> {
+    let snapshots = mirrored_snapshots(repo, options)?;
+
+    let mut contents = HashSet::new();
+
+    for &hash in &snapshots {
+        contents.extend(repo.fetch_snapshot(hash)?.files.into_values());
+    }
+
+    if options.stash {
+        contents.extend(stashed_content(repo));
+    }
+
+    Ok(MirrorCounts {
+        snapshots: snapshots.len(),
+        contents: contents.len()
+    })
+}
+
+pub async fn handle_mirror_as_client(
+    stream: &mut impl Stream,
+    repo: Repo,
+    remote: &Remote,
+    options: MirrorOptions
+) -> Result<MirrorReport>
+{
+    let mut repo = repo.write().await;
+
+    let user = unwrap!(
+        repo.current_user(),
+        "no valid user set for this repository."
+    );
+
+    login_as(
+        user.public_key,
+        stream,
+        &mut repo,
+        remote
+    ).await?;
+
+    stream.send(&repo.users.without_private_keys()).await?;
+
+    stream.send(&options).await?;
+
+    stream.send(&repo.refs.branches().to_named_items()).await?;
+    stream.send(&repo.refs.tags().to_named_items()).await?;
+    stream.send(repo.refs.tag_stamps()).await?;
+
+    let snapshots: Vec<ObjectHash> = mirrored_snapshots(&repo, &options)?
+        .into_iter()
+        .collect();
+
+    stream.send(&snapshots).await?;
+
+    client_send_objects(stream, &repo).await?;
+
+    if options.trash {
+        stream.send(&repo.trash).await?;
+    }
+
+    if options.stash {
+        stream.send(&repo.stash).await?;
+
+        stream.send(&stashed_content(&repo)).await?;
+
+        let missing: Vec<ObjectHash> = stream.receive().await?;
+
+        let mut contents: HashMap<ObjectHash, Content> = HashMap::new();
+
+        for hash in missing {
+            contents.insert(hash, repo.fetch_content_object(hash)?);
+        }
+
+        stream.send(&contents).await?;
+    }
+
+    let results: Vec<MirrorResult> = stream.receive().await?;
+
+    let remote_counts: MirrorCounts = stream.receive().await?;
+
+    Ok(MirrorReport {
+        results,
+        local: count_mirrored(&repo, &options)?,
+        remote: remote_counts
+    })
+}
+
+/// Make the branches here match `client_branches`, except for those
+/// that have to stay linear and would be moved back or deleted.
+fn mirror_branches(
+    repo: &mut Repository,
+    login: &PublicKey,
+    client_branches: NamedItems<ObjectHash>,
+    results: &mut Vec<MirrorResult>
+) -> Result<()>
+{
+    let removed: Vec<(String, ObjectHash)> = repo.refs
+        .branches()
+        .iter()
+        .filter(|(name, _)| !client_branches.contains(name))
+        .map(|(name, hash)| (name.to_string(), hash))
+        .collect();
+
+    for (name, client_tip) in client_branches.into_iter() {
+        let server_tip = repo.refs.branches().get(&name);
+
+        if server_tip == Some(client_tip) {
+            continue;
+        }
+
+        if server_tip.is_none()
+            && let Err(e) = refname::check_branch(&name, &repo.config)
+        {
+            let reason = e.to_string();
+
+            let note = format!("has a name that isn't allowed - {reason}");
+
+            audit::record(repo, Some(login), SyncEvent::branch_update(&name, None, Some(client_tip), Some(&note)))?;
+
+            results.push(MirrorResult::Branch(name, MirrorUpdate::InvalidName(reason)));
+
+            continue;
+        }
+
+        if repo.config.branches.is_linear(&name) && check_linear(repo, server_tip, client_tip)?.is_some() {
+            audit::record(repo, Some(login), SyncEvent::branch_update(&name, server_tip, Some(client_tip), Some("has to stay linear")))?;
+
+            results.push(MirrorResult::Branch(name, MirrorUpdate::Linear));
+
+            continue;
+        }
+
+        if let Some(old) = server_tip
+            && let Some(hold) = retention::first_hold_left_behind(repo, old, Some(client_tip))?
+        {
+            retention::record_blocked_by(repo, Some(*login), format!("mirror {name} {client_tip}"), &hold)?;
+
+            let note = format!("would leave behind {}, which is held", hold.hash);
+
+            audit::record(repo, Some(login), SyncEvent::branch_update(&name, server_tip, Some(client_tip), Some(&note)))?;
+
+            results.push(MirrorResult::Branch(name, MirrorUpdate::Held(hold.hash)));
+
+            continue;
+        }
+
+        repo.refs.branches_mut().create(name.clone(), client_tip);
+
+        let (action, update) = match server_tip {
+            Some(old) => (
+                Action::MoveBranch { name: name.clone(), old, new: client_tip },
+                MirrorUpdate::Moved(old, client_tip)
+            ),
+
+            None => (
+                Action::CreateBranch { name: name.clone(), hash: client_tip },
+                MirrorUpdate::Created(client_tip)
+            )
+        };
+
+        repo.action_history.push(action);
+
+        audit::record(repo, Some(login), SyncEvent::branch_update(&name, server_tip, Some(client_tip), None))?;
+
+        results.push(MirrorResult::Branch(name, update));
+    }
+
+    for (name, hash) in removed {
+        if repo.config.branches.is_linear(&name) {
+            audit::record(repo, Some(login), SyncEvent::branch_update(&name, Some(hash), None, Some("has to stay linear")))?;
+
+            results.push(MirrorResult::Branch(name, MirrorUpdate::Linear));
+
+            continue;
+        }
+
+        if let Some(hold) = retention::first_hold_left_behind(repo, hash, None)? {
+            retention::record_blocked_by(repo, Some(*login), format!("mirror delete {name}"), &hold)?;
+
+            let note = format!("would leave behind {}, which is held", hold.hash);
+
+            audit::record(repo, Some(login), SyncEvent::branch_update(&name, Some(hash), None, Some(&note)))?;
+
+            results.push(MirrorResult::Branch(name, MirrorUpdate::Held(hold.hash)));
+
+            continue;
+        }
+
+        // The head can't be left on a branch that doesn't exist.
+        if repo.current_branch() == Some(name.as_str()) {
+            repo.refs.detach_head(hash);
+        }
+
+        repo.refs.branches_mut().remove(&name);
+
+        repo.action_history.push(Action::DeleteBranch { name: name.clone(), hash });
+
+        audit::record(repo, Some(login), SyncEvent::branch_update(&name, Some(hash), None, None))?;
+
+        results.push(MirrorResult::Branch(name, MirrorUpdate::Deleted(hash)));
+    }
+
+    Ok(())
+}
+
+/// Make the tags here match `client_tags`, except for
+/// those that are protected or that `login` can't push.
+fn mirror_tags(
+    repo: &mut Repository,
+    login: &PublicKey,
+    client_tags: NamedItems<ObjectHash>,
+    mut client_stamps: BTreeMap<String, TagStamp>,
+    results: &mut Vec<MirrorResult>
+)
+{
+    let removed: Vec<(String, ObjectHash)> = repo.refs
+        .tags()
+        .iter()
+        .filter(|(name, _)| !client_tags.contains(name))
+        .map(|(name, hash)| (name.to_string(), hash))
+        .collect();
+
+    for (name, client_hash) in client_tags.into_iter() {
+        let server_hash = repo.refs.tags().get(&name);
+
+        if server_hash == Some(client_hash) {
+            continue;
+        }
+
+        if server_hash.is_none()
+            && let Err(e) = refname::check_tag(&name, &repo.config)
+        {
+            results.push(MirrorResult::Tag(name, MirrorUpdate::InvalidName(e.to_string())));
+
+            continue;
+        }
+
+        if !repo.config.tags.can_push(&name, &repo.users, login) {
+            results.push(MirrorResult::Tag(name, MirrorUpdate::Denied));
+
+            continue;
+        }
+
+        if server_hash.is_some() && repo.config.tags.is_protected(&name) {
+            results.push(MirrorResult::Tag(name, MirrorUpdate::Protected));
+
+            continue;
+        }
+
+        repo.refs.tags_mut().create(name.clone(), client_hash);
+
+        repo.refs.set_tag_stamp(&name, client_stamps.remove(&name));
+
+        let (action, update) = match server_hash {
+            Some(old) => (
+                Action::MoveTag { name: name.clone(), old, new: client_hash },
+                MirrorUpdate::Moved(old, client_hash)
+            ),
+
+            None => (
+                Action::CreateTag { name: name.clone(), hash: client_hash },
+                MirrorUpdate::Created(client_hash)
+            )
+        };
+
+        repo.action_history.push(action);
+
+        results.push(MirrorResult::Tag(name, update));
+    }
+
+    for (name, hash) in removed {
+        if repo.config.tags.is_protected(&name) {
+            results.push(MirrorResult::Tag(name, MirrorUpdate::Protected));
+
+            continue;
+        }
+
+        if !repo.config.tags.can_push(&name, &repo.users, login) {
+            results.push(MirrorResult::Tag(name, MirrorUpdate::Denied));
+
+            continue;
+        }
+
+        repo.refs.tags_mut().remove(&name);
+
+        repo.refs.set_tag_stamp(&name, None);
+
+        repo.action_history.push(Action::RemoveTag { name: name.clone(), hash });
+
+        results.push(MirrorResult::Tag(name, MirrorUpdate::Deleted(hash)));
+    }
+}
+
+pub async fn handle_mirror_as_server(
+    stream: &mut impl Stream,
+    repo: Repo
+) -> Result<()>
+{
+    // TODO: implement hooks
+    let check = |_: &User| Ok(());
+
+    // Like a push, only take the repository for itself once logged in.
+    let login = {
+        let repo = repo.read().await;
+
+        let Some(login) = handle_login(&repo, stream, check).await? else {
+            return Ok(());
+        };
+
+        audit::record(&repo, Some(&login), SyncEvent::Mirror)?;
+
+        login
+    };
+
+    // Other servers and commands may have saved the repository
+    // since it was loaded, so it is loaded again once locked.
+    let _lock = lock_repo(&repo, LockMode::Exclusive, "mirror").await?;
+
+    let mut repo = repo.write().await;
+
+    let client_users: Users = stream.receive().await?;
+
+    repo.users.merge(client_users);
+
+    // Nothing refers to what is received until the repository
+    // is saved, so keep it safe from a clean until then.
+    let mut fence = Fence::new(&repo)?;
+
+    let options: MirrorOptions = stream.receive().await?;
+
+    let client_branches: NamedItems<ObjectHash> = stream.receive().await?;
+    let client_tags: NamedItems<ObjectHash> = stream.receive().await?;
+    let client_stamps: BTreeMap<String, TagStamp> = stream.receive().await?;
+
+    let snapshots: Vec<ObjectHash> = stream.receive().await?;
+
+    let wanted: Vec<ObjectHash> = snapshots
+        .into_iter()
+        .filter(|&hash| !repo.history.contains(hash) || !repo.has_snapshot(hash))
+        .collect();
+
+    server_receive_objects(stream, &mut repo, &mut fence, wanted).await?;
+
+    let mut results = vec![];
+
+    if options.trash {
+        let trash: Trash = stream.receive().await?;
+
+        // Replacing the trash trashes whatever is new in it, along with
+        // everything after it, so none of that can be held.
+        let trashed: Vec<ObjectHash> = trash
+            .entries()
+            .iter()
+            .filter(|entry| !repo.trash.contains(entry.hash))
+            .flat_map(|entry| iter::once(entry.hash).chain(repo.history.descendants(entry.hash)))
+            .filter(|&hash| repo.has_snapshot(hash))
+            .collect();
+
+        match retention::first_hold(&repo, trashed)? {
+            Some(hold) => {
+                retention::record_blocked_by(&repo, Some(login), "mirror --trash", &hold)?;
+
+                results.push(MirrorResult::Trash(MirrorUpdate::Held(hold.hash)));
+            },
+
+            None => repo.trash = trash
+        }
+    }
+
+    if options.stash {
+        repo.stash = stream.receive().await?;
+
+        let stashed: Vec<ObjectHash> = stream.receive().await?;
+
+        let missing: Vec<ObjectHash> = stashed
+            .into_iter()
+            .filter(|&hash| !repo.has_content(hash))
+            .collect();
+
+        stream.send(&missing).await?;
+
+        let contents: HashMap<ObjectHash, Content> = stream.receive().await?;
+
+        fence.extend(contents.keys().copied())?;
+
+        let blobs = repo.objects();
+
+        for (hash, content) in contents {
+            blobs.save_content_object_async(content, hash).await?;
+        }
+    }
+
+    mirror_branches(&mut repo, &login, client_branches, &mut results)?;
+
+    mirror_tags(&mut repo, &login, client_tags, client_stamps, &mut results);
+
+    repo.save()?;
+
+    drop(fence);
+
+    stream.send(&results).await?;
+
+    stream.send(&count_mirrored(&repo, &options)?).await?;
+
+    Ok(())
+}
@@ -1,10 +1,56 @@
+//! Remotes are part of every repository, so [`remote`] is always here.
+//! Talking to them needs the `sync` feature.
+
 // TODO: add progress reporting
 
+use std::time::Duration;
+
+/// The largest frame either end of a connection accepts,
+/// unless its config says otherwise. Larger messages are sent in chunks.
+pub static DEFAULT_MAX_FRAME_SIZE: u64 = 16 * 1024 * 1024;
+
+/// The largest message a client accepts, across all the frames it is split
+/// into. A clone gets every object in one message, so this is generous.
+pub static DEFAULT_MAX_MESSAGE_SIZE: u64 = 4 * 1024 * 1024 * 1024;
+
+/// The largest message a server accepts from a client, unless its config says
+/// otherwise. Pushes send their objects in batches well under this, and large
+/// files go over their own chunked channel.
+pub static DEFAULT_SERVER_MAX_MESSAGE_SIZE: u64 = 64 * 1024 * 1024;
+
+/// How long a post-push hook can run before it is killed,
+/// unless the config says otherwise.
+pub static DEFAULT_HOOK_TIMEOUT: Duration = Duration::from_secs(30);
+
+pub mod remote;
+
+#[cfg(feature = "sync")]
+pub mod audit;
+#[cfg(feature = "sync")]
 pub mod client;
+#[cfg(feature = "sync")]
 pub mod clone;
+#[cfg(feature = "sync")]
+pub mod hooks;
+#[cfg(feature = "sync")]
+pub mod init;
+#[cfg(feature = "sync")]
+pub mod lfs;
+#[cfg(feature = "sync")]
+pub mod mirror;
+#[cfg(feature = "sync")]
 pub mod server;
+#[cfg(feature = "sync")]
 pub mod pull;
+#[cfg(feature = "sync")]
+pub mod proxy;
+#[cfg(feature = "sync")]
 pub mod push;
-pub mod remote;
+#[cfg(feature = "sync")]
+pub mod session;
+#[cfg(feature = "sync")]
 pub mod stream;
+#[cfg(feature = "sync")]
 pub mod utils;
+#[cfg(feature = "sync")]
+pub mod whoami;
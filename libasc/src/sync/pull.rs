@@ -1,9 +1,9 @@
-use std::collections::{HashMap, HashSet, VecDeque};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 
 use eyre::{Result, eyre};
 use rateless_tables::{Decoder, Encoder};
 
-use crate::{action::Action, content::Content, graph::Graph, hash::ObjectHash, repository::{NamedItems, Repository}, sync::{stream::Stream, utils::{dfs_get, handle_login, login_as, Object, Repo, SendState, DONE, PENDING}}, unwrap, user::User};
+use crate::{action::Action, blobs::AsyncObjectStore, content::Content, fence::Fence, graph::Graph, hash::ObjectHash, lock::LockMode, pin::Pin, refname, refs::{keeps_tag, Ref, RefKind, TagStamp}, repository::{NamedItems, Repository}, sync::{audit::{self, SyncEvent}, remote::Remote, stream::Stream, utils::{dfs_get, handle_login, lock_repo, login_as, save_received_snapshot, Object, Repo, SendState, DONE, PENDING}}, unwrap, user::User};
 
 pub async fn client_fetch_objects(
     stream: &mut impl Stream,
@@ -65,6 +65,8 @@ pub async fn server_serve_objects(
     repo: &Repository
 ) -> Result<()>
 {
+    let blobs = repo.objects();
+
     loop {
         let state: SendState<()> = stream.receive().await?;
 
@@ -75,12 +77,12 @@ pub async fn server_serve_objects(
         let hash: ObjectHash = stream.receive().await?;
 
         let result = if repo.history.contains(hash) {
-            repo.fetch_snapshot(hash)
+            blobs.fetch_snapshot_async(hash).await
                 .map(Box::new)
                 .map(Object::Commit)
         }
         else {
-            repo.fetch_content_object(hash)
+            blobs.fetch_content_object_async(hash).await
                 .map(Object::Content)
         };
 
@@ -107,13 +109,40 @@ pub enum BranchPullResult {
     // (new history, old hash, new hash)
     FastForward(Graph, ObjectHash, ObjectHash),
 
+    // (new history, local tip, remote tip, backup)
+    // The local tip is kept as `backups/<backup>`.
+    Conflict(Graph, ObjectHash, ObjectHash, String),
+
+    // (local tip, remote tip)
+    // The branch has snapshots the remote doesn't, and the remote
+    // has nothing the branch doesn't, so there is nothing to pull.
+    Ahead(ObjectHash, ObjectHash),
+
     // (new history, local tip, remote tip)
-    Conflict(Graph, ObjectHash, ObjectHash)
+    // The remote has snapshots the branch doesn't, which were
+    // fetched without moving the branch, since it isn't checked out.
+    Fetched(Graph, ObjectHash, ObjectHash)
 }
 
 pub enum TagPullResult {
-    Conflict(ObjectHash, ObjectHash),
-    New(ObjectHash)
+    // (local, remote, copy)
+    // The local tag was renamed to the copy's name.
+    Conflict(ObjectHash, ObjectHash, String),
+    New(ObjectHash),
+
+    // (local, remote)
+    // With `tags.replicated`, the remote's version of the tag
+    // was made first, so the local one was moved to match it.
+    Replaced(ObjectHash, ObjectHash),
+
+    // (local, remote)
+    // With `tags.replicated`, the local version of the tag was made
+    // first, or is protected, so it was kept as it is.
+    Kept(ObjectHash, ObjectHash),
+
+    // (remote, reason)
+    // The tag's name isn't allowed here, so it wasn't made.
+    InvalidName(ObjectHash, String)
 }
 
 pub enum PullResult {
@@ -124,12 +153,12 @@ pub enum PullResult {
 pub async fn client_pull_one_branch(
     stream: &mut impl Stream,
     repo: &Repository,
-    branch: &str
+    name: &str
 ) -> Result<BranchPullResult>
 {
-    let local_tip = *repo.branches.get(branch).unwrap();
+    let local_tip = repo.refs.branches().get(name).unwrap();
 
-    stream.send(&(branch, local_tip)).await?;
+    stream.send(&(name, local_tip)).await?;
 
     let remote_tip_if_any: Option<ObjectHash> = stream.receive().await?;
 
@@ -163,20 +192,32 @@ pub async fn client_pull_one_branch(
 
     branch.extend(&changes);
 
-    if branch.is_descendant(remote_tip, local_tip)? {
+    if branch.is_descendant(local_tip, remote_tip)? {
+        Ok(BranchPullResult::Ahead(local_tip, remote_tip))
+    }
+    else if branch.is_descendant(remote_tip, local_tip)? {
         Ok(BranchPullResult::FastForward(branch, local_tip, remote_tip))
     }
     else {
-        Ok(BranchPullResult::Conflict(branch, local_tip, remote_tip))
+        let backup = repo.refs.conflict_copy_name(RefKind::Backup, name, local_tip);
+
+        Ok(BranchPullResult::Conflict(branch, local_tip, remote_tip, backup))
     }
 }
 
+/// Pull the branches and tags of `repo` from a remote.
+///
+/// With `current_only`, only the current branch is moved to its tip on the
+/// remote, and other branches just have the remote's snapshots fetched for
+/// them, so they can be compared with where they are on the remote.
 pub async fn handle_pull_as_client(
     stream: &mut impl Stream,
-    repo: Repo
+    repo: Repo,
+    remote: &Remote,
+    current_only: bool
 ) -> Result<Vec<PullResult>>
 {
-    let mut repo = repo.lock().await;
+    let mut repo = repo.write().await;
 
     let user = unwrap!(
         repo.current_user(),
@@ -186,13 +227,13 @@ pub async fn handle_pull_as_client(
     login_as(
         user.public_key,
         stream,
-        repo.project_code,
-        &mut repo.users
+        &mut repo,
+        remote
     ).await?;
 
-    let branch_names: Vec<_> = repo.branches
-        .iter()
-        .map(|(name, _)| name.clone())
+    let branch_names: Vec<String> = repo.refs.branches()
+        .names()
+        .map(String::from)
         .collect();
 
     let mut pull_results: Vec<PullResult> = vec![];
@@ -201,21 +242,32 @@ pub async fn handle_pull_as_client(
         stream.send(&PENDING).await?;
     
         let result = client_pull_one_branch(stream, &repo, &name).await?;
+
+        let result = match result {
+            BranchPullResult::FastForward(graph, local_tip, remote_tip) | BranchPullResult::Conflict(graph, local_tip, remote_tip, _)
+                if current_only && repo.current_branch() != Some(name.as_str()) =>
+            {
+                BranchPullResult::Fetched(graph, local_tip, remote_tip)
+            }
+
+            result => result
+        };
         
         match &result {
             BranchPullResult::NotOnRemote => {},
             BranchPullResult::UpToDate => {},
+            BranchPullResult::Ahead(..) => {},
 
-            BranchPullResult::FastForward(graph, local_tip, remote_tip) => {
-                if repo.current_hash == *local_tip {
-                    repo.current_hash = *remote_tip;
-                }
-                
+            BranchPullResult::Fetched(graph, ..) => {
+                repo.history.extend(graph);
+            }
+
+            BranchPullResult::FastForward(graph, _, remote_tip) => {
                 repo.history.extend(graph);
 
-                let old = *repo.branches.get(&name).unwrap();
+                let old = repo.refs.branches().get(&name).unwrap();
                 
-                repo.branches.create(name.clone(), *remote_tip);
+                repo.refs.branches_mut().create(name.clone(), *remote_tip);
 
                 repo.action_history.push(
                     Action::MoveBranch {
@@ -226,16 +278,12 @@ pub async fn handle_pull_as_client(
                 );
             }
 
-            BranchPullResult::Conflict(graph, local_tip, remote_tip) => {
-                if repo.current_hash == *local_tip {
-                    repo.current_hash = *remote_tip;
-                }
-                
+            BranchPullResult::Conflict(graph, local_tip, remote_tip, backup) => {
                 repo.history.extend(graph);
 
-                repo.branches.create(format!("local/{name}"), *local_tip);
+                repo.refs.set(Ref::Backup(backup.clone()), *local_tip);
                 
-                repo.branches.create(name.clone(), *remote_tip);
+                repo.refs.branches_mut().create(name.clone(), *remote_tip);
 
                 repo.action_history.push(
                     Action::MoveBranch {
@@ -244,13 +292,6 @@ pub async fn handle_pull_as_client(
                         new: *remote_tip
                     }
                 );
-
-                repo.action_history.push(
-                    Action::CreateBranch {
-                        name: format!("local/{name}"),
-                        hash: *local_tip
-                    }
-                );
             }
         }
 
@@ -259,37 +300,88 @@ pub async fn handle_pull_as_client(
 
     stream.send(&DONE).await?;
 
-    stream.send(&repo.tags).await?;
+    stream.send(&repo.refs.tags().to_named_items()).await?;
+
+    let client_stamps = repo.config.tags.replicated.then(|| repo.refs.tag_stamps().clone());
+
+    stream.send(&client_stamps).await?;
+
+    stream.send(&repo.config.sync.pins).await?;
 
     let new_tags: NamedItems<ObjectHash> = stream.receive().await?;
 
+    // Only sent when both sides have `tags.replicated` set.
+    let server_stamps: Option<BTreeMap<String, TagStamp>> = stream.receive().await?;
+
+    // Likewise with `sync.pins`.
+    let server_pins: Option<Vec<Pin>> = stream.receive().await?;
+
     for (name, server_hash) in new_tags.into_iter() {
-        let tag_result = match repo.tags.get(&name) {
-            Some(&client_hash) if client_hash != server_hash => {
-                repo.tags.rename(&name, format!("{name}-local"));
+        let server_stamp = server_stamps
+            .as_ref()
+            .and_then(|stamps| stamps.get(&name))
+            .cloned();
+
+        let tag_result = match repo.refs.tags().get(&name) {
+            Some(client_hash) if client_hash != server_hash && server_stamps.is_some() => {
+                let kept = repo.config.tags.is_protected(&name)
+                    || keeps_tag((client_hash, repo.refs.tag_stamp(&name)), (server_hash, server_stamp.as_ref()));
+
+                if kept {
+                    TagPullResult::Kept(client_hash, server_hash)
+                }
+                else {
+                    repo.refs.tags_mut().create(name.clone(), server_hash);
+
+                    repo.refs.set_tag_stamp(&name, server_stamp);
+
+                    repo.action_history.push(
+                        Action::MoveTag {
+                            name: name.to_string(),
+                            old: client_hash,
+                            new: server_hash
+                        }
+                    );
+
+                    TagPullResult::Replaced(client_hash, server_hash)
+                }
+            },
+
+            Some(client_hash) if client_hash != server_hash => {
+                let copy = repo.refs.conflict_copy_name(RefKind::Tag, &name, client_hash);
+
+                repo.refs.tags_mut().rename(&name, copy.clone());
                 
                 repo.action_history.push(
                     Action::RenameTag {
                         old: name.to_string(),
-                        new: format!("{name}-local"),
+                        new: copy.clone(),
                         hash: client_hash
                     }
                 );
 
-                repo.tags.create(name.clone(), server_hash);
+                repo.refs.tags_mut().create(name.clone(), server_hash);
 
                 repo.action_history.push(
                     Action::CreateTag {
                         name: name.to_string(),
-                        hash: client_hash
+                        hash: server_hash
                     }
                 );
 
-                TagPullResult::Conflict(client_hash, server_hash)
+                TagPullResult::Conflict(client_hash, server_hash, copy)
             },
             
             None => {
-                repo.tags.create(name.to_string(), server_hash);
+                if let Err(e) = refname::check_tag(&name, &repo.config) {
+                    pull_results.push(PullResult::Tag(name, TagPullResult::InvalidName(server_hash, e.to_string())));
+
+                    continue;
+                }
+
+                repo.refs.tags_mut().create(name.to_string(), server_hash);
+
+                repo.refs.set_tag_stamp(&name, server_stamp);
 
                 repo.action_history.push(
                     Action::CreateTag {
@@ -309,14 +401,26 @@ pub async fn handle_pull_as_client(
 
     let new_objects = client_fetch_objects(stream, &repo).await?;
 
+    // Nothing on disk points to the new objects until the repository
+    // is saved, so keep them safe from a clean in the meantime.
+    let mut fence = Fence::new(&repo)?;
+
+    fence.extend(new_objects.keys().copied())?;
+
     for (hash, object) in new_objects {
         match object {
-            Object::Commit(snapshot) => repo.save_snapshot(*snapshot)?,
+            Object::Commit(snapshot) => { save_received_snapshot(&mut repo, *snapshot)?; },
             Object::Content(content) => repo.save_content_object(content, hash)?
         }
     }
 
+    if let Some(pins) = server_pins {
+        repo.merge_pins(pins);
+    }
+
     repo.save()?;
+
+    drop(fence);
     
     Ok(pull_results)
 }
@@ -325,12 +429,26 @@ pub async fn handle_pull_as_server(
     stream: &mut impl Stream,
     repo: Repo
 ) -> Result<()> {
-    let repo = repo.lock().await;
-
     // TODO: implement hooks
     let check = |_: &User| Ok(());
 
-    handle_login(&repo, stream, check).await?;
+    // Clients are logged in, and held to their limit on logins, before the
+    // lock is taken, so one that is refused never keeps out writers.
+    {
+        let repo = repo.read().await;
+
+        let Some(login) = handle_login(&repo, stream, check).await? else {
+            return Ok(());
+        };
+
+        audit::record(&repo, Some(&login), SyncEvent::Pull)?;
+    }
+
+    // Pulling doesn't change the repository, so it shares the lock with
+    // other readers, and only keeps out writers meanwhile.
+    let _lock = lock_repo(&repo, LockMode::Shared, "pull").await?;
+
+    let repo = repo.read().await;
 
     loop {
         let do_branches: SendState<()> = stream.receive().await?;
@@ -341,7 +459,7 @@ pub async fn handle_pull_as_server(
 
         let (branch_name, client_tip): (String, ObjectHash) = stream.receive().await?;
     
-        let Some(&server_tip) = repo.branches.get(&branch_name) else {
+        let Some(server_tip) = repo.refs.branches().get(&branch_name) else {
             stream.send(&None::<()>).await?;
 
             continue;
@@ -398,9 +516,15 @@ pub async fn handle_pull_as_server(
 
     let client_tags: NamedItems<ObjectHash> = stream.receive().await?;
 
+    let client_stamps: Option<BTreeMap<String, TagStamp>> = stream.receive().await?;
+
+    let replicated = client_stamps.is_some() && repo.config.tags.replicated;
+
+    let wants_pins: bool = stream.receive().await?;
+
     let mut new_tags = NamedItems::new();
 
-    for (name, &server_hash) in repo.tags.iter() {
+    for (name, server_hash) in repo.refs.tags().iter() {
         if let Some(&client_hash) = client_tags.get(name)
             && client_hash == server_hash
         {
@@ -410,8 +534,21 @@ pub async fn handle_pull_as_server(
         new_tags.create(name.to_string(), server_hash);
     }
 
+    let server_stamps: Option<BTreeMap<String, TagStamp>> = replicated.then(|| {
+        new_tags
+            .iter()
+            .filter_map(|(name, _)| Some((name.to_string(), repo.refs.tag_stamp(name)?.clone())))
+            .collect()
+    });
+
     stream.send(&new_tags).await?;
 
+    stream.send(&server_stamps).await?;
+
+    let server_pins = (wants_pins && repo.config.sync.pins).then(|| repo.pins.iter().cloned().collect::<Vec<Pin>>());
+
+    stream.send(&server_pins).await?;
+
     server_serve_objects(stream, &repo).await?;
 
     Ok(())
@@ -1,22 +1,48 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 
 use eyre::Result;
 use rateless_tables::{Decoder, Encoder};
 use serde::{Deserialize, Serialize};
 
-use crate::{action::Action, graph::Graph, hash::ObjectHash, repository::{NamedItems, Repository}, sync::{stream::Stream, utils::{dfs_get, handle_login, login_as, Object, Repo, SendState, DONE, PENDING}}, unwrap, user::User};
+use crate::{action::Action, blobs::AsyncObjectStore, fence::Fence, graph::Graph, hash::ObjectHash, lock::LockMode, pin::Pin, refname, refs::{keeps_tag, TagStamp}, repository::{NamedItems, Repository}, retention, sync::{audit::{self, SyncEvent}, hooks::{self, BranchUpdate}, remote::Remote, stream::Stream, utils::{dfs_get, handle_login, lock_repo, login_as, save_received_snapshot, Object, Repo, SendState, DONE, PENDING}}, unwrap, user::{User, Users}};
 
+#[derive(Debug, Deserialize, Serialize)]
 pub enum BranchPushResult {
     CreatedOnRemote,
     UpToDate,
     FastForward(ObjectHash, ObjectHash),
-    SplitHistory
+    SplitHistory,
+
+    /// The branch has to keep a linear history on the remote,
+    /// and this merge snapshot would have been brought into it.
+    Merge(ObjectHash),
+
+    /// The branch is new on the remote, and its name isn't allowed there.
+    InvalidName(String),
+
+    /// The push would move the branch back on the remote, leaving
+    /// behind this snapshot, which is held there for retention.
+    Held(ObjectHash)
 }
 
 #[derive(Debug, Deserialize, Serialize)]
 pub enum TagPushResult {
     Conflict,
-    CreatedOnRemote
+    CreatedOnRemote,
+
+    /// The tag is in a namespace the user can't push to.
+    Denied,
+
+    /// With `tags.replicated`, the local version of the tag
+    /// was made first, so the remote's was moved to match it.
+    Replaced,
+
+    /// With `tags.replicated`, the remote's version of the tag was made
+    /// first, or can't be moved, so it was kept. Pulling moves the local one.
+    Kept,
+
+    /// The tag is new on the remote, and its name isn't allowed there.
+    InvalidName(String)
 }
 
 pub enum PushResult {
@@ -24,13 +50,77 @@ pub enum PushResult {
     Tag(String, TagPushResult)
 }
 
+/// How many bytes of objects go in one message when pushing, which
+/// keeps each well under what a server accepts.
+const OBJECT_BATCH_SIZE: usize = 8 * 1024 * 1024;
+
+/// Send the snapshots the server asks for, along with their content.
+/// 
+/// The server says which content it already has, so only what is
+/// missing is sent. This is the other side of [`server_receive_objects`].
+pub(crate) async fn client_send_objects(stream: &mut impl Stream, repo: &Repository) -> Result<()> {
+    let requested: Vec<ObjectHash> = stream.receive().await?;
+
+    let mut snapshots = vec![];
+
+    let mut content_hashes = HashSet::new();
+
+    for hash in requested {
+        let snapshot = repo.fetch_snapshot(hash)?;
+
+        content_hashes.extend(snapshot.files.values().copied());
+
+        snapshots.push((hash, Object::Commit(Box::new(snapshot))));
+    }
+
+    let content_hashes: Vec<ObjectHash> = content_hashes.into_iter().collect();
+
+    stream.send(&content_hashes).await?;
+
+    let held: HashSet<ObjectHash> = stream.receive().await?;
+
+    // Content goes first, so the server never
+    // saves a snapshot before what it refers to.
+    let contents = content_hashes
+        .into_iter()
+        .filter(|hash| !held.contains(hash))
+        .map(|hash| repo.fetch_content_object(hash).map(|content| (hash, Object::Content(content))));
+
+    let mut batch: HashMap<ObjectHash, Object> = HashMap::new();
+    let mut batch_size = 0;
+
+    for object in contents.chain(snapshots.into_iter().map(Ok)) {
+        let (hash, object) = object?;
+
+        batch_size += rmp_serde::to_vec(&object)?.len();
+        batch.insert(hash, object);
+
+        if batch_size >= OBJECT_BATCH_SIZE {
+            stream.send(&PENDING).await?;
+            stream.send(&batch).await?;
+
+            batch.clear();
+            batch_size = 0;
+        }
+    }
+
+    if !batch.is_empty() {
+        stream.send(&PENDING).await?;
+        stream.send(&batch).await?;
+    }
+
+    stream.send(&DONE).await?;
+
+    Ok(())
+}
+
 pub async fn client_push_one_branch(
     stream: &mut impl Stream,
     repo: &Repository,
     branch: &str
 ) -> Result<BranchPushResult>
 {
-    let local_tip = *repo.branches.get(branch).unwrap();
+    let local_tip = repo.refs.branches().get(branch).unwrap();
 
     stream.send(&(branch, local_tip)).await?;
 
@@ -71,31 +161,13 @@ pub async fn client_push_one_branch(
         }
     }
 
-    let requested: Vec<ObjectHash> = stream.receive().await?;
-
-    let mut objects: HashMap<ObjectHash, Object> = HashMap::new();
-
-    for hash in requested {
-        let object = if repo.history.contains(hash) {
-            let snapshot = repo.fetch_snapshot(hash)?;
-
-            for &content_hash in snapshot.files.values() {
-                let content = repo.fetch_content_object(content_hash)?;
+    client_send_objects(stream, repo).await?;
 
-                objects.insert(content_hash, Object::Content(content));
-            }
-
-            Ok(Object::Commit(Box::new(snapshot)))
-        }
-        else {
-            repo.fetch_content_object(hash)
-                .map(Object::Content)
-        }?;
+    let rejected: Option<BranchPushResult> = stream.receive().await?;
 
-        objects.insert(hash, object);
+    if let Some(result) = rejected {
+        return Ok(result);
     }
-
-    stream.send(&objects).await?;
     
     let result = if let Some(remote_tip) = remote_tip_if_any {
         BranchPushResult::FastForward(remote_tip, local_tip)
@@ -110,10 +182,12 @@ pub async fn client_push_one_branch(
 
 pub async fn handle_push_as_client(
     stream: &mut impl Stream,
-    repo: Repo
+    repo: Repo,
+    remote: &Remote,
+    ephemeral: bool
 ) -> Result<Vec<PushResult>>
 {
-    let mut repo = repo.lock().await;
+    let mut repo = repo.write().await;
 
     let user = unwrap!(
         repo.current_user(),
@@ -123,13 +197,22 @@ pub async fn handle_push_as_client(
     login_as(
         user.public_key,
         stream,
-        repo.project_code,
-        &mut repo.users
+        &mut repo,
+        remote
     ).await?;
 
+    // Accounts made here, like from an approved invitation,
+    // have to be on the server before their snapshots can be.
+    stream.send(&repo.users.without_private_keys()).await?;
+
     let mut results: Vec<PushResult> = vec![];
 
-    for branch in repo.branches.names() {
+    for branch in repo.refs.branches().names() {
+        // Ephemeral branches are only pushed when asked for.
+        if !ephemeral && repo.refs.is_ephemeral(branch) {
+            continue;
+        }
+
         stream.send(&PENDING).await?;
 
         let branch_result = client_push_one_branch(stream, &repo, branch).await?;
@@ -139,7 +222,15 @@ pub async fn handle_push_as_client(
 
     stream.send(&DONE).await?;
 
-    stream.send(&repo.tags).await?;
+    stream.send(&repo.refs.tags().to_named_items()).await?;
+
+    let stamps = repo.config.tags.replicated.then(|| repo.refs.tag_stamps().clone());
+
+    stream.send(&stamps).await?;
+
+    let pins = repo.config.sync.pins.then(|| repo.pins.iter().cloned().collect::<Vec<Pin>>());
+
+    stream.send(&pins).await?;
 
     let tag_results: HashMap<String, TagPushResult> = stream.receive().await?;
 
@@ -147,25 +238,124 @@ pub async fn handle_push_as_client(
         results.push(PushResult::Tag(name, tag_result));
     }
 
-    let missing_on_server: Vec<ObjectHash> = stream.receive().await?;
+    client_send_objects(stream, &repo).await?;
 
-    let mut requested_objects = HashMap::new();
+    // Wait for the server to save, so the push isn't
+    // cut short by the client finishing first.
+    let _: SendState<()> = stream.receive().await?;
+    
+    Ok(results)
+}
 
-    for hash in missing_on_server {
-        let snapshot = repo.fetch_snapshot(hash)?;
+/// Receive the snapshots in `wanted` from the client, and their content.
+/// 
+/// Objects already stored here, like those left behind by a push that
+/// was interrupted before the repository was saved, aren't asked for
+/// again. Snapshots among them are put back in the history.
+pub(crate) async fn server_receive_objects(
+    stream: &mut impl Stream,
+    repo: &mut Repository,
+    fence: &mut Fence,
+    wanted: Vec<ObjectHash>
+) -> Result<()>
+{
+    let mut missing = wanted;
+
+    // A snapshot can only go back in the history once its
+    // parents are there, so keep going until nothing changes.
+    loop {
+        let before = missing.len();
 
-        for &content_hash in snapshot.files.values() {
-            let content = repo.fetch_content_object(content_hash)?;
+        let mut remaining = vec![];
 
-            requested_objects.insert(hash, Object::Content(content));
+        for hash in missing {
+            if repo.restore_snapshot(hash)? {
+                fence.extend([hash])?;
+            }
+            else {
+                remaining.push(hash);
+            }
         }
 
-        requested_objects.insert(hash, Object::Commit(Box::new(snapshot)));
+        missing = remaining;
+
+        if missing.len() == before {
+            break;
+        }
     }
 
-    stream.send(&requested_objects).await?;
-    
-    Ok(results)
+    stream.send(&missing).await?;
+
+    let content_hashes: Vec<ObjectHash> = stream.receive().await?;
+
+    let held: HashSet<ObjectHash> = content_hashes
+        .into_iter()
+        .filter(|&hash| repo.has_content(hash))
+        .collect();
+
+    fence.extend(held.iter().copied())?;
+
+    stream.send(&held).await?;
+
+    let blobs = repo.objects();
+
+    // The objects come in batches, each saved as it arrives.
+    loop {
+        let state: SendState<()> = stream.receive().await?;
+
+        if state == DONE {
+            break;
+        }
+
+        let objects: HashMap<ObjectHash, Object> = stream.receive().await?;
+
+        fence.extend(objects.keys().copied())?;
+
+        // Content goes first, so no snapshot is
+        // saved without the content it refers to.
+        let (snapshots, contents): (Vec<_>, Vec<_>) = objects
+            .into_iter()
+            .partition(|(_, object)| matches!(object, Object::Commit(_)));
+
+        for (hash, object) in contents.into_iter().chain(snapshots) {
+            match object {
+                Object::Commit(snapshot) => { save_received_snapshot(repo, *snapshot)?; },
+                Object::Content(content) => blobs.save_content_object_async(content, hash).await?
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Check that moving a branch from `server_tip` to `client_tip` keeps its
+/// history linear, giving why it doesn't if not. The move has to be a
+/// fast-forward, and none of the snapshots it adds can be merges.
+pub(crate) fn check_linear(
+    repo: &Repository,
+    server_tip: Option<ObjectHash>,
+    client_tip: ObjectHash
+) -> Result<Option<BranchPushResult>>
+{
+    let held = match server_tip {
+        Some(tip) => {
+            if !repo.history.is_descendant(client_tip, tip)? {
+                return Ok(Some(BranchPushResult::SplitHistory));
+            }
+
+            repo.history.reachable([tip], |_| false)?
+        },
+
+        None => HashSet::new()
+    };
+
+    let added = repo.history.reachable([client_tip], |hash| held.contains(&hash))?;
+
+    let merge = added
+        .into_iter()
+        .find(|&hash| repo.history.get_parents(hash).is_some_and(|parents| parents.len() > 1));
+
+    Ok(merge.map(BranchPushResult::Merge))
 }
 
 pub async fn handle_push_as_server(
@@ -173,12 +363,39 @@ pub async fn handle_push_as_server(
     repo: Repo
 ) -> Result<()>
 {
-    let mut repo = repo.lock().await;
-
     // TODO: implement hooks
     let check = |_: &User| Ok(());
 
-    handle_login(&repo, stream, check).await?;
+    // Logging in waits on the client, so other connections can keep
+    // reading the repository until there is something to change.
+    let login = {
+        let repo = repo.read().await;
+
+        let Some(login) = handle_login(&repo, stream, check).await? else {
+            return Ok(());
+        };
+
+        audit::record(&repo, Some(&login), SyncEvent::Push)?;
+
+        login
+    };
+
+    // Other servers and commands may have saved the repository
+    // since it was loaded, so it is loaded again once locked.
+    let lock = lock_repo(&repo, LockMode::Exclusive, "push").await?;
+
+    let mut repo = repo.write().await;
+
+    let client_users: Users = stream.receive().await?;
+
+    repo.users.merge(client_users);
+
+    // Objects received from the client aren't referenced by any branch
+    // on disk until the repository is saved, so keep them safe from a
+    // clean in the meantime.
+    let mut fence = Fence::new(&repo)?;
+
+    let mut updates = vec![];
 
     loop {
         let state: SendState<()> = stream.receive().await?;
@@ -189,7 +406,7 @@ pub async fn handle_push_as_server(
 
         let (branch_name, client_tip): (String, ObjectHash) = stream.receive().await?;
 
-        let server_tip_if_any = repo.branches.get(&branch_name);
+        let server_tip_if_any = repo.refs.branches().get(&branch_name);
 
         stream.send(&server_tip_if_any).await?;
 
@@ -203,7 +420,7 @@ pub async fn handle_push_as_server(
 
         let mut dec = Decoder::default();
 
-        if let Some(&server_tip) = server_tip_if_any {
+        if let Some(server_tip) = server_tip_if_any {
             dfs_get(&repo.history, server_tip, &mut branch);
         }
 
@@ -227,24 +444,56 @@ pub async fn handle_push_as_server(
 
         let (changes, _) = dec.consume();
 
-        stream.send(&changes).await?;
+        server_receive_objects(stream, &mut repo, &mut fence, changes).await?;
 
-        let requested: HashMap<ObjectHash, Object> = stream.receive().await?;
+        // The client only checks that it is pushing a fast-forward, so a
+        // branch moved back is caught here, if it would leave history behind.
+        let held = match server_tip_if_any {
+            Some(server_tip) => retention::first_hold_left_behind(&repo, server_tip, Some(client_tip))?,
+            None => None
+        };
 
-        for (hash, object) in requested {
-            match object {
-                Object::Commit(snapshot) => repo.save_snapshot(*snapshot)?,
-                Object::Content(content) => repo.save_content_object(content, hash)?
+        let rejected = if server_tip_if_any.is_none()
+            && let Err(e) = refname::check_branch(&branch_name, &repo.config)
+        {
+            Some(BranchPushResult::InvalidName(e.to_string()))
+        }
+        else if let Some(hold) = &held {
+            Some(BranchPushResult::Held(hold.hash))
+        }
+        else if repo.config.branches.is_linear(&branch_name) {
+            check_linear(&repo, server_tip_if_any, client_tip)?
+        }
+        else {
+            None
+        };
+
+        stream.send(&rejected).await?;
+
+        if let Some(result) = &rejected {
+            let reason = match result {
+                BranchPushResult::Merge(hash) => format!("has to stay linear, but {hash} is a merge"),
+                BranchPushResult::InvalidName(reason) => format!("has a name that isn't allowed - {reason}"),
+                BranchPushResult::Held(hash) => format!("would leave behind {hash}, which is held"),
+                _ => "has to stay linear, but the push isn't a fast-forward".to_string()
+            };
+
+            if let Some(hold) = &held {
+                retention::record_blocked_by(&repo, Some(login), format!("push {branch_name} {client_tip}"), hold)?;
             }
+
+            audit::record(&repo, Some(&login), SyncEvent::branch_update(&branch_name, server_tip_if_any, Some(client_tip), Some(&reason)))?;
+
+            continue;
         }
 
-        let previous = repo.branches.create(branch_name.clone(), client_tip);
+        let previous = repo.refs.branches_mut().create(branch_name.clone(), client_tip);
+
+        updates.push(BranchUpdate::new(&repo.project_name, &branch_name, previous, client_tip, &login));
+
+        audit::record(&repo, Some(&login), SyncEvent::branch_update(&branch_name, previous, Some(client_tip), None))?;
 
         let action = if let Some(old) = previous {
-            if old == repo.current_hash {
-                repo.current_hash = client_tip;
-            }
-            
             Action::MoveBranch {
                 name: branch_name,
                 old,
@@ -263,15 +512,45 @@ pub async fn handle_push_as_server(
 
     let client_tags: NamedItems<ObjectHash> = stream.receive().await?;
 
+    let mut client_stamps: Option<BTreeMap<String, TagStamp>> = stream.receive().await?;
+
+    if !repo.config.tags.replicated {
+        client_stamps = None;
+    }
+
+    let mut client_pins: Option<Vec<Pin>> = stream.receive().await?;
+
+    if !repo.config.sync.pins {
+        client_pins = None;
+    }
+
     let mut tag_results: HashMap<String, TagPushResult> = HashMap::new();
 
     let mut needed_snapshots = Vec::new();
 
     for (name, client_hash) in client_tags.into_iter() {
-        let Some(&server_hash) = repo.tags.get(&name) else {
+        let client_stamp = client_stamps
+            .as_mut()
+            .and_then(|stamps| stamps.remove(&name));
+
+        let Some(server_hash) = repo.refs.tags().get(&name) else {
+            if let Err(e) = refname::check_tag(&name, &repo.config) {
+                tag_results.insert(name, TagPushResult::InvalidName(e.to_string()));
+
+                continue;
+            }
+
+            if !repo.config.tags.can_push(&name, &repo.users, &login) {
+                tag_results.insert(name, TagPushResult::Denied);
+
+                continue;
+            }
+
             needed_snapshots.push(client_hash);
 
-            repo.tags.create(name.to_string(), client_hash);
+            repo.refs.tags_mut().create(name.to_string(), client_hash);
+
+            repo.refs.set_tag_stamp(&name, client_stamp);
 
             tag_results.insert(name, TagPushResult::CreatedOnRemote);
 
@@ -282,23 +561,150 @@ pub async fn handle_push_as_server(
             continue;
         }
 
-        tag_results.insert(name, TagPushResult::Conflict);
-    }
+        if client_stamps.is_none() {
+            tag_results.insert(name, TagPushResult::Conflict);
 
-    stream.send(&tag_results).await?;
+            continue;
+        }
 
-    stream.send(&needed_snapshots).await?;
+        let kept = repo.config.tags.is_protected(&name)
+            || !repo.config.tags.can_push(&name, &repo.users, &login)
+            || keeps_tag((server_hash, repo.refs.tag_stamp(&name)), (client_hash, client_stamp.as_ref()));
 
-    let tag_objects: HashMap<ObjectHash, Object> = stream.receive().await?;
+        if kept {
+            tag_results.insert(name, TagPushResult::Kept);
 
-    for (hash, object) in tag_objects {
-        match object {
-            Object::Commit(snapshot) => repo.save_snapshot(*snapshot)?,
-            Object::Content(content) => repo.save_content_object(content, hash)?
+            continue;
         }
+
+        needed_snapshots.push(client_hash);
+
+        repo.refs.tags_mut().create(name.to_string(), client_hash);
+
+        repo.refs.set_tag_stamp(&name, client_stamp);
+
+        repo.action_history.push(
+            Action::MoveTag {
+                name: name.to_string(),
+                old: server_hash,
+                new: client_hash
+            }
+        );
+
+        tag_results.insert(name, TagPushResult::Replaced);
+    }
+
+    stream.send(&tag_results).await?;
+
+    server_receive_objects(stream, &mut repo, &mut fence, needed_snapshots).await?;
+
+    if let Some(pins) = client_pins {
+        repo.merge_pins(pins);
     }
 
     repo.save()?;
 
+    drop(fence);
+
+    stream.send(&DONE).await?;
+
+    if updates.is_empty() {
+        return Ok(());
+    }
+
+    let config = repo.config.hooks.clone();
+    let root_dir = repo.root_dir.clone();
+
+    // The push is finished by now, so the client isn't kept waiting on
+    // the hooks, and nor is anyone else waiting on the repository.
+    drop(repo);
+    drop(lock);
+
+    // Hooks and webhooks can take a while, so they shouldn't
+    // hold up the other connections on the runtime.
+    unwrap!(
+        tokio::task::spawn_blocking(move || hooks::notify_push(&config, &root_dir, &updates)).await,
+        "failed to run the post-push hooks"
+    );
+
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use chrono::Utc;
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::{content::Content, snapshot::Snapshot, sync::stream::{ConnectionLimits, local_duplex}};
+
+    fn temp_repo() -> (TempDir, Repository) {
+        let dir = tempfile::tempdir().unwrap();
+
+        let repo = Repository::create_new(dir.path(), "axo".to_string(), "test".to_string()).unwrap();
+
+        (dir, repo)
+    }
+
+    /// Bytes that don't get any smaller when compressed.
+    fn noise(len: usize, mut seed: u64) -> Vec<u8> {
+        (0..len)
+            .map(|_| {
+                seed ^= seed << 13;
+                seed ^= seed >> 7;
+                seed ^= seed << 17;
+
+                seed as u8
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn pushes_larger_than_a_message_are_sent_in_batches() {
+        let (_client_dir, mut client) = temp_repo();
+        let (_server_dir, mut server) = temp_repo();
+
+        server.users = client.users.clone();
+
+        let mut files = BTreeMap::new();
+
+        for i in 0..4u8 {
+            let hash = ObjectHash::from([i + 1; 32]);
+
+            let content = Content::Literal(noise(5 * 1024 * 1024, i as u64 + 1));
+
+            client.objects().save_content_object(content, hash).unwrap();
+
+            files.insert(format!("file-{i}").into(), hash);
+        }
+
+        let key = client.current_user().unwrap().private_key.clone().unwrap();
+
+        let snapshot = Snapshot::new(key, "big".to_string(), Utc::now(), files.clone(), HashSet::new());
+
+        let hash = client.save_snapshot(snapshot).unwrap();
+
+        let (mut client_stream, mut server_stream) = local_duplex();
+
+        // Any one message holding all of it would be refused.
+        server_stream.framing().set_limits(ConnectionLimits {
+            max_message_size: Some(2 * OBJECT_BATCH_SIZE as u64),
+            ..Default::default()
+        });
+
+        let mut fence = Fence::new(&server).unwrap();
+
+        tokio::try_join!(
+            client_send_objects(&mut client_stream, &client),
+            server_receive_objects(&mut server_stream, &mut server, &mut fence, vec![hash])
+        ).unwrap();
+
+        assert!(server.history.contains(hash));
+
+        for content_hash in files.into_values() {
+            assert!(server.has_content(content_hash));
+        }
+    }
+}
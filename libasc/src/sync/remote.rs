@@ -6,6 +6,24 @@ use serde::{Deserialize, Serialize};
 
 use crate::unwrap;
 
+/// Take the brackets off an IPv6 literal, like `[::1]`.
+pub fn unbracket(host: &str) -> &str {
+    host.strip_prefix('[')
+        .and_then(|host| host.strip_suffix(']'))
+        .unwrap_or(host)
+}
+
+/// Put brackets around a host if it is an IPv6 literal,
+/// so a port can go after it.
+pub fn bracket(host: &str) -> String {
+    if host.contains(':') {
+        format!("[{host}]")
+    }
+    else {
+        host.to_string()
+    }
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct SshRemote {
     username: String,
@@ -34,10 +52,19 @@ impl SshRemote {
         format!(
             "ssh://{}{password}@{}{port}",
             self.username,
-            self.host,
+            bracket(&self.host),
         )
     }
 
+    /// Get the host, without brackets if it is an IPv6 literal.
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
     pub fn path(&self) -> &PathBuf {
         &self.repo_path
     }
@@ -90,7 +117,7 @@ impl Remote {
             .map(String::from);
 
         let host = unwrap!(
-            url.host().map(String::from),
+            url.host().map(|host| unbracket(host).to_string()),
             "host missing from SSH URL"
         );
 
@@ -113,6 +140,11 @@ impl Remote {
         Ok(Remote::File(file_remote))
     }
 
+    /// Create a remote for a repository on the local file system.
+    pub fn from_path(path: impl Into<PathBuf>) -> Self {
+        Remote::File(FileRemote { path: path.into() })
+    }
+
     pub fn from_url(url: &str) -> Result<Self> {
         let parsed = GitUrl::parse(url)?;
 
@@ -138,7 +170,7 @@ impl Display for Remote {
                     path = path.split_off(1);
                 }
 
-                write!(f, "ssh://{}@{}/{}", ssh.username, ssh.host, path)
+                write!(f, "ssh://{}@{}/{}", ssh.username, bracket(&ssh.host), path)
             },
             
             Remote::File(FileRemote { path }) => {
@@ -1,21 +1,81 @@
-use eyre::Result;
+use std::path::Path;
+
+use eyre::{Result, bail};
 use serde::{Deserialize, Serialize};
 
-use crate::{sync::{clone::handle_clone_as_server, pull::handle_pull_as_server, push::handle_push_as_server, stream::Stream, utils::Repo}};
+use crate::{lock::LockMode, sync::{clone::handle_clone_as_server, init::{ServerConfig, handle_init_as_server, refuse_init}, lfs::{handle_lfs_fetch_as_server, handle_lfs_push_as_server}, mirror::handle_mirror_as_server, pull::handle_pull_as_server, push::handle_push_as_server, stream::{DEFAULT_MAX_FRAME_SIZE, Stream, negotiate_as_server}, utils::{Repo, lock_repo}, whoami::handle_whoami_as_server}};
 
 #[derive(Deserialize, Serialize)]
 pub enum Method {
     Push,
     Pull,
-    Clone
+    Clone,
+    Init,
+    LfsFetch,
+    LfsPush,
+    WhoAmI,
+    Mirror
 }
 
-pub async fn handle_server(stream: &mut impl Stream, repo: Repo) -> Result<()> {
+/// Handle a connection to a repository.
+/// 
+/// `config` holds the server's settings, which tell clients what
+/// they can do on the server and limit what each connection can use.
+pub async fn handle_server(
+    stream: &mut impl Stream,
+    repo: Repo,
+    config: Option<&ServerConfig>
+) -> Result<()>
+{
+    let max_frame_size = config.map_or(DEFAULT_MAX_FRAME_SIZE, |config| config.max_frame_size());
+
+    if let Some(config) = config {
+        stream.framing().set_limits(config.connection_limits());
+    }
+
+    negotiate_as_server(stream, max_frame_size).await?;
+
     let method: Method = stream.receive().await?;
 
     match method {
         Method::Pull => handle_pull_as_server(stream, repo).await,
         Method::Push => handle_push_as_server(stream, repo).await,
-        Method::Clone => handle_clone_as_server(stream, repo).await
+        Method::Clone => handle_clone_as_server(stream, repo).await,
+        Method::Init => refuse_init(stream).await,
+        Method::LfsFetch => handle_lfs_fetch_as_server(stream, repo).await,
+        Method::LfsPush => handle_lfs_push_as_server(stream, repo).await,
+        Method::WhoAmI => {
+            let _lock = lock_repo(&repo, LockMode::Shared, "whoami").await?;
+
+            handle_whoami_as_server(stream, Some(&*repo.read().await), config).await
+        },
+        Method::Mirror => handle_mirror_as_server(stream, repo).await
+    }
+}
+
+/// Handle a connection to a location with no repository,
+/// where the only valid request is to create one.
+/// 
+/// If `config` is `None`, any client can create the repository.
+pub async fn handle_server_without_repo(
+    stream: &mut impl Stream,
+    path: &Path,
+    config: Option<&ServerConfig>
+) -> Result<()>
+{
+    let max_frame_size = config.map_or(DEFAULT_MAX_FRAME_SIZE, |config| config.max_frame_size());
+
+    if let Some(config) = config {
+        stream.framing().set_limits(config.connection_limits());
+    }
+
+    negotiate_as_server(stream, max_frame_size).await?;
+
+    let method: Method = stream.receive().await?;
+
+    match method {
+        Method::Init => handle_init_as_server(stream, path, config).await,
+        Method::WhoAmI => handle_whoami_as_server(stream, None, config).await,
+        _ => bail!("no repository exists at {}", path.display())
     }
 }
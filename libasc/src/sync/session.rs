@@ -0,0 +1,243 @@
+use std::{collections::HashMap, fs::{self, OpenOptions}, path::PathBuf, time::Duration};
+
+use chrono::{DateTime, Utc};
+use eyre::Result;
+use rand::random;
+use serde::{Deserialize, Serialize};
+
+use crate::{hash::ObjectHash, key::{PrivateKey, PublicKey}, repository::Repository, sync::remote::Remote, unwrap, utils::{hash_raw_bytes, load_as_msgpack, save_as_msgpack}};
+
+/// How long a session can be used for after it is issued.
+pub static SESSION_LIFETIME: Duration = Duration::from_secs(30 * 60);
+
+/// A session handed to a client after it logs in with a signature,
+/// which it can use instead of signing again until it expires.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+pub struct IssuedSession {
+    pub token: ObjectHash,
+    pub expires: DateTime<Utc>
+}
+
+/// A session as the server remembers it.
+#[derive(Deserialize, Serialize)]
+struct Session {
+    key: PublicKey,
+    expires: DateTime<Utc>
+}
+
+impl Session {
+    fn is_expired(&self) -> bool {
+        self.expires <= Utc::now()
+    }
+}
+
+/// Sessions are stored under a hash of their token, so
+/// reading the directory doesn't give away any tokens.
+fn session_path(repo: &Repository, token: ObjectHash) -> PathBuf {
+    repo.sessions_dir().join(hash_raw_bytes(token.as_bytes()).full())
+}
+
+/// Issue a new session for the user with the given key.
+///
+/// Expired sessions are removed while this is done.
+pub fn issue_session(repo: &Repository, key: PublicKey) -> Result<IssuedSession> {
+    let dir = repo.sessions_dir();
+
+    unwrap!(
+        fs::create_dir_all(&dir),
+        "failed to create directory: {}", dir.display()
+    );
+
+    for entry in fs::read_dir(&dir)? {
+        let path = entry?.path();
+
+        let expired = load_as_msgpack::<Session>(&path)
+            .map(|session| session.is_expired())
+            .unwrap_or(true);
+
+        if expired {
+            let _ = fs::remove_file(path);
+        }
+    }
+
+    let token = hash_raw_bytes(random::<[u8; 32]>());
+
+    let expires = Utc::now() + SESSION_LIFETIME;
+
+    save_as_msgpack(&Session { key, expires }, session_path(repo, token))?;
+
+    Ok(IssuedSession { token, expires })
+}
+
+/// Find the user a session was issued to, if it exists and hasn't expired.
+pub fn check_session(repo: &Repository, token: ObjectHash) -> Option<PublicKey> {
+    let path = session_path(repo, token);
+
+    let session: Session = load_as_msgpack(&path).ok()?;
+
+    if session.is_expired() {
+        let _ = fs::remove_file(path);
+
+        return None;
+    }
+
+    Some(session.key)
+}
+
+/// When each user last started connections, for limiting how often they can.
+#[derive(Default, Deserialize, Serialize)]
+struct SessionStarts {
+    starts: HashMap<String, Vec<DateTime<Utc>>>
+}
+
+/// Note that the user with the given key is starting a connection, unless
+/// they've already started `per_minute` in the last minute, and return
+/// whether they can.
+///
+/// The record is shared by every server process handling the repository,
+/// so it is locked while it is read and written back.
+pub fn allow_session(repo: &Repository, key: PublicKey, per_minute: u32) -> Result<bool> {
+    let path = repo.main_dir().join("session-starts");
+
+    let lock_path = repo.main_dir().join("session-starts.lock");
+
+    let lock = unwrap!(
+        OpenOptions::new().write(true).create(true).truncate(false).open(&lock_path),
+        "failed to open lock at {}", lock_path.display()
+    );
+
+    unwrap!(
+        lock.lock(),
+        "failed to lock {}", lock_path.display()
+    );
+
+    let mut record: SessionStarts = if path.exists() {
+        load_as_msgpack(&path).unwrap_or_default()
+    }
+    else {
+        SessionStarts::default()
+    };
+
+    let now = Utc::now();
+
+    let minute_ago = now - Duration::from_secs(60);
+
+    for starts in record.starts.values_mut() {
+        starts.retain(|&start| start > minute_ago);
+    }
+
+    record.starts.retain(|_, starts| !starts.is_empty());
+
+    let starts = record.starts.entry(key.to_string()).or_default();
+
+    if starts.len() >= per_minute as usize {
+        return Ok(false);
+    }
+
+    starts.push(now);
+
+    save_as_msgpack(&record, path)?;
+
+    Ok(true)
+}
+
+/// A session token kept by the client, encrypted with the user's key.
+#[derive(Deserialize, Serialize)]
+struct CachedSession {
+    key: PublicKey,
+    nonce: ObjectHash,
+    sealed: ObjectHash,
+    expires: DateTime<Utc>
+}
+
+/// Mix a token with a keystream made from the user's private key,
+/// which both seals and unseals it.
+fn seal(token: ObjectHash, user_key: &PrivateKey, nonce: ObjectHash) -> ObjectHash {
+    let mut input = b"asc-session".to_vec();
+
+    input.extend(user_key.to_bytes());
+    input.extend(nonce.as_bytes());
+
+    let stream = hash_raw_bytes(input);
+
+    let mut sealed = *token.as_bytes();
+
+    for (byte, mask) in sealed.iter_mut().zip(stream.as_bytes()) {
+        *byte ^= mask;
+    }
+
+    sealed.into()
+}
+
+/// The sessions a client holds, one for each remote and user.
+#[derive(Default, Deserialize, Serialize)]
+pub struct SessionCache {
+    sessions: HashMap<String, CachedSession>
+}
+
+impl SessionCache {
+    fn path(repo: &Repository) -> PathBuf {
+        repo.main_dir().join("session-cache")
+    }
+
+    fn name(remote: &Remote, key: &PublicKey) -> String {
+        format!("{key} {remote}")
+    }
+
+    /// Load the cache for a repository, which is empty if it doesn't exist.
+    pub fn load(repo: &Repository) -> Result<SessionCache> {
+        let path = SessionCache::path(repo);
+
+        if !path.exists() {
+            return Ok(SessionCache::default());
+        }
+
+        load_as_msgpack(path)
+    }
+
+    /// Write the cache back to the repository, leaving out expired sessions.
+    pub fn save(&mut self, repo: &Repository) -> Result<()> {
+        self.sessions.retain(|_, session| session.expires > Utc::now());
+
+        save_as_msgpack(self, SessionCache::path(repo))
+    }
+
+    /// Get the token for a session with a remote, if there is one that hasn't expired.
+    pub fn get(&self, remote: &Remote, user_key: &PrivateKey) -> Option<ObjectHash> {
+        let key = user_key.public_key();
+
+        let session = self.sessions.get(&SessionCache::name(remote, &key))?;
+
+        if session.expires <= Utc::now() || session.key != key {
+            return None;
+        }
+
+        Some(seal(session.sealed, user_key, session.nonce))
+    }
+
+    /// Keep a session issued by a remote.
+    pub fn insert(&mut self, remote: &Remote, user_key: &PrivateKey, session: IssuedSession) {
+        let key = user_key.public_key();
+
+        let nonce = hash_raw_bytes(random::<[u8; 32]>());
+
+        let cached = CachedSession {
+            key,
+            nonce,
+            sealed: seal(session.token, user_key, nonce),
+            expires: session.expires
+        };
+
+        self.sessions.insert(SessionCache::name(remote, &key), cached);
+    }
+
+    /// Forget the session with a remote, returning `true` if there was one.
+    pub fn remove(&mut self, remote: &Remote, key: &PublicKey) -> bool {
+        self.sessions.remove(&SessionCache::name(remote, key)).is_some()
+    }
+
+    /// Forget every session.
+    pub fn clear(&mut self) {
+        self.sessions.clear();
+    }
+}
@@ -1,9 +1,294 @@
-use std::io;
+use std::{io, time::Duration};
 
 use async_trait::async_trait;
-use eyre::Result;
-use serde::{Serialize, de::DeserializeOwned};
-use tokio::{io::{AsyncReadExt as Read, AsyncWriteExt as Write, ReadHalf, SimplexStream, Stdin, Stdout, WriteHalf, simplex, stdin, stdout}, process::{ChildStdin, ChildStdout}};
+use eyre::{Result, bail};
+use rand::random;
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use sha2::{Digest, Sha256};
+use tokio::{io::{AsyncReadExt as Read, AsyncWriteExt as Write, ReadHalf, SimplexStream, Stdin, Stdout, WriteHalf, simplex, stdin, stdout}, process::{ChildStdin, ChildStdout}, time::timeout};
+
+pub use super::{DEFAULT_MAX_FRAME_SIZE, DEFAULT_MAX_MESSAGE_SIZE, DEFAULT_SERVER_MAX_MESSAGE_SIZE};
+
+/// Set in a frame's length header when the message
+/// carries on in the frame after it.
+const MORE_CHUNKS: u64 = 1 << 63;
+
+/// The length of what is added to the end of a frame once checks are agreed on:
+/// its sequence number, then a SHA-256 digest of the nonce for the direction
+/// it is sent in, the sequence number and the frame.
+const TRAILER_LEN: usize = 8 + 32;
+
+/// Mixed into the nonce for frames sent by the client.
+const FROM_CLIENT: u8 = 0;
+
+/// Mixed into the nonce for frames sent by the server.
+const FROM_SERVER: u8 = 1;
+
+/// The checks made on every frame in a connection, after both ends have agreed on them.
+///
+/// Each frame carries a sequence number and a digest keyed by a nonce both ends
+/// contributed to, so frames that are corrupted, dropped, reordered, or replayed
+/// from another connection are noticed instead of being read as valid data.
+/// Each direction has its own nonce, so a frame can't be sent back to the end
+/// it came from either.
+pub struct FrameChecks {
+    send_nonce: [u8; 32],
+    receive_nonce: [u8; 32],
+    sent: u64,
+    received: u64
+}
+
+impl FrameChecks {
+    fn new(client_nonce: [u8; 32], server_nonce: [u8; 32], as_client: bool) -> FrameChecks {
+        let direction_nonce = |direction: u8| -> [u8; 32] {
+            let mut hasher = Sha256::new();
+
+            hasher.update(client_nonce);
+            hasher.update(server_nonce);
+            hasher.update([direction]);
+
+            hasher.finalize().into()
+        };
+
+        let (ours, theirs) = if as_client {
+            (FROM_CLIENT, FROM_SERVER)
+        }
+        else {
+            (FROM_SERVER, FROM_CLIENT)
+        };
+
+        FrameChecks {
+            send_nonce: direction_nonce(ours),
+            receive_nonce: direction_nonce(theirs),
+            sent: 0,
+            received: 0
+        }
+    }
+
+    fn digest(nonce: &[u8; 32], sequence: u64, frame: &[u8]) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+
+        hasher.update(nonce);
+        hasher.update(sequence.to_le_bytes());
+        hasher.update(frame);
+
+        hasher.finalize().into()
+    }
+
+    /// Make the trailer for the next frame sent.
+    fn seal(&mut self, frame: &[u8]) -> Vec<u8> {
+        let sequence = self.sent;
+
+        self.sent += 1;
+
+        let mut trailer = sequence.to_le_bytes().to_vec();
+
+        trailer.extend(FrameChecks::digest(&self.send_nonce, sequence, frame));
+
+        trailer
+    }
+
+    /// Check the trailer of the next frame received.
+    fn open(&mut self, frame: &[u8], trailer: &[u8]) -> io::Result<()> {
+        let (sequence, digest) = trailer.split_at(8);
+
+        let sequence = u64::from_le_bytes(sequence.try_into().unwrap());
+
+        if sequence != self.received {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("expected frame {} but received frame {sequence}", self.received)
+            ));
+        }
+
+        if digest != FrameChecks::digest(&self.receive_nonce, sequence, frame) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("frame {sequence} failed its checksum")
+            ));
+        }
+
+        self.received += 1;
+
+        Ok(())
+    }
+}
+
+/// Limits a server puts on a connection, so a client that is stuck or
+/// misbehaving can't keep the repository to itself or fill up the disk.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ConnectionLimits {
+    /// How long to wait for the other end to send anything.
+    pub read_timeout: Option<Duration>,
+
+    /// How long to wait for the other end to take what is sent.
+    pub write_timeout: Option<Duration>,
+
+    /// The most bytes read over the whole connection.
+    pub max_bytes: Option<u64>,
+
+    /// The most bytes read for one message, across all its frames.
+    ///
+    /// If this is unset, [`DEFAULT_MAX_MESSAGE_SIZE`] is used. Servers set
+    /// this from their config, which falls back to [`DEFAULT_SERVER_MAX_MESSAGE_SIZE`].
+    pub max_message_size: Option<u64>,
+
+    /// How many times a user can try to log in each minute, whether they can or not.
+    pub sessions_per_minute: Option<u32>
+}
+
+/// How frames are read and written on one end of a connection.
+pub struct Framing {
+    checks: Option<FrameChecks>,
+    max_receive: u64,
+    max_send: u64,
+    limits: ConnectionLimits,
+    bytes_read: u64
+}
+
+impl Default for Framing {
+    fn default() -> Self {
+        Framing {
+            checks: None,
+            max_receive: DEFAULT_MAX_FRAME_SIZE,
+            max_send: DEFAULT_MAX_FRAME_SIZE,
+            limits: ConnectionLimits::default(),
+            bytes_read: 0
+        }
+    }
+}
+
+impl Framing {
+    /// Get the limits put on the connection.
+    pub fn limits(&self) -> ConnectionLimits {
+        self.limits
+    }
+
+    /// Put limits on the connection from now on.
+    pub fn set_limits(&mut self, limits: ConnectionLimits) {
+        self.limits = limits;
+    }
+
+    /// Count bytes read from the connection, failing
+    /// if that takes it over [`ConnectionLimits::max_bytes`].
+    fn count_read(&mut self, n: usize) -> io::Result<()> {
+        self.bytes_read += n as u64;
+
+        match self.limits.max_bytes {
+            Some(max) if self.bytes_read > max => Err(io::Error::new(
+                io::ErrorKind::QuotaExceeded,
+                format!("connection went over the limit of {max} bytes")
+            )),
+
+            _ => Ok(())
+        }
+    }
+}
+
+/// What one end of a connection supports, sent by both
+/// ends before anything else when a connection is made.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+pub struct Capabilities {
+    /// Whether frames can carry a sequence number and checksum.
+    pub checked_frames: bool,
+
+    /// The largest frame this end will read, in bytes.
+    #[serde(default = "default_max_frame_size")]
+    pub max_frame_size: u64
+}
+
+fn default_max_frame_size() -> u64 {
+    DEFAULT_MAX_FRAME_SIZE
+}
+
+impl Default for Capabilities {
+    fn default() -> Self {
+        Capabilities {
+            checked_frames: true,
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+struct Hello {
+    capabilities: Capabilities,
+    nonce: [u8; 32]
+}
+
+/// Tell the server what the client supports, and turn on
+/// whatever both ends support for the rest of the connection.
+///
+/// Frames larger than `max_frame_size` are refused from then on.
+pub async fn negotiate_as_client(stream: &mut impl Stream, max_frame_size: u64) -> Result<Capabilities> {
+    let ours = Hello {
+        capabilities: Capabilities {
+            max_frame_size,
+            ..Default::default()
+        },
+        nonce: random()
+    };
+
+    stream.send(&ours).await?;
+
+    let theirs: Hello = stream.receive().await?;
+
+    Ok(agree(stream, ours.nonce, &ours, &theirs, theirs.nonce, true))
+}
+
+/// Answer a client's capabilities, and turn on whatever
+/// both ends support for the rest of the connection.
+///
+/// Frames larger than `max_frame_size` are refused from then on.
+pub async fn negotiate_as_server(stream: &mut impl Stream, max_frame_size: u64) -> Result<Capabilities> {
+    let theirs = match stream.receive::<Hello>().await {
+        Ok(theirs) => theirs,
+
+        // Like the client going away, or taking too long.
+        Err(e) if e.is::<io::Error>() => return Err(e),
+
+        Err(_) => bail!("client did not send its capabilities - it may need updating.")
+    };
+
+    let ours = Hello {
+        capabilities: Capabilities {
+            max_frame_size,
+            ..Default::default()
+        },
+        nonce: random()
+    };
+
+    stream.send(&ours).await?;
+
+    Ok(agree(stream, theirs.nonce, &ours, &theirs, ours.nonce, false))
+}
+
+fn agree(
+    stream: &mut impl Stream,
+    client_nonce: [u8; 32],
+    ours: &Hello,
+    theirs: &Hello,
+    server_nonce: [u8; 32],
+    as_client: bool
+) -> Capabilities
+{
+    let agreed = Capabilities {
+        checked_frames: ours.capabilities.checked_frames && theirs.capabilities.checked_frames,
+        max_frame_size: theirs.capabilities.max_frame_size
+    };
+
+    let framing = stream.framing();
+
+    if agreed.checked_frames {
+        framing.checks = Some(FrameChecks::new(client_nonce, server_nonce, as_client));
+    }
+
+    framing.max_receive = ours.capabilities.max_frame_size;
+
+    framing.max_send = theirs.capabilities.max_frame_size.max(1);
+
+    agreed
+}
 
 #[async_trait]
 pub trait Stream: Send {
@@ -11,26 +296,148 @@ pub trait Stream: Send {
 
     async fn raw_write(&mut self, bytes: &[u8]) -> io::Result<()>;
 
-    async fn read(&mut self) -> io::Result<Vec<u8>> {
-        let header = {
-            let bytes = self.raw_read(8).await?;
+    /// How frames are read and written, which is
+    /// agreed on when the connection is made.
+    fn framing(&mut self) -> &mut Framing;
 
-            assert!(bytes.len() == 8);
+    /// Read exactly `n` bytes, within the connection's limits.
+    async fn limited_read(&mut self, n: usize) -> io::Result<Vec<u8>> {
+        let limits = self.framing().limits;
 
-            let bytes = bytes.try_into().unwrap();
+        let bytes = match limits.read_timeout {
+            Some(after) => match timeout(after, self.raw_read(n)).await {
+                Ok(result) => result?,
+                Err(_) => return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!("nothing was received for {} seconds", after.as_secs())
+                ))
+            },
 
-            usize::from_le_bytes(bytes)
+            None => self.raw_read(n).await?
         };
 
-        self.raw_read(header).await
+        self.framing().count_read(n)?;
+
+        Ok(bytes)
+    }
+
+    /// Write all of `bytes`, within the connection's limits.
+    async fn limited_write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        match self.framing().limits.write_timeout {
+            Some(after) => match timeout(after, self.raw_write(bytes)).await {
+                Ok(result) => result,
+                Err(_) => Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!("nothing could be sent for {} seconds", after.as_secs())
+                ))
+            },
+
+            None => self.raw_write(bytes).await
+        }
     }
 
+    /// Read a message, which may have been split over several frames.
+    ///
+    /// A frame longer than the agreed frame size, or that would take the
+    /// message over [`ConnectionLimits::max_message_size`], is refused before
+    /// anything is allocated for it. A peer can only make this use as much
+    /// memory as it actually sends, and never more than that message limit:
+    /// [`DEFAULT_SERVER_MAX_MESSAGE_SIZE`] on servers that don't set one, and
+    /// [`DEFAULT_MAX_MESSAGE_SIZE`] on clients.
+    ///
+    /// This is fuzzed by the `stream_read` target in `libasc/fuzz`.
+    async fn read(&mut self) -> io::Result<Vec<u8>> {
+        let mut message = vec![];
+
+        loop {
+            let header = {
+                let bytes = self.limited_read(8).await?;
+
+                assert!(bytes.len() == 8);
+
+                let bytes = bytes.try_into().unwrap();
+
+                u64::from_le_bytes(bytes)
+            };
+
+            let len = header & !MORE_CHUNKS;
+
+            let limit = self.framing().max_receive;
+
+            if len > limit {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("frame of {len} bytes is over the limit of {limit} bytes")
+                ));
+            }
+
+            let message_limit = self.framing().limits.max_message_size.unwrap_or(DEFAULT_MAX_MESSAGE_SIZE);
+
+            if (message.len() as u64).saturating_add(len) > message_limit {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("message is over the limit of {message_limit} bytes")
+                ));
+            }
+
+            let frame = self.limited_read(len as usize).await?;
+
+            if self.framing().checks.is_some() {
+                let trailer = self.limited_read(TRAILER_LEN).await?;
+
+                if let Some(checks) = &mut self.framing().checks {
+                    checks.open(&frame, &trailer)?;
+                }
+            }
+
+            message.extend(frame);
+
+            if header & MORE_CHUNKS == 0 {
+                return Ok(message);
+            }
+        }
+    }
+
+    /// Write a message, splitting it over several frames
+    /// if it is longer than the other end accepts.
     async fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
-        let header = bytes.len().to_le_bytes();
+        let chunk_size = self.framing().max_send as usize;
+
+        let mut chunks = bytes.chunks(chunk_size).peekable();
 
-        self.raw_write(&header).await?;
+        // An empty message still needs a frame.
+        if chunks.peek().is_none() {
+            return self.write_frame(&[], false).await;
+        }
+
+        while let Some(chunk) = chunks.next() {
+            self.write_frame(chunk, chunks.peek().is_some()).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn write_frame(&mut self, frame: &[u8], more: bool) -> io::Result<()> {
+        let mut header = frame.len() as u64;
+
+        if more {
+            header |= MORE_CHUNKS;
+        }
+
+        let trailer = self.framing()
+            .checks
+            .as_mut()
+            .map(|checks| checks.seal(frame));
+
+        self.limited_write(&header.to_le_bytes()).await?;
+
+        self.limited_write(frame).await?;
+
+        if let Some(trailer) = trailer {
+            self.limited_write(&trailer).await?;
+        }
 
-        self.raw_write(bytes).await
+        Ok(())
     }
 
     async fn close(&mut self) -> io::Result<()>;
@@ -67,11 +474,12 @@ pub trait Stream: Send {
 pub struct LocalStream {
     reader: ReadHalf<SimplexStream>,
     writer: WriteHalf<SimplexStream>,
+    framing: Framing
 }
 
 impl LocalStream {
     pub fn new(reader: ReadHalf<SimplexStream>, writer: WriteHalf<SimplexStream>) -> Self {
-        Self { reader, writer }
+        Self { reader, writer, framing: Framing::default() }
     }
 }
 
@@ -94,6 +502,10 @@ impl Stream for LocalStream {
             .map_err(|e| io::Error::new(io::ErrorKind::ConnectionAborted, e))
     }
 
+    fn framing(&mut self) -> &mut Framing {
+        &mut self.framing
+    }
+
     async fn close(&mut self) -> io::Result<()> {
         Ok(())
     }
@@ -111,12 +523,13 @@ pub fn local_duplex() -> (LocalStream, LocalStream) {
 
 pub struct ChildProcessStream {
     reader: ChildStdout,
-    writer: ChildStdin
+    writer: ChildStdin,
+    framing: Framing
 }
 
 impl ChildProcessStream {
     pub fn new(reader: ChildStdout, writer: ChildStdin) -> Self {
-        Self { reader, writer }
+        Self { reader, writer, framing: Framing::default() }
     }
 }
 
@@ -138,6 +551,10 @@ impl Stream for ChildProcessStream {
         Ok(())
     }
 
+    fn framing(&mut self) -> &mut Framing {
+        &mut self.framing
+    }
+
     async fn close(&mut self) -> io::Result<()> {
         Ok(())
     }
@@ -145,14 +562,16 @@ impl Stream for ChildProcessStream {
 
 pub struct StdinStdout {
     reader: Stdin,
-    writer: Stdout
+    writer: Stdout,
+    framing: Framing
 }
 
 impl Default for StdinStdout {
     fn default() -> Self {
         Self {
             reader: stdin(),
-            writer: stdout()
+            writer: stdout(),
+            framing: Framing::default()
         }
     }
 }
@@ -179,7 +598,228 @@ impl Stream for StdinStdout {
         self.writer.flush().await
     }
 
+    fn framing(&mut self) -> &mut Framing {
+        &mut self.framing
+    }
+
     async fn close(&mut self) -> io::Result<()> {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::VecDeque, mem};
+
+    use super::*;
+
+    /// One end of a connection that keeps what is written to it,
+    /// so it can be handed to the other end, or tampered with first.
+    #[derive(Default)]
+    struct Pipe {
+        bytes: VecDeque<u8>,
+        framing: Framing
+    }
+
+    impl Pipe {
+        fn checked(client_nonce: [u8; 32], server_nonce: [u8; 32], as_client: bool) -> Pipe {
+            let mut pipe = Pipe::default();
+
+            pipe.framing.checks = Some(FrameChecks::new(client_nonce, server_nonce, as_client));
+
+            pipe
+        }
+
+        /// Pass everything written to this end on to `other`.
+        fn pass_to(&mut self, other: &mut Pipe) {
+            other.bytes.extend(mem::take(&mut self.bytes));
+        }
+    }
+
+    #[async_trait]
+    impl Stream for Pipe {
+        async fn raw_read(&mut self, n: usize) -> io::Result<Vec<u8>> {
+            if self.bytes.len() < n {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+
+            Ok(self.bytes.drain(..n).collect())
+        }
+
+        async fn raw_write(&mut self, bytes: &[u8]) -> io::Result<()> {
+            self.bytes.extend(bytes);
+
+            Ok(())
+        }
+
+        fn framing(&mut self) -> &mut Framing {
+            &mut self.framing
+        }
+
+        async fn close(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn checked_pair() -> (Pipe, Pipe) {
+        let (client_nonce, server_nonce) = (random(), random());
+
+        (Pipe::checked(client_nonce, server_nonce, true), Pipe::checked(client_nonce, server_nonce, false))
+    }
+
+    #[tokio::test]
+    async fn messages_round_trip_after_negotiating() {
+        let (mut client, mut server) = local_duplex();
+
+        let (client_caps, server_caps) = tokio::join!(
+            negotiate_as_client(&mut client, 16),
+            negotiate_as_server(&mut server, 16)
+        );
+
+        assert!(client_caps.unwrap().checked_frames);
+        assert!(server_caps.unwrap().checked_frames);
+
+        let long: Vec<u8> = (0..100).collect();
+
+        for message in [long.as_slice(), b"", b"short"] {
+            let (sent, received) = tokio::join!(client.write(message), server.read());
+
+            sent.unwrap();
+
+            assert_eq!(received.unwrap(), message);
+
+            let (sent, received) = tokio::join!(server.write(message), client.read());
+
+            sent.unwrap();
+
+            assert_eq!(received.unwrap(), message);
+        }
+    }
+
+    #[tokio::test]
+    async fn messages_round_trip_without_checks() {
+        let (mut client, mut server) = (Pipe::default(), Pipe::default());
+
+        client.framing.max_send = 3;
+
+        client.write(b"hello world").await.unwrap();
+        client.pass_to(&mut server);
+
+        assert_eq!(server.read().await.unwrap(), b"hello world");
+        assert!(server.bytes.is_empty());
+    }
+
+    #[tokio::test]
+    async fn corrupted_frames_are_refused() {
+        let (mut client, mut server) = checked_pair();
+
+        client.write(b"hello world").await.unwrap();
+        client.pass_to(&mut server);
+
+        // The first byte after the length header.
+        server.bytes[8] ^= 1;
+
+        let e = server.read().await.unwrap_err();
+
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[tokio::test]
+    async fn replayed_frames_are_refused() {
+        let (mut client, mut server) = checked_pair();
+
+        client.write(b"hello world").await.unwrap();
+
+        let sent = client.bytes.clone();
+
+        client.pass_to(&mut server);
+
+        assert_eq!(server.read().await.unwrap(), b"hello world");
+
+        server.bytes = sent;
+
+        let e = server.read().await.unwrap_err();
+
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[tokio::test]
+    async fn frames_sent_back_are_refused() {
+        let (client_nonce, server_nonce) = (random(), random());
+
+        let mut client = Pipe::checked(client_nonce, server_nonce, true);
+        let mut reflected = Pipe::checked(client_nonce, server_nonce, true);
+
+        client.write(b"hello world").await.unwrap();
+        client.pass_to(&mut reflected);
+
+        assert!(reflected.read().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn frames_from_other_connections_are_refused() {
+        let (mut client, _) = checked_pair();
+        let (_, mut server) = checked_pair();
+
+        client.write(b"hello world").await.unwrap();
+        client.pass_to(&mut server);
+
+        assert!(server.read().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn frames_over_the_agreed_size_are_refused() {
+        let (mut client, mut server) = checked_pair();
+
+        server.framing.max_receive = 8;
+
+        client.write(&[0; 9]).await.unwrap();
+        client.pass_to(&mut server);
+
+        let e = server.read().await.unwrap_err();
+
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[tokio::test]
+    async fn messages_over_the_limit_are_refused() {
+        let (mut client, mut server) = checked_pair();
+
+        client.framing.max_send = 4;
+
+        server.framing.set_limits(ConnectionLimits {
+            max_message_size: Some(10),
+            ..Default::default()
+        });
+
+        client.write(&[0; 10]).await.unwrap();
+        client.write(&[0; 11]).await.unwrap();
+        client.pass_to(&mut server);
+
+        assert_eq!(server.read().await.unwrap(), [0; 10]);
+
+        let e = server.read().await.unwrap_err();
+
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[tokio::test]
+    async fn connections_over_the_byte_limit_are_refused() {
+        let (mut client, mut server) = checked_pair();
+
+        server.framing.set_limits(ConnectionLimits {
+            max_bytes: Some(64),
+            ..Default::default()
+        });
+
+        client.write(&[0; 16]).await.unwrap();
+        client.write(&[0; 16]).await.unwrap();
+        client.pass_to(&mut server);
+
+        assert!(server.read().await.is_ok());
+
+        let e = server.read().await.unwrap_err();
+
+        assert_eq!(e.kind(), io::ErrorKind::QuotaExceeded);
+    }
+}
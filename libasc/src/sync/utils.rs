@@ -1,13 +1,48 @@
 use std::sync::Arc;
 
+use derive_more::{Display, Error};
 use eyre::{Result, bail};
 use rand::random;
 use serde::{Deserialize, Serialize};
-use tokio::sync::Mutex;
+use tokio::sync::RwLock;
+
+use crate::{content::Content, graph::Graph, hash::ObjectHash, key::{PublicKey, Signature}, lock::{LockMode, RepoLock}, paths, repository::Repository, snapshot::Snapshot, stats, sync::{audit::{self, SyncEvent}, remote::Remote, session::{IssuedSession, SessionCache, allow_session, check_session, issue_session}, stream::Stream}, unwrap, user::{User, Users}};
+
+/// A repository shared between the connections to it.
+///
+/// Connections only reading it share the lock, and only take it for
+/// themselves once they have something to change, like a branch to move.
+/// This only covers one process - servers also take the [`RepoLock`]
+/// on it with [`lock_repo`], which covers every process.
+pub type Repo = Arc<RwLock<Repository>>;
+
+/// Take the lock on `repo` that every process shares, waiting for other
+/// servers and commands to let go of it as `io.lock_timeout` allows, then
+/// load it again, so that it has whatever they saved in the meantime.
+///
+/// `method` says what a writer is doing, like `push`, for anyone
+/// waiting on the lock.
+pub async fn lock_repo(repo: &Repo, mode: LockMode, method: &str) -> Result<RepoLock> {
+    let (root_dir, timeout) = {
+        let repo = repo.read().await;
+
+        (repo.root_dir.clone(), repo.config.io.lock_timeout()?)
+    };
+
+    let lock = {
+        let root_dir = root_dir.clone();
+        let command = format!("{method} (serving a client)");
 
-use crate::{content::Content, graph::Graph, hash::ObjectHash, key::{PublicKey, Signature}, repository::Repository, snapshot::Snapshot, sync::stream::Stream, unwrap, user::{User, Users}};
+        tokio::task::spawn_blocking(move || match mode {
+            LockMode::Shared => RepoLock::acquire_shared(&root_dir, timeout),
+            LockMode::Exclusive => RepoLock::acquire(&root_dir, &command, timeout)
+        }).await??
+    };
 
-pub type Repo = Arc<Mutex<Repository>>;
+    *repo.write().await = Repository::load_from(&root_dir)?;
+
+    Ok(lock)
+}
 
 pub type ServerSecret = [u8; 32];
 
@@ -23,83 +58,180 @@ pub fn get_server_secret() -> ServerSecret {
     buf
 }
 
+/// The error given when a server doesn't let a user log in.
+#[derive(Debug, Display, Error)]
+#[display("server refused login: {message}")]
+pub struct LoginRefused {
+    pub message: String
+}
+
+/// What the server asks of a client logging in.
+#[derive(Deserialize, Serialize)]
+pub enum Challenge {
+    /// Sign this secret to prove who you are.
+    Sign(ServerSecret),
+
+    /// The session the client offered is still valid, so nothing needs signing.
+    Resumed
+}
+
+/// Log in to the server as the user with the given key.
+/// 
+/// A session cached from an earlier login to `remote` is offered
+/// first, so the server can skip the challenge while it is valid.
 pub async fn login_as(
     user_key: PublicKey,
     stream: &mut impl Stream,
-    project_code: ObjectHash,
-    repo_users: &mut Users
+    repo: &mut Repository,
+    remote: &Remote
 ) -> Result<()> {
     let user = unwrap!(
-        repo_users.get_user(&user_key),
+        repo.users.get_user(&user_key),
         "user with public key {user_key:?} does not exist."
     );
 
-    stream.send(&project_code).await?;
+    let mut key = unwrap!(
+        user.private_key.clone(),
+        "no private key for user {:?}.", user.name
+    );
+
+    let mut cache = SessionCache::load(repo)?;
 
-    let get_secret: Option<ServerSecret> = stream.receive().await?;
+    stream.send(&repo.project_code).await?;
 
-    let Some(secret) = get_secret else {
+    stream.send(&cache.get(remote, &key)).await?;
+
+    let challenge: Option<Challenge> = stream.receive().await?;
+
+    let Some(challenge) = challenge else {
         bail!("project codes do not match.");
     };
 
-    let mut key = user.private_key.clone().unwrap();
+    stats::record_lookup(matches!(challenge, Challenge::Resumed));
 
-    let auth = key.sign(&secret);
+    if let Challenge::Sign(secret) = challenge {
+        let auth = key.sign(&secret);
 
-    stream.send(&auth).await?;
+        stream.send(&auth).await?;
+    }
 
     let result: Result<(), String> = stream.receive().await?;
 
     if let Err(message) = result {
-        bail!("server error: {message}");
+        if cache.remove(remote, &user_key) {
+            cache.save(repo)?;
+        }
+
+        return Err(LoginRefused { message }.into());
     }
 
     let users: Users = stream.receive().await?;
 
-    for user in users.iter_owned() {
-        // An `Err` is returned if the user already exists,
-        // which we can just skip.
-        let _ = repo_users.add_user(user);
+    repo.users.merge(users);
+
+    let issued: Option<IssuedSession> = stream.receive().await?;
+
+    if let Some(session) = issued {
+        cache.insert(remote, &key, session);
+
+        cache.save(repo)?;
     }
 
     Ok(())
 }
 
+/// Log in the client on the other end, returning
+/// the key of the user that logged in, if one did.
 pub async fn handle_login(
     repo: &Repository,
     stream: &mut impl Stream,
     validate_user: impl FnOnce(&User) -> Result<(), String>
-) -> Result<()>
+) -> Result<Option<PublicKey>>
 {
     let client_project_code: ObjectHash = stream.receive().await?;
 
-    let secret = (repo.project_code == client_project_code).then(get_server_secret);
+    let session: Option<ObjectHash> = stream.receive().await?;
+
+    if !repo.project_code.ct_eq(&client_project_code) {
+        stream.send(&None::<Challenge>).await?;
 
-    stream.send(&secret).await?;
+        audit::record(repo, None, SyncEvent::Login {
+            accepted: false,
+            reason: Some("project codes do not match".to_string())
+        })?;
 
-    if secret.is_none() {
-        return Ok(());
+        return Ok(None);
     }
 
-    let login: Signature = stream.receive().await?;
+    let resumed = session.and_then(|token| check_session(repo, token));
+
+    // Who the client said it was, even if it couldn't prove it.
+    let mut claimed = resumed;
+
+    let login = match resumed {
+        Some(key) => {
+            stream.send(&Some(Challenge::Resumed)).await?;
+
+            Ok(key)
+        }
 
-    let result: Result<(), String> = if login.verify(&secret.unwrap()) {
-        match repo.users.get_user(&login.key()) {
+        None => {
+            let secret = get_server_secret();
+
+            stream.send(&Some(Challenge::Sign(secret))).await?;
+
+            let login: Signature = stream.receive().await?;
+
+            claimed = Some(login.key());
+
+            if login.verify(&secret) {
+                Ok(login.key())
+            }
+            else {
+                Err("failed to verify signature".to_string())
+            }
+        }
+    };
+
+    let mut result: Result<(), String> = login.clone().and_then(|key| {
+        match repo.users.get_user(&key) {
             Some(user) => validate_user(user),
             None => Err("user does not exist".to_string())
         }
+    });
+
+    // Every attempt counts towards the limit, so guessing
+    // at logins is limited as much as connecting is.
+    if let Some(key) = &claimed
+        && let Some(limit) = stream.framing().limits().sessions_per_minute
+        && !allow_session(repo, *key, limit)?
+    {
+        result = Err(format!("too many connections - only {limit} are allowed a minute"));
     }
-    else {
-        Err("failed to verify signature".to_string())
-    };
 
     stream.send(&result).await?;
 
-    if result.is_ok() {
-        stream.send(&repo.users).await?;
+    audit::record(repo, claimed.as_ref(), SyncEvent::Login {
+        accepted: result.is_ok(),
+        reason: result.clone().err()
+    })?;
+
+    if result.is_err() {
+        return Ok(None);
     }
 
-    Ok(())
+    stream.send(&repo.users).await?;
+
+    // A session is only issued for a fresh login, so
+    // it can't be kept alive forever by using it.
+    let issued = match (resumed, &login) {
+        (None, Ok(key)) => issue_session(repo, *key).ok(),
+        _ => None
+    };
+
+    stream.send(&issued).await?;
+
+    Ok(login.ok())
 }
 
 pub fn dfs_get(graph: &Graph, start: ObjectHash, chain: &mut Graph) {
@@ -133,3 +265,182 @@ pub enum Object {
     Commit(Box<Snapshot>),
     Content(Content)
 }
+
+/// Save a snapshot sent by the other side.
+///
+/// Snapshots can't be changed without changing their hash, so paths in a
+/// form that isn't canonical are kept as they are, and only put in that
+/// form when checked out. A snapshot with two paths that are the same once
+/// normalised could never be checked out properly, so it is refused.
+pub fn save_received_snapshot(repo: &mut Repository, snapshot: Snapshot) -> Result<ObjectHash> {
+    if let Err(e) = paths::check_collisions(snapshot.files.keys(), true) {
+        bail!("refusing snapshot {}: {e}", snapshot.hash);
+    }
+
+    if let Err(e) = snapshot.check_symlink_ancestors() {
+        bail!("refusing snapshot {}: {e}", snapshot.hash);
+    }
+
+    repo.save_snapshot(snapshot)
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::{key::PrivateKey, sync::stream::{ConnectionLimits, LocalStream, local_duplex}};
+
+    fn temp_repo() -> (TempDir, Repository) {
+        let dir = tempfile::tempdir().unwrap();
+
+        let repo = Repository::create_new(dir.path(), "axo".to_string(), "test".to_string()).unwrap();
+
+        (dir, repo)
+    }
+
+    fn user_key(repo: &Repository) -> PrivateKey {
+        repo.current_user().unwrap().private_key.clone().unwrap()
+    }
+
+    /// Log in as the client, like [`login_as`] without a session to offer,
+    /// signing whatever `sign` gives for the server's secret.
+    async fn log_in(
+        stream: &mut LocalStream,
+        project_code: ObjectHash,
+        mut key: PrivateKey,
+        sign: impl FnOnce(ServerSecret) -> ServerSecret
+    ) -> Result<Result<(), String>>
+    {
+        stream.send(&project_code).await?;
+
+        stream.send(&None::<ObjectHash>).await?;
+
+        let Some(Challenge::Sign(secret)) = stream.receive::<Option<Challenge>>().await? else {
+            return Ok(Err("no challenge".to_string()));
+        };
+
+        stream.send(&key.sign(&sign(secret))).await?;
+
+        let result: Result<(), String> = stream.receive().await?;
+
+        if result.is_ok() {
+            let _: Users = stream.receive().await?;
+            let _: Option<IssuedSession> = stream.receive().await?;
+        }
+
+        Ok(result)
+    }
+
+    async fn serve_login(
+        repo: &Repository,
+        key: PrivateKey,
+        limits: ConnectionLimits,
+        sign: impl FnOnce(ServerSecret) -> ServerSecret
+    ) -> (Option<PublicKey>, Result<(), String>)
+    {
+        let (mut client, mut server) = local_duplex();
+
+        server.framing().set_limits(limits);
+
+        let (served, sent) = tokio::join!(
+            handle_login(repo, &mut server, |_| Ok(())),
+            log_in(&mut client, repo.project_code, key, sign)
+        );
+
+        (served.unwrap(), sent.unwrap())
+    }
+
+    #[tokio::test]
+    async fn users_of_the_repository_can_log_in() {
+        let (_dir, repo) = temp_repo();
+
+        let key = user_key(&repo);
+
+        let (served, sent) = serve_login(&repo, key.clone(), ConnectionLimits::default(), |secret| secret).await;
+
+        assert_eq!(served, Some(key.public_key()));
+        assert_eq!(sent, Ok(()));
+    }
+
+    #[tokio::test]
+    async fn logins_to_other_projects_are_refused() {
+        let (_dir, repo) = temp_repo();
+
+        let (mut client, mut server) = local_duplex();
+
+        let other: ObjectHash = [0; 32].into();
+
+        let (served, sent) = tokio::join!(
+            handle_login(&repo, &mut server, |_| Ok(())),
+            log_in(&mut client, other, user_key(&repo), |secret| secret)
+        );
+
+        assert_eq!(served.unwrap(), None);
+        assert_eq!(sent.unwrap(), Err("no challenge".to_string()));
+    }
+
+    #[tokio::test]
+    async fn forged_signatures_are_refused() {
+        let (_dir, repo) = temp_repo();
+
+        let (served, sent) = serve_login(&repo, user_key(&repo), ConnectionLimits::default(), |mut secret| {
+            secret[0] ^= 1;
+            secret
+        }).await;
+
+        assert_eq!(served, None);
+        assert_eq!(sent, Err("failed to verify signature".to_string()));
+    }
+
+    #[tokio::test]
+    async fn unknown_users_are_refused() {
+        let (_dir, repo) = temp_repo();
+
+        let (served, sent) = serve_login(&repo, PrivateKey::new(), ConnectionLimits::default(), |secret| secret).await;
+
+        assert_eq!(served, None);
+        assert_eq!(sent, Err("user does not exist".to_string()));
+    }
+
+    #[tokio::test]
+    async fn logins_over_the_limit_are_refused() {
+        let (_dir, repo) = temp_repo();
+
+        let limits = ConnectionLimits {
+            sessions_per_minute: Some(1),
+            ..Default::default()
+        };
+
+        let (served, _) = serve_login(&repo, user_key(&repo), limits, |secret| secret).await;
+
+        assert!(served.is_some());
+
+        let (served, sent) = serve_login(&repo, user_key(&repo), limits, |secret| secret).await;
+
+        assert_eq!(served, None);
+        assert!(sent.unwrap_err().starts_with("too many connections"));
+    }
+
+    #[tokio::test]
+    async fn refused_logins_count_towards_the_limit() {
+        let (_dir, repo) = temp_repo();
+
+        let limits = ConnectionLimits {
+            sessions_per_minute: Some(1),
+            ..Default::default()
+        };
+
+        let (served, _) = serve_login(&repo, user_key(&repo), limits, |mut secret| {
+            secret[0] ^= 1;
+            secret
+        }).await;
+
+        assert_eq!(served, None);
+
+        let (served, sent) = serve_login(&repo, user_key(&repo), limits, |secret| secret).await;
+
+        assert_eq!(served, None);
+        assert!(sent.unwrap_err().starts_with("too many connections"));
+    }
+}
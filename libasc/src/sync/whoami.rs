@@ -0,0 +1,157 @@
+use std::{fs, path::{Path, PathBuf}};
+
+use eyre::{Result, eyre};
+use serde::{Deserialize, Serialize};
+
+use crate::{hash::ObjectHash, key::{PublicKey, Signature}, repository::Repository, sync::{init::ServerConfig, stream::Stream, utils::{Repo, ServerSecret, get_server_secret}}, unwrap, user::Users, utils::load_as_msgpack};
+
+/// What a server knows about the key a client connected with.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Identity {
+    /// The account the key belongs to in the remote repository.
+    pub user: Option<String>,
+
+    /// Whether that account has been closed.
+    pub closed: bool,
+
+    /// The groups that account is in.
+    pub groups: Vec<String>,
+
+    /// Whether the remote repository is the same project as the local one,
+    /// or `None` if there is no repository at the remote.
+    pub same_project: Option<bool>,
+
+    /// Whether the server lets this key create repositories.
+    pub can_create: bool,
+
+    /// The repositories on the server with an account for this key,
+    /// relative to the server's repositories directory.
+    pub repositories: Vec<PathBuf>
+}
+
+impl Identity {
+    /// Check if the key can be used to push to and pull from the remote repository.
+    pub fn can_sync(&self) -> bool {
+        self.user.is_some() && self.same_project == Some(true)
+    }
+}
+
+/// Ask the server who the current user of `repo` is to it.
+pub async fn handle_whoami_as_client(
+    stream: &mut impl Stream,
+    repo: Repo
+) -> Result<Identity>
+{
+    let repo = repo.read().await;
+
+    let user = unwrap!(
+        repo.current_user(),
+        "no valid user set for this repository."
+    );
+
+    let mut key = unwrap!(
+        user.private_key.clone(),
+        "no private key for user {:?}.", user.name
+    );
+
+    stream.send(&repo.project_code).await?;
+
+    let secret: ServerSecret = stream.receive().await?;
+
+    stream.send(&key.sign(&secret)).await?;
+
+    let result: Result<Identity, String> = stream.receive().await?;
+
+    result.map_err(|message| eyre!("server error: {message}"))
+}
+
+/// Find every repository under `dir` with an account for `key`.
+fn find_repositories(base: &Path, dir: &Path, key: &PublicKey, found: &mut Vec<PathBuf>) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+
+        if !path.is_dir() {
+            continue;
+        }
+
+        if !path.join(".asc").is_dir() {
+            find_repositories(base, &path, key, found);
+
+            continue;
+        }
+
+        let Ok(users) = load_as_msgpack::<Users>(path.join(".asc").join("users")) else {
+            continue;
+        };
+
+        if users.get_user(key).is_some()
+            && let Ok(relative) = path.strip_prefix(base)
+        {
+            found.push(relative.to_path_buf());
+        }
+    }
+}
+
+/// Tell a client what the server knows about its key.
+///
+/// `repo` is the repository at the location the client connected to, if there is one.
+pub async fn handle_whoami_as_server(
+    stream: &mut impl Stream,
+    repo: Option<&Repository>,
+    config: Option<&ServerConfig>
+) -> Result<()>
+{
+    let project_code: ObjectHash = stream.receive().await?;
+
+    let secret = get_server_secret();
+
+    stream.send(&secret).await?;
+
+    let login: Signature = stream.receive().await?;
+
+    if !login.verify(&secret) {
+        let error: Result<Identity, String> = Err("failed to verify signature".to_string());
+
+        return stream.send(&error).await;
+    }
+
+    let key = login.key();
+
+    let user = repo.and_then(|repo| repo.users.get_user(&key));
+
+    let groups = match repo {
+        Some(repo) => repo.users
+            .groups()
+            .groups_of(&key)
+            .map(String::from)
+            .collect(),
+
+        None => vec![]
+    };
+
+    let mut repositories = vec![];
+
+    if let Some(base) = config.and_then(|config| config.repositories_dir.as_ref()) {
+        find_repositories(base, base, &key, &mut repositories);
+
+        repositories.sort();
+    }
+
+    let identity = Identity {
+        user: user.map(|user| user.name.clone()),
+        closed: user.is_some_and(|user| user.closed),
+        groups,
+        same_project: repo.map(|repo| repo.project_code.ct_eq(&project_code)),
+        // Without any settings, like for a local remote, anyone can create repositories.
+        can_create: config.is_none_or(|config| config.repositories_dir.is_some() && config.is_admin(&key)),
+        repositories
+    };
+
+    let result: Result<Identity, String> = Ok(identity);
+
+    stream.send(&result).await
+}
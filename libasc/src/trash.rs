@@ -1,11 +1,17 @@
+use std::time::Duration;
+
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 
 use crate::hash::ObjectHash;
 
+/// How long snapshots are kept in the trash before they can
+/// be purged, unless the repository's config says otherwise.
+pub static DEFAULT_TRASH_RETENTION: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
 // For documentation purposes.
 #[allow(unused_imports)]
-use crate::graph::Graph;
+use crate::{graph::Graph, repository::Repository};
 
 /// For _how_ an [`ObjectHash`] is included in the trash.
 pub enum TrashStatus {
@@ -17,7 +23,7 @@ pub enum TrashStatus {
     Indirect(ObjectHash)
 }
 
-#[derive(Clone, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Entry {
     pub when: DateTime<Utc>,
     pub hash: ObjectHash
@@ -88,3 +94,22 @@ impl Trash {
         self.entries.as_slice()
     }
 }
+
+/// What was deleted by [`Repository::purge_trash`].
+#[derive(Debug, Default)]
+pub struct Purged {
+    /// The entries that were removed from the trash.
+    pub entries: Vec<Entry>,
+
+    /// The snapshots that were deleted, including
+    /// those trashed along with an entry.
+    pub snapshots: Vec<ObjectHash>,
+
+    /// How many content objects and large files were deleted.
+    pub objects: usize,
+
+    /// Entries that were old enough to purge, but are kept because
+    /// a branch, tag, stash, pin or the HEAD still uses them,
+    /// or because they are held by the repository's retention rules.
+    pub kept: Vec<ObjectHash>
+}
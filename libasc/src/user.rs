@@ -1,7 +1,9 @@
+use std::collections::BTreeMap;
+
 use eyre::{OptionExt, Result, bail};
 use serde::{Deserialize, Serialize};
 
-use crate::key::{PrivateKey, PublicKey};
+use crate::{key::{PrivateKey, PublicKey}, unwrap};
 
 /// Represents a user account in the repository.
 #[derive(Clone, Deserialize, Serialize)]
@@ -69,10 +71,120 @@ impl<'data> AsSearchType<'data> for &'data PrivateKey {
     }
 }
 
+/// Named groups of users, so that rules can refer to many users at once.
+#[derive(Clone, Default, Deserialize, Serialize)]
+pub struct Groups {
+    inner: BTreeMap<String, Vec<PublicKey>>
+}
+
+impl Groups {
+    /// Create a new, empty group.
+    pub fn create(&mut self, name: String) -> Result<()> {
+        if name.is_empty() {
+            bail!("empty group names are not allowed.");
+        }
+
+        if self.inner.contains_key(&name) {
+            bail!("group {name:?} already exists.");
+        }
+
+        self.inner.insert(name, vec![]);
+
+        Ok(())
+    }
+
+    /// Delete a group, returning its members if it existed.
+    pub fn delete(&mut self, name: &str) -> Option<Vec<PublicKey>> {
+        self.inner.remove(name)
+    }
+
+    /// Add a user to a group.
+    /// 
+    /// Returns `false` if they were already a member.
+    pub fn add_member(&mut self, group: &str, key: PublicKey) -> Result<bool> {
+        let members = unwrap!(
+            self.inner.get_mut(group),
+            "group {group:?} does not exist."
+        );
+
+        if members.contains(&key) {
+            return Ok(false);
+        }
+
+        members.push(key);
+
+        Ok(true)
+    }
+
+    /// Remove a user from a group.
+    /// 
+    /// Returns `false` if they weren't a member.
+    pub fn remove_member(&mut self, group: &str, key: &PublicKey) -> Result<bool> {
+        let members = unwrap!(
+            self.inner.get_mut(group),
+            "group {group:?} does not exist."
+        );
+
+        let before = members.len();
+
+        members.retain(|member| member != key);
+
+        Ok(members.len() != before)
+    }
+
+    /// Get the members of a group.
+    pub fn members(&self, group: &str) -> Option<&[PublicKey]> {
+        self.inner.get(group).map(Vec::as_slice)
+    }
+
+    /// Check if a user is in a group.
+    pub fn contains(&self, group: &str, key: &PublicKey) -> bool {
+        self.members(group).is_some_and(|members| members.contains(key))
+    }
+
+    /// Get the names of the groups a user is in.
+    pub fn groups_of<'a>(&'a self, key: &'a PublicKey) -> impl Iterator<Item = &'a str> {
+        self.inner
+            .iter()
+            .filter(move |(_, members)| members.contains(key))
+            .map(|(name, _)| name.as_str())
+    }
+
+    /// Iterate through every group and its members, in order of name.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &[PublicKey])> {
+        self.inner
+            .iter()
+            .map(|(name, members)| (name.as_str(), members.as_slice()))
+    }
+
+    /// Check if there are no groups.
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
+    /// Merge groups from elsewhere, like from a remote, into these.
+    /// 
+    /// Groups in both keep the members from each.
+    pub fn merge(&mut self, other: Groups) {
+        for (name, members) in other.inner {
+            let existing = self.inner.entry(name).or_default();
+
+            for key in members {
+                if !existing.contains(&key) {
+                    existing.push(key);
+                }
+            }
+        }
+    }
+}
+
 /// A collection of users for a repository.
 #[derive(Clone, Default, Deserialize, Serialize)]
 pub struct Users {
     inner: Vec<User>,
+
+    #[serde(default)]
+    groups: Groups
 }
 
 impl Users {
@@ -137,6 +249,26 @@ impl Users {
         self.get_user(query).is_some()
     }
 
+    /// Get the groups of users in the repository.
+    pub fn groups(&self) -> &Groups {
+        &self.groups
+    }
+
+    /// Get the groups of users in the repository mutably.
+    pub fn groups_mut(&mut self) -> &mut Groups {
+        &mut self.groups
+    }
+
+    /// Check if a rule names a user, either as `@group`
+    /// for any member of a group, or by their username.
+    pub fn matches_rule(&self, rule: &str, key: &PublicKey) -> bool {
+        if let Some(group) = rule.strip_prefix('@') {
+            return self.groups.contains(group, key);
+        }
+
+        self.get_user(rule).is_some_and(|user| user.public_key == *key)
+    }
+
     /// Iterature through all [`User`]s in the repository.
     pub fn iter(&self) -> impl Iterator<Item = &User> {
         self.inner.iter()
@@ -151,6 +283,19 @@ impl Users {
         self.inner.is_empty()
     }
     
+    /// Merge users from elsewhere, like from a remote, into these.
+    /// 
+    /// Users that already exist here (by name or key) are left as they are.
+    pub fn merge(&mut self, other: Users) {
+        self.groups.merge(other.groups);
+
+        for user in other.inner {
+            // An `Err` is returned if the user already exists,
+            // which we can just skip.
+            let _ = self.add_user(user);
+        }
+    }
+
     /// Return a new [`Users`] where no account has a private key.
     pub fn without_private_keys(&self) -> Users {
         let mut users = Users::new();
@@ -162,6 +307,8 @@ impl Users {
 
             users.add_user(user).unwrap();
         }
+
+        users.groups = self.groups.clone();
         
         users
     }
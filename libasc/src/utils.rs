@@ -1,10 +1,17 @@
-use crate::{hash::ObjectHash, hash::RawObjectHash, unwrap};
+use crate::{exec::Policy, hash::ObjectHash, hash::RawObjectHash, unwrap};
 
-use std::{fs::{self, File}, io::Write, path::{Path, PathBuf}, process::Command};
+use std::{fs::{self, File}, io::Write, path::Path, time::Duration};
+#[cfg(feature = "fs")]
+use std::path::PathBuf;
 
-use eyre::{Context, Result, bail, eyre};
+use eyre::{Context, Result, bail};
+#[cfg(feature = "fs")]
+use eyre::eyre;
+#[cfg(feature = "fs")]
 use glob::glob;
+#[cfg(feature = "fs")]
 use glob_match::glob_match;
+#[cfg(feature = "fs")]
 use miniz_oxide::{deflate::compress_to_vec, inflate::decompress_to_vec};
 use relative_path::{PathExt, RelativePath, RelativePathBuf};
 use serde::{de::DeserializeOwned, Serialize};
@@ -13,6 +20,7 @@ use sha2::{Digest, Sha256};
 /// Expand a path with wildcards into all possible matches by querying the filesystem.
 /// 
 /// This wraps the [`glob::glob`] function to make it more ergonomic.
+#[cfg(feature = "fs")]
 pub fn resolve_wildcard_path(root: impl AsRef<Path>) -> Result<Vec<PathBuf>> {
     let root = root.as_ref();
     
@@ -47,6 +55,7 @@ pub fn resolve_wildcard_path(root: impl AsRef<Path>) -> Result<Vec<PathBuf>> {
 }
 
 /// Filter a list of strings using a list of glob patterns.
+#[cfg(feature = "fs")]
 pub fn filter_with_glob<G, I>(
     globs: Vec<G>,
     input: &[I]
@@ -68,6 +77,7 @@ pub fn filter_with_glob<G, I>(
 }
 
 /// Filter a list of strings using a list of glob patterns.
+#[cfg(feature = "fs")]
 pub fn filter_with_glob_indexes<G, I>(
     globs: Vec<G>,
     input: &[I]
@@ -111,6 +121,7 @@ pub fn normalise_with_root(
         ))
 }
 
+#[cfg(feature = "fs")]
 fn match_path_by_glob(
     glob: impl AsRef<RelativePath>,
     path: impl AsRef<RelativePath>,
@@ -143,6 +154,7 @@ fn match_path_by_glob(
 /// 
 /// This will match globs starting with `..`. Use
 /// [`filter_paths_with_glob_strict`] to disallow this.
+#[cfg(feature = "fs")]
 pub fn filter_paths_with_glob<'a, P: AsRef<RelativePath>>(
     globs: &[impl AsRef<RelativePath>],
     paths: &'a [P],
@@ -161,6 +173,7 @@ pub fn filter_paths_with_glob<'a, P: AsRef<RelativePath>>(
 /// 
 /// This will disallow any globs starting with `..` that would
 /// search outside of the tree.
+#[cfg(feature = "fs")]
 pub fn filter_paths_with_glob_strict<'glob, 'path, G, P>(
     globs: &'glob [G],
     paths: &'path [P],
@@ -190,6 +203,7 @@ pub fn filter_paths_with_glob_strict<'glob, 'path, G, P>(
 /// 
 /// This will match globs starting with `..`. Use
 /// [`filter_paths_with_glob_strict`] to disallow this.
+#[cfg(feature = "fs")]
 pub fn filter_paths_with_glob_indexes<'a, P: AsRef<RelativePath>>(
     globs: &[impl AsRef<RelativePath>],
     paths: &'a [P],
@@ -209,6 +223,7 @@ pub fn filter_paths_with_glob_indexes<'a, P: AsRef<RelativePath>>(
 /// 
 /// This will disallow any globs starting with `..` that would
 /// search outside of the tree.
+#[cfg(feature = "fs")]
 pub fn filter_paths_with_glob_indexes_strict<'glob, 'path, G, P>(
     globs: &'glob [G],
     paths: &'path [P],
@@ -236,11 +251,13 @@ pub fn filter_paths_with_glob_indexes_strict<'glob, 'path, G, P>(
 }
 
 /// Compress data using [`miniz_oxide::deflate::compress_to_vec`].
+#[cfg(feature = "fs")]
 pub fn compress_data(input: impl AsRef<[u8]>) -> Vec<u8> {
     compress_to_vec(input.as_ref(), 6)
 }
 
 /// Decompress data using [`miniz_oxide::inflate::decompress_to_vec`].
+#[cfg(feature = "fs")]
 pub fn decompress_data(input: impl AsRef<[u8]>) -> Result<Vec<u8>> {
     let buf = decompress_to_vec(input.as_ref())
         .map_err(|e| eyre!("failed to decompress data: {e}"))?;
@@ -326,11 +343,68 @@ pub fn create_file(path: impl AsRef<Path>) -> Result<File> {
     )
 }
 
+/// Split a command into its program and arguments, the way a shell would
+/// for a simple command: on whitespace, except inside single or double quotes,
+/// and with a backslash escaping the next character outside single quotes.
+///
+/// Nothing else a shell would do, like expanding variables, is done.
+pub fn split_command(raw: &str) -> Result<Vec<String>> {
+    let mut words = vec![];
+
+    let mut current: Option<String> = None;
+
+    let mut quote: Option<char> = None;
+
+    let mut chars = raw.chars();
+
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+
+            (Some('\''), c) => current.get_or_insert_default().push(c),
+
+            (_, '\\') => {
+                let Some(next) = chars.next() else {
+                    bail!("command ends with a backslash: {raw}");
+                };
+
+                current.get_or_insert_default().push(next);
+            },
+
+            (Some(_), c) => current.get_or_insert_default().push(c),
+
+            (None, '\'' | '"') => {
+                quote = Some(c);
+
+                // Quotes make a word even if nothing is in them.
+                current.get_or_insert_default();
+            },
+
+            (None, c) if c.is_whitespace() => words.extend(current.take()),
+
+            (None, c) => current.get_or_insert_default().push(c)
+        }
+    }
+
+    if quote.is_some() {
+        bail!("command has an unclosed quote: {raw}");
+    }
+
+    words.extend(current);
+
+    Ok(words)
+}
+
 /// Open an interactive editor, wait for the process to end, then return
 /// the content of the file after.
 /// 
-/// To spawn a process on Windows, this uses `cmd`, while on Unix, it uses `bash`.
-/// Anything else and you get a special error message :)
+/// `editor` is split into a program and arguments with [`split_command`], and
+/// the program is run directly with the path added on the end, without a
+/// shell, so paths with spaces or shell syntax in them are passed as they are.
+/// Editors that return straight away need a flag to wait, like `code --wait`.
+///
+/// The editor is run from the directory the file is in, under
+/// [`Policy::interactive`], so it only sees some of asc's environment.
 pub fn get_content_from_editor(editor: &str, snapshot_message_path: &Path, template_message: &str) -> Result<String> {
     unwrap!(
         fs::write(snapshot_message_path, template_message),
@@ -338,32 +412,18 @@ pub fn get_content_from_editor(editor: &str, snapshot_message_path: &Path, templ
         snapshot_message_path.display()
     );
 
-    let mut editor_cmd = if cfg!(windows) {
-        let mut cmd = Command::new("cmd");
-
-        cmd
-            .arg("/c")
-            .arg(editor)
-            .arg(snapshot_message_path.display().to_string());
-
-        cmd
+    if split_command(editor)?.is_empty() {
+        bail!("no editor was given.");
     }
-    else if cfg!(unix) {
-        let mut cmd = Command::new("bash");
-        
-        cmd
-            .arg("-c")
-            .arg(format!("{editor} {}", snapshot_message_path.display()));
 
-        cmd
-    }
-    else {
-        bail!("what the fuck are you running bro 😭");
-    };
+    let dir = snapshot_message_path.parent().unwrap_or(Path::new("."));
 
-    let mut child = editor_cmd.spawn()?;
+    let output = unwrap!(
+        Policy::interactive(dir).run(editor, &[snapshot_message_path.into()], &[]),
+        "failed to run editor"
+    );
 
-    let status = child.wait()?;
+    let status = output.status;
 
     if !status.success() {
         let message = match status.code() {
@@ -379,12 +439,22 @@ pub fn get_content_from_editor(editor: &str, snapshot_message_path: &Path, templ
         "cannot read content of: {}", snapshot_message_path.display()
     );
 
-    let cleaned: String = content
+    Ok(clean_message(&content))
+}
+
+/// Tidy up a message written in an editor, the way [`get_content_from_editor`]
+/// does: lines starting with `#` are dropped, then whitespace
+/// at the start and end is removed.
+/// 
+/// Cleaning the template an editor was opened with shows
+/// what the message would be if it was left unchanged.
+pub fn clean_message(raw: &str) -> String {
+    let cleaned: Vec<&str> = raw
         .lines()
         .filter(|line| !line.starts_with('#'))
         .collect();
 
-    Ok(cleaned)
+    cleaned.join("\n").trim().to_string()
 }
 
 /// Write data to a file, compressing it with messagepack.
@@ -430,3 +500,34 @@ impl<T: AsRef<RelativePath>> IsGlob for T {
         self.as_ref().as_str().contains(['*', '?', '['])
     }
 }
+
+/// Parse a length of time like `30d`, `12h` or `2w`.
+/// 
+/// The units are `s`, `m`, `h`, `d`, `w` and `y` (365 days),
+/// and a number on its own is taken as days.
+pub fn parse_duration(raw: &str) -> Result<Duration> {
+    let raw = raw.trim();
+
+    let split = raw
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(raw.len());
+
+    let (number, unit) = raw.split_at(split);
+
+    let number: u64 = unwrap!(
+        number.parse(),
+        "invalid length of time: {raw:?}"
+    );
+
+    let seconds = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "" | "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        "y" => 365 * 24 * 60 * 60,
+        _ => bail!("unknown unit of time {unit:?} in {raw:?} (expected s, m, h, d, w or y)")
+    };
+
+    Ok(Duration::from_secs(number * seconds))
+}
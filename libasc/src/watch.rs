@@ -0,0 +1,181 @@
+//! A record of the hash each tracked file had when it was last looked at,
+//! with its size and modification time, kept in `.asc/watch-state`.
+//!
+//! `asc watch` keeps this up to date as files change, so working out what
+//! has changed only needs to read the files that are different, rather
+//! than re-reading and re-hashing every tracked file each time. The watcher
+//! is told about changes by the OS, and looks over every file's metadata
+//! now and then as well, for changes the OS doesn't report.
+//!
+//! A recorded hash is only used while the file's size and modification time
+//! still match, so a stale record, or one left behind by a watcher that
+//! has stopped, never gives a wrong answer - it just saves less work.
+
+use std::{collections::BTreeMap, fs, path::{Path, PathBuf}, time::{Duration, SystemTime}};
+
+use eyre::Result;
+use relative_path::{RelativePath, RelativePathBuf};
+use serde::{Deserialize, Serialize};
+
+use crate::{hash::ObjectHash, mode, paths, repository::Repository, unwrap, utils::{hash_raw_bytes, load_as_msgpack, save_as_msgpack}};
+
+/// How long after a file was modified it has to have been hashed for the hash
+/// to be trusted. A file written again in the same tick of the clock as it was
+/// hashed would otherwise keep its modification time, and look unchanged.
+const RACY_WINDOW: Duration = Duration::from_secs(2);
+
+/// What a file looked like when it was hashed.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct FileStamp {
+    pub size: u64,
+    pub modified: SystemTime,
+    pub hash: ObjectHash,
+
+    /// When the file was hashed.
+    pub checked: SystemTime
+}
+
+impl FileStamp {
+    /// Hash a file on disk.
+    pub fn read(path: &Path) -> Result<FileStamp> {
+        let checked = SystemTime::now();
+
+        let metadata = fs::symlink_metadata(path)?;
+
+        let bytes = unwrap!(
+            mode::read_working_bytes(path),
+            "failed to read path: {}", path.display()
+        );
+
+        Ok(FileStamp {
+            size: metadata.len(),
+            modified: metadata.modified()?,
+            hash: hash_raw_bytes(bytes),
+            checked
+        })
+    }
+
+    /// Check if the file at `path` still has the content this was made from.
+    fn is_current(&self, path: &Path) -> bool {
+        let Ok(metadata) = fs::symlink_metadata(path) else {
+            return false;
+        };
+
+        let Ok(modified) = metadata.modified() else {
+            return false;
+        };
+
+        let settled = self.checked
+            .duration_since(self.modified)
+            .is_ok_and(|gap| gap >= RACY_WINDOW);
+
+        settled && metadata.len() == self.size && modified == self.modified
+    }
+}
+
+/// The stamps of the tracked files, as kept in `.asc/watch-state`.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct WatchState {
+    pub files: BTreeMap<RelativePathBuf, FileStamp>
+}
+
+impl WatchState {
+    fn path(repo: &Repository) -> PathBuf {
+        repo.main_dir().join("watch-state")
+    }
+
+    /// Load the state kept for `repo`, or an empty one if there
+    /// isn't one or it can't be read, since it is only a shortcut.
+    pub fn load(repo: &Repository) -> WatchState {
+        load_as_msgpack(WatchState::path(repo)).unwrap_or_default()
+    }
+
+    /// Write the state for `repo`.
+    ///
+    /// It is written to a temporary file first, so
+    /// that commands never read it half-written.
+    pub fn save(&self, repo: &Repository) -> Result<()> {
+        let path = WatchState::path(repo);
+
+        let temp = path.with_extension("tmp");
+
+        save_as_msgpack(self, &temp)?;
+
+        unwrap!(
+            fs::rename(&temp, &path),
+            "failed to write watch state to: {}", path.display()
+        );
+
+        Ok(())
+    }
+
+    /// Get the hash a tracked file has in the working directory, without
+    /// reading it if it hasn't changed since it was recorded.
+    ///
+    /// Returns `None` if the file doesn't exist.
+    pub fn working_hash(&self, repo: &Repository, path: &RelativePath) -> Result<Option<ObjectHash>> {
+        let full_path = paths::on_disk(path, &repo.root_dir);
+
+        if let Some(stamp) = self.files.get(path)
+            && stamp.is_current(&full_path)
+        {
+            return Ok(Some(stamp.hash));
+        }
+
+        if !mode::exists(&full_path) {
+            return Ok(None);
+        }
+
+        let bytes = unwrap!(
+            mode::read_working_bytes(&full_path),
+            "failed to read path: {path}"
+        );
+
+        Ok(Some(hash_raw_bytes(bytes)))
+    }
+
+    /// Bring the stamps up to date with the tracked files in `repo`,
+    /// returning the paths whose content changed, appeared or disappeared.
+    pub fn refresh(&mut self, repo: &Repository) -> Result<Vec<RelativePathBuf>> {
+        let mut changed = vec![];
+
+        let mut files = BTreeMap::new();
+
+        for path in repo.staging.paths() {
+            let full_path = paths::on_disk(path, &repo.root_dir);
+
+            let previous = self.files.remove(path);
+
+            if let Some(stamp) = &previous
+                && stamp.is_current(&full_path)
+            {
+                files.insert(path.clone(), stamp.clone());
+
+                continue;
+            }
+
+            if !mode::exists(&full_path) || full_path.is_dir() {
+                if previous.is_some() {
+                    changed.push(path.clone());
+                }
+
+                continue;
+            }
+
+            let stamp = FileStamp::read(&full_path)?;
+
+            if previous.is_none_or(|previous| previous.hash != stamp.hash) {
+                changed.push(path.clone());
+            }
+
+            files.insert(path.clone(), stamp);
+        }
+
+        // Whatever is left is no longer tracked.
+        changed.extend(self.files.keys().cloned());
+
+        self.files = files;
+
+        Ok(changed)
+    }
+}